@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use lumatone_core::midi::calibration::{
+  CalibrationOutcome, CalibrationProgress, CalibrationTiming,
+};
+use lumatone_core::midi::sysex::to_hex_debug_str;
+
+use super::connect;
+use crate::config::Config;
+
+/// Runs key calibration (or aftertouch calibration, if `aftertouch` is set), printing
+/// progress until it looks finished or `timeout_secs` have passed.
+pub async fn run_calibrate(aftertouch: bool, timeout_secs: u64, config: &Config) {
+  let client = connect(config, false).await;
+
+  let timing = CalibrationTiming {
+    max_duration: Duration::from_secs(timeout_secs),
+    ..Default::default()
+  };
+  let started = if aftertouch {
+    client.driver().calibrate_aftertouch(timing).await
+  } else {
+    client.driver().calibrate_keys(timing).await
+  };
+
+  let mut succeeded = true;
+  match started {
+    Ok(mut progress) => {
+      while let Some(event) = progress.recv().await {
+        println!("{}", describe_progress(&event, aftertouch));
+      }
+    }
+    Err(err) => {
+      eprintln!("unable to start calibration: {err}");
+      succeeded = false;
+    }
+  }
+
+  client.close().await.expect("error shutting down driver");
+  if !succeeded {
+    std::process::exit(1);
+  }
+}
+
+fn describe_progress(event: &CalibrationProgress, aftertouch: bool) -> String {
+  match event {
+    CalibrationProgress::Started if aftertouch => "aftertouch calibration started".to_string(),
+    CalibrationProgress::Started => {
+      "key calibration started. Press the pair of macro buttons on each octave when it's done."
+        .to_string()
+    }
+    CalibrationProgress::Status(status) => format!(
+      "status from {}: {}",
+      status.board,
+      to_hex_debug_str(&status.payload)
+    ),
+    CalibrationProgress::Finished(CalibrationOutcome::Completed { boards }) => {
+      let boards: Vec<String> = boards.iter().map(|b| b.to_string()).collect();
+      format!("calibration finished. Heard from: {}", boards.join(", "))
+    }
+    CalibrationProgress::Finished(CalibrationOutcome::Unconfirmed) => {
+      "no status received from the device before the time limit. Calibration may still \
+       have finished; check that all keys respond normally."
+        .to_string()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use lumatone_core::midi::calibration::{
+    CalibrationKind, CalibrationOutcome, CalibrationProgress, CalibrationStatus,
+  };
+  use lumatone_core::midi::constants::BoardIndex;
+
+  use super::describe_progress;
+
+  #[test]
+  fn test_describe_progress() {
+    let status = CalibrationProgress::Status(CalibrationStatus {
+      kind: CalibrationKind::Keys,
+      board: BoardIndex::Octave2,
+      payload: vec![0x1, 0xa],
+    });
+    assert_eq!(
+      describe_progress(&status, false),
+      format!("status from {}: [ 1 a ]", BoardIndex::Octave2)
+    );
+
+    let done = CalibrationProgress::Finished(CalibrationOutcome::Completed {
+      boards: vec![BoardIndex::Octave1, BoardIndex::Octave2],
+    });
+    assert_eq!(
+      describe_progress(&done, false),
+      format!(
+        "calibration finished. Heard from: {}, {}",
+        BoardIndex::Octave1,
+        BoardIndex::Octave2
+      )
+    );
+
+    let silent = CalibrationProgress::Finished(CalibrationOutcome::Unconfirmed);
+    assert!(describe_progress(&silent, true).starts_with("no status received"));
+  }
+}
@@ -0,0 +1,79 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use lumatone_core::keymap::error::LumatoneKeymapError;
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+
+/// The keymap formats that `convert` reads and writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+  Ltn,
+  Csv,
+}
+
+impl Format {
+  /// Picks the format from a file's extension.
+  fn of(path: &Path) -> Option<Format> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+      "ltn" => Some(Format::Ltn),
+      "csv" => Some(Format::Csv),
+      _ => None,
+    }
+  }
+}
+
+/// Converts a keymap between .ltn and CSV, picking each format from the file's extension.
+/// Without an `output`, the keymap is printed in the other format.
+pub fn run_convert(input: &PathBuf, output: Option<&PathBuf>) {
+  let Some(from) = Format::of(input) else {
+    eprintln!("{}: expected a .ltn or .csv file", input.display());
+    std::process::exit(1);
+  };
+  let to = match output {
+    Some(path) => Format::of(path).unwrap_or_else(|| {
+      eprintln!("{}: can only write .ltn or .csv files", path.display());
+      std::process::exit(1);
+    }),
+    None if from == Format::Csv => Format::Ltn,
+    None => Format::Csv,
+  };
+
+  let loaded = match from {
+    Format::Ltn => {
+      let ltn = fs::read_to_string(input).expect("unable to read preset");
+      LumatoneKeyMap::from_ini_str(ltn)
+    }
+    Format::Csv => LumatoneKeyMap::from_csv(File::open(input).expect("unable to read layout")),
+  };
+  let keymap = match loaded {
+    Ok(keymap) => keymap,
+    Err(LumatoneKeymapError::CsvError {
+      row,
+      column,
+      message,
+    }) => {
+      eprintln!("{}:{row}:{column}: {message}", input.display());
+      std::process::exit(1);
+    }
+    Err(err) => panic!("unable to load {}: {err:?}", input.display()),
+  };
+
+  match (to, output) {
+    (Format::Ltn, _) => {
+      let ltn = keymap.to_ini_string().expect("unable to serialize preset");
+      match output {
+        Some(path) => fs::write(path, ltn).expect("unable to write preset"),
+        None => print!("{ltn}"),
+      }
+    }
+    (Format::Csv, Some(path)) => {
+      let file = File::create(path).expect("unable to write layout");
+      keymap.to_csv(file).expect("unable to write layout");
+    }
+    (Format::Csv, None) => keymap
+      .to_csv(io::stdout().lock())
+      .expect("unable to write layout"),
+  }
+}
@@ -1,33 +1,22 @@
-use lumatone_core::midi::{
-  commands::set_key_color,
-  constants::{LumatoneKeyLocation, RGBColor},
-  detect::detect_device,
-  driver::MidiDriver,
-};
+use lumatone_core::midi::constants::{LumatoneKeyLocation, RGBColor};
 
 use log::debug;
-use tokio;
 
-pub async fn run_debug_cmd() {
-  let device = detect_device().await.expect("device detection failed");
-  let (driver, driver_future) = MidiDriver::new(&device).expect("driver creation failed");
+use super::connect;
+use crate::config::Config;
 
-  debug!("starting driver loop");
-  let h = tokio::spawn(driver_future);
-  debug!("driver loop spawned");
-
-  let commands = LumatoneKeyLocation::all()
-    .into_iter()
-    .map(|loc| set_key_color(loc, RGBColor::random()));
+pub async fn run_debug_cmd(config: &Config) {
+  let client = connect(config, false).await;
+  debug!("driver loop started");
 
   debug!("sending commands");
-  for c in commands {
+  for loc in LumatoneKeyLocation::all() {
     debug!("sending command");
-    let res = driver.send(c).await;
+    let color = RGBColor::random().scaled(config.brightness);
+    let res = client.set_key_color(loc, color).await;
     debug!("received response: {res:?}");
   }
 
-  debug!("sending done signal");
-  driver.done().await.expect("error sending done signal");
-  tokio::join!(h).0.expect("error joining driver future");
+  debug!("shutting down driver");
+  client.close().await.expect("error shutting down driver");
 }
@@ -0,0 +1,30 @@
+use lumatone_core::midi::client::Client;
+use lumatone_core::midi::detect::{detect_device_with_options, list_ports, DetectOptions};
+use lumatone_core::midi::doctor::run_checks;
+
+use super::driver_config;
+use crate::config::Config;
+
+/// Runs the checks in [run_checks] and prints the report. Always detects the device,
+/// even if its ports are configured, since detection is one of the things being checked.
+pub async fn run_doctor(config: &Config, json: bool, ignore_device_cache: bool) {
+  let options = DetectOptions {
+    ignore_cache: ignore_device_cache,
+    ..Default::default()
+  };
+  let report = run_checks(
+    list_ports(),
+    || detect_device_with_options(&options),
+    |device| Client::with_device(device, driver_config(config)),
+  )
+  .await;
+
+  if json {
+    println!("{}", report.to_json());
+  } else {
+    println!("{report}");
+  }
+  if !report.passed() {
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::PathBuf;
+
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::keymap::validation::{short_location, Severity, ValidationIssue};
+use serde_json::json;
+
+pub fn run_lint(path: &PathBuf, json: bool, strict: bool) {
+  let contents = fs::read_to_string(path).expect("unable to read preset");
+  let keymap = LumatoneKeyMap::from_ini_str(contents).expect("unable to load preset");
+
+  let issues = keymap.validate();
+  if json {
+    println!("{}", format_json(&issues));
+  } else if !issues.is_empty() {
+    println!("{}", format_text(&issues));
+  }
+
+  let code = exit_code(&issues, strict);
+  if code != 0 {
+    std::process::exit(code);
+  }
+}
+
+/// One issue per line, using the issue's Display impl.
+fn format_text(issues: &[ValidationIssue]) -> String {
+  issues
+    .iter()
+    .map(|i| i.to_string())
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+fn format_json(issues: &[ValidationIssue]) -> String {
+  let findings: Vec<_> = issues
+    .iter()
+    .map(|i| {
+      json!({
+        "severity": i.severity.to_string(),
+        "kind": i.kind.code(),
+        "locations": i.locations.iter().map(short_location).collect::<Vec<String>>(),
+        "message": i.message,
+        "suggestion": i.suggestion,
+      })
+    })
+    .collect();
+  serde_json::to_string_pretty(&findings).expect("failed to serialize lint findings")
+}
+
+/// Errors always fail the lint. Warnings only fail it in strict mode.
+fn exit_code(issues: &[ValidationIssue], strict: bool) -> i32 {
+  let failing = |i: &ValidationIssue| i.severity == Severity::Error || strict;
+  if issues.iter().any(failing) {
+    1
+  } else {
+    0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{exit_code, format_json, format_text};
+  use lumatone_core::keymap::ltn::{KeyDefinition, LumatoneKeyMap};
+  use lumatone_core::midi::constants::{
+    key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor,
+  };
+
+  fn note(channel: u8, note_num: u8) -> KeyDefinition {
+    KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(channel),
+        note_num,
+      },
+      color: RGBColor::green(),
+    }
+  }
+
+  fn cc(cc_num: u8) -> KeyDefinition {
+    KeyDefinition {
+      function: LumatoneKeyFunction::ContinuousController {
+        channel: MidiChannel::default(),
+        cc_num,
+        fader_up_is_null: false,
+      },
+      color: RGBColor::green(),
+    }
+  }
+
+  /// A keymap with one issue of each kind.
+  fn fixture() -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key(key_loc_unchecked(1, 0), note(1, 60))
+      .set_key(key_loc_unchecked(3, 12), note(1, 60))
+      .set_key(key_loc_unchecked(2, 7), note(4, 130))
+      .set_key(key_loc_unchecked(5, 55), cc(200));
+    keymap
+  }
+
+  #[test]
+  fn test_text_output() {
+    let issues = fixture().validate();
+    assert_eq!(
+      format_text(&issues),
+      "error [note-out-of-range] 2:7: note 130 is out of range (0 ..= 127)
+error [cc-out-of-range] 5:55: CC number 200 is out of range (0 ..= 127)
+warning [duplicate-note] 1:0, 3:12: duplicate (ch1, note 60) (consider channel 2)"
+    );
+  }
+
+  #[test]
+  fn test_json_output() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key(key_loc_unchecked(1, 0), note(1, 60))
+      .set_key(key_loc_unchecked(3, 12), note(1, 60))
+      .set_key(key_loc_unchecked(5, 55), cc(200));
+    let issues = keymap.validate();
+    assert_eq!(
+      format_json(&issues),
+      r#"[
+  {
+    "kind": "cc-out-of-range",
+    "locations": [
+      "5:55"
+    ],
+    "message": "CC number 200 is out of range (0 ..= 127)",
+    "severity": "error",
+    "suggestion": null
+  },
+  {
+    "kind": "duplicate-note",
+    "locations": [
+      "1:0",
+      "3:12"
+    ],
+    "message": "duplicate (ch1, note 60)",
+    "severity": "warning",
+    "suggestion": "consider channel 2"
+  }
+]"#
+    );
+  }
+
+  #[test]
+  fn test_exit_code() {
+    assert_eq!(exit_code(&fixture().validate(), false), 1);
+
+    let mut warnings_only = LumatoneKeyMap::new();
+    warnings_only
+      .set_key(key_loc_unchecked(1, 0), note(1, 60))
+      .set_key(key_loc_unchecked(1, 1), note(1, 60));
+    let issues = warnings_only.validate();
+    assert_eq!(exit_code(&issues, false), 0);
+    assert_eq!(exit_code(&issues, true), 1);
+
+    assert_eq!(exit_code(&[], true), 0);
+  }
+}
@@ -1,10 +1,34 @@
+mod calibrate;
+mod convert;
 mod debug;
+mod doctor;
+mod lint;
+mod progress;
+mod recolor;
+mod render;
+mod sample;
 mod send_preset;
 
 use clap::Subcommand;
+use lumatone_core::midi::client::Client;
+use lumatone_core::midi::detect::{detect_device_with_options, DetectOptions, DetectionSource};
+use lumatone_core::midi::device::LumatoneDevice;
+use lumatone_core::midi::driver::MidiDriverConfig;
+use lumatone_core::midi::error::LumatoneMidiError;
 use std::path::PathBuf;
 
-use self::{debug::run_debug_cmd, send_preset::run_send_preset};
+use self::{
+  calibrate::run_calibrate,
+  convert::run_convert,
+  debug::run_debug_cmd,
+  doctor::run_doctor,
+  lint::run_lint,
+  recolor::run_recolor,
+  render::{parse_param, run_render},
+  sample::run_sample,
+  send_preset::{apply_parts, run_send_preset, PresetPart},
+};
+use crate::config::Config;
 
 #[derive(Subcommand)]
 pub enum CliCommand {
@@ -15,15 +39,264 @@ pub enum CliCommand {
   SendPreset {
     #[clap(value_parser)]
     preset: PathBuf,
+
+    /// Check the payloads echoed by the device against what was sent, without a full read-back
+    #[clap(long)]
+    verify_light: bool,
+
+    /// Scan all MIDI ports for the device, instead of trying the last detected ports first
+    #[clap(long)]
+    ignore_device_cache: bool,
+
+    /// Only send these parts of the preset, e.g. `--only tables`. Sends everything if not given
+    #[clap(long, value_enum, value_delimiter = ',')]
+    only: Vec<PresetPart>,
+
+    /// Write progress to stderr as JSON, one event per line, for tools that wrap the CLI
+    #[clap(long)]
+    progress_json: bool,
+  },
+
+  /// Checks a .ltn preset file for problems like duplicate or out of range notes.
+  /// Exits with a non-zero status if any errors are found.
+  Lint {
+    #[clap(value_parser)]
+    preset: PathBuf,
+
+    /// Print findings as JSON
+    #[clap(long)]
+    json: bool,
+
+    /// Treat warnings as errors
+    #[clap(long)]
+    strict: bool,
+  },
+
+  /// Reduces the number of distinct key colors in a .ltn preset, replacing each color with
+  /// the average of a cluster of similar ones
+  Recolor {
+    #[clap(value_parser)]
+    preset: PathBuf,
+
+    /// Use at most this many colors
+    #[clap(long, value_name = "COLORS", value_parser = clap::value_parser!(u64).range(1..))]
+    quantize: u64,
+
+    /// Seed for choosing the initial color clusters. The same seed always gives the same result
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Write the preset to this file instead of printing it
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Only print which colors would be replaced
+    #[clap(long)]
+    dry_run: bool,
+  },
+
+  /// Renders a parameterized JSON layout into a .ltn preset
+  Render {
+    #[clap(value_parser)]
+    layout: PathBuf,
+
+    /// Sets a layout parameter, e.g. `--param root=48`. May be repeated
+    #[clap(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    params: Vec<(String, i64)>,
+
+    /// Write the preset to this file instead of printing it
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+  },
+
+  /// Converts a keymap between .ltn and CSV (board,key,type,note_or_cc,channel,color,
+  /// fader_up_null), going by the file extensions, e.g. `convert layout.csv -o layout.ltn`
+  Convert {
+    #[clap(value_parser)]
+    input: PathBuf,
+
+    /// Write the keymap to this .ltn or .csv file instead of printing it in the other format
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+  },
+
+  /// Streams raw key sensor readings from one board and reports per-key stats,
+  /// for tracking down keys that misfire or don't respond reliably
+  Sample {
+    /// Which board (octave) to sample, from 1 to 5
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+    board: u8,
+
+    /// How long to sample for
+    #[clap(long, default_value_t = 5)]
+    seconds: u64,
+
+    /// Write the stats to this CSV file instead of printing them
+    #[clap(long)]
+    csv: Option<PathBuf>,
   },
+
+  /// Runs the device's key calibration routine, or aftertouch calibration, and reports
+  /// its progress
+  Calibrate {
+    /// Calibrate aftertouch instead of the keys
+    #[clap(long)]
+    aftertouch: bool,
+
+    /// How long to wait for calibration to finish, in seconds
+    #[clap(long, default_value_t = 180)]
+    timeout: u64,
+  },
+
+  /// Runs a series of checks on the connection to the device, from listing MIDI ports to
+  /// reading a board's keys, and prints a report suitable for pasting into a bug report.
+  /// Exits with a non-zero status if any check fails
+  Doctor {
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+
+    /// Scan all MIDI ports for the device, instead of trying the last detected ports first
+    #[clap(long)]
+    ignore_device_cache: bool,
+  },
+
+  /// Inspects the settings from flags, `LUMATONE_*` environment variables, and lumatone.toml
+  Config {
+    #[clap(subcommand)]
+    command: ConfigCommand,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+  /// Prints the effective settings, in config file format
+  Show,
 }
 
 impl CliCommand {
-  pub async fn run(&self) {
+  pub async fn run(&self, config: &Config) {
     match self {
-      Self::Debug => run_debug_cmd().await,
+      Self::Debug => run_debug_cmd(config).await,
+
+      Self::SendPreset {
+        preset,
+        verify_light,
+        ignore_device_cache,
+        only,
+        progress_json,
+      } => {
+        let parts = apply_parts(only);
+        run_send_preset(
+          preset,
+          config,
+          *verify_light,
+          *ignore_device_cache,
+          parts,
+          *progress_json,
+        )
+        .await
+      }
+
+      Self::Lint {
+        preset,
+        json,
+        strict,
+      } => run_lint(preset, *json, *strict),
+
+      Self::Recolor {
+        preset,
+        quantize,
+        seed,
+        output,
+        dry_run,
+      } => run_recolor(preset, *quantize as usize, *seed, output.as_ref(), *dry_run),
+
+      Self::Render {
+        layout,
+        params,
+        output,
+      } => run_render(layout, params, output.as_ref()),
+
+      Self::Convert { input, output } => run_convert(input, output.as_ref()),
+
+      Self::Sample {
+        board,
+        seconds,
+        csv,
+      } => run_sample(*board, *seconds, csv.as_ref(), config).await,
 
-      Self::SendPreset { preset } => run_send_preset(preset).await,
+      Self::Calibrate {
+        aftertouch,
+        timeout,
+      } => run_calibrate(*aftertouch, *timeout, config).await,
+
+      Self::Doctor {
+        json,
+        ignore_device_cache,
+      } => run_doctor(config, *json, *ignore_device_cache).await,
+
+      Self::Config {
+        command: ConfigCommand::Show,
+      } => {
+        match &config.file {
+          Some(path) => println!("# loaded from {}", path.display()),
+          None => println!("# no config file found"),
+        }
+        print!("{}", config.to_toml_string());
+      }
+    }
+  }
+}
+
+/// Finds the device with [find_device] and connects a [Client] to it, using the configured
+/// receive timeout and busy retry limit.
+///
+/// Exits if another process is using the device, unless `--steal` was given.
+async fn connect(config: &Config, ignore_device_cache: bool) -> Client {
+  let device = find_device(config, ignore_device_cache).await;
+  match Client::with_device(&device, driver_config(config)) {
+    Ok(client) => client,
+    Err(err @ LumatoneMidiError::DeviceLocked { .. }) => {
+      eprintln!("{err}");
+      eprintln!("Close the other process, or pass --steal to use the device anyway.");
+      std::process::exit(1);
     }
+    Err(err) => panic!("driver creation failed: {err}"),
+  }
+}
+
+/// The driver settings from `config`.
+fn driver_config(config: &Config) -> MidiDriverConfig {
+  MidiDriverConfig {
+    receive_timeout: config.receive_timeout,
+    max_busy_retries: config.max_busy_retries,
+    steal_lock: config.steal_device_lock,
+    ..Default::default()
+  }
+}
+
+/// Returns the device on the configured ports if both are set, otherwise detects one.
+async fn find_device(config: &Config, ignore_device_cache: bool) -> LumatoneDevice {
+  match (&config.in_port, &config.out_port) {
+    (Some(in_port), Some(out_port)) => {
+      println!("using configured device ports");
+      return LumatoneDevice::new(out_port, in_port);
+    }
+    (None, None) => {}
+    _ => log::warn!("both in_port and out_port must be set to skip device detection"),
+  }
+
+  let options = DetectOptions {
+    ignore_cache: ignore_device_cache,
+    ..Default::default()
+  };
+  let (device, source) = detect_device_with_options(&options)
+    .await
+    .expect("device detection failed");
+  match source {
+    DetectionSource::Cache => println!("using cached device ports"),
+    DetectionSource::Scan => println!("found device by scanning ports"),
   }
+  device
 }
@@ -0,0 +1,162 @@
+use std::io::Write;
+
+use lumatone_core::midi::client::PresetSendReport;
+use lumatone_core::midi::script::ScriptProgress;
+use serde_json::{json, Value};
+
+/// Writes progress events for `--progress-json`, one JSON object per line, for tools that
+/// wrap the CLI.
+///
+/// A run starts with a `start` event and ends with a `finish` event. In between, there's a
+/// `progress` event after each command, followed by an `error` event if the command failed.
+pub struct JsonProgress<W: Write> {
+  out: W,
+}
+
+impl<W: Write> JsonProgress<W> {
+  pub fn new(out: W) -> Self {
+    JsonProgress { out }
+  }
+
+  pub fn start(&mut self, total: usize) {
+    self.emit(json!({ "event": "start", "total": total }));
+  }
+
+  pub fn progress(&mut self, p: &ScriptProgress) {
+    let stage = p.stage();
+    self.emit(json!({
+      "event": "progress",
+      "done": p.done,
+      "total": p.total,
+      "stage": stage,
+    }));
+    if let Err(err) = p.result {
+      self.emit(json!({
+        "event": "error",
+        "stage": stage,
+        "command": p.command.to_string(),
+        "message": err.to_string(),
+      }));
+    }
+  }
+
+  /// An error that isn't tied to a command, e.g. a preset that can't be sent at all.
+  pub fn error(&mut self, message: &str) {
+    self.emit(json!({ "event": "error", "message": message }));
+  }
+
+  /// The final counts, after failed commands have been retried.
+  pub fn finish(&mut self, report: &PresetSendReport) {
+    self.emit(json!({
+      "event": "finish",
+      "sent": report.results.len(),
+      "succeeded": report.succeeded(),
+      "failed": report.failed(),
+      "retried": report.retried,
+    }));
+  }
+
+  fn emit(&mut self, event: Value) {
+    // progress is best-effort, so a closed pipe shouldn't stop the send
+    let _ = writeln!(self.out, "{event}").and_then(|_| self.out.flush());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use lumatone_core::keymap::ltn::{ApplyParts, KeyDefinition, LumatoneKeyMap};
+  use lumatone_core::midi::client::{ApplyOptions, Client};
+  use lumatone_core::midi::commands::set_key_color;
+  use lumatone_core::midi::constants::{
+    key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor, ResponseStatusCode,
+  };
+  use lumatone_core::midi::device::MidiTransport;
+  use lumatone_core::midi::driver::MidiDriverConfig;
+  use lumatone_core::midi::error::LumatoneMidiError;
+  use lumatone_core::midi::sysex::{EncodedSysex, MSG_STATUS};
+  use tokio::sync::mpsc;
+
+  use super::JsonProgress;
+
+  /// Acks everything except the `refused` message.
+  struct MockDevice {
+    tx: mpsc::Sender<EncodedSysex>,
+    rx: mpsc::Receiver<EncodedSysex>,
+    refused: EncodedSysex,
+  }
+
+  impl MidiTransport for MockDevice {
+    fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+      let status = if msg == self.refused {
+        ResponseStatusCode::Nack
+      } else {
+        ResponseStatusCode::Ack
+      };
+      // the status goes right after the command id, one past MSG_STATUS because of the
+      // sysex start byte
+      let mut reply = msg[..MSG_STATUS + 1].to_vec();
+      reply.push(status as u8);
+      reply.extend(&msg[MSG_STATUS + 1..]);
+      self
+        .tx
+        .try_send(reply)
+        .map_err(|e| LumatoneMidiError::DeviceSendError(e.to_string()))
+    }
+
+    fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex> {
+      &mut self.rx
+    }
+  }
+
+  // serde_json writes object keys in alphabetical order
+  const EXPECTED_EVENTS: &str = r#"{"event":"start","total":2}
+{"done":1,"event":"progress","stage":"board3","total":2}
+{"done":2,"event":"progress","stage":"board3","total":2}
+{"command":"SetKeyColor(LumatoneKeyLocation(Octave3, 1, #0000ff)","event":"error","message":"MESSAGE","stage":"board3"}
+{"event":"finish","failed":1,"retried":1,"sent":2,"succeeded":1}
+"#;
+
+  #[tokio::test]
+  async fn test_progress_events_for_preset_send() {
+    // a single key, since the order that keys are sent in isn't fixed
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_key(
+      key_loc_unchecked(3, 1),
+      KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::default(),
+          note_num: 61,
+        },
+        color: RGBColor::blue(),
+      },
+    );
+
+    let (tx, rx) = mpsc::channel(8);
+    let refused = set_key_color(key_loc_unchecked(3, 1), RGBColor::blue()).to_sysex_message();
+    let device = MockDevice { tx, rx, refused };
+    let client = Client::with_transport(Box::new(device), MidiDriverConfig::default());
+    let opts = ApplyOptions {
+      parts: ApplyParts::keys(),
+      ..Default::default()
+    };
+
+    let mut out = Vec::new();
+    let mut progress = JsonProgress::new(&mut out);
+    progress.start(keymap.to_midi_commands_filtered(opts.parts).len());
+    let report = client
+      .send_preset_with_progress(&keymap, &opts, |p| progress.progress(&p))
+      .await;
+    progress.finish(&report);
+    client.close().await.unwrap();
+
+    // the error message comes from the driver, so just check that there is one
+    let refused = report.results[1].1.as_ref().unwrap_err();
+    let expected = EXPECTED_EVENTS.replace(
+      "MESSAGE",
+      serde_json::to_string(&refused.to_string())
+        .unwrap()
+        .trim_matches('"'),
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+  }
+}
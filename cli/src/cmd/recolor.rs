@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::PathBuf;
+
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::keymap::quantize::{QuantizeReport, DEFAULT_QUANTIZE_SEED};
+
+/// Quantizes a preset's key colors down to at most `max_colors`, and writes the result to
+/// `output` or to stdout. The color mapping is printed to stderr, so with `dry_run` this
+/// previews the change without writing anything.
+pub fn run_recolor(
+  preset: &PathBuf,
+  max_colors: usize,
+  seed: Option<u64>,
+  output: Option<&PathBuf>,
+  dry_run: bool,
+) {
+  let contents = fs::read_to_string(preset).expect("unable to read preset");
+  let mut keymap = LumatoneKeyMap::from_ini_str(contents).expect("unable to load preset");
+
+  let report = keymap.quantize_colors_with_seed(max_colors, seed.unwrap_or(DEFAULT_QUANTIZE_SEED));
+  eprintln!("{}", format_report(&report));
+  if dry_run {
+    return;
+  }
+
+  let ltn = keymap.to_ini_string().expect("unable to serialize preset");
+  match output {
+    Some(path) => fs::write(path, ltn).expect("unable to write preset"),
+    None => print!("{ltn}"),
+  }
+}
+
+/// A summary line, followed by one line per color that was replaced.
+fn format_report(report: &QuantizeReport) -> String {
+  let mut lines = vec![format!(
+    "{} colors -> {}, {} keys changed",
+    report.mapping.len(),
+    report.palette.len(),
+    report.keys_changed
+  )];
+  lines.extend(
+    report
+      .mapping
+      .iter()
+      .filter(|(original, replacement)| original != replacement)
+      .map(|(original, replacement)| format!("  {original} -> {replacement}")),
+  );
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::format_report;
+  use lumatone_core::keymap::quantize::QuantizeReport;
+  use lumatone_core::midi::constants::RGBColor;
+
+  #[test]
+  fn test_format_report() {
+    let report = QuantizeReport {
+      palette: vec![RGBColor(0xf0, 0, 0), RGBColor::blue()],
+      mapping: vec![
+        (RGBColor(0, 0, 0xff), RGBColor::blue()),
+        (RGBColor(0xe0, 0, 0), RGBColor(0xf0, 0, 0)),
+        (RGBColor(0xff, 0, 0x10), RGBColor(0xf0, 0, 0)),
+      ],
+      keys_changed: 4,
+    };
+    assert_eq!(
+      format_report(&report),
+      "3 colors -> 2, 4 keys changed
+  #e00000 -> #f00000
+  #ff0010 -> #f00000"
+    );
+  }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use lumatone_core::keymap::error::LumatoneKeymapError;
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+
+/// Loads a JSON layout with the given parameter values and writes it out as a .ltn preset,
+/// to `output` or to stdout.
+pub fn run_render(layout: &PathBuf, params: &[(String, i64)], output: Option<&PathBuf>) {
+  let json = fs::read_to_string(layout).expect("unable to read layout");
+  let overrides: HashMap<String, i64> = params.iter().cloned().collect();
+  let keymap = match LumatoneKeyMap::from_json_with_params(&json, &overrides) {
+    Ok(keymap) => keymap,
+    Err(LumatoneKeymapError::LayoutError(msg)) => {
+      eprintln!("{}: {msg}", layout.display());
+      std::process::exit(1);
+    }
+    Err(LumatoneKeymapError::JsonError(err)) => {
+      eprintln!("{}: {err}", layout.display());
+      std::process::exit(1);
+    }
+    Err(err) => panic!("unable to load layout: {err:?}"),
+  };
+
+  let ltn = keymap.to_ini_string().expect("unable to serialize preset");
+  match output {
+    Some(path) => fs::write(path, ltn).expect("unable to write preset"),
+    None => print!("{ltn}"),
+  }
+}
+
+/// Parses a `--param` value of the form `name=value`.
+pub fn parse_param(s: &str) -> Result<(String, i64), String> {
+  let (name, value) = s
+    .split_once('=')
+    .ok_or_else(|| format!("expected name=value, got {s:?}"))?;
+  let name = name.trim().trim_start_matches('$');
+  if name.is_empty() {
+    return Err(format!("missing parameter name in {s:?}"));
+  }
+  let value = value
+    .trim()
+    .parse::<i64>()
+    .map_err(|_| format!("value for {name} must be an integer, got {value:?}"))?;
+  Ok((name.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse_param;
+
+  #[test]
+  fn test_parse_param() {
+    assert_eq!(parse_param("root=48"), Ok(("root".to_string(), 48)));
+    assert_eq!(parse_param("$offset = -3"), Ok(("offset".to_string(), -3)));
+    assert!(parse_param("root").is_err());
+    assert!(parse_param("=48").is_err());
+    assert!(parse_param("root=C4").is_err());
+  }
+}
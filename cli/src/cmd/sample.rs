@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use lumatone_core::midi::constants::BoardIndex;
+use lumatone_core::midi::sampling::KeySamplingReport;
+
+use super::connect;
+use crate::config::Config;
+
+/// Samples the key sensors on `board` (1-5) for `seconds`, then prints per-key stats,
+/// or writes them to `csv` if given.
+pub async fn run_sample(board: u8, seconds: u64, csv: Option<&PathBuf>, config: &Config) {
+  let board = BoardIndex::try_from(board).expect("invalid board index");
+  let client = connect(config, false).await;
+
+  println!("sampling keys on {board} for {seconds}s...");
+  let result = client
+    .driver()
+    .sample_keys(board, Duration::from_secs(seconds))
+    .await;
+
+  client.close().await.expect("error shutting down driver");
+
+  let report = match result {
+    Ok(report) => report,
+    Err(err) => {
+      eprintln!("key sampling failed: {err}");
+      std::process::exit(1);
+    }
+  };
+
+  match csv {
+    Some(path) => {
+      fs::write(path, report.to_csv()).expect("unable to write csv");
+      println!(
+        "wrote stats for {} keys from {} frames to {}",
+        report.keys.len(),
+        report.frames,
+        path.display()
+      );
+    }
+    None => print!("{}", format_table(&report)),
+  }
+}
+
+/// One row per key, with the noisiest keys easy to spot by their range and deviation.
+fn format_table(report: &KeySamplingReport) -> String {
+  if report.frames == 0 {
+    return "no samples received. Does this firmware support key sampling?\n".to_string();
+  }
+  let mut out = format!(
+    "{} frames in {:.1}s\n{:>4} {:>6} {:>6} {:>6} {:>8} {:>8}\n",
+    report.frames,
+    report.duration.as_secs_f64(),
+    "key",
+    "min",
+    "max",
+    "range",
+    "mean",
+    "std_dev"
+  );
+  for k in &report.keys {
+    out.push_str(&format!(
+      "{:>4} {:>6} {:>6} {:>6} {:>8.1} {:>8.2}\n",
+      k.key,
+      k.min,
+      k.max,
+      k.range(),
+      k.mean,
+      k.std_dev()
+    ));
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use lumatone_core::midi::constants::BoardIndex;
+  use lumatone_core::midi::sampling::KeySamplingReport;
+
+  use super::format_table;
+
+  #[test]
+  fn test_format_table() {
+    let mut report = KeySamplingReport::new(BoardIndex::Octave1);
+    assert!(format_table(&report).starts_with("no samples received"));
+
+    report.record(&[100, 2000]);
+    report.record(&[102, 2000]);
+    let table = format_table(&report);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("2 frames"));
+    assert_eq!(
+      lines[2].split_whitespace().collect::<Vec<_>>(),
+      vec!["0", "100", "102", "2", "101.0", "1.00"]
+    );
+  }
+}
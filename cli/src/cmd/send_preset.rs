@@ -1,30 +1,213 @@
 use std::fs;
 use std::path::PathBuf;
 
-use lumatone_core::keymap::ltn::LumatoneKeyMap;
-use lumatone_core::midi::detect::detect_device;
-use lumatone_core::midi::driver::MidiDriver;
+use clap::ValueEnum;
+use lumatone_core::keymap::labels::location_label;
+use lumatone_core::keymap::ltn::{ApplyParts, LumatoneKeyMap};
+use lumatone_core::midi::client::ApplyOptions;
 
-pub async fn run_send_preset(path: &PathBuf) {
-  let contents = fs::read_to_string(path).expect("unable to read preset");
-  let keymap = LumatoneKeyMap::from_ini_str(contents).expect("unable to load presest");
+use super::connect;
+use super::progress::JsonProgress;
+use crate::config::Config;
 
-  let device = detect_device().await.expect("device detection failed");
-  let (driver, driver_future) = MidiDriver::new(&device).expect("driver creation failed");
+/// Sends all keys and options in the preset at `path` to the device.
+///
+/// Commands that fail are retried once. At the end, a summary is printed, listing each
+/// command that still failed and the key it was for, and the process exits with an error
+/// if there were any.
+///
+/// With `verify_light`, the payloads the device echoes back for key and toggle commands
+/// are compared with what was sent, and any mismatches are reported at the end. Only
+/// commands whose echo is known are checked; see
+/// [Response::confirms](lumatone_core::midi::responses::Response::confirms).
+///
+/// With `ignore_device_cache`, all MIDI ports are scanned for the device, even if it
+/// was found on a known pair of ports last time.
+///
+/// Only the given `parts` of the preset are sent.
+///
+/// With `progress_json`, progress events are written to stderr as JSON lines (see
+/// [JsonProgress]).
+///
+/// Key colors are scaled by the configured brightness.
+pub async fn run_send_preset(
+  path: &PathBuf,
+  config: &Config,
+  verify_light: bool,
+  ignore_device_cache: bool,
+  parts: ApplyParts,
+  progress_json: bool,
+) {
+  let mut progress = progress_json.then(|| JsonProgress::new(std::io::stderr()));
+  let keymap = match load_preset(path) {
+    Ok(keymap) => keymap,
+    Err(err) => {
+      match &mut progress {
+        Some(progress) => progress.error(&err),
+        None => eprintln!("{err}"),
+      }
+      std::process::exit(1);
+    }
+  };
+  // colors and tables can't be out of range, so only the key functions need checking
+  let checked = if parts.functions {
+    keymap.check_hardware_compat()
+  } else {
+    Ok(())
+  };
+  if let Err(problems) = checked {
+    if let Some(progress) = &mut progress {
+      for problem in problems {
+        progress.error(&problem.to_string());
+      }
+    } else {
+      eprintln!("{} can't be sent to the device:", path.display());
+      for problem in problems {
+        eprintln!("  {problem}");
+      }
+    }
+    std::process::exit(1);
+  }
+
+  let client = connect(config, ignore_device_cache).await;
+  let opts = ApplyOptions {
+    brightness: config.brightness,
+    parts,
+    ..Default::default()
+  };
+  let report = match &mut progress {
+    Some(progress) => {
+      progress.start(keymap.to_midi_commands_filtered(parts).len());
+      let report = client
+        .send_preset_with_progress(&keymap, &opts, |p| progress.progress(&p))
+        .await;
+      progress.finish(&report);
+      report
+    }
+    None => client.send_preset(&keymap, &opts).await,
+  };
+  // the report is complete, but make sure the device is done before shutting down
+  if let Err(err) = client.flush().await {
+    log::warn!("driver stopped before the device finished: {err}");
+  }
+  client.close().await.expect("error shutting down driver");
+  log::debug!(
+    "sent {} commands in {:?} ({} retried)",
+    report.results.len(),
+    report.duration,
+    report.retried
+  );
 
-  log::debug!("starting driver loop");
-  let h = tokio::spawn(driver_future);
-  log::debug!("driver loop spawned");
+  println!("{}", report.summary());
+  for (c, err) in report.failures() {
+    match c.key_location() {
+      Some(location) => println!("  {}: {c}: {err}", location_label(location)),
+      None => println!("  {c}: {err}"),
+    }
+  }
+
+  if verify_light {
+    let checked: Vec<_> = report
+      .results
+      .iter()
+      .filter_map(|(c, res)| Some((c, res.as_ref().ok()?.confirms(c)?)))
+      .collect();
+    let mismatches: Vec<_> = checked
+      .iter()
+      .filter(|(_, matched)| !matched)
+      .map(|(c, _)| c)
+      .collect();
 
-  let commands = keymap.to_midi_commands();
-  log::debug!("sending commands");
-  for c in commands {
-    log::debug!("sending command {}", c);
-    let res = driver.send(c).await;
-    log::debug!("received response: {res:?}");
+    if checked.is_empty() {
+      println!("nothing verified: no sent command has a known echo to check");
+    } else if mismatches.is_empty() {
+      println!("verified: all echoed commands matched");
+    } else {
+      println!("{} command(s) were not echoed correctly:", mismatches.len());
+      for c in &mismatches {
+        println!("  {c}");
+      }
+      std::process::exit(1);
+    }
   }
 
-  log::debug!("sending done signal");
-  driver.done().await.expect("error sending done signal");
-  tokio::join!(h).0.expect("error joining driver future");
+  if !report.is_success() {
+    std::process::exit(1);
+  }
+}
+
+/// Reads and parses the preset at `path`, describing what went wrong if it can't.
+fn load_preset(path: &PathBuf) -> Result<LumatoneKeyMap, String> {
+  let contents =
+    fs::read_to_string(path).map_err(|err| format!("unable to read {}: {err}", path.display()))?;
+  LumatoneKeyMap::from_ini_str(contents)
+    .map_err(|err| format!("unable to load {}: {err:?}", path.display()))
+}
+
+/// A part of a preset that can be sent on its own with `send-preset --only`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PresetPart {
+  /// Both the functions and the colors of the keys
+  Keys,
+  /// Key functions: what each key sends, and on which channel
+  Functions,
+  /// Key colors
+  Colors,
+  /// General options and macro button colors
+  Options,
+  /// Velocity and fader tables
+  Tables,
+}
+
+/// Combines the parts given with `--only`. If none were given, everything is sent.
+pub fn apply_parts(only: &[PresetPart]) -> ApplyParts {
+  if only.is_empty() {
+    return ApplyParts::all();
+  }
+  let mut parts = ApplyParts::none();
+  for part in only {
+    match part {
+      PresetPart::Keys => {
+        parts.functions = true;
+        parts.colors = true;
+      }
+      PresetPart::Functions => parts.functions = true,
+      PresetPart::Colors => parts.colors = true,
+      PresetPart::Options => parts.options = true,
+      PresetPart::Tables => parts.tables = true,
+    }
+  }
+  parts
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::{apply_parts, load_preset, PresetPart};
+  use lumatone_core::keymap::ltn::ApplyParts;
+
+  #[test]
+  fn test_apply_parts() {
+    assert_eq!(apply_parts(&[]), ApplyParts::all());
+    assert_eq!(apply_parts(&[PresetPart::Keys]), ApplyParts::keys());
+    assert_eq!(
+      apply_parts(&[PresetPart::Tables, PresetPart::Options]),
+      ApplyParts {
+        options: true,
+        tables: true,
+        ..ApplyParts::none()
+      }
+    );
+  }
+
+  #[test]
+  fn test_load_preset_errors_name_the_file() {
+    let path = PathBuf::from("no-such-preset.ltn");
+    let err = load_preset(&path).unwrap_err();
+    assert!(
+      err.starts_with("unable to read no-such-preset.ltn: "),
+      "{err}"
+    );
+  }
 }
@@ -0,0 +1,357 @@
+//! Defaults for connection settings and other options that would be tedious to pass on
+//! every invocation.
+//!
+//! Each setting is resolved from the first of these that sets it:
+//!
+//! 1. command line flags
+//! 2. `LUMATONE_*` environment variables (e.g. `LUMATONE_IN_PORT`)
+//! 3. a `lumatone.toml` file in the current directory, or else in the platform config dir
+//!
+//! The config file uses the same names as the flags, with underscores instead of dashes:
+//!
+//! ```toml
+//! in_port = "Lumatone"
+//! out_port = "Lumatone"
+//! receive_timeout = 10
+//! max_busy_retries = 3
+//! brightness = 0.5
+//! log_level = "info"
+//! ```
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+use lumatone_core::midi::driver::MidiDriverConfig;
+use lumatone_core::settings::config_dir;
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_FILE_NAME: &str = "lumatone.toml";
+const ENV_PREFIX: &str = "LUMATONE_";
+
+/// `LUMATONE_*` variables that aren't settings, so aren't warned about, e.g. the one that
+/// tells the ini snapshot test to rewrite its snapshot.
+const NON_CONFIG_ENV_VARS: &[&str] = &["UPDATE_SNAPSHOTS"];
+
+const DEFAULT_RECEIVE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_BRIGHTNESS: f64 = 1.0;
+const DEFAULT_LOG_LEVEL: &str = "debug";
+
+/// Settings from one source. Anything left unset falls through to the next source.
+#[derive(Args, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+  /// Name of the MIDI input port the device is connected to. Skips device detection if
+  /// set along with --out-port.
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub in_port: Option<String>,
+
+  /// Name of the MIDI output port the device is connected to. Skips device detection if
+  /// set along with --in-port.
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub out_port: Option<String>,
+
+  /// How many seconds to wait for the device to respond to a command
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub receive_timeout: Option<u64>,
+
+  /// How many times to re-send a command that the device says it's too busy for, before
+  /// giving up on it
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_busy_retries: Option<usize>,
+
+  /// Scales key colors sent to the device, from 0.0 (off) to 1.0 (as defined in the preset)
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub brightness: Option<f64>,
+
+  /// Log filter, in `RUST_LOG` syntax (e.g. "info", or "lumatone_core=trace")
+  #[clap(long, global = true)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub log_level: Option<String>,
+}
+
+impl ConfigLayer {
+  /// Reads a layer from `LUMATONE_*` variables in `vars`, ignoring everything else.
+  fn from_env_vars<I>(vars: I) -> Result<ConfigLayer, ConfigError>
+  where
+    I: IntoIterator<Item = (String, String)>,
+  {
+    let mut layer = ConfigLayer::default();
+    for (name, value) in vars {
+      let key = match name.strip_prefix(ENV_PREFIX) {
+        Some(key) => key,
+        None => continue,
+      };
+      let invalid = || ConfigError::InvalidEnvVar {
+        name: name.clone(),
+        value: value.clone(),
+      };
+      match key {
+        "IN_PORT" => layer.in_port = Some(value.clone()),
+        "OUT_PORT" => layer.out_port = Some(value.clone()),
+        "RECEIVE_TIMEOUT" => layer.receive_timeout = Some(value.parse().map_err(|_| invalid())?),
+        "MAX_BUSY_RETRIES" => layer.max_busy_retries = Some(value.parse().map_err(|_| invalid())?),
+        "BRIGHTNESS" => layer.brightness = Some(value.parse().map_err(|_| invalid())?),
+        "LOG_LEVEL" => layer.log_level = Some(value.clone()),
+        key if NON_CONFIG_ENV_VARS.contains(&key) => {}
+        // logging isn't set up yet, since the log level is one of the settings
+        _ => eprintln!("warning: ignoring unknown environment variable {name}"),
+      }
+    }
+    Ok(layer)
+  }
+
+  fn from_toml_str(s: &str, path: &Path) -> Result<ConfigLayer, ConfigError> {
+    toml::from_str(s).map_err(|e| ConfigError::InvalidFile(path.to_path_buf(), e.to_string()))
+  }
+
+  /// Returns a layer with each setting taken from `self` if set, otherwise from `fallback`.
+  fn or(self, fallback: ConfigLayer) -> ConfigLayer {
+    ConfigLayer {
+      in_port: self.in_port.or(fallback.in_port),
+      out_port: self.out_port.or(fallback.out_port),
+      receive_timeout: self.receive_timeout.or(fallback.receive_timeout),
+      max_busy_retries: self.max_busy_retries.or(fallback.max_busy_retries),
+      brightness: self.brightness.or(fallback.brightness),
+      log_level: self.log_level.or(fallback.log_level),
+    }
+  }
+}
+
+/// The effective settings, after resolving all sources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+  pub in_port: Option<String>,
+  pub out_port: Option<String>,
+  pub receive_timeout: Duration,
+  pub max_busy_retries: usize,
+  pub brightness: f64,
+
+  /// `None` unless set explicitly, so that `RUST_LOG` still works.
+  pub log_level: Option<String>,
+
+  /// The config file that was loaded, if any.
+  pub file: Option<PathBuf>,
+
+  /// Whether to use the device even if another process has it locked. Only ever set from
+  /// the `--steal` flag, since it's not something to leave switched on.
+  pub steal_device_lock: bool,
+}
+
+impl Config {
+  /// Resolves the config from the command line flags in `cli`, the process environment,
+  /// and the first `lumatone.toml` found.
+  pub fn resolve(cli: &ConfigLayer) -> Result<Config, ConfigError> {
+    let env = ConfigLayer::from_env_vars(std::env::vars())?;
+
+    let cwd = std::env::current_dir().ok();
+    let file = find_config_file(cwd.as_deref(), config_dir().as_deref());
+    let file_layer = match &file {
+      Some(path) => {
+        let contents =
+          std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        ConfigLayer::from_toml_str(&contents, path)?
+      }
+      None => ConfigLayer::default(),
+    };
+
+    let mut config = Config::from_layers(cli.clone(), env, file_layer)?;
+    config.file = file;
+    Ok(config)
+  }
+
+  /// Merges the layers in order of precedence and fills in defaults.
+  fn from_layers(
+    cli: ConfigLayer,
+    env: ConfigLayer,
+    file: ConfigLayer,
+  ) -> Result<Config, ConfigError> {
+    let merged = cli.or(env).or(file);
+
+    let brightness = merged.brightness.unwrap_or(DEFAULT_BRIGHTNESS);
+    if !(0.0..=1.0).contains(&brightness) {
+      return Err(ConfigError::InvalidBrightness(brightness));
+    }
+
+    Ok(Config {
+      in_port: merged.in_port,
+      out_port: merged.out_port,
+      receive_timeout: Duration::from_secs(
+        merged
+          .receive_timeout
+          .unwrap_or(DEFAULT_RECEIVE_TIMEOUT_SECS),
+      ),
+      max_busy_retries: merged
+        .max_busy_retries
+        .unwrap_or(MidiDriverConfig::default().max_busy_retries),
+      brightness,
+      log_level: merged.log_level,
+      file: None,
+      steal_device_lock: false,
+    })
+  }
+
+  /// The log filter to use, falling back to `RUST_LOG` and then the default level.
+  pub fn log_filter(&self) -> String {
+    self
+      .log_level
+      .clone()
+      .or_else(|| std::env::var("RUST_LOG").ok())
+      .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+  }
+
+  /// Renders the effective settings in config file format.
+  pub fn to_toml_string(&self) -> String {
+    let layer = ConfigLayer {
+      in_port: self.in_port.clone(),
+      out_port: self.out_port.clone(),
+      receive_timeout: Some(self.receive_timeout.as_secs()),
+      max_busy_retries: Some(self.max_busy_retries),
+      brightness: Some(self.brightness),
+      log_level: Some(self.log_filter()),
+    };
+    toml::to_string(&layer).expect("failed to serialize config")
+  }
+}
+
+/// Returns the path to the config file in `cwd` if there is one, otherwise the one in `config_dir`.
+fn find_config_file(cwd: Option<&Path>, config_dir: Option<&Path>) -> Option<PathBuf> {
+  [cwd, config_dir]
+    .into_iter()
+    .flatten()
+    .map(|dir| dir.join(CONFIG_FILE_NAME))
+    .find(|path| path.is_file())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Io(PathBuf, std::io::Error),
+  InvalidFile(PathBuf, String),
+  InvalidEnvVar { name: String, value: String },
+  InvalidBrightness(f64),
+}
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use ConfigError::*;
+    match self {
+      Io(path, e) => write!(f, "unable to read {}: {e}", path.display()),
+      InvalidFile(path, msg) => write!(f, "invalid config file {}: {msg}", path.display()),
+      InvalidEnvVar { name, value } => write!(f, "invalid value for {name}: {value:?}"),
+      InvalidBrightness(b) => write!(f, "invalid brightness {b}. Valid range is 0.0 ..= 1.0"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use std::time::Duration;
+
+  use super::{find_config_file, Config, ConfigError, ConfigLayer, CONFIG_FILE_NAME};
+
+  fn env(vars: &[(&str, &str)]) -> ConfigLayer {
+    ConfigLayer::from_env_vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+  }
+
+  fn file(contents: &str) -> ConfigLayer {
+    ConfigLayer::from_toml_str(contents, Path::new(CONFIG_FILE_NAME)).unwrap()
+  }
+
+  #[test]
+  fn test_defaults() {
+    let config = Config::from_layers(
+      ConfigLayer::default(),
+      ConfigLayer::default(),
+      ConfigLayer::default(),
+    )
+    .unwrap();
+    assert_eq!(config.in_port, None);
+    assert_eq!(config.receive_timeout, Duration::from_secs(30));
+    assert_eq!(config.max_busy_retries, 10);
+    assert_eq!(config.brightness, 1.0);
+    assert_eq!(config.log_level, None);
+  }
+
+  #[test]
+  fn test_precedence() {
+    let cli = ConfigLayer {
+      in_port: Some("cli in".to_string()),
+      ..Default::default()
+    };
+    let env = env(&[
+      ("LUMATONE_IN_PORT", "env in"),
+      ("LUMATONE_OUT_PORT", "env out"),
+      ("LUMATONE_RECEIVE_TIMEOUT", "5"),
+      ("LUMATONE_MAX_BUSY_RETRIES", "2"),
+      ("PATH", "/usr/bin"),
+    ]);
+    let file = file(
+      r#"
+      in_port = "file in"
+      out_port = "file out"
+      receive_timeout = 10
+      max_busy_retries = 20
+      brightness = 0.25
+      "#,
+    );
+
+    let config = Config::from_layers(cli, env, file).unwrap();
+    assert_eq!(config.in_port.as_deref(), Some("cli in"));
+    assert_eq!(config.out_port.as_deref(), Some("env out"));
+    assert_eq!(config.receive_timeout, Duration::from_secs(5));
+    assert_eq!(config.max_busy_retries, 2);
+    assert_eq!(config.brightness, 0.25);
+  }
+
+  #[test]
+  fn test_invalid_values() {
+    assert!(matches!(
+      ConfigLayer::from_env_vars([("LUMATONE_RECEIVE_TIMEOUT".to_string(), "soon".to_string())]),
+      Err(ConfigError::InvalidEnvVar { .. })
+    ));
+    assert!(matches!(
+      ConfigLayer::from_toml_str("in_prot = \"typo\"", Path::new(CONFIG_FILE_NAME)),
+      Err(ConfigError::InvalidFile(..))
+    ));
+    assert!(matches!(
+      Config::from_layers(
+        ConfigLayer::default(),
+        env(&[("LUMATONE_BRIGHTNESS", "1.5")]),
+        ConfigLayer::default()
+      ),
+      Err(ConfigError::InvalidBrightness(_))
+    ));
+  }
+
+  #[test]
+  fn test_find_config_file() {
+    let root = std::env::temp_dir().join(format!("lumatone-config-{}", std::process::id()));
+    let cwd = root.join("cwd");
+    let config_dir = root.join("config");
+    std::fs::create_dir_all(&cwd).unwrap();
+    std::fs::create_dir_all(&config_dir).unwrap();
+
+    assert_eq!(find_config_file(Some(&cwd), Some(&config_dir)), None);
+
+    std::fs::write(config_dir.join(CONFIG_FILE_NAME), "").unwrap();
+    assert_eq!(
+      find_config_file(Some(&cwd), Some(&config_dir)),
+      Some(config_dir.join(CONFIG_FILE_NAME))
+    );
+
+    std::fs::write(cwd.join(CONFIG_FILE_NAME), "").unwrap();
+    assert_eq!(
+      find_config_file(Some(&cwd), Some(&config_dir)),
+      Some(cwd.join(CONFIG_FILE_NAME))
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
@@ -1,6 +1,8 @@
 mod cmd;
+mod config;
 
 use crate::cmd::CliCommand;
+use crate::config::{Config, ConfigLayer};
 
 use clap::Parser;
 use tokio;
@@ -10,14 +12,31 @@ use tokio;
 struct Cli {
   #[clap(subcommand)]
   command: CliCommand,
+
+  #[clap(flatten)]
+  config: ConfigLayer,
+
+  /// Use the device even if another lumatone-rs process (e.g. the GUI) has it locked
+  #[clap(long, global = true)]
+  steal: bool,
 }
 
 #[tokio::main]
 async fn main() {
-  let default_log_level = "debug";
-  let env = env_logger::Env::default().filter_or("RUST_LOG", default_log_level);
-  env_logger::init_from_env(env);
-
   let cli = Cli::parse();
-  cli.command.run().await;
+  let mut config = match Config::resolve(&cli.config) {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("{err}");
+      std::process::exit(2);
+    }
+  };
+
+  config.steal_device_lock = cli.steal;
+
+  env_logger::Builder::new()
+    .parse_filters(&config.log_filter())
+    .init();
+
+  cli.command.run(&config).await;
 }
@@ -0,0 +1,23 @@
+//! Helpers for making the editor usable from the keyboard and with screen readers.
+
+/// Returns true if `key` (as given by a keyboard event's `key()`) should activate a
+/// focused button-like element, the same as clicking on it.
+pub fn is_activation_key(key: &str) -> bool {
+  matches!(key, "Enter" | " ")
+}
+
+/// Returns the index of the tab that `key` moves to from the tab at `current`, following
+/// the usual tablist conventions: the arrow keys move to the previous or next tab, wrapping
+/// around at either end, and Home and End move to the first and last tabs.
+pub fn tab_index_for_key(key: &str, current: usize, count: usize) -> Option<usize> {
+  if count == 0 {
+    return None;
+  }
+  match key {
+    "ArrowLeft" => Some((current + count - 1) % count),
+    "ArrowRight" => Some((current + 1) % count),
+    "Home" => Some(0),
+    "End" => Some(count - 1),
+    _ => None,
+  }
+}
@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use dioxus::prelude::*;
+use lumatone_core::keymap::ltn::{ApplyParts, LumatoneKeyMap};
+use lumatone_core::midi::commands::Command;
+use lumatone_core::midi::constants::BoardIndex;
+
+#[derive(Props)]
+pub struct ApplyDialogProps<'a> {
+  keymap: Rc<LumatoneKeyMap>,
+
+  /// Called with the commands for the checked boards when the Apply button is clicked.
+  on_apply: EventHandler<'a, Vec<Command>>,
+  on_cancel: EventHandler<'a, ()>,
+}
+
+/// Asks which boards to send a keymap to, with a checkbox for each octave board and the
+/// number of commands it would get. Commands for the keyboard as a whole (options,
+/// tables and macro buttons) are always sent.
+pub fn ApplyDialog<'a>(cx: Scope<'a, ApplyDialogProps<'a>>) -> Element<'a> {
+  let ApplyDialogProps {
+    keymap,
+    on_apply,
+    on_cancel,
+  } = cx.props;
+  let included = use_state(cx, BoardIndex::all_octaves);
+
+  let all_commands = keymap.to_midi_commands();
+  let count_for = |board: Option<BoardIndex>| {
+    all_commands
+      .iter()
+      .filter(|c| c.board_index() == board)
+      .count()
+  };
+  let global_count = count_for(None);
+  let total: usize = global_count
+    + included
+      .get()
+      .iter()
+      .map(|board| count_for(Some(*board)))
+      .sum::<usize>();
+
+  let boards = BoardIndex::all_octaves().into_iter().map(|board| {
+    let count = count_for(Some(board));
+    let checked = included.get().contains(&board);
+    rsx! {
+      li {
+        key: "{board}",
+        label {
+          input {
+            r#type: "checkbox",
+            checked: checked,
+            onchange: move |_| {
+              let mut next = included.get().clone();
+              if checked {
+                next.retain(|b| *b != board);
+              } else {
+                next.push(board);
+              }
+              included.set(next);
+            },
+          }
+          "{board}"
+        }
+        span { class: "command-count", "{count} commands" }
+      }
+    }
+  });
+
+  cx.render(rsx! {
+    div {
+      class: "apply-dialog",
+      role: "dialog",
+      "aria-label": "Apply to device",
+      style { include_str!("./style.css") }
+
+      h3 { "Apply to device" }
+      ul {
+        boards
+        li {
+          "Whole keyboard"
+          span { class: "command-count", "{global_count} commands" }
+        }
+      }
+      p { "{total} commands will be sent." }
+      div {
+        class: "apply-dialog-buttons",
+        button {
+          onclick: move |_| on_cancel.call(()),
+          "Cancel"
+        }
+        button {
+          disabled: total == 0,
+          onclick: move |_| {
+            let boards = included.get();
+            on_apply.call(keymap.to_midi_commands_for_boards(ApplyParts::all(), boards));
+          },
+          "Apply"
+        }
+      }
+    }
+  })
+}
@@ -0,0 +1,163 @@
+use dioxus::prelude::*;
+use lumatone_core::geometry::axis::{sort_keys_along_axis, HexAxis};
+use lumatone_core::keymap::error::LumatoneKeymapError;
+use lumatone_core::keymap::labels::{location_label, spoken_note_name};
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::midi::constants::{LumatoneKeyLocation, RGBColor};
+
+#[derive(Props)]
+pub struct AssignNotesDialogProps<'a> {
+  keymap: &'a UseRef<LumatoneKeyMap>,
+
+  /// The selected keys, in any order.
+  keys: Vec<LumatoneKeyLocation>,
+
+  /// The colors of the current tuning's pitch classes. Coloring keys by pitch class is
+  /// only offered if this is given.
+  #[props(!optional)]
+  palette: Option<Vec<RGBColor>>,
+
+  /// Called once the notes have been assigned to the keymap.
+  on_done: EventHandler<'a, ()>,
+  on_cancel: EventHandler<'a, ()>,
+}
+
+/// Assigns a run of note numbers to the selected keys, in their order along a hex axis,
+/// e.g. to make a diagonal into a chromatic scale. The axis starts out as the one the
+/// keys lie on, if they form a line. Optionally colors each key by its pitch class.
+pub fn AssignNotesDialog<'a>(cx: Scope<'a, AssignNotesDialogProps<'a>>) -> Element<'a> {
+  let AssignNotesDialogProps {
+    keymap,
+    keys,
+    palette,
+    on_done,
+    on_cancel,
+  } = cx.props;
+  let axis = use_state(cx, || HexAxis::of_line(keys).unwrap_or(HexAxis::Row));
+  let start = use_state(cx, || 60_u8);
+  let step = use_state(cx, || 1_i8);
+  let color_by_pitch_class = use_state(cx, || false);
+  let error = use_state(cx, || None::<String>);
+
+  let ordered = sort_keys_along_axis(keys, *axis.get());
+  let preview = ordered.iter().enumerate().map(|(i, location)| {
+    let note = *start.get() as i32 + *step.get() as i32 * i as i32;
+    let note_name = u8::try_from(note)
+      .ok()
+      .filter(|n| *n <= 127)
+      .map(spoken_note_name)
+      .unwrap_or_else(|| "out of range".to_string());
+    let place = location_label(location);
+    rsx! {
+      li { key: "{place}", "{place}: {note} ({note_name})" }
+    }
+  });
+
+  let axis_options = HexAxis::ALL.into_iter().map(|a| {
+    let value = a.to_string();
+    rsx! {
+      option {
+        key: "{value}",
+        value: "{value}",
+        selected: a == *axis.get(),
+        "{value}"
+      }
+    }
+  });
+
+  let on_line = HexAxis::of_line(keys).is_some();
+  let error_text = error.get().clone();
+
+  cx.render(rsx! {
+    div {
+      class: "assign-notes-dialog",
+      role: "dialog",
+      "aria-label": "Assign notes",
+      style { include_str!("./style.css") }
+
+      h3 { "Assign notes" }
+      if !on_line {
+        rsx! { p { class: "hint", "The selected keys aren't in a line, so they're ordered along the axis, then across it." } }
+      }
+      label {
+        "Axis "
+        select {
+          onchange: move |evt| {
+            if let Some(a) = HexAxis::ALL.into_iter().find(|a| a.to_string() == evt.value) {
+              axis.set(a);
+            }
+          },
+          axis_options
+        }
+      }
+      label {
+        "First note "
+        input {
+          r#type: "number",
+          min: "0",
+          max: "127",
+          value: "{start}",
+          oninput: move |evt| {
+            if let Ok(n) = evt.value.parse::<u8>() {
+              start.set(n.min(127));
+            }
+          },
+        }
+      }
+      label {
+        "Step "
+        input {
+          r#type: "number",
+          min: "-127",
+          max: "127",
+          value: "{step}",
+          oninput: move |evt| {
+            if let Ok(n) = evt.value.parse::<i8>() {
+              step.set(n);
+            }
+          },
+        }
+      }
+      label {
+        input {
+          r#type: "checkbox",
+          checked: *color_by_pitch_class.get(),
+          disabled: palette.is_none(),
+          onchange: move |_| color_by_pitch_class.set(!color_by_pitch_class.get()),
+        }
+        "Color keys by pitch class"
+      }
+      ol { preview }
+      error_text.map(|msg| rsx! { p { class: "error", role: "alert", "{msg}" } })
+      div {
+        class: "assign-notes-dialog-buttons",
+        button {
+          onclick: move |_| on_cancel.call(()),
+          "Cancel"
+        }
+        button {
+          disabled: keys.is_empty(),
+          onclick: move |_| {
+            let colors = palette.as_deref().filter(|_| *color_by_pitch_class.get());
+            let ordered = sort_keys_along_axis(keys, *axis.get());
+            let assigned = keymap
+              .write()
+              .assign_note_run(&ordered, *start.get(), *step.get(), colors)
+              .map(|_| ());
+            match assigned {
+              Ok(()) => {
+                error.set(None);
+                on_done.call(());
+              }
+              Err(LumatoneKeymapError::NoteOutOfRange(note)) => {
+                error.set(Some(format!("The run would reach note {note}, outside the MIDI range of 0 to 127.")));
+              }
+              Err(err) => error.set(Some(format!("Unable to assign notes: {err:?}"))),
+            }
+          },
+          "Assign"
+        }
+      }
+    }
+  })
+}
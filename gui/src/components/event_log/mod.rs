@@ -0,0 +1,93 @@
+use dioxus::prelude::*;
+use lumatone_core::midi::event_log::{EventLog, Severity};
+
+#[derive(Props)]
+pub struct EventLogPanelProps<'a> {
+  log: &'a UseRef<EventLog>,
+}
+
+/// A collapsible panel listing recent driver activity from an [EventLog], newest last,
+/// with a severity filter and a button that copies the shown entries to the clipboard
+/// for pasting into a bug report.
+///
+/// Use [use_driver_log](crate::hooks::usedriverlog::use_driver_log) to keep the log fed.
+pub fn EventLogPanel<'a>(cx: Scope<'a, EventLogPanelProps<'a>>) -> Element<'a> {
+  let log = cx.props.log;
+  let min_severity = use_state(cx, || Severity::Info);
+  let eval = use_eval(cx);
+
+  let options = Severity::ALL.iter().enumerate().map(|(i, severity)| {
+    rsx! {
+      option {
+        key: "{i}",
+        value: "{i}",
+        selected: *severity == *min_severity.get(),
+        "{severity}"
+      }
+    }
+  });
+
+  let entries: Vec<_> = log
+    .read()
+    .entries(*min_severity.get())
+    .map(|entry| (entry.severity, entry.to_string()))
+    .collect();
+  let items = entries.iter().enumerate().map(|(i, (severity, text))| {
+    let class = match severity {
+      Severity::Debug => "debug",
+      Severity::Info => "info",
+      Severity::Warning => "warning",
+      Severity::Error => "error",
+    };
+    rsx! {
+      li { key: "{i}", class: class, "{text}" }
+    }
+  });
+  let count = log.read().len();
+
+  cx.render(rsx! {
+    details {
+      class: "event-log",
+      style { include_str!("./style.css") }
+
+      summary { "Device log ({count})" }
+
+      div {
+        class: "event-log-controls",
+
+        label {
+          "Show "
+          select {
+            onchange: move |evt| {
+              let chosen = evt.value.parse::<usize>().ok().and_then(|i| Severity::ALL.get(i));
+              if let Some(severity) = chosen {
+                min_severity.set(*severity);
+              }
+            },
+            options
+          }
+          " and above"
+        }
+        button {
+          onclick: move |_| {
+            let text = log.read().to_text(*min_severity.get());
+            // serializing a String can't fail, and gives us a safely quoted JS string literal
+            let literal = serde_json::to_string(&text).unwrap_or_default();
+            let _ = eval(&format!("navigator.clipboard.writeText({literal})"));
+          },
+          "Copy to clipboard"
+        }
+        button {
+          onclick: move |_| log.write().clear(),
+          "Clear"
+        }
+      }
+
+      ol {
+        class: "event-log-entries",
+        "aria-live": "polite",
+        items
+      }
+    }
+  })
+}
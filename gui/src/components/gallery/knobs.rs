@@ -0,0 +1,125 @@
+//! Simple controls for adjusting the props of a gallery entry.
+//!
+//! Each knob is bound to a piece of state owned by the gallery entry, so the entry
+//! re-renders its component whenever a knob changes. Group knobs in a [Knobs] panel.
+
+use dioxus::prelude::*;
+
+#[derive(Props)]
+pub struct KnobsProps<'a> {
+  children: Element<'a>,
+}
+
+/// A panel that lays out a group of knobs above a gallery entry.
+pub fn Knobs<'a>(cx: Scope<'a, KnobsProps<'a>>) -> Element {
+  cx.render(rsx! {
+    div {
+      class: "knobs",
+      &cx.props.children
+    }
+  })
+}
+
+#[derive(Props)]
+pub struct SliderKnobProps<'a> {
+  label: &'a str,
+  min: i64,
+  max: i64,
+  value: &'a UseState<i64>,
+}
+
+/// A range slider for an integer prop.
+pub fn SliderKnob<'a>(cx: Scope<'a, SliderKnobProps<'a>>) -> Element {
+  let SliderKnobProps {
+    label,
+    min,
+    max,
+    value,
+  } = cx.props;
+
+  cx.render(rsx! {
+    label {
+      class: "knob",
+      "{label}"
+      input {
+        r#type: "range",
+        min: "{min}",
+        max: "{max}",
+        value: "{value}",
+        oninput: move |evt| {
+          if let Ok(v) = evt.value.parse::<i64>() {
+            value.set(v);
+          }
+        },
+      }
+      span { class: "knob-value", "{value}" }
+    }
+  })
+}
+
+#[derive(Props)]
+pub struct SelectKnobProps<'a> {
+  label: &'a str,
+  options: &'a [&'a str],
+
+  /// Index into `options` of the selected option.
+  selected: &'a UseState<usize>,
+}
+
+/// A dropdown for choosing one of a fixed set of options.
+pub fn SelectKnob<'a>(cx: Scope<'a, SelectKnobProps<'a>>) -> Element {
+  let SelectKnobProps {
+    label,
+    options,
+    selected,
+  } = cx.props;
+
+  let items = options.iter().enumerate().map(|(i, name)| {
+    rsx! {
+      option {
+        key: "{i}",
+        value: "{i}",
+        selected: i == *selected.get(),
+        "{name}"
+      }
+    }
+  });
+
+  cx.render(rsx! {
+    label {
+      class: "knob",
+      "{label}"
+      select {
+        onchange: move |evt| {
+          if let Ok(i) = evt.value.parse::<usize>() {
+            selected.set(i);
+          }
+        },
+        items
+      }
+    }
+  })
+}
+
+#[derive(Props)]
+pub struct ToggleKnobProps<'a> {
+  label: &'a str,
+  value: &'a UseState<bool>,
+}
+
+/// A checkbox for a boolean prop.
+pub fn ToggleKnob<'a>(cx: Scope<'a, ToggleKnobProps<'a>>) -> Element {
+  let ToggleKnobProps { label, value } = cx.props;
+
+  cx.render(rsx! {
+    label {
+      class: "knob",
+      input {
+        r#type: "checkbox",
+        checked: *value.get(),
+        onchange: move |_| value.set(!*value.get()),
+      }
+      "{label}"
+    }
+  })
+}
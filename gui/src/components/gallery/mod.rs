@@ -0,0 +1,524 @@
+//! A developer-facing gallery of the reusable components, each rendered with a
+//! [knobs] panel for adjusting its props. This makes visual regressions easy to spot,
+//! and gives new contributors a quick tour of what's available.
+//!
+//! When adding a reusable component, add an entry for it to [Gallery].
+
+pub mod knobs;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::{
+  components::{
+    apply_dialog::ApplyDialog,
+    assign_notes::AssignNotesDialog,
+    event_log::EventLogPanel,
+    key_editor::KeyEditor,
+    key_search::{matching_keys, KeySearch},
+    keyboard::{
+      board::Board,
+      channels::ChannelView,
+      map::{
+        AnnotationMapper, ChannelMapper, DebugMapper, KeyMapper, LumatoneLocationDebugMapper,
+        PaintedMapper, SearchMapper,
+      },
+    },
+    palette_bar::PaletteBar,
+    tabs::{TabContainer, TabItem},
+    tunings::TuningManager,
+    wheel::ColorWheel,
+  },
+  harmony::view_model::{Scale, Tuning},
+  hooks::useuniqueid::use_unique_id,
+};
+use dioxus::html::input_data::keyboard_types::Modifiers;
+use dioxus::prelude::*;
+use lumatone_core::color::{
+  pinned::PinnedColors,
+  utils::{from_rgb_color, to_rgb_color},
+};
+use lumatone_core::geometry::{
+  coordinates::{gen_full_board_coords, gen_octave_coords, lumatone_location_for_hex, Hex},
+  layout::Layout,
+  Point,
+};
+use lumatone_core::keymap::annotations::KeyAnnotation;
+use lumatone_core::keymap::labels::spoken_note_name;
+use lumatone_core::keymap::ltn::{KeyDefinition, LumatoneKeyMap};
+use lumatone_core::midi::constants::{
+  key_loc_unchecked, BoardIndex, LumatoneKeyFunction, LumatoneKeyIndex, LumatoneKeyLocation,
+  MidiChannel, RGBColor,
+};
+use lumatone_core::midi::event_log::{EventLog, LogEntry, Severity};
+use palette::LinSrgb;
+
+use knobs::{Knobs, SelectKnob, SliderKnob, ToggleKnob};
+
+/// Renders one tab per gallery entry.
+pub fn Gallery(cx: Scope<()>) -> Element {
+  cx.render(rsx! {
+    div {
+      width: "100%",
+      height: "100%",
+
+      TabContainer {
+        tabs: vec![
+          TabItem {
+            title: "Keyboard",
+            id: "gallery-keyboard",
+            content: cx.render(rsx! { KeyboardEntry { } }),
+          },
+          TabItem {
+            title: "Annotations",
+            id: "gallery-annotations",
+            content: cx.render(rsx! { AnnotationsEntry { } }),
+          },
+          TabItem {
+            title: "Color Wheel",
+            id: "gallery-wheel",
+            content: cx.render(rsx! { WheelEntry { } }),
+          },
+          TabItem {
+            title: "Tunings",
+            id: "gallery-tunings",
+            content: cx.render(rsx! { TuningManager { } }),
+          },
+          TabItem {
+            title: "Tabs",
+            id: "gallery-tabs",
+            content: cx.render(rsx! { TabsEntry { } }),
+          },
+          TabItem {
+            title: "Event Log",
+            id: "gallery-event-log",
+            content: cx.render(rsx! { EventLogEntry { } }),
+          },
+          TabItem {
+            title: "Apply Dialog",
+            id: "gallery-apply-dialog",
+            content: cx.render(rsx! { ApplyDialogEntry { } }),
+          },
+          TabItem {
+            title: "Assign Notes",
+            id: "gallery-assign-notes",
+            content: cx.render(rsx! { AssignNotesEntry { } }),
+          },
+          TabItem {
+            title: "Key Search",
+            id: "gallery-key-search",
+            content: cx.render(rsx! { KeySearchEntry { } }),
+          },
+        ]
+      }
+    }
+  })
+}
+
+const KEYBOARD_MAPPERS: &[&str] = &["Hex coords", "Lumatone key indices", "MIDI channels"];
+
+fn KeyboardEntry(cx: Scope<()>) -> Element {
+  let hex_size = use_state(cx, || 25_i64);
+  let mapper_index = use_state(cx, || 0_usize);
+  let full_board = use_state(cx, || true);
+  let channel_keymap = cx.use_hook(|| Rc::new(channel_demo_keymap())).clone();
+  let paint_mode = use_state(cx, || false);
+  let paint_color = use_state(cx, || None::<RGBColor>);
+  let pinned = use_ref(cx, || {
+    PinnedColors::default_path()
+      .map(|path| PinnedColors::load(&path))
+      .unwrap_or_default()
+  });
+  let painted = use_ref(cx, HashMap::<Hex, LinSrgb>::new);
+
+  let size = *hex_size.get() as f64;
+  let layout = Layout::new(Point { x: size, y: size });
+  let coordinates: HashSet<Hex> = if *full_board.get() {
+    gen_full_board_coords()
+  } else {
+    gen_octave_coords(0).into_iter().collect()
+  };
+
+  let mapper: Option<Box<dyn KeyMapper>> = match *mapper_index.get() {
+    0 => Some(Box::new(DebugMapper {
+      color: LinSrgb::new(1.0, 0.0, 0.0),
+    })),
+    1 => Some(Box::new(LumatoneLocationDebugMapper {})),
+    _ => None,
+  };
+  let mapper = mapper.map(|base| -> Box<dyn KeyMapper> {
+    Box::new(PaintedMapper {
+      base,
+      painted: painted.read().clone(),
+    })
+  });
+  let tuning = Tuning::edo_12();
+  let tuning_colors: Vec<RGBColor> = (0..tuning.divisions())
+    .map(|i| to_rgb_color(tuning.get_color(i)))
+    .collect();
+
+  let board = match mapper {
+    Some(mapper) => rsx! {
+      svg {
+        width: "2000px",
+        height: "1200px",
+
+        Board {
+          layout: layout,
+          coordinates: coordinates,
+          mapper: mapper,
+          on_hex_clicked: move |coord| {
+            if let (true, Some(color)) = (*paint_mode.get(), *paint_color.get()) {
+              painted.write().insert(coord, from_rgb_color(color));
+            }
+          },
+        }
+      }
+    },
+    // the channel view always shows the full keyboard
+    None => rsx! {
+      ChannelView {
+        keymap: channel_keymap,
+        layout: layout,
+      }
+    },
+  };
+
+  cx.render(rsx! {
+    Knobs {
+      SliderKnob { label: "Hex size", min: 10, max: 40, value: hex_size }
+      SelectKnob { label: "Mapper", options: KEYBOARD_MAPPERS, selected: mapper_index }
+      ToggleKnob { label: "Full keyboard", value: full_board }
+      ToggleKnob { label: "Paint", value: paint_mode }
+    }
+    if *paint_mode.get() {
+      rsx! {
+        PaletteBar {
+          tuning_colors: tuning_colors,
+          pinned: pinned,
+          selected: paint_color,
+        }
+      }
+    }
+    board
+  })
+}
+
+fn AnnotationsEntry(cx: Scope<()>) -> Element {
+  let keymap = use_ref(cx, annotation_demo_keymap);
+  let query = use_state(cx, String::new);
+  let selected = use_state(cx, || None::<LumatoneKeyLocation>);
+
+  let layout = Layout::new(Point { x: 25.0, y: 25.0 });
+  let mapper = Box::new(AnnotationMapper::new(
+    Box::new(LumatoneLocationDebugMapper {}),
+    &keymap.read(),
+    query.get(),
+  ));
+
+  let editor = (*selected.get()).map(|location| {
+    let annotation = keymap
+      .read()
+      .annotation(location)
+      .cloned()
+      .unwrap_or_default();
+    rsx! {
+      KeyEditor {
+        location: location,
+        annotation: annotation,
+        on_change: move |a| {
+          keymap.write().set_annotation(location, a);
+        },
+      }
+    }
+  });
+
+  cx.render(rsx! {
+    label {
+      "Filter by tag "
+      input {
+        r#type: "search",
+        placeholder: "e.g. drone left",
+        value: "{query}",
+        oninput: move |evt| query.set(evt.value.clone()),
+      }
+    }
+    p { "Click a key to edit its annotation." }
+    editor
+    svg {
+      width: "2000px",
+      height: "1200px",
+
+      Board {
+        layout: layout,
+        coordinates: gen_full_board_coords(),
+        mapper: mapper,
+        on_hex_clicked: move |coord| {
+          selected.set(lumatone_location_for_hex(&coord).copied());
+        },
+      }
+    }
+  })
+}
+
+const WHEEL_SCALES: &[&str] = &["C major", "D major"];
+
+fn WheelEntry(cx: Scope<()>) -> Element {
+  let divisions = use_state(cx, || 12_i64);
+  let scale_index = use_state(cx, || 0_usize);
+
+  let tuning = match *divisions.get() {
+    12 => Tuning::edo_12(),
+    n => Tuning::edo(n as usize),
+  };
+  let scale = match *scale_index.get() {
+    0 => Scale::c_major(),
+    _ => Scale::d_major(),
+  };
+
+  cx.render(rsx! {
+    Knobs {
+      SliderKnob { label: "Divisions of the octave", min: 5, max: 53, value: divisions }
+      SelectKnob { label: "Scale (12 EDO only)", options: WHEEL_SCALES, selected: scale_index }
+    }
+    div {
+      max_width: "600px",
+      max_height: "600px",
+
+      ColorWheel {
+        tuning: tuning,
+        scale: scale,
+      }
+    }
+  })
+}
+
+const TAB_TITLES: &[&str] = &[
+  "First", "Second", "Third", "Fourth", "Fifth", "Sixth", "Seventh", "Eighth",
+];
+
+fn TabsEntry(cx: Scope<()>) -> Element {
+  let tab_count = use_state(cx, || 3_i64);
+
+  let tabs = TAB_TITLES
+    .iter()
+    .take(*tab_count.get() as usize)
+    .map(|&title| TabItem {
+      title,
+      id: title,
+      content: cx.render(rsx! {
+        p { "Content for the {title} tab" }
+      }),
+    })
+    .collect();
+
+  cx.render(rsx! {
+    Knobs {
+      SliderKnob { label: "Tabs", min: 1, max: TAB_TITLES.len() as i64, value: tab_count }
+    }
+    TabContainer { tabs: tabs }
+  })
+}
+
+fn EventLogEntry(cx: Scope<()>) -> Element {
+  // small, so it's easy to see the oldest entries being dropped
+  let log = use_ref(cx, || EventLog::new(8));
+  let next = use_state(cx, || 0_u64);
+
+  cx.render(rsx! {
+    button {
+      onclick: move |_| {
+        log.write().push(demo_log_entry(*next.get()));
+        next.set(*next.get() + 1);
+      },
+      "Add entry"
+    }
+    EventLogPanel { log: log }
+  })
+}
+
+fn ApplyDialogEntry(cx: Scope<()>) -> Element {
+  let keymap = cx.use_hook(|| Rc::new(channel_demo_keymap())).clone();
+  let outcome = use_state(cx, || "Nothing applied yet.".to_string());
+
+  cx.render(rsx! {
+    ApplyDialog {
+      keymap: keymap,
+      on_apply: move |commands: Vec<_>| {
+        outcome.set(format!("Would send {} commands.", commands.len()));
+      },
+      on_cancel: move |_| outcome.set("Cancelled.".to_string()),
+    }
+    p { "{outcome}" }
+  })
+}
+
+fn AssignNotesEntry(cx: Scope<()>) -> Element {
+  let keymap = use_ref(cx, channel_demo_keymap);
+  let outcome = use_state(cx, || "Nothing assigned yet.".to_string());
+  // a diagonal down from board 1, key 8
+  let keys: Vec<LumatoneKeyLocation> = (0..5)
+    .filter_map(|r| lumatone_location_for_hex(&Hex::new(0, 2 + r)).copied())
+    .collect();
+  let tuning = Tuning::edo_12();
+  let palette: Vec<RGBColor> = (0..tuning.divisions())
+    .map(|i| to_rgb_color(tuning.get_color(i)))
+    .collect();
+
+  let assigned = keys.iter().map(|location| {
+    let label = match keymap.read().get_key(*location).map(|def| def.function) {
+      Some(LumatoneKeyFunction::NoteOnOff { note_num, .. }) => spoken_note_name(note_num),
+      _ => "no note".to_string(),
+    };
+    rsx! {
+      li { key: "{location}", "{location}: {label}" }
+    }
+  });
+
+  // the list above borrows `keys`, so the dialog gets its own copy
+  let dialog_keys = keys.clone();
+  cx.render(rsx! {
+    AssignNotesDialog {
+      keymap: keymap,
+      keys: dialog_keys,
+      palette: Some(palette),
+      on_done: move |_| outcome.set("Assigned.".to_string()),
+      on_cancel: move |_| outcome.set("Cancelled.".to_string()),
+    }
+    p { "{outcome}" }
+    ul { assigned }
+  })
+}
+
+const SEARCH_TUNINGS: &[&str] = &["12 EDO", "31 EDO"];
+
+fn KeySearchEntry(cx: Scope<()>) -> Element {
+  let keymap = cx.use_hook(|| Rc::new(channel_demo_keymap())).clone();
+  let tuning_index = use_state(cx, || 0_usize);
+  let query = use_state(cx, String::new);
+  let keyboard_id = use_unique_id(cx, "key-search-keyboard");
+  let input_id = use_unique_id(cx, "key-search-input");
+  let eval = use_eval(cx);
+
+  let tuning = match *tuning_index.get() {
+    0 => Tuning::edo_12(),
+    _ => Tuning::edo(31),
+  };
+  let names: Vec<String> = (0..tuning.divisions())
+    .map(|i| tuning.get_pitch_class(i).name().to_string())
+    .collect();
+  let matches = matching_keys(&keymap, &names, query.get());
+  let mapper = Box::new(SearchMapper {
+    base: Box::new(ChannelMapper::new(keymap.clone(), None)),
+    matches: matches.map(|keys| keys.into_iter().collect()),
+  });
+  let layout = Layout::new(Point { x: 25.0, y: 25.0 });
+
+  cx.render(rsx! {
+    Knobs {
+      SelectKnob { label: "Tuning", options: SEARCH_TUNINGS, selected: tuning_index }
+    }
+    div {
+      // Ctrl+F (or Cmd+F) goes back to the search box, even from a key it jumped to
+      onkeydown: move |evt: KeyboardEvent| {
+        let modifiers = evt.modifiers();
+        let command = modifiers.contains(Modifiers::CONTROL) || modifiers.contains(Modifiers::META);
+        if command && evt.key().to_string().eq_ignore_ascii_case("f") {
+          let _ = eval(&format!("document.getElementById('{}')?.focus()", input_id.get()));
+        }
+      },
+
+      KeySearch {
+        keymap: keymap,
+        pitch_class_names: names,
+        query: query,
+        keyboard_id: keyboard_id.get(),
+        input_id: input_id.get(),
+      }
+      svg {
+        id: "{keyboard_id}",
+        width: "2000px",
+        height: "1200px",
+
+        Board {
+          layout: layout,
+          coordinates: gen_full_board_coords(),
+          mapper: mapper,
+        }
+      }
+    }
+  })
+}
+
+/// Cycles through the kinds of entry a real driver produces.
+fn demo_log_entry(n: u64) -> LogEntry {
+  let ping_latency = Some(Duration::from_millis(10 + n % 7));
+  let (severity, summary, detail, latency) = match n % 4 {
+    0 => (
+      Severity::Info,
+      format!("Ping({n})"),
+      Some(format!("Pong({n})")),
+      ping_latency,
+    ),
+    1 => (Severity::Warning, "device busy".to_string(), None, None),
+    2 => (
+      Severity::Error,
+      format!("Ping({n})"),
+      Some("response timed out".to_string()),
+      ping_latency,
+    ),
+    _ => (Severity::Debug, "queue drained".to_string(), None, None),
+  };
+  LogEntry {
+    elapsed: Duration::from_millis(n * 250),
+    severity,
+    summary,
+    detail,
+    latency,
+  }
+}
+
+/// An MPE-style keymap for the channel view, using two channels per board
+/// (lower and upper half of each octave).
+fn channel_demo_keymap() -> LumatoneKeyMap {
+  let mut keymap = LumatoneKeyMap::new();
+  for (i, board) in BoardIndex::all_octaves().into_iter().enumerate() {
+    let lower = MidiChannel::unchecked((i * 2 + 1) as u8);
+    let upper = MidiChannel::unchecked((i * 2 + 2) as u8);
+    let def_fn = |channel: MidiChannel| {
+      move |k: LumatoneKeyIndex| KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff {
+          channel,
+          note_num: k.get(),
+        },
+        color: RGBColor::red(),
+      }
+    };
+    keymap
+      .set_key_range(board, 0, 27, def_fn(lower))
+      .and_then(|keymap| keymap.set_key_range(board, 28, 55, def_fn(upper)))
+      .expect("demo key ranges are valid");
+  }
+  keymap
+}
+
+/// A keymap with a few annotated keys, for the annotations entry.
+fn annotation_demo_keymap() -> LumatoneKeyMap {
+  let annotate = |label: &str, tags: &[&str]| KeyAnnotation {
+    label: label.to_string(),
+    tags: tags.iter().map(|t| t.to_string()).collect(),
+  };
+  let mut keymap = LumatoneKeyMap::new();
+  keymap
+    .set_annotation(
+      key_loc_unchecked(1, 20),
+      annotate("Low drone", &["drone", "left-hand"]),
+    )
+    .set_annotation(key_loc_unchecked(2, 27), annotate("Tonic", &["root"]))
+    .set_annotation(
+      key_loc_unchecked(4, 30),
+      annotate("", &["lead", "right-hand"]),
+    );
+  keymap
+}
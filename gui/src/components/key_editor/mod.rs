@@ -0,0 +1,94 @@
+use dioxus::prelude::*;
+use lumatone_core::keymap::annotations::KeyAnnotation;
+use lumatone_core::keymap::labels::location_label;
+use lumatone_core::midi::constants::LumatoneKeyLocation;
+
+#[derive(Props)]
+pub struct KeyEditorProps<'a> {
+  location: LumatoneKeyLocation,
+  annotation: KeyAnnotation,
+
+  /// Called with the whole annotation whenever the label or tags change.
+  on_change: EventHandler<'a, KeyAnnotation>,
+}
+
+/// Edits the annotation of one key: a text field for its label, and its tags as chips.
+/// Typing a tag and pressing Enter adds it, and each chip has a button to remove it.
+pub fn KeyEditor<'a>(cx: Scope<'a, KeyEditorProps<'a>>) -> Element<'a> {
+  let KeyEditorProps {
+    location,
+    annotation,
+    on_change,
+  } = cx.props;
+  let new_tag = use_state(cx, String::new);
+
+  let heading = location_label(location);
+  let label = annotation.label.clone();
+
+  let chips = annotation.tags.iter().map(|tag| {
+    let text = tag.clone();
+    let remove_label = format!("Remove tag {tag}");
+    let removed = tag.clone();
+    rsx! {
+      span {
+        key: "{text}",
+        class: "tag-chip",
+        "{text}"
+        button {
+          "aria-label": "{remove_label}",
+          onclick: move |_| {
+            let mut next = annotation.clone();
+            next.remove_tag(&removed);
+            on_change.call(next);
+          },
+          "×"
+        }
+      }
+    }
+  });
+
+  let add_tag = move || {
+    let mut next = annotation.clone();
+    if next.add_tag(new_tag.get()) {
+      on_change.call(next);
+    }
+    new_tag.set(String::new());
+  };
+
+  cx.render(rsx! {
+    div {
+      class: "key-editor",
+      style { include_str!("./style.css") }
+
+      h3 { "{heading}" }
+      label {
+        "Label "
+        input {
+          value: "{label}",
+          oninput: move |evt| {
+            let mut next = annotation.clone();
+            next.label = evt.value.clone();
+            on_change.call(next);
+          },
+        }
+      }
+      div {
+        class: "tags",
+        role: "group",
+        "aria-label": "Tags",
+        chips
+        input {
+          placeholder: "Add a tag",
+          "aria-label": "New tag",
+          value: "{new_tag}",
+          oninput: move |evt| new_tag.set(evt.value.clone()),
+          onkeydown: move |evt: KeyboardEvent| {
+            if evt.key().to_string() == "Enter" {
+              add_tag();
+            }
+          },
+        }
+      }
+    }
+  })
+}
@@ -0,0 +1,136 @@
+use std::rc::Rc;
+
+use dioxus::html::input_data::keyboard_types::Modifiers;
+use dioxus::prelude::*;
+use lumatone_core::geometry::coordinates::hex_for_lumatone_location;
+use lumatone_core::keymap::error::LumatoneKeymapError;
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::keymap::search::{find_keys, KeyQuery};
+use lumatone_core::midi::constants::LumatoneKeyLocation;
+
+use crate::components::keyboard::key::key_selector;
+
+#[derive(Props)]
+pub struct KeySearchProps<'a> {
+  keymap: Rc<LumatoneKeyMap>,
+
+  /// The current tuning's pitch class names, one per step, for finding notes by name.
+  pitch_class_names: Vec<String>,
+
+  query: &'a UseState<String>,
+
+  /// The id of the element containing the keyboard, whose keys get focus when jumping
+  /// between matches.
+  keyboard_id: &'a str,
+
+  /// The id of the search box, e.g. for focusing it with a shortcut.
+  input_id: &'a str,
+}
+
+/// The keys matching `query`, in the order [KeySearch] jumps between them, or `None` if
+/// the query is blank or can't be parsed. Use this to highlight the matches, e.g. with a
+/// [SearchMapper](crate::components::keyboard::map::SearchMapper).
+pub fn matching_keys(
+  keymap: &LumatoneKeyMap,
+  pitch_class_names: &[String],
+  query: &str,
+) -> Option<Vec<LumatoneKeyLocation>> {
+  let query = KeyQuery::parse(query, pitch_class_names).ok()?;
+  Some(find_keys(keymap, &query))
+}
+
+/// A search box for finding keys by note number, note name, or `channel:note`. Enter
+/// moves focus to each matching key in turn, and Shift+Enter goes back.
+pub fn KeySearch<'a>(cx: Scope<'a, KeySearchProps<'a>>) -> Element<'a> {
+  let KeySearchProps {
+    keymap,
+    pitch_class_names,
+    query,
+    keyboard_id,
+    input_id,
+  } = cx.props;
+  let eval = use_eval(cx);
+  // the match that last got focus
+  let current = use_state(cx, || None::<usize>);
+
+  let found = match query.get().trim() {
+    "" => None,
+    q => Some(KeyQuery::parse(q, pitch_class_names).map(|q| find_keys(keymap, &q))),
+  };
+  let (status, is_error) = match &found {
+    None => (
+      "Type a note number, a note name like F#4, or a channel and note like 3:61.".to_string(),
+      false,
+    ),
+    Some(Err(LumatoneKeymapError::InvalidKeyQuery(reason))) => {
+      (format!("Can't search: {reason}."), true)
+    }
+    Some(Err(err)) => (format!("Can't search: {err:?}"), true),
+    Some(Ok(keys)) if keys.is_empty() => ("No keys play that note.".to_string(), false),
+    Some(Ok(keys)) => match *current.get() {
+      Some(i) => (
+        format!(
+          "Key {} of {}. Press Ctrl+F to come back here.",
+          i + 1,
+          keys.len()
+        ),
+        false,
+      ),
+      None if keys.len() == 1 => ("1 key matches. Press Enter to go to it.".to_string(), false),
+      None => (
+        format!(
+          "{} keys match. Press Enter to go to each in turn.",
+          keys.len()
+        ),
+        false,
+      ),
+    },
+  };
+  let found = found.and_then(Result::ok).unwrap_or_default();
+  let status_class = if is_error { "status error" } else { "status" };
+
+  cx.render(rsx! {
+    div {
+      class: "key-search",
+      role: "search",
+      style { include_str!("./style.css") }
+
+      label {
+        "Find key "
+        input {
+          id: "{input_id}",
+          r#type: "search",
+          placeholder: "e.g. 61, F#4 or 3:61",
+          value: "{query}",
+          oninput: move |evt| {
+            query.set(evt.value.clone());
+            current.set(None);
+          },
+          onkeydown: move |evt: KeyboardEvent| {
+            if evt.key().to_string() != "Enter" || found.is_empty() {
+              return;
+            }
+            let backwards = evt.modifiers().contains(Modifiers::SHIFT);
+            let next = next_match(*current.get(), found.len(), backwards);
+            current.set(Some(next));
+            if let Some(hex) = hex_for_lumatone_location(&found[next]) {
+              let selector = format!("#{keyboard_id} {}", key_selector(*hex));
+              let _ = eval(&format!("document.querySelector('{selector}')?.focus()"));
+            }
+          },
+        }
+      }
+      p { class: "{status_class}", role: "status", "{status}" }
+    }
+  })
+}
+
+/// The index of the match to jump to from `current`, wrapping around at either end.
+fn next_match(current: Option<usize>, count: usize, backwards: bool) -> usize {
+  match (current, backwards) {
+    (None, false) => 0,
+    (None, true) => count - 1,
+    (Some(i), false) => (i + 1) % count,
+    (Some(i), true) => (i + count - 1) % count,
+  }
+}
@@ -20,6 +20,11 @@ pub fn Board<'a>(cx: Scope<'a, BoardProps<'a>>) -> Element {
           key: "{dioxus_key}",
           fill_color: def.color,
           label: def.label,
+          description: def.description,
+          tooltip: def.tooltip,
+          badge: def.badge,
+          dimmed: def.dimmed,
+          highlighted: def.highlighted,
           layout: &cx.props.layout,
           coord: *c,
           on_click: move |coord| {
@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use dioxus::prelude::*;
+use lumatone_core::color::{palette::ColorPalette, utils::ToHexColorStr};
+use lumatone_core::geometry::{coordinates::gen_full_board_coords, layout::Layout};
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::midi::constants::MidiChannel;
+
+use super::{board::Board, map::ChannelMapper};
+
+#[derive(Props)]
+pub struct ChannelViewProps<'a> {
+  keymap: Rc<LumatoneKeyMap>,
+  layout: Layout,
+
+  /// Called when the channel filter changes, with `None` when it's cleared.
+  on_channel_selected: Option<EventHandler<'a, Option<MidiChannel>>>,
+}
+
+/// Shows a keymap with keys colored by MIDI channel, along with a legend.
+/// Clicking a channel in the legend shows only the keys on that channel;
+/// clicking it again clears the filter.
+pub fn ChannelView<'a>(cx: Scope<'a, ChannelViewProps<'a>>) -> Element {
+  let only_channel = use_state(cx, || None::<MidiChannel>);
+
+  // sorted by channel number, so the legend order doesn't depend on key order
+  let channels: Vec<MidiChannel> = cx
+    .props
+    .keymap
+    .keys()
+    .filter_map(|(_, def)| def.function.channel())
+    .map(|ch| ch.get())
+    .collect::<BTreeSet<u8>>()
+    .into_iter()
+    .map(MidiChannel::unchecked)
+    .collect();
+
+  let mapper = Box::new(ChannelMapper::new(
+    cx.props.keymap.clone(),
+    *only_channel.get(),
+  ));
+
+  cx.render(rsx! {
+    div {
+      ChannelLegend {
+        channels: channels,
+        selected: *only_channel.get(),
+        on_select: move |ch| {
+          only_channel.set(ch);
+          if let Some(handler) = &cx.props.on_channel_selected {
+            handler.call(ch);
+          }
+        },
+      }
+      svg {
+        width: "2000px",
+        height: "1200px",
+
+        Board {
+          layout: cx.props.layout,
+          coordinates: gen_full_board_coords(),
+          mapper: mapper,
+        }
+      }
+    }
+  })
+}
+
+#[derive(Props)]
+pub struct ChannelLegendProps<'a> {
+  channels: Vec<MidiChannel>,
+  #[props(!optional)]
+  selected: Option<MidiChannel>,
+  on_select: EventHandler<'a, Option<MidiChannel>>,
+}
+
+/// A list of channel → color swatches. Clicking a channel selects it, or clears
+/// the selection if it was already selected.
+pub fn ChannelLegend<'a>(cx: Scope<'a, ChannelLegendProps<'a>>) -> Element {
+  let palette = ColorPalette::midi_channels();
+  let items = cx.props.channels.iter().map(|ch| {
+    let ch = *ch;
+    let num = ch.get();
+    let color = palette.get_for_channel(ch).to_hex_color();
+    let is_selected = cx.props.selected == Some(ch);
+    let class = if is_selected { "selected" } else { "" };
+    rsx! {
+      li {
+        key: "{num}",
+        class: "{class}",
+        onclick: move |_| {
+          let next = if is_selected { None } else { Some(ch) };
+          cx.props.on_select.call(next);
+        },
+
+        span {
+          class: "swatch",
+          background_color: "{color}",
+        }
+        "Channel {num}"
+      }
+    }
+  });
+
+  cx.render(rsx! {
+    ul {
+      class: "channel-legend",
+      items
+    }
+  })
+}
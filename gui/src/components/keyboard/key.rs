@@ -2,7 +2,10 @@ use dioxus::prelude::*;
 use palette::LinSrgb;
 
 use lumatone_core::geometry::{coordinates::Hex, layout::Layout};
-use lumatone_core::color::utils::{text_color_for_bgcolor, ToHexColorStr};
+use lumatone_core::color::utils::{label_colors_for_bgcolor, ToHexColorStr};
+
+use crate::components::a11y::is_activation_key;
+
 #[derive(Props)]
 pub struct KeyProps<'a> {
   layout: &'a Layout,
@@ -13,24 +16,80 @@ pub struct KeyProps<'a> {
 
   #[props(into)]
   label: Option<String>,
+  /// Defaults to black or white, whichever is more legible over the fill color.
   label_color: Option<LinSrgb>,
+
+  /// Whether to outline the default label color when the fill color is too close to the
+  /// middle for black or white text to stand out on its own.
+  #[props(default = true)]
+  label_halo: bool,
+
+  /// Read out by screen readers. Defaults to the label.
+  #[props(default, !optional)]
+  description: Option<String>,
+
+  /// Shown when hovering over the key.
+  #[props(default, !optional)]
+  tooltip: Option<String>,
+
+  /// Draws a small dot near the top of the key.
+  #[props(default)]
+  badge: bool,
+
+  /// Fades the key out.
+  #[props(default)]
+  dimmed: bool,
+
+  /// Outlines the key with a thick, bright border, e.g. to mark a search result.
+  #[props(default)]
+  highlighted: bool,
+}
+
+/// A CSS selector for the element of the key at `coord`, e.g. for focusing it from a script.
+/// Scope it to the keyboard's container if there may be more than one keyboard on the page.
+pub fn key_selector(coord: Hex) -> String {
+  format!("[data-hex=\"{}\"]", hex_data_attr(coord))
+}
+
+fn hex_data_attr(coord: Hex) -> String {
+  format!("{},{}", coord.q(), coord.r())
 }
 
 pub fn Key<'a>(cx: Scope<'a, KeyProps<'a>>) -> Element {
   let fill = cx.props.fill_color.to_hex_color();
-  let stroke = "black"; // TODO: add to props?
+  let (stroke, stroke_width) = if cx.props.highlighted {
+    ("#ffb000", "3")
+  } else {
+    ("black", "1") // TODO: add to props?
+  };
   let layout = cx.props.layout;
   let center = layout.hex_to_pixel(cx.props.coord);
   let points = layout.svg_polygon_points(cx.props.coord);
 
   let label = cx.props.label.clone().unwrap_or(String::new());
+  let auto_colors = label_colors_for_bgcolor(cx.props.fill_color);
   let label_color = cx
     .props
     .label_color
-    .map(|c| c.to_hex_color())
-    .unwrap_or(text_color_for_bgcolor(cx.props.fill_color).to_hex_color());
+    .unwrap_or(auto_colors.text)
+    .to_hex_color();
+  // an explicit label color is used as-is
+  let label_halo = match (cx.props.label_color, auto_colors.halo) {
+    (None, Some(halo)) if cx.props.label_halo => Some(halo.to_hex_color()),
+    _ => None,
+  };
+  let label_stroke = label_halo.clone().unwrap_or_else(|| label_color.clone());
+  let label_stroke_width = if label_halo.is_some() { "3" } else { "1" };
+
+  let description = cx.props.description.clone().unwrap_or(label.clone());
 
   let coord = cx.props.coord;
+  let data_hex = hex_data_attr(coord);
+  let activate = move || {
+    if let Some(handler) = &cx.props.on_click {
+      handler.call(coord);
+    }
+  };
 
   // scale label size, based on the default font size looking decent for 30px hexes
   // note that the y offset to center the label is a bit brittle (assumes 16px / em)
@@ -38,29 +97,69 @@ pub fn Key<'a>(cx: Scope<'a, KeyProps<'a>>) -> Element {
   let font_scalar = hex_size / 30.0;
   let y_offset = font_scalar * 4.0;
 
+  let opacity = if cx.props.dimmed { "0.3" } else { "1" };
+  let tooltip = cx.props.tooltip.as_ref().map(|text| {
+    rsx! {
+      title { "{text}" }
+    }
+  });
+  let badge = cx.props.badge.then(|| {
+    // the dot takes the label's color, outlined in the key's own
+    let badge_fill = label_color.clone();
+    let badge_stroke = fill.clone();
+    let badge_y = center.y - hex_size * 0.55;
+    let badge_r = hex_size * 0.12;
+    rsx! {
+      circle {
+        class: "badge",
+        "aria-hidden": "true",
+        cx: "{center.x}",
+        cy: "{badge_y}",
+        r: "{badge_r}",
+        fill: "{badge_fill}",
+        stroke: "{badge_stroke}",
+      }
+    }
+  });
+
   cx.render(rsx! {
     g {
+      class: "key",
+      role: "button",
+      tabindex: "0",
+      "aria-label": "{description}",
+      "data-hex": "{data_hex}",
+      opacity: opacity,
+      onkeydown: move |event| {
+        if is_activation_key(&event.key().to_string()) {
+          activate();
+        }
+      },
+
       polygon {
         fill: "{fill}",
         stroke: stroke,
+        "stroke-width": stroke_width,
         points: "{points}",
-        onclick: move |_event| {
-          if let Some(handler) = &cx.props.on_click {
-            handler.call(coord);
-          }
-        },
+        onclick: move |_event| activate(),
       }
       text {
+        "aria-hidden": "true",
         x: center.x,
         y: center.y,
         text_anchor: "middle",
-        stroke: "{label_color}",
+        stroke: "{label_stroke}",
+        "stroke-width": "{label_stroke_width}",
+        "stroke-linejoin": "round",
+        "paint-order": "stroke",
         fill: "{label_color}",
         font_size: "{font_scalar}em",
         transform: "translate(0 {y_offset})",
 
         label
       }
+      badge
+      tooltip
     }
   })
 }
@@ -1,11 +1,28 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
 use palette::LinSrgb;
 
-use lumatone_core::color::palette::wheel_colors;
+use lumatone_core::color::palette::{wheel_colors, ColorPalette};
 use lumatone_core::geometry::coordinates::{lumatone_location_for_hex, Hex};
+use lumatone_core::keymap::annotations::KeyAnnotation;
+use lumatone_core::keymap::labels::{key_label, location_label};
+use lumatone_core::keymap::ltn::LumatoneKeyMap;
+use lumatone_core::midi::constants::{LumatoneKeyLocation, MidiChannel};
 
 pub struct KeyDefinition {
   pub color: LinSrgb,
   pub label: String,
+  /// Read out by screen readers in place of the label, if set.
+  pub description: Option<String>,
+  /// Shown when hovering over the key, if set.
+  pub tooltip: Option<String>,
+  /// Whether to mark the key with a small badge, e.g. because it has an annotation.
+  pub badge: bool,
+  /// Whether to fade the key out, e.g. because it doesn't match a filter.
+  pub dimmed: bool,
+  /// Whether to outline the key, e.g. because it matches a search.
+  pub highlighted: bool,
   // TODO: everything else...
 }
 
@@ -23,6 +40,11 @@ impl KeyMapper for DebugMapper {
     Some(KeyDefinition {
       color: self.color.clone(),
       label,
+      description: None,
+      tooltip: None,
+      badge: false,
+      dimmed: false,
+      highlighted: false,
     })
   }
 }
@@ -36,7 +58,145 @@ impl KeyMapper for LumatoneLocationDebugMapper {
       let board_index: u8 = loc.board_index().into();
       let color = colors[(board_index as usize) - 1];
       let label = format!("{}", loc.key_index());
-      KeyDefinition { color, label }
+      let description = Some(location_label(loc));
+      KeyDefinition {
+        color,
+        label,
+        description,
+        tooltip: None,
+        badge: false,
+        dimmed: false,
+        highlighted: false,
+      }
+    })
+  }
+}
+
+/// Colors keys by MIDI channel instead of their stored color, and labels them with
+/// their channel number. This is purely a view transform; the keymap is never modified.
+pub struct ChannelMapper {
+  pub keymap: Rc<LumatoneKeyMap>,
+  pub palette: ColorPalette,
+  /// If set, only keys on this channel are colored, and everything else is grayed out.
+  pub only_channel: Option<MidiChannel>,
+}
+
+impl ChannelMapper {
+  pub fn new(keymap: Rc<LumatoneKeyMap>, only_channel: Option<MidiChannel>) -> Self {
+    ChannelMapper {
+      keymap,
+      palette: ColorPalette::midi_channels(),
+      only_channel,
+    }
+  }
+}
+
+impl KeyMapper for ChannelMapper {
+  fn key_definition_for_coordinate(&self, coord: &Hex) -> Option<KeyDefinition> {
+    let location = lumatone_location_for_hex(coord)?;
+    let channel = self.keymap.channel_at(*location);
+    let color = match channel {
+      Some(ch) if self.only_channel.map_or(true, |only| only == ch) => {
+        self.palette.get_for_channel(ch)
+      }
+      _ => LinSrgb::new(0.2, 0.2, 0.2),
+    };
+    let label = channel.map(|ch| ch.get().to_string()).unwrap_or_default();
+    let function = self.keymap.get_key(*location).map(|def| &def.function);
+    let description = Some(key_label(location, function));
+    Some(KeyDefinition {
+      color,
+      label,
+      description,
+      tooltip: None,
+      badge: false,
+      dimmed: false,
+      highlighted: false,
     })
   }
 }
+
+/// Overrides the colors of another mapper's keys with colors painted onto them,
+/// keeping its labels. Keys that haven't been painted are shown as the base mapper has them.
+pub struct PaintedMapper {
+  pub base: Box<dyn KeyMapper>,
+  pub painted: HashMap<Hex, LinSrgb>,
+}
+
+impl KeyMapper for PaintedMapper {
+  fn key_definition_for_coordinate(&self, coord: &Hex) -> Option<KeyDefinition> {
+    let mut def = self.base.key_definition_for_coordinate(coord)?;
+    if let Some(color) = self.painted.get(coord) {
+      def.color = *color;
+    }
+    Some(def)
+  }
+}
+
+/// Marks the annotated keys of another mapper with a badge, and shows their label and tags
+/// in a tooltip. If there's a tag query, keys that don't match it (see
+/// [KeyAnnotation::matches]) are dimmed.
+pub struct AnnotationMapper {
+  pub base: Box<dyn KeyMapper>,
+  pub annotations: HashMap<LumatoneKeyLocation, KeyAnnotation>,
+  pub query: String,
+}
+
+impl AnnotationMapper {
+  /// Takes a snapshot of the annotations in `keymap`.
+  pub fn new(base: Box<dyn KeyMapper>, keymap: &LumatoneKeyMap, query: &str) -> Self {
+    AnnotationMapper {
+      base,
+      annotations: keymap
+        .annotations()
+        .map(|(loc, a)| (*loc, a.clone()))
+        .collect(),
+      query: query.to_string(),
+    }
+  }
+}
+
+impl KeyMapper for AnnotationMapper {
+  fn key_definition_for_coordinate(&self, coord: &Hex) -> Option<KeyDefinition> {
+    let mut def = self.base.key_definition_for_coordinate(coord)?;
+    let annotation = lumatone_location_for_hex(coord).and_then(|loc| self.annotations.get(loc));
+    if let Some(annotation) = annotation {
+      def.badge = true;
+      def.tooltip = Some(annotation_tooltip(annotation));
+    }
+    if !self.query.trim().is_empty() {
+      def.dimmed = !annotation.is_some_and(|a| a.matches(&self.query));
+    }
+    Some(def)
+  }
+}
+
+/// Outlines the keys of another mapper that match a search, and dims the rest.
+pub struct SearchMapper {
+  pub base: Box<dyn KeyMapper>,
+  /// `None` if nothing is being searched for, in which case keys are shown as the base
+  /// mapper has them.
+  pub matches: Option<HashSet<LumatoneKeyLocation>>,
+}
+
+impl KeyMapper for SearchMapper {
+  fn key_definition_for_coordinate(&self, coord: &Hex) -> Option<KeyDefinition> {
+    let mut def = self.base.key_definition_for_coordinate(coord)?;
+    if let Some(matches) = &self.matches {
+      let found = lumatone_location_for_hex(coord).is_some_and(|loc| matches.contains(loc));
+      def.highlighted = found;
+      def.dimmed = !found;
+    }
+    Some(def)
+  }
+}
+
+/// The label, followed by the tags with a `#` in front of each.
+fn annotation_tooltip(annotation: &KeyAnnotation) -> String {
+  let tags = annotation.tags.iter().map(|t| format!("#{t}"));
+  std::iter::once(annotation.label.trim().to_string())
+    .chain(tags)
+    .filter(|s| !s.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
@@ -1,4 +1,5 @@
 pub(crate) mod board;
+pub(crate) mod channels;
 pub(crate) mod key;
 pub(crate) mod map;
 pub(crate) mod octave;
@@ -1,4 +1,12 @@
+pub mod a11y;
+pub mod apply_dialog;
+pub mod assign_notes;
+pub mod event_log;
+pub mod gallery;
+pub mod key_editor;
+pub mod key_search;
 pub mod keyboard;
-pub mod scratchpad;
+pub mod palette_bar;
 pub mod tabs;
+pub mod tunings;
 pub mod wheel;
@@ -0,0 +1,141 @@
+use dioxus::prelude::*;
+use lumatone_core::color::pinned::PinnedColors;
+use lumatone_core::midi::constants::RGBColor;
+
+#[derive(Props)]
+pub struct PaletteBarProps<'a> {
+  /// The current tuning's colors, one per pitch class. These can't be edited or unpinned.
+  tuning_colors: Vec<RGBColor>,
+
+  pinned: &'a UseRef<PinnedColors>,
+
+  /// The color that painting applies, if any. Clicking a swatch selects it, and clicking
+  /// the selected swatch again clears the selection.
+  selected: &'a UseState<Option<RGBColor>>,
+}
+
+/// A horizontal bar of color swatches for painting a layout: the tuning's colors,
+/// followed by the user's pinned colors.
+///
+/// Right-clicking a pinned swatch opens an editor for changing or unpinning it. Changes
+/// to the pinned colors are saved to [PinnedColors::default_path] as they're made.
+pub fn PaletteBar<'a>(cx: Scope<'a, PaletteBarProps<'a>>) -> Element<'a> {
+  let PaletteBarProps {
+    tuning_colors,
+    pinned,
+    selected,
+  } = cx.props;
+  // index of the pinned swatch being edited
+  let editing = use_state(cx, || None::<usize>);
+
+  let swatch = |key: String, color: RGBColor, pinned_index: Option<usize>| {
+    let is_selected = *selected.get() == Some(color);
+    let class = if is_selected {
+      "swatch selected"
+    } else {
+      "swatch"
+    };
+    rsx! {
+      button {
+        key: "{key}",
+        class: class,
+        background_color: "{color}",
+        title: "{color}",
+        "aria-label": "{color}",
+        "aria-pressed": "{is_selected}",
+        prevent_default: "oncontextmenu",
+        onclick: move |_| selected.set(if is_selected { None } else { Some(color) }),
+        oncontextmenu: move |_| {
+          if pinned_index.is_some() {
+            editing.set(pinned_index);
+          }
+        },
+      }
+    }
+  };
+
+  let tuning_swatches = tuning_colors
+    .iter()
+    .enumerate()
+    .map(|(i, color)| swatch(format!("tuning-{i}"), *color, None));
+  let pinned_colors = pinned.read().colors().to_vec();
+  let pinned_swatches = pinned_colors
+    .iter()
+    .enumerate()
+    .map(|(i, color)| swatch(format!("pinned-{i}"), *color, Some(i)));
+
+  let can_pin = selected.get().is_some_and(|c| !pinned.read().contains(c));
+
+  let editor = editing
+    .get()
+    .and_then(|i| pinned_colors.get(i).map(|c| (i, *c)))
+    .map(|(index, color)| {
+      rsx! {
+        div {
+          class: "swatch-editor",
+          input {
+            r#type: "color",
+            value: "{color}",
+            oninput: move |evt| {
+              if let Some(new_color) = RGBColor::from_hex_str(&evt.value) {
+                update_pinned(pinned, |p| {
+                  p.replace(index, new_color);
+                });
+                if *selected.get() == Some(color) {
+                  selected.set(Some(new_color));
+                }
+              }
+            },
+          }
+          button {
+            onclick: move |_| {
+              update_pinned(pinned, |p| {
+                p.unpin(index);
+              });
+              editing.set(None);
+            },
+            "Unpin"
+          }
+          button {
+            onclick: move |_| editing.set(None),
+            "Done"
+          }
+        }
+      }
+    });
+
+  cx.render(rsx! {
+    div {
+      class: "palette-bar",
+      role: "toolbar",
+      "aria-label": "Paint colors",
+      style { include_str!("./style.css") }
+
+      tuning_swatches
+      div { class: "divider" }
+      pinned_swatches
+      button {
+        disabled: !can_pin,
+        onclick: move |_| {
+          if let Some(color) = *selected.get() {
+            update_pinned(pinned, |p| {
+              p.pin(color);
+            });
+          }
+        },
+        "Pin selected"
+      }
+      editor
+    }
+  })
+}
+
+/// Applies `f` to the pinned colors and saves the result.
+fn update_pinned(pinned: &UseRef<PinnedColors>, f: impl FnOnce(&mut PinnedColors)) {
+  f(&mut pinned.write());
+  if let Some(path) = PinnedColors::default_path() {
+    if let Err(err) = pinned.read().save(&path) {
+      log::error!("unable to save pinned colors: {err}");
+    }
+  }
+}
@@ -1,5 +1,7 @@
 use dioxus::prelude::*;
 
+use crate::components::a11y::tab_index_for_key;
+
 /// A definition for a single tab, to be rendered in a [TabContainer].
 /// Note that the `id` prop must be unique within a TabContainer.
 /// The `content` prop should be rendered using `cx.render`, e.g.:
@@ -33,6 +35,9 @@ pub struct TabsProps<'a> {
 /// A container component that renders a tab header, allowing the user to
 /// click to change which tab is displayed.
 ///
+/// The header follows the ARIA tabs pattern: only the active tab is in the Tab order,
+/// and the arrow keys (plus Home and End) move between tabs when the header has focus.
+///
 /// The tabs are defined in a Vec of [TabItem]s, which consist of a (unique) id,
 /// title, and content Element. The content element must be rendered with `cx.render`
 /// to have the correct type.
@@ -76,8 +81,10 @@ pub fn TabContainer<'a>(cx: Scope<'a, TabsProps<'a>>) -> Element<'a> {
     .unwrap_or_default();
 
   let active_tab = use_state(cx, || first_id);
+  let eval = use_eval(cx);
 
-  let nav_items = cx.props.tabs.iter().map(|t| {
+  let tab_count = cx.props.tabs.len();
+  let nav_items = cx.props.tabs.iter().enumerate().map(|(i, t)| {
     rsx! {
       TabNavItem {
         key: "{t.id}",
@@ -85,6 +92,14 @@ pub fn TabContainer<'a>(cx: Scope<'a, TabsProps<'a>>) -> Element<'a> {
         title: t.title,
         active_tab: "{active_tab}",
         onclick: move |_e| { active_tab.set(String::from(t.id)) },
+        onkeydown: move |e: KeyboardEvent| {
+          if let Some(next) = tab_index_for_key(&e.key().to_string(), i, tab_count) {
+            let next_id = cx.props.tabs[next].id;
+            active_tab.set(String::from(next_id));
+            // keep focus on the newly selected tab, since it's now the only one in the Tab order
+            let _ = eval(&format!("document.getElementById('tab-{next_id}')?.focus()"));
+          }
+        },
       }
     }
   });
@@ -109,6 +124,7 @@ pub fn TabContainer<'a>(cx: Scope<'a, TabsProps<'a>>) -> Element<'a> {
 
         ul {
           class: "tab-header",
+          role: "tablist",
 
           nav_items
         }
@@ -125,6 +141,7 @@ struct TabNavItemProps<'a> {
   active_tab: &'a str,
 
   onclick: EventHandler<'a, MouseEvent>,
+  onkeydown: EventHandler<'a, KeyboardEvent>,
 }
 
 fn TabNavItem<'a>(cx: Scope<'a, TabNavItemProps<'a>>) -> Element<'a> {
@@ -134,16 +151,20 @@ fn TabNavItem<'a>(cx: Scope<'a, TabNavItemProps<'a>>) -> Element<'a> {
     active_tab,
     ..
   } = cx.props;
-  let class = if *id == *active_tab {
-    "active"
-  } else {
-    "inactive"
-  };
+  let active = *id == *active_tab;
+  let class = if active { "active" } else { "inactive" };
+  let tabindex = if active { "0" } else { "-1" };
   let title = *title;
   cx.render(rsx! {
     li {
       class: class,
+      id: "tab-{id}",
+      role: "tab",
+      tabindex: tabindex,
+      "aria-selected": "{active}",
+      "aria-controls": "tabpanel-{id}",
       onclick: move |evt| cx.props.onclick.call(evt),
+      onkeydown: move |evt| cx.props.onkeydown.call(evt),
 
       title
     }
@@ -167,6 +188,10 @@ fn TabContent<'a>(cx: Scope<'a, TabContentProps<'a>>) -> Element<'a> {
   cx.render(rsx! {
     div {
       key: "{id}",
+      id: "tabpanel-{id}",
+      role: "tabpanel",
+      "aria-labelledby": "tab-{id}",
+      hidden: !active,
 
       if active {
         children
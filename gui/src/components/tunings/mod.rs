@@ -0,0 +1,259 @@
+use dioxus::prelude::*;
+use lumatone_core::color::{palette::wheel_colors, utils::to_rgb_color};
+use lumatone_core::harmony::tunings::{PitchClassDef, UserTuning};
+use lumatone_core::midi::constants::RGBColor;
+
+use crate::{
+  components::wheel::ColorWheel,
+  harmony::view_model::{Scale, Tuning},
+  hooks::usetunings::{update_user_tunings, use_user_tunings},
+};
+
+/// The range of division counts offered for new tunings, and for the generated EDOs.
+const MIN_DIVISIONS: usize = 5;
+const MAX_DIVISIONS: usize = 53;
+
+/// A tuning being created or edited, along with the name it had when editing started.
+#[derive(Clone)]
+struct Draft {
+  original: Option<String>,
+  tuning: UserTuning,
+}
+
+/// A page listing the built-in tunings and the user's own, with an editor for creating,
+/// changing, and deleting user tunings and a color wheel previewing the selected one.
+///
+/// Changes are saved to [UserTunings::default_dir](lumatone_core::harmony::tunings::UserTunings::default_dir)
+/// as they're made, via [update_user_tunings].
+pub fn TuningManager(cx: Scope<()>) -> Element {
+  let tunings = use_user_tunings(cx);
+  // name of the user tuning to preview, or None for 12 EDO
+  let selected = use_state(cx, || None::<String>);
+  let draft = use_ref(cx, || None::<Draft>);
+  let error = use_state(cx, || None::<String>);
+
+  let user_tunings = tunings.read().tunings().to_vec();
+  let preview = selected
+    .get()
+    .as_ref()
+    .and_then(|name| user_tunings.iter().find(|t| &t.name == name))
+    .map(Tuning::from_user_tuning)
+    .unwrap_or_else(Tuning::edo_12);
+
+  let items = user_tunings.iter().map(|t| {
+    let name = t.name.clone();
+    let is_selected = selected.get().as_ref() == Some(&name);
+    let select_name = name.clone();
+    let edit_tuning = t.clone();
+    let delete_name = name.clone();
+    let class = if is_selected {
+      "tuning selected"
+    } else {
+      "tuning"
+    };
+    rsx! {
+      li {
+        key: "{name}",
+        button {
+          class: class,
+          "aria-pressed": "{is_selected}",
+          onclick: move |_| selected.set(Some(select_name.clone())),
+          "{name} ({t.divisions} notes)"
+        }
+        button {
+          onclick: move |_| {
+            error.set(None);
+            *draft.write() = Some(Draft {
+              original: Some(edit_tuning.name.clone()),
+              tuning: edit_tuning.clone(),
+            });
+          },
+          "Edit"
+        }
+        button {
+          onclick: move |_| {
+            let result = update_user_tunings(tunings, |t| t.remove(&delete_name).map(|_| ()));
+            match result {
+              Ok(()) => {
+                if selected.get().as_ref() == Some(&delete_name) {
+                  selected.set(None);
+                }
+                error.set(None);
+              }
+              Err(err) => error.set(Some(err.to_string())),
+            }
+          },
+          "Delete"
+        }
+      }
+    }
+  });
+
+  let current_draft = draft.read().clone();
+  let editor = current_draft.as_ref().map(|Draft { original, tuning }| {
+    let submit_label = if original.is_some() { "Save" } else { "Add" };
+    let rows = tuning.pitch_classes.iter().enumerate().map(|(i, pc)| {
+      rsx! {
+        li {
+          key: "{i}",
+          input {
+            r#type: "text",
+            "aria-label": "Name of step {i}",
+            value: "{pc.name}",
+            oninput: move |evt| edit_draft(draft, |t| t.pitch_classes[i].name = evt.value.clone()),
+          }
+          input {
+            r#type: "color",
+            "aria-label": "Color of step {i}",
+            value: "{pc.color}",
+            oninput: move |evt| {
+              if let Some(color) = RGBColor::from_hex_str(&evt.value) {
+                edit_draft(draft, |t| t.pitch_classes[i].color = color);
+              }
+            },
+          }
+        }
+      }
+    });
+    rsx! {
+      form {
+        class: "tuning-editor",
+        prevent_default: "onsubmit",
+        onsubmit: move |_| {
+          let Some(Draft { original, tuning }) = draft.read().clone() else {
+            return;
+          };
+          let name = tuning.name.clone();
+          let result = update_user_tunings(tunings, |t| match &original {
+            Some(original) => t.update(original, tuning),
+            None => t.add(tuning),
+          });
+          match result {
+            Ok(()) => {
+              *draft.write() = None;
+              selected.set(Some(name));
+              error.set(None);
+            }
+            Err(err) => error.set(Some(err.to_string())),
+          }
+        },
+        label {
+          "Name"
+          input {
+            r#type: "text",
+            value: "{tuning.name}",
+            oninput: move |evt| edit_draft(draft, |t| t.name = evt.value.clone()),
+          }
+        }
+        label {
+          "Divisions"
+          input {
+            r#type: "number",
+            min: "{MIN_DIVISIONS}",
+            max: "{MAX_DIVISIONS}",
+            value: "{tuning.divisions}",
+            oninput: move |evt| {
+              if let Ok(n) = evt.value.parse::<usize>() {
+                edit_draft(draft, |t| resize(t, n.clamp(MIN_DIVISIONS, MAX_DIVISIONS)));
+              }
+            },
+          }
+        }
+        ol { rows }
+        button { r#type: "submit", "{submit_label}" }
+        button {
+          r#type: "button",
+          onclick: move |_| {
+            *draft.write() = None;
+            error.set(None);
+          },
+          "Cancel"
+        }
+      }
+    }
+  });
+
+  let builtin_class = if selected.get().is_none() {
+    "tuning selected"
+  } else {
+    "tuning"
+  };
+  let error_message = error.get().as_ref().map(|message| {
+    rsx! {
+      p { class: "error", role: "alert", "{message}" }
+    }
+  });
+
+  cx.render(rsx! {
+    div {
+      class: "tuning-manager",
+      style { include_str!("./style.css") }
+
+      div {
+        class: "tuning-list",
+        h3 { "Built-in" }
+        ul {
+          li {
+            button {
+              class: builtin_class,
+              onclick: move |_| selected.set(None),
+              "12 EDO"
+            }
+          }
+          li { "Equal divisions of the octave, from {MIN_DIVISIONS} to {MAX_DIVISIONS} EDO" }
+        }
+        h3 { "Yours" }
+        ul { items }
+        button {
+          onclick: move |_| {
+            error.set(None);
+            *draft.write() = Some(Draft {
+              original: None,
+              tuning: UserTuning::numbered("", 12, &default_colors(12)),
+            });
+          },
+          "New tuning"
+        }
+        error_message
+        editor
+      }
+      div {
+        class: "tuning-preview",
+        ColorWheel {
+          tuning: preview,
+          scale: Scale::c_major(),
+        }
+      }
+    }
+  })
+}
+
+/// Applies `f` to the draft tuning, if there is one.
+fn edit_draft(draft: &UseRef<Option<Draft>>, f: impl FnOnce(&mut UserTuning)) {
+  if let Some(d) = draft.write().as_mut() {
+    f(&mut d.tuning);
+  }
+}
+
+/// The color wheel's colors for `divisions` steps.
+fn default_colors(divisions: usize) -> Vec<RGBColor> {
+  wheel_colors(divisions)
+    .into_iter()
+    .map(to_rgb_color)
+    .collect()
+}
+
+/// Changes the number of steps in `tuning`, keeping the names and colors of the steps
+/// that remain and numbering any new ones.
+fn resize(tuning: &mut UserTuning, divisions: usize) {
+  let colors = default_colors(divisions);
+  let len = tuning.pitch_classes.len();
+  tuning.pitch_classes.truncate(divisions);
+  tuning
+    .pitch_classes
+    .extend((len..divisions).map(|i| PitchClassDef {
+      name: i.to_string(),
+      color: colors[i],
+    }));
+  tuning.divisions = divisions;
+}
@@ -23,6 +23,9 @@ pub struct WedgeProps {
 /// A component that renders a partial element with a "wedge" shape, to be used
 /// as part of the rim of the color wheel.
 ///
+/// Wedges are focusable, so that keyboard users can step through the pitch classes
+/// with Tab and have them read out by a screen reader.
+///
 /// Note that this returns a `<g>` (group) element, not a full SVG, so it must be
 /// embedded in an `<svg>` element to render properly.
 pub fn Wedge(cx: Scope<WedgeProps>) -> Element {
@@ -51,6 +54,10 @@ pub fn Wedge(cx: Scope<WedgeProps>) -> Element {
       fill: "{color}",
       stroke: "{color}",
       key: "{props.label}",
+      class: "wedge",
+      role: "img",
+      tabindex: "0",
+      "aria-label": "pitch class {props.label}",
 
       path {
         d: "{wedge_path}",
@@ -59,6 +66,7 @@ pub fn Wedge(cx: Scope<WedgeProps>) -> Element {
       }
 
       text {
+        "aria-hidden": "true",
         text_anchor: "middle",
         x: "{label_pt.x}",
         y: "{label_pt.y}",
@@ -52,7 +52,7 @@ pub fn ColorWheel(cx: Scope<WheelProps>) -> Element {
     let color = tuning.get_color(i);
     let text_color = tuning.get_text_color(i);
     let pc = tuning.get_pitch_class(i);
-    let label = pc.name();
+    let label = pc.label();
 
     rsx! {
       Wedge {
@@ -63,7 +63,7 @@ pub fn ColorWheel(cx: Scope<WheelProps>) -> Element {
         arc_angle: arc_angle,
         color: color,
         text_color: text_color,
-        label: String::from(label),
+        label: label.clone(),
       }
     }
   });
@@ -1,19 +1,71 @@
 //! WIP view models for tuning & scales. needs a lot of revision to fully cover the domain
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 
-use lumatone_core::color::palette::ColorPalette;
+use lumatone_core::color::{palette::ColorPalette, utils::from_rgb_color};
+use lumatone_core::harmony::tunings::UserTuning;
 use palette::LinSrgb;
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Debug)]
 pub struct PitchClass {
   name: String,
+  /// Distance above the tonic of the tuning, for pitch classes that aren't a step of an EDO
+  cents: Option<f64>,
   // TODO: add optional enharmonic name(s)
 }
 
 impl PitchClass {
+  /// A pitch class that lies `cents` above the tonic, e.g. for a pitch imported from a
+  /// Scala file, which are often unnamed (pass an empty name) and not part of any EDO.
+  pub fn from_cents(cents: f64, name: &str) -> PitchClass {
+    PitchClass {
+      name: String::from(name),
+      cents: Some(cents),
+    }
+  }
+
   pub fn name(&self) -> &str {
     &self.name
   }
+
+  pub fn cents(&self) -> Option<f64> {
+    self.cents
+  }
+
+  /// Text to show for this pitch class: its name, along with its cents value if it has one.
+  pub fn label(&self) -> String {
+    match self.cents {
+      None => self.name.clone(),
+      Some(cents) if self.name.is_empty() => format!("{cents:.1}¢"),
+      Some(cents) => format!("{} ({cents:.1}¢)", self.name),
+    }
+  }
+}
+
+impl From<&str> for PitchClass {
+  fn from(name: &str) -> Self {
+    PitchClass {
+      name: String::from(name),
+      cents: None,
+    }
+  }
+}
+
+// f64 isn't Eq or Hash, so these compare cents by bit pattern. That's fine for telling
+// pitch classes apart, since the same source value always produces the same bits.
+impl PartialEq for PitchClass {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name && self.cents.map(f64::to_bits) == other.cents.map(f64::to_bits)
+  }
+}
+
+impl Eq for PitchClass {}
+
+impl Hash for PitchClass {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.name.hash(state);
+    self.cents.map(f64::to_bits).hash(state);
+  }
 }
 
 #[derive(PartialEq)]
@@ -36,46 +88,49 @@ impl Tuning {
   pub fn edo_12() -> Tuning {
     let name = "12 EDO";
     let pitch_classes = vec![
-      PitchClass {
-        name: String::from("C"),
-      },
-      PitchClass {
-        name: String::from("C#"),
-      },
-      PitchClass {
-        name: String::from("D"),
-      },
-      PitchClass {
-        name: String::from("D#"),
-      },
-      PitchClass {
-        name: String::from("E"),
-      },
-      PitchClass {
-        name: String::from("F"),
-      },
-      PitchClass {
-        name: String::from("F#"),
-      },
-      PitchClass {
-        name: String::from("G"),
-      },
-      PitchClass {
-        name: String::from("G#"),
-      },
-      PitchClass {
-        name: String::from("A"),
-      },
-      PitchClass {
-        name: String::from("A#"),
-      },
-      PitchClass {
-        name: String::from("B"),
-      },
+      PitchClass::from("C"),
+      PitchClass::from("C#"),
+      PitchClass::from("D"),
+      PitchClass::from("D#"),
+      PitchClass::from("E"),
+      PitchClass::from("F"),
+      PitchClass::from("F#"),
+      PitchClass::from("G"),
+      PitchClass::from("G#"),
+      PitchClass::from("A"),
+      PitchClass::from("A#"),
+      PitchClass::from("B"),
     ];
     Tuning::new(String::from(name), pitch_classes)
   }
 
+  /// An equal division of the octave into `divisions` steps, with pitch classes
+  /// named by step number.
+  pub fn edo(divisions: usize) -> Tuning {
+    let pitch_classes = (0..divisions)
+      .map(|i| PitchClass::from(i.to_string().as_str()))
+      .collect();
+    Tuning::new(format!("{divisions} EDO"), pitch_classes)
+  }
+
+  /// A tuning defined by the user, with its own pitch class names and colors.
+  pub fn from_user_tuning(tuning: &UserTuning) -> Tuning {
+    if tuning.pitch_classes.is_empty() {
+      return Tuning::new(tuning.name.clone(), vec![]);
+    }
+    let pitch_classes = tuning
+      .pitch_classes
+      .iter()
+      .map(|pc| PitchClass::from(pc.name.as_str()))
+      .collect();
+    let colors = tuning.colors().into_iter().map(from_rgb_color).collect();
+    Tuning {
+      name: tuning.name.clone(),
+      pitch_classes,
+      palette: ColorPalette::from_colors(colors),
+    }
+  }
+
   pub fn divisions(&self) -> usize {
     self.pitch_classes.len()
   }
@@ -131,31 +186,15 @@ impl Scale {
   pub fn c_major() -> Scale {
     Scale {
       name: String::from("C major"),
-      tonic: PitchClass {
-        name: String::from("C"),
-      },
+      tonic: PitchClass::from("C"),
       scale_tones: HashSet::from([
-        PitchClass {
-          name: String::from("C"),
-        },
-        PitchClass {
-          name: String::from("D"),
-        },
-        PitchClass {
-          name: String::from("E"),
-        },
-        PitchClass {
-          name: String::from("F"),
-        },
-        PitchClass {
-          name: String::from("G"),
-        },
-        PitchClass {
-          name: String::from("A"),
-        },
-        PitchClass {
-          name: String::from("B"),
-        },
+        PitchClass::from("C"),
+        PitchClass::from("D"),
+        PitchClass::from("E"),
+        PitchClass::from("F"),
+        PitchClass::from("G"),
+        PitchClass::from("A"),
+        PitchClass::from("B"),
       ]),
     }
   }
@@ -163,32 +202,35 @@ impl Scale {
   pub fn d_major() -> Scale {
     Scale {
       name: String::from("C major"),
-      tonic: PitchClass {
-        name: String::from("D"),
-      },
+      tonic: PitchClass::from("D"),
       scale_tones: HashSet::from([
-        PitchClass {
-          name: String::from("D"),
-        },
-        PitchClass {
-          name: String::from("E"),
-        },
-        PitchClass {
-          name: String::from("F#"),
-        },
-        PitchClass {
-          name: String::from("G"),
-        },
-        PitchClass {
-          name: String::from("A"),
-        },
-        PitchClass {
-          name: String::from("B"),
-        },
-        PitchClass {
-          name: String::from("C#"),
-        },
+        PitchClass::from("D"),
+        PitchClass::from("E"),
+        PitchClass::from("F#"),
+        PitchClass::from("G"),
+        PitchClass::from("A"),
+        PitchClass::from("B"),
+        PitchClass::from("C#"),
       ]),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::PitchClass;
+
+  #[test]
+  fn test_pitch_class_from_cents() {
+    let pc = PitchClass::from_cents(350.0, "neutral third");
+    assert_eq!(pc.name(), "neutral third");
+    assert_eq!(pc.cents(), Some(350.0));
+    assert_eq!(pc.label(), "neutral third (350.0¢)");
+    assert_eq!(PitchClass::from_cents(350.0, "").label(), "350.0¢");
+
+    // a named pitch class is distinct from one with the same name at a given pitch
+    assert_ne!(pc, PitchClass::from("neutral third"));
+    assert_eq!(pc, PitchClass::from_cents(350.0, "neutral third"));
+    assert_eq!(PitchClass::from("C").label(), "C");
+  }
+}
@@ -1,2 +1,4 @@
+pub(crate) mod usedriverlog;
 pub(crate) mod usesizeobserver;
+pub(crate) mod usetunings;
 pub(crate) mod useuniqueid;
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use dioxus::prelude::*;
+use lumatone_core::midi::driver::MidiDriver;
+use lumatone_core::midi::event_log::EventLog;
+use tokio::sync::broadcast::error::RecvError;
+
+/// A hook that keeps an [EventLog] of `driver`'s activity, holding up to `capacity` entries.
+/// Entries are added as the driver reports them, so components that read the log
+/// re-render as it grows. If the log falls behind the driver, the missed entries are skipped.
+pub fn use_driver_log(
+  cx: &ScopeState,
+  driver: Arc<MidiDriver>,
+  capacity: usize,
+) -> &UseRef<EventLog> {
+  let log = use_ref(cx, || EventLog::new(capacity));
+
+  use_future(cx, (), |_| {
+    to_owned![log];
+    let mut events = driver.subscribe_events();
+    let mut trace = driver.subscribe_trace();
+    async move {
+      loop {
+        tokio::select! {
+          event = events.recv() => match event {
+            Ok(event) => log.write().record_event(&event),
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+          },
+          traced = trace.recv() => match traced {
+            Ok(traced) => log.write().record_trace(&traced),
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+          },
+        }
+      }
+    }
+  });
+  log
+}
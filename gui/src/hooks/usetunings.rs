@@ -0,0 +1,34 @@
+use dioxus::prelude::*;
+use lumatone_core::harmony::tunings::{TuningError, UserTunings};
+
+/// Shared state provider for the [use_user_tunings] hook. Loads the user's tunings from
+/// [UserTunings::default_dir], so call it once at the top of the component tree.
+pub fn use_user_tunings_provider(cx: &ScopeState) {
+  use_shared_state_provider(cx, || {
+    UserTunings::default_dir()
+      .map(|dir| UserTunings::load(&dir))
+      .unwrap_or_default()
+  });
+}
+
+/// A hook that returns the user's tunings, shared by every component that shows or
+/// edits them. Change them with [update_user_tunings] so the changes are saved.
+pub fn use_user_tunings(cx: &ScopeState) -> &UseSharedState<UserTunings> {
+  use_shared_state::<UserTunings>(cx)
+    .expect("No user tunings provider found! Call use_user_tunings_provider in a top-level component first.")
+}
+
+/// Applies `f` to the user's tunings, saving them if it succeeds. Nothing changes if `f`
+/// fails.
+pub fn update_user_tunings(
+  tunings: &UseSharedState<UserTunings>,
+  f: impl FnOnce(&mut UserTunings) -> Result<(), TuningError>,
+) -> Result<(), TuningError> {
+  let mut updated = tunings.read().clone();
+  f(&mut updated)?;
+  if let Some(dir) = UserTunings::default_dir() {
+    updated.save(&dir)?;
+  }
+  *tunings.write() = updated;
+  Ok(())
+}
@@ -4,11 +4,11 @@ pub(crate) mod components;
 pub(crate) mod harmony;
 pub(crate) mod hooks;
 
-use components::scratchpad::Scratchpad;
+use components::gallery::Gallery;
 
 use dioxus::prelude::*;
 use dioxus_desktop::{Config, WindowBuilder};
-use hooks::useuniqueid::use_unique_id_provider;
+use hooks::{usetunings::use_user_tunings_provider, useuniqueid::use_unique_id_provider};
 
 fn main() {
   // hot_reload_init!();
@@ -20,11 +20,25 @@ fn main() {
   dioxus_desktop::launch_cfg(app, config);
 }
 
+/// The component gallery is shown in debug builds, or when launched with `--gallery`.
+fn gallery_enabled() -> bool {
+  cfg!(debug_assertions) || std::env::args().any(|arg| arg == "--gallery")
+}
+
 fn app(cx: Scope) -> Element {
   use_unique_id_provider(cx);
+  use_user_tunings_provider(cx);
+
+  let content = if gallery_enabled() {
+    rsx! { Gallery { } }
+  } else {
+    rsx! {
+      p { "Nothing here yet. Run a debug build, or pass --gallery to see the component gallery." }
+    }
+  };
 
   cx.render(rsx! {
     style { include_str!("./app.css") },
-    Scratchpad { }
+    content
   })
 }
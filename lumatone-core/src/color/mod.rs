@@ -1,2 +1,3 @@
 pub mod palette;
+pub mod pinned;
 pub mod utils;
@@ -1,6 +1,14 @@
 use palette::{Gradient, LinSrgb};
 use std::str::FromStr;
-use super::utils::text_color_for_bgcolor;
+use super::utils::{label_colors_for_bgcolor, LabelColors};
+use crate::midi::constants::MidiChannel;
+
+/// Visually distinct colors for categorical data, where neighboring entries shouldn't
+/// look like a gradient.
+const CATEGORICAL_COLORS: [&str; 16] = [
+  "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+  "#bcbd22", "#17becf", "#aec7e8", "#ffbb78", "#98df8a", "#ff9896", "#c5b0d5", "#c49c94",
+];
 
 #[derive(PartialEq)]
 pub struct ColorPalette {
@@ -19,14 +27,52 @@ impl ColorPalette {
     Self::new(wheel_gradient(), divisions)
   }
 
+  /// A palette of the given colors, in order, e.g. from a user-defined tuning.
+  /// `colors` must not be empty.
+  pub fn from_colors(colors: Vec<LinSrgb>) -> Self {
+    ColorPalette {
+      divisions: colors.len(),
+      colors,
+    }
+  }
+
+  /// A palette of distinct, unordered colors, for things like MIDI channels where
+  /// a gradient would suggest a relationship between neighboring values.
+  /// Colors repeat if `divisions` is larger than the number of built-in colors.
+  pub fn categorical(divisions: usize) -> Self {
+    let colors = CATEGORICAL_COLORS
+      .iter()
+      .cycle()
+      .take(divisions)
+      .map(|s| parse_hex_color(s))
+      .collect();
+    ColorPalette { divisions, colors }
+  }
+
+  /// A categorical palette with one color for each of the 16 MIDI channels.
+  /// Use [ColorPalette::get_for_channel] to look up a channel's color.
+  pub fn midi_channels() -> Self {
+    Self::categorical(16)
+  }
+
+  /// Returns the color for a MIDI channel. The color depends only on the channel number,
+  /// so it stays the same no matter which other channels are in use.
+  pub fn get_for_channel(&self, channel: MidiChannel) -> LinSrgb {
+    self.get(channel.get_as_zero_indexed() as usize)
+  }
+
   pub fn get(&self, index: usize) -> LinSrgb {
     let index = index % self.divisions;
     self.colors[index]
   }
 
   pub fn get_text_color(&self, index: usize) -> LinSrgb {
-    let c = self.get(index);
-    text_color_for_bgcolor(c)
+    self.get_label_colors(index).text
+  }
+
+  /// Returns colors for a legible label drawn over the color at `index`.
+  pub fn get_label_colors(&self, index: usize) -> LabelColors {
+    label_colors_for_bgcolor(self.get(index))
   }
 }
 
@@ -38,7 +84,7 @@ fn wheel_gradient() -> Gradient<LinSrgb> {
     "#ffff00", "#ffbf00", "#ff8000", "#ff4000",
   ]
     .iter()
-    .map(|s| LinSrgb::<u8>::from_str(*s).unwrap().into_format())
+    .map(|s| parse_hex_color(s))
     .collect();
 
   Gradient::new(ryb_colors)
@@ -48,4 +94,42 @@ pub fn wheel_colors(divisions: usize) -> Vec<LinSrgb> {
   wheel_gradient().take(divisions).collect()
 }
 
+fn parse_hex_color(s: &str) -> LinSrgb {
+  LinSrgb::<u8>::from_str(s).unwrap().into_format()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ColorPalette;
+  use crate::midi::constants::MidiChannel;
+
+  #[test]
+  fn test_channel_colors_are_distinct_and_stable() {
+    let palette = ColorPalette::midi_channels();
+    let colors: Vec<_> = (1..=16)
+      .map(|ch| palette.get_for_channel(MidiChannel::unchecked(ch)))
+      .collect();
 
+    for (i, a) in colors.iter().enumerate() {
+      for b in &colors[i + 1..] {
+        assert_ne!(a, b);
+      }
+    }
+
+    // looking channels up in a different order, or from a fresh palette, gives the same colors
+    let other = ColorPalette::midi_channels();
+    for ch in (1..=16).rev() {
+      assert_eq!(
+        other.get_for_channel(MidiChannel::unchecked(ch)),
+        colors[(ch - 1) as usize]
+      );
+    }
+  }
+
+  #[test]
+  fn test_categorical_repeats_past_builtin_colors() {
+    let palette = ColorPalette::categorical(20);
+    assert_eq!(palette.get(16), palette.get(0));
+    assert_eq!(palette.get(19), palette.get(3));
+  }
+}
@@ -0,0 +1,212 @@
+//! Colors the user has pinned for quick reuse while painting a layout, persisted as a
+//! small JSON file in the [config_dir].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::midi::constants::RGBColor;
+use crate::settings::config_dir;
+
+/// File name for the pinned colors, within [config_dir].
+const PINNED_COLORS_FILE: &str = "pinned_colors.json";
+
+/// How many colors can be pinned at once. Pinning another drops the oldest.
+pub const MAX_PINNED_COLORS: usize = 16;
+
+/// An ordered list of distinct colors, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PinnedColors {
+  colors: Vec<RGBColor>,
+}
+
+/// On-disk form, with colors as `#rrggbb` strings so the file is easy to edit by hand.
+#[derive(Serialize, Deserialize)]
+struct PinnedColorsFile {
+  colors: Vec<String>,
+}
+
+impl PinnedColors {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn colors(&self) -> &[RGBColor] {
+    &self.colors
+  }
+
+  pub fn len(&self) -> usize {
+    self.colors.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.colors.is_empty()
+  }
+
+  pub fn contains(&self, color: RGBColor) -> bool {
+    self.colors.contains(&color)
+  }
+
+  /// Adds `color` to the end of the list, dropping the oldest color if the list is full.
+  /// Returns false if the color was already pinned, in which case nothing changes.
+  pub fn pin(&mut self, color: RGBColor) -> bool {
+    if self.contains(color) {
+      return false;
+    }
+    if self.colors.len() >= MAX_PINNED_COLORS {
+      self.colors.remove(0);
+    }
+    self.colors.push(color);
+    true
+  }
+
+  /// Removes and returns the color at `index`, if there is one.
+  pub fn unpin(&mut self, index: usize) -> Option<RGBColor> {
+    (index < self.colors.len()).then(|| self.colors.remove(index))
+  }
+
+  /// Changes the color at `index` in place. If `color` is already pinned elsewhere, the
+  /// other copy is removed so the list stays free of duplicates.
+  /// Returns false if `index` is out of range.
+  pub fn replace(&mut self, index: usize, color: RGBColor) -> bool {
+    if index >= self.colors.len() {
+      return false;
+    }
+    self.colors[index] = color;
+    let mut i = 0;
+    self.colors.retain(|c| {
+      let keep = i == index || *c != color;
+      i += 1;
+      keep
+    });
+    true
+  }
+
+  /// Moves the color at `from` so it ends up at `to`, shifting the colors in between.
+  /// Returns false if either index is out of range.
+  pub fn move_to(&mut self, from: usize, to: usize) -> bool {
+    if from >= self.colors.len() || to >= self.colors.len() {
+      return false;
+    }
+    let color = self.colors.remove(from);
+    self.colors.insert(to, color);
+    true
+  }
+
+  /// Parses the JSON form written by [PinnedColors::to_json]. Invalid colors and
+  /// duplicates are skipped, and only the newest [MAX_PINNED_COLORS] are kept.
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    let file: PinnedColorsFile = serde_json::from_str(json)?;
+    let mut pinned = PinnedColors::new();
+    for color in file.colors.iter().filter_map(|s| RGBColor::from_hex_str(s)) {
+      pinned.pin(color);
+    }
+    Ok(pinned)
+  }
+
+  pub fn to_json(&self) -> String {
+    let file = PinnedColorsFile {
+      colors: self.colors.iter().map(|c| c.to_string()).collect(),
+    };
+    // a list of strings always serializes
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+  }
+
+  /// Where the pinned colors are stored by default, or `None` if there's no config dir.
+  pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(PINNED_COLORS_FILE))
+  }
+
+  /// Loads pinned colors from `path`. A missing or unreadable file gives an empty list,
+  /// since losing pinned colors isn't worth interrupting anyone over.
+  pub fn load(path: &Path) -> Self {
+    fs::read_to_string(path)
+      .ok()
+      .and_then(|json| Self::from_json(&json).ok())
+      .unwrap_or_default()
+  }
+
+  /// Writes the pinned colors to `path`, creating its directory if needed.
+  pub fn save(&self, path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(path, self.to_json())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{PinnedColors, MAX_PINNED_COLORS};
+  use crate::midi::constants::RGBColor;
+
+  fn gray(n: u8) -> RGBColor {
+    RGBColor(n, n, n)
+  }
+
+  fn pinned(colors: &[RGBColor]) -> PinnedColors {
+    let mut pinned = PinnedColors::new();
+    for c in colors {
+      pinned.pin(*c);
+    }
+    pinned
+  }
+
+  #[test]
+  fn test_pin_and_unpin() {
+    let mut p = pinned(&[RGBColor::red(), RGBColor::green()]);
+    assert!(!p.pin(RGBColor::red()));
+    assert_eq!(p.colors(), &[RGBColor::red(), RGBColor::green()]);
+
+    assert_eq!(p.unpin(0), Some(RGBColor::red()));
+    assert_eq!(p.unpin(5), None);
+    assert_eq!(p.colors(), &[RGBColor::green()]);
+
+    let mut full = pinned(&(0..MAX_PINNED_COLORS as u8).map(gray).collect::<Vec<_>>());
+    assert!(full.pin(RGBColor::blue()));
+    assert_eq!(full.len(), MAX_PINNED_COLORS);
+    assert_eq!(full.colors()[0], gray(1));
+    assert_eq!(full.colors().last(), Some(&RGBColor::blue()));
+  }
+
+  #[test]
+  fn test_replace_and_move() {
+    let mut p = pinned(&[RGBColor::red(), RGBColor::green(), RGBColor::blue()]);
+    assert!(p.replace(1, gray(9)));
+    assert_eq!(p.colors(), &[RGBColor::red(), gray(9), RGBColor::blue()]);
+
+    // editing a swatch into another pinned color merges them, keeping the edited slot
+    assert!(p.replace(2, RGBColor::red()));
+    assert_eq!(p.colors(), &[gray(9), RGBColor::red()]);
+    assert!(!p.replace(2, gray(1)));
+
+    let mut p = pinned(&[gray(0), gray(1), gray(2), gray(3)]);
+    assert!(p.move_to(0, 2));
+    assert_eq!(p.colors(), &[gray(1), gray(2), gray(0), gray(3)]);
+    assert!(p.move_to(3, 0));
+    assert_eq!(p.colors(), &[gray(3), gray(1), gray(2), gray(0)]);
+    assert!(!p.move_to(0, 4));
+  }
+
+  #[test]
+  fn test_persistence() {
+    let p = pinned(&[RGBColor(0xff, 0x80, 0x00), gray(0x10)]);
+    assert_eq!(PinnedColors::from_json(&p.to_json()).unwrap(), p);
+
+    let loaded =
+      PinnedColors::from_json(r##"{"colors": ["#ff0000", "nope", "#FF0000", "#00ff00"]}"##);
+    assert_eq!(
+      loaded.unwrap().colors(),
+      &[RGBColor::red(), RGBColor::green()]
+    );
+    assert!(PinnedColors::from_json("[]").is_err());
+
+    let dir = std::env::temp_dir().join(format!("lumatone-pinned-{}", std::process::id()));
+    let path = dir.join("pinned_colors.json");
+    assert_eq!(PinnedColors::load(&path), PinnedColors::new());
+    p.save(&path).unwrap();
+    assert_eq!(PinnedColors::load(&path), p);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,24 +1,80 @@
 use palette::{LinSrgb, Srgb, Xyz, IntoColor};
 
+use crate::midi::constants::RGBColor;
+
 /// Returns the color as a CSS-compatible hex string, with `#` prefix.
 pub fn color_hex(col: LinSrgb) -> String {
   let col: LinSrgb<u8> = col.into_format();
   format!("#{col:x}")
 }
 
-/// Returns a legible text color for the given background color.
+/// Converts a display color to the device's 8-bit [RGBColor], using the same channel
+/// values that [color_hex] would show.
+pub fn to_rgb_color(col: LinSrgb) -> RGBColor {
+  let c: LinSrgb<u8> = col.into_format();
+  RGBColor(c.red, c.green, c.blue)
+}
+
+/// The inverse of [to_rgb_color].
+pub fn from_rgb_color(color: RGBColor) -> LinSrgb {
+  let RGBColor(r, g, b) = color;
+  LinSrgb::<u8>::new(r, g, b).into_format()
+}
+
+/// Below this contrast ratio, labels get a [LabelColors::halo]. Black or white text can
+/// always reach 4.5:1 (WCAG AA), so this is the stricter AAA level.
+const HALO_CONTRAST_THRESHOLD: f32 = 7.0;
+
+/// Colors for drawing a legible label over some background.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelColors {
+  /// Black or white, whichever contrasts more with the background.
+  pub text: LinSrgb,
+
+  /// For mid-luminance backgrounds where neither black nor white stands out much, an
+  /// outline to draw around the text, in the opposite color.
+  pub halo: Option<LinSrgb>,
+}
+
+/// Returns the WCAG relative luminance (0 ..= 1) of a display color.
 ///
-/// Returns white for "dark" colors (luminance < 0.5) and black for "bright" colors.
-pub fn text_color_for_bgcolor(bg: LinSrgb) -> LinSrgb {
-  let xyz: Xyz = Srgb::from_linear(bg).into_color();
-  let luminance = xyz.y;
-  if luminance < 0.5 {
-    LinSrgb::new(1.0, 1.0, 1.0)
+/// Colors in the GUI hold sRGB-encoded channel values (they're written straight out as
+/// CSS hex colors), so they're decoded before weighting.
+pub fn wcag_luminance(col: LinSrgb) -> f32 {
+  let xyz: Xyz = Srgb::new(col.red, col.green, col.blue).into_color();
+  xyz.y
+}
+
+/// Returns the WCAG contrast ratio between two display colors, from 1 (none) to 21
+/// (black on white).
+pub fn contrast_ratio(a: LinSrgb, b: LinSrgb) -> f32 {
+  let (la, lb) = (wcag_luminance(a), wcag_luminance(b));
+  (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Picks label colors for the given background color. See [LabelColors].
+pub fn label_colors_for_bgcolor(bg: LinSrgb) -> LabelColors {
+  let black = LinSrgb::new(0.0, 0.0, 0.0);
+  let white = LinSrgb::new(1.0, 1.0, 1.0);
+  let (on_black, on_white) = (contrast_ratio(bg, black), contrast_ratio(bg, white));
+  let (text, other, contrast) = if on_white > on_black {
+    (white, black, on_white)
   } else {
-    LinSrgb::new(0.0, 0.0, 0.0)
+    (black, white, on_black)
+  };
+  LabelColors {
+    text,
+    halo: (contrast < HALO_CONTRAST_THRESHOLD).then_some(other),
   }
 }
 
+/// Returns a legible text color for the given background color.
+///
+/// This is the [LabelColors::text] from [label_colors_for_bgcolor].
+pub fn text_color_for_bgcolor(bg: LinSrgb) -> LinSrgb {
+  label_colors_for_bgcolor(bg).text
+}
+
 pub trait ToHexColorStr {
   fn to_hex_color(&self) -> String;
 }
@@ -28,4 +84,50 @@ impl ToHexColorStr for LinSrgb {
     let c: LinSrgb<u8> = self.into_format();
     format!("#{c:x}")
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{color_hex, from_rgb_color, label_colors_for_bgcolor, to_rgb_color, LabelColors};
+  use crate::midi::constants::RGBColor;
+  use palette::LinSrgb;
+
+  #[test]
+  fn test_rgb_color_conversion() {
+    let color = RGBColor(0x12, 0x80, 0xfe);
+    assert_eq!(to_rgb_color(from_rgb_color(color)), color);
+    assert_eq!(color_hex(from_rgb_color(color)), color.to_string());
+  }
+
+  #[test]
+  fn test_label_colors() {
+    let black = LinSrgb::new(0.0, 0.0, 0.0);
+    let white = LinSrgb::new(1.0, 1.0, 1.0);
+    let plain = |text| LabelColors { text, halo: None };
+    let haloed = |text, halo| LabelColors {
+      text,
+      halo: Some(halo),
+    };
+
+    let cases = [
+      ("pure red", RGBColor(0xff, 0, 0), haloed(black, white)),
+      ("mid gray", RGBColor(0x80, 0x80, 0x80), haloed(black, white)),
+      (
+        "dark gray",
+        RGBColor(0x66, 0x66, 0x66),
+        haloed(white, black),
+      ),
+      ("saturated yellow", RGBColor(0xff, 0xff, 0), plain(black)),
+      ("pure blue", RGBColor(0, 0, 0xff), plain(white)),
+      ("white", RGBColor(0xff, 0xff, 0xff), plain(black)),
+      ("black", RGBColor(0, 0, 0), plain(white)),
+    ];
+    for (name, bg, expected) in cases {
+      assert_eq!(
+        label_colors_for_bgcolor(from_rgb_color(bg)),
+        expected,
+        "{name}"
+      );
+    }
+  }
+}
@@ -0,0 +1,148 @@
+//! The three directions that keys line up in on the hex grid, for operations on a run of
+//! keys like "make this diagonal a chromatic scale".
+
+use std::fmt::Display;
+
+use super::coordinates::{hex_for_lumatone_location, Hex};
+use crate::midi::constants::LumatoneKeyLocation;
+
+/// A direction along which keys form a straight line. Each one is named for the way it
+/// runs on an unrotated grid, with board 1 at the top left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HexAxis {
+  /// Along a row, from left to right.
+  Row,
+  /// Down and to the right, from one row to the next.
+  DownRight,
+  /// Down and to the left, from one row to the next.
+  DownLeft,
+}
+
+impl HexAxis {
+  pub const ALL: [HexAxis; 3] = [HexAxis::Row, HexAxis::DownRight, HexAxis::DownLeft];
+
+  /// Returns where `hex` is along this axis, and across it. Keys on the same line have
+  /// the same `across` value.
+  fn position(&self, hex: &Hex) -> (i32, i32) {
+    match self {
+      HexAxis::Row => (hex.q(), hex.r()),
+      HexAxis::DownRight => (hex.r(), hex.q()),
+      HexAxis::DownLeft => (hex.r(), hex.s()),
+    }
+  }
+
+  /// Returns the axis that all of `keys` lie on a single line along, or `None` if they
+  /// don't, or if there are fewer than two keys on the board.
+  pub fn of_line(keys: &[LumatoneKeyLocation]) -> Option<HexAxis> {
+    let hexes: Vec<&Hex> = keys.iter().filter_map(hex_for_lumatone_location).collect();
+    if hexes.len() < 2 {
+      return None;
+    }
+    HexAxis::ALL.into_iter().find(|axis| {
+      let across = axis.position(hexes[0]).1;
+      hexes.iter().all(|hex| axis.position(hex).1 == across)
+    })
+  }
+}
+
+impl Display for HexAxis {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      HexAxis::Row => write!(f, "Row"),
+      HexAxis::DownRight => write!(f, "Down right"),
+      HexAxis::DownLeft => write!(f, "Down left"),
+    }
+  }
+}
+
+/// Returns `keys` in the order they come along `axis`. Keys that aren't on a line along
+/// the axis are ordered by how far along it they are, then by which line they're on, so
+/// the result is the same however the keys were picked. Keys that aren't on one of the
+/// octave boards are left out.
+pub fn sort_keys_along_axis(
+  keys: &[LumatoneKeyLocation],
+  axis: HexAxis,
+) -> Vec<LumatoneKeyLocation> {
+  let mut positioned: Vec<((i32, i32), LumatoneKeyLocation)> = keys
+    .iter()
+    .filter_map(|key| hex_for_lumatone_location(key).map(|hex| (axis.position(hex), *key)))
+    .collect();
+  positioned.sort_by_key(|(position, _)| *position);
+  positioned.dedup_by_key(|(_, key)| *key);
+  positioned.into_iter().map(|(_, key)| key).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{sort_keys_along_axis, HexAxis};
+  use crate::geometry::coordinates::{lumatone_location_for_hex, Hex};
+  use crate::midi::constants::{
+    key_loc_unchecked, BoardIndex, LumatoneKeyIndex, LumatoneKeyLocation,
+  };
+
+  fn key_at(q: i32, r: i32) -> LumatoneKeyLocation {
+    *lumatone_location_for_hex(&Hex::new(q, r)).expect("hex should be on the board")
+  }
+
+  #[test]
+  fn test_sort_keys_along_each_axis() {
+    // three keys in a line along each axis, starting from board 1, key 8 at (0, 2)
+    let row = [key_at(2, 2), key_at(0, 2), key_at(1, 2)];
+    assert_eq!(HexAxis::of_line(&row), Some(HexAxis::Row));
+    assert_eq!(
+      sort_keys_along_axis(&row, HexAxis::Row),
+      vec![key_at(0, 2), key_at(1, 2), key_at(2, 2)]
+    );
+
+    let down_right = [key_at(0, 4), key_at(0, 2), key_at(0, 3)];
+    assert_eq!(HexAxis::of_line(&down_right), Some(HexAxis::DownRight));
+    assert_eq!(
+      sort_keys_along_axis(&down_right, HexAxis::DownRight),
+      vec![key_at(0, 2), key_at(0, 3), key_at(0, 4)]
+    );
+
+    let down_left = [key_at(1, 3), key_at(0, 4), key_at(2, 2)];
+    assert_eq!(HexAxis::of_line(&down_left), Some(HexAxis::DownLeft));
+    assert_eq!(
+      sort_keys_along_axis(&down_left, HexAxis::DownLeft),
+      vec![key_at(2, 2), key_at(1, 3), key_at(0, 4)]
+    );
+  }
+
+  #[test]
+  fn test_lines_cross_board_boundaries() {
+    // row 10 runs from the last keys of board 1 across all of board 2's row 8
+    let row: Vec<LumatoneKeyLocation> = (-1..=6).rev().map(|q| key_at(q, 10)).collect();
+    assert_eq!(HexAxis::of_line(&row), Some(HexAxis::Row));
+
+    let sorted = sort_keys_along_axis(&row, HexAxis::Row);
+    assert_eq!(sorted[0], key_loc_unchecked(1, 54));
+    assert_eq!(sorted[1], key_loc_unchecked(1, 55));
+    assert!(sorted[2..]
+      .iter()
+      .all(|k| k.board_index() == BoardIndex::Octave2));
+  }
+
+  #[test]
+  fn test_keys_off_a_line() {
+    let scattered = [key_at(0, 2), key_at(1, 3), key_at(0, 4)];
+    assert_eq!(HexAxis::of_line(&scattered), None);
+    assert_eq!(HexAxis::of_line(&[key_at(0, 2)]), None);
+
+    // still sorted along the axis, then across it
+    assert_eq!(
+      sort_keys_along_axis(&scattered, HexAxis::Row),
+      vec![key_at(0, 2), key_at(0, 4), key_at(1, 3)]
+    );
+
+    // off-board keys and duplicates are dropped
+    let server = LumatoneKeyLocation(BoardIndex::Server, LumatoneKeyIndex::unchecked(0));
+    assert_eq!(
+      sort_keys_along_axis(
+        &[key_at(1, 2), server, key_at(0, 2), key_at(1, 2)],
+        HexAxis::Row
+      ),
+      vec![key_at(0, 2), key_at(1, 2)]
+    );
+  }
+}
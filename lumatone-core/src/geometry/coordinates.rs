@@ -138,7 +138,9 @@ pub fn lumatone_location_for_hex(hex: &Hex) -> Option<&LumatoneKeyLocation> {
   LUMATONE_MAPPING.get_lumatone_key(hex)
 }
 
-pub fn hex_for_lumatone_location(location: &LumatoneKeyLocation) -> &Hex {
+/// Returns the [Hex] coordinate for a key location, or `None` if the location isn't on
+/// one of the five octave boards (e.g. a location constructed with [BoardIndex::Server]).
+pub fn hex_for_lumatone_location(location: &LumatoneKeyLocation) -> Option<&Hex> {
   LUMATONE_MAPPING.get_hex(location)
 }
 
@@ -174,11 +176,43 @@ impl LumatoneCoordinateMapping {
     }
   }
 
-  fn get_hex(&self, location: &LumatoneKeyLocation) -> &Hex {
-    self.from_lumatone.get(location).unwrap()
+  fn get_hex(&self, location: &LumatoneKeyLocation) -> Option<&Hex> {
+    self.from_lumatone.get(location)
   }
 
   fn get_lumatone_key(&self, hex: &Hex) -> Option<&LumatoneKeyLocation> {
     self.from_hex.get(hex)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{hex_for_lumatone_location, lumatone_location_for_hex, Hex};
+  use crate::midi::constants::{
+    key_loc_unchecked, BoardIndex, LumatoneKeyIndex, LumatoneKeyLocation,
+  };
+
+  #[test]
+  fn test_in_range_location_round_trips() {
+    let location = key_loc_unchecked(1, 0);
+    let hex = hex_for_lumatone_location(&location).expect("octave 1 key 0 should be mapped");
+    assert_eq!(*hex, Hex::new(0, 0));
+    assert_eq!(lumatone_location_for_hex(hex), Some(&location));
+  }
+
+  #[test]
+  fn test_all_octave_locations_are_mapped() {
+    for location in LumatoneKeyLocation::all() {
+      assert!(
+        hex_for_lumatone_location(&location).is_some(),
+        "{location:?} has no hex"
+      );
+    }
+  }
+
+  #[test]
+  fn test_unmapped_location_returns_none() {
+    let location = LumatoneKeyLocation(BoardIndex::Server, LumatoneKeyIndex::unchecked(0));
+    assert_eq!(hex_for_lumatone_location(&location), None);
+  }
+}
@@ -1,3 +1,4 @@
+pub mod axis;
 pub mod coordinates;
 pub mod layout;
 
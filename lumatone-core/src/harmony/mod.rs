@@ -1,3 +1,3 @@
 use tune::key::PianoKey;
 
-
+pub mod tunings;
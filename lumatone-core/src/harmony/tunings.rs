@@ -0,0 +1,474 @@
+//! Tunings the user has defined, beyond the generated EDOs, persisted as one JSON file
+//! per tuning in a `tunings` directory within the [config_dir].
+//!
+//! Each tuning names its pitch classes and gives each one a color, which the GUI uses for
+//! the color wheel, key labels, and recoloring layouts.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::midi::constants::RGBColor;
+use crate::settings::config_dir;
+
+/// Name of the directory holding user tunings, within [config_dir].
+const TUNINGS_DIR: &str = "tunings";
+
+/// One step of a [UserTuning].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PitchClassDef {
+  pub name: String,
+  pub color: RGBColor,
+}
+
+/// A named tuning with `divisions` steps to the octave.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserTuning {
+  pub name: String,
+  pub divisions: usize,
+  pub pitch_classes: Vec<PitchClassDef>,
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+  /// The name was empty, or only whitespace.
+  EmptyName,
+
+  /// The name belongs to a built-in tuning, like "12 EDO".
+  ReservedName(String),
+
+  /// Another tuning already has this name, or one that would be saved to the same file.
+  DuplicateName(String),
+
+  /// The division count didn't match the number of pitch classes.
+  DivisionMismatch {
+    divisions: usize,
+    pitch_classes: usize,
+  },
+
+  /// A pitch class color in a tuning file wasn't a `#rrggbb` string.
+  InvalidColor(String),
+
+  /// There's no tuning with this name.
+  NotFound(String),
+
+  JsonError(serde_json::Error),
+  IoError(std::io::Error),
+}
+
+impl fmt::Display for TuningError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TuningError::EmptyName => write!(f, "tunings need a name"),
+      TuningError::ReservedName(name) => write!(f, "\"{name}\" is the name of a built-in tuning"),
+      TuningError::DuplicateName(name) => write!(f, "there's already a tuning named \"{name}\""),
+      TuningError::DivisionMismatch {
+        divisions,
+        pitch_classes,
+      } => write!(
+        f,
+        "a tuning with {divisions} divisions needs {divisions} pitch classes, not {pitch_classes}"
+      ),
+      TuningError::InvalidColor(color) => write!(f, "invalid color \"{color}\""),
+      TuningError::NotFound(name) => write!(f, "no tuning named \"{name}\""),
+      TuningError::JsonError(err) => write!(f, "invalid tuning file: {err}"),
+      TuningError::IoError(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl std::error::Error for TuningError {}
+
+impl From<serde_json::Error> for TuningError {
+  fn from(err: serde_json::Error) -> Self {
+    TuningError::JsonError(err)
+  }
+}
+
+impl From<std::io::Error> for TuningError {
+  fn from(err: std::io::Error) -> Self {
+    TuningError::IoError(err)
+  }
+}
+
+/// On-disk form, with colors as `#rrggbb` strings so the file is easy to edit by hand.
+#[derive(Serialize, Deserialize)]
+struct TuningFile {
+  name: String,
+  divisions: usize,
+  pitch_classes: Vec<PitchClassFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PitchClassFile {
+  name: String,
+  color: String,
+}
+
+impl UserTuning {
+  /// A tuning with `divisions` pitch classes named by step number, colored with `colors`
+  /// (repeating if there are fewer colors than divisions). A starting point for editing.
+  pub fn numbered(name: &str, divisions: usize, colors: &[RGBColor]) -> UserTuning {
+    let pitch_classes = (0..divisions)
+      .map(|i| PitchClassDef {
+        name: i.to_string(),
+        color: colors
+          .get(i % colors.len().max(1))
+          .copied()
+          .unwrap_or(RGBColor(0, 0, 0)),
+      })
+      .collect();
+    UserTuning {
+      name: name.to_string(),
+      divisions,
+      pitch_classes,
+    }
+  }
+
+  /// The color of each pitch class, in order.
+  pub fn colors(&self) -> Vec<RGBColor> {
+    self.pitch_classes.iter().map(|pc| pc.color).collect()
+  }
+
+  /// Checks the tuning on its own; see [UserTunings] for checks against other tunings.
+  pub fn validate(&self) -> Result<(), TuningError> {
+    let name = self.name.trim();
+    if name.is_empty() {
+      return Err(TuningError::EmptyName);
+    }
+    if is_reserved_name(name) {
+      return Err(TuningError::ReservedName(name.to_string()));
+    }
+    if self.divisions != self.pitch_classes.len() {
+      return Err(TuningError::DivisionMismatch {
+        divisions: self.divisions,
+        pitch_classes: self.pitch_classes.len(),
+      });
+    }
+    Ok(())
+  }
+
+  /// Parses the JSON form written by [UserTuning::to_json], and validates the result.
+  pub fn from_json(json: &str) -> Result<Self, TuningError> {
+    let file: TuningFile = serde_json::from_str(json)?;
+    let pitch_classes = file
+      .pitch_classes
+      .into_iter()
+      .map(|pc| match RGBColor::from_hex_str(&pc.color) {
+        Some(color) => Ok(PitchClassDef {
+          name: pc.name,
+          color,
+        }),
+        None => Err(TuningError::InvalidColor(pc.color)),
+      })
+      .collect::<Result<_, _>>()?;
+    let tuning = UserTuning {
+      name: file.name,
+      divisions: file.divisions,
+      pitch_classes,
+    };
+    tuning.validate()?;
+    Ok(tuning)
+  }
+
+  pub fn to_json(&self) -> String {
+    let file = TuningFile {
+      name: self.name.clone(),
+      divisions: self.divisions,
+      pitch_classes: self
+        .pitch_classes
+        .iter()
+        .map(|pc| PitchClassFile {
+          name: pc.name.clone(),
+          color: pc.color.to_string(),
+        })
+        .collect(),
+    };
+    // plain strings and numbers always serialize
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+  }
+
+  /// The name of the file this tuning is saved to. Names that differ only in case or
+  /// punctuation share a file, so [UserTunings] treats them as duplicates.
+  pub fn file_name(&self) -> String {
+    format!("{}.json", file_stem(&self.name))
+  }
+}
+
+/// Names like "12 EDO" belong to the generated equal divisions of the octave.
+fn is_reserved_name(name: &str) -> bool {
+  name
+    .strip_suffix(" EDO")
+    .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn file_stem(name: &str) -> String {
+  name
+    .trim()
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() {
+        c.to_ascii_lowercase()
+      } else {
+        '_'
+      }
+    })
+    .collect()
+}
+
+/// The user's tunings, in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct UserTunings {
+  tunings: Vec<UserTuning>,
+  /// Names of the files these tunings were last loaded from or saved to. They're the only
+  /// files [UserTunings::save] removes.
+  files: Vec<String>,
+}
+
+/// Tunings are equal if they have the same tunings, in the same order, wherever they
+/// were loaded from.
+impl PartialEq for UserTunings {
+  fn eq(&self, other: &Self) -> bool {
+    self.tunings == other.tunings
+  }
+}
+
+impl UserTunings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn tunings(&self) -> &[UserTuning] {
+    &self.tunings
+  }
+
+  pub fn len(&self) -> usize {
+    self.tunings.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.tunings.is_empty()
+  }
+
+  pub fn get(&self, name: &str) -> Option<&UserTuning> {
+    self.position(name).map(|i| &self.tunings[i])
+  }
+
+  fn position(&self, name: &str) -> Option<usize> {
+    self.tunings.iter().position(|t| t.name == name)
+  }
+
+  /// Checks that `tuning` is valid and wouldn't clash with any tuning but the one at
+  /// `replacing`.
+  fn check(&self, tuning: &UserTuning, replacing: Option<usize>) -> Result<(), TuningError> {
+    tuning.validate()?;
+    let stem = file_stem(&tuning.name);
+    let clash = self
+      .tunings
+      .iter()
+      .enumerate()
+      .any(|(i, t)| Some(i) != replacing && file_stem(&t.name) == stem);
+    if clash {
+      return Err(TuningError::DuplicateName(tuning.name.clone()));
+    }
+    Ok(())
+  }
+
+  /// Adds a new tuning, if it's valid and its name isn't taken.
+  pub fn add(&mut self, tuning: UserTuning) -> Result<(), TuningError> {
+    self.check(&tuning, None)?;
+    self.tunings.push(tuning);
+    Ok(())
+  }
+
+  /// Replaces the tuning called `name` with `tuning`, which may have a different name as
+  /// long as it isn't taken.
+  pub fn update(&mut self, name: &str, tuning: UserTuning) -> Result<(), TuningError> {
+    let index = self
+      .position(name)
+      .ok_or_else(|| TuningError::NotFound(name.to_string()))?;
+    self.check(&tuning, Some(index))?;
+    self.tunings[index] = tuning;
+    Ok(())
+  }
+
+  /// Removes and returns the tuning called `name`.
+  pub fn remove(&mut self, name: &str) -> Result<UserTuning, TuningError> {
+    let index = self
+      .position(name)
+      .ok_or_else(|| TuningError::NotFound(name.to_string()))?;
+    Ok(self.tunings.remove(index))
+  }
+
+  /// Where user tunings are stored by default, or `None` if there's no config dir.
+  pub fn default_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(TUNINGS_DIR))
+  }
+
+  /// Loads every `.json` file in `dir`, sorted by file name. A missing directory gives no
+  /// tunings; files that can't be read, are invalid, or clash with a tuning that's already
+  /// loaded are skipped with a warning.
+  pub fn load(dir: &Path) -> Self {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect(),
+      Err(_) => return Self::new(),
+    };
+    paths.sort();
+
+    let mut tunings = Self::new();
+    for path in paths {
+      let result = fs::read_to_string(&path)
+        .map_err(TuningError::from)
+        .and_then(|json| UserTuning::from_json(&json))
+        .and_then(|tuning| tunings.add(tuning));
+      match (result, path.file_name().and_then(|name| name.to_str())) {
+        (Ok(()), Some(file_name)) => tunings.files.push(file_name.to_string()),
+        (Ok(()), None) => {}
+        (Err(err), _) => warn!("skipping tuning file {}: {err}", path.display()),
+      }
+    }
+    tunings
+  }
+
+  /// Writes each tuning to its own file in `dir`, creating the directory if needed, and
+  /// removes the files of tunings that have been removed or renamed since they were
+  /// loaded or last saved. Other files are left alone, including ones [UserTunings::load]
+  /// skipped.
+  pub fn save(&mut self, dir: &Path) -> Result<(), TuningError> {
+    fs::create_dir_all(dir)?;
+    let file_names: Vec<String> = self.tunings.iter().map(|t| t.file_name()).collect();
+    for stale in self.files.iter().filter(|f| !file_names.contains(f)) {
+      match fs::remove_file(dir.join(stale)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+      }
+    }
+    for (tuning, file_name) in self.tunings.iter().zip(&file_names) {
+      fs::write(dir.join(file_name), tuning.to_json())?;
+    }
+    self.files = file_names;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{TuningError, UserTuning, UserTunings};
+  use crate::midi::constants::RGBColor;
+
+  fn tuning(name: &str, divisions: usize) -> UserTuning {
+    UserTuning::numbered(name, divisions, &[RGBColor::red(), RGBColor::blue()])
+  }
+
+  #[test]
+  fn test_validation() {
+    let mut tunings = UserTunings::new();
+    tunings.add(tuning("Bohlen-Pierce", 13)).unwrap();
+    assert_eq!(tunings.get("Bohlen-Pierce").unwrap().divisions, 13);
+
+    let mut mismatched = tuning("Short", 5);
+    mismatched.divisions = 7;
+    for (bad, expected) in [
+      (tuning("  ", 5), "EmptyName"),
+      (tuning("19 EDO", 19), "ReservedName"),
+      (tuning("bohlen pierce", 13), "DuplicateName"),
+      (mismatched, "DivisionMismatch"),
+    ] {
+      let err = tunings.add(bad).unwrap_err();
+      assert!(format!("{err:?}").starts_with(expected), "{err:?}");
+    }
+    assert_eq!(tunings.len(), 1);
+
+    // "EDO" alone, or with something other than a number, is fine
+    tunings.add(tuning("Pelog EDO", 7)).unwrap();
+
+    // renaming to itself is fine, renaming onto another tuning isn't
+    tunings.update("Pelog EDO", tuning("Pelog", 7)).unwrap();
+    assert!(matches!(
+      tunings.update("Pelog", tuning("Bohlen-Pierce", 7)),
+      Err(TuningError::DuplicateName(_))
+    ));
+    assert!(matches!(
+      tunings.update("Slendro", tuning("Slendro", 5)),
+      Err(TuningError::NotFound(_))
+    ));
+
+    assert_eq!(tunings.remove("Pelog").unwrap().name, "Pelog");
+    assert!(tunings.remove("Pelog").is_err());
+    assert_eq!(tunings.len(), 1);
+  }
+
+  #[test]
+  fn test_json() {
+    let t = tuning("Slendro", 5);
+    assert_eq!(
+      t.colors()[..3],
+      [RGBColor::red(), RGBColor::blue(), RGBColor::red()]
+    );
+    assert_eq!(UserTuning::from_json(&t.to_json()).unwrap(), t);
+    assert_eq!(t.file_name(), "slendro.json");
+
+    let bad_color =
+      r##"{"name": "x", "divisions": 1, "pitch_classes": [{"name": "a", "color": "red"}]}"##;
+    assert!(matches!(
+      UserTuning::from_json(bad_color),
+      Err(TuningError::InvalidColor(_))
+    ));
+    let mismatched =
+      r##"{"name": "x", "divisions": 2, "pitch_classes": [{"name": "a", "color": "#ff0000"}]}"##;
+    assert!(matches!(
+      UserTuning::from_json(mismatched),
+      Err(TuningError::DivisionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn test_persistence() {
+    let dir = std::env::temp_dir().join(format!("lumatone-tunings-{}", std::process::id()));
+    assert_eq!(UserTunings::load(&dir), UserTunings::new());
+
+    let mut tunings = UserTunings::new();
+    tunings.add(tuning("Slendro", 5)).unwrap();
+    tunings.add(tuning("Bohlen-Pierce", 13)).unwrap();
+    tunings.save(&dir).unwrap();
+
+    // loading sorts by file name, and skips files that aren't valid tunings
+    std::fs::write(dir.join("broken.json"), "{").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a tuning").unwrap();
+    let loaded = UserTunings::load(&dir);
+    let names: Vec<_> = loaded.tunings().iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, ["Bohlen-Pierce", "Slendro"]);
+    assert_eq!(loaded.get("Slendro"), tunings.get("Slendro"));
+
+    // saving removes the files of deleted tunings, and leaves other files alone
+    tunings.remove("Slendro").unwrap();
+    tunings.save(&dir).unwrap();
+    assert!(!dir.join("slendro.json").exists());
+    assert!(dir.join("broken.json").exists());
+    assert!(dir.join("notes.txt").exists());
+    assert_eq!(UserTunings::load(&dir), tunings);
+
+    // a file skipped for clashing with another tuning's name isn't deleted by a save
+    let clashing = tuning("bohlen pierce", 13);
+    std::fs::write(dir.join("bohlen_pierce_copy.json"), clashing.to_json()).unwrap();
+    let mut loaded = UserTunings::load(&dir);
+    assert_eq!(loaded.len(), 1);
+    loaded.save(&dir).unwrap();
+    assert!(dir.join("bohlen_pierce_copy.json").exists());
+
+    // renaming a loaded tuning removes its old file
+    loaded
+      .update("Bohlen-Pierce", tuning("Tritave", 13))
+      .unwrap();
+    loaded.save(&dir).unwrap();
+    assert!(!dir.join("bohlen_pierce.json").exists());
+    assert!(dir.join("tritave.json").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
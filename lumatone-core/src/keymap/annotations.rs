@@ -0,0 +1,99 @@
+//! Notes attached to keys in a [LumatoneKeyMap](super::ltn::LumatoneKeyMap): a free-form
+//! label, and tags for finding related keys again (e.g. "drone", "left-hand").
+//!
+//! Annotations are for the person editing a layout. They're never sent to the device.
+
+use serde::{Deserialize, Serialize};
+
+/// A key's label and tags.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyAnnotation {
+  pub label: String,
+  pub tags: Vec<String>,
+}
+
+impl KeyAnnotation {
+  /// Returns true if there's no label and no tags.
+  pub fn is_empty(&self) -> bool {
+    self.label.trim().is_empty() && self.tags.is_empty()
+  }
+
+  /// Adds `tag`, trimmed, unless it's blank or already present. Returns true if it was
+  /// added.
+  pub fn add_tag(&mut self, tag: &str) -> bool {
+    let tag = tag.trim();
+    if tag.is_empty() || self.tags.iter().any(|t| t == tag) {
+      return false;
+    }
+    self.tags.push(tag.to_string());
+    true
+  }
+
+  pub fn remove_tag(&mut self, tag: &str) {
+    self.tags.retain(|t| t != tag);
+  }
+
+  /// See [matches_tag_query].
+  pub fn matches(&self, query: &str) -> bool {
+    matches_tag_query(&self.tags, query)
+  }
+}
+
+/// Returns true if every space-separated term in `query` is a prefix of at least one of
+/// `tags`, ignoring case. A blank query matches everything, including no tags at all.
+///
+/// For example, "lead dr" matches tags `["lead", "drone"]`, but not `["lead"]`.
+pub fn matches_tag_query<S: AsRef<str>>(tags: &[S], query: &str) -> bool {
+  let tags: Vec<String> = tags.iter().map(|t| t.as_ref().to_lowercase()).collect();
+  query.split_whitespace().all(|term| {
+    let term = term.to_lowercase();
+    tags.iter().any(|tag| tag.starts_with(&term))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{matches_tag_query, KeyAnnotation};
+
+  #[test]
+  fn test_matches_tag_query() {
+    let tags = ["lead", "Drone", "left-hand"];
+    assert!(matches_tag_query(&tags, ""));
+    assert!(matches_tag_query(&tags, "  "));
+    assert!(matches_tag_query(&tags, "lead"));
+    // prefixes, in any case
+    assert!(matches_tag_query(&tags, "dr"));
+    assert!(matches_tag_query(&tags, "DRONE"));
+    assert!(matches_tag_query(&tags, "le"));
+    // every term has to match
+    assert!(matches_tag_query(&tags, "lead drone"));
+    assert!(matches_tag_query(&tags, "left dr"));
+    assert!(!matches_tag_query(&tags, "lead bass"));
+    // a tag has to start with the term, not just contain it
+    assert!(!matches_tag_query(&tags, "hand"));
+    assert!(!matches_tag_query(&tags, "leads"));
+
+    let no_tags: [&str; 0] = [];
+    assert!(matches_tag_query(&no_tags, ""));
+    assert!(!matches_tag_query(&no_tags, "lead"));
+  }
+
+  #[test]
+  fn test_tags() {
+    let mut annotation = KeyAnnotation::default();
+    assert!(annotation.is_empty());
+    assert!(annotation.add_tag(" drone "));
+    assert!(!annotation.add_tag("drone"));
+    assert!(!annotation.add_tag("  "));
+    assert!(annotation.add_tag("lead"));
+    assert_eq!(annotation.tags, vec!["drone", "lead"]);
+    assert!(annotation.matches("dr le"));
+
+    annotation.remove_tag("drone");
+    annotation.remove_tag("lead");
+    assert!(annotation.is_empty());
+    annotation.label = "root".to_string();
+    assert!(!annotation.is_empty());
+  }
+}
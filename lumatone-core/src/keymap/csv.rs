@@ -0,0 +1,409 @@
+//! A CSV format for keymaps, for layouts designed in a spreadsheet.
+//!
+//! ```text
+//! board,key,type,note_or_cc,channel,color,fader_up_null
+//! 1,0,note,60,1,#ff0000,
+//! 1,1,cc,64,2,#00ff00,true
+//! 2,0,lumatouch,62,1,#0000ff,false
+//! 2,1,disabled,,,,
+//! ```
+//!
+//! The header row is required, but its columns can be in any order. `board` is 1 to 5,
+//! `key` is 0 to 55, and `type` is `note`, `cc`, `lumatouch` or `disabled`, like in the
+//! [JSON format](super::json). `note_or_cc` is 0 to 127, and is required unless the key is
+//! disabled. `channel` is 1 to 16 and defaults to 1, `color` is `#rrggbb` (the `#` is
+//! optional) and defaults to black, and `fader_up_null` is `true` or `false`, defaulting
+//! to false.
+//!
+//! Keys without a row are left unset, and so are rows with a blank `type`, so a sheet
+//! can list every key and only fill in some of them. Blank lines are skipped. A key with
+//! more than one row gets the last one.
+//!
+//! Values can be wrapped in double quotes, as some spreadsheets do, but can't contain
+//! commas. None of them need to.
+
+use std::io::{Read, Write};
+
+use crate::midi::constants::{
+  BoardIndex, LumatoneKeyFunction, LumatoneKeyIndex, LumatoneKeyLocation, MidiChannel, RGBColor,
+};
+
+use super::{
+  error::LumatoneKeymapError,
+  ltn::{KeyDefinition, LumatoneKeyMap},
+};
+
+const COLUMNS: [&str; 7] = [
+  "board",
+  "key",
+  "type",
+  "note_or_cc",
+  "channel",
+  "color",
+  "fader_up_null",
+];
+
+// indexes into COLUMNS
+const BOARD: usize = 0;
+const KEY: usize = 1;
+const TYPE: usize = 2;
+const NOTE_OR_CC: usize = 3;
+const CHANNEL: usize = 4;
+const COLOR: usize = 5;
+const FADER_UP_NULL: usize = 6;
+
+impl LumatoneKeyMap {
+  /// Parses a keymap from the CSV format described in the [module docs](self).
+  ///
+  /// Fails with a [LumatoneKeymapError::CsvError] that points at the offending cell if the
+  /// header or a row is invalid.
+  pub fn from_csv(mut reader: impl Read) -> Result<LumatoneKeyMap, LumatoneKeymapError> {
+    let mut source = String::new();
+    reader.read_to_string(&mut source)?;
+    // spreadsheets on Windows tend to start their exports with a byte order mark
+    let source = source.strip_prefix('\u{feff}').unwrap_or(&source);
+
+    let mut lines = source
+      .lines()
+      .enumerate()
+      .map(|(i, line)| (i + 1, line))
+      .filter(|(_, line)| !line.trim().is_empty());
+    let (header_row, header) = lines
+      .next()
+      .ok_or_else(|| csv_error(1, 1, "missing header row"))?;
+    let positions = parse_header(header_row, header)?;
+
+    let mut keymap = LumatoneKeyMap::new();
+    for (row, line) in lines {
+      let fields: Vec<&str> = line.split(',').map(unquote).collect();
+      if fields.len() > COLUMNS.len() {
+        return Err(csv_error(
+          row,
+          COLUMNS.len() + 1,
+          &format!("expected {} columns, got {}", COLUMNS.len(), fields.len()),
+        ));
+      }
+      let cells = Cells {
+        row,
+        fields,
+        positions: &positions,
+      };
+      if let Some((location, def)) = cells.parse_key()? {
+        keymap.set_key(location, def);
+      }
+    }
+    Ok(keymap)
+  }
+
+  /// Writes the keymap in the CSV format described in the [module docs](self), one row per
+  /// defined key, sorted by board and key index.
+  pub fn to_csv(&self, mut writer: impl Write) -> Result<(), LumatoneKeymapError> {
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+
+    let mut keys: Vec<_> = self.keys().collect();
+    keys.sort_by_key(|(loc, _)| (loc.board_index() as u8, loc.key_index().get()));
+    for (location, def) in keys {
+      let board = location.board_index() as u8;
+      let key = location.key_index();
+      let color = def.color;
+      let row = match def.function {
+        LumatoneKeyFunction::NoteOnOff { channel, note_num } => {
+          format!("{board},{key},note,{note_num},{channel},{color},")
+        }
+        LumatoneKeyFunction::ContinuousController {
+          channel,
+          cc_num,
+          fader_up_is_null,
+        } => format!("{board},{key},cc,{cc_num},{channel},{color},{fader_up_is_null}"),
+        LumatoneKeyFunction::LumaTouch {
+          channel,
+          note_num,
+          fader_up_is_null,
+        } => format!("{board},{key},lumatouch,{note_num},{channel},{color},{fader_up_is_null}"),
+        LumatoneKeyFunction::Disabled => format!("{board},{key},disabled,,,{color},"),
+      };
+      writeln!(writer, "{row}")?;
+    }
+    Ok(())
+  }
+}
+
+fn csv_error(row: usize, column: usize, message: &str) -> LumatoneKeymapError {
+  LumatoneKeymapError::CsvError {
+    row,
+    column,
+    message: message.to_string(),
+  }
+}
+
+fn unquote(field: &str) -> &str {
+  let field = field.trim();
+  field
+    .strip_prefix('"')
+    .and_then(|f| f.strip_suffix('"'))
+    .map_or(field, str::trim)
+}
+
+/// Returns the position of each of [COLUMNS] in the header.
+fn parse_header(row: usize, header: &str) -> Result<[usize; 7], LumatoneKeymapError> {
+  let mut positions = [None; 7];
+  for (i, name) in header.split(',').map(unquote).enumerate() {
+    let column = COLUMNS
+      .iter()
+      .position(|c| c.eq_ignore_ascii_case(name))
+      .ok_or_else(|| csv_error(row, i + 1, &format!("unknown column {name:?}")))?;
+    if positions[column].replace(i).is_some() {
+      return Err(csv_error(row, i + 1, &format!("duplicate column {name:?}")));
+    }
+  }
+
+  let mut found = [0; 7];
+  for (column, position) in positions.iter().enumerate() {
+    found[column] = position.ok_or_else(|| {
+      csv_error(
+        row,
+        1,
+        &format!("the header has no {:?} column", COLUMNS[column]),
+      )
+    })?;
+  }
+  Ok(found)
+}
+
+/// One row of a CSV keymap, split into fields.
+struct Cells<'a> {
+  row: usize,
+  fields: Vec<&'a str>,
+  /// From [parse_header].
+  positions: &'a [usize; 7],
+}
+
+impl<'a> Cells<'a> {
+  /// Returns the value in `column` (one of [COLUMNS], by index), or "" if the row is short.
+  fn get(&self, column: usize) -> &'a str {
+    self
+      .fields
+      .get(self.positions[column])
+      .copied()
+      .unwrap_or("")
+  }
+
+  fn error(&self, column: usize, message: String) -> LumatoneKeymapError {
+    csv_error(self.row, self.positions[column] + 1, &message)
+  }
+
+  /// Parses the number in `column`, which must be in `range`.
+  fn number(
+    &self,
+    column: usize,
+    range: std::ops::RangeInclusive<u8>,
+  ) -> Result<u8, LumatoneKeymapError> {
+    let value = self.get(column);
+    if value.is_empty() {
+      return Err(self.error(column, format!("{} is required", COLUMNS[column])));
+    }
+    value
+      .parse::<u8>()
+      .ok()
+      .filter(|n| range.contains(n))
+      .ok_or_else(|| {
+        self.error(
+          column,
+          format!(
+            "{} is {value:?}, but must be a number from {} to {}",
+            COLUMNS[column],
+            range.start(),
+            range.end()
+          ),
+        )
+      })
+  }
+
+  /// Parses the key in this row, or returns `None` if its type is blank.
+  fn parse_key(&self) -> Result<Option<(LumatoneKeyLocation, KeyDefinition)>, LumatoneKeymapError> {
+    let kind = self.get(TYPE).to_ascii_lowercase();
+    if kind.is_empty() {
+      return Ok(None);
+    }
+
+    let board = self.number(BOARD, 1..=5)?;
+    let key = self.number(
+      KEY,
+      LumatoneKeyIndex::MIN_VALUE..=LumatoneKeyIndex::MAX_VALUE,
+    )?;
+    let location = LumatoneKeyLocation(
+      BoardIndex::try_from(board).map_err(|e| self.error(BOARD, e.to_string()))?,
+      LumatoneKeyIndex::try_from(key).map_err(|e| self.error(KEY, e.to_string()))?,
+    );
+
+    let color = match self.get(COLOR) {
+      "" => RGBColor(0, 0, 0),
+      value => {
+        let hex = format!("#{}", value.trim_start_matches('#'));
+        RGBColor::from_hex_str(&hex).ok_or_else(|| {
+          self.error(
+            COLOR,
+            format!("color is {value:?}, but must look like #rrggbb"),
+          )
+        })?
+      }
+    };
+
+    let function = match kind.as_str() {
+      "disabled" => LumatoneKeyFunction::Disabled,
+      "note" | "cc" | "lumatouch" => {
+        let number = self.number(NOTE_OR_CC, 0..=127)?;
+        let channel = match self.get(CHANNEL) {
+          "" => MidiChannel::default(),
+          _ => MidiChannel::unchecked(self.number(CHANNEL, 1..=16)?),
+        };
+        let fader_up_is_null = match self.get(FADER_UP_NULL).to_ascii_lowercase().as_str() {
+          "" | "false" | "0" => false,
+          "true" | "1" => true,
+          value => {
+            return Err(self.error(
+              FADER_UP_NULL,
+              format!("fader_up_null is {value:?}, but must be true or false"),
+            ))
+          }
+        };
+        match kind.as_str() {
+          "note" => LumatoneKeyFunction::NoteOnOff {
+            channel,
+            note_num: number,
+          },
+          "cc" => LumatoneKeyFunction::ContinuousController {
+            channel,
+            cc_num: number,
+            fader_up_is_null,
+          },
+          _ => LumatoneKeyFunction::LumaTouch {
+            channel,
+            note_num: number,
+            fader_up_is_null,
+          },
+        }
+      }
+      _ => {
+        return Err(self.error(
+          TYPE,
+          format!("type is {kind:?}, but must be note, cc, lumatouch or disabled"),
+        ))
+      }
+    };
+
+    Ok(Some((location, KeyDefinition { function, color })))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::keymap::error::LumatoneKeymapError;
+  use crate::keymap::layouts::piano_like;
+  use crate::keymap::ltn::LumatoneKeyMap;
+  use crate::midi::constants::MidiChannel;
+
+  const LAYOUT: &str = "board,key,type,note_or_cc,channel,color,fader_up_null
+1,0,note,60,1,#ff0000,
+1,1,cc,64,2,#00ff00,true
+2,0,lumatouch,62,1,#0000ff,false
+2,1,disabled,,,,
+";
+
+  fn csv_error(csv: &str) -> (usize, usize, String) {
+    match LumatoneKeyMap::from_csv(csv.as_bytes()) {
+      Err(LumatoneKeymapError::CsvError {
+        row,
+        column,
+        message,
+      }) => (row, column, message),
+      other => panic!("expected a csv error, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_matches_equivalent_dsl() {
+    let keymap = LumatoneKeyMap::from_csv(LAYOUT.as_bytes()).unwrap();
+    let expected = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 60 ch 1 #ff0000
+      1:1 = cc 64 ch 2 fader_up_is_null #00ff00
+      2:0 = lumatouch 62 ch 1 #0000ff
+      2:1 = disabled
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap, expected);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let mut out = Vec::new();
+    LumatoneKeyMap::from_csv(LAYOUT.as_bytes())
+      .unwrap()
+      .to_csv(&mut out)
+      .unwrap();
+    // disabled keys get their color written out, even when it's black
+    let expected = LAYOUT.replace("2,1,disabled,,,,", "2,1,disabled,,,#000000,");
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+    let keymap = piano_like(36, MidiChannel::unchecked(3));
+    let mut out = Vec::new();
+    keymap.to_csv(&mut out).unwrap();
+    assert_eq!(LumatoneKeyMap::from_csv(out.as_slice()).unwrap(), keymap);
+  }
+
+  #[test]
+  fn test_unset_rows_and_loose_formatting() {
+    let csv = "\u{feff}Color,Board,Key,Type,Note_Or_CC,Channel,Fader_Up_Null
+
+\"00ff00\",1,0,\"NOTE\",60,,
+#ff0000,1,1,,,,
+,1,2
+
+#0000ff,1,0,note,61,4,
+";
+    let keymap = LumatoneKeyMap::from_csv(csv.as_bytes()).unwrap();
+    let expected = LumatoneKeyMap::from_dsl("1:0 = note 61 ch 4 #0000ff").unwrap();
+    assert_eq!(keymap, expected);
+  }
+
+  #[test]
+  fn test_malformed_rows() {
+    let header = "board,key,type,note_or_cc,channel,color,fader_up_null\n";
+    let with_row = |row: &str| format!("{header}1,0,note,60,1,#ff0000,\n{row}\n");
+
+    assert_eq!(
+      csv_error(&with_row("1,56,note,60,1,,")),
+      (
+        3,
+        2,
+        "key is \"56\", but must be a number from 0 to 55".to_string()
+      )
+    );
+    assert_eq!(
+      csv_error(&with_row("6,0,note,60,1,,")).0,
+      3,
+      "board out of range"
+    );
+    assert_eq!(csv_error(&with_row("1,1,note,,1,,")).1, 4);
+    assert_eq!(csv_error(&with_row("1,1,note,60,17,,")).1, 5);
+    assert_eq!(csv_error(&with_row("1,1,note,60,1,red,")).1, 6);
+    assert_eq!(csv_error(&with_row("1,1,cc,60,1,,maybe")).1, 7);
+    assert_eq!(csv_error(&with_row("1,1,drum,60,1,,")).1, 3);
+    assert_eq!(csv_error(&with_row("1,1,note,60,1,,,extra")).1, 8);
+
+    // columns are counted in the file's order, not the usual one
+    let reordered = "type,board,key,note_or_cc,channel,color,fader_up_null\nnote,1,99,60,1,,\n";
+    assert_eq!(csv_error(reordered).1, 3);
+
+    let (row, _, message) = csv_error("board,key,type,note_or_cc,channel,color\n");
+    assert_eq!(row, 1);
+    assert!(message.contains("fader_up_null"), "{message}");
+    assert_eq!(
+      csv_error("board,key,kind\n").2,
+      "unknown column \"kind\"".to_string()
+    );
+    assert_eq!(csv_error("").2, "missing header row".to_string());
+  }
+}
@@ -0,0 +1,560 @@
+//! A compact text format for describing keymaps, handy for tests, docs, and keeping
+//! layouts in version control where .ltn diffs are hard to read.
+//!
+//! Each line defines one or more keys, or sets general options:
+//!
+//! ```text
+//! # lines starting with '#' are comments
+//! options aftertouch on light_on_keystrokes off
+//! 2:13 = note 60 ch 3 #ff0000
+//! 1:0..=5 = cc 20..=25 ch 1 #00ff00
+//! 5:55 = lumatouch 72 ch 2 fader_up_is_null #0000ff
+//! 3:0..3 = disabled
+//! ```
+//!
+//! Key definitions have the form `board:keys = function [value] [ch channel] [fader_up_is_null] [#rrggbb]`:
+//!
+//! - `board` is the octave board, from 1 to 5, and `keys` is a key index from 0 to 55, or a
+//!   range of them, written `start..end` (excluding `end`) or `start..=end` (including it).
+//! - `function` is `note`, `cc`, `lumatouch` or `disabled`. All but `disabled` take a note or
+//!   controller number from 0 to 127.
+//! - `ch` sets the MIDI channel, from 1 to 16, and defaults to 1.
+//! - `fader_up_is_null` applies to `cc` and `lumatouch` keys.
+//! - The color defaults to black (off).
+//!
+//! The value and channel can be ranges too. A range expands in lockstep with the key range,
+//! so `1:0..=2 = note 60..=62` assigns notes 60, 61 and 62 to keys 0, 1 and 2. A single
+//! value applies to every key in the range. A key defined more than once gets its last definition.
+//!
+//! `options` lines take pairs of option names and values. `aftertouch`, `light_on_keystrokes`,
+//! `invert_foot_controller` and `invert_sustain` are `on` or `off`, and
+//! `expression_sensitivity` is a number from 0 to 255. Options that aren't mentioned are left
+//! unset, so applying the keymap doesn't change them. There's no syntax for configuration
+//! tables.
+//!
+//! [LumatoneKeyMap::to_dsl] writes a keymap in this format, one key per line in board and
+//! key order, so that changing a key changes one line.
+
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::midi::constants::{
+  key_loc_unchecked, LumatoneKeyFunction, LumatoneKeyLocation, MidiChannel, RGBColor,
+};
+
+use super::{
+  error::LumatoneKeymapError,
+  ltn::{GeneralOptions, KeyDefinition, LumatoneKeyMap},
+  validation::short_location,
+};
+
+impl LumatoneKeyMap {
+  /// Parses a keymap from the text format described in the [module docs](self).
+  ///
+  /// Returns a [LumatoneKeymapError::DslSyntaxError] pointing at the first problem found.
+  pub fn from_dsl(source: &str) -> Result<LumatoneKeyMap, LumatoneKeymapError> {
+    let mut keymap = LumatoneKeyMap::new();
+    let mut options = GeneralOptions::default();
+
+    for (i, line) in source.lines().enumerate() {
+      let mut parser = LineParser::new(i + 1, line);
+      match parser.peek() {
+        None => continue,
+        Some(token) if token.text.starts_with('#') => continue,
+        Some(token) if token.text == "options" => {
+          parser.next("options")?;
+          parser.parse_options(&mut options)?;
+        }
+        Some(_) => {
+          for (location, def) in parser.parse_key_definitions()? {
+            keymap.set_key(location, def);
+          }
+        }
+      }
+    }
+
+    keymap.set_global_options(options);
+    Ok(keymap)
+  }
+
+  /// Writes the keymap in the text format described in the [module docs](self).
+  /// Configuration tables aren't included.
+  pub fn to_dsl(&self) -> String {
+    let mut out = String::new();
+    let on_off = |b: bool| if b { "on" } else { "off" };
+    let opts = self.global_options();
+    let flags = [
+      ("aftertouch", opts.after_touch_active),
+      ("light_on_keystrokes", opts.light_on_key_strokes),
+      ("invert_foot_controller", opts.invert_foot_controller),
+      ("invert_sustain", opts.invert_sustain),
+    ];
+    let mut options: Vec<String> = flags
+      .iter()
+      .filter_map(|(name, value)| value.map(|b| format!("{name} {}", on_off(b))))
+      .collect();
+    if let Some(sensitivity) = opts.expression_controller_sensitivity {
+      options.push(format!("expression_sensitivity {sensitivity}"));
+    }
+    if !options.is_empty() {
+      writeln!(out, "options {}", options.join(" ")).unwrap();
+    }
+
+    let mut keys: Vec<_> = self.keys().collect();
+    keys.sort_by_key(|(loc, _)| {
+      let board: u8 = loc.board_index().into();
+      let key: u8 = loc.key_index().into();
+      (board, key)
+    });
+    for (location, def) in keys {
+      let function = match def.function {
+        LumatoneKeyFunction::NoteOnOff { channel, note_num } => {
+          format!("note {note_num} ch {}", channel.get())
+        }
+        LumatoneKeyFunction::ContinuousController {
+          channel,
+          cc_num,
+          fader_up_is_null,
+        } => format!(
+          "cc {cc_num} ch {}{}",
+          channel.get(),
+          fader_flag(fader_up_is_null)
+        ),
+        LumatoneKeyFunction::LumaTouch {
+          channel,
+          note_num,
+          fader_up_is_null,
+        } => format!(
+          "lumatouch {note_num} ch {}{}",
+          channel.get(),
+          fader_flag(fader_up_is_null)
+        ),
+        LumatoneKeyFunction::Disabled => "disabled".to_string(),
+      };
+      writeln!(
+        out,
+        "{} = {function} #{}",
+        short_location(location),
+        def.color.to_hex_string()
+      )
+      .unwrap();
+    }
+    out
+  }
+}
+
+fn fader_flag(fader_up_is_null: bool) -> &'static str {
+  if fader_up_is_null {
+    " fader_up_is_null"
+  } else {
+    ""
+  }
+}
+
+/// A whitespace-separated word, and the (1-based) column it starts at.
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+  text: &'a str,
+  column: usize,
+}
+
+struct LineParser<'a> {
+  line_num: usize,
+  tokens: Vec<Token<'a>>,
+  pos: usize,
+  /// Column just past the end of the line, for errors about missing tokens.
+  end_column: usize,
+}
+
+impl<'a> LineParser<'a> {
+  fn new(line_num: usize, line: &'a str) -> Self {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+      match (c.is_whitespace(), start) {
+        (false, None) => start = Some(i),
+        (true, Some(s)) => {
+          tokens.push(Token {
+            text: &line[s..i],
+            column: s + 1,
+          });
+          start = None;
+        }
+        _ => {}
+      }
+    }
+    if let Some(s) = start {
+      tokens.push(Token {
+        text: &line[s..],
+        column: s + 1,
+      });
+    }
+
+    LineParser {
+      line_num,
+      tokens,
+      pos: 0,
+      end_column: line.len() + 1,
+    }
+  }
+
+  fn error(&self, column: usize, message: impl Into<String>) -> LumatoneKeymapError {
+    LumatoneKeymapError::DslSyntaxError {
+      line: self.line_num,
+      column,
+      message: message.into(),
+    }
+  }
+
+  fn peek(&self) -> Option<Token<'a>> {
+    self.tokens.get(self.pos).copied()
+  }
+
+  /// Returns the next token, or an error saying that `expected` is missing.
+  fn next(&mut self, expected: &str) -> Result<Token<'a>, LumatoneKeymapError> {
+    match self.peek() {
+      Some(token) => {
+        self.pos += 1;
+        Ok(token)
+      }
+      None => Err(self.error(self.end_column, format!("expected {expected}"))),
+    }
+  }
+
+  fn parse_options(&mut self, options: &mut GeneralOptions) -> Result<(), LumatoneKeymapError> {
+    if self.peek().is_none() {
+      return Err(self.error(self.end_column, "expected an option name"));
+    }
+
+    while let Some(name) = self.peek() {
+      self.pos += 1;
+      let value = self.next(&format!("a value for {}", name.text))?;
+      let on_off = || match value.text {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(self.error(value.column, "expected 'on' or 'off'")),
+      };
+      match name.text {
+        "aftertouch" => options.after_touch_active = Some(on_off()?),
+        "light_on_keystrokes" => options.light_on_key_strokes = Some(on_off()?),
+        "invert_foot_controller" => options.invert_foot_controller = Some(on_off()?),
+        "invert_sustain" => options.invert_sustain = Some(on_off()?),
+        "expression_sensitivity" => {
+          options.expression_controller_sensitivity = Some(self.parse_number(value, 0..=255)?)
+        }
+        other => return Err(self.error(name.column, format!("unknown option '{other}'"))),
+      }
+    }
+    Ok(())
+  }
+
+  fn parse_key_definitions(
+    &mut self,
+  ) -> Result<Vec<(LumatoneKeyLocation, KeyDefinition)>, LumatoneKeymapError> {
+    let location = self.next("a key location")?;
+    let (board, keys) = self.parse_location(location)?;
+
+    let equals = self.next("'='")?;
+    if equals.text != "=" {
+      return Err(self.error(equals.column, "expected '='"));
+    }
+
+    let kind = self.next("a key function")?;
+    let values = match kind.text {
+      "note" | "cc" | "lumatouch" => {
+        let value = self.next(&format!("a value for {}", kind.text))?;
+        Some(self.parse_lockstep(value, 0..=127, keys.len())?)
+      }
+      "disabled" => None,
+      other => {
+        return Err(self.error(
+          kind.column,
+          format!("unknown key function '{other}'. Expected note, cc, lumatouch or disabled"),
+        ))
+      }
+    };
+
+    let mut channels = vec![1];
+    let mut fader_up_is_null = false;
+    let mut color = RGBColor(0, 0, 0);
+    while let Some(token) = self.peek() {
+      self.pos += 1;
+      match token.text {
+        "ch" => {
+          let value = self.next("a channel number")?;
+          channels = self.parse_lockstep(value, 1..=16, keys.len())?;
+        }
+        "fader_up_is_null" if matches!(kind.text, "cc" | "lumatouch") => fader_up_is_null = true,
+        hex if hex.starts_with('#') => color = self.parse_color(token)?,
+        other => return Err(self.error(token.column, format!("unexpected '{other}'"))),
+      }
+    }
+
+    let defs = keys
+      .iter()
+      .enumerate()
+      .map(|(i, key)| {
+        let pick = |v: &[u8]| if v.len() == 1 { v[0] } else { v[i] };
+        let channel = MidiChannel::unchecked(pick(&channels));
+        let function = match (kind.text, &values) {
+          ("note", Some(v)) => LumatoneKeyFunction::NoteOnOff {
+            channel,
+            note_num: pick(v),
+          },
+          ("cc", Some(v)) => LumatoneKeyFunction::ContinuousController {
+            channel,
+            cc_num: pick(v),
+            fader_up_is_null,
+          },
+          ("lumatouch", Some(v)) => LumatoneKeyFunction::LumaTouch {
+            channel,
+            note_num: pick(v),
+            fader_up_is_null,
+          },
+          _ => LumatoneKeyFunction::Disabled,
+        };
+        (
+          key_loc_unchecked(board, *key),
+          KeyDefinition { function, color },
+        )
+      })
+      .collect();
+    Ok(defs)
+  }
+
+  /// Parses a `board:keys` location, returning the board number and key indices.
+  fn parse_location(&self, token: Token) -> Result<(u8, Vec<u8>), LumatoneKeymapError> {
+    let (board, keys) = token
+      .text
+      .split_once(':')
+      .ok_or_else(|| self.error(token.column, "expected a location like '1:0' or '1:0..=5'"))?;
+
+    let board = self.parse_number(
+      Token {
+        text: board,
+        column: token.column,
+      },
+      1..=5,
+    )?;
+    let keys = self.parse_values(
+      Token {
+        text: keys,
+        column: token.column + board.to_string().len() + 1,
+      },
+      0..=55,
+    )?;
+    Ok((board, keys))
+  }
+
+  /// Parses a number or range for one part of a key definition, checking that it lines up
+  /// with a key range of `key_count` keys.
+  fn parse_lockstep(
+    &self,
+    token: Token,
+    bounds: RangeInclusive<u8>,
+    key_count: usize,
+  ) -> Result<Vec<u8>, LumatoneKeymapError> {
+    let values = self.parse_values(token, bounds)?;
+    if values.len() != 1 && values.len() != key_count {
+      return Err(self.error(
+        token.column,
+        format!(
+          "range has {} values, but there are {key_count} keys",
+          values.len()
+        ),
+      ));
+    }
+    Ok(values)
+  }
+
+  /// Parses a single number, or a `start..end` or `start..=end` range, into a list of values.
+  fn parse_values(
+    &self,
+    token: Token,
+    bounds: RangeInclusive<u8>,
+  ) -> Result<Vec<u8>, LumatoneKeymapError> {
+    let (start, end, inclusive) = match token.text.split_once("..") {
+      None => {
+        let n = self.parse_number(token, bounds)?;
+        return Ok(vec![n]);
+      }
+      Some((start, end)) => match end.strip_prefix('=') {
+        Some(end) => (start, end, true),
+        None => (start, end, false),
+      },
+    };
+
+    let start_token = Token {
+      text: start,
+      column: token.column,
+    };
+    let end_token = Token {
+      text: end,
+      column: token.column + token.text.len() - end.len(),
+    };
+    // the exclusive end can be one past the last valid value
+    let end_bounds = if inclusive {
+      bounds.clone()
+    } else {
+      *bounds.start()..=bounds.end().saturating_add(1)
+    };
+    let start = self.parse_number(start_token, bounds)?;
+    let end = self.parse_number(end_token, end_bounds)?;
+
+    let values: Vec<u8> = if inclusive {
+      (start..=end).collect()
+    } else {
+      (start..end).collect()
+    };
+    if values.is_empty() {
+      return Err(self.error(token.column, "range is empty"));
+    }
+    Ok(values)
+  }
+
+  fn parse_number(
+    &self,
+    token: Token,
+    bounds: RangeInclusive<u8>,
+  ) -> Result<u8, LumatoneKeymapError> {
+    let out_of_range = || {
+      self.error(
+        token.column,
+        format!(
+          "expected a number from {} to {}, found '{}'",
+          bounds.start(),
+          bounds.end(),
+          token.text
+        ),
+      )
+    };
+    let n: u8 = token.text.parse().map_err(|_| out_of_range())?;
+    if !bounds.contains(&n) {
+      return Err(out_of_range());
+    }
+    Ok(n)
+  }
+
+  fn parse_color(&self, token: Token) -> Result<RGBColor, LumatoneKeymapError> {
+    let hex = &token.text[1..];
+    if hex.len() != 6 {
+      return Err(self.error(token.column, "expected a color like '#ff0000'"));
+    }
+    let value = u32::from_str_radix(hex, 16)
+      .map_err(|_| self.error(token.column, "expected a color like '#ff0000'"))?;
+    Ok(RGBColor::from(value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::keymap::error::LumatoneKeymapError;
+  use crate::keymap::ltn::LumatoneKeyMap;
+  use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor};
+
+  fn syntax_error(source: &str) -> (usize, usize, String) {
+    match LumatoneKeyMap::from_dsl(source) {
+      Err(LumatoneKeymapError::DslSyntaxError {
+        line,
+        column,
+        message,
+      }) => (line, column, message),
+      other => panic!("expected a syntax error, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_parse_key_definitions() {
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      # a comment
+      2:13 = note 60 ch 3 #ff0000
+      1:0..=2 = cc 20..23 ch 1..=3 #00ff00
+      5:55 = lumatouch 72 fader_up_is_null
+      3:0..2 = disabled #0000ff
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap.keys().count(), 7);
+
+    let key = |board, key| keymap.get_key(key_loc_unchecked(board, key)).unwrap();
+    assert_eq!(
+      key(2, 13).function,
+      LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(3),
+        note_num: 60
+      }
+    );
+    assert_eq!(key(2, 13).color, RGBColor::red());
+
+    for k in 0..=2 {
+      assert_eq!(
+        key(1, k).function,
+        LumatoneKeyFunction::ContinuousController {
+          channel: MidiChannel::unchecked(k + 1),
+          cc_num: 20 + k,
+          fader_up_is_null: false,
+        }
+      );
+    }
+    assert_eq!(
+      key(5, 55).function,
+      LumatoneKeyFunction::LumaTouch {
+        channel: MidiChannel::default(),
+        note_num: 72,
+        fader_up_is_null: true,
+      }
+    );
+    assert_eq!(key(5, 55).color, RGBColor(0, 0, 0));
+    assert_eq!(key(3, 1).function, LumatoneKeyFunction::Disabled);
+    assert!(keymap.get_key(key_loc_unchecked(3, 2)).is_none());
+  }
+
+  #[test]
+  fn test_parse_options() {
+    let keymap = LumatoneKeyMap::from_dsl(
+      "options aftertouch on light_on_keystrokes off\noptions expression_sensitivity 100",
+    )
+    .unwrap();
+    let opts = keymap.global_options();
+    assert_eq!(opts.after_touch_active, Some(true));
+    assert_eq!(opts.light_on_key_strokes, Some(false));
+    assert_eq!(opts.invert_sustain, None);
+    assert_eq!(opts.expression_controller_sensitivity, Some(100));
+  }
+
+  #[test]
+  fn test_syntax_errors_report_position() {
+    assert_eq!(
+      syntax_error("1:0 = note 60\n6:0 = note 61"),
+      (2, 1, "expected a number from 1 to 5, found '6'".to_string())
+    );
+    assert_eq!(
+      syntax_error("1:0..=5 = note 60..=62"),
+      (
+        1,
+        16,
+        "range has 3 values, but there are 6 keys".to_string()
+      )
+    );
+    assert_eq!(syntax_error("1:0 = note 60 ch 17").1, 18);
+    assert_eq!(syntax_error("1:50..60 = disabled").1, 7);
+    assert_eq!(syntax_error("1:0 = note").1, 11);
+    assert_eq!(syntax_error("1:0 note 60").1, 5);
+    assert_eq!(syntax_error("1:0 = note 60 #ff00").1, 15);
+    assert_eq!(syntax_error("options aftertouch maybe").1, 20);
+  }
+
+  #[test]
+  fn test_dsl_round_trip() {
+    let source = "\
+options aftertouch on light_on_keystrokes off invert_foot_controller off invert_sustain on expression_sensitivity 10
+1:0 = note 60 ch 1 #ff0000
+1:1 = cc 7 ch 2 fader_up_is_null #00ff00
+4:55 = lumatouch 40 ch 16 #0000ff
+5:3 = disabled #000000
+";
+    let keymap = LumatoneKeyMap::from_dsl(source).unwrap();
+    assert_eq!(keymap.to_dsl(), source);
+  }
+}
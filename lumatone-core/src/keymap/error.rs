@@ -6,7 +6,36 @@ pub enum LumatoneKeymapError {
 
   ValueParseError,
 
+  /// A key index range was empty or extended past the last key on a board.
+  InvalidKeyRange(u8, u8),
+
+  /// A run of notes would have gone past the MIDI note range, reaching this note number.
+  NoteOutOfRange(i32),
+
+  /// The text format from [crate::keymap::dsl] couldn't be parsed.
+  /// `line` and `column` are 1-based.
+  DslSyntaxError {
+    line: usize,
+    column: usize,
+    message: String,
+  },
+
+  /// A layout from [crate::keymap::json] had a bad parameter or key definition.
+  LayoutError(String),
+
+  /// A keymap from [crate::keymap::csv] had a bad header or row.
+  /// `row` (counting the header) and `column` are 1-based.
+  CsvError {
+    row: usize,
+    column: usize,
+    message: String,
+  },
+
+  /// A query from [crate::keymap::search] couldn't be understood, for the given reason.
+  InvalidKeyQuery(String),
+
   ParseError(ini::ParseError),
+  JsonError(serde_json::Error),
   IoError(std::io::Error),
   EncodingError(std::str::Utf8Error),
 }
@@ -17,6 +46,12 @@ impl From<ini::ParseError> for LumatoneKeymapError {
   }
 }
 
+impl From<serde_json::Error> for LumatoneKeymapError {
+  fn from(err: serde_json::Error) -> Self {
+    LumatoneKeymapError::JsonError(err)
+  }
+}
+
 impl From<std::io::Error> for LumatoneKeymapError {
   fn from(value: std::io::Error) -> Self {
     LumatoneKeymapError::IoError(value)
@@ -0,0 +1,207 @@
+//! A tiny integer expression language for parameterized layouts (see [crate::keymap::json]).
+//!
+//! Expressions are made of integer literals, `$name` parameter references, `+`, `-`, `*`,
+//! unary minus, and parentheses, e.g. `$root + 7` or `($octave - 1) * 12 + $offset`.
+//! The usual precedence applies: `*` binds tighter than `+` and `-`.
+//!
+//! Evaluation can't run away: there are no loops or function calls, arithmetic overflow is
+//! an error rather than a wrap or a panic, and nesting is limited so a hostile file can't
+//! exhaust the stack.
+
+use std::collections::HashMap;
+
+/// How deeply parentheses and unary minus can nest.
+const MAX_DEPTH: usize = 32;
+
+/// Evaluates `source` with `$name` references taken from `params`.
+///
+/// Returns a description of the problem if the expression is malformed, refers to a
+/// parameter that isn't in `params`, or overflows an `i64`.
+pub fn evaluate(source: &str, params: &HashMap<String, i64>) -> Result<i64, String> {
+  let mut parser = Parser {
+    source,
+    pos: 0,
+    params,
+  };
+  let value = parser.parse_sum(0)?;
+  parser.skip_whitespace();
+  match parser.peek() {
+    None => Ok(value),
+    Some(c) => Err(parser.error(format!("unexpected '{c}'"))),
+  }
+}
+
+/// A recursive descent parser that evaluates as it goes.
+struct Parser<'a> {
+  source: &'a str,
+  pos: usize,
+  params: &'a HashMap<String, i64>,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&self, message: impl Into<String>) -> String {
+    format!(
+      "{} at position {} in expression \"{}\"",
+      message.into(),
+      self.pos + 1,
+      self.source
+    )
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.source[self.pos..].chars().next()
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.peek().is_some_and(|c| c.is_whitespace()) {
+      self.pos += 1;
+    }
+  }
+
+  /// Consumes the longest run of characters matching `pred` and returns it.
+  fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+    let start = self.pos;
+    while let Some(c) = self.peek().filter(|c| pred(*c)) {
+      self.pos += c.len_utf8();
+    }
+    &self.source[start..self.pos]
+  }
+
+  /// sum := product (('+' | '-') product)*
+  fn parse_sum(&mut self, depth: usize) -> Result<i64, String> {
+    let mut value = self.parse_product(depth)?;
+    loop {
+      self.skip_whitespace();
+      let op = match self.peek() {
+        Some(c @ ('+' | '-')) => c,
+        _ => return Ok(value),
+      };
+      self.pos += 1;
+      let rhs = self.parse_product(depth)?;
+      let result = if op == '+' {
+        value.checked_add(rhs)
+      } else {
+        value.checked_sub(rhs)
+      };
+      value = result.ok_or_else(|| self.error("overflow"))?;
+    }
+  }
+
+  /// product := factor ('*' factor)*
+  fn parse_product(&mut self, depth: usize) -> Result<i64, String> {
+    let mut value = self.parse_factor(depth)?;
+    loop {
+      self.skip_whitespace();
+      if self.peek() != Some('*') {
+        return Ok(value);
+      }
+      self.pos += 1;
+      let rhs = self.parse_factor(depth)?;
+      value = value
+        .checked_mul(rhs)
+        .ok_or_else(|| self.error("overflow"))?;
+    }
+  }
+
+  /// factor := integer | '$' name | '-' factor | '(' sum ')'
+  fn parse_factor(&mut self, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+      return Err(self.error("expression is nested too deeply"));
+    }
+    self.skip_whitespace();
+    match self.peek() {
+      Some('(') => {
+        self.pos += 1;
+        let value = self.parse_sum(depth + 1)?;
+        self.skip_whitespace();
+        if self.peek() != Some(')') {
+          return Err(self.error("expected ')'"));
+        }
+        self.pos += 1;
+        Ok(value)
+      }
+      Some('-') => {
+        self.pos += 1;
+        let value = self.parse_factor(depth + 1)?;
+        value.checked_neg().ok_or_else(|| self.error("overflow"))
+      }
+      Some('$') => {
+        self.pos += 1;
+        let start = self.pos;
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+          return Err(self.error("expected a parameter name after '$'"));
+        }
+        self.params.get(name).copied().ok_or_else(|| {
+          self.pos = start;
+          self.error(format!("unknown parameter ${name}"))
+        })
+      }
+      Some(c) if c.is_ascii_digit() => {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        digits
+          .parse::<i64>()
+          .map_err(|_| self.error(format!("number {digits} is too large")))
+      }
+      Some(c) => Err(self.error(format!("unexpected '{c}'"))),
+      None => Err(self.error("unexpected end of expression")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::evaluate;
+
+  fn params() -> HashMap<String, i64> {
+    HashMap::from([("root".to_string(), 48), ("ch".to_string(), 3)])
+  }
+
+  #[test]
+  fn test_evaluate() {
+    let p = params();
+    assert_eq!(evaluate("42", &p), Ok(42));
+    assert_eq!(evaluate("$root + 7", &p), Ok(55));
+    assert_eq!(evaluate("$root+7-2", &p), Ok(53));
+    assert_eq!(evaluate("2 + 3 * 4", &p), Ok(14));
+    assert_eq!(evaluate("(2 + 3) * 4", &p), Ok(20));
+    assert_eq!(evaluate("-$ch * -2", &p), Ok(6));
+    assert_eq!(evaluate("  ( $root - 12 )  ", &p), Ok(36));
+    assert_eq!(evaluate("1 - 2 - 3", &p), Ok(-4));
+  }
+
+  #[test]
+  fn test_evaluate_errors() {
+    let p = params();
+    let err = evaluate("$rot + 1", &p).unwrap_err();
+    assert!(err.contains("unknown parameter $rot"), "{err}");
+    assert!(err.contains("position 2"), "{err}");
+
+    for bad in ["", "1 +", "(1 + 2", "1 + 2)", "$", "1 / 2", "3 4", "0x10"] {
+      assert!(evaluate(bad, &p).is_err(), "{bad:?} should fail");
+    }
+  }
+
+  #[test]
+  fn test_evaluate_is_bounded() {
+    let p = params();
+    assert!(evaluate("9223372036854775807 + 1", &p)
+      .unwrap_err()
+      .contains("overflow"));
+    assert!(evaluate("99999999999999999999", &p)
+      .unwrap_err()
+      .contains("too large"));
+    assert!(evaluate("-(-9223372036854775807 - 1)", &p).is_err());
+
+    let deep = format!("{}1{}", "(".repeat(1000), ")".repeat(1000));
+    assert!(evaluate(&deep, &p)
+      .unwrap_err()
+      .contains("nested too deeply"));
+    let negated = format!("{}1", "-".repeat(1000));
+    assert!(evaluate(&negated, &p).is_err());
+    let shallow = format!("{}1{}", "(".repeat(10), ")".repeat(10));
+    assert_eq!(evaluate(&shallow, &p), Ok(1));
+  }
+}
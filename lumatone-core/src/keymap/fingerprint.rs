@@ -0,0 +1,316 @@
+//! A cheap way to tell whether a keymap has changed, e.g. for marking an editor as
+//! having unsaved changes, or skipping a resend when a watched file is saved unchanged.
+//!
+//! [LumatoneKeyMap::fingerprint] hashes everything that ends up in a preset, along with key
+//! annotations and unmodeled preset entries, in a fixed
+//! order, so it doesn't depend on the keymap's `HashMap` iteration order. It uses its own
+//! hash function rather than [DefaultHasher](std::collections::hash_map::DefaultHasher), whose output may change between
+//! Rust releases.
+
+use crate::midi::constants::{
+  BoardIndex, LumatoneKeyFunction, LumatoneKeyIndex, LumatoneKeyLocation,
+};
+
+use super::{
+  ltn::LumatoneKeyMap,
+  tables::{ConfigTableDefinition, EditingStrategy},
+};
+
+/// Bumped whenever the fingerprinted data or its encoding changes, so that fingerprints
+/// from different formats never collide by accident.
+const FINGERPRINT_VERSION: u8 = 3;
+
+impl LumatoneKeyMap {
+  /// Returns a 64-bit hash of the keys, general options, configuration tables, macro
+  /// button colors, annotations and [IniExtras](super::ltn::IniExtras).
+  ///
+  /// Keymaps that are equal (see [PartialEq]) have the same fingerprint, however they were
+  /// built. Different keymaps almost certainly have different fingerprints.
+  ///
+  /// Fingerprints are stable within a version of this crate, so they can be saved and
+  /// compared later, but may change between versions.
+  pub fn fingerprint(&self) -> u64 {
+    let mut h = Fnv1a::new();
+    h.write_u8(FINGERPRINT_VERSION);
+
+    for b in 1..=5u8 {
+      let board = BoardIndex::try_from(b).unwrap();
+      for k in LumatoneKeyIndex::MIN_VALUE..=LumatoneKeyIndex::MAX_VALUE {
+        let location = LumatoneKeyLocation(board, LumatoneKeyIndex::unchecked(k));
+        match self.get_key(location) {
+          None => h.write_u8(0),
+          Some(def) => {
+            let fader_up_is_null = match def.function {
+              LumatoneKeyFunction::ContinuousController {
+                fader_up_is_null, ..
+              }
+              | LumatoneKeyFunction::LumaTouch {
+                fader_up_is_null, ..
+              } => fader_up_is_null,
+              _ => false,
+            };
+            h.write(&[
+              1,
+              def.function.key_type_code(),
+              def.function.note_or_cc_num(),
+              def.function.midi_channel_num(),
+              fader_up_is_null as u8,
+            ]);
+            h.write(&def.color.to_bytes());
+          }
+        }
+      }
+    }
+
+    let general = self.global_options();
+    // 2 for an unset flag, so that it differs from both on and off
+    let flag = |f: Option<bool>| f.map_or(2, u8::from);
+    h.write(&[
+      flag(general.after_touch_active),
+      flag(general.light_on_key_strokes),
+      flag(general.invert_foot_controller),
+      flag(general.invert_sustain),
+      general.expression_controller_sensitivity.is_some() as u8,
+      general.expression_controller_sensitivity.unwrap_or(0),
+    ]);
+
+    let tables = &general.config_tables;
+    for table in [
+      &tables.on_off_velocity,
+      &tables.fader_velocity,
+      &tables.aftertouch_velocity,
+      &tables.lumatouch_velocity,
+    ] {
+      write_config_table(&mut h, table.as_ref());
+    }
+    match &tables.velocity_intervals {
+      None => h.write_u8(0),
+      Some(intervals) => {
+        h.write_u8(1);
+        for interval in intervals {
+          h.write(&interval.to_le_bytes());
+        }
+      }
+    }
+
+    match self.macro_button_colors() {
+      None => h.write_u8(0),
+      Some(colors) => {
+        h.write_u8(1);
+        h.write(&colors.active.to_bytes());
+        h.write(&colors.inactive.to_bytes());
+      }
+    }
+
+    for b in 1..=5u8 {
+      let board = BoardIndex::try_from(b).unwrap();
+      for k in LumatoneKeyIndex::MIN_VALUE..=LumatoneKeyIndex::MAX_VALUE {
+        let location = LumatoneKeyLocation(board, LumatoneKeyIndex::unchecked(k));
+        match self.annotation(location) {
+          None => h.write_u8(0),
+          Some(annotation) => {
+            h.write_u8(1);
+            h.write_str(&annotation.label);
+            h.write_len(annotation.tags.len());
+            for tag in &annotation.tags {
+              h.write_str(tag);
+            }
+          }
+        }
+      }
+    }
+
+    let sections: Vec<_> = self.extras().sections().collect();
+    h.write_len(sections.len());
+    for (section, entries) in sections {
+      match section {
+        None => h.write_u8(0),
+        Some(name) => {
+          h.write_u8(1);
+          h.write_str(name);
+        }
+      }
+      h.write_len(entries.len());
+      for (key, value) in entries {
+        h.write_str(key);
+        h.write_str(value);
+      }
+    }
+
+    h.finish()
+  }
+}
+
+fn write_config_table(h: &mut Fnv1a, table: Option<&ConfigTableDefinition>) {
+  match table {
+    None => h.write_u8(0),
+    Some(t) => {
+      let strategy = match t.edit_strategy {
+        EditingStrategy::FreeDrawing => 1,
+        EditingStrategy::LinearSegments => 2,
+        EditingStrategy::QuadraticCurves => 3,
+      };
+      h.write_u8(strategy);
+      h.write(t.table.values());
+    }
+  }
+}
+
+/// 64-bit FNV-1a. Not cryptographic, but simple, fast, and the same everywhere.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+  const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+  const PRIME: u64 = 0x100000001b3;
+
+  fn new() -> Self {
+    Fnv1a(Self::OFFSET_BASIS)
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for b in bytes {
+      self.0 ^= *b as u64;
+      self.0 = self.0.wrapping_mul(Self::PRIME);
+    }
+  }
+
+  fn write_u8(&mut self, b: u8) {
+    self.write(&[b]);
+  }
+
+  /// Writes a length, so that e.g. the strings `"ab", "c"` and `"a", "bc"` hash differently.
+  fn write_len(&mut self, len: usize) {
+    self.write(&(len as u64).to_le_bytes());
+  }
+
+  fn write_str(&mut self, s: &str) {
+    self.write_len(s.len());
+    self.write(s.as_bytes());
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Fnv1a;
+  use crate::keymap::annotations::KeyAnnotation;
+  use crate::keymap::ltn::{GeneralOptions, LumatoneKeyMap, MacroButtonColors};
+  use crate::midi::constants::{key_loc_unchecked, RGBColor};
+
+  #[test]
+  fn test_fnv1a_reference_values() {
+    // from the FNV reference test vectors
+    assert_eq!(Fnv1a::new().finish(), 0xcbf29ce484222325);
+    let mut h = Fnv1a::new();
+    h.write(b"a");
+    assert_eq!(h.finish(), 0xaf63dc4c8601ec8c);
+  }
+
+  #[test]
+  fn test_insertion_order_does_not_matter() {
+    let forwards = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 60 ch 1 #ff0000
+      2:13 = cc 20 ch 2 #00ff00
+      5:55 = lumatouch 72 ch 3 fader_up_is_null #0000ff
+      ",
+    )
+    .unwrap();
+    let backwards = LumatoneKeyMap::from_dsl(
+      "
+      5:55 = lumatouch 72 ch 3 fader_up_is_null #0000ff
+      2:13 = cc 20 ch 2 #00ff00
+      1:0 = note 60 ch 1 #ff0000
+      ",
+    )
+    .unwrap();
+    assert_eq!(forwards, backwards);
+    assert_eq!(forwards.fingerprint(), backwards.fingerprint());
+  }
+
+  #[test]
+  fn test_changes_alter_fingerprint() {
+    let dsl = "1:0..=55 = note 0..=55 ch 1 #102030";
+    let original = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    let fingerprint = original.fingerprint();
+
+    let recolored =
+      LumatoneKeyMap::from_dsl(&format!("{dsl}\n1:20 = note 20 ch 1 #102031")).unwrap();
+    assert_ne!(original, recolored);
+    assert_ne!(recolored.fingerprint(), fingerprint);
+
+    let mut with_options = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    with_options.set_global_options(GeneralOptions::builder().invert_sustain(true).build());
+    assert_ne!(with_options.fingerprint(), fingerprint);
+
+    // setting an option to off is different from leaving it unset
+    let mut with_options_off = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    with_options_off.set_global_options(GeneralOptions::builder().invert_sustain(false).build());
+    assert_ne!(with_options_off.fingerprint(), fingerprint);
+    assert_ne!(with_options_off.fingerprint(), with_options.fingerprint());
+
+    let mut with_macro_colors = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    with_macro_colors.set_macro_button_colors(Some(MacroButtonColors {
+      active: RGBColor::green(),
+      inactive: RGBColor(0, 0, 0),
+    }));
+    assert_ne!(with_macro_colors.fingerprint(), fingerprint);
+
+    // a disabled key isn't the same as a missing one
+    let with_disabled = LumatoneKeyMap::from_dsl(&format!("{dsl}\n2:0 = disabled")).unwrap();
+    assert_ne!(with_disabled.fingerprint(), fingerprint);
+  }
+
+  #[test]
+  fn test_annotations_alter_fingerprint() {
+    let dsl = "1:0 = note 60 ch 1 #ff0000";
+    let original = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    let loc = key_loc_unchecked(1, 0);
+
+    let mut labeled = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    labeled.set_annotation(
+      loc,
+      KeyAnnotation {
+        label: "C".to_string(),
+        tags: vec![],
+      },
+    );
+    assert_ne!(labeled.fingerprint(), original.fingerprint());
+
+    let mut tagged = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    tagged.set_annotation(
+      loc,
+      KeyAnnotation {
+        label: "C".to_string(),
+        tags: vec!["root".to_string()],
+      },
+    );
+    assert_ne!(tagged.fingerprint(), labeled.fingerprint());
+
+    // an annotation on a key with no definition counts too
+    let mut elsewhere = LumatoneKeyMap::from_dsl(dsl).unwrap();
+    elsewhere.set_annotation(
+      key_loc_unchecked(3, 7),
+      KeyAnnotation {
+        label: "C".to_string(),
+        tags: vec![],
+      },
+    );
+    assert_ne!(elsewhere.fingerprint(), original.fingerprint());
+    assert_ne!(elsewhere.fingerprint(), labeled.fingerprint());
+  }
+
+  #[test]
+  fn test_extras_alter_fingerprint() {
+    let ini = "[Board0]\nKey_0=60\nChan_0=1\nCol_0=ff0000\n";
+    let plain = LumatoneKeyMap::from_ini_str(ini).unwrap();
+    let with_extra = LumatoneKeyMap::from_ini_str(format!("{ini}KeyLabel_0=C\n")).unwrap();
+    let other_extra = LumatoneKeyMap::from_ini_str(format!("{ini}KeyLabel_0=D\n")).unwrap();
+    assert_ne!(plain, with_extra);
+    assert_ne!(plain.fingerprint(), with_extra.fingerprint());
+    assert_ne!(with_extra.fingerprint(), other_extra.fingerprint());
+  }
+}
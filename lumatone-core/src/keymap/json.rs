@@ -0,0 +1,579 @@
+//! A JSON format for keymaps that can be parameterized, so that layouts which only differ
+//! in, say, their root note or channel can share one file.
+//!
+//! ```json
+//! {
+//!   "parameters": [
+//!     { "name": "root", "type": "int", "default": 60 },
+//!     { "name": "ch", "type": "int", "default": 1 }
+//!   ],
+//!   "keys": [
+//!     { "board": 1, "key": 0, "function": "note", "note": "$root", "channel": "$ch", "color": "#ff0000" },
+//!     { "board": 1, "key": 1, "function": "note", "note": "$root + 7", "channel": "$ch" },
+//!     { "board": 2, "key": 0, "function": "cc", "cc": 64, "fader_up_is_null": true },
+//!     { "board": 2, "key": 1, "function": "disabled" }
+//!   ]
+//! }
+//! ```
+//!
+//! Each key has a `board` from 1 to 5, a `key` index from 0 to 55, and a `function` of
+//! `note`, `cc`, `lumatouch` or `disabled`. `note` and `lumatouch` keys need a `note` number
+//! and `cc` keys need a `cc` number, from 0 to 127. `channel` is 1 to 16 and defaults to 1,
+//! `fader_up_is_null` defaults to false, and `color` is `#rrggbb`, defaulting to black (off).
+//! A key defined more than once gets its last definition.
+//!
+//! Any number can instead be a string holding an expression over the parameters, like
+//! `"$root + 7"`. See [crate::keymap::expr] for the syntax. Parameters take their `default`
+//! unless overridden when loading, with [LumatoneKeyMap::from_json_with_params]. The only
+//! parameter `type` so far is `int`, which is also the default.
+//!
+//! # Saved keymaps
+//!
+//! [LumatoneKeyMap] also implements serde's `Serialize` and `Deserialize`, for saving a
+//! whole keymap without the losses of the .ltn format. Its `keys` are written the same way
+//! as a layout's, with plain numbers and sorted by location. Alongside them are its
+//! `general` options, `macro_buttons`, `annotations` (each with a `board` and `key`), and
+//! any `extras` from the preset it was loaded from, leaving out the ones it doesn't have.
+//!
+//! ```json
+//! {
+//!   "general": { "invert_sustain": true, "config_tables": {} },
+//!   "keys": [
+//!     { "board": 1, "key": 0, "function": "note", "channel": 1, "note": 60, "color": "#ff0000" }
+//!   ],
+//!   "annotations": [{ "board": 1, "key": 0, "label": "root", "tags": [] }]
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::midi::constants::{
+  BoardIndex, LumatoneKeyFunction, LumatoneKeyIndex, LumatoneKeyLocation, MidiChannel, RGBColor,
+};
+
+use super::{
+  annotations::KeyAnnotation,
+  error::LumatoneKeymapError,
+  expr::evaluate,
+  ltn::{GeneralOptions, IniExtras, KeyDefinition, LumatoneKeyMap, MacroButtonColors},
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutFile {
+  #[serde(default)]
+  parameters: Vec<ParameterDef>,
+  keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParameterDef {
+  name: String,
+  #[serde(rename = "type", default)]
+  kind: ParameterType,
+  default: Option<i64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ParameterType {
+  #[default]
+  Int,
+}
+
+/// A number, or an expression that evaluates to one.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntValue {
+  Literal(i64),
+  Expression(String),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FunctionName {
+  Note,
+  Cc,
+  Lumatouch,
+  Disabled,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+  board: IntValue,
+  key: IntValue,
+  function: FunctionName,
+  note: Option<IntValue>,
+  cc: Option<IntValue>,
+  channel: Option<IntValue>,
+  #[serde(default)]
+  fader_up_is_null: bool,
+  color: Option<String>,
+}
+
+/// The serialized form of a [LumatoneKeyMap].
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+  #[serde(default, skip_serializing_if = "GeneralOptions::is_empty")]
+  general: GeneralOptions,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  macro_buttons: Option<MacroButtonColors>,
+  keys: Vec<Located<KeyDefinition>>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  annotations: Vec<Located<KeyAnnotation>>,
+  #[serde(default, skip_serializing_if = "IniExtras::is_empty")]
+  extras: IniExtras,
+}
+
+/// Something about a key, written with the key's `board` and `key` alongside its fields.
+#[derive(Serialize, Deserialize)]
+struct Located<T> {
+  #[serde(flatten)]
+  location: LumatoneKeyLocation,
+  #[serde(flatten)]
+  value: T,
+}
+
+/// Returns `entries` in order of board, then key index.
+fn sorted_by_location<'a, T: Clone + 'a>(
+  entries: impl Iterator<Item = (&'a LumatoneKeyLocation, &'a T)>,
+) -> Vec<Located<T>> {
+  let mut located: Vec<Located<T>> = entries
+    .map(|(location, value)| Located {
+      location: *location,
+      value: value.clone(),
+    })
+    .collect();
+  located.sort_by_key(|l| (l.location.board_index() as u8, l.location.key_index().get()));
+  located
+}
+
+impl Serialize for LumatoneKeyMap {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    KeymapFile {
+      general: self.global_options().clone(),
+      macro_buttons: self.macro_button_colors().copied(),
+      keys: sorted_by_location(self.keys()),
+      annotations: sorted_by_location(self.annotations()),
+      extras: self.extras().clone(),
+    }
+    .serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for LumatoneKeyMap {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let file = KeymapFile::deserialize(deserializer)?;
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_global_options(file.general)
+      .set_macro_button_colors(file.macro_buttons);
+    for Located { location, value } in file.keys {
+      if location.board_index() == BoardIndex::Server {
+        return Err(serde::de::Error::custom("keys must be on boards 1 to 5"));
+      }
+      keymap.set_key(location, value);
+    }
+    for Located { location, value } in file.annotations {
+      keymap.set_annotation(location, value);
+    }
+    keymap.set_extras(file.extras);
+    Ok(keymap)
+  }
+}
+
+impl LumatoneKeyMap {
+  /// Parses a keymap from the JSON format described in the [module docs](self), using
+  /// each parameter's default value.
+  pub fn from_json(json: &str) -> Result<LumatoneKeyMap, LumatoneKeymapError> {
+    LumatoneKeyMap::from_json_with_params(json, &HashMap::new())
+  }
+
+  /// Like [LumatoneKeyMap::from_json], but with parameter values from `overrides` taking
+  /// the place of their defaults.
+  ///
+  /// Fails with a [LumatoneKeymapError::LayoutError] if an override names a parameter the
+  /// layout doesn't declare, a parameter has no value, or a key's values are invalid.
+  pub fn from_json_with_params(
+    json: &str,
+    overrides: &HashMap<String, i64>,
+  ) -> Result<LumatoneKeyMap, LumatoneKeymapError> {
+    let layout: LayoutFile = serde_json::from_str(json)?;
+    let params = resolve_parameters(&layout.parameters, overrides)?;
+
+    let mut keymap = LumatoneKeyMap::new();
+    for (i, entry) in layout.keys.iter().enumerate() {
+      let (location, def) = entry
+        .resolve(&params)
+        .map_err(|msg| LumatoneKeymapError::LayoutError(format!("keys[{i}]: {msg}")))?;
+      keymap.set_key(location, def);
+    }
+    Ok(keymap)
+  }
+}
+
+/// Returns the value of each parameter, from `overrides` or the parameter's default.
+fn resolve_parameters(
+  defs: &[ParameterDef],
+  overrides: &HashMap<String, i64>,
+) -> Result<HashMap<String, i64>, LumatoneKeymapError> {
+  let layout_error = LumatoneKeymapError::LayoutError;
+
+  for name in overrides.keys() {
+    if !defs.iter().any(|d| &d.name == name) {
+      return Err(layout_error(format!(
+        "the layout has no parameter named {name}"
+      )));
+    }
+  }
+
+  let mut params = HashMap::new();
+  for def in defs {
+    let ParameterType::Int = def.kind;
+    let value = overrides
+      .get(&def.name)
+      .copied()
+      .or(def.default)
+      .ok_or_else(|| layout_error(format!("parameter {} needs a value", def.name)))?;
+    if params.insert(def.name.clone(), value).is_some() {
+      return Err(layout_error(format!(
+        "parameter {} is declared twice",
+        def.name
+      )));
+    }
+  }
+  Ok(params)
+}
+
+impl IntValue {
+  /// Evaluates to a number in `range`, naming the value `field` in errors.
+  fn resolve(
+    &self,
+    field: &str,
+    params: &HashMap<String, i64>,
+    range: std::ops::RangeInclusive<u8>,
+  ) -> Result<u8, String> {
+    let value = match self {
+      IntValue::Literal(n) => *n,
+      IntValue::Expression(expr) => evaluate(expr, params).map_err(|e| format!("{field}: {e}"))?,
+    };
+    u8::try_from(value)
+      .ok()
+      .filter(|v| range.contains(v))
+      .ok_or_else(|| {
+        format!(
+          "{field} is {value}, but must be from {} to {}",
+          range.start(),
+          range.end()
+        )
+      })
+  }
+}
+
+impl KeyEntry {
+  fn resolve(
+    &self,
+    params: &HashMap<String, i64>,
+  ) -> Result<(LumatoneKeyLocation, KeyDefinition), String> {
+    let board = self.board.resolve("board", params, 1..=5)?;
+    let key = self.key.resolve(
+      "key",
+      params,
+      LumatoneKeyIndex::MIN_VALUE..=LumatoneKeyIndex::MAX_VALUE,
+    )?;
+    let location = LumatoneKeyLocation(
+      BoardIndex::try_from(board).map_err(|e| e.to_string())?,
+      LumatoneKeyIndex::try_from(key).map_err(|e| e.to_string())?,
+    );
+
+    let channel = match &self.channel {
+      None => MidiChannel::default(),
+      Some(ch) => {
+        let ch = ch.resolve("channel", params, 1..=16)?;
+        MidiChannel::try_from(ch).map_err(|e| e.to_string())?
+      }
+    };
+    let required = |value: &Option<IntValue>, field: &str| match value {
+      Some(v) => v.resolve(field, params, 0..=127),
+      None => Err(format!("{field} is required for this function")),
+    };
+    let fader_up_is_null = self.fader_up_is_null;
+    let function = match self.function {
+      FunctionName::Note => LumatoneKeyFunction::NoteOnOff {
+        channel,
+        note_num: required(&self.note, "note")?,
+      },
+      FunctionName::Cc => LumatoneKeyFunction::ContinuousController {
+        channel,
+        cc_num: required(&self.cc, "cc")?,
+        fader_up_is_null,
+      },
+      FunctionName::Lumatouch => LumatoneKeyFunction::LumaTouch {
+        channel,
+        note_num: required(&self.note, "note")?,
+        fader_up_is_null,
+      },
+      FunctionName::Disabled => LumatoneKeyFunction::Disabled,
+    };
+
+    let color = match &self.color {
+      None => RGBColor(0, 0, 0),
+      Some(c) => {
+        RGBColor::from_hex_str(c).ok_or_else(|| format!("color {c} isn't in #rrggbb form"))?
+      }
+    };
+    Ok((location, KeyDefinition { function, color }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use serde_json::json;
+
+  use crate::keymap::annotations::KeyAnnotation;
+  use crate::keymap::error::LumatoneKeymapError;
+  use crate::keymap::ltn::{GeneralOptions, KeyDefinition, LumatoneKeyMap, MacroButtonColors};
+  use crate::keymap::table_defaults::{
+    DEFAULT_FADER_VELOCITY_TABLE, DEFAULT_VELOCITY_INTERVAL_TABLE,
+  };
+  use crate::keymap::tables::{ConfigTableDefinition, ConfigurationTables, EditingStrategy};
+  use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor};
+
+  const LAYOUT: &str = r##"{
+    "parameters": [
+      { "name": "root", "type": "int", "default": 60 },
+      { "name": "ch", "default": 1 }
+    ],
+    "keys": [
+      { "board": 1, "key": 0, "function": "note", "note": "$root", "channel": "$ch", "color": "#ff0000" },
+      { "board": 1, "key": 1, "function": "note", "note": "$root + 7", "channel": "$ch + 1" },
+      { "board": 2, "key": 0, "function": "cc", "cc": 64, "fader_up_is_null": true },
+      { "board": 2, "key": 1, "function": "disabled" }
+    ]
+  }"##;
+
+  fn layout_error(result: Result<LumatoneKeyMap, LumatoneKeymapError>) -> String {
+    match result {
+      Err(LumatoneKeymapError::LayoutError(msg)) => msg,
+      other => panic!("expected a layout error, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_defaults_match_equivalent_dsl() {
+    let keymap = LumatoneKeyMap::from_json(LAYOUT).unwrap();
+    let expected = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 60 ch 1 #ff0000
+      1:1 = note 67 ch 2
+      2:0 = cc 64 ch 1 fader_up_is_null
+      2:1 = disabled
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap, expected);
+  }
+
+  #[test]
+  fn test_overrides_replace_defaults() {
+    let overrides = HashMap::from([("root".to_string(), 48), ("ch".to_string(), 5)]);
+    let keymap = LumatoneKeyMap::from_json_with_params(LAYOUT, &overrides).unwrap();
+    let expected = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 48 ch 5 #ff0000
+      1:1 = note 55 ch 6
+      2:0 = cc 64 ch 1 fader_up_is_null
+      2:1 = disabled
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap, expected);
+  }
+
+  #[test]
+  fn test_layout_errors() {
+    let unknown = HashMap::from([("rooot".to_string(), 48)]);
+    let msg = layout_error(LumatoneKeyMap::from_json_with_params(LAYOUT, &unknown));
+    assert!(msg.contains("no parameter named rooot"), "{msg}");
+
+    let too_high = HashMap::from([("root".to_string(), 125)]);
+    let msg = layout_error(LumatoneKeyMap::from_json_with_params(LAYOUT, &too_high));
+    assert_eq!(msg, "keys[1]: note is 132, but must be from 0 to 127");
+
+    let no_default = r#"{
+      "parameters": [{ "name": "root" }],
+      "keys": [{ "board": 1, "key": 0, "function": "note", "note": "$root" }]
+    }"#;
+    let msg = layout_error(LumatoneKeyMap::from_json(no_default));
+    assert!(msg.contains("root needs a value"), "{msg}");
+
+    let bad_expr = r#"{
+      "keys": [{ "board": 1, "key": 0, "function": "note", "note": "$nope + 1" }]
+    }"#;
+    let msg = layout_error(LumatoneKeyMap::from_json(bad_expr));
+    assert!(
+      msg.starts_with("keys[0]: note: unknown parameter $nope"),
+      "{msg}"
+    );
+
+    let missing_note = r#"{ "keys": [{ "board": 1, "key": 0, "function": "note" }] }"#;
+    let msg = layout_error(LumatoneKeyMap::from_json(missing_note));
+    assert!(msg.contains("note is required"), "{msg}");
+
+    let bad_board = r#"{ "keys": [{ "board": 0, "key": 0, "function": "disabled" }] }"#;
+    let msg = layout_error(LumatoneKeyMap::from_json(bad_board));
+    assert!(msg.contains("board is 0"), "{msg}");
+
+    let typo =
+      r##"{ "keys": [{ "board": 1, "key": 0, "function": "disabled", "colour": "#fff" }] }"##;
+    assert!(matches!(
+      LumatoneKeyMap::from_json(typo),
+      Err(LumatoneKeymapError::JsonError(_))
+    ));
+  }
+
+  #[test]
+  fn test_colors() {
+    let json =
+      r##"{ "keys": [{ "board": 3, "key": 55, "function": "disabled", "color": "#0a0b0c" }] }"##;
+    let keymap = LumatoneKeyMap::from_json(json).unwrap();
+    let def = keymap.get_key(key_loc_unchecked(3, 55)).unwrap();
+    assert_eq!(def.color, RGBColor(0x0a, 0x0b, 0x0c));
+
+    let json = r#"{ "keys": [{ "board": 3, "key": 55, "function": "disabled", "color": "red" }] }"#;
+    let msg = layout_error(LumatoneKeyMap::from_json(json));
+    assert!(msg.contains("#rrggbb"), "{msg}");
+  }
+
+  /// A keymap with a bit of everything that can be serialized.
+  fn full_keymap() -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 60 ch 1 #ff0000
+      2:13 = cc 64 ch 3 fader_up_is_null #00ff00
+      5:55 = lumatouch 70 ch 16 #0000ff
+      3:10 = disabled #102030
+      ",
+    )
+    .unwrap();
+    keymap
+      .set_global_options(GeneralOptions {
+        invert_sustain: Some(true),
+        expression_controller_sensitivity: Some(90),
+        config_tables: ConfigurationTables {
+          fader_velocity: Some(ConfigTableDefinition::new_with_edit_strategy(
+            DEFAULT_FADER_VELOCITY_TABLE,
+            EditingStrategy::LinearSegments,
+          )),
+          velocity_intervals: Some(DEFAULT_VELOCITY_INTERVAL_TABLE),
+          ..Default::default()
+        },
+        ..Default::default()
+      })
+      .set_macro_button_colors(Some(MacroButtonColors {
+        active: RGBColor(0xff, 0xff, 0xff),
+        inactive: RGBColor(0x20, 0x20, 0x20),
+      }))
+      .set_annotation(
+        key_loc_unchecked(1, 0),
+        KeyAnnotation {
+          label: "root".to_string(),
+          tags: vec!["drone".to_string()],
+        },
+      );
+    keymap
+  }
+
+  #[test]
+  fn test_keymap_json_round_trip() {
+    let keymap = full_keymap();
+    let json = serde_json::to_string_pretty(&keymap).unwrap();
+    let parsed: LumatoneKeyMap = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, keymap);
+    assert_eq!(serde_json::to_string_pretty(&parsed).unwrap(), json);
+  }
+
+  #[test]
+  fn test_serialized_keys() {
+    let value = serde_json::to_value(full_keymap()).unwrap();
+    assert_eq!(
+      value["keys"],
+      json!([
+        { "board": 1, "key": 0, "function": "note", "channel": 1, "note": 60, "color": "#ff0000" },
+        { "board": 2, "key": 13, "function": "cc", "channel": 3, "cc": 64, "fader_up_is_null": true, "color": "#00ff00" },
+        { "board": 3, "key": 10, "function": "disabled", "color": "#102030" },
+        { "board": 5, "key": 55, "function": "lumatouch", "channel": 16, "note": 70, "fader_up_is_null": false, "color": "#0000ff" },
+      ])
+    );
+    assert_eq!(
+      value["annotations"],
+      json!([{ "board": 1, "key": 0, "label": "root", "tags": ["drone"] }])
+    );
+    assert_eq!(
+      value["general"]["config_tables"]["fader_velocity"]["edit_strategy"],
+      "linear_segments"
+    );
+    assert!(value.get("extras").is_none());
+
+    // the keys on their own are a valid layout
+    let layout = json!({ "keys": value["keys"] }).to_string();
+    let keys_only = LumatoneKeyMap::from_json(&layout).unwrap();
+    assert_eq!(keys_only.keys().count(), 4);
+    for (location, def) in full_keymap().keys() {
+      assert_eq!(keys_only.get_key(*location), Some(def));
+    }
+  }
+
+  #[test]
+  fn test_ltn_keymap_json_round_trip() {
+    let ltn = include_str!("fixtures/future_keys.ltn");
+    let keymap = LumatoneKeyMap::from_ini_str(ltn).unwrap();
+    assert!(!keymap.extras().is_empty());
+
+    let json = serde_json::to_string(&keymap).unwrap();
+    let parsed: LumatoneKeyMap = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, keymap);
+    assert_eq!(
+      parsed.to_ini_string().unwrap(),
+      keymap.to_ini_string().unwrap()
+    );
+  }
+
+  #[test]
+  fn test_invalid_keymap_json() {
+    let parse = |keys: serde_json::Value| {
+      serde_json::from_value::<LumatoneKeyMap>(json!({ "keys": [keys] })).unwrap_err()
+    };
+    let err = parse(json!({ "board": 1, "key": 0, "function": "note", "note": 128 }));
+    assert!(err.to_string().contains("note is 128"), "{err}");
+    let err = parse(json!({ "board": 1, "key": 56, "function": "disabled" }));
+    assert!(!err.to_string().is_empty());
+    let err = parse(json!({ "board": 0, "key": 0, "function": "disabled" }));
+    assert!(err.to_string().contains("boards 1 to 5"), "{err}");
+    let err = parse(json!({ "board": 1, "key": 0, "function": "disabled", "color": "red" }));
+    assert!(err.to_string().contains("#rrggbb"), "{err}");
+
+    // a missing channel or color takes the same default as in a layout
+    let keymap: LumatoneKeyMap = serde_json::from_value(json!({
+      "keys": [{ "board": 1, "key": 0, "function": "note", "note": 60 }]
+    }))
+    .unwrap();
+    assert_eq!(
+      keymap.get_key(key_loc_unchecked(1, 0)),
+      Some(&KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::unchecked(1),
+          note_num: 60,
+        },
+        color: RGBColor(0, 0, 0),
+      })
+    );
+  }
+}
@@ -0,0 +1,96 @@
+//! Human-readable descriptions of keys, for screen readers and tooltips.
+
+use crate::midi::constants::{LumatoneKeyFunction, LumatoneKeyLocation};
+
+const PITCH_CLASS_NAMES: [&str; 12] = [
+  "C", "C sharp", "D", "D sharp", "E", "F", "F sharp", "G", "G sharp", "A", "A sharp", "B",
+];
+
+/// Returns the name of a MIDI note number in 12-EDO, spelled out for reading aloud,
+/// with MIDI note 60 as middle C (e.g. "C 4", "F sharp 2").
+pub fn spoken_note_name(note_num: u8) -> String {
+  let pitch_class = PITCH_CLASS_NAMES[(note_num % 12) as usize];
+  let octave = (note_num / 12) as i32 - 1;
+  format!("{pitch_class} {octave}")
+}
+
+/// Describes where a key is, e.g. "board 2, key 13".
+pub fn location_label(location: &LumatoneKeyLocation) -> String {
+  let board: u8 = location.board_index().into();
+  let key: u8 = location.key_index().into();
+  format!("board {board}, key {key}")
+}
+
+/// Describes a key by its location and what it does, e.g.
+/// "board 2, key 13: note C 4, channel 3". Pass `None` for keys with no definition.
+pub fn key_label(location: &LumatoneKeyLocation, function: Option<&LumatoneKeyFunction>) -> String {
+  let place = location_label(location);
+  let function = match function {
+    None => return format!("{place}: not assigned"),
+    Some(f) => f,
+  };
+  match function {
+    LumatoneKeyFunction::NoteOnOff { channel, note_num } => format!(
+      "{place}: note {}, channel {}",
+      spoken_note_name(*note_num),
+      channel.get()
+    ),
+    LumatoneKeyFunction::ContinuousController {
+      channel, cc_num, ..
+    } => format!("{place}: controller {cc_num}, channel {}", channel.get()),
+    LumatoneKeyFunction::LumaTouch {
+      channel, note_num, ..
+    } => format!(
+      "{place}: LumaTouch note {}, channel {}",
+      spoken_note_name(*note_num),
+      channel.get()
+    ),
+    LumatoneKeyFunction::Disabled => format!("{place}: disabled"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{key_label, spoken_note_name};
+  use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel};
+
+  #[test]
+  fn test_spoken_note_name() {
+    assert_eq!(spoken_note_name(60), "C 4");
+    assert_eq!(spoken_note_name(61), "C sharp 4");
+    assert_eq!(spoken_note_name(21), "A 0");
+    assert_eq!(spoken_note_name(0), "C -1");
+    assert_eq!(spoken_note_name(127), "G 9");
+  }
+
+  #[test]
+  fn test_key_label() {
+    let loc = key_loc_unchecked(2, 13);
+    assert_eq!(key_label(&loc, None), "board 2, key 13: not assigned");
+    assert_eq!(
+      key_label(
+        &loc,
+        Some(&LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::unchecked(3),
+          note_num: 66,
+        })
+      ),
+      "board 2, key 13: note F sharp 4, channel 3"
+    );
+    assert_eq!(
+      key_label(
+        &loc,
+        Some(&LumatoneKeyFunction::ContinuousController {
+          channel: MidiChannel::default(),
+          cc_num: 20,
+          fader_up_is_null: false,
+        })
+      ),
+      "board 2, key 13: controller 20, channel 1"
+    );
+    assert_eq!(
+      key_label(&loc, Some(&LumatoneKeyFunction::Disabled)),
+      "board 2, key 13: disabled"
+    );
+  }
+}
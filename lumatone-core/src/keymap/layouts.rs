@@ -0,0 +1,123 @@
+//! Ready-made [LumatoneKeyMap]s for common layouts.
+
+use crate::geometry::coordinates::{gen_full_board_coords, lumatone_location_for_hex, Hex};
+use crate::midi::constants::{LumatoneKeyFunction, MidiChannel, RGBColor};
+
+use super::ltn::{KeyDefinition, LumatoneKeyMap};
+
+const NATURAL_KEY_COLOR: RGBColor = RGBColor(0xff, 0xff, 0xff);
+const ACCIDENTAL_KEY_COLOR: RGBColor = RGBColor(0x20, 0x20, 0x40);
+
+/// Horizontal position of a hex's center, in half-key widths.
+/// Each row is offset by half a key from the rows above and below it.
+fn horizontal_position(hex: &Hex) -> i32 {
+  2 * hex.q() + hex.r()
+}
+
+/// Returns a keymap that lays out notes by horizontal position, the way a piano does,
+/// so pianists can find their way around without learning an isomorphic layout.
+///
+/// The leftmost key gets `start_note`, and pitch goes up a semitone for every half-key
+/// width to the right. That means moving right along a row goes up a whole tone, and the
+/// keys in the rows just above and below fill in the semitones in between, so zig-zagging
+/// down and to the right between two adjacent rows plays a chromatic scale. Keys in the
+/// same column two rows apart share a note, much like the front and back of a piano key.
+///
+/// The board is 60 half-keys wide, so this covers five of a piano's seven and a bit octaves.
+/// Pick `start_note` for the part of the range you need: 21 starts at a piano's lowest A.
+/// Keys that would be above MIDI note 127 are disabled.
+///
+/// Natural notes are colored white and sharps/flats dark blue.
+pub fn piano_like(start_note: u8, channel: MidiChannel) -> LumatoneKeyMap {
+  let coords = gen_full_board_coords();
+  let leftmost = coords.iter().map(horizontal_position).min().unwrap_or(0);
+
+  let mut keymap = LumatoneKeyMap::new();
+  for hex in coords {
+    let location = match lumatone_location_for_hex(&hex) {
+      Some(location) => *location,
+      None => continue,
+    };
+
+    let note = start_note as i32 + horizontal_position(&hex) - leftmost;
+    let def = match u8::try_from(note) {
+      Ok(note_num) if note_num <= 127 => KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff { channel, note_num },
+        color: if is_accidental(note_num) {
+          ACCIDENTAL_KEY_COLOR
+        } else {
+          NATURAL_KEY_COLOR
+        },
+      },
+      _ => KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor(0, 0, 0),
+      },
+    };
+    keymap.set_key(location, def);
+  }
+  keymap
+}
+
+/// Returns true if `note_num` is a black key on a piano.
+fn is_accidental(note_num: u8) -> bool {
+  matches!(note_num % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::piano_like;
+  use crate::geometry::coordinates::{gen_full_board_coords, lumatone_location_for_hex, Hex};
+  use crate::keymap::ltn::LumatoneKeyMap;
+  use crate::midi::constants::{LumatoneKeyFunction, MidiChannel};
+
+  fn note_at(keymap: &LumatoneKeyMap, hex: &Hex) -> Option<u8> {
+    let location = lumatone_location_for_hex(hex)?;
+    match keymap.get_key(*location)?.function {
+      LumatoneKeyFunction::NoteOnOff { note_num, .. } => Some(note_num),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn test_piano_like_ascends_chromatically() {
+    let channel = MidiChannel::try_from(1).unwrap();
+    let keymap = piano_like(21, channel);
+    let coords = gen_full_board_coords();
+    assert_eq!(keymap.keys().count(), 280);
+
+    let notes: Vec<u8> = coords.iter().filter_map(|h| note_at(&keymap, h)).collect();
+    assert_eq!(notes.iter().min(), Some(&21));
+    assert_eq!(notes.iter().max(), Some(&(21 + 59)));
+
+    let mut checked = 0;
+    for hex in &coords {
+      let note = note_at(&keymap, hex).unwrap();
+      // down and to the right is a semitone up
+      let down_right = Hex::new(hex.q(), hex.r() + 1);
+      if let Some(next) = note_at(&keymap, &down_right) {
+        assert_eq!(next, note + 1, "{hex:?} -> {down_right:?}");
+        checked += 1;
+      }
+      // right along the row is a whole tone up
+      if let Some(next) = note_at(&keymap, &Hex::new(hex.q() + 1, hex.r())) {
+        assert_eq!(next, note + 2);
+      }
+    }
+    assert!(checked > 200);
+  }
+
+  #[test]
+  fn test_piano_like_disables_notes_out_of_range() {
+    let keymap = piano_like(100, MidiChannel::try_from(1).unwrap());
+    let disabled = keymap
+      .keys()
+      .filter(|(_, def)| def.function == LumatoneKeyFunction::Disabled)
+      .count();
+    assert!(disabled > 0);
+    assert!(gen_full_board_coords()
+      .iter()
+      .filter_map(|h| note_at(&keymap, h))
+      .all(|n| n <= 127));
+  }
+}
@@ -9,6 +9,14 @@
 //! Midi note and channel number.
 //!
 //! You can convert [LumatoneKeyMap]s to and from strings in ini format.
+//!
+//! Macro button colors aren't part of the official editor's preset format, so they're
+//! written under keys of our own (see [MacroButtonColors]). The official editor ignores
+//! keys it doesn't recognize, so presets that set them still load there.
+//!
+//! Going the other way, sections and keys that this crate doesn't recognize (say, from a
+//! newer version of the editor) are kept in [IniExtras] when a preset is loaded, and
+//! written back out when it's saved.
 
 use crate::midi::{
   commands::Command,
@@ -18,13 +26,15 @@ use crate::midi::{
   },
 };
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
 use ini::{Ini, Properties};
 use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
 
 use super::{
+  annotations::KeyAnnotation,
   error::LumatoneKeymapError,
   tables::{
     parse_velocity_intervals, velocity_intervals_to_string, ConfigTableDefinition,
@@ -41,23 +51,230 @@ mod keys {
   pub const INVERT_SUSTAIN: &'static str = "InvertSustain";
   pub const LIGHT_ON_KEYSTROKES: &'static str = "LightOnKeyStrokes";
   pub const LUMATOUCH_CONFIG: &'static str = "LumaTouchConfig";
+  pub const MACRO_BUTTON_ACTIVE_COLOR: &str = "MacroButtonActiveCol";
+  pub const MACRO_BUTTON_INACTIVE_COLOR: &str = "MacroButtonInactiveCol";
   pub const NOTE_ON_OFF_VELOCITY_TABLE: &'static str = "NoteOnOffVelocityCrvTbl";
   pub const VELOCITY_INTERVAL_TABLE: &'static str = "VelocityIntrvlTbl";
+  /// The name the note on/off velocity table is read under when loading a preset.
+  pub const NOTE_ON_OFF_VELOCITY_TABLE_ALT: &str = "NoteOnOffVelocityCurveTbl";
+
+  /// Options that [LumatoneKeyMap::from_ini_str](super::LumatoneKeyMap::from_ini_str)
+  /// reads. They may appear in the general section, or in a board section when the
+  /// official editor appends them to the end of the file.
+  pub const GENERAL: &[&str] = &[
+    AFTERTOUCH_ACTIVE,
+    AFTERTOUCH_CONFIG,
+    EXPRESSION_CONTROLLER_SENSITIVITY,
+    FADER_CONFIG,
+    INVERT_FOOT_CONTROLLER,
+    INVERT_SUSTAIN,
+    LIGHT_ON_KEYSTROKES,
+    LUMATOUCH_CONFIG,
+    MACRO_BUTTON_ACTIVE_COLOR,
+    MACRO_BUTTON_INACTIVE_COLOR,
+    NOTE_ON_OFF_VELOCITY_TABLE_ALT,
+    VELOCITY_INTERVAL_TABLE,
+  ];
+
+  /// Prefixes of the per-key properties in a board section, which are followed by the
+  /// key index.
+  pub const KEY_PREFIXES: &[&str] = &["Key_", "Chan_", "Col_", "KTyp_"];
 }
 
-#[derive(Debug)]
+/// Preset file sections and keys that a [LumatoneKeyMap] doesn't model, kept verbatim so
+/// that loading and saving a preset doesn't lose them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IniExtras {
+  /// Sections in the order they were found, with `None` for the general section. Each
+  /// has its unrecognized keys and values, in order.
+  sections: Vec<ExtraSection>,
+}
+
+type ExtraSection = (Option<String>, Vec<(String, String)>);
+
+impl IniExtras {
+  /// Collects everything in `ini` that [is_modeled] doesn't claim. Board sections are
+  /// renumbered from 0 if `first_board` is 1 (see [first_board_section]), so their
+  /// extras are written back to the same board.
+  fn from_ini(ini: &Ini, first_board: u8) -> Self {
+    let sections = ini
+      .iter()
+      .filter_map(|(section, props)| {
+        let unknown: Vec<(String, String)> = props
+          .iter()
+          .filter(|(key, _)| !is_modeled(section, key, first_board))
+          .map(|(key, value)| (key.to_string(), value.to_string()))
+          .collect();
+        // keep unknown sections even if they're empty, since the header may matter
+        let modeled = is_modeled_section(section, first_board);
+        let keep = !unknown.is_empty() || !modeled;
+        let name = section.map(|name| match board_section_number(name) {
+          Some(n) if modeled => format!("Board{}", n - first_board),
+          _ => name.to_string(),
+        });
+        keep.then_some((name, unknown))
+      })
+      .collect();
+    IniExtras { sections }
+  }
+
+  /// Adds the extra keys to `conf`, leaving alone any that it already has.
+  fn write_to(&self, conf: &mut Ini) {
+    for (section, entries) in &self.sections {
+      let props = conf.entry(section.clone()).or_insert_with(Default::default);
+      for (key, value) in entries {
+        if !props.contains_key(key) {
+          props.append(key, value);
+        }
+      }
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.sections.is_empty()
+  }
+
+  /// Returns each section name (`None` for the general section) with its extra keys and
+  /// values.
+  pub fn sections(&self) -> impl Iterator<Item = (Option<&str>, &[(String, String)])> {
+    self
+      .sections
+      .iter()
+      .map(|(name, entries)| (name.as_deref(), entries.as_slice()))
+  }
+}
+
+/// Returns true for the general section and the board sections.
+fn is_modeled_section(section: Option<&str>, first_board: u8) -> bool {
+  match section {
+    None => true,
+    Some(name) => {
+      board_section_number(name).is_some_and(|n| (first_board..first_board + 5).contains(&n))
+    }
+  }
+}
+
+/// The number `n` of a `Board<n>` section.
+fn board_section_number(name: &str) -> Option<u8> {
+  name
+    .strip_prefix("Board")
+    .and_then(|n| n.parse::<u8>().ok())
+}
+
+/// Returns the number of the first board's section in `ini`. Boards are numbered from 0,
+/// but older versions of this crate numbered them from 1, so a file with a `Board5`
+/// section and no `Board0` is taken to be one of theirs.
+fn first_board_section(ini: &Ini) -> u8 {
+  let has_board = |n: u8| ini.section(Some(format!("Board{n}"))).is_some();
+  if has_board(5) && !has_board(0) {
+    1
+  } else {
+    0
+  }
+}
+
+/// Returns true if [LumatoneKeyMap::from_ini_str] reads `key` in `section`.
+fn is_modeled(section: Option<&str>, key: &str, first_board: u8) -> bool {
+  if !is_modeled_section(section, first_board) {
+    return false;
+  }
+  if keys::GENERAL.contains(&key) {
+    return true;
+  }
+  section.is_some()
+    && keys::KEY_PREFIXES.iter().any(|prefix| {
+      key
+        .strip_prefix(prefix)
+        .and_then(|k| k.parse::<u8>().ok())
+        .is_some_and(|k| k <= LumatoneKeyIndex::MAX_VALUE)
+    })
+}
+
+/// Serializes as the function's fields alongside the color, e.g.
+/// `{"function": "note", "channel": 1, "note": 60, "color": "#ff0000"}`. A missing color
+/// is read as black (off).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyDefinition {
+  #[serde(flatten)]
   pub function: LumatoneKeyFunction,
+  #[serde(default = "unlit")]
   pub color: RGBColor,
 }
 
-#[derive(Debug)]
+fn unlit() -> RGBColor {
+  RGBColor(0, 0, 0)
+}
+
+/// What [LumatoneKeyMap::set_keys_counted] did, for sanity checking generated layouts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyUpdateCounts {
+  /// Keys that weren't defined before.
+  pub added: usize,
+  /// Keys that were defined before, and now have a new definition.
+  pub replaced: usize,
+  /// Locations that came up again after their first definition in the same batch.
+  /// They aren't counted as added or replaced.
+  pub duplicates: usize,
+}
+
+/// Colors for the macro buttons, which are set together since the device has no
+/// default for either one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MacroButtonColors {
+  /// Color of a macro button while it's held down.
+  pub active: RGBColor,
+  /// Color of a macro button the rest of the time.
+  pub inactive: RGBColor,
+}
+
+impl MacroButtonColors {
+  /// Returns the commands that set both colors on the device.
+  pub fn to_midi_commands(&self) -> Vec<Command> {
+    vec![
+      Command::SetMacroButtonActiveColor(self.active),
+      Command::SetMacroButtonInactiveColor(self.inactive),
+    ]
+  }
+
+  /// Reads the colors from an ini section, if both are set.
+  fn from_ini_section(
+    props: &Properties,
+  ) -> Result<Option<MacroButtonColors>, LumatoneKeymapError> {
+    let active = props.get(keys::MACRO_BUTTON_ACTIVE_COLOR);
+    let inactive = props.get(keys::MACRO_BUTTON_INACTIVE_COLOR);
+    match (active, inactive) {
+      (Some(active), Some(inactive)) => Ok(Some(MacroButtonColors {
+        active: parse_color(active)?,
+        inactive: parse_color(inactive)?,
+      })),
+      _ => Ok(None),
+    }
+  }
+}
+
+/// Settings for the keyboard as a whole. Each one is optional: `None` means the keymap
+/// doesn't say, so applying it leaves the device's setting alone, and the setting isn't
+/// written to .ltn files.
+///
+/// These used to be plain values that defaulted to off and 0, so applying any keymap turned
+/// aftertouch off unless it asked for it. Wrap values in `Some` when building the struct
+/// directly, or use [GeneralOptions::builder].
+///
+/// Serializes with the options that aren't set left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GeneralOptions {
-  pub after_touch_active: bool,
-  pub light_on_key_strokes: bool,
-  pub invert_foot_controller: bool,
-  pub invert_sustain: bool,
-  pub expression_controller_sensitivity: u8,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub after_touch_active: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub light_on_key_strokes: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub invert_foot_controller: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub invert_sustain: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub expression_controller_sensitivity: Option<u8>,
 
   pub config_tables: ConfigurationTables,
 }
@@ -73,8 +290,49 @@ fn config_table_from_ini_section(
 }
 
 impl GeneralOptions {
+  /// Starts building options with nothing set.
+  pub fn builder() -> GeneralOptionsBuilder {
+    GeneralOptionsBuilder::default()
+  }
+
+  /// Returns true if none of the options or tables are set.
+  pub fn is_empty(&self) -> bool {
+    *self == GeneralOptions::default()
+  }
+
+  /// Returns options with each one taken from `self` if set, otherwise from `fallback`.
+  pub fn or(self, fallback: GeneralOptions) -> GeneralOptions {
+    let tables = self.config_tables;
+    let fallback_tables = fallback.config_tables;
+    GeneralOptions {
+      after_touch_active: self.after_touch_active.or(fallback.after_touch_active),
+      light_on_key_strokes: self.light_on_key_strokes.or(fallback.light_on_key_strokes),
+      invert_foot_controller: self
+        .invert_foot_controller
+        .or(fallback.invert_foot_controller),
+      invert_sustain: self.invert_sustain.or(fallback.invert_sustain),
+      expression_controller_sensitivity: self
+        .expression_controller_sensitivity
+        .or(fallback.expression_controller_sensitivity),
+      config_tables: ConfigurationTables {
+        on_off_velocity: tables.on_off_velocity.or(fallback_tables.on_off_velocity),
+        fader_velocity: tables.fader_velocity.or(fallback_tables.fader_velocity),
+        aftertouch_velocity: tables
+          .aftertouch_velocity
+          .or(fallback_tables.aftertouch_velocity),
+        lumatouch_velocity: tables
+          .lumatouch_velocity
+          .or(fallback_tables.lumatouch_velocity),
+        velocity_intervals: tables
+          .velocity_intervals
+          .or(fallback_tables.velocity_intervals),
+      },
+    }
+  }
+
   fn from_ini_section(props: &Properties) -> Result<GeneralOptions, LumatoneKeymapError> {
-    let on_off_velocity = config_table_from_ini_section(props, "NoteOnOffVelocityCurveTbl")?;
+    let on_off_velocity =
+      config_table_from_ini_section(props, keys::NOTE_ON_OFF_VELOCITY_TABLE_ALT)?;
     let fader_velocity = config_table_from_ini_section(props, "FaderConfig")?;
     let aftertouch_velocity = config_table_from_ini_section(props, "afterTouchConfig")?;
     let lumatouch_velocity = config_table_from_ini_section(props, "LumaTouchConfig")?;
@@ -84,21 +342,13 @@ impl GeneralOptions {
     };
 
     Ok(GeneralOptions {
-      after_touch_active: props.get(keys::AFTERTOUCH_ACTIVE).map(bool_val).unwrap_or(false),
-      light_on_key_strokes: props
-        .get(keys::LIGHT_ON_KEYSTROKES)
-        .map(bool_val)
-        .unwrap_or(false),
-      invert_foot_controller: props
-        .get(keys::INVERT_FOOT_CONTROLLER)
-        .map(bool_val)
-        .unwrap_or(false),
-      invert_sustain: props.get(keys::INVERT_SUSTAIN).map(bool_val).unwrap_or(false),
+      after_touch_active: props.get(keys::AFTERTOUCH_ACTIVE).map(bool_val),
+      light_on_key_strokes: props.get(keys::LIGHT_ON_KEYSTROKES).map(bool_val),
+      invert_foot_controller: props.get(keys::INVERT_FOOT_CONTROLLER).map(bool_val),
+      invert_sustain: props.get(keys::INVERT_SUSTAIN).map(bool_val),
       expression_controller_sensitivity: props
         .get(keys::EXPRESSION_CONTROLLER_SENSITIVITY)
-        .map(|s| u8::from_str_radix(s, 10).ok())
-        .flatten()
-        .unwrap_or(0),
+        .and_then(|s| s.parse().ok()),
       config_tables: ConfigurationTables {
         on_off_velocity,
         fader_velocity,
@@ -110,23 +360,66 @@ impl GeneralOptions {
   }
 }
 
-impl Default for GeneralOptions {
-  fn default() -> Self {
-    GeneralOptions {
-      after_touch_active: false,
-      light_on_key_strokes: false,
-      invert_foot_controller: false,
-      invert_sustain: false,
-      expression_controller_sensitivity: 0,
-      config_tables: ConfigurationTables::default(),
-    }
+/// Builds [GeneralOptions], setting only the options it's told to:
+///
+/// ```
+/// use lumatone_core::keymap::ltn::GeneralOptions;
+///
+/// let options = GeneralOptions::builder().aftertouch(true).build();
+/// assert_eq!(options.after_touch_active, Some(true));
+/// assert_eq!(options.invert_sustain, None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GeneralOptionsBuilder {
+  options: GeneralOptions,
+}
+
+impl GeneralOptionsBuilder {
+  pub fn aftertouch(mut self, active: bool) -> Self {
+    self.options.after_touch_active = Some(active);
+    self
+  }
+
+  pub fn light_on_key_strokes(mut self, light: bool) -> Self {
+    self.options.light_on_key_strokes = Some(light);
+    self
+  }
+
+  pub fn invert_foot_controller(mut self, invert: bool) -> Self {
+    self.options.invert_foot_controller = Some(invert);
+    self
+  }
+
+  pub fn invert_sustain(mut self, invert: bool) -> Self {
+    self.options.invert_sustain = Some(invert);
+    self
+  }
+
+  pub fn expression_sensitivity(mut self, sensitivity: u8) -> Self {
+    self.options.expression_controller_sensitivity = Some(sensitivity);
+    self
+  }
+
+  pub fn config_tables(mut self, tables: ConfigurationTables) -> Self {
+    self.options.config_tables = tables;
+    self
+  }
+
+  pub fn build(self) -> GeneralOptions {
+    self.options
   }
 }
 
-#[derive(Debug)]
+/// Two keymaps are equal if they define the same keys the same way and have the same
+/// options, [IniExtras] and annotations. See [LumatoneKeyMap::fingerprint] for a cheaper
+/// way to compare them over time.
+#[derive(Debug, PartialEq)]
 pub struct LumatoneKeyMap {
   keys: HashMap<LumatoneKeyLocation, KeyDefinition>,
   general: GeneralOptions,
+  macro_buttons: Option<MacroButtonColors>,
+  extras: IniExtras,
+  annotations: HashMap<LumatoneKeyLocation, KeyAnnotation>,
 }
 
 impl LumatoneKeyMap {
@@ -134,6 +427,9 @@ impl LumatoneKeyMap {
     LumatoneKeyMap {
       keys: HashMap::new(),
       general: GeneralOptions::default(),
+      macro_buttons: None,
+      extras: IniExtras::default(),
+      annotations: HashMap::new(),
     }
   }
 
@@ -150,37 +446,269 @@ impl LumatoneKeyMap {
     self.keys.get(&location)
   }
 
-  // TODO: add batch key update fn that takes HashMap or seq of (location, definition) tuples
+  /// Returns the MIDI channel of the key at `location`, or `None` if the key
+  /// isn't defined or is disabled.
+  pub fn channel_at(&self, location: LumatoneKeyLocation) -> Option<MidiChannel> {
+    self
+      .get_key(location)
+      .and_then(|def| def.function.channel())
+  }
+
+  /// Returns an iterator over all defined keys, in arbitrary order.
+  pub fn keys(&self) -> impl Iterator<Item = (&LumatoneKeyLocation, &KeyDefinition)> {
+    self.keys.iter()
+  }
+
+  /// Returns the sum of the red, green and blue components of every defined key's color.
+  ///
+  /// LED power draw goes up with brightness, so this gives a rough idea of how much power
+  /// a layout will use, for comparing layouts or checking how much dimming helps.
+  pub fn total_led_intensity(&self) -> u64 {
+    self
+      .keys
+      .values()
+      .map(|def| led_intensity(&def.color))
+      .sum()
+  }
+
+  /// Returns the `n` keys with the highest LED intensity (red + green + blue), brightest first.
+  /// Keys with the same intensity are ordered by location.
+  pub fn brightest_keys(&self, n: usize) -> Vec<(&LumatoneKeyLocation, &KeyDefinition)> {
+    let mut keys: Vec<_> = self.keys.iter().collect();
+    keys.sort_by_key(|(loc, def)| {
+      let board: u8 = loc.board_index().into();
+      let key: u8 = loc.key_index().into();
+      (std::cmp::Reverse(led_intensity(&def.color)), board, key)
+    });
+    keys.truncate(n);
+    keys
+  }
+
+  /// Returns the lowest and highest MIDI note sent by any note or Lumatouch key, or `None`
+  /// if no key sends a note. Continuous controller keys aren't counted.
+  pub fn note_range(&self) -> Option<(u8, u8)> {
+    let notes = self.keys.values().filter_map(|def| match def.function {
+      LumatoneKeyFunction::NoteOnOff { note_num, .. }
+      | LumatoneKeyFunction::LumaTouch { note_num, .. } => Some(note_num),
+      _ => None,
+    });
+    notes.fold(None, |range, n| match range {
+      None => Some((n, n)),
+      Some((lo, hi)) => Some((lo.min(n), hi.max(n))),
+    })
+  }
+
+  /// Sets the definition for each key on `board` with an index in `start..=end`,
+  /// using `def_fn` to create each key's [KeyDefinition].
+  ///
+  /// Returns an error without modifying the keymap if the range is empty or extends past key 55.
+  pub fn set_key_range(
+    &mut self,
+    board: BoardIndex,
+    start: u8,
+    end: u8,
+    def_fn: impl Fn(LumatoneKeyIndex) -> KeyDefinition,
+  ) -> Result<&mut LumatoneKeyMap, LumatoneKeymapError> {
+    if start > end || end > LumatoneKeyIndex::MAX_VALUE {
+      return Err(LumatoneKeymapError::InvalidKeyRange(start, end));
+    }
+
+    for k in start..=end {
+      let key_index = LumatoneKeyIndex::unchecked(k);
+      self.set_key(LumatoneKeyLocation(board, key_index), def_fn(key_index));
+    }
+    Ok(self)
+  }
+
+  /// Makes each of `keys` play a note, in order: the first plays `start`, and each one
+  /// after it plays `step` higher, or lower if `step` is negative. Keys keep their MIDI
+  /// channel if they have one, and otherwise play on channel 1.
+  ///
+  /// If `palette` is given, each key also gets the color of its note's pitch class, with
+  /// note 0 taking the first color and each note after it the next one, wrapping around.
+  /// Otherwise keys keep their colors.
+  ///
+  /// Returns an error without modifying the keymap if a note would go outside 0..=127.
+  pub fn assign_note_run(
+    &mut self,
+    keys: &[LumatoneKeyLocation],
+    start: u8,
+    step: i8,
+    palette: Option<&[RGBColor]>,
+  ) -> Result<&mut LumatoneKeyMap, LumatoneKeymapError> {
+    let last = start as i32 + step as i32 * (keys.len() as i32 - 1).max(0);
+    if !(0..=127).contains(&last) {
+      return Err(LumatoneKeymapError::NoteOutOfRange(last));
+    }
+
+    for (i, location) in keys.iter().enumerate() {
+      let note_num = (start as i32 + step as i32 * i as i32) as u8;
+      let existing = self.keys.get(location);
+      let channel = existing
+        .and_then(|def| def.function.channel())
+        .unwrap_or_default();
+      let color = match palette {
+        Some(colors) if !colors.is_empty() => colors[note_num as usize % colors.len()],
+        _ => existing.map_or(RGBColor(0, 0, 0), |def| def.color),
+      };
+      let function = LumatoneKeyFunction::NoteOnOff { channel, note_num };
+      self.set_key(*location, KeyDefinition { function, color });
+    }
+    Ok(self)
+  }
+
+  /// Splits the keymap into one keymap per board, each with only that board's keys and
+  /// annotations, and a copy of the general options and macro button colors. Boards with
+  /// no keys or annotations are left out.
+  ///
+  /// [IniExtras] aren't copied, since they belong to the preset file as a whole.
+  pub fn split_by_board(&self) -> HashMap<BoardIndex, LumatoneKeyMap> {
+    let mut boards: HashMap<BoardIndex, LumatoneKeyMap> = HashMap::new();
+    let new_board = || LumatoneKeyMap {
+      general: self.general.clone(),
+      macro_buttons: self.macro_buttons,
+      ..LumatoneKeyMap::new()
+    };
+    for (location, def) in &self.keys {
+      let board = boards
+        .entry(location.board_index())
+        .or_insert_with(new_board);
+      board.keys.insert(*location, def.clone());
+    }
+    for (location, annotation) in &self.annotations {
+      let board = boards
+        .entry(location.board_index())
+        .or_insert_with(new_board);
+      board.annotations.insert(*location, annotation.clone());
+    }
+    boards
+  }
+
+  /// Sets every key in `keys`, which can be a `HashMap` or any other iterator of
+  /// `(location, definition)` pairs. If a location comes up more than once, the last
+  /// definition for it wins.
+  ///
+  /// Use [LumatoneKeyMap::set_keys_counted] to find out how many keys were added or replaced.
+  pub fn set_keys(
+    &mut self,
+    keys: impl IntoIterator<Item = (LumatoneKeyLocation, KeyDefinition)>,
+  ) -> &mut LumatoneKeyMap {
+    self.set_keys_counted(keys);
+    self
+  }
+
+  /// Like [LumatoneKeyMap::set_keys], but returns how many keys were added or replaced,
+  /// and how many locations were given more than once.
+  pub fn set_keys_counted(
+    &mut self,
+    keys: impl IntoIterator<Item = (LumatoneKeyLocation, KeyDefinition)>,
+  ) -> KeyUpdateCounts {
+    let mut counts = KeyUpdateCounts::default();
+    let mut seen = HashSet::new();
+    for (location, def) in keys {
+      let previous = self.keys.insert(location, def);
+      if !seen.insert(location) {
+        counts.duplicates += 1;
+      } else if previous.is_some() {
+        counts.replaced += 1;
+      } else {
+        counts.added += 1;
+      }
+    }
+    counts
+  }
+
+  pub fn global_options(&self) -> &GeneralOptions {
+    &self.general
+  }
 
   pub fn set_global_options<'a>(&'a mut self, opts: GeneralOptions) -> &'a mut LumatoneKeyMap {
     self.general = opts;
     self
   }
 
+  /// Returns the macro button colors, or `None` if the keymap leaves them as they are
+  /// on the device.
+  pub fn macro_button_colors(&self) -> Option<&MacroButtonColors> {
+    self.macro_buttons.as_ref()
+  }
+
+  pub fn set_macro_button_colors(
+    &mut self,
+    colors: Option<MacroButtonColors>,
+  ) -> &mut LumatoneKeyMap {
+    self.macro_buttons = colors;
+    self
+  }
+
+  /// Returns the sections and keys from the preset this keymap was loaded from that it
+  /// doesn't otherwise model. They're written back out by [LumatoneKeyMap::to_ini].
+  pub fn extras(&self) -> &IniExtras {
+    &self.extras
+  }
+
+  pub(super) fn set_extras(&mut self, extras: IniExtras) {
+    self.extras = extras;
+  }
+
+  /// Returns the [KeyAnnotation] for the key at `location`, if it has one.
+  pub fn annotation(&self, location: LumatoneKeyLocation) -> Option<&KeyAnnotation> {
+    self.annotations.get(&location)
+  }
+
+  /// Returns an iterator over all annotated keys, in arbitrary order.
+  pub fn annotations(&self) -> impl Iterator<Item = (&LumatoneKeyLocation, &KeyAnnotation)> {
+    self.annotations.iter()
+  }
+
+  /// Sets the annotation for the key at `location`, or removes it if `annotation` is
+  /// empty. Annotations aren't sent to the device, and aren't saved in .ltn presets yet.
+  pub fn set_annotation(
+    &mut self,
+    location: LumatoneKeyLocation,
+    annotation: KeyAnnotation,
+  ) -> &mut LumatoneKeyMap {
+    if annotation.is_empty() {
+      self.annotations.remove(&location);
+    } else {
+      self.annotations.insert(location, annotation);
+    }
+    self
+  }
+
+  /// Returns the commands that set the macro button colors, or nothing if they aren't set.
+  pub fn macro_button_commands(&self) -> Vec<Command> {
+    self
+      .macro_buttons
+      .map(|colors| colors.to_midi_commands())
+      .unwrap_or_default()
+  }
+
   pub fn to_ini(&self) -> Ini {
     let mut conf = Ini::new();
 
     let bool_str = |b: bool| if b { 1 } else { 0 }.to_string();
-    // set general options
-    conf
-      .with_general_section()
-      .set(
-        keys::AFTERTOUCH_ACTIVE,
-        bool_str(self.general.after_touch_active),
-      )
-      .set(
-        keys::LIGHT_ON_KEYSTROKES,
-        bool_str(self.general.light_on_key_strokes),
-      )
-      .set(
+    // set general options, leaving out the ones the keymap doesn't set
+    let flags = [
+      (keys::AFTERTOUCH_ACTIVE, self.general.after_touch_active),
+      (keys::LIGHT_ON_KEYSTROKES, self.general.light_on_key_strokes),
+      (
         keys::INVERT_FOOT_CONTROLLER,
-        bool_str(self.general.invert_foot_controller),
-      )
-      .set(keys::INVERT_SUSTAIN, bool_str(self.general.invert_sustain))
-      .set(
+        self.general.invert_foot_controller,
+      ),
+      (keys::INVERT_SUSTAIN, self.general.invert_sustain),
+    ];
+    for (key, value) in flags {
+      if let Some(b) = value {
+        conf.with_general_section().set(key, bool_str(b));
+      }
+    }
+    if let Some(sensitivity) = self.general.expression_controller_sensitivity {
+      conf.with_general_section().set(
         keys::EXPRESSION_CONTROLLER_SENSITIVITY,
-        self.general.expression_controller_sensitivity.to_string(),
+        sensitivity.to_string(),
       );
+    }
 
     if let Some(t) = &self.general.config_tables.velocity_intervals {
       conf
@@ -212,54 +740,68 @@ impl LumatoneKeyMap {
         .set(keys::LUMATOUCH_CONFIG, t.to_string());
     }
 
-    // Key definitions are split into sections, one for each board / octave
+    if let Some(colors) = &self.macro_buttons {
+      conf
+        .with_general_section()
+        .set(
+          keys::MACRO_BUTTON_ACTIVE_COLOR,
+          colors.active.to_hex_string(),
+        )
+        .set(
+          keys::MACRO_BUTTON_INACTIVE_COLOR,
+          colors.inactive.to_hex_string(),
+        );
+    }
+
+    // Key definitions are split into sections, one for each board / octave, numbered
+    // from 0 like the official editor's presets, so they load back the same way.
+    // Keys are written in index order, so the output is stable for a given keymap.
     for b in 1..=5 {
       let board_index: BoardIndex = FromPrimitive::from_u8(b).unwrap();
-      let keys = self
-        .keys
-        .iter()
-        .filter(|(loc, _)| loc.board_index() == board_index);
-
-      let section_name = format!("Board{}", b);
-      for (loc, def) in keys {
-        let key_index: u8 = loc.key_index().into();
-        let key_type = def.function.key_type_code();
-
-        conf
-          .with_section(Some(section_name.clone()))
-          .set(
-            format!("Key_{key_index}"),
-            def.function.note_or_cc_num().to_string(),
-          )
-          .set(
-            format!("Chan_{key_index}"),
-            def.function.midi_channel_num().to_string(),
-          )
-          .set(format!("Col_{key_index}"), def.color.to_hex_string());
-
-        if key_type != 1 {
-          conf
-            .with_section(Some(section_name.clone()))
-            .set(format!("KTyp_{key_index}"), key_type.to_string());
-        }
-      }
-
-      // explicitly set any missing keys to "disabled"
+      let section_name = format!("Board{}", b - 1);
       for k in LumatoneKeyIndex::MIN_VALUE..=LumatoneKeyIndex::MAX_VALUE {
         let key_index = LumatoneKeyIndex::unchecked(k);
         let loc = LumatoneKeyLocation(board_index, key_index);
-        if self.keys.contains_key(&loc) {
-          continue;
+
+        match self.keys.get(&loc) {
+          Some(def) => {
+            let key_type = def.function.key_type_code();
+            conf
+              .with_section(Some(section_name.clone()))
+              .set(
+                format!("Key_{key_index}"),
+                def.function.note_or_cc_num().to_string(),
+              )
+              .set(
+                format!("Chan_{key_index}"),
+                def.function.midi_channel_num().to_string(),
+              )
+              .set(format!("Col_{key_index}"), def.color.to_hex_string());
+
+            if key_type != 1 {
+              conf
+                .with_section(Some(section_name.clone()))
+                .set(format!("KTyp_{key_index}"), key_type.to_string());
+            }
+          }
+
+          // explicitly set any missing keys to "disabled". Keys that are defined as
+          // disabled keep their color, e.g. for purely decorative lighting.
+          None => {
+            conf
+              .with_section(Some(section_name.clone()))
+              .set(format!("Key_{key_index}"), "0")
+              .set(format!("Chan_{key_index}"), "1")
+              .set(format!("Col_{key_index}"), "000000")
+              .set(format!("KTyp_{key_index}"), "4");
+          }
         }
-        conf
-          .with_section(Some(section_name.clone()))
-          .set(format!("Key_{key_index}"), "0")
-          .set(format!("Chan_{key_index}"), "1")
-          .set(format!("Col_{key_index}"), "000000")
-          .set(format!("KTyp_{key_index}"), "4");
       }
     }
 
+    // anything we loaded but don't model goes back out as it was, unless it clashes
+    self.extras.write_to(&mut conf);
+
     conf
   }
 
@@ -274,16 +816,34 @@ impl LumatoneKeyMap {
   pub fn from_ini_str<S: AsRef<str>>(source: S) -> Result<LumatoneKeyMap, LumatoneKeymapError> {
     let ini = Ini::load_from_str(source.as_ref())?;
 
-    let mut general = GeneralOptions::default();
+    // we write options to the general section, but the official editor puts them in a
+    // board section (see below), so options set in either place are kept
+    // (`general_section` panics if there isn't one, e.g. when a keymap sets no options)
+    let general_section = ini.section(None::<String>);
+    let mut general = general_section
+      .and_then(|props| GeneralOptions::from_ini_section(props).ok())
+      .unwrap_or_default();
     let mut keys: HashMap<LumatoneKeyLocation, KeyDefinition> = HashMap::new();
+    let mut macro_buttons = match general_section {
+      Some(props) => MacroButtonColors::from_ini_section(props)?,
+      None => None,
+    };
+
+    let first_board = first_board_section(&ini);
+    if first_board == 1 {
+      log::warn!("preset numbers its boards from 1, as older versions of lumatone-rs did");
+    }
 
     for b in 1..=5 {
-      let key = format!("Board{}", b - 1);
+      let key = format!("Board{}", b - 1 + first_board);
       if let Some(section) = ini.section(Some(key)) {
         // The official LumatoneEditor just spits global options out at the end of the file,
         // so they get slurped into the [Board5] section.
         if let Ok(general_opts) = GeneralOptions::from_ini_section(section) {
-          general = general_opts;
+          general = general_opts.or(general);
+        }
+        if let Some(colors) = MacroButtonColors::from_ini_section(section)? {
+          macro_buttons = Some(colors);
         }
 
         for k in 0..=55 {
@@ -291,9 +851,7 @@ impl LumatoneKeyMap {
           let note_or_cc_num = get_u8_or_default_from_ini_section(section, format!("Key_{k}"), 0);
           let chan = get_u8_or_default_from_ini_section(section, format!("Chan_{k}"), 1);
           let color_str = section.get(format!("Col_{k}")).unwrap_or("000000");
-          let color_u32 =
-            u32::from_str_radix(color_str, 16).map_err(|_| LumatoneKeymapError::ValueParseError)?;
-          let color = RGBColor::from(color_u32);
+          let color = parse_color(color_str)?;
 
           let channel = MidiChannel::new(chan).unwrap_or_default();
           let function = match key_type_code {
@@ -326,49 +884,152 @@ impl LumatoneKeyMap {
       }
     }
 
-    Ok(LumatoneKeyMap { keys, general })
+    Ok(LumatoneKeyMap {
+      keys,
+      general,
+      macro_buttons,
+      extras: IniExtras::from_ini(&ini, first_board),
+      annotations: HashMap::new(),
+    })
   }
 
   pub fn to_midi_commands(&self) -> Vec<Command> {
+    self.to_midi_commands_filtered(ApplyParts::all())
+  }
+
+  /// Like [LumatoneKeyMap::to_midi_commands], but only for the given parts of the keymap,
+  /// e.g. to send a tweaked velocity curve without re-sending every key.
+  pub fn to_midi_commands_filtered(&self, parts: ApplyParts) -> Vec<Command> {
     use Command::*;
-    let mut commands = vec![
-      SetAftertouchEnabled(self.general.after_touch_active),
-      SetLightOnKeystrokes(self.general.light_on_key_strokes),
-      InvertFootController(self.general.invert_foot_controller),
-      InvertSustainPedal(self.general.invert_sustain),
-      SetExpressionPedalSensitivity(self.general.expression_controller_sensitivity),
-    ];
+    let mut commands = vec![];
 
-    let tables = &self.general.config_tables;
-    if let Some(t) = &tables.on_off_velocity {
-      commands.push(SetVelocityConfig(Box::new(t.table)));
-    }
-    if let Some(t) = &tables.aftertouch_velocity {
-      commands.push(SetAftertouchConfig(Box::new(t.table)));
-    }
-    if let Some(t) = &tables.fader_velocity {
-      commands.push(SetFaderConfig(Box::new(t.table)));
+    if parts.options {
+      // options the keymap doesn't set are left as they are on the device
+      let general = &self.general;
+      commands.extend(general.after_touch_active.map(SetAftertouchEnabled));
+      commands.extend(general.light_on_key_strokes.map(SetLightOnKeystrokes));
+      commands.extend(general.invert_foot_controller.map(InvertFootController));
+      commands.extend(general.invert_sustain.map(InvertSustainPedal));
+      commands.extend(
+        general
+          .expression_controller_sensitivity
+          .map(SetExpressionPedalSensitivity),
+      );
     }
-    if let Some(t) = &tables.lumatouch_velocity {
-      commands.push(SetLumatouchConfig(Box::new(t.table)));
+
+    if parts.tables {
+      let tables = &self.general.config_tables;
+      if let Some(t) = &tables.on_off_velocity {
+        commands.push(SetVelocityConfig(Box::new(t.table)));
+      }
+      if let Some(t) = &tables.aftertouch_velocity {
+        commands.push(SetAftertouchConfig(Box::new(t.table)));
+      }
+      if let Some(t) = &tables.fader_velocity {
+        commands.push(SetFaderConfig(Box::new(t.table)));
+      }
+      if let Some(t) = &tables.lumatouch_velocity {
+        commands.push(SetLumatouchConfig(Box::new(t.table)));
+      }
+      if let Some(t) = tables.velocity_intervals {
+        commands.push(SetVelocityIntervals(Box::new(t)));
+      }
     }
-    if let Some(t) = tables.velocity_intervals {
-      commands.push(SetVelocityIntervals(Box::new(t)));
+
+    if parts.options {
+      commands.extend(self.macro_button_commands());
     }
 
     for (location, definition) in self.keys.iter() {
-      commands.push(SetKeyFunction {
-        location: *location,
-        function: definition.function,
-      });
-      commands.push(SetKeyColor {
-        location: *location,
-        color: definition.color,
-      });
+      if parts.functions {
+        commands.push(SetKeyFunction {
+          location: *location,
+          function: definition.function,
+        });
+      }
+      if parts.colors {
+        commands.push(SetKeyColor {
+          location: *location,
+          color: definition.color,
+        });
+      }
     }
 
     commands
   }
+
+  /// Like [LumatoneKeyMap::to_midi_commands_filtered], but leaves out the per-key and
+  /// per-board commands for boards not in `boards`, e.g. when only some octaves are
+  /// plugged in. Commands for the keyboard as a whole are always included.
+  pub fn to_midi_commands_for_boards(
+    &self,
+    parts: ApplyParts,
+    boards: &[BoardIndex],
+  ) -> Vec<Command> {
+    self
+      .to_midi_commands_filtered(parts)
+      .into_iter()
+      .filter(|c| c.board_index().is_none_or(|b| boards.contains(&b)))
+      .collect()
+  }
+}
+
+/// Which parts of a keymap [LumatoneKeyMap::to_midi_commands_filtered] makes commands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyParts {
+  /// What each key sends (note, CC, etc.) and on which channel.
+  pub functions: bool,
+  /// The color of each key.
+  pub colors: bool,
+  /// The [GeneralOptions] flags and sensitivity, and the macro button colors.
+  pub options: bool,
+  /// The velocity and fader tables, and the velocity intervals.
+  pub tables: bool,
+}
+
+impl ApplyParts {
+  pub fn all() -> Self {
+    ApplyParts {
+      functions: true,
+      colors: true,
+      options: true,
+      tables: true,
+    }
+  }
+
+  pub fn none() -> Self {
+    ApplyParts {
+      functions: false,
+      colors: false,
+      options: false,
+      tables: false,
+    }
+  }
+
+  /// Both the functions and the colors of the keys, and nothing else.
+  pub fn keys() -> Self {
+    ApplyParts {
+      functions: true,
+      colors: true,
+      ..ApplyParts::none()
+    }
+  }
+}
+
+impl Default for ApplyParts {
+  fn default() -> Self {
+    ApplyParts::all()
+  }
+}
+
+fn led_intensity(color: &RGBColor) -> u64 {
+  color.0 as u64 + color.1 as u64 + color.2 as u64
+}
+
+/// Parses a color in the `rrggbb` hex format used by preset files.
+fn parse_color(s: &str) -> Result<RGBColor, LumatoneKeymapError> {
+  let color_u32 = u32::from_str_radix(s, 16).map_err(|_| LumatoneKeymapError::ValueParseError)?;
+  Ok(RGBColor::from(color_u32))
 }
 
 fn bool_val(s: &str) -> bool {
@@ -392,61 +1053,138 @@ mod tests {
   use crate::keymap::tables::ConfigurationTables;
   use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor};
 
-  use super::{GeneralOptions, KeyDefinition, LumatoneKeyMap};
+  use super::{GeneralOptions, KeyDefinition, KeyUpdateCounts, LumatoneKeyMap, MacroButtonColors};
+  use crate::keymap::annotations::KeyAnnotation;
+  use crate::keymap::error::LumatoneKeymapError;
+  use crate::keymap::layouts::piano_like;
+  use crate::midi::commands::Command;
+  use crate::midi::constants::BoardIndex;
+  use std::collections::HashMap;
 
   #[test]
   fn test_keymap_to_ini() {
-    let mut keymap = LumatoneKeyMap::new();
-
-    keymap
-      .set_key(
-        key_loc_unchecked(1, 0),
-        KeyDefinition {
-          function: LumatoneKeyFunction::NoteOnOff {
-            channel: MidiChannel::default(),
-            note_num: 60,
-          },
-          color: RGBColor(0xff, 0, 0),
-        },
-      )
-      .set_key(
-        key_loc_unchecked(2, 0),
-        KeyDefinition {
-          function: LumatoneKeyFunction::LumaTouch {
-            channel: MidiChannel::unchecked(2),
-            note_num: 70,
-            fader_up_is_null: false,
-          },
-          color: RGBColor::green(),
-        },
-      );
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = note 60 ch 1 #ff0000
+      2:0 = lumatouch 70 ch 2 #00ff00
+      ",
+    )
+    .unwrap();
 
     let ini = keymap.to_ini();
-    let board_1 = ini.section(Some("Board1".to_string())).unwrap();
+    let board_1 = ini.section(Some("Board0".to_string())).unwrap();
     assert_eq!(board_1.get("Key_0"), Some("60"));
     assert_eq!(board_1.get("Chan_0"), Some("1"));
     assert_eq!(board_1.get("Col_0"), Some("ff0000"));
     assert_eq!(board_1.get("KTyp_0"), None); // KTyp is only set if keytype is not NoteOnOff
 
-    let board_2 = ini.section(Some("Board2".to_string())).unwrap();
+    let board_2 = ini.section(Some("Board1".to_string())).unwrap();
     assert_eq!(board_2.get("Key_0"), Some("70"));
     assert_eq!(board_2.get("Chan_0"), Some("2"));
     assert_eq!(board_2.get("Col_0"), Some("00ff00"));
     assert_eq!(board_2.get("KTyp_0"), Some("3"));
 
     // missing keys should have KTyp == 4 (disabled), Key = 0, Chan = 1, Col = 000000
-    let board_3 = ini.section(Some("Board3".to_string())).unwrap();
+    let board_3 = ini.section(Some("Board2".to_string())).unwrap();
     assert_eq!(board_3.get("Key_10"), Some("0"));
     assert_eq!(board_3.get("Chan_10"), Some("1"));
     assert_eq!(board_3.get("Col_10"), Some("000000"));
     assert_eq!(board_3.get("KTyp_10"), Some("4"));
 
-    let general = ini.general_section();
-    assert_eq!(general.get("AfterTouchActive"), Some("0"));
-    assert_eq!(general.get("LightOnKeyStrokes"), Some("0"));
-    assert_eq!(general.get("InvertFootController"), Some("0"));
-    assert_eq!(general.get("InvertSustain"), Some("0"));
-    assert_eq!(general.get("ExprCtrlSensivity"), Some("0"));
+    // options the keymap doesn't set aren't written, so there's no general section at all
+    assert!(ini.section(None::<String>).is_none());
+  }
+
+  /// A keymap with one playable key on board 1, and board 3 lit up in a gradient without
+  /// any of its keys playing anything.
+  fn decorative_keymap() -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::from_dsl("1:0 = note 60 ch 1 #ff0000").unwrap();
+    keymap
+      .set_key_range(BoardIndex::Octave3, 0, 55, |k| KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor(k.get() * 4, 0x40, 0xff - k.get() * 4),
+      })
+      .unwrap();
+    keymap
+  }
+
+  #[test]
+  fn test_disabled_key_colors_survive_ini_round_trip() {
+    let keymap = decorative_keymap();
+    let ini = keymap.to_ini();
+    let board_3 = ini.section(Some("Board2".to_string())).unwrap();
+    assert_eq!(board_3.get("KTyp_10"), Some("4"));
+    assert_eq!(board_3.get("Col_10"), Some("2840d7"));
+
+    let reloaded = LumatoneKeyMap::from_ini_str(keymap.to_ini_string().unwrap()).unwrap();
+    for (location, def) in keymap.keys() {
+      assert_eq!(reloaded.get_key(*location), Some(def), "{location}");
+    }
+    // keys that weren't defined at all are filled in as disabled and unlit
+    assert_eq!(
+      reloaded.get_key(key_loc_unchecked(2, 10)),
+      Some(&KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor(0, 0, 0),
+      })
+    );
+  }
+
+  #[test]
+  fn test_boards_numbered_from_one_load_on_the_right_board() {
+    // as saved by older versions of this crate
+    let legacy = "[Board1]\nKey_0=60\nChan_0=1\nCol_0=ff0000\nFutureKey=1\n\
+                  [Board5]\nKey_0=72\nChan_0=2\nCol_0=00ff00\n";
+    let keymap = LumatoneKeyMap::from_ini_str(legacy).unwrap();
+    let note = |channel, note_num| LumatoneKeyFunction::NoteOnOff {
+      channel: MidiChannel::unchecked(channel),
+      note_num,
+    };
+    assert_eq!(
+      keymap.get_key(key_loc_unchecked(1, 0)).unwrap().function,
+      note(1, 60)
+    );
+    assert_eq!(
+      keymap.get_key(key_loc_unchecked(5, 0)).unwrap().function,
+      note(2, 72)
+    );
+    // the extras move with their board
+    let sections: Vec<_> = keymap.extras().sections().collect();
+    assert_eq!(
+      sections,
+      vec![(
+        Some("Board0"),
+        &[("FutureKey".to_string(), "1".to_string())][..]
+      )]
+    );
+
+    // a Board5 section next to Board0 isn't a board, so it's kept as it is
+    let current = "[Board0]\nKey_0=60\nChan_0=1\nCol_0=ff0000\n[Board5]\nKey_0=72\n";
+    let keymap = LumatoneKeyMap::from_ini_str(current).unwrap();
+    assert!(keymap.get_key(key_loc_unchecked(5, 0)).is_none());
+    let sections: Vec<_> = keymap.extras().sections().collect();
+    assert_eq!(
+      sections,
+      vec![(
+        Some("Board5"),
+        &[("Key_0".to_string(), "72".to_string())][..]
+      )]
+    );
+  }
+
+  #[test]
+  fn test_disabled_key_colors_are_sent() {
+    let keymap = decorative_keymap();
+    let commands = keymap.to_midi_commands();
+    assert_eq!(commands.len(), 2 * 57);
+    assert!(commands.contains(&Command::SetKeyFunction {
+      location: key_loc_unchecked(3, 10),
+      function: LumatoneKeyFunction::Disabled,
+    }));
+    assert!(commands.contains(&Command::SetKeyColor {
+      location: key_loc_unchecked(3, 10),
+      color: RGBColor(40, 0x40, 0xff - 40),
+    }));
   }
 
   #[test]
@@ -454,11 +1192,11 @@ mod tests {
     let mut keymap = LumatoneKeyMap::new();
 
     keymap.set_global_options(GeneralOptions {
-      after_touch_active: true,
-      light_on_key_strokes: true,
-      invert_foot_controller: true,
-      invert_sustain: true,
-      expression_controller_sensitivity: 100,
+      after_touch_active: Some(true),
+      light_on_key_strokes: Some(true),
+      invert_foot_controller: Some(true),
+      invert_sustain: Some(true),
+      expression_controller_sensitivity: Some(100),
       config_tables: ConfigurationTables::default(),
     });
 
@@ -470,4 +1208,667 @@ mod tests {
     assert_eq!(general.get("InvertSustain"), Some("1"));
     assert_eq!(general.get("ExprCtrlSensivity"), Some("100"));
   }
+
+  #[test]
+  fn test_unset_general_opts_are_not_sent() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_global_options(GeneralOptions::builder().aftertouch(false).build());
+
+    let commands = keymap.to_midi_commands();
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], Command::SetAftertouchEnabled(false)));
+
+    keymap.set_global_options(GeneralOptions::default());
+    assert!(keymap.global_options().is_empty());
+    assert!(keymap.to_midi_commands().is_empty());
+  }
+
+  #[test]
+  fn test_general_opts_ini_round_trip_keeps_unset_opts_unset() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_global_options(
+      GeneralOptions::builder()
+        .invert_sustain(false)
+        .expression_sensitivity(0)
+        .build(),
+    );
+
+    let ini = keymap.to_ini_string().unwrap();
+    let loaded = LumatoneKeyMap::from_ini_str(ini).unwrap();
+    let opts = loaded.global_options();
+    // an explicit off or 0 comes back as set...
+    assert_eq!(opts.invert_sustain, Some(false));
+    assert_eq!(opts.expression_controller_sensitivity, Some(0));
+    // ...and the rest stay unset
+    assert_eq!(opts.after_touch_active, None);
+    assert_eq!(opts.light_on_key_strokes, None);
+    assert_eq!(opts.invert_foot_controller, None);
+  }
+
+  #[test]
+  fn test_general_opts_merge_general_and_board_sections() {
+    let ltn = "AfterTouchActive=1\nExprCtrlSensivity=20\n\n[Board4]\nExprCtrlSensivity=30\nInvertSustain=1\n";
+    let keymap = LumatoneKeyMap::from_ini_str(ltn).unwrap();
+    let opts = keymap.global_options();
+    assert_eq!(opts.after_touch_active, Some(true));
+    assert_eq!(opts.invert_sustain, Some(true));
+    // the board section wins, like it would in a file from the official editor
+    assert_eq!(opts.expression_controller_sensitivity, Some(30));
+    assert_eq!(opts.light_on_key_strokes, None);
+  }
+
+  #[test]
+  fn test_general_opts_or() {
+    let fallback = GeneralOptions::builder()
+      .aftertouch(true)
+      .light_on_key_strokes(true)
+      .build();
+    let opts = GeneralOptions::builder()
+      .aftertouch(false)
+      .expression_sensitivity(5)
+      .build()
+      .or(fallback);
+    assert_eq!(opts.after_touch_active, Some(false));
+    assert_eq!(opts.light_on_key_strokes, Some(true));
+    assert_eq!(opts.expression_controller_sensitivity, Some(5));
+    assert_eq!(opts.invert_sustain, None);
+  }
+
+  #[test]
+  fn test_assign_note_run() {
+    let mut keymap = LumatoneKeyMap::new();
+    let keep_channel = key_loc_unchecked(1, 10);
+    keymap.set_key(
+      keep_channel,
+      KeyDefinition {
+        function: LumatoneKeyFunction::ContinuousController {
+          channel: MidiChannel::unchecked(5),
+          cc_num: 7,
+          fader_up_is_null: false,
+        },
+        color: RGBColor::blue(),
+      },
+    );
+    let keys = [
+      key_loc_unchecked(1, 11),
+      keep_channel,
+      key_loc_unchecked(2, 0),
+    ];
+
+    keymap.assign_note_run(&keys, 60, 2, None).unwrap();
+    let notes: Vec<(u8, u8)> = keys
+      .iter()
+      .map(|k| {
+        let f = &keymap.get_key(*k).unwrap().function;
+        (f.note_or_cc_num(), f.midi_channel_num())
+      })
+      .collect();
+    assert_eq!(notes, vec![(60, 1), (62, 5), (64, 1)]);
+    // colors are kept, or left unlit for keys that had none
+    assert_eq!(
+      keymap.get_key(keep_channel).unwrap().color,
+      RGBColor::blue()
+    );
+    assert_eq!(keymap.get_key(keys[0]).unwrap().color, RGBColor(0, 0, 0));
+
+    // colored by pitch class, counting down
+    let palette = [RGBColor::red(), RGBColor::green(), RGBColor::blue()];
+    keymap
+      .assign_note_run(&keys, 4, -1, Some(&palette))
+      .unwrap();
+    let colors: Vec<RGBColor> = keys
+      .iter()
+      .map(|k| keymap.get_key(*k).unwrap().color)
+      .collect();
+    assert_eq!(
+      colors,
+      vec![RGBColor::green(), RGBColor::red(), RGBColor::blue()]
+    );
+
+    // nothing changes if the run would leave the MIDI range
+    assert!(matches!(
+      keymap.assign_note_run(&keys, 126, 1, None),
+      Err(LumatoneKeymapError::NoteOutOfRange(128))
+    ));
+    assert!(matches!(
+      keymap.assign_note_run(&keys, 1, -1, None),
+      Err(LumatoneKeymapError::NoteOutOfRange(-1))
+    ));
+    assert_eq!(
+      keymap.get_key(keys[0]).unwrap().function.note_or_cc_num(),
+      4
+    );
+  }
+
+  #[test]
+  fn test_set_key_range() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key_range(BoardIndex::Octave2, 0, 5, |k| KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::unchecked(2),
+          note_num: 60 + k.get(),
+        },
+        color: RGBColor::blue(),
+      })
+      .expect("valid range should succeed");
+
+    for k in 0..=5 {
+      let def = keymap
+        .get_key(key_loc_unchecked(2, k))
+        .expect("key in range should be set");
+      assert_eq!(
+        def.function,
+        LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::unchecked(2),
+          note_num: 60 + k,
+        }
+      );
+      assert_eq!(def.color, RGBColor::blue());
+    }
+    assert!(keymap.get_key(key_loc_unchecked(2, 6)).is_none());
+    assert!(keymap.get_key(key_loc_unchecked(1, 0)).is_none());
+  }
+
+  #[test]
+  fn test_led_intensity() {
+    let keymap = LumatoneKeyMap::new();
+    assert_eq!(keymap.total_led_intensity(), 0);
+    assert!(keymap.brightest_keys(3).is_empty());
+
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = disabled #101010
+      1:1 = disabled #ffffff
+      2:5 = disabled #ff0000
+      1:2 = disabled #0000ff
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap.total_led_intensity(), 0x30 + 3 * 0xff + 0xff + 0xff);
+
+    let brightest: Vec<_> = keymap
+      .brightest_keys(3)
+      .into_iter()
+      .map(|(loc, _)| *loc)
+      .collect();
+    assert_eq!(
+      brightest,
+      vec![
+        key_loc_unchecked(1, 1),
+        // red and blue tie, so they're in location order
+        key_loc_unchecked(1, 2),
+        key_loc_unchecked(2, 5),
+      ]
+    );
+    assert_eq!(keymap.brightest_keys(10).len(), 4);
+  }
+
+  #[test]
+  fn test_note_range() {
+    assert_eq!(LumatoneKeyMap::new().note_range(), None);
+
+    let channel = MidiChannel::unchecked(1);
+    assert_eq!(piano_like(36, channel).note_range(), Some((36, 36 + 59)));
+    // keys past note 127 are disabled, so they don't count
+    assert_eq!(piano_like(100, channel).note_range(), Some((100, 127)));
+
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0 = cc 1 ch 1
+      1:1 = lumatouch 40 ch 2
+      1:2 = note 72 ch 1
+      1:3 = cc 120 ch 1
+      1:4 = disabled
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap.note_range(), Some((40, 72)));
+
+    let only_cc = LumatoneKeyMap::from_dsl("1:0..=3 = cc 10..=13 ch 1").unwrap();
+    assert_eq!(only_cc.note_range(), None);
+  }
+
+  #[test]
+  fn test_split_by_board() {
+    let mut keymap = piano_like(36, MidiChannel::unchecked(1));
+    keymap.set_global_options(GeneralOptions::builder().invert_sustain(true).build());
+
+    let boards = keymap.split_by_board();
+    assert_eq!(boards.len(), 5);
+    let mut total = 0;
+    for (board, sub) in &boards {
+      assert!(sub.keys().all(|(loc, _)| loc.board_index() == *board));
+      for (loc, def) in sub.keys() {
+        assert_eq!(keymap.get_key(*loc), Some(def));
+      }
+      assert_eq!(sub.global_options().invert_sustain, Some(true));
+      total += sub.keys().count();
+    }
+    assert_eq!(total, keymap.keys().count());
+
+    let mut one_board = LumatoneKeyMap::from_dsl("2:0..=3 = note 60..=63 ch 1").unwrap();
+    let annotation = KeyAnnotation {
+      label: "root".to_string(),
+      tags: vec!["drone".to_string()],
+    };
+    one_board.set_annotation(key_loc_unchecked(2, 0), annotation);
+    let boards = one_board.split_by_board();
+    assert_eq!(boards.len(), 1);
+    assert_eq!(boards[&BoardIndex::Octave2], one_board);
+
+    // a board with only annotations still gets its own keymap
+    let note = KeyAnnotation {
+      label: "spare".to_string(),
+      tags: vec![],
+    };
+    one_board.set_annotation(key_loc_unchecked(4, 7), note.clone());
+    let boards = one_board.split_by_board();
+    assert_eq!(boards.len(), 2);
+    let board4 = &boards[&BoardIndex::Octave4];
+    assert_eq!(board4.keys().count(), 0);
+    assert_eq!(board4.annotation(key_loc_unchecked(4, 7)), Some(&note));
+    assert_eq!(boards[&BoardIndex::Octave2].annotations().count(), 1);
+  }
+
+  #[test]
+  fn test_set_keys() {
+    let def = |note_num| KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::default(),
+        note_num,
+      },
+      color: RGBColor::green(),
+    };
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_key(key_loc_unchecked(1, 0), def(60));
+
+    let counts = keymap.set_keys_counted(vec![
+      (key_loc_unchecked(1, 0), def(61)),
+      (key_loc_unchecked(1, 1), def(62)),
+      (key_loc_unchecked(1, 2), def(63)),
+      (key_loc_unchecked(1, 1), def(64)),
+    ]);
+    assert_eq!(
+      counts,
+      KeyUpdateCounts {
+        added: 2,
+        replaced: 1,
+        duplicates: 1,
+      }
+    );
+    assert_eq!(keymap.get_key(key_loc_unchecked(1, 0)), Some(&def(61)));
+    // last write wins
+    assert_eq!(keymap.get_key(key_loc_unchecked(1, 1)), Some(&def(64)));
+
+    // whole layouts can be merged in from a HashMap, e.g. all of another keymap
+    let layout: HashMap<_, _> = piano_like(36, MidiChannel::default())
+      .keys()
+      .map(|(loc, def)| (*loc, def.clone()))
+      .collect();
+    keymap
+      .set_keys(layout.clone())
+      .set_key(key_loc_unchecked(5, 55), def(0));
+    assert_eq!(keymap.keys().count(), 280);
+    assert_eq!(keymap.get_key(key_loc_unchecked(5, 55)), Some(&def(0)));
+    assert_eq!(
+      keymap.get_key(key_loc_unchecked(1, 1)),
+      layout.get(&key_loc_unchecked(1, 1))
+    );
+  }
+
+  #[test]
+  fn test_annotations() {
+    let mut keymap = LumatoneKeyMap::new();
+    let loc = key_loc_unchecked(3, 7);
+    assert_eq!(keymap.annotation(loc), None);
+
+    let annotation = KeyAnnotation {
+      label: "tonic".to_string(),
+      tags: vec![],
+    };
+    keymap.set_annotation(loc, annotation.clone());
+    assert_eq!(keymap.annotation(loc), Some(&annotation));
+    assert_eq!(keymap.annotations().count(), 1);
+    assert_ne!(keymap, LumatoneKeyMap::new());
+
+    // clearing everything removes the annotation
+    keymap.set_annotation(loc, KeyAnnotation::default());
+    assert_eq!(keymap.annotation(loc), None);
+    assert_eq!(keymap, LumatoneKeyMap::new());
+  }
+
+  #[test]
+  fn test_macro_button_commands() {
+    let mut keymap = LumatoneKeyMap::new();
+    assert!(keymap.macro_button_commands().is_empty());
+
+    keymap.set_macro_button_colors(Some(MacroButtonColors {
+      active: RGBColor::green(),
+      inactive: RGBColor(0x10, 0x10, 0x10),
+    }));
+    let commands = keymap.macro_button_commands();
+    assert_eq!(commands.len(), 2);
+    assert!(matches!(
+      commands[0],
+      Command::SetMacroButtonActiveColor(c) if c == RGBColor::green()
+    ));
+    assert!(matches!(
+      commands[1],
+      Command::SetMacroButtonInactiveColor(c) if c == RGBColor(0x10, 0x10, 0x10)
+    ));
+
+    // they're sent along with the rest of the preset
+    let all = keymap.to_midi_commands();
+    assert!(all
+      .iter()
+      .any(|c| matches!(c, Command::SetMacroButtonActiveColor(_))));
+    assert!(all
+      .iter()
+      .any(|c| matches!(c, Command::SetMacroButtonInactiveColor(_))));
+  }
+
+  #[test]
+  fn test_filtered_commands() {
+    use super::ApplyParts;
+    use crate::keymap::table_defaults::{
+      DEFAULT_FADER_VELOCITY_TABLE, DEFAULT_VELOCITY_INTERVAL_TABLE,
+    };
+    use crate::keymap::tables::ConfigTableDefinition;
+
+    // 3 keys, 5 option flags + 2 macro button colors, 2 tables
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key_range(BoardIndex::Octave1, 0, 2, |_| KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor::red(),
+      })
+      .unwrap();
+    keymap.set_macro_button_colors(Some(MacroButtonColors {
+      active: RGBColor::green(),
+      inactive: RGBColor::blue(),
+    }));
+    let mut general = GeneralOptions::builder()
+      .aftertouch(true)
+      .light_on_key_strokes(false)
+      .invert_foot_controller(false)
+      .invert_sustain(true)
+      .expression_sensitivity(10)
+      .build();
+    general.config_tables.fader_velocity =
+      Some(ConfigTableDefinition::new(DEFAULT_FADER_VELOCITY_TABLE));
+    general.config_tables.velocity_intervals = Some(DEFAULT_VELOCITY_INTERVAL_TABLE);
+    keymap.set_global_options(general);
+
+    for bits in 0..16 {
+      let parts = ApplyParts {
+        functions: bits & 1 != 0,
+        colors: bits & 2 != 0,
+        options: bits & 4 != 0,
+        tables: bits & 8 != 0,
+      };
+      let expected = [
+        (parts.functions, 3),
+        (parts.colors, 3),
+        (parts.options, 7),
+        (parts.tables, 2),
+      ]
+      .iter()
+      .filter(|(included, _)| *included)
+      .map(|(_, n)| n)
+      .sum::<usize>();
+      let commands = keymap.to_midi_commands_filtered(parts);
+      assert_eq!(commands.len(), expected, "{parts:?}");
+    }
+
+    let colors_only = keymap.to_midi_commands_filtered(ApplyParts {
+      colors: true,
+      ..ApplyParts::none()
+    });
+    assert!(colors_only
+      .iter()
+      .all(|c| matches!(c, Command::SetKeyColor { .. })));
+    assert_eq!(
+      keymap.to_midi_commands_filtered(ApplyParts::keys()).len(),
+      6
+    );
+    assert_eq!(keymap.to_midi_commands().len(), 15);
+  }
+
+  #[test]
+  fn test_to_midi_commands_for_boards() {
+    use super::ApplyParts;
+
+    let mut keymap = piano_like(36, MidiChannel::default());
+    keymap.set_global_options(GeneralOptions::builder().aftertouch(true).build());
+    let all = keymap.to_midi_commands();
+    assert_eq!(
+      keymap.to_midi_commands_for_boards(ApplyParts::all(), &BoardIndex::all_octaves()),
+      all
+    );
+
+    let some = keymap.to_midi_commands_for_boards(
+      ApplyParts::all(),
+      &[BoardIndex::Octave2, BoardIndex::Octave4],
+    );
+    let global = all.iter().filter(|c| c.board_index().is_none()).count();
+    assert!(global > 0);
+    // two boards' worth of keys, each with a function and a color
+    assert_eq!(some.len(), global + 2 * 56 * 2);
+    assert!(some.iter().all(|c| matches!(
+      c.board_index(),
+      None | Some(BoardIndex::Octave2) | Some(BoardIndex::Octave4)
+    )));
+
+    let none = keymap.to_midi_commands_for_boards(ApplyParts::keys(), &[]);
+    assert!(none.is_empty());
+  }
+
+  #[test]
+  fn test_macro_button_colors_ini_round_trip() {
+    let colors = MacroButtonColors {
+      active: RGBColor(0xff, 0x80, 0x00),
+      inactive: RGBColor(0x00, 0x00, 0x40),
+    };
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_macro_button_colors(Some(colors));
+
+    let ini = keymap.to_ini_string().unwrap();
+    assert!(ini.contains("MacroButtonActiveCol=ff8000"));
+    assert!(ini.contains("MacroButtonInactiveCol=000040"));
+    let parsed = LumatoneKeyMap::from_ini_str(&ini).unwrap();
+    assert_eq!(parsed.macro_button_colors(), Some(&colors));
+
+    // unset colors aren't written, so presets without them don't gain them
+    let ini = LumatoneKeyMap::new().to_ini_string().unwrap();
+    assert!(!ini.contains("MacroButton"));
+    let parsed = LumatoneKeyMap::from_ini_str(&ini).unwrap();
+    assert_eq!(parsed.macro_button_colors(), None);
+  }
+
+  #[test]
+  fn test_set_key_range_rejects_invalid_ranges() {
+    let def_fn = |_| KeyDefinition {
+      function: LumatoneKeyFunction::Disabled,
+      color: RGBColor(0, 0, 0),
+    };
+    let mut keymap = LumatoneKeyMap::new();
+    assert!(matches!(
+      keymap.set_key_range(BoardIndex::Octave1, 50, 56, def_fn),
+      Err(LumatoneKeymapError::InvalidKeyRange(50, 56))
+    ));
+    assert!(matches!(
+      keymap.set_key_range(BoardIndex::Octave1, 5, 4, def_fn),
+      Err(LumatoneKeymapError::InvalidKeyRange(5, 4))
+    ));
+    assert_eq!(keymap.keys().count(), 0);
+  }
+
+  /// Expected output of [LumatoneKeyMap::to_ini_string] for [snapshot_keymap].
+  ///
+  /// If you change the ini output on purpose, regenerate the fixture with
+  /// `LUMATONE_UPDATE_SNAPSHOTS=1 cargo test -p lumatone-core test_keymap_ini_snapshot`
+  /// and check the diff before committing it.
+  const INI_SNAPSHOT: &str = include_str!("fixtures/snapshot.ltn");
+
+  fn snapshot_keymap() -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap.set_global_options(GeneralOptions {
+      after_touch_active: Some(true),
+      light_on_key_strokes: Some(false),
+      invert_foot_controller: Some(true),
+      invert_sustain: Some(false),
+      expression_controller_sensitivity: Some(64),
+      config_tables: ConfigurationTables::default(),
+    });
+    keymap
+      .set_key_range(BoardIndex::Octave1, 0, 3, |k| KeyDefinition {
+        function: LumatoneKeyFunction::NoteOnOff {
+          channel: MidiChannel::unchecked(1),
+          note_num: 48 + k.get(),
+        },
+        color: RGBColor(0x10, 0x20, 0x30),
+      })
+      .unwrap()
+      .set_key(
+        key_loc_unchecked(1, 55),
+        KeyDefinition {
+          function: LumatoneKeyFunction::ContinuousController {
+            channel: MidiChannel::unchecked(16),
+            cc_num: 74,
+            fader_up_is_null: false,
+          },
+          color: RGBColor::red(),
+        },
+      )
+      .set_key(
+        key_loc_unchecked(2, 10),
+        KeyDefinition {
+          function: LumatoneKeyFunction::LumaTouch {
+            channel: MidiChannel::unchecked(2),
+            note_num: 70,
+            fader_up_is_null: false,
+          },
+          color: RGBColor::green(),
+        },
+      );
+    keymap
+  }
+
+  #[test]
+  fn test_keymap_ini_snapshot() {
+    // rust-ini uses the platform line separator, and git may convert the fixture's line
+    // endings on checkout, so normalize both
+    let actual = snapshot_keymap()
+      .to_ini_string()
+      .unwrap()
+      .replace("\r\n", "\n");
+    let expected = INI_SNAPSHOT.replace("\r\n", "\n");
+
+    if std::env::var_os("LUMATONE_UPDATE_SNAPSHOTS").is_some() {
+      let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/keymap/fixtures/snapshot.ltn"
+      );
+      std::fs::write(path, &actual).expect("unable to write snapshot fixture");
+      return;
+    }
+
+    // compare line by line first, for a more useful failure message than one giant string diff
+    for (i, (actual_line, expected_line)) in actual.lines().zip(expected.lines()).enumerate() {
+      assert_eq!(actual_line, expected_line, "mismatch at line {}", i + 1);
+    }
+    assert_eq!(actual, expected);
+  }
+
+  const FUTURE_KEYS: &str = include_str!("fixtures/future_keys.ltn");
+
+  /// Groups the lines of an ini file by the section they're in, with "" for the general
+  /// section.
+  fn lines_by_section(ini: &str) -> std::collections::HashMap<String, Vec<String>> {
+    let mut sections: std::collections::HashMap<String, Vec<String>> = Default::default();
+    let mut current = String::new();
+    for line in ini.lines().map(str::trim).filter(|l| !l.is_empty()) {
+      if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        current = name.to_string();
+        sections.entry(current.clone()).or_default();
+      } else {
+        sections
+          .entry(current.clone())
+          .or_default()
+          .push(line.to_string());
+      }
+    }
+    sections
+  }
+
+  #[test]
+  fn test_unknown_ini_keys_are_preserved() {
+    assert!(LumatoneKeyMap::new().extras().is_empty());
+
+    let keymap = LumatoneKeyMap::from_ini_str(FUTURE_KEYS).unwrap();
+    assert!(!keymap.extras().is_empty());
+    let saved = keymap.to_ini_string().unwrap().replace("\r\n", "\n");
+    let saved_sections = lines_by_section(&saved);
+
+    let unknown = [
+      ("", "FutureGlobalOption=42"),
+      ("", "NoteOnOffVelocityCrvTbl=future"),
+      ("Board0", "KeyLabel_0=C"),
+      ("Board0", "KeyLabel_1=C#"),
+      ("Board0", "Key_56=60"),
+      ("Board4", "BoardFirmwareHint=2.1"),
+      ("LayoutMetadata", "Author=Someone"),
+      ("LayoutMetadata", "Description=Made up keys"),
+      ("LayoutMetadata", "Tags=test,future"),
+    ];
+    for (section, line) in unknown {
+      assert!(
+        saved_sections
+          .get(section)
+          .is_some_and(|lines| lines.iter().any(|l| l == line)),
+        "expected {line:?} in section {section:?} of:\n{saved}"
+      );
+    }
+    // modeled keys aren't written a second time as extras
+    assert_eq!(
+      saved_sections["Board0"]
+        .iter()
+        .filter(|l| l.starts_with("Col_0="))
+        .count(),
+      1
+    );
+    assert!(!saved_sections["Board4"].contains(&"InvertSustain=1".to_string()));
+
+    // a round trip through the saved file keeps them again, though sections may move
+    let sorted_extras = |keymap: &LumatoneKeyMap| {
+      let mut sections: Vec<_> = keymap.extras().sections().collect();
+      sections.sort();
+      format!("{sections:?}")
+    };
+    let reloaded = LumatoneKeyMap::from_ini_str(&saved).unwrap();
+    assert_eq!(sorted_extras(&reloaded), sorted_extras(&keymap));
+  }
+
+  #[test]
+  fn test_modeled_ini_keys_take_precedence_over_extras() {
+    use crate::keymap::table_defaults::DEFAULT_FADER_VELOCITY_TABLE;
+    use crate::keymap::tables::ConfigTableDefinition;
+
+    let mut keymap = LumatoneKeyMap::from_ini_str(FUTURE_KEYS).unwrap();
+    let table = ConfigTableDefinition::new(DEFAULT_FADER_VELOCITY_TABLE);
+    let expected = format!("NoteOnOffVelocityCrvTbl={}", table.to_string());
+    let mut general = GeneralOptions::default();
+    general.config_tables.on_off_velocity = Some(table);
+    keymap.set_global_options(general);
+
+    let saved = keymap.to_ini_string().unwrap().replace("\r\n", "\n");
+    let general_lines = &lines_by_section(&saved)[""];
+    let table_lines: Vec<_> = general_lines
+      .iter()
+      .filter(|l| l.starts_with("NoteOnOffVelocityCrvTbl="))
+      .collect();
+    assert_eq!(table_lines, vec![&expected]);
+    assert!(general_lines.contains(&"FutureGlobalOption=42".to_string()));
+  }
 }
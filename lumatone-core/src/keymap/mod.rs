@@ -1,4 +1,17 @@
+pub mod annotations;
+pub mod csv;
+pub mod dsl;
 pub mod error;
+pub mod expr;
+pub mod fingerprint;
+pub mod json;
+pub mod labels;
+pub mod layouts;
 pub mod ltn;
+pub mod offline;
+pub mod pacing;
+pub mod quantize;
+pub mod search;
 mod table_defaults;
 pub mod tables;
+pub mod validation;
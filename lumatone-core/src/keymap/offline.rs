@@ -0,0 +1,149 @@
+//! Keeps track of key edits made while the device can't be reached (say, after the computer
+//! wakes from sleep and the MIDI connection is gone), so they can be sent once it's back.
+//!
+//! Only the net change to each key is kept. Editing a key several times replays just its
+//! final definition, and a key that's edited back to what the device still has isn't
+//! replayed at all.
+//!
+//! [Client::reconnect](crate::midi::client::Client::reconnect) replays them once the device
+//! has been found again.
+
+use std::collections::HashMap;
+
+use crate::midi::commands::{set_key_color, set_key_function, Command};
+use crate::midi::constants::LumatoneKeyLocation;
+
+use super::ltn::KeyDefinition;
+
+#[derive(Debug, Default)]
+pub struct OfflineEdits {
+  /// For each edited key: what the device has for it, if that's known, and the latest edit.
+  keys: HashMap<LumatoneKeyLocation, (Option<KeyDefinition>, KeyDefinition)>,
+}
+
+impl OfflineEdits {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Records that the key at `location` was changed from `before` to `edited`.
+  ///
+  /// Only the first edit of each key keeps its `before`, since that's what the device
+  /// still has. `None` means it isn't known, so the key is replayed in full.
+  pub fn record(
+    &mut self,
+    location: LumatoneKeyLocation,
+    before: Option<&KeyDefinition>,
+    edited: KeyDefinition,
+  ) {
+    self
+      .keys
+      .entry(location)
+      .and_modify(|(_, latest)| *latest = edited.clone())
+      .or_insert_with(|| (before.cloned(), edited));
+  }
+
+  /// The number of keys that differ from what the device has.
+  pub fn len(&self) -> usize {
+    self.changed().count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns the commands that bring the device up to date, in board / key order. Only the
+  /// parts of each key that changed are sent.
+  pub fn commands(&self) -> Vec<Command> {
+    let mut changed: Vec<_> = self.changed().collect();
+    changed.sort_by_key(|(location, _, _)| {
+      let LumatoneKeyLocation(board, key) = location;
+      (*board as u8, Into::<u8>::into(*key))
+    });
+
+    let mut commands = vec![];
+    for (location, before, edited) in changed {
+      if before.map(|b| b.function) != Some(edited.function) {
+        commands.push(set_key_function(*location, edited.function));
+      }
+      if before.map(|b| b.color) != Some(edited.color) {
+        commands.push(set_key_color(*location, edited.color));
+      }
+    }
+    commands
+  }
+
+  /// Forgets every edit, once they've been replayed.
+  pub fn clear(&mut self) {
+    self.keys.clear();
+  }
+
+  fn changed(
+    &self,
+  ) -> impl Iterator<Item = (&LumatoneKeyLocation, Option<&KeyDefinition>, &KeyDefinition)> {
+    self
+      .keys
+      .iter()
+      .map(|(location, (before, edited))| (location, before.as_ref(), edited))
+      .filter(|(_, before, edited)| *before != Some(*edited))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::OfflineEdits;
+  use crate::keymap::ltn::KeyDefinition;
+  use crate::midi::commands::{set_key_color, set_key_function};
+  use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor};
+
+  fn note(note_num: u8, color: RGBColor) -> KeyDefinition {
+    KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(1),
+        note_num,
+      },
+      color,
+    }
+  }
+
+  #[test]
+  fn test_only_net_changes_are_replayed() {
+    let (a, b, c) = (
+      key_loc_unchecked(2, 3),
+      key_loc_unchecked(1, 40),
+      key_loc_unchecked(1, 2),
+    );
+    let original = note(60, RGBColor::red());
+    let mut edits = OfflineEdits::new();
+    assert!(edits.is_empty());
+
+    // a's color changes twice, and only the final color is sent
+    edits.record(a, Some(&original), note(60, RGBColor::green()));
+    edits.record(
+      a,
+      Some(&note(60, RGBColor::green())),
+      note(60, RGBColor::blue()),
+    );
+
+    // b is changed, then changed back
+    edits.record(b, Some(&original), note(61, RGBColor::red()));
+    edits.record(b, Some(&note(61, RGBColor::red())), original.clone());
+
+    // c wasn't known before, so all of it is sent
+    edits.record(c, None, note(62, RGBColor::red()));
+
+    assert_eq!(edits.len(), 2);
+    assert_eq!(
+      edits.commands(),
+      vec![
+        set_key_function(c, note(62, RGBColor::red()).function),
+        set_key_color(c, RGBColor::red()),
+        set_key_color(a, RGBColor::blue()),
+      ]
+    );
+
+    edits.clear();
+    assert!(edits.is_empty());
+    assert!(edits.commands().is_empty());
+  }
+}
@@ -0,0 +1,66 @@
+//! Helpers for pacing large batches of commands sent to the device.
+//!
+//! Sending a full keymap produces several hundred commands. The driver sends
+//! them one at a time, but callers that want to throttle (e.g. pausing between
+//! batches so the device isn't flooded) can use [chunk_commands] to split them up.
+
+use crate::midi::commands::Command;
+
+/// Splits `commands` into batches of at most `max_per_batch` commands each, preserving order.
+///
+/// Every batch except the last will contain exactly `max_per_batch` commands.
+/// A `max_per_batch` of zero is treated as one.
+pub fn chunk_commands(commands: Vec<Command>, max_per_batch: usize) -> Vec<Vec<Command>> {
+  let max_per_batch = max_per_batch.max(1);
+  let mut batches = Vec::with_capacity(commands.len().div_ceil(max_per_batch));
+  let mut current = Vec::with_capacity(max_per_batch);
+  for cmd in commands {
+    current.push(cmd);
+    if current.len() == max_per_batch {
+      batches.push(current);
+      current = Vec::with_capacity(max_per_batch);
+    }
+  }
+  if !current.is_empty() {
+    batches.push(current);
+  }
+  batches
+}
+
+#[cfg(test)]
+mod tests {
+  use super::chunk_commands;
+  use crate::midi::{
+    commands::set_key_color,
+    constants::{LumatoneKeyLocation, RGBColor},
+  };
+
+  #[test]
+  fn test_chunk_full_board_into_batches_of_50() {
+    let commands: Vec<_> = LumatoneKeyLocation::all()
+      .into_iter()
+      .map(|loc| set_key_color(loc, RGBColor::red()))
+      .collect();
+    assert_eq!(commands.len(), 280);
+
+    let batches = chunk_commands(commands.clone(), 50);
+    assert_eq!(batches.len(), 6);
+    for b in &batches[0..5] {
+      assert_eq!(b.len(), 50);
+    }
+    assert_eq!(batches[5].len(), 30);
+
+    // order is preserved
+    let flattened: Vec<_> = batches.into_iter().flatten().collect();
+    assert_eq!(flattened, commands);
+  }
+
+  #[test]
+  fn test_chunk_empty_and_zero_batch_size() {
+    assert!(chunk_commands(vec![], 10).is_empty());
+
+    let commands = vec![set_key_color(LumatoneKeyLocation::all()[0], RGBColor::red()); 3];
+    let batches = chunk_commands(commands, 0);
+    assert_eq!(batches.len(), 3);
+  }
+}
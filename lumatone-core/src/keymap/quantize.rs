@@ -0,0 +1,421 @@
+//! Reduces the number of distinct key colors in a [LumatoneKeyMap].
+//!
+//! Layouts with hundreds of slightly different colors are slow to send and tend to look
+//! noisy on the device. [LumatoneKeyMap::quantize_colors] clusters the key colors with
+//! k-means and replaces each one with its cluster's average, weighted by how many keys use
+//! each color.
+//!
+//! The clustering is seeded, so the same keymap and seed always give the same palette.
+
+use std::collections::HashMap;
+
+use palette::LinSrgb;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::color::utils::{from_rgb_color, to_rgb_color};
+use crate::midi::constants::RGBColor;
+
+use super::ltn::{KeyDefinition, LumatoneKeyMap};
+
+/// Layouts with more distinct colors than this get a warning from
+/// [LumatoneKeyMap::validate]. There's no hard limit on the device; this is a rough point
+/// past which quantizing usually helps more than it hurts.
+pub const PRACTICAL_COLOR_LIMIT: usize = 64;
+
+/// Seed used by [LumatoneKeyMap::quantize_colors].
+pub const DEFAULT_QUANTIZE_SEED: u64 = 1;
+
+/// Gives up on k-means if the clusters haven't settled after this many rounds.
+const MAX_ITERATIONS: usize = 100;
+
+/// What [LumatoneKeyMap::quantize_colors] did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizeReport {
+  /// The colors the keys use now, in the order the clusters were found.
+  pub palette: Vec<RGBColor>,
+  /// Each color the keys used before, and the color it was replaced with. Sorted by the
+  /// original color.
+  pub mapping: Vec<(RGBColor, RGBColor)>,
+  /// How many keys had their color changed.
+  pub keys_changed: usize,
+}
+
+impl LumatoneKeyMap {
+  /// Returns the distinct key colors, sorted by red, green, then blue, with the number of
+  /// keys using each one.
+  pub fn color_counts(&self) -> Vec<(RGBColor, usize)> {
+    let mut counts: HashMap<(u8, u8, u8), usize> = HashMap::new();
+    for (_, def) in self.keys() {
+      let RGBColor(r, g, b) = def.color;
+      *counts.entry((r, g, b)).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort();
+    counts
+      .into_iter()
+      .map(|((r, g, b), n)| (RGBColor(r, g, b), n))
+      .collect()
+  }
+
+  /// Recolors the keys so that at most `max_colors` distinct colors are used, with
+  /// [DEFAULT_QUANTIZE_SEED]. See [LumatoneKeyMap::quantize_colors_with_seed].
+  pub fn quantize_colors(&mut self, max_colors: usize) -> QuantizeReport {
+    self.quantize_colors_with_seed(max_colors, DEFAULT_QUANTIZE_SEED)
+  }
+
+  /// Recolors the keys so that at most `max_colors` distinct colors are used, choosing
+  /// the initial clusters with a random number generator seeded with `seed`.
+  ///
+  /// Keymaps that already use few enough colors are left alone. A `max_colors` of zero is
+  /// treated as one.
+  pub fn quantize_colors_with_seed(&mut self, max_colors: usize, seed: u64) -> QuantizeReport {
+    let counts = self.color_counts();
+    let k = max_colors.max(1);
+    if counts.len() <= k {
+      return QuantizeReport {
+        palette: counts.iter().map(|(c, _)| *c).collect(),
+        mapping: counts.iter().map(|(c, _)| (*c, *c)).collect(),
+        keys_changed: 0,
+      };
+    }
+
+    let points: Vec<WeightedPoint> = counts
+      .iter()
+      .map(|(color, n)| WeightedPoint {
+        pos: to_point(*color),
+        weight: *n as f32,
+      })
+      .collect();
+    let (centers, assignments) = k_means(&points, k, seed);
+
+    let palette: Vec<RGBColor> = centers.iter().map(|c| from_point(*c)).collect();
+    let mapping: Vec<(RGBColor, RGBColor)> = counts
+      .iter()
+      .zip(assignments)
+      .map(|((color, _), cluster)| (*color, palette[cluster]))
+      .collect();
+
+    let replacements: HashMap<(u8, u8, u8), RGBColor> = mapping
+      .iter()
+      .filter(|(original, replacement)| original != replacement)
+      .map(|(RGBColor(r, g, b), replacement)| ((*r, *g, *b), *replacement))
+      .collect();
+    let changed: Vec<_> = self
+      .keys()
+      .filter_map(|(location, def)| {
+        let RGBColor(r, g, b) = def.color;
+        let color = *replacements.get(&(r, g, b))?;
+        let function = def.function;
+        Some((*location, KeyDefinition { function, color }))
+      })
+      .collect();
+    let keys_changed = changed.len();
+    for (location, def) in changed {
+      self.set_key(location, def);
+    }
+
+    QuantizeReport {
+      palette,
+      mapping,
+      keys_changed,
+    }
+  }
+}
+
+type Point = [f32; 3];
+
+struct WeightedPoint {
+  pos: Point,
+  weight: f32,
+}
+
+fn to_point(color: RGBColor) -> Point {
+  let c = from_rgb_color(color);
+  [c.red, c.green, c.blue]
+}
+
+fn from_point(p: Point) -> RGBColor {
+  to_rgb_color(LinSrgb::new(p[0], p[1], p[2]))
+}
+
+fn distance_sq(a: &Point, b: &Point) -> f32 {
+  a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Returns the index of the center closest to `p`, preferring lower indices on ties.
+fn nearest(p: &Point, centers: &[Point]) -> usize {
+  let mut best = 0;
+  for (i, c) in centers.iter().enumerate().skip(1) {
+    if distance_sq(p, c) < distance_sq(p, &centers[best]) {
+      best = i;
+    }
+  }
+  best
+}
+
+/// Clusters `points` into `k` groups, returning the cluster centers and the cluster index
+/// of each point. Expects more than `k` distinct points.
+fn k_means(points: &[WeightedPoint], k: usize, seed: u64) -> (Vec<Point>, Vec<usize>) {
+  let mut rng = StdRng::seed_from_u64(seed);
+  let centers = initial_centers(points, k, &mut rng);
+  refine(points, centers)
+}
+
+/// Moves each of the starting `centers` to the average of its points until they settle,
+/// as in [k_means]. Clusters left with no points are dropped.
+fn refine(points: &[WeightedPoint], mut centers: Vec<Point>) -> (Vec<Point>, Vec<usize>) {
+  let mut assignments: Vec<usize> = points.iter().map(|p| nearest(&p.pos, &centers)).collect();
+
+  for _ in 0..MAX_ITERATIONS {
+    let mut sums = vec![([0.0f32; 3], 0.0f32); centers.len()];
+    for (p, cluster) in points.iter().zip(&assignments) {
+      let (sum, weight) = &mut sums[*cluster];
+      for (s, v) in sum.iter_mut().zip(p.pos) {
+        *s += v * p.weight;
+      }
+      *weight += p.weight;
+    }
+    let mut empty = vec![];
+    for (i, (sum, weight)) in sums.into_iter().enumerate() {
+      if weight > 0.0 {
+        centers[i] = sum.map(|s| s / weight);
+      } else {
+        empty.push(i);
+      }
+    }
+    // a cluster that ends up empty starts over at the point that fits worst, so it can
+    // split up the cluster that's most spread out
+    for i in empty {
+      if let Some(p) = worst_fit(points, &centers) {
+        centers[i] = p;
+      }
+    }
+
+    let next: Vec<usize> = points.iter().map(|p| nearest(&p.pos, &centers)).collect();
+    if next == assignments {
+      break;
+    }
+    assignments = next;
+  }
+  drop_empty_clusters(centers, assignments)
+}
+
+/// Returns the point that adds the most to the weighted squared distance from each point
+/// to its closest center, or `None` if every point is on a center already.
+fn worst_fit(points: &[WeightedPoint], centers: &[Point]) -> Option<Point> {
+  points
+    .iter()
+    .map(|p| {
+      let closest = &centers[nearest(&p.pos, centers)];
+      (p.pos, p.weight * distance_sq(&p.pos, closest))
+    })
+    .filter(|(_, cost)| *cost > 0.0)
+    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    .map(|(pos, _)| pos)
+}
+
+/// Removes the centers that no point is assigned to, renumbering the assignments to match,
+/// so that every color in the palette is used.
+fn drop_empty_clusters(centers: Vec<Point>, assignments: Vec<usize>) -> (Vec<Point>, Vec<usize>) {
+  let mut used = vec![false; centers.len()];
+  for cluster in &assignments {
+    used[*cluster] = true;
+  }
+  let mut kept = vec![];
+  let mut new_index = vec![0; centers.len()];
+  for (i, center) in centers.into_iter().enumerate() {
+    if used[i] {
+      new_index[i] = kept.len();
+      kept.push(center);
+    }
+  }
+  let assignments = assignments.iter().map(|c| new_index[*c]).collect();
+  (kept, assignments)
+}
+
+/// Picks starting centers with k-means++: the first at random, and each one after that
+/// with a probability proportional to its squared distance from the closest center so far.
+/// Points are weighted by how many keys use them.
+fn initial_centers(points: &[WeightedPoint], k: usize, rng: &mut StdRng) -> Vec<Point> {
+  let pick = |rng: &mut StdRng, weights: &[f32]| -> Option<usize> {
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+      return None;
+    }
+    let mut target = rng.gen::<f32>() * total;
+    for (i, w) in weights.iter().enumerate() {
+      if target < *w {
+        return Some(i);
+      }
+      target -= w;
+    }
+    // rounding can leave a sliver past the end
+    weights.iter().rposition(|w| *w > 0.0)
+  };
+
+  let weights: Vec<f32> = points.iter().map(|p| p.weight).collect();
+  let first = pick(rng, &weights).unwrap_or(0);
+  let mut centers = vec![points[first].pos];
+  while centers.len() < k {
+    let weights: Vec<f32> = points
+      .iter()
+      .map(|p| {
+        let closest = centers
+          .iter()
+          .map(|c| distance_sq(&p.pos, c))
+          .fold(f32::INFINITY, f32::min);
+        p.weight * closest
+      })
+      .collect();
+    match pick(rng, &weights) {
+      Some(i) => centers.push(points[i].pos),
+      None => break,
+    }
+  }
+  centers
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{drop_empty_clusters, refine, WeightedPoint};
+  use crate::keymap::ltn::{KeyDefinition, LumatoneKeyMap};
+  use crate::midi::constants::{
+    key_loc_unchecked, LumatoneKeyFunction, LumatoneKeyLocation, RGBColor,
+  };
+
+  fn keymap_with_colors(colors: &[RGBColor]) -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::new();
+    for (i, color) in colors.iter().enumerate() {
+      keymap.set_key(
+        key_loc_unchecked(1 + (i / 56) as u8, (i % 56) as u8),
+        KeyDefinition {
+          function: LumatoneKeyFunction::Disabled,
+          color: *color,
+        },
+      );
+    }
+    keymap
+  }
+
+  fn color_at(keymap: &LumatoneKeyMap, location: LumatoneKeyLocation) -> RGBColor {
+    keymap.get_key(location).unwrap().color
+  }
+
+  /// Reds and blues, with a little noise in each.
+  fn two_clusters() -> Vec<RGBColor> {
+    let mut colors = vec![];
+    for i in 0..10u8 {
+      colors.push(RGBColor(230 + i * 2, i * 2, 20 - i * 2));
+      colors.push(RGBColor(i * 2, 20 - i * 2, 230 + i * 2));
+    }
+    colors
+  }
+
+  #[test]
+  fn test_two_clusters() {
+    let colors = two_clusters();
+    let mut keymap = keymap_with_colors(&colors);
+    assert_eq!(keymap.color_counts().len(), 20);
+
+    let report = keymap.quantize_colors(2);
+    assert_eq!(report.palette.len(), 2);
+    assert_eq!(report.mapping.len(), 20);
+    assert_eq!(report.keys_changed, 20);
+    assert_eq!(keymap.color_counts().len(), 2);
+
+    // every red key gets the same color, and every blue key the other
+    let red = color_at(&keymap, key_loc_unchecked(1, 0));
+    let blue = color_at(&keymap, key_loc_unchecked(1, 1));
+    assert_ne!(red, blue);
+    for i in 0..colors.len() {
+      let expected = if i % 2 == 0 { red } else { blue };
+      assert_eq!(color_at(&keymap, key_loc_unchecked(1, i as u8)), expected);
+    }
+    // each cluster's average
+    assert_eq!(red, RGBColor(239, 9, 11));
+    assert_eq!(blue, RGBColor(9, 11, 239));
+  }
+
+  #[test]
+  fn test_quantize_is_deterministic() {
+    // three loose groups squeezed into two clusters, so the result depends on the start
+    let mut colors = two_clusters();
+    colors.extend((0..10u8).map(|i| RGBColor(i * 3, 200 + i, i)));
+
+    let quantize = |seed| {
+      let mut keymap = keymap_with_colors(&colors);
+      keymap.quantize_colors_with_seed(2, seed)
+    };
+    assert_eq!(quantize(7), quantize(7));
+    assert_eq!(quantize(1234), quantize(1234));
+  }
+
+  #[test]
+  fn test_few_colors_are_left_alone() {
+    let colors = [RGBColor::red(), RGBColor::green(), RGBColor::red()];
+    let mut keymap = keymap_with_colors(&colors);
+    let report = keymap.quantize_colors(2);
+    assert_eq!(report.keys_changed, 0);
+    assert_eq!(report.palette, vec![RGBColor::green(), RGBColor::red()]);
+    assert_eq!(
+      report.mapping,
+      vec![
+        (RGBColor::green(), RGBColor::green()),
+        (RGBColor::red(), RGBColor::red())
+      ]
+    );
+    assert_eq!(keymap, keymap_with_colors(&colors));
+  }
+
+  #[test]
+  fn test_every_palette_color_is_used() {
+    // a spread of colors, quantized with lots of seeds and cluster counts
+    let colors: Vec<_> = (0..150u32)
+      .map(|i| {
+        RGBColor(
+          (i * 37 % 256) as u8,
+          (i * 91 % 256) as u8,
+          (i * 13 % 256) as u8,
+        )
+      })
+      .collect();
+    for seed in 0..20 {
+      for max_colors in [3, 8, 20] {
+        let mut keymap = keymap_with_colors(&colors);
+        let report = keymap.quantize_colors_with_seed(max_colors, seed);
+        assert!(report.palette.len() <= max_colors);
+        for color in &report.palette {
+          assert!(
+            report.mapping.iter().any(|(_, c)| c == color),
+            "{color:?} isn't used (seed {seed}, {max_colors} colors)"
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_empty_clusters_are_reseeded() {
+    let point = |red: f32| WeightedPoint {
+      pos: [red, 0.0, 0.0],
+      weight: 1.0,
+    };
+    let points = [point(0.0), point(0.1), point(1.0), point(1.1)];
+    // the middle center is closest to none of the points
+    let (centers, assignments) =
+      refine(&points, vec![[0.0; 3], [0.54, 0.0, 0.0], [1.05, 0.0, 0.0]]);
+    assert_eq!(centers.len(), 3);
+    let mut used = assignments.clone();
+    used.sort();
+    used.dedup();
+    assert_eq!(used, vec![0, 1, 2]);
+  }
+
+  #[test]
+  fn test_drop_empty_clusters() {
+    let centers = vec![[0.0; 3], [0.5; 3], [1.0; 3]];
+    let (kept, assignments) = drop_empty_clusters(centers, vec![2, 0, 2]);
+    assert_eq!(kept, vec![[0.0; 3], [1.0; 3]]);
+    assert_eq!(assignments, vec![1, 0, 1]);
+  }
+}
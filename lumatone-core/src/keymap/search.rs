@@ -0,0 +1,332 @@
+//! Finding keys by the note they play, for jumping to a note in a dense layout.
+//!
+//! A [KeyQuery] is parsed from what the user types, which can be:
+//!
+//! - a MIDI note number, e.g. `61`, matching that note on any channel
+//! - a channel and note number, e.g. `3:61`
+//! - the name of one of the current tuning's pitch classes, e.g. `F#`, optionally followed by
+//!   an octave number, e.g. `F#4` or `F# 4`
+//!
+//! A bare number is always a note number, even in tunings whose pitch classes are named
+//! by step number. To find a step by name there, give an octave as well, e.g. `5 4`.
+//!
+//! A name can belong to more than one pitch class, e.g. in a tuning where enharmonic
+//! spellings share a name, and then every one of them matches. Names are compared
+//! exactly first, and only ignoring case if nothing matched exactly.
+
+use super::{error::LumatoneKeymapError, ltn::LumatoneKeyMap};
+use crate::midi::constants::{LumatoneKeyFunction, LumatoneKeyLocation, MidiChannel};
+
+/// Which keys to find. See the [module docs](self) for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyQuery {
+  /// A note number, on any channel.
+  Note(u8),
+
+  /// A note number on one channel.
+  ChannelNote(MidiChannel, u8),
+
+  /// Notes in any of the pitch classes `classes` of a tuning with `divisions` steps to the
+  /// octave, in `octave` if given. The pitch class of a note is its number modulo
+  /// `divisions`, and its octave counts up from -1 at note 0, so note 60 is C 4 in 12 EDO.
+  PitchClass {
+    classes: Vec<usize>,
+    divisions: usize,
+    octave: Option<i32>,
+  },
+}
+
+impl KeyQuery {
+  /// Parses `query`, looking up names in `pitch_class_names`, which has one name per step
+  /// of the current tuning, in order.
+  pub fn parse<S: AsRef<str>>(
+    query: &str,
+    pitch_class_names: &[S],
+  ) -> Result<KeyQuery, LumatoneKeymapError> {
+    let query = query.trim();
+    if query.is_empty() {
+      return Err(invalid("enter a note name or number"));
+    }
+    if let Some((channel, note)) = query.split_once(':') {
+      let channel = channel
+        .trim()
+        .parse::<u8>()
+        .ok()
+        .and_then(MidiChannel::new)
+        .ok_or_else(|| {
+          invalid(format!(
+            "\"{}\" isn't a MIDI channel from 1 to 16",
+            channel.trim()
+          ))
+        })?;
+      return Ok(KeyQuery::ChannelNote(channel, parse_note_num(note.trim())?));
+    }
+    if query.chars().all(|c| c.is_ascii_digit()) {
+      return Ok(KeyQuery::Note(parse_note_num(query)?));
+    }
+
+    let divisions = pitch_class_names.len();
+    let classes = pitch_classes_named(pitch_class_names, query);
+    if !classes.is_empty() {
+      return Ok(KeyQuery::PitchClass {
+        classes,
+        divisions,
+        octave: None,
+      });
+    }
+    if let Some((name, octave)) = split_octave(query) {
+      let classes = pitch_classes_named(pitch_class_names, name);
+      if !classes.is_empty() {
+        return Ok(KeyQuery::PitchClass {
+          classes,
+          divisions,
+          octave: Some(octave),
+        });
+      }
+    }
+    Err(invalid(format!("no note named \"{query}\" in this tuning")))
+  }
+
+  /// Returns true if a key with this `function` plays a note the query is looking for.
+  /// Only note and LumaTouch keys can match.
+  pub fn matches(&self, function: &LumatoneKeyFunction) -> bool {
+    let (channel, note_num) = match function {
+      LumatoneKeyFunction::NoteOnOff { channel, note_num }
+      | LumatoneKeyFunction::LumaTouch {
+        channel, note_num, ..
+      } => (*channel, *note_num),
+      _ => return false,
+    };
+    match self {
+      KeyQuery::Note(note) => note_num == *note,
+      KeyQuery::ChannelNote(ch, note) => channel == *ch && note_num == *note,
+      KeyQuery::PitchClass {
+        classes,
+        divisions,
+        octave,
+      } => {
+        if *divisions == 0 {
+          return false;
+        }
+        let note = note_num as usize;
+        let in_octave = octave.is_none() || *octave == Some((note / divisions) as i32 - 1);
+        in_octave && classes.contains(&(note % divisions))
+      }
+    }
+  }
+}
+
+/// Returns the locations of every key in `keymap` that matches `query`, in board and key
+/// order, e.g. for cycling through them.
+pub fn find_keys(keymap: &LumatoneKeyMap, query: &KeyQuery) -> Vec<LumatoneKeyLocation> {
+  let mut found: Vec<LumatoneKeyLocation> = keymap
+    .keys()
+    .filter(|(_, def)| query.matches(&def.function))
+    .map(|(location, _)| *location)
+    .collect();
+  found.sort_by_key(|l| (l.board_index() as u8, l.key_index().get()));
+  found
+}
+
+fn invalid(message: impl Into<String>) -> LumatoneKeymapError {
+  LumatoneKeymapError::InvalidKeyQuery(message.into())
+}
+
+fn parse_note_num(text: &str) -> Result<u8, LumatoneKeymapError> {
+  text
+    .parse::<u8>()
+    .ok()
+    .filter(|n| *n <= 127)
+    .ok_or_else(|| invalid(format!("\"{text}\" isn't a note number from 0 to 127")))
+}
+
+/// The indices of the pitch classes named `name`, compared exactly, or ignoring case if
+/// none match exactly.
+fn pitch_classes_named<S: AsRef<str>>(names: &[S], name: &str) -> Vec<usize> {
+  let indices_where = |matches: &dyn Fn(&str) -> bool| -> Vec<usize> {
+    names
+      .iter()
+      .enumerate()
+      .filter(|(_, n)| matches(n.as_ref().trim()))
+      .map(|(i, _)| i)
+      .collect()
+  };
+  let exact = indices_where(&|n| n == name);
+  if !exact.is_empty() {
+    return exact;
+  }
+  let lower = name.to_lowercase();
+  indices_where(&|n| n.to_lowercase() == lower)
+}
+
+/// Splits a trailing octave number, which may be negative, off of a name, e.g. "C#-1"
+/// into ("C#", -1). Returns `None` if there's no name before the number.
+fn split_octave(query: &str) -> Option<(&str, i32)> {
+  let digits_start = query.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+  if digits_start == query.len() {
+    return None;
+  }
+  let (name, octave) = match query[..digits_start].strip_suffix('-') {
+    Some(name) => (name, -query[digits_start..].parse::<i32>().ok()?),
+    None => (
+      &query[..digits_start],
+      query[digits_start..].parse::<i32>().ok()?,
+    ),
+  };
+  let name = name.trim_end();
+  (!name.is_empty()).then_some((name, octave))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{find_keys, KeyQuery};
+  use crate::keymap::error::LumatoneKeymapError;
+  use crate::keymap::ltn::{KeyDefinition, LumatoneKeyMap};
+  use crate::midi::constants::{
+    key_loc_unchecked, BoardIndex, LumatoneKeyFunction, MidiChannel, RGBColor,
+  };
+
+  const EDO_12: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+  ];
+
+  fn note(channel: u8, note_num: u8) -> KeyDefinition {
+    KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(channel),
+        note_num,
+      },
+      color: RGBColor::red(),
+    }
+  }
+
+  /// Board 1 plays notes 60 to 115 on channel 1, board 2 the same notes on channel 2, and
+  /// key 0 of board 3 is a controller with number 60.
+  fn keymap() -> LumatoneKeyMap {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key_range(BoardIndex::Octave1, 0, 55, |k| note(1, 60 + k.get()))
+      .unwrap()
+      .set_key_range(BoardIndex::Octave2, 0, 55, |k| note(2, 60 + k.get()))
+      .unwrap();
+    keymap.set_key(
+      key_loc_unchecked(3, 0),
+      KeyDefinition {
+        function: LumatoneKeyFunction::ContinuousController {
+          channel: MidiChannel::default(),
+          cc_num: 60,
+          fader_up_is_null: false,
+        },
+        color: RGBColor::red(),
+      },
+    );
+    keymap
+  }
+
+  fn find(query: &str, names: &[&str]) -> Vec<(u8, u8)> {
+    let query = KeyQuery::parse(query, names).unwrap();
+    find_keys(&keymap(), &query)
+      .into_iter()
+      .map(|l| (l.board_index() as u8, l.key_index().get()))
+      .collect()
+  }
+
+  #[test]
+  fn test_parse() {
+    let ch = MidiChannel::unchecked;
+    assert_eq!(
+      KeyQuery::parse(" 61 ", &EDO_12).unwrap(),
+      KeyQuery::Note(61)
+    );
+    assert_eq!(
+      KeyQuery::parse("3:61", &EDO_12).unwrap(),
+      KeyQuery::ChannelNote(ch(3), 61)
+    );
+    assert_eq!(
+      KeyQuery::parse("f#4", &EDO_12).unwrap(),
+      KeyQuery::PitchClass {
+        classes: vec![6],
+        divisions: 12,
+        octave: Some(4)
+      }
+    );
+    assert_eq!(
+      KeyQuery::parse("C -1", &EDO_12).unwrap(),
+      KeyQuery::PitchClass {
+        classes: vec![0],
+        divisions: 12,
+        octave: Some(-1)
+      }
+    );
+
+    for bad in ["", "128", "0:60", "17:60", "1:", "H", "C#"] {
+      let result = KeyQuery::parse(bad, &["C"]);
+      assert!(
+        matches!(result, Err(LumatoneKeymapError::InvalidKeyQuery(_))),
+        "{bad:?} parsed as {result:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_find_keys_in_12_edo() {
+    assert_eq!(find("61", &EDO_12), vec![(1, 1), (2, 1)]);
+    assert_eq!(find("2:61", &EDO_12), vec![(2, 1)]);
+    // notes 60, 72, 84, 96 and 108 on both boards; the controller doesn't count
+    assert_eq!(find("C", &EDO_12).len(), 10);
+    assert_eq!(find("C5", &EDO_12), vec![(1, 12), (2, 12)]);
+    assert_eq!(find("F# 4", &EDO_12), vec![(1, 6), (2, 6)]);
+    assert!(find("C9", &EDO_12).is_empty());
+  }
+
+  #[test]
+  fn test_ambiguous_names_in_other_edos() {
+    // 24 EDO, where the quarter tones between the sharps are spelled with the same
+    // half-sharp name as the step above, so "^C" is two pitch classes
+    let names: Vec<String> = EDO_12
+      .iter()
+      .flat_map(|n| [n.to_string(), format!("^{}", n.trim_end_matches('#'))])
+      .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    assert_eq!(
+      KeyQuery::parse("^C", &names).unwrap(),
+      KeyQuery::PitchClass {
+        classes: vec![1, 3],
+        divisions: 24,
+        octave: None
+      }
+    );
+    // notes 73 and 75 are in octave 2 of 24 EDO, and 97 and 99 in octave 3
+    assert_eq!(find("^C", &names).len(), 8);
+    assert_eq!(
+      find("^C2", &names),
+      vec![(1, 13), (1, 15), (2, 13), (2, 15)]
+    );
+
+    // an exact match wins over one that only differs in case
+    let names = ["e", "E", "f"];
+    assert_eq!(
+      KeyQuery::parse("E", &names).unwrap(),
+      KeyQuery::PitchClass {
+        classes: vec![1],
+        divisions: 3,
+        octave: None
+      }
+    );
+    assert_eq!(
+      KeyQuery::parse("F", &names).unwrap(),
+      KeyQuery::PitchClass {
+        classes: vec![2],
+        divisions: 3,
+        octave: None
+      }
+    );
+
+    // steps named by number are notes when bare, and names when given an octave
+    let numbered: Vec<String> = (0..31).map(|i| i.to_string()).collect();
+    let numbered: Vec<&str> = numbered.iter().map(String::as_str).collect();
+    assert_eq!(KeyQuery::parse("5", &numbered).unwrap(), KeyQuery::Note(5));
+    // note 67 is step 5 of octave 1 of 31 EDO
+    assert_eq!(find("5 1", &numbered), vec![(1, 7), (2, 7)]);
+  }
+}
@@ -4,20 +4,32 @@ use log::warn;
 use crate::midi::sysex::{SysexTable, VelocityIntervalTable};
 
 use ini::Ini;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum EditingStrategy {
   FreeDrawing,
   LinearSegments,
   QuadraticCurves,
 }
 
-#[derive(Debug)]
+/// Serializes with the tables that aren't set left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConfigurationTables {
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub on_off_velocity: Option<ConfigTableDefinition>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub fader_velocity: Option<ConfigTableDefinition>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub aftertouch_velocity: Option<ConfigTableDefinition>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub lumatouch_velocity: Option<ConfigTableDefinition>,
+  #[serde(
+    skip_serializing_if = "Option::is_none",
+    with = "crate::midi::sysex::velocity_intervals_serde::option"
+  )]
   pub velocity_intervals: Option<VelocityIntervalTable>,
 }
 
@@ -33,7 +45,7 @@ impl Default for ConfigurationTables {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConfigTableDefinition {
   pub table: SysexTable,
   pub edit_strategy: EditingStrategy,
@@ -94,12 +106,14 @@ impl ConfigTableDefinition {
       )));
     }
 
-    let mut table: SysexTable = [0; 128];
+    let mut values = [0; 128];
     for (i, s) in tokens.iter().enumerate() {
-      table[i] = u8::from_str_radix(*s, 10).map_err(|e| {
+      values[i] = u8::from_str_radix(*s, 10).map_err(|e| {
         InvalidTableDefinition(format!("unable to parse int in table definition: {e}"))
       })?;
     }
+    let table = SysexTable::try_from(values)
+      .map_err(|_| InvalidTableDefinition("table values must be from 0 to 127".to_string()))?;
 
     Ok(ConfigTableDefinition {
       table,
@@ -143,3 +157,18 @@ pub fn velocity_intervals_to_string(table: &VelocityIntervalTable) -> String {
     .collect::<Vec<String>>()
     .join(" ")
 }
+
+#[cfg(test)]
+mod tests {
+  use super::ConfigTableDefinition;
+
+  #[test]
+  fn test_table_definition_rejects_8bit_values() {
+    let mut values = vec!["1"; 128];
+    let def = ConfigTableDefinition::from_str(&values.join(" ")).unwrap();
+    assert_eq!(def.table[0], 1);
+
+    values[3] = "200";
+    assert!(ConfigTableDefinition::from_str(&values.join(" ")).is_err());
+  }
+}
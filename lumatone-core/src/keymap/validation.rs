@@ -0,0 +1,436 @@
+//! Consistency checks for [LumatoneKeyMap]s.
+//!
+//! [LumatoneKeyMap::validate] returns a list of [ValidationIssue]s, each with a [Severity].
+//! Errors describe keymaps that can't be sent to the device as-is (e.g. out of range note numbers),
+//! while warnings flag things that are legal but probably unintended, like two keys sending the
+//! same note on the same channel.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+
+use crate::geometry::coordinates::hex_for_lumatone_location;
+use crate::midi::constants::{
+  HardwareModel, LumatoneKeyFunction, LumatoneKeyLocation, MidiChannel,
+};
+
+use super::{ltn::LumatoneKeyMap, quantize::PRACTICAL_COLOR_LIMIT};
+
+/// The highest valid MIDI note or CC number.
+const MAX_MIDI_VALUE: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+    }
+  }
+}
+
+/// The category of a [ValidationIssue].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+  /// More than one key sends the same note number on the same channel.
+  DuplicateNote,
+  /// A note-producing key has a note number above 127.
+  NoteOutOfRange,
+  /// A continuous controller key has a CC number above 127.
+  ControllerOutOfRange,
+  /// The layout uses more distinct colors than [PRACTICAL_COLOR_LIMIT].
+  TooManyColors,
+}
+
+impl IssueKind {
+  /// A short, stable identifier for the issue kind, suitable for machine-readable output.
+  pub fn code(&self) -> &'static str {
+    match self {
+      IssueKind::DuplicateNote => "duplicate-note",
+      IssueKind::NoteOutOfRange => "note-out-of-range",
+      IssueKind::ControllerOutOfRange => "cc-out-of-range",
+      IssueKind::TooManyColors => "too-many-colors",
+    }
+  }
+}
+
+/// A single problem found by [LumatoneKeyMap::validate].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+  pub severity: Severity,
+  pub kind: IssueKind,
+  /// The keys involved in the issue, in board / key index order. Empty for issues with
+  /// the layout as a whole.
+  pub locations: Vec<LumatoneKeyLocation>,
+  pub message: String,
+  /// A suggested fix, if there's an obvious one.
+  pub suggestion: Option<String>,
+}
+
+impl Display for ValidationIssue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let locations = self
+      .locations
+      .iter()
+      .map(short_location)
+      .collect::<Vec<String>>()
+      .join(", ");
+    write!(f, "{} [{}] ", self.severity, self.kind.code())?;
+    if !locations.is_empty() {
+      write!(f, "{locations}: ")?;
+    }
+    write!(f, "{}", self.message)?;
+    if let Some(suggestion) = &self.suggestion {
+      write!(f, " ({suggestion})")?;
+    }
+    Ok(())
+  }
+}
+
+/// Formats a key location as `board:key`, e.g. `3:12` for key 12 on Octave3.
+pub fn short_location(location: &LumatoneKeyLocation) -> String {
+  let board: u8 = location.board_index().into();
+  let key: u8 = location.key_index().into();
+  format!("{board}:{key}")
+}
+
+fn location_sort_key(location: &LumatoneKeyLocation) -> (u8, u8) {
+  (location.board_index().into(), location.key_index().into())
+}
+
+impl LumatoneKeyMap {
+  /// Checks the keymap for problems, returning all issues found, with errors before warnings.
+  /// An empty result means the keymap is good to go.
+  pub fn validate(&self) -> Vec<ValidationIssue> {
+    let mut keys: Vec<_> = self.keys().collect();
+    keys.sort_by_key(|(loc, _)| location_sort_key(loc));
+
+    let mut issues = vec![];
+    // (channel, note) -> locations of keys that send that note.
+    // BTreeMap so that duplicate warnings come out in a stable order.
+    let mut notes: BTreeMap<(u8, u8), Vec<LumatoneKeyLocation>> = BTreeMap::new();
+
+    for (location, def) in keys {
+      match def.function {
+        LumatoneKeyFunction::NoteOnOff { channel, note_num }
+        | LumatoneKeyFunction::LumaTouch {
+          channel, note_num, ..
+        } => {
+          if note_num > MAX_MIDI_VALUE {
+            issues.push(ValidationIssue {
+              severity: Severity::Error,
+              kind: IssueKind::NoteOutOfRange,
+              locations: vec![*location],
+              message: format!("note {note_num} is out of range (0 ..= {MAX_MIDI_VALUE})"),
+              suggestion: None,
+            });
+          } else {
+            notes
+              .entry((channel.get(), note_num))
+              .or_default()
+              .push(*location);
+          }
+        }
+
+        LumatoneKeyFunction::ContinuousController { cc_num, .. } if cc_num > MAX_MIDI_VALUE => {
+          issues.push(ValidationIssue {
+            severity: Severity::Error,
+            kind: IssueKind::ControllerOutOfRange,
+            locations: vec![*location],
+            message: format!("CC number {cc_num} is out of range (0 ..= {MAX_MIDI_VALUE})"),
+            suggestion: None,
+          });
+        }
+
+        _ => {}
+      }
+    }
+
+    let used: HashSet<(u8, u8)> = notes.keys().copied().collect();
+    for ((channel, note), locations) in notes.iter() {
+      if locations.len() < 2 {
+        continue;
+      }
+      let free_channel =
+        (MidiChannel::MIN_VALUE..=MidiChannel::MAX_VALUE).find(|ch| !used.contains(&(*ch, *note)));
+      issues.push(ValidationIssue {
+        severity: Severity::Warning,
+        kind: IssueKind::DuplicateNote,
+        locations: locations.clone(),
+        message: format!("duplicate (ch{channel}, note {note})"),
+        suggestion: free_channel.map(|ch| format!("consider channel {ch}")),
+      });
+    }
+
+    let color_count = self.color_counts().len();
+    if color_count > PRACTICAL_COLOR_LIMIT {
+      issues.push(ValidationIssue {
+        severity: Severity::Warning,
+        kind: IssueKind::TooManyColors,
+        locations: vec![],
+        message: format!("{color_count} distinct key colors, which will upload slowly"),
+        suggestion: Some("consider quantizing to a smaller palette".to_string()),
+      });
+    }
+
+    // stable sort, so issues of the same severity keep their board / key ordering
+    issues.sort_by_key(|issue| Reverse(issue.severity));
+    issues
+  }
+
+  /// Checks that every key can be sent to the device as-is: its location is one of the
+  /// 280 physical keys, and its note or CC number is in range. (Channels can't be out of
+  /// range, since [MidiChannel] is checked when it's created.)
+  ///
+  /// Unlike [LumatoneKeyMap::validate], this only looks for hard errors, and is meant to be
+  /// called right before sending, to catch generator bugs without a device round-trip.
+  /// Returns a description of each problem, in board / key order.
+  ///
+  /// This assumes current hardware; see [LumatoneKeyMap::check_hardware_compat_for].
+  pub fn check_hardware_compat(&self) -> Result<(), Vec<String>> {
+    self.check_hardware_compat_for(&HardwareModel::default())
+  }
+
+  /// Like [LumatoneKeyMap::check_hardware_compat], but also checks that every key is one
+  /// that `model` can address, since early firmware only knows about 55 keys per board.
+  pub fn check_hardware_compat_for(&self, model: &HardwareModel) -> Result<(), Vec<String>> {
+    let mut keys: Vec<_> = self.keys().collect();
+    keys.sort_by_key(|(loc, _)| location_sort_key(loc));
+
+    let mut problems = vec![];
+    for (location, def) in keys {
+      let loc = short_location(location);
+      if hex_for_lumatone_location(location).is_none() {
+        problems.push(format!("{loc}: not a key on the keyboard"));
+      } else if !model.has_key(location.key_index()) {
+        problems.push(format!(
+          "{loc}: this device only has {} keys per board",
+          model.key_count_per_board
+        ));
+      }
+
+      let (name, value) = match def.function {
+        LumatoneKeyFunction::NoteOnOff { note_num, .. }
+        | LumatoneKeyFunction::LumaTouch { note_num, .. } => ("note", note_num),
+        LumatoneKeyFunction::ContinuousController { cc_num, .. } => ("CC number", cc_num),
+        LumatoneKeyFunction::Disabled => continue,
+      };
+      if value > MAX_MIDI_VALUE {
+        problems.push(format!(
+          "{loc}: {name} {value} is out of range (0 ..= {MAX_MIDI_VALUE})"
+        ));
+      }
+    }
+
+    if problems.is_empty() {
+      Ok(())
+    } else {
+      Err(problems)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{IssueKind, Severity};
+  use crate::keymap::ltn::{KeyDefinition, LumatoneKeyMap};
+  use crate::keymap::quantize::PRACTICAL_COLOR_LIMIT;
+  use crate::midi::constants::{
+    key_loc_unchecked, BoardIndex, HardwareModel, LumatoneKeyFunction, LumatoneKeyIndex,
+    LumatoneKeyLocation, MidiChannel, RGBColor,
+  };
+
+  fn note(channel: u8, note_num: u8) -> KeyDefinition {
+    KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(channel),
+        note_num,
+      },
+      color: RGBColor::red(),
+    }
+  }
+
+  #[test]
+  fn test_valid_keymap_has_no_issues() {
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0..=1 = note 60..=61 ch 1
+      2:0 = note 60 ch 2
+      ",
+    )
+    .unwrap();
+    assert!(keymap.validate().is_empty());
+  }
+
+  #[test]
+  fn test_too_many_colors() {
+    let mut keymap = LumatoneKeyMap::new();
+    for i in 0..=PRACTICAL_COLOR_LIMIT as u8 {
+      let location = key_loc_unchecked(1 + i / 56, i % 56);
+      let def = KeyDefinition {
+        color: RGBColor(i, 0, 0),
+        ..note(1 + i / 56, i % 56)
+      };
+      keymap.set_key(location, def);
+    }
+
+    let issues = keymap.validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].kind, IssueKind::TooManyColors);
+    assert!(issues[0].locations.is_empty());
+    assert_eq!(
+      issues[0].to_string(),
+      "warning [too-many-colors] 65 distinct key colors, which will upload slowly \
+       (consider quantizing to a smaller palette)"
+    );
+
+    keymap.quantize_colors(PRACTICAL_COLOR_LIMIT);
+    assert!(keymap.validate().is_empty());
+  }
+
+  #[test]
+  fn test_duplicate_notes() {
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      3:12 = note 60 ch 1
+      1:0 = note 60 ch 1
+      1:1 = note 60 ch 2
+      ",
+    )
+    .unwrap();
+
+    let issues = keymap.validate();
+    assert_eq!(issues.len(), 1);
+    let issue = &issues[0];
+    assert_eq!(issue.severity, Severity::Warning);
+    assert_eq!(issue.kind, IssueKind::DuplicateNote);
+    assert_eq!(
+      issue.locations,
+      vec![key_loc_unchecked(1, 0), key_loc_unchecked(3, 12)]
+    );
+    // channel 2 is taken by 1:1, so channel 3 is the first free one
+    assert_eq!(issue.suggestion.as_deref(), Some("consider channel 3"));
+    assert_eq!(
+      issue.to_string(),
+      "warning [duplicate-note] 1:0, 3:12: duplicate (ch1, note 60) (consider channel 3)"
+    );
+  }
+
+  #[test]
+  fn test_out_of_range_values_are_errors() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key(key_loc_unchecked(1, 0), note(1, 60))
+      .set_key(key_loc_unchecked(1, 1), note(1, 60))
+      .set_key(key_loc_unchecked(2, 5), note(1, 200))
+      .set_key(
+        key_loc_unchecked(4, 0),
+        KeyDefinition {
+          function: LumatoneKeyFunction::ContinuousController {
+            channel: MidiChannel::default(),
+            cc_num: 128,
+            fader_up_is_null: false,
+          },
+          color: RGBColor::blue(),
+        },
+      );
+
+    let kinds: Vec<_> = keymap.validate().iter().map(|i| i.kind).collect();
+    assert_eq!(
+      kinds,
+      vec![
+        IssueKind::NoteOutOfRange,
+        IssueKind::ControllerOutOfRange,
+        IssueKind::DuplicateNote
+      ]
+    );
+  }
+
+  #[test]
+  fn test_hardware_compatible_keymap() {
+    let keymap = LumatoneKeyMap::from_dsl(
+      "
+      1:0..=55 = note 0..=55 ch 1
+      5:55 = cc 127 ch 16
+      3:7 = disabled
+      ",
+    )
+    .unwrap();
+    assert_eq!(keymap.check_hardware_compat(), Ok(()));
+    assert_eq!(LumatoneKeyMap::new().check_hardware_compat(), Ok(()));
+  }
+
+  #[test]
+  fn test_hardware_compat_out_of_range_values() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key(key_loc_unchecked(2, 5), note(1, 200))
+      .set_key(
+        key_loc_unchecked(1, 3),
+        KeyDefinition {
+          function: LumatoneKeyFunction::LumaTouch {
+            channel: MidiChannel::default(),
+            note_num: 128,
+            fader_up_is_null: false,
+          },
+          color: RGBColor::green(),
+        },
+      )
+      .set_key(
+        key_loc_unchecked(4, 0),
+        KeyDefinition {
+          function: LumatoneKeyFunction::ContinuousController {
+            channel: MidiChannel::default(),
+            cc_num: 255,
+            fader_up_is_null: false,
+          },
+          color: RGBColor::blue(),
+        },
+      );
+
+    assert_eq!(
+      keymap.check_hardware_compat(),
+      Err(vec![
+        "1:3: note 128 is out of range (0 ..= 127)".to_string(),
+        "2:5: note 200 is out of range (0 ..= 127)".to_string(),
+        "4:0: CC number 255 is out of range (0 ..= 127)".to_string(),
+      ])
+    );
+  }
+
+  #[test]
+  fn test_hardware_compat_invalid_location() {
+    let mut keymap = LumatoneKeyMap::new();
+    keymap
+      .set_key(key_loc_unchecked(1, 0), note(1, 60))
+      .set_key(
+        LumatoneKeyLocation(BoardIndex::Server, LumatoneKeyIndex::unchecked(4)),
+        note(1, 61),
+      );
+    assert_eq!(
+      keymap.check_hardware_compat(),
+      Err(vec!["0:4: not a key on the keyboard".to_string()])
+    );
+  }
+
+  #[test]
+  fn test_hardware_compat_55_key_firmware() {
+    let keymap = LumatoneKeyMap::from_dsl("2:54..=55 = note 1..=2 ch 1").unwrap();
+    let early = HardwareModel {
+      key_count_per_board: 55,
+      has_serial: false,
+    };
+    assert_eq!(keymap.check_hardware_compat(), Ok(()));
+    assert_eq!(
+      keymap.check_hardware_compat_for(&early),
+      Err(vec![
+        "2:55: this device only has 55 keys per board".to_string()
+      ])
+    );
+  }
+}
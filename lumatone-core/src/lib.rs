@@ -1,5 +1,27 @@
+//! Tools for talking to a [Lumatone](https://www.lumatone.io) keyboard over MIDI and
+//! working with its keymaps.
+//!
+//! [midi::client::Client] is the easiest way in. This lights up every key in green:
+//!
+//! ```no_run
+//! use lumatone_core::midi::client::Client;
+//! use lumatone_core::midi::constants::{LumatoneKeyLocation, RGBColor};
+//!
+//! # async fn light_it_up() -> Result<(), lumatone_core::midi::error::LumatoneMidiError> {
+//! let client = Client::connect().await?;
+//! for location in LumatoneKeyLocation::all() {
+//!   client.set_key_color(location, RGBColor::green()).await?;
+//! }
+//! client.close().await
+//! # }
+//! ```
+//!
+//! Whole layouts live in [keymap::ltn::LumatoneKeyMap], which can be loaded from `.ltn`
+//! preset files and sent with [midi::client::Client::apply_keymap].
+
 pub mod midi;
 pub mod keymap;
 pub mod geometry;
 pub mod color;
 pub mod harmony;
+pub mod settings;
@@ -0,0 +1,135 @@
+//! Decides when to stop sending to a device that keeps saying it's busy.
+//!
+//! The driver already retries individual commands after a Busy response, but something
+//! that sends continuously (like syncing every edit to the device as it happens) can pile
+//! up retries faster than an overwhelmed device can work through them. [BusyBackoff]
+//! counts Busy responses (see [DriverEvent::DeviceBusy](super::driver::DriverEvent::DeviceBusy))
+//! in a sliding window and says when the sender should pause until the user resumes it.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A sliding-window counter of Busy responses that trips once too many arrive too quickly.
+///
+/// Times are passed in rather than read from the clock, so that callers (and tests)
+/// control them.
+#[derive(Debug, Clone)]
+pub struct BusyBackoff {
+  threshold: usize,
+  window: Duration,
+  /// When each Busy response in the current window arrived, oldest first.
+  busy_times: VecDeque<Instant>,
+  paused: bool,
+}
+
+impl BusyBackoff {
+  /// Creates a counter that pauses once `threshold` Busy responses arrive within `window`.
+  pub fn new(threshold: usize, window: Duration) -> Self {
+    BusyBackoff {
+      threshold: threshold.max(1),
+      window,
+      busy_times: VecDeque::new(),
+      paused: false,
+    }
+  }
+
+  /// Records a Busy response that arrived at `now`.
+  /// Returns true if this is the one that tripped the threshold and paused sending.
+  pub fn record_busy(&mut self, now: Instant) -> bool {
+    if self.paused {
+      return false;
+    }
+
+    self.busy_times.push_back(now);
+    self.expire(now);
+    if self.busy_times.len() >= self.threshold {
+      self.paused = true;
+      self.busy_times.clear();
+      return true;
+    }
+    false
+  }
+
+  /// Returns the number of Busy responses within the window ending at `now`.
+  pub fn busy_count(&mut self, now: Instant) -> usize {
+    self.expire(now);
+    self.busy_times.len()
+  }
+
+  /// Returns true if sending should be paused.
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+
+  /// Lifts a pause, starting over with an empty window.
+  pub fn resume(&mut self) {
+    self.paused = false;
+    self.busy_times.clear();
+  }
+
+  /// Forgets Busy responses that are older than the window ending at `now`.
+  fn expire(&mut self, now: Instant) {
+    while let Some(oldest) = self.busy_times.front() {
+      if now.saturating_duration_since(*oldest) < self.window {
+        break;
+      }
+      self.busy_times.pop_front();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use super::BusyBackoff;
+
+  #[test]
+  fn test_pauses_at_threshold_within_window() {
+    let start = Instant::now();
+    let secs = |n: u64| start + Duration::from_secs(n);
+    let mut backoff = BusyBackoff::new(3, Duration::from_secs(10));
+
+    assert!(!backoff.record_busy(secs(0)));
+    assert!(!backoff.record_busy(secs(5)));
+    assert!(!backoff.is_paused());
+    assert!(backoff.record_busy(secs(9)));
+    assert!(backoff.is_paused());
+
+    // already paused, so further busy responses don't trip it again
+    assert!(!backoff.record_busy(secs(9)));
+  }
+
+  #[test]
+  fn test_old_busy_responses_slide_out_of_window() {
+    let start = Instant::now();
+    let secs = |n: u64| start + Duration::from_secs(n);
+    let mut backoff = BusyBackoff::new(3, Duration::from_secs(10));
+
+    backoff.record_busy(secs(0));
+    backoff.record_busy(secs(5));
+    assert_eq!(backoff.busy_count(secs(9)), 2);
+
+    // the first one has expired by now
+    assert!(!backoff.record_busy(secs(10)));
+    assert_eq!(backoff.busy_count(secs(10)), 2);
+    assert!(!backoff.is_paused());
+
+    assert_eq!(backoff.busy_count(secs(30)), 0);
+  }
+
+  #[test]
+  fn test_resume_starts_a_fresh_window() {
+    let start = Instant::now();
+    let secs = |n: u64| start + Duration::from_secs(n);
+    let mut backoff = BusyBackoff::new(2, Duration::from_secs(10));
+
+    backoff.record_busy(secs(0));
+    assert!(backoff.record_busy(secs(1)));
+
+    backoff.resume();
+    assert!(!backoff.is_paused());
+    assert!(!backoff.record_busy(secs(2)));
+    assert!(backoff.record_busy(secs(3)));
+  }
+}
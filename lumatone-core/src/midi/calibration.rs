@@ -0,0 +1,257 @@
+//! Follows key and aftertouch calibration, which the firmware runs on its own once started
+//! (see [MidiDriver::calibrate_keys](super::driver::MidiDriver::calibrate_keys)).
+//!
+//! ## What the firmware sends
+//!
+//! [Command::StartKeyCalibration](super::commands::Command::StartKeyCalibration) and
+//! [Command::StartAftertouchCalibration](super::commands::Command::StartAftertouchCalibration)
+//! are acknowledged right away, by the Server board they're addressed to. Key calibration
+//! then runs until the pair of macro buttons on each octave has been pressed.
+//!
+//! The firmware reportedly sends status messages while key calibration runs, but their
+//! encoding isn't documented and we haven't captured any yet, so this assumes as little
+//! as possible:
+//!
+//! - A message with the `CalibrateKeys` or `CalibrateAftertouch` command id that comes from
+//!   an octave board, rather than the Server board, is a status message. Its payload is
+//!   kept as-is in a [CalibrationStatus].
+//! - There's no known "finished" message, so [CalibrationTracker] decides when calibration
+//!   is over using timeouts: once boards have sent status messages and then gone quiet for
+//!   a while, it's [Completed](CalibrationOutcome::Completed). If nothing arrives at all
+//!   before the overall time limit, the outcome is [Unconfirmed](CalibrationOutcome::Unconfirmed),
+//!   since the firmware may simply not report anything.
+//!
+//! Once real captures are available, decoding the payload and recognizing the end of
+//! calibration directly should replace the heuristic.
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::{
+  commands::Command,
+  constants::{BoardIndex, CommandId},
+};
+
+/// Which calibration routine to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CalibrationKind {
+  Keys,
+  Aftertouch,
+}
+
+impl CalibrationKind {
+  pub fn command_id(&self) -> CommandId {
+    match self {
+      CalibrationKind::Keys => CommandId::CalibrateKeys,
+      CalibrationKind::Aftertouch => CommandId::CalibrateAftertouch,
+    }
+  }
+
+  /// Returns the kind of calibration that messages with `cmd` belong to, if any.
+  pub fn from_command_id(cmd: &CommandId) -> Option<Self> {
+    match cmd {
+      CommandId::CalibrateKeys => Some(CalibrationKind::Keys),
+      CommandId::CalibrateAftertouch => Some(CalibrationKind::Aftertouch),
+      _ => None,
+    }
+  }
+
+  /// The command that starts this kind of calibration.
+  pub fn start_command(&self) -> Command {
+    match self {
+      CalibrationKind::Keys => Command::StartKeyCalibration,
+      CalibrationKind::Aftertouch => Command::StartAftertouchCalibration,
+    }
+  }
+}
+
+impl Display for CalibrationKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CalibrationKind::Keys => write!(f, "key"),
+      CalibrationKind::Aftertouch => write!(f, "aftertouch"),
+    }
+  }
+}
+
+/// A status message sent by a board during calibration. The payload's meaning is unknown,
+/// so it's left undecoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CalibrationStatus {
+  pub kind: CalibrationKind,
+  pub board: BoardIndex,
+  pub payload: Vec<u8>,
+}
+
+/// How calibration seems to have ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalibrationOutcome {
+  /// Boards sent status messages, then stopped. Lists the boards that reported,
+  /// in the order they first did.
+  Completed { boards: Vec<BoardIndex> },
+
+  /// Nothing was heard from the device before the time limit. Calibration may well have
+  /// finished anyway; the firmware doesn't necessarily say.
+  Unconfirmed,
+}
+
+/// What's happened so far in a calibration run, as reported by
+/// [MidiDriver::calibrate_keys](super::driver::MidiDriver::calibrate_keys).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalibrationProgress {
+  /// The device acknowledged the start command.
+  Started,
+  /// A board sent a status message.
+  Status(CalibrationStatus),
+  /// Calibration looks to be over. This is always the last event.
+  Finished(CalibrationOutcome),
+}
+
+/// The timeouts used to decide when calibration has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalibrationTiming {
+  /// How long to wait after the last status message before deciding calibration is done.
+  /// Long enough to move between octaves, in case boards only report while calibrating.
+  pub quiet_period: Duration,
+
+  /// How long to wait overall. Status messages that keep arriving past this don't extend it.
+  pub max_duration: Duration,
+}
+
+impl Default for CalibrationTiming {
+  fn default() -> Self {
+    CalibrationTiming {
+      quiet_period: Duration::from_secs(15),
+      max_duration: Duration::from_secs(180),
+    }
+  }
+}
+
+/// Applies the completion heuristic described in the [module docs](self) to the status
+/// messages received during a calibration run.
+#[derive(Debug, Clone)]
+pub struct CalibrationTracker {
+  timing: CalibrationTiming,
+  started: Instant,
+  last_status: Option<Instant>,
+  boards: Vec<BoardIndex>,
+}
+
+impl CalibrationTracker {
+  pub fn new(timing: CalibrationTiming, started: Instant) -> Self {
+    CalibrationTracker {
+      timing,
+      started,
+      last_status: None,
+      boards: Vec::new(),
+    }
+  }
+
+  /// Notes a status message received at `now`.
+  pub fn record(&mut self, status: &CalibrationStatus, now: Instant) {
+    self.last_status = Some(now);
+    if !self.boards.contains(&status.board) {
+      self.boards.push(status.board);
+    }
+  }
+
+  /// The next time [CalibrationTracker::outcome] could change, if no more status messages arrive.
+  pub fn deadline(&self) -> Instant {
+    let limit = self.started + self.timing.max_duration;
+    match self.last_status {
+      Some(last) => limit.min(last + self.timing.quiet_period),
+      None => limit,
+    }
+  }
+
+  /// Returns how calibration ended, as of `now`, or `None` if it still seems to be running.
+  pub fn outcome(&self, now: Instant) -> Option<CalibrationOutcome> {
+    let gone_quiet = self
+      .last_status
+      .is_some_and(|last| now >= last + self.timing.quiet_period);
+    let out_of_time = now >= self.started + self.timing.max_duration;
+    if !(gone_quiet || out_of_time) {
+      return None;
+    }
+    Some(match self.last_status {
+      Some(_) => CalibrationOutcome::Completed {
+        boards: self.boards.clone(),
+      },
+      None => CalibrationOutcome::Unconfirmed,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use super::{
+    CalibrationKind, CalibrationOutcome, CalibrationStatus, CalibrationTiming, CalibrationTracker,
+  };
+  use crate::midi::constants::BoardIndex;
+
+  const TIMING: CalibrationTiming = CalibrationTiming {
+    quiet_period: Duration::from_secs(10),
+    max_duration: Duration::from_secs(60),
+  };
+
+  fn status(board: BoardIndex) -> CalibrationStatus {
+    CalibrationStatus {
+      kind: CalibrationKind::Keys,
+      board,
+      payload: vec![],
+    }
+  }
+
+  fn secs(n: u64) -> Duration {
+    Duration::from_secs(n)
+  }
+
+  #[test]
+  fn test_silent_firmware_is_unconfirmed_at_the_time_limit() {
+    let start = Instant::now();
+    let tracker = CalibrationTracker::new(TIMING, start);
+    assert_eq!(tracker.deadline(), start + secs(60));
+    assert_eq!(tracker.outcome(start + secs(59)), None);
+    assert_eq!(
+      tracker.outcome(start + secs(60)),
+      Some(CalibrationOutcome::Unconfirmed)
+    );
+  }
+
+  #[test]
+  fn test_completes_once_boards_go_quiet() {
+    let start = Instant::now();
+    let mut tracker = CalibrationTracker::new(TIMING, start);
+    tracker.record(&status(BoardIndex::Octave2), start + secs(5));
+    tracker.record(&status(BoardIndex::Octave1), start + secs(12));
+    tracker.record(&status(BoardIndex::Octave2), start + secs(20));
+    assert_eq!(tracker.deadline(), start + secs(30));
+
+    // a pause shorter than the quiet period doesn't end it
+    assert_eq!(tracker.outcome(start + secs(29)), None);
+    assert_eq!(
+      tracker.outcome(start + secs(30)),
+      Some(CalibrationOutcome::Completed {
+        boards: vec![BoardIndex::Octave2, BoardIndex::Octave1],
+      })
+    );
+  }
+
+  #[test]
+  fn test_status_messages_do_not_extend_the_time_limit() {
+    let start = Instant::now();
+    let mut tracker = CalibrationTracker::new(TIMING, start);
+    tracker.record(&status(BoardIndex::Octave5), start + secs(55));
+    assert_eq!(tracker.deadline(), start + secs(60));
+    assert_eq!(
+      tracker.outcome(start + secs(60)),
+      Some(CalibrationOutcome::Completed {
+        boards: vec![BoardIndex::Octave5],
+      })
+    );
+  }
+}
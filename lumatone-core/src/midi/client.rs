@@ -0,0 +1,826 @@
+//! A high-level entry point for talking to a Lumatone.
+//!
+//! A [Client] finds the device, runs a [MidiDriver] event loop in a background task, and
+//! wraps the common operations in methods with typed results, so most programs don't need
+//! to deal with [Command]s and [Response]s directly. Anything it doesn't cover can still be
+//! done through [Client::driver].
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use super::{
+  commands::{ping, set_key_color, Command},
+  constants::{
+    BoardIndex, HardwareModel, LumatoneKeyFunction, LumatoneKeyIndex, LumatoneKeyLocation, RGBColor,
+  },
+  detect::detect_device,
+  device::{LumatoneDevice, MidiTransport},
+  driver::{DriverEvent, MidiDriver, MidiDriverBuilder, MidiDriverConfig},
+  error::LumatoneMidiError,
+  responses::{unexpected_response, Response},
+  script::{ScriptProgress, ScriptReport},
+};
+use crate::keymap::ltn::{ApplyParts, KeyDefinition, LumatoneKeyMap};
+use crate::keymap::offline::OfflineEdits;
+
+pub use super::responses::{FirmwareVersion, PeripheralChannels};
+
+/// A connection to a Lumatone, with its driver running in a background task.
+///
+/// Must be created from within a tokio runtime. Call [Client::close] when done, to shut
+/// the driver down cleanly.
+pub struct Client {
+  driver: MidiDriver,
+  driver_task: JoinHandle<()>,
+  next_ping: AtomicU32,
+  /// Set once [Client::identify] or [Client::info] has found out.
+  model: Mutex<Option<HardwareModel>>,
+}
+
+/// What a device reports about itself. See [Client::info].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+  /// The device's serial number, or `None` if its firmware is too old to report one.
+  pub serial_id: Option<[u8; 6]>,
+  pub firmware: FirmwareVersion,
+  pub model: HardwareModel,
+}
+
+/// Options for [Client::apply_keymap].
+#[derive(Debug, Clone)]
+pub struct ApplyOptions {
+  /// Scales every key color, as with [RGBColor::scaled]. 1.0 sends colors unchanged.
+  pub brightness: f64,
+
+  /// Stop at the first command that fails, instead of sending the rest anyway.
+  pub stop_on_error: bool,
+
+  /// Which parts of the keymap to send.
+  pub parts: ApplyParts,
+}
+
+impl Default for ApplyOptions {
+  fn default() -> Self {
+    ApplyOptions {
+      brightness: 1.0,
+      stop_on_error: false,
+      parts: ApplyParts::all(),
+    }
+  }
+}
+
+/// The outcome of [Client::send_preset].
+#[derive(Debug)]
+pub struct PresetSendReport {
+  /// Each command that was sent, in order, with its final result. For a command that
+  /// failed and was sent again, that's the result of the retry.
+  pub results: Vec<(Command, Result<Response, LumatoneMidiError>)>,
+
+  /// How many commands failed the first time and were retried.
+  pub retried: usize,
+
+  pub duration: Duration,
+}
+
+impl PresetSendReport {
+  /// The number of commands that succeeded, either the first time or on retry.
+  pub fn succeeded(&self) -> usize {
+    self.results.iter().filter(|(_, res)| res.is_ok()).count()
+  }
+
+  /// The number of commands that still failed after being retried.
+  pub fn failed(&self) -> usize {
+    self.results.len() - self.succeeded()
+  }
+
+  /// Returns true if every command that was sent succeeded in the end.
+  pub fn is_success(&self) -> bool {
+    self.failed() == 0
+  }
+
+  /// Returns the commands that still failed after being retried, with their errors. Use
+  /// [Command::key_location] to find which key a failed command was for.
+  pub fn failures(&self) -> impl Iterator<Item = (&Command, &LumatoneMidiError)> {
+    self
+      .results
+      .iter()
+      .filter_map(|(c, res)| res.as_ref().err().map(|err| (c, err)))
+  }
+
+  /// A one-line summary, e.g. "542 commands ok, 3 failed".
+  pub fn summary(&self) -> String {
+    format!("{} commands ok, {} failed", self.succeeded(), self.failed())
+  }
+}
+
+impl Client {
+  /// Detects a connected Lumatone (see [detect_device]) and connects to it with the
+  /// default [MidiDriverConfig].
+  pub async fn connect() -> Result<Client, LumatoneMidiError> {
+    let device = detect_device().await?;
+    Client::with_device(&device, MidiDriverConfig::default())
+  }
+
+  /// Connects to a known `device`, skipping detection.
+  pub fn with_device(
+    device: &LumatoneDevice,
+    config: MidiDriverConfig,
+  ) -> Result<Client, LumatoneMidiError> {
+    let (driver, driver_future) = MidiDriverBuilder::from_config(config).build(device)?;
+    Ok(Client::start(driver, driver_future))
+  }
+
+  fn start(driver: MidiDriver, driver_future: impl Future<Output = ()> + Send + 'static) -> Client {
+    Client {
+      driver,
+      driver_task: tokio::spawn(driver_future),
+      next_ping: AtomicU32::new(1),
+      model: Mutex::new(None),
+    }
+  }
+
+  /// Connects over a custom [MidiTransport], as with [MidiDriver::with_transport].
+  pub fn with_transport(device_io: Box<dyn MidiTransport>, config: MidiDriverConfig) -> Client {
+    let (driver, driver_future) = MidiDriver::with_transport(device_io, config);
+    Client::start(driver, driver_future)
+  }
+
+  /// The underlying driver, for anything the client doesn't have a method for.
+  pub fn driver(&self) -> &MidiDriver {
+    &self.driver
+  }
+
+  /// Sends a single [Command] and returns its [Response].
+  pub async fn send(&self, command: Command) -> Result<Response, LumatoneMidiError> {
+    self.driver.send(command).await
+  }
+
+  /// Reads the device's firmware version and serial number, and works out its
+  /// [HardwareModel] along the way.
+  pub async fn info(&self) -> Result<DeviceInfo, LumatoneMidiError> {
+    let firmware = self.driver.get_firmware_revision().await?;
+    let serial_id = self.read_serial_id().await?;
+    let model = self.identify_with_serial(serial_id).await?;
+    Ok(DeviceInfo {
+      serial_id,
+      firmware,
+      model,
+    })
+  }
+
+  /// Works out which generation of hardware the device is, from whether it has a serial
+  /// number and how many keys its first board reports.
+  ///
+  /// The result is remembered, and [Client::read_keymap] reads only as many keys per
+  /// board as the model has.
+  pub async fn identify(&self) -> Result<HardwareModel, LumatoneMidiError> {
+    let serial_id = self.read_serial_id().await?;
+    self.identify_with_serial(serial_id).await
+  }
+
+  /// The [HardwareModel] found by the last call to [Client::identify] or [Client::info],
+  /// if there's been one.
+  pub fn model(&self) -> Option<HardwareModel> {
+    *self.model.lock().unwrap()
+  }
+
+  async fn identify_with_serial(
+    &self,
+    serial_id: Option<[u8; 6]>,
+  ) -> Result<HardwareModel, LumatoneMidiError> {
+    let key_count = match self
+      .send(Command::GetKeyTypeConfig(BoardIndex::Octave1))
+      .await?
+    {
+      Response::KeyTypeConfig(_, types) => types.len().min(HardwareModel::FULL_KEY_COUNT as usize),
+      other => return Err(unexpected_response("key types", &other)),
+    };
+    let model = HardwareModel {
+      key_count_per_board: key_count as u8,
+      has_serial: serial_id.is_some(),
+    };
+    *self.model.lock().unwrap() = Some(model);
+    Ok(model)
+  }
+
+  /// Reads the serial number, or `None` if the firmware doesn't report one.
+  async fn read_serial_id(&self) -> Result<Option<[u8; 6]>, LumatoneMidiError> {
+    match self.driver.get_serial_id().await {
+      Ok(id) => Ok(Some(id)),
+      Err(LumatoneMidiError::FirmwareTooOld(_)) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Pings the device, returning how long it took to answer.
+  pub async fn ping(&self) -> Result<Duration, LumatoneMidiError> {
+    // a ping carries 3 7-bit bytes, so values are limited to 21 bits
+    let value = self.next_ping.fetch_add(1, Ordering::Relaxed) & 0x1fffff;
+    let started = Instant::now();
+    match self.send(ping(value)).await? {
+      Response::Pong(v) if v == value => Ok(started.elapsed()),
+      other => Err(unexpected_response(&format!("Pong({value})"), &other)),
+    }
+  }
+
+  /// Reads the function and color of every key back from the device.
+  ///
+  /// Only keys are read; the firmware can't report the global options or tables, so those
+  /// are left at their defaults. If any board can't be read, the result is a
+  /// [LumatoneMidiError::BoardErrors] listing each board that failed, with a
+  /// [LumatoneMidiError::CommandFailed] naming the query that failed for it.
+  ///
+  /// Each board's keys are read as far as its shortest table goes, and no further than
+  /// the [HardwareModel]'s key count, if [Client::identify] has been called.
+  pub async fn read_keymap(&self) -> Result<LumatoneKeyMap, LumatoneMidiError> {
+    let mut keymap = LumatoneKeyMap::new();
+    let mut errors = vec![];
+    for board in BoardIndex::all_octaves() {
+      match self.read_board_keys(board).await {
+        Ok(keys) => {
+          for (location, def) in keys {
+            keymap.set_key(location, def);
+          }
+        }
+        Err(err) => errors.push((board, err)),
+      }
+    }
+
+    if errors.is_empty() {
+      Ok(keymap)
+    } else {
+      Err(LumatoneMidiError::BoardErrors(errors))
+    }
+  }
+
+  async fn read_board_keys(
+    &self,
+    board: BoardIndex,
+  ) -> Result<Vec<(LumatoneKeyLocation, KeyDefinition)>, LumatoneMidiError> {
+    use Response::*;
+    let colors = self.driver.get_board_colors(board).await?;
+    let commands = vec![
+      Command::GetMidiChannelConfig(board),
+      Command::GetNoteConfig(board),
+      Command::GetKeyTypeConfig(board),
+    ];
+    let responses = self.driver.send_all_or_fail(commands).await?;
+
+    let (channels, notes, types) = match responses.as_slice() {
+      [ChannelConfig(b1, channels), NoteConfig(b2, notes), KeyTypeConfig(b3, types)]
+        if [b1, b2, b3].iter().all(|b| **b == board) =>
+      {
+        (channels, notes, types)
+      }
+      _ => {
+        return Err(LumatoneMidiError::InvalidResponseMessage(format!(
+          "unexpected responses when reading the keys of {board}"
+        )))
+      }
+    };
+
+    // older firmware leaves out the last key, so go by the shortest table
+    let model_key_count = self
+      .model()
+      .map_or(usize::MAX, |m| m.key_count_per_board as usize);
+    let key_count = [colors.len(), channels.len(), notes.len(), types.len()]
+      .into_iter()
+      .min()
+      .unwrap_or(0)
+      .min(model_key_count);
+
+    let keys = (0..key_count)
+      .map(|i| {
+        let function = LumatoneKeyFunction::from_type_code(types[i], channels[i], notes[i])
+          .unwrap_or_else(|| {
+            log::warn!("unrecognized key type code: {}", types[i]);
+            LumatoneKeyFunction::Disabled
+          });
+        let location = LumatoneKeyLocation(board, LumatoneKeyIndex::unchecked(i as u8));
+        (
+          location,
+          KeyDefinition {
+            function,
+            color: colors[i],
+          },
+        )
+      })
+      .collect();
+    Ok(keys)
+  }
+
+  /// Sends the keys and options in `keymap` to the device, one command at a time.
+  /// By default everything is sent; see [ApplyOptions::parts].
+  ///
+  /// Individual command failures don't make this fail; they're listed in the returned
+  /// report. Use [LumatoneKeyMap::check_hardware_compat] first to catch keymaps that the
+  /// device would reject.
+  pub async fn apply_keymap(&self, keymap: &LumatoneKeyMap, opts: &ApplyOptions) -> ScriptReport {
+    self.apply_keymap_with_progress(keymap, opts, |_| {}).await
+  }
+
+  /// Like [Client::apply_keymap], but calls `on_progress` after each command, as with
+  /// [MidiDriver::run_commands_with_progress].
+  pub async fn apply_keymap_with_progress(
+    &self,
+    keymap: &LumatoneKeyMap,
+    opts: &ApplyOptions,
+    on_progress: impl FnMut(ScriptProgress),
+  ) -> ScriptReport {
+    let commands = keymap
+      .to_midi_commands_filtered(opts.parts)
+      .into_iter()
+      .map(|c| match c {
+        Command::SetKeyColor { location, color } => Command::SetKeyColor {
+          location,
+          color: color.scaled(opts.brightness),
+        },
+        c => c,
+      })
+      .collect();
+    self
+      .driver
+      .run_commands_with_progress(commands, opts.stop_on_error, on_progress)
+      .await
+  }
+
+  /// Like [Client::apply_keymap], but once everything has been sent, each command that
+  /// failed (e.g. was refused, or timed out) is sent once more. The report has the final
+  /// result of each command, so a preset that only failed to apply partly can be
+  /// reported key by key.
+  ///
+  /// With [ApplyOptions::stop_on_error], the command that stopped the run is retried,
+  /// but the ones after it still aren't sent.
+  pub async fn send_preset(
+    &self,
+    keymap: &LumatoneKeyMap,
+    opts: &ApplyOptions,
+  ) -> PresetSendReport {
+    self.send_preset_with_progress(keymap, opts, |_| {}).await
+  }
+
+  /// Like [Client::send_preset], but calls `on_progress` after each command of the first
+  /// pass, as with [Client::apply_keymap_with_progress]. Retries aren't reported.
+  pub async fn send_preset_with_progress(
+    &self,
+    keymap: &LumatoneKeyMap,
+    opts: &ApplyOptions,
+    on_progress: impl FnMut(ScriptProgress),
+  ) -> PresetSendReport {
+    let start = Instant::now();
+    let mut results = self
+      .apply_keymap_with_progress(keymap, opts, on_progress)
+      .await
+      .results;
+    let mut retried = 0;
+    for (command, res) in results.iter_mut().filter(|(_, res)| res.is_err()) {
+      log::debug!("retrying failed command {command}");
+      retried += 1;
+      *res = self.driver.send(command.clone()).await;
+    }
+    PresetSendReport {
+      results,
+      retried,
+      duration: start.elapsed(),
+    }
+  }
+
+  /// Sets the function and color of the key at `location`, like [MidiDriver::set_key].
+  pub async fn set_key(
+    &self,
+    location: LumatoneKeyLocation,
+    def: &KeyDefinition,
+  ) -> Result<(), LumatoneMidiError> {
+    self.driver.set_key(location, def).await
+  }
+
+  /// Sets the color of the key at `location`, leaving its function alone.
+  pub async fn set_key_color(
+    &self,
+    location: LumatoneKeyLocation,
+    color: RGBColor,
+  ) -> Result<(), LumatoneMidiError> {
+    self.send(set_key_color(location, color)).await.map(|_| ())
+  }
+
+  /// Returns a receiver for [DriverEvent]s, like [MidiDriver::subscribe_events].
+  pub fn subscribe_events(&self) -> broadcast::Receiver<DriverEvent> {
+    self.driver.subscribe_events()
+  }
+
+  /// Waits for the device to answer everything sent so far, like [MidiDriver::flush].
+  pub async fn flush(&self) -> Result<(), LumatoneMidiError> {
+    self.driver.flush().await
+  }
+
+  /// Sends the edits made while the device couldn't be reached (see [OfflineEdits]), and
+  /// forgets them once the device has accepted every one. If any fails, they're all kept,
+  /// so they can be sent again later.
+  pub async fn replay_edits(&self, edits: &mut OfflineEdits) -> Result<(), LumatoneMidiError> {
+    let commands = edits.commands();
+    if commands.is_empty() {
+      return Ok(());
+    }
+    log::info!("replaying {} offline edits", edits.len());
+    let results = self.driver.send_all(commands, None).await;
+    if let Some(err) = results.into_iter().find_map(Result::err) {
+      return Err(err);
+    }
+    edits.clear();
+    Ok(())
+  }
+
+  /// Reconnects after the connection to the device was lost, e.g. when the computer woke
+  /// from sleep. The old driver is shut down, detection is run again (trying the cached
+  /// ports first, as with [Client::connect]), and then the `edits` made in the meantime are
+  /// replayed with [Client::replay_edits].
+  ///
+  /// Only failing to connect makes this fail. If replaying fails, the new client is still
+  /// returned, and the edits are left in `edits` to be replayed again.
+  pub async fn reconnect(self, edits: &mut OfflineEdits) -> Result<Client, LumatoneMidiError> {
+    self.reconnect_using(edits, Client::connect()).await
+  }
+
+  /// Like [Client::reconnect], but connects with `connect` instead of running detection.
+  pub async fn reconnect_using(
+    self,
+    edits: &mut OfflineEdits,
+    connect: impl Future<Output = Result<Client, LumatoneMidiError>>,
+  ) -> Result<Client, LumatoneMidiError> {
+    if let Err(err) = self.close().await {
+      log::debug!("error closing the lost connection: {err}");
+    }
+    let client = connect.await?;
+    if let Err(err) = client.replay_edits(edits).await {
+      log::warn!("unable to replay {} offline edits: {err}", edits.len());
+    }
+    Ok(client)
+  }
+
+  /// Shuts the driver down and waits for its task to finish.
+  pub async fn close(self) -> Result<(), LumatoneMidiError> {
+    self.driver.done().await?;
+    self
+      .driver_task
+      .await
+      .map_err(|e| LumatoneMidiError::DeviceConnectionError(format!("driver task failed: {e}")))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::Ordering;
+  use std::sync::{Arc, Mutex};
+
+  use super::{ApplyOptions, Client, FirmwareVersion};
+  use crate::keymap::ltn::{ApplyParts, KeyDefinition, LumatoneKeyMap};
+  use crate::keymap::offline::OfflineEdits;
+  use crate::midi::{
+    commands::set_key_color,
+    constants::{
+      key_loc_unchecked, BoardIndex, CommandId, HardwareModel, LumatoneKeyFunction, MidiChannel,
+      RGBColor, ResponseStatusCode,
+    },
+    driver::MidiDriverConfig,
+    error::LumatoneMidiError,
+    mock::{reply_with_status, MockDevice},
+    sysex::{create_sysex, message_command_id, BOARD_IND},
+  };
+
+  /// A device whose keys are all LumaTouch keys on channel 2, with the note and color
+  /// depending on the board and key index. Board 4 has old firmware that leaves out
+  /// the last key, and board 5 doesn't answer key type requests.
+  fn keyboard_device() -> MockDevice {
+    MockDevice::new(Box::new(|msg| {
+      use CommandId::*;
+      let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+      let command = message_command_id(msg).unwrap();
+      let n = board as u8;
+      let key_count = if board == BoardIndex::Octave4 { 55 } else { 56 };
+      let nibbles = |value: u8| [value >> 4, value & 0xf].repeat(key_count);
+      let data = match command {
+        GetRedLedConfig => nibbles(n * 10),
+        GetGreenLedConfig => nibbles(0x80),
+        GetBlueLedConfig => nibbles(0xff),
+        GetChannelConfig => vec![1; key_count],
+        GetNoteConfig => (0..key_count as u8).map(|k| k + n).collect(),
+        GetKeytypeConfig if board == BoardIndex::Octave5 => {
+          return Some(reply_with_status(msg, ResponseStatusCode::Nack))
+        }
+        GetKeytypeConfig => vec![(1 << 4) | 3; key_count],
+        GetFirmwareRevision => vec![1, 2, 3],
+        _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+      };
+      let canned = create_sysex(board, command, data);
+      Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+    }))
+  }
+
+  /// A device with `key_count` keys on every board, which answers serial number requests
+  /// with `serial`, or without one like early firmware.
+  fn firmware_device(key_count: usize, serial: Option<[u8; 6]>) -> MockDevice {
+    MockDevice::new(Box::new(move |msg| {
+      use CommandId::*;
+      let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+      let command = message_command_id(msg).unwrap();
+      let data = match (command, serial) {
+        (GetSerialIdentity, Some(serial)) => serial.to_vec(),
+        (GetRedLedConfig | GetGreenLedConfig | GetBlueLedConfig, _) => vec![0; key_count * 2],
+        (GetChannelConfig | GetNoteConfig, _) => vec![0; key_count],
+        (GetKeytypeConfig, _) => vec![1; key_count],
+        _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+      };
+      let canned = create_sysex(board, command, data);
+      Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+    }))
+  }
+
+  #[tokio::test]
+  async fn test_identify_firmware_generations() {
+    let serial = [1, 2, 3, 4, 5, 6];
+    let client = Client::with_transport(
+      Box::new(firmware_device(56, Some(serial))),
+      MidiDriverConfig::default(),
+    );
+    assert_eq!(client.model(), None);
+    let info = client.info().await.unwrap();
+    assert_eq!(info.serial_id, Some(serial));
+    assert_eq!(info.model, HardwareModel::default());
+    assert_eq!(client.read_keymap().await.unwrap().keys().count(), 5 * 56);
+    client.close().await.unwrap();
+
+    let client = Client::with_transport(
+      Box::new(firmware_device(55, None)),
+      MidiDriverConfig::default(),
+    );
+    let model = client.identify().await.unwrap();
+    assert_eq!(
+      model,
+      HardwareModel {
+        key_count_per_board: 55,
+        has_serial: false
+      }
+    );
+    assert_eq!(client.model(), Some(model));
+    let keymap = client.read_keymap().await.unwrap();
+    assert_eq!(keymap.keys().count(), 5 * 55);
+    assert_eq!(keymap.check_hardware_compat_for(&model), Ok(()));
+    client.close().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_info_and_ping() {
+    let client = Client::with_transport(Box::new(keyboard_device()), MidiDriverConfig::default());
+    let info = client.info().await.unwrap();
+    assert_eq!(
+      info.firmware,
+      FirmwareVersion {
+        major: 1,
+        minor: 2,
+        revision: 3
+      }
+    );
+    assert_eq!(info.firmware.to_string(), "1.2.3");
+    // the mock echoes the zeroed serial id request, like early firmware
+    assert_eq!(info.serial_id, None);
+    assert_eq!(
+      info.model,
+      HardwareModel {
+        key_count_per_board: 56,
+        has_serial: false
+      }
+    );
+
+    assert!(client.ping().await.is_ok());
+    assert!(client.ping().await.is_ok());
+
+    // the counter wraps at the 21 bits a ping can carry
+    client.next_ping.store((1 << 21) - 1, Ordering::Relaxed);
+    assert!(client.ping().await.is_ok());
+    assert!(client.ping().await.is_ok());
+    client.close().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_read_keymap() {
+    let client = Client::with_transport(Box::new(keyboard_device()), MidiDriverConfig::default());
+    let errors = match client.read_keymap().await {
+      Err(LumatoneMidiError::BoardErrors(errors)) => errors,
+      other => panic!("expected board errors, got {other:?}"),
+    };
+    match errors.as_slice() {
+      [(BoardIndex::Octave5, LumatoneMidiError::CommandFailed { command, .. })] => {
+        assert_eq!(*command, CommandId::GetKeytypeConfig)
+      }
+      other => panic!("expected board 5's key types to fail, got {other:?}"),
+    }
+    client.close().await.unwrap();
+
+    let client = Client::with_transport(Box::new(keyboard_device()), MidiDriverConfig::default());
+    let keymap = client.read_board_keys(BoardIndex::Octave3).await.unwrap();
+    assert_eq!(keymap.len(), 56);
+    let (location, def) = &keymap[10];
+    assert_eq!(*location, key_loc_unchecked(3, 10));
+    assert_eq!(
+      *def,
+      KeyDefinition {
+        function: LumatoneKeyFunction::LumaTouch {
+          channel: MidiChannel::unchecked(2),
+          note_num: 13,
+          fader_up_is_null: true,
+        },
+        color: RGBColor(30, 0x80, 0xff),
+      }
+    );
+    let short = client.read_board_keys(BoardIndex::Octave4).await.unwrap();
+    assert_eq!(short.len(), 55);
+    client.close().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_read_keymap_keeps_colors_of_disabled_keys() {
+    // every key is disabled, but lit in a color that depends on its board
+    let device = MockDevice::new(Box::new(|msg| {
+      use CommandId::*;
+      let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+      let command = message_command_id(msg).unwrap();
+      let nibbles = |value: u8| [value >> 4, value & 0xf].repeat(56);
+      let data = match command {
+        GetRedLedConfig => nibbles(board as u8 * 0x20),
+        GetGreenLedConfig => nibbles(0x10),
+        GetBlueLedConfig => nibbles(0xc0),
+        GetChannelConfig => vec![0; 56],
+        GetNoteConfig => vec![0; 56],
+        GetKeytypeConfig => vec![4; 56],
+        _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+      };
+      let canned = create_sysex(board, command, data);
+      Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+    }));
+    let client = Client::with_transport(Box::new(device), MidiDriverConfig::default());
+    let keymap = client.read_keymap().await.unwrap();
+    client.close().await.unwrap();
+
+    assert_eq!(keymap.keys().count(), 5 * 56);
+    assert_eq!(
+      keymap.get_key(key_loc_unchecked(3, 20)),
+      Some(&KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor(0x60, 0x10, 0xc0),
+      })
+    );
+    assert!(keymap.to_midi_commands().contains(&set_key_color(
+      key_loc_unchecked(5, 0),
+      RGBColor(0xa0, 0x10, 0xc0)
+    )));
+  }
+
+  #[tokio::test]
+  async fn test_apply_keymap_scales_colors() {
+    let mut keymap = LumatoneKeyMap::new();
+    let location = key_loc_unchecked(1, 0);
+    keymap.set_key(
+      location,
+      KeyDefinition {
+        function: LumatoneKeyFunction::Disabled,
+        color: RGBColor(200, 100, 0),
+      },
+    );
+    let client =
+      Client::with_transport(Box::new(MockDevice::acking()), MidiDriverConfig::default());
+    let opts = ApplyOptions {
+      brightness: 0.5,
+      ..Default::default()
+    };
+    let report = client.apply_keymap(&keymap, &opts).await;
+    assert!(report.is_success());
+    let colors: Vec<_> = report
+      .results
+      .iter()
+      .filter_map(|(c, _)| match c {
+        crate::midi::commands::Command::SetKeyColor { color, .. } => Some(*color),
+        _ => None,
+      })
+      .collect();
+    assert!(colors.contains(&RGBColor(200, 100, 0).scaled(0.5)));
+    assert!(!colors.contains(&RGBColor(200, 100, 0)));
+    client.close().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_send_preset_retries_failures_once() {
+    let mut keymap = LumatoneKeyMap::new();
+    for (key, color) in [
+      (0, RGBColor::red()),
+      (1, RGBColor::green()),
+      (2, RGBColor::blue()),
+    ] {
+      keymap.set_key(
+        key_loc_unchecked(2, key),
+        KeyDefinition {
+          function: LumatoneKeyFunction::Disabled,
+          color,
+        },
+      );
+    }
+
+    // key 1's color is refused once, and key 2's every time
+    let flaky = set_key_color(key_loc_unchecked(2, 1), RGBColor::green()).to_sysex_message();
+    let broken = set_key_color(key_loc_unchecked(2, 2), RGBColor::blue()).to_sysex_message();
+    let mut refused_flaky = false;
+    let device = MockDevice::new(Box::new(move |msg| {
+      let refuse = msg == broken || (msg == flaky && !std::mem::replace(&mut refused_flaky, true));
+      let status = if refuse {
+        ResponseStatusCode::Nack
+      } else {
+        ResponseStatusCode::Ack
+      };
+      Some(reply_with_status(msg, status))
+    }));
+    let client = Client::with_transport(Box::new(device), MidiDriverConfig::default());
+    let opts = ApplyOptions {
+      parts: ApplyParts::keys(),
+      ..Default::default()
+    };
+
+    let report = client.send_preset(&keymap, &opts).await;
+    client.close().await.unwrap();
+    assert_eq!(report.results.len(), 6);
+    assert_eq!(report.retried, 2);
+    assert!(!report.is_success());
+    assert_eq!(report.summary(), "5 commands ok, 1 failed");
+    let failed: Vec<_> = report.failures().map(|(c, _)| c.key_location()).collect();
+    assert_eq!(failed, vec![Some(&key_loc_unchecked(2, 2))]);
+  }
+
+  #[tokio::test]
+  async fn test_reconnect_replays_offline_edits() {
+    let location = key_loc_unchecked(1, 3);
+    let before = KeyDefinition {
+      function: LumatoneKeyFunction::Disabled,
+      color: RGBColor::red(),
+    };
+    let mut edits = OfflineEdits::new();
+    edits.record(
+      location,
+      Some(&before),
+      KeyDefinition {
+        color: RGBColor::blue(),
+        ..before.clone()
+      },
+    );
+
+    // the first device to come back refuses everything, so the edit is kept
+    let lost = Client::with_transport(Box::new(MockDevice::acking()), MidiDriverConfig::default());
+    let refusing = MockDevice::new(Box::new(|msg| {
+      Some(reply_with_status(msg, ResponseStatusCode::Nack))
+    }));
+    let client = lost
+      .reconnect_using(&mut edits, async {
+        Ok(Client::with_transport(
+          Box::new(refusing),
+          MidiDriverConfig::default(),
+        ))
+      })
+      .await
+      .unwrap();
+    assert_eq!(edits.len(), 1);
+
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg| {
+      recorded.lock().unwrap().push(msg.to_vec());
+      Some(reply_with_status(msg, ResponseStatusCode::Ack))
+    }));
+    let client = client
+      .reconnect_using(&mut edits, async {
+        Ok(Client::with_transport(
+          Box::new(device),
+          MidiDriverConfig::default(),
+        ))
+      })
+      .await
+      .unwrap();
+    client.close().await.unwrap();
+    assert!(edits.is_empty());
+    assert_eq!(
+      *sent.lock().unwrap(),
+      vec![set_key_color(location, RGBColor::blue()).to_sysex_message()]
+    );
+
+    // failing to connect is an error
+    let client =
+      Client::with_transport(Box::new(MockDevice::acking()), MidiDriverConfig::default());
+    let res = client
+      .reconnect_using(&mut edits, async {
+        Err(LumatoneMidiError::DeviceDetectionFailed("gone".to_string()))
+      })
+      .await;
+    assert!(res.is_err());
+  }
+}
@@ -1,25 +1,38 @@
 #![allow(dead_code)]
 
 use std::fmt::Debug;
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
 
 use super::{
   constants::{
-    BoardIndex, CommandId, LumatoneKeyFunction, LumatoneKeyLocation, MidiChannel, PresetNumber,
-    RGBColor, TEST_ECHO,
+    deserialize_midi_channel, BoardIndex, CommandId, LumatoneKeyFunction, LumatoneKeyIndex,
+    LumatoneKeyLocation, MidiChannel, PresetNumber, RGBColor, TEST_ECHO,
   },
+  error::LumatoneMidiError,
   sysex::{
     create_extended_key_color_sysex, create_extended_macro_color_sysex,
-    create_single_arg_server_sysex, create_sysex, create_sysex_toggle, create_table_sysex,
-    create_zero_arg_server_sysex, create_zero_arg_sysex, reverse_table, EncodedSysex, SysexTable,
-    VelocityIntervalTable,
+    create_single_arg_server_sysex, create_sysex, create_sysex_into, create_sysex_toggle,
+    create_table_sysex, create_zero_arg_server_sysex, create_zero_arg_sysex, is_lumatone_message,
+    message_command_id, reverse_table, strip_sysex_markers, velocity_intervals_serde, EncodedSysex,
+    SysexTable, VelocityIntervalTable, BOARD_IND, MSG_STATUS,
   },
 };
 
-#[derive(Debug, Clone, PartialEq)]
+/// Commands serialize with serde's default enum representation, like
+/// [Response](super::responses::Response)s: `"GetSerialId"` for commands without
+/// parameters, `{"Ping": 5}` for those with one, and e.g.
+/// `{"SetKeyColor": {"location": {"board": 1, "key": 3}, "color": "#ff0000"}}` for the rest.
+/// Key functions are flattened into [Command::SetKeyFunction], e.g.
+/// `{"SetKeyFunction": {"location": {"board": 1, "key": 3}, "function": "note", "note": 60}}`.
+/// See [from_json_lines] for reading a script of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Command {
   /// Echo the payload, 0x00-0x7f, for use in connection monitoring
   Ping(u32),
   /// Send a single key's functionctional configuration
+  #[serde(with = "flat_key_function")]
   SetKeyFunction {
     location: LumatoneKeyLocation,
     function: LumatoneKeyFunction,
@@ -33,6 +46,8 @@ pub enum Command {
   SaveProgram(PresetNumber),
   /// Send expression pedal sensitivity
   SetExpressionPedalSensitivity(u8),
+  /// Read back the expression pedal sensitivity
+  GetExpressionPedalSensitivity,
   /// Set mod wheel sensitivity
   SetModWheelSensitivity(u8),
   /// Set pitch wheel sensitivity
@@ -65,7 +80,9 @@ pub enum Command {
   /// Adjust the Lumatouch table, a 128 byte array with value of 127 being a key fully pressed
   SetLumatouchConfig(Box<SysexTable>),
   /// Set the velocity interval table, 127 12-bit values
-  SetVelocityIntervals(Box<VelocityIntervalTable>),
+  SetVelocityIntervals(
+    #[serde(with = "velocity_intervals_serde::boxed")] Box<VelocityIntervalTable>,
+  ),
 
   /// Set abs. distance from max value to trigger CA-004 submodule key events, ranging from 0x00 to 0xFE
   SetKeyMaximumThreshold {
@@ -180,9 +197,13 @@ pub enum Command {
 
   /// Set the MIDI channels for peripheral controllers
   SetPeripheralChannels {
+    #[serde(deserialize_with = "deserialize_midi_channel")]
     pitch_wheel: MidiChannel,
+    #[serde(deserialize_with = "deserialize_midi_channel")]
     mod_wheel: MidiChannel,
+    #[serde(deserialize_with = "deserialize_midi_channel")]
     expression: MidiChannel,
+    #[serde(deserialize_with = "deserialize_midi_channel")]
     sustain: MidiChannel,
   },
   /// Retrieve the MIDI channels for peripheral controllers
@@ -204,6 +225,7 @@ impl Command {
       SetKeyColor { .. } => CommandId::SetKeyColour,
       SaveProgram(_) => CommandId::SaveProgram,
       SetExpressionPedalSensitivity(_) => CommandId::SetFootControllerSensitivity,
+      GetExpressionPedalSensitivity => CommandId::GetExpressionPedalSensitivity,
       SetModWheelSensitivity(_) => CommandId::SetModWheelSensitivity,
       SetPitchWheelSensitivity(_) => CommandId::SetPitchWheelSensitivity,
 
@@ -284,7 +306,147 @@ impl Command {
     }
   }
 
+  /// Returns the key targeted by this command, if it's a per-key command.
+  pub fn key_location(&self) -> Option<&LumatoneKeyLocation> {
+    match self {
+      Command::SetKeyFunction { location, .. } | Command::SetKeyColor { location, .. } => {
+        Some(location)
+      }
+      _ => None,
+    }
+  }
+
+  /// Returns the board targeted by this command, if it's a per-key or per-board command.
+  /// Commands for the keyboard as a whole return `None`.
+  pub fn board_index(&self) -> Option<BoardIndex> {
+    use Command::*;
+    match self {
+      SetKeyFunction { location, .. } | SetKeyColor { location, .. } => {
+        Some(location.board_index())
+      }
+      SetKeyMaximumThreshold { board_index, .. } | SetKeyMinimumThreshold { board_index, .. } => {
+        Some(*board_index)
+      }
+      SetKeyFaderSensitivity(board, _)
+      | SetKeyAftertouchSensitivity(board, _)
+      | SetCCActiveThreshold(board, _)
+      | ResetBoardThresholds(board)
+      | SetAftertouchTriggerDelay(board, _)
+      | GetAftertouchTriggerDelay(board)
+      | SetLumatouchNoteOffDelay(board, _)
+      | GetLumatouchNoteOffDelay(board)
+      | GetRedLEDConfig(board)
+      | GetGreenLEDConfig(board)
+      | GetBlueLEDConfig(board)
+      | GetMidiChannelConfig(board)
+      | GetNoteConfig(board)
+      | GetKeyTypeConfig(board)
+      | GetMaxFaderThreshold(board)
+      | GetMinFaderThreshold(board)
+      | GetMaxAftertouchThreshold(board)
+      | GetKeyValidity(board)
+      | GetFaderTypeConfig(board)
+      | GetBoardThresholdValues(board)
+      | GetBoardSensitivityValues(board)
+      | EnableKeySampling(board, _) => Some(*board),
+
+      Ping(_)
+      | SaveProgram(_)
+      | SetExpressionPedalSensitivity(_)
+      | GetExpressionPedalSensitivity
+      | SetModWheelSensitivity(_)
+      | SetPitchWheelSensitivity(_)
+      | InvertFootController(_)
+      | InvertSustainPedal(_)
+      | SetLightOnKeystrokes(_)
+      | SetAftertouchEnabled(_)
+      | EnableDemoMode(_)
+      | EnablePitchModWheelCalibrationMode(_)
+      | EnableExpressionPedalCalibrationMode(_)
+      | SetMacroButtonActiveColor(_)
+      | SetMacroButtonInactiveColor(_)
+      | SetVelocityConfig(_)
+      | SetFaderConfig(_)
+      | SetAftertouchConfig(_)
+      | SetLumatouchConfig(_)
+      | SetVelocityIntervals(_)
+      | SetPitchWheelZeroThreshold(_)
+      | GetVelocityConfig
+      | GetVelocityIntervalConfig
+      | GetFaderConfig
+      | GetAftertouchConfig
+      | GetLumatouchConfig
+      | GetSerialId
+      | GetFirmwareRevision
+      | StartAftertouchCalibration
+      | StartKeyCalibration
+      | SaveVelocityConfig
+      | ResetVelocityConfig
+      | SaveFaderConfig
+      | ResetFaderConfig
+      | SaveAftertouchConfig
+      | ResetAftertouchConfig
+      | SaveLumatouchConfig
+      | ResetLumatouchConfig
+      | ResetWheelThresholds
+      | ResetExpressionPedalBounds
+      | SetPeripheralChannels { .. }
+      | GetPeripheralChannels
+      | SetExpressionPedalADCThreshold(_)
+      | GetExpressionPedalADCThreshold => None,
+    }
+  }
+
+  /// Returns true if both commands are key commands of the same kind for the same key,
+  /// meaning that sending `other` after `self` will overwrite the effect of `self`.
+  pub fn same_target(&self, other: &Command) -> bool {
+    match (self.key_location(), other.key_location()) {
+      (Some(a), Some(b)) => a == b && self.command_id() == other.command_id(),
+      _ => false,
+    }
+  }
+
   pub fn to_sysex_message(&self) -> EncodedSysex {
+    let mut buf = Vec::new();
+    self.encode_into(&mut buf);
+    buf
+  }
+
+  /// Clears `buf` and writes the encoded sysex message for this command into it.
+  ///
+  /// Reusing one buffer avoids an allocation per message. The key commands
+  /// ([Command::SetKeyColor] and [Command::SetKeyFunction]) are encoded without
+  /// allocating at all, since they're the ones that get sent in bulk.
+  pub fn encode_into(&self, buf: &mut Vec<u8>) {
+    use Command::*;
+    match self {
+      SetKeyFunction { location, function } => {
+        let data = [
+          location.key_index().into(),
+          function.note_or_cc_num(),
+          function.midi_channel_byte(),
+          function.type_code(),
+        ];
+        create_sysex_into(buf, location.board_index(), CommandId::ChangeKeyNote, &data);
+      }
+
+      SetKeyColor { location, color } => {
+        let mut data = [0u8; 7];
+        data[0] = location.key_index().into();
+        data[1..].copy_from_slice(&color.to_byte_array());
+        create_sysex_into(buf, location.board_index(), CommandId::SetKeyColour, &data);
+      }
+
+      _ => {
+        let msg = self.encode_allocating();
+        buf.clear();
+        buf.extend_from_slice(&msg);
+      }
+    }
+  }
+
+  /// Encodes commands that aren't handled directly by [Command::encode_into].
+  fn encode_allocating(&self) -> EncodedSysex {
     use Command::*;
     match self {
       Ping(value) => encode_ping(*value),
@@ -301,6 +463,8 @@ impl Command {
         create_single_arg_server_sysex(self.command_id(), *value)
       }
 
+      GetExpressionPedalSensitivity => create_zero_arg_server_sysex(self.command_id()),
+
       SetModWheelSensitivity(value) => {
         create_single_arg_server_sysex(self.command_id(), (*value).clamp(1, 0x7f))
       }
@@ -509,6 +673,177 @@ impl Command {
       GetExpressionPedalADCThreshold => create_zero_arg_server_sysex(self.command_id()),
     }
   }
+
+  /// The inverse of [Command::to_sysex_message], e.g. for a software device that needs to
+  /// know what it's being asked to do.
+  ///
+  /// Bytes after the data a command needs are ignored, so the zero padding that
+  /// [create_sysex] adds to short messages doesn't matter. Values that the encoder clamps or
+  /// masks (like the low bit of key thresholds) decode to what was actually sent.
+  pub fn from_sysex_message(msg: &[u8]) -> Result<Command, LumatoneMidiError> {
+    use Command::*;
+    let msg = strip_sysex_markers(msg);
+    if !is_lumatone_message(msg) {
+      return Err(LumatoneMidiError::NotLumatoneMessage(msg.to_vec()));
+    }
+    let cmd_id = message_command_id(msg)?;
+    let board = BoardIndex::try_from(msg[BOARD_IND])?;
+    let data = |len: usize| command_data(msg, cmd_id, len);
+    let toggle = || data(1).map(|d| d[0] != 0);
+    let table = || data(128).and_then(SysexTable::try_from).map(Box::new);
+
+    let cmd = match cmd_id {
+      CommandId::LumaPing => {
+        let d = data(4)?;
+        Ping(((d[1] as u32) << 14) | ((d[2] as u32) << 7) | d[3] as u32)
+      }
+      CommandId::ChangeKeyNote => {
+        let d = data(4)?;
+        let channel = MidiChannel::try_from_zero_indexed(d[2])?;
+        let function =
+          LumatoneKeyFunction::from_type_code(d[3], channel, d[1]).ok_or_else(|| {
+            LumatoneMidiError::MessagePayloadInvalid(format!("unknown key type code {}", d[3]))
+          })?;
+        SetKeyFunction {
+          location: decode_key_location(board, d[0])?,
+          function,
+        }
+      }
+      CommandId::SetKeyColour => {
+        let d = data(7)?;
+        SetKeyColor {
+          location: decode_key_location(board, d[0])?,
+          color: decode_color(&d[1..]),
+        }
+      }
+      CommandId::SaveProgram => {
+        let n = data(1)?[0];
+        SaveProgram(PresetNumber::new(n).ok_or(LumatoneMidiError::InvalidPresetIndex(n))?)
+      }
+      CommandId::SetFootControllerSensitivity => SetExpressionPedalSensitivity(data(1)?[0]),
+      CommandId::GetExpressionPedalSensitivity => GetExpressionPedalSensitivity,
+      CommandId::SetModWheelSensitivity => SetModWheelSensitivity(data(1)?[0]),
+      CommandId::SetPitchWheelSensitivity => {
+        let d = data(2)?;
+        SetPitchWheelSensitivity(((d[0] as u16) << 7) | d[1] as u16)
+      }
+      CommandId::InvertFootController => InvertFootController(toggle()?),
+      CommandId::InvertSustainPedal => InvertSustainPedal(toggle()?),
+      CommandId::SetLightOnKeystrokes => SetLightOnKeystrokes(toggle()?),
+      CommandId::SetAftertouchFlag => SetAftertouchEnabled(toggle()?),
+      CommandId::DemoMode => EnableDemoMode(toggle()?),
+      CommandId::CalibratePitchModWheel => EnablePitchModWheelCalibrationMode(toggle()?),
+      CommandId::CalibrateExpressionPedal => EnableExpressionPedalCalibrationMode(toggle()?),
+      CommandId::MacrobuttonColourOn => SetMacroButtonActiveColor(decode_color(data(6)?)),
+      CommandId::MacrobuttonColourOff => SetMacroButtonInactiveColor(decode_color(data(6)?)),
+
+      // sent in reverse, see encode_allocating
+      CommandId::SetVelocityConfig => SetVelocityConfig(Box::new(reverse_table(&*table()?))),
+      CommandId::SetFaderConfig => SetFaderConfig(table()?),
+      CommandId::SetAftertouchConfig => SetAftertouchConfig(table()?),
+      CommandId::SetLumatouchConfig => SetLumatouchConfig(table()?),
+      CommandId::SetVelocityIntervals => {
+        let mut intervals: VelocityIntervalTable = [0; 127];
+        for (value, pair) in intervals.iter_mut().zip(data(254)?.chunks_exact(2)) {
+          *value = ((pair[0] as u16) << 6) | pair[1] as u16;
+        }
+        SetVelocityIntervals(Box::new(intervals))
+      }
+
+      CommandId::SetKeyMaxThreshold => {
+        let d = data(4)?;
+        SetKeyMaximumThreshold {
+          board_index: board,
+          max_threshold: decode_8bit(&d[0..2]),
+          aftertouch_max: decode_8bit(&d[2..4]),
+        }
+      }
+      CommandId::SetKeyMinThreshold => {
+        let d = data(4)?;
+        SetKeyMinimumThreshold {
+          board_index: board,
+          threshold_high: decode_8bit(&d[0..2]),
+          threshold_low: decode_8bit(&d[2..4]),
+        }
+      }
+      CommandId::SetPitchWheelCenterThreshold => SetPitchWheelZeroThreshold(data(1)?[0]),
+      CommandId::SetKeyFaderSensitivity => SetKeyFaderSensitivity(board, decode_8bit(data(2)?)),
+      CommandId::SetKeyAftertouchSensitivity => {
+        SetKeyAftertouchSensitivity(board, decode_8bit(data(2)?))
+      }
+      CommandId::SetCCActiveThreshold => SetCCActiveThreshold(board, decode_8bit(data(2)?)),
+      CommandId::ResetBoardThresholds => ResetBoardThresholds(board),
+      CommandId::SetAftertouchTriggerDelay => {
+        SetAftertouchTriggerDelay(board, decode_8bit(data(2)?))
+      }
+      CommandId::GetAftertouchTriggerDelay => GetAftertouchTriggerDelay(board),
+      CommandId::SetLumatouchNoteOffDelay => {
+        SetLumatouchNoteOffDelay(board, decode_12bit(data(3)?))
+      }
+      CommandId::GetLumatouchNoteOffDelay => GetLumatouchNoteOffDelay(board),
+
+      CommandId::GetRedLedConfig => GetRedLEDConfig(board),
+      CommandId::GetGreenLedConfig => GetGreenLEDConfig(board),
+      CommandId::GetBlueLedConfig => GetBlueLEDConfig(board),
+      CommandId::GetChannelConfig => GetMidiChannelConfig(board),
+      CommandId::GetNoteConfig => GetNoteConfig(board),
+      CommandId::GetKeytypeConfig => GetKeyTypeConfig(board),
+      CommandId::GetMaxThreshold => GetMaxFaderThreshold(board),
+      CommandId::GetMinThreshold => GetMinFaderThreshold(board),
+      CommandId::GetAftertouchMax => GetMaxAftertouchThreshold(board),
+      CommandId::GetKeyValidity => GetKeyValidity(board),
+      CommandId::GetFaderTypeConfiguration => GetFaderTypeConfig(board),
+      CommandId::GetBoardThresholdValues => GetBoardThresholdValues(board),
+      CommandId::GetBoardSensitivityValues => GetBoardSensitivityValues(board),
+
+      CommandId::GetVelocityConfig => GetVelocityConfig,
+      CommandId::GetVelocityIntervals => GetVelocityIntervalConfig,
+      CommandId::GetFaderConfig => GetFaderConfig,
+      CommandId::GetAftertouchConfig => GetAftertouchConfig,
+      CommandId::GetLumatouchConfig => GetLumatouchConfig,
+      CommandId::GetSerialIdentity => GetSerialId,
+      CommandId::GetFirmwareRevision => GetFirmwareRevision,
+      CommandId::CalibrateAftertouch => StartAftertouchCalibration,
+      CommandId::CalibrateKeys => StartKeyCalibration,
+
+      CommandId::SaveVelocityConfig => SaveVelocityConfig,
+      CommandId::ResetVelocityConfig => ResetVelocityConfig,
+      CommandId::SaveFaderConfig => SaveFaderConfig,
+      CommandId::ResetFaderConfig => ResetFaderConfig,
+      CommandId::SaveAftertouchConfig => SaveAftertouchConfig,
+      CommandId::ResetAftertouchConfig => ResetAftertouchConfig,
+      CommandId::SaveLumatouchConfig => SaveLumatouchConfig,
+      CommandId::ResetLumatouchConfig => ResetLumatouchConfig,
+      CommandId::ResetWheelsThreshold => ResetWheelThresholds,
+      CommandId::ResetExpressionPedalBounds => ResetExpressionPedalBounds,
+
+      CommandId::SetKeySampling => EnableKeySampling(board, toggle()?),
+
+      CommandId::SetPeripheralChannels => {
+        let d = data(4)?;
+        SetPeripheralChannels {
+          pitch_wheel: MidiChannel::try_from_zero_indexed(d[0])?,
+          mod_wheel: MidiChannel::try_from_zero_indexed(d[1])?,
+          expression: MidiChannel::try_from_zero_indexed(d[2])?,
+          sustain: MidiChannel::try_from_zero_indexed(d[3])?,
+        }
+      }
+      CommandId::GetPeripheralChannels => GetPeripheralChannels,
+
+      CommandId::SetExpressionPedalThreshold => {
+        SetExpressionPedalADCThreshold(decode_12bit(data(3)?))
+      }
+      CommandId::GetExpressionPedalThreshold => GetExpressionPedalADCThreshold,
+
+      CommandId::PeripheralCalbrationData => {
+        return Err(LumatoneMidiError::UnsupportedCommandId(
+          cmd_id,
+          "only the device sends this".to_string(),
+        ))
+      }
+    };
+    Ok(cmd)
+  }
 }
 
 impl std::fmt::Display for Command {
@@ -523,6 +858,7 @@ impl std::fmt::Display for Command {
       Command::SetExpressionPedalSensitivity(val) => {
         write!(f, "SetExpressionPedalSensitivity({val})")
       }
+      Command::GetExpressionPedalSensitivity => write!(f, "GetExpressionPedalSensitivity"),
       Command::SetModWheelSensitivity(val) => write!(f, "SetModWheelSensitivity({val})"),
       Command::SetPitchWheelSensitivity(val) => write!(f, "SetPitchWheelSensitivity({val})"),
       Command::InvertFootController(val) => write!(f, "InvertFootController({val})"),
@@ -646,6 +982,94 @@ pub fn set_key_function(location: LumatoneKeyLocation, function: LumatoneKeyFunc
   Command::SetKeyFunction { location, function }
 }
 
+/// Returns a [Command::SetKeyColor] for each key on `board`, in key index order, where
+/// `colors[i]` is the color of key `i`.
+pub fn set_board_colors(board: BoardIndex, colors: &[RGBColor; 56]) -> Vec<Command> {
+  LumatoneKeyIndex::all()
+    .into_iter()
+    .zip(colors)
+    .map(|(index, color)| set_key_color(LumatoneKeyLocation(board, index), *color))
+    .collect()
+}
+
+/// Returns the commands that put the key at `location` back to a blank state: disabled,
+/// on channel 1, and unlit. These match what a .ltn file has for keys it doesn't define
+/// (`KTyp` 4, `Chan` 1, `Col` 000000).
+pub fn reset_key(location: LumatoneKeyLocation) -> Vec<Command> {
+  vec![
+    set_key_function(location, LumatoneKeyFunction::Disabled),
+    set_key_color(location, RGBColor(0, 0, 0)),
+  ]
+}
+
+// endregion
+
+// region: JSON
+
+/// Serializes [Command::SetKeyFunction] with its key function's fields alongside the
+/// location, since serde can't derive `#[serde(flatten)]` for fields of enum variants.
+mod flat_key_function {
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+  use crate::midi::constants::{LumatoneKeyFunction, LumatoneKeyLocation};
+
+  #[derive(Serialize)]
+  struct FieldsRef<'a> {
+    location: &'a LumatoneKeyLocation,
+    #[serde(flatten)]
+    function: &'a LumatoneKeyFunction,
+  }
+
+  #[derive(Deserialize)]
+  struct Fields {
+    location: LumatoneKeyLocation,
+    #[serde(flatten)]
+    function: LumatoneKeyFunction,
+  }
+
+  pub fn serialize<S: Serializer>(
+    location: &LumatoneKeyLocation,
+    function: &LumatoneKeyFunction,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    FieldsRef { location, function }.serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<(LumatoneKeyLocation, LumatoneKeyFunction), D::Error> {
+    let Fields { location, function } = Fields::deserialize(deserializer)?;
+    Ok((location, function))
+  }
+}
+
+/// Returns `command` as a single line of JSON, without a trailing newline, in the form
+/// [from_json_lines] reads.
+pub fn to_json_line(command: &Command) -> String {
+  serde_json::to_string(command).expect("failed to serialize command")
+}
+
+/// Reads newline-delimited JSON commands, one per line, in the form described on
+/// [Command]. Blank lines are skipped. Stops at the first line that can't be read or
+/// parsed, with a [LumatoneMidiError::InvalidCommandJson] saying which line it was and why,
+/// e.g. because a channel is 0 or a color isn't in `#rrggbb` form.
+pub fn from_json_lines(reader: impl BufRead) -> Result<Vec<Command>, LumatoneMidiError> {
+  let mut commands = vec![];
+  for (i, line) in reader.lines().enumerate() {
+    let invalid = |message: String| LumatoneMidiError::InvalidCommandJson {
+      line: i + 1,
+      message,
+    };
+    let line = line.map_err(|err| invalid(err.to_string()))?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let command = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+    commands.push(command);
+  }
+  Ok(commands)
+}
+
 // endregion
 
 // region: Sysex Encoders
@@ -719,3 +1143,582 @@ fn encode_set_key_sensitivity(board_index: BoardIndex, cmd: CommandId, value: u8
 }
 
 // endregion
+
+// region: Sysex Decoders
+
+/// Returns the first `len` data bytes of a command message, which has its data right after
+/// the command id. (Responses have a status byte there instead.)
+fn command_data(msg: &[u8], cmd: CommandId, len: usize) -> Result<&[u8], LumatoneMidiError> {
+  let data = msg.get(MSG_STATUS..).unwrap_or(&[]);
+  if data.len() < len {
+    return Err(LumatoneMidiError::MessagePayloadTooShort {
+      command: cmd,
+      expected: len,
+      actual: data.len(),
+    });
+  }
+  Ok(&data[..len])
+}
+
+fn decode_key_location(
+  board: BoardIndex,
+  key_index: u8,
+) -> Result<LumatoneKeyLocation, LumatoneMidiError> {
+  Ok(LumatoneKeyLocation(
+    board,
+    LumatoneKeyIndex::try_from(key_index)?,
+  ))
+}
+
+/// The inverse of [RGBColor::to_bytes].
+fn decode_color(nibbles: &[u8]) -> RGBColor {
+  RGBColor(
+    decode_8bit(&nibbles[0..2]),
+    decode_8bit(&nibbles[2..4]),
+    decode_8bit(&nibbles[4..6]),
+  )
+}
+
+/// Joins a high and a low nibble.
+fn decode_8bit(nibbles: &[u8]) -> u8 {
+  (nibbles[0] << 4) | (nibbles[1] & 0xf)
+}
+
+/// Joins three nibbles, most significant first.
+fn decode_12bit(nibbles: &[u8]) -> u16 {
+  ((nibbles[0] as u16) << 8) | ((nibbles[1] as u16) << 4) | nibbles[2] as u16
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+  use num_traits::FromPrimitive;
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::{
+    encode_set_key_color, encode_set_key_function, from_json_lines, ping, reset_key,
+    set_board_colors, set_key_color, set_key_function, to_json_line, Command,
+  };
+  use crate::midi::constants::{
+    key_loc_unchecked, BoardIndex, CommandId, LumatoneKeyFunction, MidiChannel, PresetNumber,
+    RGBColor,
+  };
+  use crate::midi::error::LumatoneMidiError;
+  use crate::midi::sysex::{create_sysex, strip_sysex_markers, SysexTable, MSG_STATUS};
+
+  /// Command ids that the device only ever sends us, so there's no [Command] for them.
+  const RESPONSE_ONLY_COMMAND_IDS: &[CommandId] = &[CommandId::PeripheralCalbrationData];
+
+  /// One of every kind of [Command]. Add new commands here, or
+  /// `test_every_command_id_has_a_command` will complain.
+  fn one_of_each_command() -> Vec<Command> {
+    use Command::*;
+    let board = BoardIndex::Octave1;
+    let location = key_loc_unchecked(1, 0);
+    let table = Box::new(SysexTable::new([0; 128]));
+    let channel = MidiChannel::default();
+    vec![
+      Ping(1),
+      SetKeyFunction {
+        location,
+        function: LumatoneKeyFunction::Disabled,
+      },
+      SetKeyColor {
+        location,
+        color: RGBColor::red(),
+      },
+      SaveProgram(PresetNumber::new(1).unwrap()),
+      SetExpressionPedalSensitivity(0),
+      // the smallest values these encode without clamping
+      SetModWheelSensitivity(1),
+      SetPitchWheelSensitivity(1),
+      InvertFootController(true),
+      InvertSustainPedal(true),
+      SetLightOnKeystrokes(true),
+      SetAftertouchEnabled(true),
+      EnableDemoMode(true),
+      EnablePitchModWheelCalibrationMode(true),
+      EnableExpressionPedalCalibrationMode(true),
+      SetMacroButtonActiveColor(RGBColor::red()),
+      SetMacroButtonInactiveColor(RGBColor::red()),
+      SetVelocityConfig(table.clone()),
+      SetFaderConfig(table.clone()),
+      SetAftertouchConfig(table.clone()),
+      SetLumatouchConfig(table),
+      SetVelocityIntervals(Box::new([0; 127])),
+      SetKeyMaximumThreshold {
+        board_index: board,
+        max_threshold: 0,
+        aftertouch_max: 0,
+      },
+      SetKeyMinimumThreshold {
+        board_index: board,
+        threshold_high: 0,
+        threshold_low: 0,
+      },
+      SetPitchWheelZeroThreshold(0),
+      SetKeyFaderSensitivity(board, 0),
+      SetKeyAftertouchSensitivity(board, 0),
+      SetCCActiveThreshold(board, 0),
+      ResetBoardThresholds(board),
+      SetAftertouchTriggerDelay(board, 0),
+      GetAftertouchTriggerDelay(board),
+      SetLumatouchNoteOffDelay(board, 0),
+      GetLumatouchNoteOffDelay(board),
+      GetRedLEDConfig(board),
+      GetGreenLEDConfig(board),
+      GetBlueLEDConfig(board),
+      GetMidiChannelConfig(board),
+      GetNoteConfig(board),
+      GetKeyTypeConfig(board),
+      GetMaxFaderThreshold(board),
+      GetMinFaderThreshold(board),
+      GetMaxAftertouchThreshold(board),
+      GetKeyValidity(board),
+      GetFaderTypeConfig(board),
+      GetBoardThresholdValues(board),
+      GetBoardSensitivityValues(board),
+      GetVelocityConfig,
+      GetVelocityIntervalConfig,
+      GetFaderConfig,
+      GetAftertouchConfig,
+      GetLumatouchConfig,
+      GetSerialId,
+      GetFirmwareRevision,
+      StartAftertouchCalibration,
+      StartKeyCalibration,
+      SaveVelocityConfig,
+      ResetVelocityConfig,
+      SaveFaderConfig,
+      ResetFaderConfig,
+      SaveAftertouchConfig,
+      ResetAftertouchConfig,
+      SaveLumatouchConfig,
+      ResetLumatouchConfig,
+      ResetWheelThresholds,
+      ResetExpressionPedalBounds,
+      EnableKeySampling(board, true),
+      SetPeripheralChannels {
+        pitch_wheel: channel,
+        mod_wheel: channel,
+        expression: channel,
+        sustain: channel,
+      },
+      GetPeripheralChannels,
+      SetExpressionPedalADCThreshold(0),
+      GetExpressionPedalADCThreshold,
+      GetExpressionPedalSensitivity,
+    ]
+  }
+
+  #[test]
+  fn test_every_command_id_has_a_command() {
+    let covered: Vec<CommandId> = one_of_each_command()
+      .iter()
+      .map(|cmd| cmd.command_id())
+      .collect();
+    let all_ids = (0..=u8::MAX).filter_map(CommandId::from_u8);
+
+    let missing: Vec<CommandId> = all_ids
+      .filter(|id| !covered.contains(id) && !RESPONSE_ONLY_COMMAND_IDS.contains(id))
+      .collect();
+    assert!(
+      missing.is_empty(),
+      "no Command maps to these command ids: {missing:?}. \
+       Add one to one_of_each_command, or list the id in RESPONSE_ONLY_COMMAND_IDS"
+    );
+
+    let stale: Vec<&CommandId> = RESPONSE_ONLY_COMMAND_IDS
+      .iter()
+      .filter(|id| covered.contains(id))
+      .collect();
+    assert!(
+      stale.is_empty(),
+      "these command ids are listed as response-only, but have a Command: {stale:?}"
+    );
+  }
+
+  #[test]
+  fn test_board_index() {
+    use BoardIndex::*;
+    use Command::*;
+    let cases = vec![
+      (
+        set_key_color(key_loc_unchecked(4, 17), RGBColor::red()),
+        Some(Octave4),
+      ),
+      (
+        set_key_function(key_loc_unchecked(2, 0), LumatoneKeyFunction::Disabled),
+        Some(Octave2),
+      ),
+      (
+        SetKeyMaximumThreshold {
+          board_index: Octave3,
+          max_threshold: 0,
+          aftertouch_max: 0,
+        },
+        Some(Octave3),
+      ),
+      (
+        SetKeyMinimumThreshold {
+          board_index: Octave5,
+          threshold_high: 0,
+          threshold_low: 0,
+        },
+        Some(Octave5),
+      ),
+      (SetKeyFaderSensitivity(Octave1, 0), Some(Octave1)),
+      (ResetBoardThresholds(Octave2), Some(Octave2)),
+      (GetLumatouchNoteOffDelay(Octave3), Some(Octave3)),
+      (GetNoteConfig(Octave4), Some(Octave4)),
+      (GetBoardSensitivityValues(Octave5), Some(Octave5)),
+      (EnableKeySampling(Octave1, true), Some(Octave1)),
+      (Ping(1), None),
+      (SaveProgram(PresetNumber::new(1).unwrap()), None),
+      (SetMacroButtonActiveColor(RGBColor::red()), None),
+      (GetFirmwareRevision, None),
+      (ResetWheelThresholds, None),
+      (GetPeripheralChannels, None),
+    ];
+    for (cmd, expected) in cases {
+      assert_eq!(cmd.board_index(), expected, "{cmd:?}");
+    }
+  }
+
+  #[test]
+  fn test_encode_into_matches_to_sysex_message() {
+    let location = key_loc_unchecked(3, 17);
+    let function = LumatoneKeyFunction::LumaTouch {
+      channel: MidiChannel::unchecked(4),
+      note_num: 64,
+      fader_up_is_null: true,
+    };
+    let commands = vec![
+      ping(1234),
+      set_key_color(location, RGBColor(0x12, 0x34, 0x56)),
+      Command::SetKeyFunction { location, function },
+      Command::SaveProgram(PresetNumber::new(2).unwrap()),
+      Command::SetPitchWheelSensitivity(0x1234),
+      Command::SetAftertouchEnabled(true),
+      Command::SetMacroButtonActiveColor(RGBColor::green()),
+    ];
+
+    // start with junk in the buffer, to make sure it's cleared between messages
+    let mut buf = vec![0xaa; 64];
+    for cmd in commands {
+      cmd.encode_into(&mut buf);
+      assert_eq!(buf, cmd.to_sysex_message(), "mismatch for {cmd}");
+    }
+  }
+
+  #[test]
+  fn test_reset_key() {
+    let location = key_loc_unchecked(4, 30);
+    let commands = reset_key(location);
+    assert_eq!(
+      commands,
+      vec![
+        Command::SetKeyFunction {
+          location,
+          function: LumatoneKeyFunction::Disabled,
+        },
+        Command::SetKeyColor {
+          location,
+          color: RGBColor(0, 0, 0),
+        },
+      ]
+    );
+
+    // same as the padding for undefined keys in .ltn files: KTyp=4, Chan=1, Key=0
+    let function = LumatoneKeyFunction::Disabled;
+    assert_eq!(function.key_type_code(), 4);
+    assert_eq!(function.midi_channel_num(), 1);
+    assert_eq!(function.note_or_cc_num(), 0);
+  }
+
+  #[test]
+  fn test_set_board_colors() {
+    let colors: [RGBColor; 56] = std::array::from_fn(|i| RGBColor(i as u8, 0, 0xff - i as u8));
+    let commands = set_board_colors(BoardIndex::Octave3, &colors);
+    assert_eq!(commands.len(), 56);
+    for (i, command) in commands.iter().enumerate() {
+      assert_eq!(command.board_index(), Some(BoardIndex::Octave3));
+      assert_eq!(
+        *command,
+        set_key_color(key_loc_unchecked(3, i as u8), colors[i])
+      );
+    }
+  }
+
+  #[test]
+  fn test_encode_into_matches_allocating_key_encoders() {
+    let location = key_loc_unchecked(5, 55);
+    let color = RGBColor(0xfe, 0x01, 0x80);
+    let function = LumatoneKeyFunction::NoteOnOff {
+      channel: MidiChannel::unchecked(16),
+      note_num: 127,
+    };
+
+    let mut buf = vec![];
+    set_key_color(location, color).encode_into(&mut buf);
+    assert_eq!(buf, encode_set_key_color(&location, &color));
+
+    Command::SetKeyFunction { location, function }.encode_into(&mut buf);
+    assert_eq!(buf, encode_set_key_function(&location, &function));
+  }
+
+  #[test]
+  fn test_same_target() {
+    let loc = key_loc_unchecked(2, 10);
+    let function = LumatoneKeyFunction::NoteOnOff {
+      channel: MidiChannel::default(),
+      note_num: 60,
+    };
+
+    // same key and command, different payload
+    assert!(set_key_color(loc, RGBColor::red()).same_target(&set_key_color(loc, RGBColor::blue())));
+    assert!(set_key_function(loc, function).same_target(&set_key_function(loc, function)));
+
+    // same key, different command
+    assert!(!set_key_color(loc, RGBColor::red()).same_target(&set_key_function(loc, function)));
+
+    // same command, different key
+    assert!(!set_key_color(loc, RGBColor::red())
+      .same_target(&set_key_color(key_loc_unchecked(3, 10), RGBColor::red())));
+    assert!(!set_key_color(loc, RGBColor::red())
+      .same_target(&set_key_color(key_loc_unchecked(2, 11), RGBColor::red())));
+
+    // non-key commands never share a target
+    assert!(!Command::SetAftertouchEnabled(true).same_target(&Command::SetAftertouchEnabled(true)));
+    assert!(!ping(1).same_target(&set_key_color(loc, RGBColor::red())));
+  }
+
+  /// Commands with random values, limited to what each encoder sends without clamping or
+  /// masking.
+  fn random_commands(rng: &mut StdRng) -> Vec<Command> {
+    use Command::*;
+    let board = BoardIndex::all_octaves()[rng.gen_range(0..5)];
+    let location = key_loc_unchecked(rng.gen_range(1..=5), rng.gen_range(0..=55));
+    let channel = MidiChannel::unchecked(rng.gen_range(1..=16));
+    let color = RGBColor(rng.gen(), rng.gen(), rng.gen());
+    let function = match rng.gen_range(0..4) {
+      0 => LumatoneKeyFunction::NoteOnOff {
+        channel,
+        note_num: rng.gen_range(0..=127),
+      },
+      1 => LumatoneKeyFunction::ContinuousController {
+        channel,
+        cc_num: rng.gen_range(0..=127),
+        fader_up_is_null: rng.gen(),
+      },
+      2 => LumatoneKeyFunction::LumaTouch {
+        channel,
+        note_num: rng.gen_range(0..=127),
+        fader_up_is_null: rng.gen(),
+      },
+      _ => LumatoneKeyFunction::Disabled,
+    };
+    let mut table = [0; 128];
+    rng.fill(&mut table[..]);
+    let table = Box::new(SysexTable::new(table.map(|v| v & 0x7f)));
+    let mut intervals = [0; 127];
+    rng.fill(&mut intervals[..]);
+    let even = |rng: &mut StdRng| rng.gen::<u8>() & 0xfe;
+
+    vec![
+      Ping(rng.gen_range(0..1 << 21)),
+      SetKeyFunction { location, function },
+      SetKeyColor { location, color },
+      SaveProgram(PresetNumber::new(rng.gen_range(0..=9)).unwrap()),
+      SetModWheelSensitivity(rng.gen_range(1..=0x7f)),
+      SetPitchWheelSensitivity(rng.gen_range(1..=0x3fff)),
+      SetAftertouchEnabled(rng.gen()),
+      EnableKeySampling(board, rng.gen()),
+      SetMacroButtonActiveColor(color),
+      SetVelocityConfig(table.clone()),
+      SetLumatouchConfig(table),
+      SetVelocityIntervals(Box::new(intervals.map(|v: u16| v & 0xfff))),
+      SetKeyMaximumThreshold {
+        board_index: board,
+        max_threshold: even(rng),
+        aftertouch_max: even(rng),
+      },
+      SetKeyFaderSensitivity(board, even(rng)),
+      SetAftertouchTriggerDelay(board, rng.gen()),
+      SetLumatouchNoteOffDelay(board, rng.gen_range(0..=0xfff)),
+      SetPeripheralChannels {
+        pitch_wheel: channel,
+        mod_wheel: MidiChannel::unchecked(rng.gen_range(1..=16)),
+        expression: MidiChannel::unchecked(rng.gen_range(1..=16)),
+        sustain: MidiChannel::unchecked(rng.gen_range(1..=16)),
+      },
+      SetExpressionPedalADCThreshold(rng.gen_range(0..=0xfff)),
+    ]
+  }
+
+  #[test]
+  fn test_decode_inverts_encode() {
+    for cmd in one_of_each_command() {
+      let decoded = Command::from_sysex_message(&cmd.to_sysex_message());
+      assert_eq!(
+        decoded.ok(),
+        Some(cmd.clone()),
+        "round trip failed for {cmd}"
+      );
+    }
+
+    let mut rng = StdRng::seed_from_u64(1001);
+    for _ in 0..100 {
+      for cmd in random_commands(&mut rng) {
+        let decoded = Command::from_sysex_message(&cmd.to_sysex_message());
+        assert_eq!(
+          decoded.ok(),
+          Some(cmd.clone()),
+          "round trip failed for {cmd:?}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_decode_ignores_padding() {
+    // short messages are padded to 4 data bytes
+    let msg = Command::SetAftertouchEnabled(true).to_sysex_message();
+    assert_eq!(&strip_sysex_markers(&msg)[MSG_STATUS..], &[1, 0, 0, 0]);
+    let msg = Command::GetSerialId.to_sysex_message();
+    assert_eq!(&strip_sysex_markers(&msg)[MSG_STATUS..], &[0, 0, 0, 0]);
+
+    // but the padding isn't needed to decode them
+    let unpadded = |cmd: CommandId, data: Vec<u8>| {
+      let mut msg = create_sysex(BoardIndex::Server, cmd, vec![]);
+      msg.truncate(MSG_STATUS + 1);
+      msg.extend(data);
+      msg.push(0xf7);
+      Command::from_sysex_message(&msg).unwrap()
+    };
+    assert_eq!(
+      unpadded(CommandId::SetAftertouchFlag, vec![1]),
+      Command::SetAftertouchEnabled(true)
+    );
+    assert_eq!(
+      unpadded(CommandId::GetSerialIdentity, vec![]),
+      Command::GetSerialId
+    );
+  }
+
+  #[test]
+  fn test_velocity_table_is_reversed_on_the_wire() {
+    let mut values = [0; 128];
+    for (i, v) in values.iter_mut().enumerate() {
+      *v = i as u8;
+    }
+    let cmd = Command::SetVelocityConfig(Box::new(SysexTable::new(values)));
+    let msg = cmd.to_sysex_message();
+    let data = &strip_sysex_markers(&msg)[MSG_STATUS..];
+    assert_eq!(data[0], 127);
+    assert_eq!(data[127], 0);
+    assert_eq!(Command::from_sysex_message(&msg).unwrap(), cmd);
+  }
+
+  #[test]
+  fn test_decode_errors() {
+    // truncated table
+    let mut msg = Command::SetFaderConfig(Box::new(SysexTable::new([1; 128]))).to_sysex_message();
+    msg.truncate(msg.len() - 10);
+    msg.push(0xf7);
+    match Command::from_sysex_message(&msg) {
+      Err(LumatoneMidiError::MessagePayloadTooShort {
+        command, expected, ..
+      }) => {
+        assert_eq!(command, CommandId::SetFaderConfig);
+        assert_eq!(expected, 128);
+      }
+      other => panic!("expected MessagePayloadTooShort, got {other:?}"),
+    }
+
+    // key index out of range
+    let mut msg = set_key_color(key_loc_unchecked(1, 0), RGBColor::red()).to_sysex_message();
+    msg[MSG_STATUS + 1] = 56;
+    assert!(matches!(
+      Command::from_sysex_message(&msg),
+      Err(LumatoneMidiError::InvalidLumatoneKeyIndex(56))
+    ));
+
+    // only sent by the device
+    let msg = create_sysex(
+      BoardIndex::Server,
+      CommandId::PeripheralCalbrationData,
+      vec![],
+    );
+    assert!(matches!(
+      Command::from_sysex_message(&msg),
+      Err(LumatoneMidiError::UnsupportedCommandId(..))
+    ));
+  }
+
+  #[test]
+  fn test_json_lines_round_trip() {
+    let commands = one_of_each_command();
+    let script: Vec<String> = commands.iter().map(to_json_line).collect();
+    assert!(script.iter().all(|line| !line.contains('\n')));
+    // blank lines are skipped
+    let script = script.join("\n\n");
+    assert_eq!(from_json_lines(script.as_bytes()).unwrap(), commands);
+
+    let function = LumatoneKeyFunction::NoteOnOff {
+      channel: MidiChannel::unchecked(2),
+      note_num: 60,
+    };
+    assert_eq!(
+      to_json_line(&set_key_function(key_loc_unchecked(1, 3), function)),
+      r#"{"SetKeyFunction":{"location":{"board":1,"key":3},"function":"note","channel":2,"note":60}}"#
+    );
+    assert_eq!(to_json_line(&Command::GetSerialId), r#""GetSerialId""#);
+    assert_eq!(
+      to_json_line(&Command::ResetBoardThresholds(BoardIndex::Octave2)),
+      r#"{"ResetBoardThresholds":"Octave2"}"#
+    );
+  }
+
+  #[test]
+  fn test_json_lines_errors() {
+    let error = |script: &str| match from_json_lines(script.as_bytes()) {
+      Err(LumatoneMidiError::InvalidCommandJson { line, message }) => (line, message),
+      other => panic!("expected InvalidCommandJson, got {other:?}"),
+    };
+
+    let (line, message) = error(
+      r#"{"Ping": 1}
+{"SetKeyFunction": {"location": {"board": 1, "key": 0}, "function": "note", "channel": 0, "note": 60}}"#,
+    );
+    assert_eq!(line, 2);
+    assert!(
+      message.starts_with("invalid midi channel 0. Valid range is 1 ..= 16"),
+      "{message}"
+    );
+
+    let (line, message) =
+      error(r##"{"SetKeyColor": {"location": {"board": 1, "key": 0}, "color": "#1000000"}}"##);
+    assert_eq!(line, 1);
+    assert!(
+      message.starts_with("color #1000000 isn't in #rrggbb form"),
+      "{message}"
+    );
+
+    let channels = r#"{"SetPeripheralChannels": {"pitch_wheel": 1, "mod_wheel": 17, "expression": 1, "sustain": 1}}"#;
+    assert!(error(channels).1.starts_with("invalid midi channel 17"));
+
+    let (_, message) = error(r#""LaunchRockets""#);
+    assert!(
+      message.contains("unknown variant `LaunchRockets`"),
+      "{message}"
+    );
+
+    assert_eq!(
+      error(r#"{"SetExpressionPedalSensitivity": 256}"#).0,
+      1,
+      "out of range for a u8"
+    );
+  }
+}
@@ -6,17 +6,36 @@ use bounded_integer::bounded_integer;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use rand;
+use serde::{Deserialize, Serialize};
 
 use super::error::LumatoneMidiError;
 
 pub const MANUFACTURER_ID: [u8; 3] = [0x00, 0x21, 0x50];
 
 pub const ECHO_FLAG: u8 = 0x5; // used to differentiate test responses from MIDI
+/// Flag byte sent at the start of a ping's data. The device never uses it as a response
+/// status, so it tells a ping apart from the device's answer to it (see [has_echo_flag](super::sysex::has_echo_flag)).
 pub const TEST_ECHO: u8 = 0x7f; // should not be returned by lumatone
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Serializes as a `#rrggbb` string.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub struct RGBColor(pub u8, pub u8, pub u8);
 
+impl From<RGBColor> for String {
+  fn from(color: RGBColor) -> Self {
+    color.to_string()
+  }
+}
+
+impl TryFrom<String> for RGBColor {
+  type Error = String;
+
+  fn try_from(s: String) -> Result<Self, Self::Error> {
+    RGBColor::from_hex_str(&s).ok_or_else(|| format!("color {s} isn't in #rrggbb form"))
+  }
+}
+
 impl RGBColor {
   pub fn red() -> RGBColor {
     RGBColor(0xff, 0, 0)
@@ -39,8 +58,84 @@ impl RGBColor {
     format!("{r:02x}{g:02x}{b:02x}")
   }
 
+  /// Parses a color in `#rrggbb` form, as shown by its `Display` impl.
+  pub fn from_hex_str(s: &str) -> Option<RGBColor> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+      return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(RGBColor::from)
+  }
+
+  /// Returns a color with the given hue (in degrees) and saturation (0 ..= 1) whose
+  /// relative luminance is as close as possible to `luminance` (0 ..= 1).
+  ///
+  /// Different hues at the same LED intensity don't look equally bright (pure blue looks
+  /// much darker than pure green), so this is useful for making multi-hue layouts look
+  /// uniform. Channel values are treated as linear intensities, which is how the LEDs
+  /// use them. If a hue can't reach the target brightness on its own, it's mixed with
+  /// white, which lowers the saturation.
+  pub fn with_perceptual_brightness(hue: f64, sat: f64, luminance: f64) -> RGBColor {
+    let sat = sat.clamp(0.0, 1.0);
+    let luminance = luminance.clamp(0.0, 1.0);
+
+    // full-value HSV -> RGB
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = sat * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = 1.0 - sat;
+    let (r, g, b) = match h as u8 {
+      0 => (sat, x, 0.0),
+      1 => (x, sat, 0.0),
+      2 => (0.0, sat, x),
+      3 => (0.0, x, sat),
+      4 => (x, 0.0, sat),
+      _ => (sat, 0.0, x),
+    };
+    let rgb = [r + m, g + m, b + m];
+
+    // scale towards the target, without pushing any channel past full intensity
+    let y = relative_luminance(rgb);
+    let max = rgb.iter().cloned().fold(0.0, f64::max);
+    let k = if y > 0.0 {
+      (luminance / y).min(1.0 / max)
+    } else {
+      0.0
+    };
+    let scaled = rgb.map(|c| c * k);
+
+    // make up any remaining difference by mixing in white
+    let y_scaled = relative_luminance(scaled);
+    let t = if y_scaled < 1.0 {
+      ((luminance - y_scaled) / (1.0 - y_scaled)).max(0.0)
+    } else {
+      0.0
+    };
+    let [r, g, b] = scaled.map(|c| ((c + t * (1.0 - c)) * 255.0).round() as u8);
+    RGBColor(r, g, b)
+  }
+
+  /// Returns the color with each channel multiplied by `factor`, clamped to 0 ..= 1.
+  pub fn scaled(&self, factor: f64) -> RGBColor {
+    let factor = factor.clamp(0.0, 1.0);
+    let RGBColor(r, g, b) = *self;
+    let [r, g, b] = [r, g, b].map(|c| (c as f64 * factor).round() as u8);
+    RGBColor(r, g, b)
+  }
+
+  /// Returns the relative luminance (0 ..= 1) of the color, treating channel values as
+  /// linear intensities.
+  pub fn relative_luminance(&self) -> f64 {
+    let RGBColor(r, g, b) = *self;
+    relative_luminance([r, g, b].map(|c| c as f64 / 255.0))
+  }
+
   /// Returns the color encoded into 6 u8's with the lower 4 bits set.
   pub fn to_bytes(&self) -> Vec<u8> {
+    self.to_byte_array().to_vec()
+  }
+
+  /// Like [RGBColor::to_bytes], but returns a fixed-size array instead of allocating.
+  pub fn to_byte_array(&self) -> [u8; 6] {
     let RGBColor(red, green, blue) = *self;
     let red_hi = red >> 4;
     let red_lo = red & 0xf;
@@ -48,10 +143,15 @@ impl RGBColor {
     let green_lo = green & 0xf;
     let blue_hi = blue >> 4;
     let blue_lo = blue & 0xf;
-    vec![red_hi, red_lo, green_hi, green_lo, blue_hi, blue_lo]
+    [red_hi, red_lo, green_hi, green_lo, blue_hi, blue_lo]
   }
 }
 
+/// Rec. 709 relative luminance of linear RGB values.
+fn relative_luminance([r, g, b]: [f64; 3]) -> f64 {
+  0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
 impl From<u32> for RGBColor {
   /// Conversion from u32 ignores the "leftmost" byte.
   /// e.g. use 0x00ffffff for white.
@@ -145,13 +245,23 @@ impl Default for MidiChannel {
   }
 }
 
+/// Deserializes a [MidiChannel] from its number, failing with the same message as
+/// [LumatoneMidiError::InvalidMidiChannel] if it's out of range. For use with
+/// `#[serde(deserialize_with)]`, since the derived impl doesn't say what the number was for.
+pub(crate) fn deserialize_midi_channel<'de, D: serde::Deserializer<'de>>(
+  deserializer: D,
+) -> Result<MidiChannel, D::Error> {
+  let value = u8::deserialize(deserializer)?;
+  MidiChannel::try_from(value).map_err(serde::de::Error::custom)
+}
+
 /// Identifies which "board" a message should be routed to.
 ///
 /// Commands that set key parameters should be targetted at one of the Octave values,
 /// which control the five 56-key Terpstra boards that comprise the full Lumatone layout.
 ///
 /// Global operations (ping, macro keys, etc) should be sent to the Server board.
-#[derive(Debug, FromPrimitive, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, FromPrimitive, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum BoardIndex {
   Server = 0,
   Octave1,
@@ -197,9 +307,35 @@ impl Display for BoardIndex {
 ///
 /// To convert from another coordinate system, add an `impl Into<LumatoneKeyLocation>` to your coordinate type.
 ///
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Serializes as the board and key numbers, e.g. `{"board": 2, "key": 13}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "KeyLocationRepr", try_from = "KeyLocationRepr")]
 pub struct LumatoneKeyLocation(pub BoardIndex, pub LumatoneKeyIndex);
 
+#[derive(Serialize, Deserialize)]
+struct KeyLocationRepr {
+  board: u8,
+  key: LumatoneKeyIndex,
+}
+
+impl From<LumatoneKeyLocation> for KeyLocationRepr {
+  fn from(LumatoneKeyLocation(board, key): LumatoneKeyLocation) -> Self {
+    KeyLocationRepr {
+      board: board.into(),
+      key,
+    }
+  }
+}
+
+impl TryFrom<KeyLocationRepr> for LumatoneKeyLocation {
+  type Error = LumatoneMidiError;
+
+  fn try_from(repr: KeyLocationRepr) -> Result<Self, Self::Error> {
+    let board = BoardIndex::try_from(repr.board)?;
+    Ok(LumatoneKeyLocation(board, repr.key))
+  }
+}
+
 impl LumatoneKeyLocation {
   pub fn board_index(&self) -> BoardIndex {
     self.0
@@ -236,6 +372,39 @@ impl Display for LumatoneKeyLocation {
   }
 }
 
+/// Hardware differences between firmware generations, inferred from how the device
+/// answers. See [Client::identify](super::client::Client::identify).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareModel {
+  /// How many keys each octave board reports. Early firmware only knows about 55 of the
+  /// 56 physical keys.
+  pub key_count_per_board: u8,
+
+  /// Whether the device answers serial number requests. Early firmware acknowledges them
+  /// without sending one.
+  pub has_serial: bool,
+}
+
+impl HardwareModel {
+  /// The number of keys on each octave board of current hardware.
+  pub const FULL_KEY_COUNT: u8 = 56;
+
+  /// Returns true if the device can address the key at `index` on each board.
+  pub fn has_key(&self, index: LumatoneKeyIndex) -> bool {
+    Into::<u8>::into(index) < self.key_count_per_board
+  }
+}
+
+impl Default for HardwareModel {
+  /// Current firmware, with all 56 keys and a serial number.
+  fn default() -> Self {
+    HardwareModel {
+      key_count_per_board: Self::FULL_KEY_COUNT,
+      has_serial: true,
+    }
+  }
+}
+
 /// Returns a (BoardIndex, LumatoneKeyIndex) tuple that identifies a Lumatone key.
 /// Will panic if input is out of range - use only on static / trusted input.
 pub fn key_loc_unchecked(board_index: u8, key_index: u8) -> LumatoneKeyLocation {
@@ -245,7 +414,10 @@ pub fn key_loc_unchecked(board_index: u8, key_index: u8) -> LumatoneKeyLocation
   LumatoneKeyLocation(board_index, key_index)
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Serializes with a `function` of `note`, `cc`, `lumatouch` or `disabled`, alongside its
+/// parameters, the same way keys are written in [JSON layouts](crate::keymap::json).
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(into = "KeyFunctionRepr", try_from = "KeyFunctionRepr")]
 pub enum LumatoneKeyFunction {
   /// Key sends note on/off messages
   NoteOnOff {
@@ -265,7 +437,121 @@ pub enum LumatoneKeyFunction {
   Disabled,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "lowercase")]
+enum KeyFunctionRepr {
+  Note {
+    #[serde(default, deserialize_with = "deserialize_midi_channel")]
+    channel: MidiChannel,
+    note: u8,
+  },
+  Cc {
+    #[serde(default, deserialize_with = "deserialize_midi_channel")]
+    channel: MidiChannel,
+    cc: u8,
+    #[serde(default)]
+    fader_up_is_null: bool,
+  },
+  Lumatouch {
+    #[serde(default, deserialize_with = "deserialize_midi_channel")]
+    channel: MidiChannel,
+    note: u8,
+    #[serde(default)]
+    fader_up_is_null: bool,
+  },
+  Disabled,
+}
+
+impl From<LumatoneKeyFunction> for KeyFunctionRepr {
+  fn from(function: LumatoneKeyFunction) -> Self {
+    use LumatoneKeyFunction::*;
+    match function {
+      NoteOnOff { channel, note_num } => KeyFunctionRepr::Note {
+        channel,
+        note: note_num,
+      },
+      ContinuousController {
+        channel,
+        cc_num,
+        fader_up_is_null,
+      } => KeyFunctionRepr::Cc {
+        channel,
+        cc: cc_num,
+        fader_up_is_null,
+      },
+      LumaTouch {
+        channel,
+        note_num,
+        fader_up_is_null,
+      } => KeyFunctionRepr::Lumatouch {
+        channel,
+        note: note_num,
+        fader_up_is_null,
+      },
+      Disabled => KeyFunctionRepr::Disabled,
+    }
+  }
+}
+
+impl TryFrom<KeyFunctionRepr> for LumatoneKeyFunction {
+  type Error = String;
+
+  fn try_from(repr: KeyFunctionRepr) -> Result<Self, Self::Error> {
+    let midi_value = |field: &str, value: u8| {
+      if value <= 127 {
+        Ok(value)
+      } else {
+        Err(format!("{field} is {value}, but must be from 0 to 127"))
+      }
+    };
+    Ok(match repr {
+      KeyFunctionRepr::Note { channel, note } => LumatoneKeyFunction::NoteOnOff {
+        channel,
+        note_num: midi_value("note", note)?,
+      },
+      KeyFunctionRepr::Cc {
+        channel,
+        cc,
+        fader_up_is_null,
+      } => LumatoneKeyFunction::ContinuousController {
+        channel,
+        cc_num: midi_value("cc", cc)?,
+        fader_up_is_null,
+      },
+      KeyFunctionRepr::Lumatouch {
+        channel,
+        note,
+        fader_up_is_null,
+      } => LumatoneKeyFunction::LumaTouch {
+        channel,
+        note_num: midi_value("note", note)?,
+        fader_up_is_null,
+      },
+      KeyFunctionRepr::Disabled => LumatoneKeyFunction::Disabled,
+    })
+  }
+}
+
+/// The kind of a [LumatoneKeyFunction], without its parameters.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum KeyFunctionKind {
+  NoteOnOff,
+  ContinuousController,
+  LumaTouch,
+  Disabled,
+}
+
 impl LumatoneKeyFunction {
+  pub fn kind(&self) -> KeyFunctionKind {
+    use LumatoneKeyFunction::*;
+    match *self {
+      NoteOnOff { .. } => KeyFunctionKind::NoteOnOff,
+      ContinuousController { .. } => KeyFunctionKind::ContinuousController,
+      LumaTouch { .. } => KeyFunctionKind::LumaTouch,
+      Disabled => KeyFunctionKind::Disabled,
+    }
+  }
+
   pub fn type_code(&self) -> u8 {
     use LumatoneKeyFunction::*;
     match *self {
@@ -300,6 +586,31 @@ impl LumatoneKeyFunction {
     }
   }
 
+  /// The inverse of [LumatoneKeyFunction::type_code], for decoding key types read back from
+  /// the device. Returns `None` for unknown type codes.
+  pub fn from_type_code(type_code: u8, channel: MidiChannel, note_or_cc_num: u8) -> Option<Self> {
+    use LumatoneKeyFunction::*;
+    let fader_up_is_null = type_code & (1 << 4) != 0;
+    match type_code & 0xf {
+      1 => Some(NoteOnOff {
+        channel,
+        note_num: note_or_cc_num,
+      }),
+      2 => Some(ContinuousController {
+        channel,
+        cc_num: note_or_cc_num,
+        fader_up_is_null,
+      }),
+      3 => Some(LumaTouch {
+        channel,
+        note_num: note_or_cc_num,
+        fader_up_is_null,
+      }),
+      4 => Some(Disabled),
+      _ => None,
+    }
+  }
+
   pub fn note_or_cc_num(&self) -> u8 {
     use LumatoneKeyFunction::*;
     match *self {
@@ -310,6 +621,17 @@ impl LumatoneKeyFunction {
     }
   }
 
+  /// The midi channel the key sends on, or `None` for disabled keys.
+  pub fn channel(&self) -> Option<MidiChannel> {
+    use LumatoneKeyFunction::*;
+    match *self {
+      NoteOnOff { channel, .. } => Some(channel),
+      ContinuousController { channel, .. } => Some(channel),
+      LumaTouch { channel, .. } => Some(channel),
+      Disabled => None,
+    }
+  }
+
   /// The midi channel number (0-indexed)
   pub fn midi_channel_byte(&self) -> u8 {
     use LumatoneKeyFunction::*;
@@ -366,8 +688,16 @@ impl Into<u8> for ResponseStatusCode {
   }
 }
 
+/// Which peripheral a [CommandId::PeripheralCalbrationData] frame has data for, as given by
+/// its [calibration_mode_byte](super::sysex::calibration_mode_byte).
+#[derive(Debug, FromPrimitive, PartialEq, Eq, Clone, Copy)]
+pub enum PeripheralCalibrationMode {
+  ExpressionPedal = 0x00,
+  PitchAndModWheels = 0x01,
+}
+
 /// Identifies a Lumatone command.
-#[derive(Debug, FromPrimitive, PartialEq)]
+#[derive(Debug, FromPrimitive, PartialEq, Clone, Copy, Serialize)]
 pub enum CommandId {
   // Start support at 55-keys firmware version, Developmental versions
   ChangeKeyNote = 0x00,
@@ -479,6 +809,8 @@ pub enum CommandId {
   SetExpressionPedalThreshold = 0x43,
   GetExpressionPedalThreshold = 0x44,
   InvertSustainPedal = 0x45,
+  // 0x46 and 0x47 reset the presets and read back their flags, which we don't support yet
+  GetExpressionPedalSensitivity = 0x48,
 }
 
 impl Into<u8> for CommandId {
@@ -489,10 +821,106 @@ impl Into<u8> for CommandId {
 
 #[cfg(test)]
 mod tests {
-  use super::RGBColor;
+  use super::{KeyFunctionKind, LumatoneKeyFunction, MidiChannel, RGBColor};
 
   #[test]
   fn test_rgb_color() {
     assert_eq!(RGBColor::from(0x00aabbcc), RGBColor(0xaa, 0xbb, 0xcc));
+
+    let color = RGBColor(0x0a, 0xbb, 0xcc);
+    assert_eq!(RGBColor::from_hex_str(&color.to_string()), Some(color));
+    assert_eq!(
+      RGBColor::from_hex_str("#AABBCC"),
+      Some(RGBColor(0xaa, 0xbb, 0xcc))
+    );
+    for bad in ["aabbcc", "#abc", "#+abbcc", "#aabbccdd", "#gg0000"] {
+      assert_eq!(RGBColor::from_hex_str(bad), None, "{bad}");
+    }
+  }
+
+  #[test]
+  fn test_scaled() {
+    let color = RGBColor(200, 100, 0);
+    assert_eq!(color.scaled(0.5), RGBColor(100, 50, 0));
+    assert_eq!(color.scaled(1.0), color);
+    assert_eq!(color.scaled(2.0), color);
+    assert_eq!(color.scaled(-1.0), RGBColor(0, 0, 0));
+  }
+
+  #[test]
+  fn test_perceptual_brightness() {
+    // at full intensity, blue is much darker than green
+    assert!(RGBColor::blue().relative_luminance() < RGBColor::green().relative_luminance() / 5.0);
+
+    let target = 0.2;
+    let green = RGBColor::with_perceptual_brightness(120.0, 1.0, target);
+    let blue = RGBColor::with_perceptual_brightness(240.0, 1.0, target);
+    assert!(
+      (green.relative_luminance() - target).abs() < 0.01,
+      "{green}"
+    );
+    assert!((blue.relative_luminance() - target).abs() < 0.01, "{blue}");
+
+    // both keep their hue: the dominant channel is unchanged
+    assert!(green.1 > green.0 && green.1 > green.2);
+    assert_eq!(blue.2, 0xff);
+
+    assert_eq!(
+      RGBColor::with_perceptual_brightness(0.0, 1.0, 0.0),
+      RGBColor(0, 0, 0)
+    );
+    assert_eq!(
+      RGBColor::with_perceptual_brightness(300.0, 0.5, 1.0),
+      RGBColor(0xff, 0xff, 0xff)
+    );
+  }
+
+  #[test]
+  fn test_key_function_kind() {
+    let channel = MidiChannel::default();
+    let cases = [
+      (
+        LumatoneKeyFunction::NoteOnOff {
+          channel,
+          note_num: 60,
+        },
+        KeyFunctionKind::NoteOnOff,
+      ),
+      (
+        LumatoneKeyFunction::ContinuousController {
+          channel,
+          cc_num: 1,
+          fader_up_is_null: true,
+        },
+        KeyFunctionKind::ContinuousController,
+      ),
+      (
+        LumatoneKeyFunction::LumaTouch {
+          channel,
+          note_num: 60,
+          fader_up_is_null: false,
+        },
+        KeyFunctionKind::LumaTouch,
+      ),
+      (LumatoneKeyFunction::Disabled, KeyFunctionKind::Disabled),
+    ];
+    for (function, kind) in cases {
+      assert_eq!(function.kind(), kind);
+      let code = function.type_code();
+      let decoded = LumatoneKeyFunction::from_type_code(code, channel, function.note_or_cc_num());
+      assert_eq!(decoded, Some(function));
+    }
+    assert_eq!(LumatoneKeyFunction::from_type_code(5, channel, 0), None);
+  }
+
+  #[test]
+  fn test_key_function_channel() {
+    let channel = MidiChannel::unchecked(3);
+    let f = LumatoneKeyFunction::NoteOnOff {
+      channel,
+      note_num: 60,
+    };
+    assert_eq!(f.channel(), Some(channel));
+    assert_eq!(LumatoneKeyFunction::Disabled.channel(), None);
   }
 }
@@ -1,10 +1,18 @@
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio::time::timeout;
+use tokio::time::{sleep_until, timeout, timeout_at, Instant};
 
 use super::{
-  commands::ping, device::LumatoneDevice, error::LumatoneMidiError, responses::decode_ping,
+  commands::{ping, Command},
+  device::LumatoneDevice,
+  error::LumatoneMidiError,
+  responses::decode_ping,
 };
+use crate::settings::config_dir;
+use ini::Ini;
 use midir::{MidiInput, MidiOutput};
 
 use log::{debug, info, warn};
@@ -12,7 +20,355 @@ use log::{debug, info, warn};
 
 const CLIENT_NAME: &'static str = "lumatone_rs";
 
+/// File name for the cached port names, within [config_dir].
+const DEVICE_CACHE_FILE: &str = "device.ini";
+
+/// How long a full port scan waits for a response, in total.
+const DETECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a ping response on the cached ports before falling back to a full scan.
+const CACHED_PING_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// How long [detect_devices] listens for answers, and how long a scan keeps listening for
+/// other devices after the first one answers.
+pub const DEFAULT_COLLECT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Options for [detect_device_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct DetectOptions {
+  /// Skip the cached ports and always scan all ports. The cache is still updated if the scan succeeds.
+  pub ignore_cache: bool,
+
+  /// Where to read and write the cached port names. If `None`, uses a file in the platform config dir.
+  pub cache_path: Option<PathBuf>,
+
+  /// How to pace the pings sent while scanning all ports.
+  pub ping_waves: PingWaves,
+
+  /// How long to keep listening for other devices once one has answered a scan. If `None`,
+  /// uses [DEFAULT_COLLECT_WINDOW].
+  pub collect_window: Option<Duration>,
+}
+
+impl DetectOptions {
+  fn collect_window(&self) -> Duration {
+    self.collect_window.unwrap_or(DEFAULT_COLLECT_WINDOW)
+  }
+}
+
+/// Pacing for the pings sent while scanning all ports.
+///
+/// Each "wave" pings every output port, then waits up to `wave_window` for a response.
+/// If none arrives, the next wave goes out after `inter_wave_delay`, until `total_timeout`
+/// has passed. Re-sending helps on busy MIDI buses, where a single ping can get lost.
+///
+/// The default is a single wave that waits the whole `total_timeout`. See [PingWaves::paced]
+/// for settings that re-send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingWaves {
+  pub wave_window: Duration,
+  pub inter_wave_delay: Duration,
+  pub total_timeout: Duration,
+}
+
+impl Default for PingWaves {
+  fn default() -> Self {
+    PingWaves {
+      wave_window: DETECTION_TIMEOUT,
+      inter_wave_delay: Duration::ZERO,
+      total_timeout: DETECTION_TIMEOUT,
+    }
+  }
+}
+
+impl PingWaves {
+  /// Waves every couple of seconds, within the usual overall timeout.
+  pub fn paced() -> Self {
+    PingWaves {
+      wave_window: Duration::from_secs(2),
+      inter_wave_delay: Duration::from_millis(250),
+      total_timeout: DETECTION_TIMEOUT,
+    }
+  }
+
+  /// Returns when each wave should be sent, relative to the start of the scan.
+  /// There's always at least one wave.
+  fn wave_offsets(&self) -> Vec<Duration> {
+    let period = self.wave_window + self.inter_wave_delay;
+    let mut offsets = vec![Duration::ZERO];
+    if period.is_zero() {
+      return offsets;
+    }
+    while let Some(next) = offsets.last().map(|o| *o + period) {
+      if next >= self.total_timeout {
+        break;
+      }
+      offsets.push(next);
+    }
+    offsets
+  }
+
+  /// Calls `send_wave` with each wave's number on schedule, until something arrives on
+  /// `responses` or the total timeout passes.
+  async fn run<T>(
+    &self,
+    mut send_wave: impl FnMut(usize),
+    responses: &mut mpsc::Receiver<T>,
+  ) -> Option<T> {
+    let start = Instant::now();
+    let deadline = start + self.total_timeout;
+    for (wave, offset) in self.wave_offsets().into_iter().enumerate() {
+      sleep_until(start + offset).await;
+      // a response to the last wave may have come in during the delay
+      if let Ok(response) = responses.try_recv() {
+        return Some(response);
+      }
+
+      debug!("sending ping wave {wave}");
+      send_wave(wave);
+      let window_end = (start + offset + self.wave_window).min(deadline);
+      if let Ok(Some(response)) = timeout_at(window_end, responses.recv()).await {
+        return Some(response);
+      }
+    }
+    None
+  }
+
+  /// Like [PingWaves::run], but once something arrives, keeps collecting until
+  /// `collect_window` after it, or the total timeout, whichever comes first. Returns
+  /// everything that arrived, in order, or nothing if the total timeout passed first.
+  async fn collect<T>(
+    &self,
+    send_wave: impl FnMut(usize),
+    collect_window: Duration,
+    responses: &mut mpsc::Receiver<T>,
+  ) -> Vec<T> {
+    let deadline = Instant::now() + self.total_timeout;
+    let Some(first) = self.run(send_wave, responses).await else {
+      return vec![];
+    };
+    let collect_end = (Instant::now() + collect_window).min(deadline);
+    let mut collected = vec![first];
+    while let Ok(Some(response)) = timeout_at(collect_end, responses.recv()).await {
+      collected.push(response);
+    }
+    collected
+  }
+}
+
+/// How a device was found by [detect_device_with_options].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionSource {
+  /// The ports from the last successful detection responded to a ping.
+  Cache,
+  /// All ports were scanned.
+  Scan,
+}
+
+/// Detects a connected Lumatone, trying the ports from the last successful detection first.
+///
+/// If a scan finds more than one Lumatone, this fails rather than picking one of them.
+/// Use [detect_devices] to choose between them.
 pub async fn detect_device() -> Result<LumatoneDevice, LumatoneMidiError> {
+  detect_device_with_options(&DetectOptions::default())
+    .await
+    .map(|(device, _)| device)
+}
+
+/// Like [detect_device], but with [DetectOptions], and also returns whether the
+/// cached ports were used.
+pub async fn detect_device_with_options(
+  options: &DetectOptions,
+) -> Result<(LumatoneDevice, DetectionSource), LumatoneMidiError> {
+  let cache_path = options
+    .cache_path
+    .clone()
+    .or_else(|| config_dir().map(|dir| dir.join(DEVICE_CACHE_FILE)));
+  detect_using(
+    cache_path.as_deref(),
+    !options.ignore_cache,
+    |device| ping_device(device, CACHED_PING_TIMEOUT),
+    || async {
+      let devices = scan_for_devices(&options.ping_waves, options.collect_window()).await?;
+      only_device(devices)
+    },
+  )
+  .await
+}
+
+/// Finds every connected Lumatone, by pinging all output ports and listening for
+/// [DEFAULT_COLLECT_WINDOW]. Returns the ports of each device that answered, in the order
+/// they answered, e.g. for letting the user choose between them. The cached ports aren't used
+/// or updated.
+pub async fn detect_devices() -> Result<Vec<LumatoneDevice>, LumatoneMidiError> {
+  detect_devices_within(DEFAULT_COLLECT_WINDOW).await
+}
+
+/// Like [detect_devices], but listens for answers for `window`.
+pub async fn detect_devices_within(
+  window: Duration,
+) -> Result<Vec<LumatoneDevice>, LumatoneMidiError> {
+  let ping_waves = PingWaves {
+    wave_window: window,
+    inter_wave_delay: Duration::ZERO,
+    total_timeout: window,
+  };
+  scan_for_devices(&ping_waves, window).await
+}
+
+/// Returns the one device in `devices`, failing if there are none or more than one.
+fn only_device(mut devices: Vec<LumatoneDevice>) -> Result<LumatoneDevice, LumatoneMidiError> {
+  use LumatoneMidiError::DeviceDetectionFailed;
+  match devices.len() {
+    0 => Err(DeviceDetectionFailed("unable to detect ports".to_string())),
+    1 => Ok(devices.remove(0)),
+    n => {
+      let ports: Vec<String> = devices
+        .iter()
+        .map(|d| format!("in: {}, out: {}", d.input_port_name(), d.output_port_name()))
+        .collect();
+      Err(DeviceDetectionFailed(format!(
+        "found {n} Lumatones ({}); configure the ports of the one to use",
+        ports.join("; ")
+      )))
+    }
+  }
+}
+
+/// The cache handling for [detect_device_with_options], with the device I/O passed in
+/// so it can be tested without hardware.
+async fn detect_using<Probe, ProbeFut, Scan, ScanFut>(
+  cache_path: Option<&Path>,
+  read_cache: bool,
+  probe: Probe,
+  scan: Scan,
+) -> Result<(LumatoneDevice, DetectionSource), LumatoneMidiError>
+where
+  Probe: FnOnce(LumatoneDevice) -> ProbeFut,
+  ProbeFut: Future<Output = bool>,
+  Scan: FnOnce() -> ScanFut,
+  ScanFut: Future<Output = Result<LumatoneDevice, LumatoneMidiError>>,
+{
+  if read_cache {
+    if let Some(cached) = cache_path.and_then(read_cached_device) {
+      debug!("trying cached ports: {cached:?}");
+      if probe(cached.clone()).await {
+        info!("device responded on cached ports");
+        return Ok((cached, DetectionSource::Cache));
+      }
+      info!("cached ports didn't respond, scanning all ports");
+    }
+  }
+
+  let device = scan().await?;
+  if let Some(path) = cache_path {
+    if let Err(err) = write_cached_device(path, &device) {
+      warn!("unable to cache detected ports: {err}");
+    }
+  }
+  Ok((device, DetectionSource::Scan))
+}
+
+fn read_cached_device(path: &Path) -> Option<LumatoneDevice> {
+  let conf = Ini::load_from_file(path).ok()?;
+  let section = conf.section(Some("device"))?;
+  let output = section.get("output")?;
+  let input = section.get("input")?;
+  Some(LumatoneDevice::new(output, input))
+}
+
+fn write_cached_device(path: &Path, device: &LumatoneDevice) -> std::io::Result<()> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let mut conf = Ini::new();
+  conf
+    .with_section(Some("device"))
+    .set("output", device.output_port_name())
+    .set("input", device.input_port_name());
+  conf.write_to_file(path)
+}
+
+/// The names of this machine's MIDI ports. See [list_ports].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MidiPorts {
+  pub inputs: Vec<String>,
+  pub outputs: Vec<String>,
+}
+
+/// Lists the MIDI input and output ports that a scan would try, by name. Ports whose
+/// names can't be read are left out.
+pub fn list_ports() -> Result<MidiPorts, LumatoneMidiError> {
+  use LumatoneMidiError::DeviceDetectionFailed;
+  let input = MidiInput::new(CLIENT_NAME)
+    .map_err(|e| DeviceDetectionFailed(format!("failed to open input port: {e}")))?;
+  let output = MidiOutput::new(CLIENT_NAME)
+    .map_err(|e| DeviceDetectionFailed(format!("failed to open output port: {e}")))?;
+  Ok(MidiPorts {
+    inputs: input
+      .ports()
+      .iter()
+      .filter_map(|p| input.port_name(p).ok())
+      .collect(),
+    outputs: output
+      .ports()
+      .iter()
+      .filter_map(|p| output.port_name(p).ok())
+      .collect(),
+  })
+}
+
+/// Returns the ping to send on output port `out_port_index` while scanning for the device.
+///
+/// The device answers a ping with the value it was sent, so a response tells us which
+/// output port reached the device (see [decode_port_probe]), and the input port it arrives
+/// on is the one the device talks back on. Only the low 21 bits of the index fit in a
+/// ping, which is far more ports than anyone has.
+pub fn port_probe_ping(out_port_index: usize) -> Command {
+  ping(out_port_index as u32)
+}
+
+/// Decodes a response to a [port_probe_ping], returning the index of the output port the
+/// ping was sent on.
+///
+/// Fails if `msg` isn't a ping response, including when it's the ping itself, looped back
+/// by something that isn't the device.
+pub fn decode_port_probe(msg: &[u8]) -> Result<usize, LumatoneMidiError> {
+  decode_ping(msg).map(|value| value as usize)
+}
+
+/// Connects to `device` and sends a ping, returning true if a valid response arrives within `wait`.
+async fn ping_device(device: LumatoneDevice, wait: Duration) -> bool {
+  let mut io = match device.connect() {
+    Ok(io) => io,
+    Err(err) => {
+      debug!("unable to connect to cached ports: {err}");
+      return false;
+    }
+  };
+
+  let responded = match io.send(&ping(0).to_sysex_message()) {
+    Ok(()) => match timeout(wait, io.incoming_messages.recv()).await {
+      Ok(Some(msg)) => decode_ping(&msg).is_ok(),
+      _ => false,
+    },
+    Err(err) => {
+      debug!("unable to ping cached ports: {err}");
+      false
+    }
+  };
+  io.close();
+  responded
+}
+
+/// Pings every output port, paced by `ping_waves`, and waits for a response on any input
+/// port. Once one arrives, keeps listening for `collect_window`, and returns a pair of
+/// ports for each output that answered (see [answered_port_pairs]). Returns an empty list
+/// if nothing answered.
+async fn scan_for_devices(
+  ping_waves: &PingWaves,
+  collect_window: Duration,
+) -> Result<Vec<LumatoneDevice>, LumatoneMidiError> {
   use LumatoneMidiError::DeviceDetectionFailed;
   debug!("beginning lumatone device detection");
 
@@ -30,7 +386,8 @@ pub async fn detect_device() -> Result<LumatoneDevice, LumatoneMidiError> {
     out_ports.len()
   );
 
-  let (tx, mut rx) = mpsc::channel(in_ports.len());
+  // room for every pair of ports to answer a wave
+  let (tx, mut rx) = mpsc::channel((in_ports.len() * out_ports.len()).max(1));
 
   let mut input_connections = vec![];
   for (port_index, p) in in_ports.iter().enumerate() {
@@ -46,10 +403,13 @@ pub async fn detect_device() -> Result<LumatoneDevice, LumatoneMidiError> {
       p,
       &port_name,
       move |_, msg, _| {
-        match decode_ping(msg) {
+        match decode_port_probe(msg) {
           Ok(output_port_index) => {
-            let _ = my_tx.blocking_send((port_index, output_port_index as usize));
-            // TODO: don't swallow channel send errors
+            // don't block the MIDI thread; answers that don't fit are repeats from a
+            // later wave, or came in after we stopped listening
+            if let Err(e) = my_tx.try_send((port_index, output_port_index)) {
+              debug!("dropped ping response: {e}");
+            }
           }
           Err(e) => {
             warn!("error decoding ping message: {:?}", e);
@@ -67,45 +427,321 @@ pub async fn detect_device() -> Result<LumatoneDevice, LumatoneMidiError> {
     }
   }
 
-  // send a ping message on all output ports, with the ping value set to the output port index
-  for (port_index, p) in out_ports.iter().enumerate() {
-    let midi_out = MidiOutput::new(CLIENT_NAME)
-      .map_err(|e| DeviceDetectionFailed(format!("failed to open output port: {e}")))?;
-    let port_name = midi_out
-      .port_name(p)
+  // send a ping message on all output ports, each identifying the port it was sent on
+  let send_pings = |_wave| {
+    for (port_index, p) in out_ports.iter().enumerate() {
+      let midi_out = match MidiOutput::new(CLIENT_NAME) {
+        Ok(midi_out) => midi_out,
+        Err(e) => {
+          warn!("failed to open output port: {e}");
+          continue;
+        }
+      };
+      let port_name = match midi_out.port_name(p) {
+        Ok(name) => name,
+        Err(e) => {
+          warn!("failed to get output port name: {e}");
+          continue;
+        }
+      };
+      if let Ok(mut conn) = midi_out.connect(p, &port_name) {
+        let cmd = port_probe_ping(port_index);
+        if let Err(send_err) = conn.send(&cmd.to_sysex_message()) {
+          warn!("send error: {send_err}");
+        }
+        debug!("sent ping on output {port_index} - {port_name}");
+        conn.close();
+      }
+    }
+  };
+
+  let answers = ping_waves
+    .collect(send_pings, collect_window, &mut rx)
+    .await;
+
+  let mut devices = vec![];
+  for (in_port_idx, out_port_idx) in answered_port_pairs(answers) {
+    // a response can name an output port index that doesn't exist
+    if out_port_idx >= out_ports.len() {
+      warn!("ping response named unknown output port {out_port_idx}");
+      continue;
+    }
+
+    let output_port_name = output
+      .port_name(&out_ports[out_port_idx])
       .map_err(|e| DeviceDetectionFailed(format!("failed to get output port name: {e}")))?;
-    if let Ok(mut conn) = midi_out.connect(p, &port_name) {
-      let cmd = ping(port_index as u32);
-      if let Err(send_err) = conn.send(&cmd.to_sysex_message()) {
-        warn!("send error: {send_err}");
+    let input_port_name = input
+      .port_name(&in_ports[in_port_idx])
+      .map_err(|e| DeviceDetectionFailed(format!("failed to get input port name: {e}")))?;
+
+    info!("detected lumatone ports: in: {input_port_name}, out: {output_port_name}");
+    devices.push(LumatoneDevice::new(&output_port_name, &input_port_name));
+  }
+  Ok(devices)
+}
+
+/// One (input port, output port) index pair per output port in `answers`, in the order
+/// the outputs first answered.
+///
+/// The probe names the output it was sent on, so each output that answered is one device.
+/// Its answer can still arrive on more than one input, e.g. when a virtual port merges
+/// several inputs, so the input that answered first is used. A device answers every wave
+/// of pings, so most answers repeat.
+fn answered_port_pairs(answers: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+  let mut pairs: Vec<(usize, usize)> = vec![];
+  for (input, output) in answers {
+    match pairs.iter().find(|(_, out)| *out == output) {
+      Some((first_input, _)) if *first_input != input => {
+        debug!("output {output} also answered on input {input}, using input {first_input}");
       }
-      debug!("sent ping on output {port_index} - {port_name}");
-      conn.close();
+      Some(_) => {}
+      None => pairs.push((input, output)),
+    }
+  }
+  pairs
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::time::Duration;
+
+  use tokio::sync::mpsc;
+
+  use super::{
+    answered_port_pairs, decode_port_probe, detect_using, only_device, port_probe_ping,
+    read_cached_device, write_cached_device, DetectionSource, PingWaves,
+  };
+  use crate::midi::{
+    constants::ResponseStatusCode, device::LumatoneDevice, error::LumatoneMidiError,
+    mock::reply_with_status,
+  };
+
+  fn temp_cache_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumatone-detect-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("device.ini")
+  }
+
+  async fn scan_finds(out: &str, input: &str) -> Result<LumatoneDevice, LumatoneMidiError> {
+    Ok(LumatoneDevice::new(out, input))
+  }
+
+  #[tokio::test]
+  async fn test_cache_hit_skips_scan() {
+    let path = temp_cache_path("hit");
+    write_cached_device(&path, &LumatoneDevice::new("cached out", "cached in")).unwrap();
+
+    let (device, source) = detect_using(
+      Some(&path),
+      true,
+      |_| async { true },
+      || async { panic!("should not scan when the cached ports respond") },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source, DetectionSource::Cache);
+    assert_eq!(device.output_port_name(), "cached out");
+    assert_eq!(device.input_port_name(), "cached in");
+  }
+
+  #[tokio::test]
+  async fn test_stale_cache_falls_back_to_scan() {
+    let path = temp_cache_path("stale");
+    write_cached_device(&path, &LumatoneDevice::new("old out", "old in")).unwrap();
+
+    let (device, source) = detect_using(
+      Some(&path),
+      true,
+      |_| async { false },
+      || scan_finds("new out", "new in"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source, DetectionSource::Scan);
+    assert_eq!(device.output_port_name(), "new out");
+
+    // the cache is updated with the newly found ports
+    let cached = read_cached_device(&path).unwrap();
+    assert_eq!(cached.output_port_name(), "new out");
+    assert_eq!(cached.input_port_name(), "new in");
+  }
+
+  #[tokio::test]
+  async fn test_ignore_cache() {
+    let path = temp_cache_path("ignored");
+    write_cached_device(&path, &LumatoneDevice::new("cached out", "cached in")).unwrap();
+
+    let (device, source) = detect_using(
+      Some(&path),
+      false,
+      |_| async { panic!("should not probe cached ports when the cache is ignored") },
+      || scan_finds("scanned out", "scanned in"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source, DetectionSource::Scan);
+    assert_eq!(device.input_port_name(), "scanned in");
+  }
+
+  #[tokio::test]
+  async fn test_missing_cache_scans() {
+    let path = temp_cache_path("missing");
+    let (_, source) = detect_using(
+      Some(&path),
+      true,
+      |_| async { true },
+      || scan_finds("out", "in"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(source, DetectionSource::Scan);
+    assert!(path.exists());
+  }
+
+  fn waves(window_ms: u64, delay_ms: u64, total_ms: u64) -> PingWaves {
+    PingWaves {
+      wave_window: Duration::from_millis(window_ms),
+      inter_wave_delay: Duration::from_millis(delay_ms),
+      total_timeout: Duration::from_millis(total_ms),
     }
   }
 
-  let mut in_port_idx: Option<usize> = None;
-  let mut out_port_idx: Option<usize> = None;
-  let with_timeout = timeout(Duration::from_secs(30), rx.recv());
-  while let Ok(Some((in_port_index, out_port_index))) = with_timeout.await {
-    in_port_idx = Some(in_port_index);
-    out_port_idx = Some(out_port_index);
-    break;
+  #[test]
+  fn test_wave_offsets() {
+    let ms = Duration::from_millis;
+    assert_eq!(PingWaves::default().wave_offsets(), vec![Duration::ZERO]);
+    assert_eq!(
+      waves(100, 20, 400).wave_offsets(),
+      vec![ms(0), ms(120), ms(240), ms(360)]
+    );
+    // a wave that would start right at the deadline is skipped
+    assert_eq!(
+      waves(100, 0, 300).wave_offsets(),
+      vec![ms(0), ms(100), ms(200)]
+    );
+    assert_eq!(waves(0, 0, 300).wave_offsets(), vec![ms(0)]);
+  }
+
+  #[tokio::test]
+  async fn test_waves_stop_at_first_response() {
+    let (tx, mut rx) = mpsc::channel(4);
+    let mut sent = vec![];
+    let response = waves(20, 5, 1000)
+      .run(
+        |wave| {
+          sent.push(wave);
+          // the device only hears the third wave
+          if wave == 2 {
+            tx.try_send("pong").unwrap();
+          }
+        },
+        &mut rx,
+      )
+      .await;
+
+    assert_eq!(response, Some("pong"));
+    assert_eq!(sent, vec![0, 1, 2]);
+  }
+
+  #[tokio::test]
+  async fn test_waves_give_up_after_total_timeout() {
+    let (_tx, mut rx) = mpsc::channel::<()>(1);
+    let mut sent = 0;
+    let started = std::time::Instant::now();
+    let response = waves(10, 5, 50).run(|_| sent += 1, &mut rx).await;
+
+    assert_eq!(response, None);
+    assert_eq!(sent, 4);
+    assert!(started.elapsed() >= Duration::from_millis(50));
+  }
+
+  #[tokio::test]
+  async fn test_collect_keeps_listening_after_first_response() {
+    let (tx, mut rx) = mpsc::channel(4);
+    let late = tx.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      late.send("second").await.unwrap();
+      tokio::time::sleep(Duration::from_millis(300)).await;
+      let _ = late.send("too late").await;
+    });
+    let collected = waves(1000, 0, 1000)
+      .collect(
+        |_| tx.try_send("first").unwrap(),
+        Duration::from_millis(100),
+        &mut rx,
+      )
+      .await;
+    assert_eq!(collected, vec!["first", "second"]);
+  }
+
+  #[tokio::test]
+  async fn test_collect_stops_at_total_timeout() {
+    let (tx, mut rx) = mpsc::channel(4);
+    let started = std::time::Instant::now();
+    let collected = waves(50, 0, 50)
+      .collect(
+        |_| tx.try_send(()).unwrap(),
+        Duration::from_secs(5),
+        &mut rx,
+      )
+      .await;
+    assert_eq!(collected.len(), 1);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let (_tx, mut rx) = mpsc::channel::<()>(1);
+    let collected = waves(10, 0, 30)
+      .collect(|_| {}, Duration::from_secs(5), &mut rx)
+      .await;
+    assert!(collected.is_empty());
   }
 
-  if in_port_idx.is_none() || out_port_idx.is_none() {
-    return Err(LumatoneMidiError::DeviceDetectionFailed("unable to detect ports".to_string()));
+  #[test]
+  fn test_answered_port_pairs() {
+    // two devices, each answering two waves
+    let answers = vec![(1, 0), (3, 2), (1, 0), (3, 2)];
+    assert_eq!(answered_port_pairs(answers), vec![(1, 0), (3, 2)]);
+
+    // one device whose answers also show up on a merging input port
+    let answers = vec![(1, 0), (4, 0), (4, 0), (1, 0)];
+    assert_eq!(answered_port_pairs(answers), vec![(1, 0)]);
   }
 
-  let output_port_name = output
-    .port_name(&out_ports[out_port_idx.unwrap()])
-    .map_err(|e| DeviceDetectionFailed(format!("failed to get output port name: {e}")))?;
-  let input_port_name = input
-    .port_name(&in_ports[in_port_idx.unwrap()])
-    .map_err(|e| DeviceDetectionFailed(format!("failed to get input port name: {e}")))?;
+  #[test]
+  fn test_only_device() {
+    assert!(matches!(
+      only_device(vec![]),
+      Err(LumatoneMidiError::DeviceDetectionFailed(_))
+    ));
 
-  info!("detected lumatone ports: in: {input_port_name}, out: {output_port_name}");
+    let studio = LumatoneDevice::new("studio out", "studio in");
+    let device = only_device(vec![studio.clone()]).unwrap();
+    assert_eq!(device.output_port_name(), "studio out");
 
-  let device = LumatoneDevice::new(&output_port_name, &input_port_name);
-  Ok(device)
+    let stage = LumatoneDevice::new("stage out", "stage in");
+    match only_device(vec![studio, stage]) {
+      Err(LumatoneMidiError::DeviceDetectionFailed(msg)) => assert_eq!(
+        msg,
+        "found 2 Lumatones (in: studio in, out: studio out; in: stage in, out: stage out); \
+         configure the ports of the one to use"
+      ),
+      other => panic!("expected DeviceDetectionFailed, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_port_probe_round_trip() {
+    for port_index in [0, 1, 5, 127, 128, 300, (1 << 21) - 1] {
+      let probe = port_probe_ping(port_index).to_sysex_message();
+      let answer = reply_with_status(&probe, ResponseStatusCode::Ack);
+      assert_eq!(decode_port_probe(&answer).unwrap(), port_index);
+
+      // the probe itself, looped back without reaching the device, doesn't count
+      assert!(decode_port_probe(&probe).is_err());
+    }
+  }
 }
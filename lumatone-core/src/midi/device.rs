@@ -22,6 +22,14 @@ impl LumatoneDevice {
     }
   }
 
+  pub fn output_port_name(&self) -> &str {
+    &self.out_port_name
+  }
+
+  pub fn input_port_name(&self) -> &str {
+    &self.in_port_name
+  }
+
   /// Connects to the MIDI ports for this LumatoneDevice.
   /// Returns a [`LumatoneIO`] on success.
   pub fn connect(&self) -> Result<LumatoneIO, LumatoneMidiError> {
@@ -83,6 +91,39 @@ pub struct LumatoneIO {
   pub incoming_messages: mpsc::Receiver<EncodedSysex>,
 }
 
+/// The connection the driver uses to talk to a device. Implemented by [LumatoneIO] for
+/// ports that midir can open, and by a simulated device in tests.
+///
+/// Devices reached some other way, like an RTP-MIDI session or a serial bridge, can be
+/// driven by implementing this and passing it to
+/// [MidiDriver::with_transport](super::driver::MidiDriver::with_transport).
+pub trait MidiTransport: Send {
+  /// Sends an encoded sysex message to the device. This shouldn't block for long, since
+  /// it's called from the driver's event loop.
+  fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError>;
+
+  /// The channel that incoming sysex messages from the device are pushed onto.
+  fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex>;
+
+  /// Closes the connection. Called once the driver's event loop stops. By default, the
+  /// transport is just dropped.
+  fn close(self: Box<Self>) {}
+}
+
+impl MidiTransport for LumatoneIO {
+  fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+    LumatoneIO::send(self, msg)
+  }
+
+  fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex> {
+    &mut self.incoming_messages
+  }
+
+  fn close(self: Box<Self>) {
+    LumatoneIO::close(*self)
+  }
+}
+
 impl LumatoneIO {
   /// Sends an encoded sysex message to the Lumatone.
   pub fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
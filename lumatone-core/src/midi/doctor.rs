@@ -0,0 +1,652 @@
+//! An end-to-end check of the connection to a Lumatone, for troubleshooting.
+//!
+//! [run_checks] goes from listing MIDI ports, through detecting and connecting to the
+//! device, to reading a board's keys, and returns a [DoctorReport] with a pass, warn, fail
+//! or skip result for each step. A step that depends on one that failed is skipped, with
+//! the reason, so the report always has every step in it.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::{
+  client::{Client, DeviceInfo},
+  commands::Command,
+  constants::{BoardIndex, HardwareModel, LumatoneKeyFunction, MidiChannel},
+  detect::{DetectionSource, MidiPorts},
+  device::LumatoneDevice,
+  error::LumatoneMidiError,
+  responses::{unexpected_response, Response},
+};
+
+/// How many pings the ping check sends.
+pub const PING_COUNT: usize = 10;
+
+/// How many pings in a row have to go unanswered, with none answered before them, for the
+/// ping check to stop early.
+const SILENT_PING_LIMIT: usize = 3;
+
+const PORTS: &str = "MIDI ports";
+const DETECTION: &str = "Detection";
+const CONNECTION: &str = "Connection";
+const PING: &str = "Ping";
+const FIRMWARE: &str = "Firmware";
+const NOTES: &str = "Board 1 notes";
+const KEY_TYPES: &str = "Board 1 key types";
+
+/// How a check went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+  Pass,
+  /// Worked, but something looks off.
+  Warn,
+  Fail,
+  /// Not run, because a check it depends on failed.
+  Skip,
+}
+
+impl Display for CheckStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CheckStatus::Pass => write!(f, "pass"),
+      CheckStatus::Warn => write!(f, "warn"),
+      CheckStatus::Fail => write!(f, "fail"),
+      CheckStatus::Skip => write!(f, "skip"),
+    }
+  }
+}
+
+/// The result of one step of [run_checks].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+  pub name: &'static str,
+  pub status: CheckStatus,
+  /// What was found, or why the check failed or was skipped.
+  pub detail: String,
+}
+
+impl CheckResult {
+  fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+    CheckResult {
+      name,
+      status,
+      detail: detail.into(),
+    }
+  }
+}
+
+impl Display for CheckResult {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "[{}] {}: {}", self.status, self.name, self.detail)
+  }
+}
+
+/// The results of [run_checks], in the order they ran. Displays as one line per check
+/// and a summary, for pasting into bug reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DoctorReport {
+  /// The version of lumatone-core that ran the checks.
+  pub version: String,
+  /// The OS and CPU architecture, e.g. `linux/x86_64`.
+  pub platform: String,
+  pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+  pub fn new(checks: Vec<CheckResult>) -> Self {
+    DoctorReport {
+      version: env!("CARGO_PKG_VERSION").to_string(),
+      platform: format!("{}/{}", std::env::consts::OS, std::env::consts::ARCH),
+      checks,
+    }
+  }
+
+  /// The number of checks with the given `status`.
+  pub fn count(&self, status: CheckStatus) -> usize {
+    self.checks.iter().filter(|c| c.status == status).count()
+  }
+
+  /// Returns true if no check failed. Warnings and skipped checks don't count.
+  pub fn passed(&self) -> bool {
+    self.count(CheckStatus::Fail) == 0
+  }
+
+  /// e.g. "5 passed, 1 warning, 0 failed, 1 skipped"
+  pub fn summary(&self) -> String {
+    let warnings = self.count(CheckStatus::Warn);
+    format!(
+      "{} passed, {} warning{}, {} failed, {} skipped",
+      self.count(CheckStatus::Pass),
+      warnings,
+      if warnings == 1 { "" } else { "s" },
+      self.count(CheckStatus::Fail),
+      self.count(CheckStatus::Skip)
+    )
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).expect("failed to serialize doctor report")
+  }
+}
+
+impl Display for DoctorReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "lumatone-rs {} ({})", self.version, self.platform)?;
+    for check in &self.checks {
+      writeln!(f, "{check}")?;
+    }
+    write!(f, "{}", self.summary())
+  }
+}
+
+/// Runs every check, in order: the MIDI `ports`, detecting the device with `detect`,
+/// connecting to it with `connect`, then pinging it [PING_COUNT] times, reading its
+/// firmware version and serial number, and reading the notes and key types of board 1.
+///
+/// The device I/O is passed in so the same checks can run from the CLI or the GUI,
+/// each with its own driver settings, and be tested without hardware.
+pub async fn run_checks<Detect, DetectFut, Connect>(
+  ports: Result<MidiPorts, LumatoneMidiError>,
+  detect: Detect,
+  connect: Connect,
+) -> DoctorReport
+where
+  Detect: FnOnce() -> DetectFut,
+  DetectFut: Future<Output = Result<(LumatoneDevice, DetectionSource), LumatoneMidiError>>,
+  Connect: FnOnce(&LumatoneDevice) -> Result<Client, LumatoneMidiError>,
+{
+  use CheckStatus::*;
+  let mut checks = vec![ports_check(&ports)];
+
+  // if listing the ports failed, detection might still manage
+  let no_ports = matches!(&ports, Ok(p) if p.inputs.is_empty() || p.outputs.is_empty());
+  let device = if no_ports {
+    checks.push(CheckResult::new(DETECTION, Skip, "no MIDI ports to scan"));
+    None
+  } else {
+    let (check, device) = detection_check(detect().await);
+    checks.push(check);
+    device
+  };
+
+  let client = match device {
+    None => {
+      checks.push(CheckResult::new(CONNECTION, Skip, "no device was found"));
+      None
+    }
+    Some(device) => match connect(&device) {
+      Ok(client) => {
+        checks.push(CheckResult::new(CONNECTION, Pass, "connected"));
+        Some(client)
+      }
+      Err(err) => {
+        checks.push(CheckResult::new(CONNECTION, Fail, err.to_string()));
+        None
+      }
+    },
+  };
+
+  match client {
+    Some(client) => {
+      checks.extend(device_checks(&client).await);
+      if let Err(err) = client.close().await {
+        log::warn!("error shutting down driver after checks: {err}");
+      }
+    }
+    None => {
+      for name in [PING, FIRMWARE, NOTES, KEY_TYPES] {
+        checks.push(CheckResult::new(name, Skip, "not connected"));
+      }
+    }
+  }
+
+  DoctorReport::new(checks)
+}
+
+/// The checks that talk to a connected device.
+async fn device_checks(client: &Client) -> Vec<CheckResult> {
+  let mut pings = vec![];
+  for _ in 0..PING_COUNT {
+    pings.push(client.ping().await);
+    // each lost ping waits out the receive timeout, so give up on a silent device early,
+    // but not over one lost ping
+    if pings.len() >= SILENT_PING_LIMIT && pings.iter().all(Result::is_err) {
+      break;
+    }
+  }
+  let ping = ping_check(&pings);
+  if ping.status == CheckStatus::Fail {
+    let mut checks = vec![ping];
+    for name in [FIRMWARE, NOTES, KEY_TYPES] {
+      checks.push(CheckResult::new(
+        name,
+        CheckStatus::Skip,
+        "the device didn't answer any pings",
+      ));
+    }
+    return checks;
+  }
+
+  let (firmware, model) = firmware_check(client.info().await);
+  let notes = notes_check(
+    client
+      .send(Command::GetNoteConfig(BoardIndex::Octave1))
+      .await,
+    model,
+  );
+  let key_types = key_types_check(
+    client
+      .send(Command::GetKeyTypeConfig(BoardIndex::Octave1))
+      .await,
+  );
+  vec![ping, firmware, notes, key_types]
+}
+
+fn ports_check(ports: &Result<MidiPorts, LumatoneMidiError>) -> CheckResult {
+  use CheckStatus::*;
+  match ports {
+    Ok(ports) => {
+      let status = if ports.inputs.is_empty() || ports.outputs.is_empty() {
+        Fail
+      } else {
+        Pass
+      };
+      let describe = |kind: &str, names: &[String]| match names.len() {
+        0 => format!("no {kind}s"),
+        1 => format!("1 {kind} ({})", names[0]),
+        n => format!("{n} {kind}s ({})", names.join(", ")),
+      };
+      let detail = format!(
+        "{}, {}",
+        describe("input", &ports.inputs),
+        describe("output", &ports.outputs)
+      );
+      CheckResult::new(PORTS, status, detail)
+    }
+    Err(err) => CheckResult::new(PORTS, Fail, err.to_string()),
+  }
+}
+
+fn detection_check(
+  result: Result<(LumatoneDevice, DetectionSource), LumatoneMidiError>,
+) -> (CheckResult, Option<LumatoneDevice>) {
+  match result {
+    Ok((device, source)) => {
+      let how = match source {
+        DetectionSource::Cache => "answered on the cached ports",
+        DetectionSource::Scan => "found by scanning all ports",
+      };
+      let detail = format!(
+        "{how} (in: {}, out: {})",
+        device.input_port_name(),
+        device.output_port_name()
+      );
+      (
+        CheckResult::new(DETECTION, CheckStatus::Pass, detail),
+        Some(device),
+      )
+    }
+    Err(err) => {
+      let detail = format!(
+        "{err}. Check that the Lumatone is on and plugged in, and that no other program \
+         (like the Lumatone Editor) is using its ports"
+      );
+      (CheckResult::new(DETECTION, CheckStatus::Fail, detail), None)
+    }
+  }
+}
+
+/// Passes if every ping was answered, warns if some were, and fails if none were.
+fn ping_check(results: &[Result<Duration, LumatoneMidiError>]) -> CheckResult {
+  let latencies: Vec<Duration> = results
+    .iter()
+    .filter_map(|r| r.as_ref().ok())
+    .copied()
+    .collect();
+  let (min, max) = match (latencies.iter().min(), latencies.iter().max()) {
+    (Some(min), Some(max)) => (*min, *max),
+    _ => {
+      let reason = results
+        .iter()
+        .find_map(|r| r.as_ref().err())
+        .map_or("no pings were sent".to_string(), |err| err.to_string());
+      return CheckResult::new(PING, CheckStatus::Fail, format!("no answer: {reason}"));
+    }
+  };
+  let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+  let status = if latencies.len() == results.len() {
+    CheckStatus::Pass
+  } else {
+    CheckStatus::Warn
+  };
+  let detail = format!(
+    "{} of {} answered; min {}, mean {}, max {}",
+    latencies.len(),
+    results.len(),
+    millis(min),
+    millis(mean),
+    millis(max)
+  );
+  CheckResult::new(PING, status, detail)
+}
+
+fn millis(duration: Duration) -> String {
+  format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn firmware_check(
+  result: Result<DeviceInfo, LumatoneMidiError>,
+) -> (CheckResult, Option<HardwareModel>) {
+  match result {
+    Ok(info) => {
+      let serial = match info.serial_id {
+        Some(id) => {
+          let bytes: Vec<String> = id.iter().map(|b| format!("{b:02x}")).collect();
+          format!("serial {}", bytes.join(":"))
+        }
+        None => "no serial number (early firmware)".to_string(),
+      };
+      let detail = format!(
+        "version {}, {serial}, {} keys per board",
+        info.firmware, info.model.key_count_per_board
+      );
+      (
+        CheckResult::new(FIRMWARE, CheckStatus::Pass, detail),
+        Some(info.model),
+      )
+    }
+    Err(err) => (
+      CheckResult::new(FIRMWARE, CheckStatus::Fail, err.to_string()),
+      None,
+    ),
+  }
+}
+
+/// Warns if the board reports fewer notes than the `model` has keys.
+fn notes_check(
+  result: Result<Response, LumatoneMidiError>,
+  model: Option<HardwareModel>,
+) -> CheckResult {
+  let notes = match result {
+    Ok(Response::NoteConfig(_, notes)) => notes,
+    Ok(other) => {
+      return CheckResult::new(
+        NOTES,
+        CheckStatus::Fail,
+        unexpected_response("notes", &other).to_string(),
+      )
+    }
+    Err(err) => return CheckResult::new(NOTES, CheckStatus::Fail, err.to_string()),
+  };
+  let range = match (notes.iter().min(), notes.iter().max()) {
+    (Some(lowest), Some(highest)) => format!("notes {lowest} to {highest}"),
+    _ => "no notes".to_string(),
+  };
+  let expected = model.map(|m| m.key_count_per_board as usize);
+  match expected {
+    Some(expected) if notes.len() < expected => CheckResult::new(
+      NOTES,
+      CheckStatus::Warn,
+      format!("{} keys, expected {expected}; {range}", notes.len()),
+    ),
+    _ => CheckResult::new(
+      NOTES,
+      CheckStatus::Pass,
+      format!("{} keys; {range}", notes.len()),
+    ),
+  }
+}
+
+/// Fails if any key has a type code that doesn't match a [LumatoneKeyFunction].
+fn key_types_check(result: Result<Response, LumatoneMidiError>) -> CheckResult {
+  let types = match result {
+    Ok(Response::KeyTypeConfig(_, types)) => types,
+    Ok(other) => {
+      return CheckResult::new(
+        KEY_TYPES,
+        CheckStatus::Fail,
+        unexpected_response("key types", &other).to_string(),
+      )
+    }
+    Err(err) => return CheckResult::new(KEY_TYPES, CheckStatus::Fail, err.to_string()),
+  };
+  let unknown: Vec<String> = types
+    .iter()
+    .enumerate()
+    .filter(|(_, t)| LumatoneKeyFunction::from_type_code(**t, MidiChannel::default(), 0).is_none())
+    .map(|(key, t)| format!("key {key} ({t})"))
+    .collect();
+  if unknown.is_empty() {
+    CheckResult::new(
+      KEY_TYPES,
+      CheckStatus::Pass,
+      format!("all {} keys have a known type", types.len()),
+    )
+  } else {
+    CheckResult::new(
+      KEY_TYPES,
+      CheckStatus::Fail,
+      format!("unknown type codes: {}", unknown.join(", ")),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{ping_check, ports_check, run_checks, CheckResult, CheckStatus, DoctorReport};
+  use crate::midi::{
+    client::Client,
+    constants::{BoardIndex, CommandId, ResponseStatusCode},
+    detect::{DetectionSource, MidiPorts},
+    device::LumatoneDevice,
+    driver::MidiDriverConfig,
+    error::LumatoneMidiError,
+    mock::{reply_with_status, MockDevice, Responder},
+    sysex::{create_sysex, message_command_id, BOARD_IND},
+  };
+
+  fn ports() -> MidiPorts {
+    MidiPorts {
+      inputs: vec!["Lumatone".to_string()],
+      outputs: vec!["Lumatone".to_string(), "Through".to_string()],
+    }
+  }
+
+  /// A device with 56 keys per board, all notes, and an unknown type code on key 3 if
+  /// `bad_key_type` is set. Answers serial number requests like early firmware.
+  fn device(bad_key_type: bool) -> MockDevice {
+    MockDevice::new(responder(bad_key_type))
+  }
+
+  fn responder(bad_key_type: bool) -> Responder {
+    Box::new(move |msg| {
+      use CommandId::*;
+      let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+      let command = message_command_id(msg).unwrap();
+      let data = match command {
+        GetFirmwareRevision => vec![1, 2, 3],
+        GetNoteConfig => (0..56).map(|k| k + 30).collect(),
+        GetKeytypeConfig => (0..56)
+          .map(|k| if bad_key_type && k == 3 { 7 } else { 1 })
+          .collect(),
+        _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+      };
+      let canned = create_sysex(board, command, data);
+      Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+    })
+  }
+
+  async fn found() -> Result<(LumatoneDevice, DetectionSource), LumatoneMidiError> {
+    Ok((
+      LumatoneDevice::new("Lumatone", "Lumatone"),
+      DetectionSource::Cache,
+    ))
+  }
+
+  fn statuses(report: &DoctorReport) -> Vec<(&str, CheckStatus)> {
+    report.checks.iter().map(|c| (c.name, c.status)).collect()
+  }
+
+  #[tokio::test]
+  async fn test_all_checks_pass() {
+    let report = run_checks(Ok(ports()), found, |_| {
+      Ok(Client::with_transport(
+        Box::new(device(false)),
+        MidiDriverConfig::default(),
+      ))
+    })
+    .await;
+    assert!(report.passed());
+    assert_eq!(report.count(CheckStatus::Pass), 7);
+    assert_eq!(
+      report.checks[0].detail,
+      "1 input (Lumatone), 2 outputs (Lumatone, Through)"
+    );
+    assert_eq!(
+      report.checks[4].detail,
+      "version 1.2.3, no serial number (early firmware), 56 keys per board"
+    );
+    assert_eq!(report.checks[5].detail, "56 keys; notes 30 to 85");
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_lost_first_ping_doesnt_end_the_checks() {
+    let mut answer = responder(false);
+    let mut dropped_ping = false;
+    let device = MockDevice::new(Box::new(move |msg| {
+      if !dropped_ping && matches!(message_command_id(msg), Ok(CommandId::LumaPing)) {
+        dropped_ping = true;
+        return None;
+      }
+      answer(msg)
+    }));
+    let config = MidiDriverConfig {
+      max_timeout_retries: 0,
+      ..Default::default()
+    };
+    let report = run_checks(Ok(ports()), found, |_| {
+      Ok(Client::with_transport(Box::new(device), config))
+    })
+    .await;
+    let ping = &report.checks[3];
+    assert_eq!(ping.status, CheckStatus::Warn);
+    let detail = &ping.detail;
+    assert!(detail.starts_with("9 of 10 answered"), "{detail}");
+    assert_eq!(report.count(CheckStatus::Pass), 6);
+  }
+
+  #[tokio::test]
+  async fn test_unknown_key_type_fails() {
+    let report = run_checks(Ok(ports()), found, |_| {
+      Ok(Client::with_transport(
+        Box::new(device(true)),
+        MidiDriverConfig::default(),
+      ))
+    })
+    .await;
+    assert!(!report.passed());
+    let key_types = report.checks.last().unwrap();
+    assert_eq!(key_types.status, CheckStatus::Fail);
+    assert_eq!(key_types.detail, "unknown type codes: key 3 (7)");
+  }
+
+  #[tokio::test]
+  async fn test_later_checks_are_skipped() {
+    use CheckStatus::*;
+    let unused = |_: &LumatoneDevice| -> Result<Client, LumatoneMidiError> {
+      panic!("shouldn't connect without a device")
+    };
+
+    let not_found = || async {
+      Err(LumatoneMidiError::DeviceDetectionFailed(
+        "unable to detect ports".to_string(),
+      ))
+    };
+    let report = run_checks(Ok(ports()), not_found, unused).await;
+    assert_eq!(
+      statuses(&report),
+      vec![
+        ("MIDI ports", Pass),
+        ("Detection", Fail),
+        ("Connection", Skip),
+        ("Ping", Skip),
+        ("Firmware", Skip),
+        ("Board 1 notes", Skip),
+        ("Board 1 key types", Skip),
+      ]
+    );
+    assert_eq!(
+      report.summary(),
+      "1 passed, 0 warnings, 1 failed, 5 skipped"
+    );
+
+    let report = run_checks(Ok(MidiPorts::default()), found, unused).await;
+    assert_eq!(report.checks[0].detail, "no inputs, no outputs");
+    assert_eq!(
+      &statuses(&report)[..2],
+      &[("MIDI ports", Fail), ("Detection", Skip)]
+    );
+
+    let locked = |_: &LumatoneDevice| Err(LumatoneMidiError::DeviceBusy("in use".to_string()));
+    let report = run_checks(Ok(ports()), found, locked).await;
+    assert_eq!(
+      &statuses(&report)[2..4],
+      &[("Connection", Fail), ("Ping", Skip)]
+    );
+  }
+
+  #[test]
+  fn test_ping_check() {
+    let ms = |n| Ok(Duration::from_millis(n));
+    let lost = || Err(LumatoneMidiError::ResponseTimedOut("ping".to_string()));
+
+    let all = ping_check(&[ms(2), ms(4), ms(9)]);
+    assert_eq!(all.status, CheckStatus::Pass);
+    assert_eq!(
+      all.detail,
+      "3 of 3 answered; min 2.0 ms, mean 5.0 ms, max 9.0 ms"
+    );
+
+    let some = ping_check(&[ms(2), lost(), ms(4)]);
+    assert_eq!(some.status, CheckStatus::Warn);
+    assert!(some.detail.starts_with("2 of 3 answered"));
+
+    let none = ping_check(&[lost(), lost()]);
+    assert_eq!(none.status, CheckStatus::Fail);
+    assert_eq!(
+      none.detail,
+      "no answer: timed out waiting for response: ping"
+    );
+  }
+
+  #[test]
+  fn test_report_formatting() {
+    let report = DoctorReport::new(vec![
+      ports_check(&Ok(ports())),
+      CheckResult::new("Ping", CheckStatus::Warn, "9 of 10 answered"),
+      CheckResult::new("Firmware", CheckStatus::Skip, "not connected"),
+    ]);
+    assert!(report.passed());
+    assert_eq!(
+      report.to_string(),
+      format!(
+        "lumatone-rs {} ({})
+[pass] MIDI ports: 1 input (Lumatone), 2 outputs (Lumatone, Through)
+[warn] Ping: 9 of 10 answered
+[skip] Firmware: not connected
+1 passed, 1 warning, 0 failed, 1 skipped",
+        report.version, report.platform
+      )
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["checks"][1]["status"], "warn");
+    assert_eq!(json["checks"][2]["name"], "Firmware");
+    assert_eq!(json["version"], report.version);
+  }
+}
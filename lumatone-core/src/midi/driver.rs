@@ -6,12 +6,15 @@
 //! to send to the device. `send` is an async method whose Future will resolve when the device
 //! returns a [Response] or an error occurs.
 //!
-//! To create a [MidiDriver], use [MidiDriver::new], which returns a tuple of
-//! `(MidiDriver, Future)`. The Future needs to be spawned and `await`ed in order to start the
-//! driver's event loop.
+//! To create a [MidiDriver], use [MidiDriver::new], or [MidiDriver::builder] to change any of
+//! its settings. Either way you get a tuple of `(MidiDriver, Future)`. The Future needs to be
+//! spawned and `await`ed in order to start the driver's event loop.
 //!
 //! To shutdown the driver loop, use [MidiDriver::done].
 //!
+//! To watch the driver's activity, e.g. for a log view, use [MidiDriver::subscribe_events]
+//! and [MidiDriver::subscribe_trace].
+//!
 //!
 //! ## State machine internals
 //!
@@ -60,53 +63,545 @@
 //!                     │                      ┌────────┘
 //!                     └──────────────────────┘
 //! ```
+//!
+//! Busy responses and receive timeouts each have a retry budget, set in [MidiDriverConfig].
+//! Once a command's busy budget is used up, the caller gets a [LumatoneMidiError::DeviceBusy]
+//! error. When the timeout budget runs out, the driver moves to the `TimedOut` state, which
+//! reports a [LumatoneMidiError::ResponseTimedOut] and then returns to `ProcessingQueue`.
+//!
+//! A send that timed out may still get a response later. Since a re-sent command is
+//! byte-for-byte the same as the original, a response that arrives while the re-send is in
+//! flight resolves the command, whichever send it was for. Once a command has been resolved,
+//! the driver expects one more response for each of its sends that timed out, and drops those
+//! (as a `LateResponseReceived` action) instead of mistaking them for the answer to the
+//! next command. Only responses that echo the command back can be recognized this way. If
+//! the command in flight is identical to the resolved one, there's no telling their
+//! responses apart, so a matching response answers the command in flight first.
+//!
+//! Some messages aren't responses to anything: while key sampling is on, the device streams
+//! sensor readings, and boards may send status messages during calibration. Those skip the
+//! state machine entirely and go out as [DriverEvent]s, so they can't be mistaken for the
+//! response to a command that's in flight. Pings that come back unanswered
+//! (see [has_echo_flag]) are dropped for the same reason.
+//!
+//! ## Effects and the event loop
+//!
+//! [DriverLoop] feeds [Action]s into the state machine and carries out the resulting
+//! [Effect]s. Anything that touches the device or the clock (sending messages, starting
+//! and cancelling timeouts, and waiting for the next [Input]) goes through an
+//! [EffectExecutor]. [MidiDriver] uses one backed by the MIDI ports and tokio timers;
+//! the tests use one with a simulated device and a virtual clock, so that whole scenarios,
+//! timeouts and all, run deterministically and without sleeping.
 
 use super::{
+  calibration::{
+    CalibrationKind, CalibrationProgress, CalibrationStatus, CalibrationTiming, CalibrationTracker,
+  },
   commands::Command,
-  constants::ResponseStatusCode,
-  device::{LumatoneDevice, LumatoneIO},
-  error::LumatoneMidiError,
-  responses::Response,
-  sysex::{is_response_to_message, message_answer_code, EncodedSysex},
+  constants::{
+    BoardIndex, CommandId, LumatoneKeyLocation, PeripheralCalibrationMode, RGBColor,
+    ResponseStatusCode,
+  },
+  device::{LumatoneDevice, MidiTransport},
+  error::{KeyPart, LumatoneMidiError},
+  lock::DeviceLock,
+  responses::{
+    is_calibration_status_message, is_key_sample_message, is_peripheral_calibration_report,
+    is_peripheral_calibration_status_message, unexpected_response, FirmwareVersion,
+    PeripheralChannels, Response, Thresholds,
+  },
+  sampling::{KeySample, KeySamplingReport},
+  sysex::{has_echo_flag, is_echo_of, is_response_to_message, message_answer_code, EncodedSysex},
 };
 use std::{
-  collections::VecDeque,
+  collections::{HashMap, VecDeque},
   fmt::{Debug, Display},
   pin::Pin,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
   time::Duration,
 };
 
+use crate::keymap::ltn::KeyDefinition;
 use futures::{Future, TryFutureExt};
-use log::{debug, error, info, warn};
+use log::{debug, error, info, log_enabled, trace, warn, Level};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use tokio::{
-  sync::mpsc,
-  time::{sleep, Sleep},
+  sync::{broadcast, mpsc, watch},
+  time::{sleep, timeout_at, Instant, Sleep},
 };
 
 use super::driver::Action::{MessageSent, QueueEmpty, ResponseDispatched};
-use super::sysex::to_hex_debug_str;
+use super::sysex::{describe, to_hex_debug_str};
 
 /// Result type returned in response to a command submission
 type ResponseResult = Result<Response, LumatoneMidiError>;
 
+/// The default for [MidiDriverConfig::retry_timeout].
+const RETRY_DELAY: Duration = Duration::from_secs(3);
+
+/// Returns `timeout`, moved by a random amount within `jitter` of it if there is one.
+/// See [MidiDriverConfig::retry_jitter].
+fn retry_delay(timeout: Duration, jitter: Option<Duration>, rng: &mut impl Rng) -> Duration {
+  let jitter = match jitter {
+    Some(j) if !j.is_zero() => j.min(timeout),
+    _ => return timeout,
+  };
+  let offset = rng.gen_range(Duration::ZERO..=jitter * 2);
+  timeout - jitter + offset
+}
+
+/// Returns true if `msg` echoes the command we're awaiting a response to. A command that's
+/// sent again after an identical one timed out gets the same response as the late one we're
+/// expecting, so a response that fits both is taken as the answer to the command in flight.
+/// If the late one shows up after all, it's then dropped in its place.
+fn answers_command_in_flight(msg: &[u8], state: &State) -> bool {
+  match state {
+    State::AwaitingResponse { command_sent, .. } => {
+      is_echo_of(&command_sent.command.to_sysex_message(), msg)
+    }
+    _ => false,
+  }
+}
+
+/// How many resolved commands to keep expecting late responses for.
+const MAX_LATE_RESPONSE_COMMANDS: usize = 16;
+
+/// The default for [MidiDriverConfig::event_capacity]. Key sampling streams readings much
+/// faster than anything else sends events, so this leaves room for a few seconds' worth.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Notifications about the driver's activity, for things that aren't the response
+/// to any one command. See [MidiDriver::subscribe_events].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriverEvent {
+  /// The driver has dispatched the response to the last command in its queue and is idle.
+  QueueDrained,
+
+  /// The device answered a command with a Busy status (or the State status it uses
+  /// in demo mode). Sent whether or not the command will be retried.
+  DeviceBusy,
+
+  /// The device sent a frame of key sensor readings, while key sampling is enabled.
+  KeySample(KeySample),
+
+  /// A board sent a status message during key or aftertouch calibration.
+  CalibrationStatus(CalibrationStatus),
+}
+
+/// The default for [MidiDriverConfig::trace_capacity].
+const TRACE_CHANNEL_CAPACITY: usize = 64;
+
+/// A record of how one command was resolved, for logging and diagnostics.
+/// See [MidiDriver::subscribe_trace].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTrace {
+  /// The command, as shown by its `Display` impl.
+  pub command: String,
+
+  /// The response the command resolved with, or the error it failed with, as text.
+  pub result: Result<String, String>,
+
+  /// How long it took from first sending the command to resolving it, including any retries.
+  /// `None` if the command was never sent.
+  pub latency: Option<Duration>,
+}
+
+/// What the event loop has gotten through, for [MidiDriver::flush] to wait on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Progress {
+  /// How many commands the loop has taken from the command channel.
+  accepted: u64,
+  /// Whether the loop is idle, with nothing queued or in flight.
+  idle: bool,
+}
+
+impl Default for Progress {
+  fn default() -> Self {
+    // the loop starts out idle
+    Progress {
+      accepted: 0,
+      idle: true,
+    }
+  }
+}
+
+/// Configuration options for a [MidiDriver].
+#[derive(Debug, Clone)]
+pub struct MidiDriverConfig {
+  /// How many times to re-send a command after the device says it's busy before giving up.
+  pub max_busy_retries: usize,
+
+  /// How many times to re-send a command after timing out waiting for a response before giving up.
+  /// A timeout may mean that the device has gone away, so this is usually lower than `max_busy_retries`.
+  pub max_timeout_retries: usize,
+
+  /// How long to wait for the device to respond to a command.
+  pub receive_timeout: Duration,
+
+  /// How long to wait before re-sending a command that the device was too busy for.
+  pub retry_timeout: Duration,
+
+  /// Whether to warn about status reports the device sends on its own during pedal and wheel
+  /// calibration. They arrive every 100ms, so they're only logged at trace level by default.
+  /// Other messages that arrive when no response is expected are always warned about.
+  pub log_unsolicited: bool,
+
+  /// Whether to connect even if another running process holds the device's
+  /// [lock](super::lock::DeviceLock).
+  pub steal_lock: bool,
+
+  /// If set, `retry_timeout` is moved randomly by up to this much in either direction for
+  /// each retry, so that retries from several senders don't all arrive at once. Capped at
+  /// `retry_timeout` itself.
+  pub retry_jitter: Option<Duration>,
+
+  /// How many [DriverEvent]s each [subscriber](MidiDriver::subscribe_events) can fall
+  /// behind by. Past that, the oldest are dropped, and the subscriber's next receive fails
+  /// with [broadcast::error::RecvError::Lagged] saying how many it missed. So a subscriber
+  /// that stops reading holds on to at most this many, and while nobody is subscribed,
+  /// events aren't kept at all. At least 1.
+  pub event_capacity: usize,
+
+  /// Like `event_capacity`, for the [CommandTrace]s from [MidiDriver::subscribe_trace].
+  pub trace_capacity: usize,
+}
+
+impl Default for MidiDriverConfig {
+  fn default() -> Self {
+    MidiDriverConfig {
+      max_busy_retries: 10,
+      max_timeout_retries: 1,
+      receive_timeout: Duration::from_secs(30),
+      retry_timeout: RETRY_DELAY,
+      log_unsolicited: false,
+      steal_lock: false,
+      retry_jitter: None,
+      event_capacity: EVENT_CHANNEL_CAPACITY,
+      trace_capacity: TRACE_CHANNEL_CAPACITY,
+    }
+  }
+}
+
+/// A setting of a [MidiDriverBuilder] that doesn't make sense on its own or alongside the
+/// others. Returned, wrapped in [LumatoneMidiError::InvalidDriverConfig], when building.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriverConfigError {
+  /// A zero `receive_timeout` would time out every command before the device could answer.
+  ZeroReceiveTimeout,
+
+  /// `retry_jitter` is bigger than `retry_timeout`, so it would be capped anyway.
+  JitterExceedsRetryTimeout {
+    retry_jitter: Duration,
+    retry_timeout: Duration,
+  },
+
+  /// An event or trace channel was given no room, so subscribers couldn't get anything.
+  /// Holds the name of the setting, e.g. `"event_capacity"`.
+  ZeroCapacity(&'static str),
+}
+
+impl Display for DriverConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use DriverConfigError::*;
+    match self {
+      ZeroReceiveTimeout => write!(f, "receive_timeout must be longer than zero"),
+      JitterExceedsRetryTimeout {
+        retry_jitter,
+        retry_timeout,
+      } => write!(
+        f,
+        "retry_jitter ({retry_jitter:?}) can't be more than retry_timeout ({retry_timeout:?})"
+      ),
+      ZeroCapacity(setting) => write!(f, "{setting} must be at least 1"),
+    }
+  }
+}
+
+/// Builds a [MidiDriver], starting from the [defaults](MidiDriverConfig::default) and
+/// checking that the settings make sense together before anything is connected.
+///
+/// Each setter corresponds to a field of [MidiDriverConfig], which documents it in full.
+/// New driver options are added here, rather than as more arguments to [MidiDriver::new].
+///
+/// ```
+/// use std::time::Duration;
+/// use lumatone_core::midi::driver::MidiDriver;
+///
+/// let config = MidiDriver::builder()
+///   .max_busy_retries(5)
+///   .max_timeout_retries(2)
+///   .receive_timeout(Duration::from_secs(5))
+///   .retry_timeout(Duration::from_secs(1))
+///   .retry_jitter(Duration::from_millis(250))
+///   .log_unsolicited(true)
+///   .steal_lock(true)
+///   .event_capacity(1024)
+///   .trace_capacity(16)
+///   .config()
+///   .unwrap();
+/// assert_eq!(config.max_busy_retries, 5);
+/// assert_eq!(config.retry_jitter, Some(Duration::from_millis(250)));
+/// ```
+///
+/// To connect, pass the device to [build](Self::build), or a transport that's already
+/// connected to [build_with_transport](Self::build_with_transport):
+///
+/// ```no_run
+/// # async fn example() -> Result<(), lumatone_core::midi::error::LumatoneMidiError> {
+/// use lumatone_core::midi::{commands::Command, device::LumatoneDevice, driver::MidiDriver};
+///
+/// let device = LumatoneDevice::new("Lumatone", "Lumatone");
+/// let (driver, driver_future) = MidiDriver::builder().max_busy_retries(3).build(&device)?;
+/// tokio::spawn(driver_future);
+/// driver.send(Command::Ping(1)).await?;
+/// driver.done().await
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MidiDriverBuilder {
+  config: MidiDriverConfig,
+}
+
+impl MidiDriverBuilder {
+  /// Starts from an existing `config` instead of the defaults, e.g. one loaded from settings.
+  pub fn from_config(config: MidiDriverConfig) -> Self {
+    MidiDriverBuilder { config }
+  }
+
+  /// How many times to re-send a command the device was too busy for. Defaults to 10.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// // give up as soon as the device says it's busy
+  /// let config = MidiDriver::builder().max_busy_retries(0).config().unwrap();
+  /// assert_eq!(config.max_busy_retries, 0);
+  /// ```
+  pub fn max_busy_retries(mut self, retries: usize) -> Self {
+    self.config.max_busy_retries = retries;
+    self
+  }
+
+  /// How many times to re-send a command that got no answer. Defaults to 1.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder().max_timeout_retries(3).config().unwrap();
+  /// assert_eq!(config.max_timeout_retries, 3);
+  /// ```
+  pub fn max_timeout_retries(mut self, retries: usize) -> Self {
+    self.config.max_timeout_retries = retries;
+    self
+  }
+
+  /// How long to wait for each answer. Defaults to 30 seconds, and can't be zero.
+  ///
+  /// ```
+  /// # use std::time::Duration;
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder()
+  ///   .receive_timeout(Duration::from_secs(2))
+  ///   .config()
+  ///   .unwrap();
+  /// assert_eq!(config.receive_timeout, Duration::from_secs(2));
+  /// assert!(MidiDriver::builder().receive_timeout(Duration::ZERO).config().is_err());
+  /// ```
+  pub fn receive_timeout(mut self, timeout: Duration) -> Self {
+    self.config.receive_timeout = timeout;
+    self
+  }
+
+  /// How long to wait before re-sending a command the device was too busy for. Defaults to
+  /// 3 seconds.
+  ///
+  /// ```
+  /// # use std::time::Duration;
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder()
+  ///   .retry_timeout(Duration::from_millis(500))
+  ///   .config()
+  ///   .unwrap();
+  /// assert_eq!(config.retry_timeout, Duration::from_millis(500));
+  /// ```
+  pub fn retry_timeout(mut self, timeout: Duration) -> Self {
+    self.config.retry_timeout = timeout;
+    self
+  }
+
+  /// How far to move each retry delay randomly in either direction. Off by default, and
+  /// can't be more than the [retry_timeout](Self::retry_timeout).
+  ///
+  /// ```
+  /// # use std::time::Duration;
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let builder = MidiDriver::builder().retry_timeout(Duration::from_secs(1));
+  /// assert!(builder.clone().retry_jitter(Duration::from_millis(200)).config().is_ok());
+  /// assert!(builder.retry_jitter(Duration::from_secs(2)).config().is_err());
+  /// ```
+  pub fn retry_jitter(mut self, jitter: Duration) -> Self {
+    self.config.retry_jitter = Some(jitter);
+    self
+  }
+
+  /// Whether to warn about the status reports the device sends during calibration.
+  /// Off by default.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder().log_unsolicited(true).config().unwrap();
+  /// assert!(config.log_unsolicited);
+  /// ```
+  pub fn log_unsolicited(mut self, log: bool) -> Self {
+    self.config.log_unsolicited = log;
+    self
+  }
+
+  /// Whether to connect even if another process holds the device's lock. Off by default.
+  /// Has no effect with [build_with_transport](Self::build_with_transport), which doesn't
+  /// take the lock.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder().steal_lock(true).config().unwrap();
+  /// assert!(config.steal_lock);
+  /// ```
+  pub fn steal_lock(mut self, steal: bool) -> Self {
+    self.config.steal_lock = steal;
+    self
+  }
+
+  /// How many events each subscriber can fall behind by. Defaults to 256, and must be at
+  /// least 1.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder().event_capacity(4096).config().unwrap();
+  /// assert_eq!(config.event_capacity, 4096);
+  /// assert!(MidiDriver::builder().event_capacity(0).config().is_err());
+  /// ```
+  pub fn event_capacity(mut self, capacity: usize) -> Self {
+    self.config.event_capacity = capacity;
+    self
+  }
+
+  /// How many command traces each subscriber can fall behind by. Defaults to 64, and must
+  /// be at least 1.
+  ///
+  /// ```
+  /// # use lumatone_core::midi::driver::MidiDriver;
+  /// let config = MidiDriver::builder().trace_capacity(8).config().unwrap();
+  /// assert_eq!(config.trace_capacity, 8);
+  /// ```
+  pub fn trace_capacity(mut self, capacity: usize) -> Self {
+    self.config.trace_capacity = capacity;
+    self
+  }
+
+  /// Checks the settings and returns them, without connecting to anything.
+  pub fn config(self) -> Result<MidiDriverConfig, LumatoneMidiError> {
+    use DriverConfigError::*;
+    let config = self.config;
+    let invalid = |err| Err(LumatoneMidiError::InvalidDriverConfig(err));
+    if config.receive_timeout.is_zero() {
+      return invalid(ZeroReceiveTimeout);
+    }
+    if let Some(retry_jitter) = config.retry_jitter {
+      if retry_jitter > config.retry_timeout {
+        return invalid(JitterExceedsRetryTimeout {
+          retry_jitter,
+          retry_timeout: config.retry_timeout,
+        });
+      }
+    }
+    if config.event_capacity == 0 {
+      return invalid(ZeroCapacity("event_capacity"));
+    }
+    if config.trace_capacity == 0 {
+      return invalid(ZeroCapacity("trace_capacity"));
+    }
+    Ok(config)
+  }
+
+  /// Checks the settings and connects to `device`, like [MidiDriver::new].
+  pub fn build(
+    self,
+    device: &LumatoneDevice,
+  ) -> Result<(MidiDriver, impl Future<Output = ()>), LumatoneMidiError> {
+    MidiDriver::connect(device, self.config()?)
+  }
+
+  /// Checks the settings and creates a driver that talks over `transport`, like
+  /// [MidiDriver::with_transport].
+  pub fn build_with_transport(
+    self,
+    transport: Box<dyn MidiTransport>,
+  ) -> Result<(MidiDriver, impl Future<Output = ()>), LumatoneMidiError> {
+    Ok(MidiDriver::with_transport(transport, self.config()?))
+  }
+}
+
 /// Request to send a command to the device, with a channel to send a response on.
 #[derive(Clone)]
 struct CommandSubmission {
   command: Command,
   response_tx: mpsc::Sender<ResponseResult>,
+
+  /// Number of busy retries left before we give up on this command.
+  busy_retries_left: usize,
+
+  /// Number of timeout retries left before we give up on this command.
+  timeout_retries_left: usize,
+
+  /// Number of sends of this command that timed out. Each of them may still get a response.
+  timeouts: usize,
+
+  /// If set, signaled the next time the queue drains after this command's response is sent.
+  drain_tx: Option<mpsc::Sender<()>>,
+
+  /// When this command was first sent to the device. Re-sends don't change it.
+  first_sent_at: Option<Instant>,
+
+  /// Whether closing `response_tx` cancels the command. Only set for callers that wait on
+  /// the response, so that e.g. [MidiDriver::blocking_send] callers can drop the receiver
+  /// without losing the command.
+  cancellable: bool,
 }
 
 impl CommandSubmission {
-  /// Creates a new CommandSubmission and returns it, along with the receive channel
-  /// for the command's [ResponseResult].
+  /// Creates a new CommandSubmission with the default retry budgets and returns it,
+  /// along with the receive channel for the command's [ResponseResult].
+  #[cfg(test)]
   fn new(command: Command) -> (Self, mpsc::Receiver<ResponseResult>) {
+    Self::with_config(command, &MidiDriverConfig::default())
+  }
+
+  /// Creates a new CommandSubmission, taking the retry budgets from `config`.
+  fn with_config(
+    command: Command,
+    config: &MidiDriverConfig,
+  ) -> (Self, mpsc::Receiver<ResponseResult>) {
     let (response_tx, response_rx) = mpsc::channel(1);
     let sub = CommandSubmission {
       command,
       response_tx,
+      busy_retries_left: config.max_busy_retries,
+      timeout_retries_left: config.max_timeout_retries,
+      timeouts: 0,
+      drain_tx: None,
+      first_sent_at: None,
+      cancellable: false,
     };
     (sub, response_rx)
   }
+
+  /// Returns true if nobody is waiting for this command's response anymore, e.g. because
+  /// the future returned by [MidiDriver::send] was dropped. Always false for commands that
+  /// weren't submitted as cancellable.
+  fn is_cancelled(&self) -> bool {
+    self.cancellable && self.response_tx.is_closed()
+  }
 }
 
 impl Debug for CommandSubmission {
@@ -158,6 +653,13 @@ enum State {
     to_retry: CommandSubmission,
   },
 
+  /// We've run out of timeout retries waiting for a response to `command_sent`,
+  /// and need to tell the sender that it failed.
+  TimedOut {
+    send_queue: VecDeque<CommandSubmission>,
+    command_sent: CommandSubmission,
+  },
+
   /// Something has gone horribly wrong, and we've shut down the state machine loop.
   Failed(LumatoneMidiError),
 }
@@ -197,6 +699,15 @@ impl Display for State {
         to_retry.command,
         send_queue.len()
       ),
+      TimedOut {
+        send_queue,
+        command_sent,
+      } => write!(
+        f,
+        "TimedOut({}, {} in queue)",
+        command_sent.command,
+        send_queue.len()
+      ),
       Failed(err) => write!(f, "Failed({:?})", err),
     }
   }
@@ -216,6 +727,10 @@ enum Action {
   /// The driver has received a message on the MIDI in port.
   MessageReceived(EncodedSysex),
 
+  /// The driver has received a response to a command that's already been resolved,
+  /// meant for one of its sends that timed out.
+  LateResponseReceived(EncodedSysex),
+
   /// The device has signaled that it can't process the last command we sent,
   /// and we should back off for a bit before trying again.
   DeviceBusy,
@@ -241,6 +756,9 @@ impl Display for Action {
       SubmitCommand(cmd) => write!(f, "SubmitCommand({})", cmd.command),
       MessageSent(cmd) => write!(f, "MessageSent({})", cmd.command),
       MessageReceived(msg) => write!(f, "MessageReceived({:?} ...)", to_hex_debug_str(msg)),
+      LateResponseReceived(msg) => {
+        write!(f, "LateResponseReceived({:?} ...)", to_hex_debug_str(msg))
+      }
       DeviceBusy => write!(f, "DeviceBusy"),
       ResponseDispatched => write!(f, "ResponseDispatched"),
       ResponseTimedOut => write!(f, "ResponseTimedOut"),
@@ -269,6 +787,9 @@ enum Effect {
   /// The [State] we just [enter](State::enter)ed wants to transition to a new state,
   /// and we should feed the given [Action] into the state machine next.
   DispatchAction(Action),
+
+  /// The send queue has drained, and we should let anyone waiting for that know.
+  NotifyQueueDrained,
 }
 
 impl Display for Effect {
@@ -282,6 +803,7 @@ impl Display for Effect {
         write!(f, "NotfiyMessageResponse({}, {:?})", cmd.command, res)
       }
       DispatchAction(action) => write!(f, "DispatchAction({})", action),
+      NotifyQueueDrained => write!(f, "NotifyQueueDrained"),
     }
   }
 }
@@ -384,7 +906,17 @@ impl State {
         response_msg,
       },
 
-      // Receiving a message when we're not expecting one logs a warning.
+      // Late responses for commands that have already been resolved are dropped in any state.
+      (LateResponseReceived(msg), state) => {
+        debug!(
+          "dropping late response to an already resolved command: {}",
+          to_hex_debug_str(&msg)
+        );
+        state
+      }
+
+      // Receiving a message when we're not expecting one logs a warning. The driver loop
+      // doesn't pass these along, but logs them itself according to its config.
       (MessageReceived(msg), state) => {
         warn!(
           "Message received when not awaiting response. msg: {:?} current state: {}",
@@ -400,31 +932,50 @@ impl State {
       // in the ProcessingResponse state.
       (ResponseDispatched, ProcessingResponse { send_queue, .. }) => ProcessingQueue { send_queue },
 
-      // Getting a DeviceBusy signal when we're processing a response transitions to WaitingToRetry
+      // Once we've reported a timeout failure, move on to the next command in the queue.
+      (ResponseDispatched, TimedOut { send_queue, .. }) => ProcessingQueue { send_queue },
+
+      // Getting a DeviceBusy signal when we're processing a response transitions to WaitingToRetry,
+      // using up one of the command's busy retries.
       (
         DeviceBusy,
         ProcessingResponse {
           send_queue,
-          command_sent,
+          mut command_sent,
           ..
         },
-      ) => WaitingToRetry {
-        send_queue,
-        to_retry: command_sent,
-      },
+      ) => {
+        command_sent.busy_retries_left = command_sent.busy_retries_left.saturating_sub(1);
+        WaitingToRetry {
+          send_queue,
+          to_retry: command_sent,
+        }
+      }
 
-      // Getting a ResponseTimedOut action while waiting for a response logs a warning
-      // and transitions to ProcessingQueue.
-      // TODO: this should retry or return a failure on the response channel instead of ignoring
+      // Getting a ResponseTimedOut action while waiting for a response logs a warning.
+      // If the command has timeout retries left and hasn't been cancelled, it goes back on the
+      // front of the queue and we transition to ProcessingQueue. Otherwise we transition to
+      // TimedOut to report the failure.
       (
         ResponseTimedOut,
         AwaitingResponse {
-          send_queue,
-          command_sent,
+          mut send_queue,
+          mut command_sent,
         },
       ) => {
         warn!("Timed out waiting for response to msg: {:?}", command_sent);
-        ProcessingQueue { send_queue }
+        command_sent.timeouts += 1;
+        // there's no point re-sending a command that nobody wants the response to
+        if command_sent.timeout_retries_left > 0 && !command_sent.is_cancelled() {
+          command_sent.timeout_retries_left -= 1;
+          send_queue.push_front(command_sent);
+          ProcessingQueue { send_queue }
+        } else {
+          TimedOut {
+            send_queue,
+            command_sent,
+          }
+        }
       }
 
       // Getting a ResponseTimedOut when we're not waiting for a response logs a warning.
@@ -478,7 +1029,7 @@ impl State {
   /// [Action] to feed into the state machine next.
   ///
   /// Note that `enter` does not perform any effects or apply actions, just returns instructions
-  /// to do so. See [MidiDriverInternal] for the bit that performs effects and advances the state
+  /// to do so. See [DriverLoop] for the bit that performs effects and advances the state
   /// machine.
   fn enter(&mut self) -> Option<Effect> {
     use Effect::*;
@@ -487,11 +1038,19 @@ impl State {
     // debug!("entering state {:?}", self);
 
     match self {
-      Idle => None,
-      ProcessingQueue { send_queue } => match send_queue.pop_front() {
-        None => Some(DispatchAction(QueueEmpty)),
-        Some(cmd) => Some(SendMidiMessage(cmd.clone())),
-      },
+      // The driver starts out Idle without entering it, so this only happens via QueueEmpty.
+      Idle => Some(NotifyQueueDrained),
+      ProcessingQueue { send_queue } => {
+        // commands that were cancelled before we got to them are dropped without being sent
+        while let Some(cmd) = send_queue.pop_front() {
+          if cmd.is_cancelled() {
+            debug!("dropping cancelled command {}", cmd.command);
+            continue;
+          }
+          return Some(SendMidiMessage(cmd));
+        }
+        Some(DispatchAction(QueueEmpty))
+      }
       WaitingToRetry { .. } => Some(StartRetryTimeout),
       AwaitingResponse { .. } => Some(StartReceiveTimeout),
       ProcessingResponse {
@@ -503,17 +1062,24 @@ impl State {
           warn!("received message that doesn't match expected response. outgoing message: {} - incoming: {}", command_sent.command, to_hex_debug_str(response_msg));
         }
 
+        if command_sent.timeouts > 0 {
+          debug!(
+            "got a response to {} after {} timed out sends",
+            command_sent.command, command_sent.timeouts
+          );
+        }
+
         let status = message_answer_code(&response_msg);
         log_message_status(&status, &command_sent.command);
 
         match status {
-          ResponseStatusCode::Busy => Some(DispatchAction(Action::DeviceBusy)),
+          ResponseStatusCode::Busy => Some(busy_or_give_up(command_sent)),
 
           ResponseStatusCode::State => {
             warn!("device is in demo mode!");
             // FIXME: demo mode should probably have its own action that triggers
             // sending a command to exit demo mode.
-            Some(DispatchAction(Action::DeviceBusy))
+            Some(busy_or_give_up(command_sent))
           }
 
           ResponseStatusCode::Error => {
@@ -547,6 +1113,17 @@ impl State {
           }
         }
       }
+      TimedOut { command_sent, .. } => {
+        let mut msg = format!(
+          "no response to command {} after {} attempt(s)",
+          command_sent.command, command_sent.timeouts
+        );
+        if let Some(sent_at) = command_sent.first_sent_at {
+          msg += &format!(", {:.1?} since it was first sent", sent_at.elapsed());
+        }
+        let res = Err(LumatoneMidiError::ResponseTimedOut(msg));
+        Some(NotifyMessageResponse(command_sent.clone(), res))
+      }
       Failed(err) => {
         error!("midi driver - unrecoverable error: {err}");
         None // todo: return ExitWithError effect
@@ -555,55 +1132,810 @@ impl State {
   }
 }
 
-/// An internal helper struct for the [MidiDriver] that owns the connection to the device
-/// and timeouts needed by some "waiting" states.
-struct MidiDriverInternal {
-  device_io: LumatoneIO,
+/// Returns an effect that retries `command_sent` if it has any busy retries left,
+/// or reports a [LumatoneMidiError::DeviceBusy] error if not.
+fn busy_or_give_up(command_sent: &CommandSubmission) -> Effect {
+  if command_sent.busy_retries_left > 0 {
+    Effect::DispatchAction(Action::DeviceBusy)
+  } else {
+    let res = Err(LumatoneMidiError::DeviceBusy(format!(
+      "gave up retrying command {}",
+      command_sent.command
+    )));
+    Effect::NotifyMessageResponse(command_sent.clone(), res)
+  }
+}
+
+/// Reports calibration status messages from `events` on `progress` until the
+/// [CalibrationTracker] decides calibration is over, or nobody's listening anymore.
+async fn watch_calibration(
+  kind: CalibrationKind,
+  timing: CalibrationTiming,
+  mut events: broadcast::Receiver<DriverEvent>,
+  progress: mpsc::Sender<CalibrationProgress>,
+) {
+  let mut tracker = CalibrationTracker::new(timing, Instant::now().into_std());
+  if progress.send(CalibrationProgress::Started).await.is_err() {
+    return;
+  }
+  loop {
+    if let Some(outcome) = tracker.outcome(Instant::now().into_std()) {
+      let _ = progress.send(CalibrationProgress::Finished(outcome)).await;
+      return;
+    }
+    let deadline = Instant::from_std(tracker.deadline());
+    match timeout_at(deadline, events.recv()).await {
+      Ok(Ok(DriverEvent::CalibrationStatus(status))) if status.kind == kind => {
+        tracker.record(&status, Instant::now().into_std());
+        if progress
+          .send(CalibrationProgress::Status(status))
+          .await
+          .is_err()
+        {
+          return;
+        }
+      }
+      Ok(Ok(_)) => {}
+      Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+        warn!("fell behind driver events during calibration, missed {n}")
+      }
+      // the driver has stopped, so nothing more will arrive
+      Ok(Err(broadcast::error::RecvError::Closed)) => {
+        let outcome = tracker.outcome(tracker.deadline());
+        if let Some(outcome) = outcome {
+          let _ = progress.send(CalibrationProgress::Finished(outcome)).await;
+        }
+        return;
+      }
+      // the tracker's deadline passed, so check the outcome again
+      Err(_) => {}
+    }
+  }
+}
+
+/// Passes calibration statuses from `events` along to `statuses`, at most one per
+/// `min_interval`, keeping only the newest of any that arrive in between.
+async fn throttle_calibration_status(
+  mut events: broadcast::Receiver<DriverEvent>,
+  min_interval: Duration,
+  statuses: mpsc::Sender<CalibrationStatus>,
+) {
+  let mut next_send = Instant::now();
+  let mut pending: Option<CalibrationStatus> = None;
+  loop {
+    // a held status only has to wait until the interval is up
+    let event = match pending {
+      Some(_) => timeout_at(next_send, events.recv()).await.ok(),
+      None => Some(events.recv().await),
+    };
+    match event {
+      Some(Ok(DriverEvent::CalibrationStatus(status))) => pending = Some(status),
+      Some(Ok(_)) | None => {}
+      // only the newest status matters, so missing some is fine
+      Some(Err(broadcast::error::RecvError::Lagged(n))) => {
+        debug!("throttled calibration stream skipped {n} events")
+      }
+      Some(Err(broadcast::error::RecvError::Closed)) => {
+        if let Some(status) = pending {
+          let _ = statuses.send(status).await;
+        }
+        return;
+      }
+    }
+
+    if Instant::now() >= next_send {
+      if let Some(status) = pending.take() {
+        if statuses.send(status).await.is_err() {
+          return;
+        }
+        next_send = Instant::now() + min_interval;
+      }
+    }
+  }
+}
+
+/// Device settings that the driver has set successfully. This is the only way to know
+/// the value of settings that the firmware has no command to read back.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct DeviceModel {
+  aftertouch_enabled: Option<bool>,
+  /// Peripheral calibration modes turned on through this driver, and not yet turned off.
+  calibration_modes: Vec<PeripheralCalibrationMode>,
+}
+
+impl DeviceModel {
+  /// Updates the model after the device has acknowledged `command`.
+  fn record(&mut self, command: &Command) {
+    match command {
+      Command::SetAftertouchEnabled(enabled) => self.aftertouch_enabled = Some(*enabled),
+      Command::EnableExpressionPedalCalibrationMode(enabled) => {
+        self.set_calibrating(PeripheralCalibrationMode::ExpressionPedal, *enabled)
+      }
+      Command::EnablePitchModWheelCalibrationMode(enabled) => {
+        self.set_calibrating(PeripheralCalibrationMode::PitchAndModWheels, *enabled)
+      }
+      _ => {}
+    }
+  }
+
+  fn set_calibrating(&mut self, mode: PeripheralCalibrationMode, enabled: bool) {
+    self.calibration_modes.retain(|m| *m != mode);
+    if enabled {
+      self.calibration_modes.push(mode);
+    }
+  }
+}
+
+/// The timeouts that the driver waits on in some states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Timer {
+  /// Waiting for the device to respond to a command.
+  Receive,
+  /// Waiting to re-send a command that the device was too busy for.
+  Retry,
+}
+
+/// Something that happened outside of the driver loop, for it to react to.
+#[derive(Debug)]
+enum Input {
+  /// A message arrived from the device.
+  Message(EncodedSysex),
+  /// A user of the driver submitted a command.
+  Command(CommandSubmission),
+  /// A timer started with [EffectExecutor::start_timer] ran out.
+  TimerFired(Timer),
+  /// The driver should shut down.
+  Done,
+}
+
+/// Performs the [Effect]s that touch the device or the clock, and reports what happens in
+/// the outside world as [Input]s. Everything else is up to [DriverLoop].
+trait EffectExecutor {
+  /// Sends an encoded sysex message to the device.
+  fn send_message(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError>;
+
+  /// Starts `timer`, restarting it if it's already running, so that it fires after `duration`.
+  fn start_timer(&mut self, timer: Timer, duration: Duration);
+
+  /// Stops `timer`, if it's running.
+  fn cancel_timer(&mut self, timer: Timer);
+
+  /// Waits for the next thing to happen: a message from the device, a command on
+  /// `commands`, a timer firing, or a signal (or hang up) on `done`.
+  /// A timer that fires is no longer running.
+  async fn next_input(
+    &mut self,
+    commands: &mut mpsc::Receiver<CommandSubmission>,
+    done: &mut mpsc::Receiver<()>,
+  ) -> Input;
+}
+
+/// Executes effects for real, on the device's MIDI ports and with tokio timers.
+struct TokioExecutor {
+  device_io: Box<dyn MidiTransport>,
   receive_timeout: Option<Pin<Box<Sleep>>>,
   retry_timeout: Option<Pin<Box<Sleep>>>,
 }
 
+impl TokioExecutor {
+  fn new(device_io: Box<dyn MidiTransport>) -> Self {
+    TokioExecutor {
+      device_io,
+      receive_timeout: None,
+      retry_timeout: None,
+    }
+  }
+
+  /// Closes the transport, once the event loop is done with it.
+  fn close(self) {
+    self.device_io.close();
+  }
+
+  fn timer_mut(&mut self, timer: Timer) -> &mut Option<Pin<Box<Sleep>>> {
+    match timer {
+      Timer::Receive => &mut self.receive_timeout,
+      Timer::Retry => &mut self.retry_timeout,
+    }
+  }
+}
+
+impl EffectExecutor for TokioExecutor {
+  fn send_message(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+    self.device_io.send(msg)
+  }
+
+  fn start_timer(&mut self, timer: Timer, duration: Duration) {
+    *self.timer_mut(timer) = Some(Box::pin(sleep(duration)));
+  }
+
+  fn cancel_timer(&mut self, timer: Timer) {
+    *self.timer_mut(timer) = None;
+  }
+
+  async fn next_input(
+    &mut self,
+    commands: &mut mpsc::Receiver<CommandSubmission>,
+    done: &mut mpsc::Receiver<()>,
+  ) -> Input {
+    // if either timeout is None, use a timeout with Duration::MAX, to make the select! logic a bit simpler
+    let mut receive_timeout = &mut Box::pin(sleep(Duration::MAX));
+    if let Some(t) = &mut self.receive_timeout {
+      receive_timeout = t;
+    }
+
+    let mut retry_timeout = &mut Box::pin(sleep(Duration::MAX));
+    if let Some(t) = &mut self.retry_timeout {
+      retry_timeout = t;
+    }
+
+    // There are two incoming streams of information: incoming midi messages,
+    // and incoming commands (requests to send out midi messages)
+    // There are also two timeouts: receive_timeout for when we're waiting for a response to a command,
+    // and retry_timeout for when we're waiting to re-send a command (because the device was busy last time).
+    tokio::select! {
+      _ = receive_timeout => {
+        self.receive_timeout = None;
+        Input::TimerFired(Timer::Receive)
+      },
+
+      _ = retry_timeout => {
+        self.retry_timeout = None;
+        Input::TimerFired(Timer::Retry)
+      },
+
+      Some(msg) = self.device_io.incoming_messages().recv() => Input::Message(msg),
+
+      Some(cmd) = commands.recv() => Input::Command(cmd),
+
+      _ = done.recv() => Input::Done,
+    }
+  }
+}
+
+/// Runs the [MidiDriver]'s event loop: feeds [Input]s into the state machine as [Action]s
+/// and performs the [Effect]s that come out, using an [EffectExecutor] for the ones that
+/// touch the device or the clock.
+struct DriverLoop<E: EffectExecutor> {
+  executor: E,
+  model: Arc<Mutex<DeviceModel>>,
+  events_tx: broadcast::Sender<DriverEvent>,
+  trace_tx: broadcast::Sender<CommandTrace>,
+  /// Set to the reason the event loop stopped, if it failed.
+  last_error: Arc<Mutex<Option<String>>>,
+  /// Senders from [CommandSubmission::drain_tx] whose responses have been dispatched,
+  /// to be signaled when the queue drains.
+  drain_waiters: Vec<mpsc::Sender<()>>,
+  /// Messages sent for resolved commands that timed out, along with how many more responses
+  /// to them we're expecting. Oldest first.
+  late_responses: VecDeque<(EncodedSysex, usize)>,
+  receive_timeout_duration: Duration,
+  retry_timeout_duration: Duration,
+  /// See [MidiDriverConfig::log_unsolicited].
+  log_unsolicited: bool,
+  /// See [MidiDriverConfig::retry_jitter].
+  retry_jitter: Option<Duration>,
+  /// Picks the retry jitter. Seeded from entropy, except in tests.
+  rng: StdRng,
+  /// Reused for encoding outgoing messages, to avoid allocating one per send.
+  send_buf: Vec<u8>,
+  /// Updated after each state transition. Dropped when the loop stops.
+  progress_tx: watch::Sender<Progress>,
+}
+
 /// The MidiDriver provides an interface for sending [Command]s to a Lumatone device
 /// and receiving [Response]s (or [LumatoneMidiError]s).
 ///
-/// Use the async [send] method
+/// Use the async [send](MidiDriver::send) method, or
+/// [send_with_timeout](MidiDriver::send_with_timeout) to give up on a command after a while.
 pub struct MidiDriver {
   command_tx: mpsc::Sender<CommandSubmission>,
   done_tx: mpsc::Sender<()>,
+  config: MidiDriverConfig,
+  model: Arc<Mutex<DeviceModel>>,
+  events_tx: broadcast::Sender<DriverEvent>,
+  trace_tx: broadcast::Sender<CommandTrace>,
+  last_error: Arc<Mutex<Option<String>>>,
+  /// How many commands have been handed to the event loop.
+  submitted: Arc<AtomicU64>,
+  progress_rx: watch::Receiver<Progress>,
 }
 
 impl MidiDriver {
   /// Sends a [Command] to the device asynchronously, returning a Future that will resolve
   /// with the Command's [Response] on success, or a [LumatoneMidiError] report on failure.
   pub async fn send(&self, command: Command) -> Result<Response, LumatoneMidiError> {
-    let (submission, mut response_rx) = CommandSubmission::new(command);
+    let (mut submission, mut response_rx) = CommandSubmission::with_config(command, &self.config);
+    submission.cancellable = true;
     let send_f = self
       .command_tx
       .send(submission)
       .map_err(|e| LumatoneMidiError::DeviceSendError(format!("send error: {e}")));
 
     send_f.await?;
-    response_rx.recv().await.unwrap()
+    self.submitted.fetch_add(1, Ordering::SeqCst);
+    match response_rx.recv().await {
+      Some(res) => res,
+      None => Err(self.stopped_error()),
+    }
   }
 
-  /// Like [MidiDriver::send], but blocks the thread and returns a Result when the response is received.
-  /// Must be called from a different thread than the one running the driver loop future.
-  pub fn blocking_send(
+  /// Like [MidiDriver::send], but fails with [LumatoneMidiError::ResponseTimedOut] if the
+  /// response hasn't arrived within `timeout`, however many times the driver has re-sent
+  /// the command by then.
+  ///
+  /// Giving up cancels the command, as does dropping the future returned by `send`. A
+  /// cancelled command that hasn't been sent yet is dropped from the queue. One that has is
+  /// still given the usual time to answer, so that its response isn't mistaken for the next
+  /// command's, but it isn't re-sent if it times out.
+  pub async fn send_with_timeout(
     &self,
     command: Command,
-  ) -> Result<mpsc::Receiver<ResponseResult>, LumatoneMidiError> {
-    let (response_tx, response_rx) = mpsc::channel(1);
-    let submission = CommandSubmission {
-      command,
-      response_tx,
-    };
+    timeout: Duration,
+  ) -> Result<Response, LumatoneMidiError> {
+    let description = command.to_string();
+    match tokio::time::timeout(timeout, self.send(command)).await {
+      Ok(res) => res,
+      Err(_) => Err(LumatoneMidiError::ResponseTimedOut(format!(
+        "gave up on command {description} after {timeout:?}"
+      ))),
+    }
+  }
+
+  /// Sends each of `commands` in order, returning their results once the device has
+  /// responded to all of them.
+  ///
+  /// If `on_drained` is given, it's called once the send queue drains after the last
+  /// response, before this resolves. If other tasks are sending commands through the same
+  /// driver, that may be a while after the last of these commands gets its response.
+  /// It's called right away if `commands` is empty.
+  pub async fn send_all(
+    &self,
+    commands: Vec<Command>,
+    on_drained: Option<Box<dyn FnOnce() + Send>>,
+  ) -> Vec<Result<Response, LumatoneMidiError>> {
+    let (drain_tx, mut drain_rx) = mpsc::channel(1);
+    let last_index = commands.len().saturating_sub(1);
+
+    // submit everything up front, so the commands go out back to back
+    let mut pending = Vec::with_capacity(commands.len());
+    for (i, command) in commands.into_iter().enumerate() {
+      let (mut submission, response_rx) = CommandSubmission::with_config(command, &self.config);
+      if i == last_index && on_drained.is_some() {
+        submission.drain_tx = Some(drain_tx.clone());
+      }
+      let sent = self
+        .command_tx
+        .send(submission)
+        .await
+        .map_err(|e| LumatoneMidiError::DeviceSendError(format!("send error: {e}")));
+      if sent.is_ok() {
+        self.submitted.fetch_add(1, Ordering::SeqCst);
+      }
+      pending.push(sent.map(|_| response_rx));
+    }
+    // drop ours, so that drain_rx closes if the last submission never made it to the driver
+    drop(drain_tx);
+
+    let mut results = Vec::with_capacity(pending.len());
+    for p in pending {
+      let res = match p {
+        Ok(mut response_rx) => match response_rx.recv().await {
+          Some(res) => res,
+          None => Err(self.stopped_error()),
+        },
+        Err(e) => Err(e),
+      };
+      results.push(res);
+    }
+
+    if let Some(callback) = on_drained {
+      drain_rx.recv().await;
+      callback();
+    }
+    results
+  }
+
+  /// Like [MidiDriver::send], but blocks the thread and returns a Result when the response is received.
+  /// Must be called from a different thread than the one running the driver loop future.
+  ///
+  /// Unlike with [MidiDriver::send], the command isn't cancelled if the returned receiver
+  /// is dropped, so fire-and-forget callers can drop it and still have the command sent.
+  pub fn blocking_send(
+    &self,
+    command: Command,
+  ) -> Result<mpsc::Receiver<ResponseResult>, LumatoneMidiError> {
+    let (submission, response_rx) = CommandSubmission::with_config(command, &self.config);
     self
       .command_tx
       .blocking_send(submission)
       .map_err(|e| LumatoneMidiError::DeviceSendError(format!("send error: {e}")))?;
+    self.submitted.fetch_add(1, Ordering::SeqCst);
     Ok(response_rx)
   }
 
+  /// Waits until the device has answered every command submitted through this driver
+  /// before the call, and the driver is idle.
+  ///
+  /// This is for callers that don't wait on each command, e.g. when sends are spawned off
+  /// or made with [MidiDriver::blocking_send]. Commands submitted after the call aren't
+  /// waited for, but they'll hold it up if they keep the driver busy. Fails if the event
+  /// loop stops first.
+  pub async fn flush(&self) -> Result<(), LumatoneMidiError> {
+    let target = self.submitted.load(Ordering::SeqCst);
+    let mut progress = self.progress_rx.clone();
+    loop {
+      let current = *progress.borrow_and_update();
+      if current.idle && current.accepted >= target {
+        return Ok(());
+      }
+      if progress.changed().await.is_err() {
+        return Err(self.stopped_error());
+      }
+    }
+  }
+
+  /// Reads the device's serial number.
+  ///
+  /// Early firmware acknowledges the request without sending one, which fails with
+  /// [LumatoneMidiError::FirmwareTooOld]. Any other response that isn't a serial number
+  /// fails with [LumatoneMidiError::InvalidResponseMessage].
+  pub async fn get_serial_id(&self) -> Result<[u8; 6], LumatoneMidiError> {
+    match self.send(Command::GetSerialId).await? {
+      Response::SerialId(id) => Ok(id),
+      other => Err(unexpected_response("a serial id", &other)),
+    }
+  }
+
+  /// Reads the device's firmware version. Fails with
+  /// [LumatoneMidiError::InvalidResponseMessage] if the device answers with anything else.
+  pub async fn get_firmware_revision(&self) -> Result<FirmwareVersion, LumatoneMidiError> {
+    match self.send(Command::GetFirmwareRevision).await? {
+      Response::FirmwareRevision {
+        major,
+        minor,
+        revision,
+      } => Ok(FirmwareVersion {
+        major,
+        minor,
+        revision,
+      }),
+      other => Err(unexpected_response("a firmware revision", &other)),
+    }
+  }
+
+  /// Reads the expression pedal sensitivity. The firmware has no way to read back the pitch
+  /// and mod wheel sensitivities. Fails with [LumatoneMidiError::InvalidResponseMessage] if
+  /// the device answers with anything else.
+  pub async fn get_expression_pedal_sensitivity(&self) -> Result<u8, LumatoneMidiError> {
+    match self.send(Command::GetExpressionPedalSensitivity).await? {
+      Response::ExpressionPedalSensitivity(value) => Ok(value),
+      other => Err(unexpected_response(
+        "an expression pedal sensitivity",
+        &other,
+      )),
+    }
+  }
+
+  /// Reads the MIDI channels of the pitch and mod wheels, expression pedal, and sustain
+  /// pedal. Fails with [LumatoneMidiError::InvalidResponseMessage] if the device answers
+  /// with anything else.
+  pub async fn get_peripheral_channels(&self) -> Result<PeripheralChannels, LumatoneMidiError> {
+    match self.send(Command::GetPeripheralChannels).await? {
+      Response::PeripheralChannels {
+        pitch_wheel,
+        mod_wheel,
+        expression,
+        sustain,
+      } => Ok(PeripheralChannels {
+        pitch_wheel,
+        mod_wheel,
+        expression,
+        sustain,
+      }),
+      other => Err(unexpected_response("peripheral channels", &other)),
+    }
+  }
+
+  /// Like [MidiDriver::send_all], but fails if any of the commands does, with
+  /// [LumatoneMidiError::CommandFailed] naming the first one that failed.
+  pub(crate) async fn send_all_or_fail(
+    &self,
+    commands: Vec<Command>,
+  ) -> Result<Vec<Response>, LumatoneMidiError> {
+    let ids: Vec<CommandId> = commands.iter().map(Command::command_id).collect();
+    self
+      .send_all(commands, None)
+      .await
+      .into_iter()
+      .zip(ids)
+      .map(|(result, command)| {
+        result.map_err(|error| LumatoneMidiError::CommandFailed {
+          command,
+          error: Box::new(error),
+        })
+      })
+      .collect()
+  }
+
+  /// Reads the color of each key on `board`, combining its red, green and blue LED tables.
+  ///
+  /// Early firmware leaves out the last key, so there are as many colors as the shortest
+  /// of the three tables has entries. If a table can't be read, fails with
+  /// [LumatoneMidiError::CommandFailed] naming it. Fails with
+  /// [LumatoneMidiError::InvalidResponseMessage] if the device answers with anything else.
+  pub async fn get_board_colors(
+    &self,
+    board: BoardIndex,
+  ) -> Result<Vec<RGBColor>, LumatoneMidiError> {
+    let commands = vec![
+      Command::GetRedLEDConfig(board),
+      Command::GetGreenLEDConfig(board),
+      Command::GetBlueLEDConfig(board),
+    ];
+    let responses = self.send_all_or_fail(commands).await?;
+
+    match responses.as_slice() {
+      [Response::RedLEDConfig(b1, red), Response::GreenLEDConfig(b2, green), Response::BlueLEDConfig(b3, blue)]
+        if [b1, b2, b3].iter().all(|b| **b == board) =>
+      {
+        let colors = red
+          .iter()
+          .zip(green)
+          .zip(blue)
+          .map(|((r, g), b)| RGBColor(*r, *g, *b))
+          .collect();
+        Ok(colors)
+      }
+      _ => {
+        let got: Vec<String> = responses.iter().map(|r| r.to_string()).collect();
+        Err(LumatoneMidiError::InvalidResponseMessage(format!(
+          "expected the LED tables of {board}, got {}",
+          got.join(", ")
+        )))
+      }
+    }
+  }
+
+  /// Returns the peripheral calibration modes that were turned on through this driver
+  /// and haven't been turned off since, oldest first.
+  pub fn active_calibration_modes(&self) -> Vec<PeripheralCalibrationMode> {
+    self.model.lock().unwrap().calibration_modes.clone()
+  }
+
+  /// Turns off every peripheral calibration mode in [MidiDriver::active_calibration_modes],
+  /// e.g. when the user backs out of calibrating the pedal or wheels partway through.
+  ///
+  /// Key and aftertouch calibration can't be interrupted: the firmware has no command to
+  /// stop them, and they run until the macro buttons on each octave have been pressed
+  /// (see [crate::midi::calibration]).
+  ///
+  /// Every mode is turned off even if one of them fails, in which case the first error is
+  /// returned. Modes the device didn't acknowledge turning off stay active.
+  pub async fn cancel_calibration(&self) -> Result<(), LumatoneMidiError> {
+    let commands = self
+      .active_calibration_modes()
+      .into_iter()
+      .map(|mode| match mode {
+        PeripheralCalibrationMode::ExpressionPedal => {
+          Command::EnableExpressionPedalCalibrationMode(false)
+        }
+        PeripheralCalibrationMode::PitchAndModWheels => {
+          Command::EnablePitchModWheelCalibrationMode(false)
+        }
+      })
+      .collect();
+    let results = self.send_all(commands, None).await;
+    results
+      .into_iter()
+      .find_map(Result::err)
+      .map_or(Ok(()), Err)
+  }
+
+  /// Returns whether aftertouch is enabled, as of the last successful
+  /// [Command::SetAftertouchEnabled] sent through this driver, or `None` if it hasn't
+  /// been set yet.
+  ///
+  /// The firmware has no command for reading the flag back, so this won't reflect
+  /// changes made on the device itself or by other software.
+  pub fn aftertouch_enabled(&self) -> Option<bool> {
+    self.model.lock().unwrap().aftertouch_enabled
+  }
+
+  /// Returns why the driver's event loop stopped, if it stopped because of an error.
+  ///
+  /// Once the loop has stopped, commands fail with a [LumatoneMidiError::DeviceSendError],
+  /// so this is the place to look for the underlying cause.
+  pub fn last_error(&self) -> Option<String> {
+    self.last_error.lock().unwrap().clone()
+  }
+
+  /// The error to report for a command whose response will never arrive, because the
+  /// event loop stopped while it was pending.
+  fn stopped_error(&self) -> LumatoneMidiError {
+    let reason = self
+      .last_error()
+      .unwrap_or_else(|| "no error recorded".to_string());
+    LumatoneMidiError::DeviceSendError(format!("driver stopped: {reason}"))
+  }
+
+  /// Returns a receiver for [DriverEvent]s that happen from now on.
+  ///
+  /// Events are buffered per subscriber, up to [MidiDriverConfig::event_capacity]; one that
+  /// falls too far behind will get a [broadcast::error::RecvError::Lagged] error and miss
+  /// the oldest events.
+  pub fn subscribe_events(&self) -> broadcast::Receiver<DriverEvent> {
+    self.events_tx.subscribe()
+  }
+
+  /// Returns a receiver for a [CommandTrace] of each command the driver resolves from now on,
+  /// whoever sent it. Buffered per subscriber, up to [MidiDriverConfig::trace_capacity], like
+  /// [MidiDriver::subscribe_events].
+  pub fn subscribe_trace(&self) -> broadcast::Receiver<CommandTrace> {
+    self.trace_tx.subscribe()
+  }
+
+  /// Sets the function and color of the key at `location`. The two commands go out back to
+  /// back, the way [crate::keymap::ltn::LumatoneKeyMap::to_midi_commands] pairs them for
+  /// each key.
+  ///
+  /// Waits for the device to answer both. If either fails, the error is a
+  /// [LumatoneMidiError::SetKeyFailed] saying which.
+  pub async fn set_key(
+    &self,
+    location: LumatoneKeyLocation,
+    def: &KeyDefinition,
+  ) -> Result<(), LumatoneMidiError> {
+    let commands = vec![
+      Command::SetKeyFunction {
+        location,
+        function: def.function,
+      },
+      Command::SetKeyColor {
+        location,
+        color: def.color,
+      },
+    ];
+    let results = self.send_all(commands, None).await;
+    let errors: Vec<_> = [KeyPart::Function, KeyPart::Color]
+      .into_iter()
+      .zip(results)
+      .filter_map(|(part, res)| res.err().map(|err| (part, err)))
+      .collect();
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(LumatoneMidiError::SetKeyFailed { location, errors })
+    }
+  }
+
+  /// Reads the threshold values of every octave board.
+  ///
+  /// The requests go out back to back. If any board's request fails, or it answers with
+  /// something other than its thresholds, the result is a [LumatoneMidiError::BoardErrors]
+  /// listing each board that failed.
+  pub async fn read_all_thresholds(
+    &self,
+  ) -> Result<HashMap<BoardIndex, Thresholds>, LumatoneMidiError> {
+    let boards = BoardIndex::all_octaves();
+    let commands = boards
+      .iter()
+      .map(|b| Command::GetBoardThresholdValues(*b))
+      .collect();
+    let results = self.send_all(commands, None).await;
+
+    let mut thresholds = HashMap::new();
+    let mut errors = vec![];
+    for (board, result) in boards.into_iter().zip(results) {
+      match result.map(|r| (r.thresholds(), r)) {
+        Ok((Some((b, values)), _)) if b == board => {
+          thresholds.insert(board, values);
+        }
+        Ok((_, response)) => errors.push((
+          board,
+          LumatoneMidiError::InvalidResponseMessage(format!(
+            "expected thresholds for {board}, got {response}"
+          )),
+        )),
+        Err(err) => errors.push((board, err)),
+      }
+    }
+
+    if errors.is_empty() {
+      Ok(thresholds)
+    } else {
+      Err(LumatoneMidiError::BoardErrors(errors))
+    }
+  }
+
+  /// Turns on key sampling for `board`, collects the sensor readings it streams for
+  /// `duration`, then turns sampling back off and returns per-key stats.
+  ///
+  /// Sampling is turned off even if collecting fails partway through. If the driver falls
+  /// behind the stream, some frames are dropped from the stats (with a warning).
+  pub async fn sample_keys(
+    &self,
+    board: BoardIndex,
+    duration: Duration,
+  ) -> Result<KeySamplingReport, LumatoneMidiError> {
+    // subscribe first, so we don't miss frames that arrive before `send` resolves
+    let mut events = self.subscribe_events();
+    self.send(Command::EnableKeySampling(board, true)).await?;
+
+    let started = Instant::now();
+    let deadline = started + duration;
+    let mut report = KeySamplingReport::new(board);
+    while let Ok(event) = timeout_at(deadline, events.recv()).await {
+      match event {
+        Ok(DriverEvent::KeySample(sample)) if sample.board == board => {
+          report.record(&sample.values)
+        }
+        Ok(_) => {}
+        Err(broadcast::error::RecvError::Lagged(n)) => {
+          warn!("fell behind the key sampling stream, dropped {n} events")
+        }
+        Err(broadcast::error::RecvError::Closed) => break,
+      }
+    }
+    report.duration = started.elapsed();
+
+    self.send(Command::EnableKeySampling(board, false)).await?;
+    Ok(report)
+  }
+
+  /// Starts key calibration and returns a channel of [CalibrationProgress] events,
+  /// ending with [CalibrationProgress::Finished].
+  ///
+  /// The firmware doesn't clearly say when calibration is done, so the end is decided by
+  /// the heuristic described in [crate::midi::calibration], using `timing`.
+  pub async fn calibrate_keys(
+    &self,
+    timing: CalibrationTiming,
+  ) -> Result<mpsc::Receiver<CalibrationProgress>, LumatoneMidiError> {
+    self.calibrate(CalibrationKind::Keys, timing).await
+  }
+
+  /// Like [MidiDriver::calibrate_keys], but for aftertouch calibration.
+  pub async fn calibrate_aftertouch(
+    &self,
+    timing: CalibrationTiming,
+  ) -> Result<mpsc::Receiver<CalibrationProgress>, LumatoneMidiError> {
+    self.calibrate(CalibrationKind::Aftertouch, timing).await
+  }
+
+  async fn calibrate(
+    &self,
+    kind: CalibrationKind,
+    timing: CalibrationTiming,
+  ) -> Result<mpsc::Receiver<CalibrationProgress>, LumatoneMidiError> {
+    // subscribe first, so we don't miss status messages that arrive before `send` resolves
+    let events = self.subscribe_events();
+    self.send(kind.start_command()).await?;
+
+    let (progress_tx, progress_rx) = mpsc::channel(16);
+    tokio::spawn(watch_calibration(kind, timing, events, progress_tx));
+    Ok(progress_rx)
+  }
+
+  /// Returns a channel of the [CalibrationStatus] messages the device sends from now on,
+  /// at most one per `min_interval`.
+  ///
+  /// The device reports every 100ms while calibrating. Statuses that arrive less than
+  /// `min_interval` after the last one passed along are held back, and only the newest of
+  /// them is passed along once the interval is up, so the last status is never lost.
+  /// The channel closes when the driver stops.
+  pub fn calibration_stream_throttled(
+    &self,
+    min_interval: Duration,
+  ) -> mpsc::Receiver<CalibrationStatus> {
+    let (status_tx, status_rx) = mpsc::channel(16);
+    tokio::spawn(throttle_calibration_status(
+      self.subscribe_events(),
+      min_interval,
+      status_tx,
+    ));
+    status_rx
+  }
+
   /// Signals to the driver to shutdown the event loop.
   pub async fn done(&self) -> Result<(), LumatoneMidiError> {
     self
@@ -615,6 +1947,12 @@ impl MidiDriver {
 }
 
 impl MidiDriver {
+  /// Returns a [MidiDriverBuilder] with the default settings, for creating a driver with
+  /// some of them changed.
+  pub fn builder() -> MidiDriverBuilder {
+    MidiDriverBuilder::default()
+  }
+
   /// Creates a new [MidiDriver] targeting the given [LumatoneDevice].
   ///
   /// May fail if unable to connect to the device.
@@ -624,31 +1962,150 @@ impl MidiDriver {
   /// You probably want to spawn a new task for the driver future,
   /// since it will not resolve until you either call [MidiDriver::done]
   /// or an error causes the driver loop to exit.
-  // TODO: maybe have this take an already connected LumatoneIO, so we
-  // don't need to return a Result.
+  ///
+  /// Takes the device's [lock](DeviceLock) for as long as the event loop runs, and fails
+  /// with [LumatoneMidiError::DeviceLocked] if another process already has it.
   pub fn new(
     device: &LumatoneDevice,
   ) -> Result<(MidiDriver, impl Future<Output = ()>), LumatoneMidiError> {
-    let internal = MidiDriverInternal::new(device)?;
+    MidiDriver::connect(device, MidiDriverConfig::default())
+  }
+
+  /// Like [MidiDriver::new], but with a custom [MidiDriverConfig].
+  #[deprecated(note = "use MidiDriver::builder, which checks the settings first")]
+  pub fn with_config(
+    device: &LumatoneDevice,
+    config: MidiDriverConfig,
+  ) -> Result<(MidiDriver, impl Future<Output = ()>), LumatoneMidiError> {
+    MidiDriver::connect(device, config)
+  }
+
+  /// Connects to `device`, as with [MidiDriver::new], without checking `config`.
+  fn connect(
+    device: &LumatoneDevice,
+    config: MidiDriverConfig,
+  ) -> Result<(MidiDriver, impl Future<Output = ()>), LumatoneMidiError> {
+    let lock = DeviceLock::acquire(device, config.steal_lock)?;
+    let device_io = device.connect()?;
+    let (driver, driver_future) = MidiDriver::with_transport(Box::new(device_io), config);
+    let driver_future = async move {
+      driver_future.await;
+      // the ports are closed once the loop exits, so the device is free for others
+      drop(lock);
+    };
+    Ok((driver, driver_future))
+  }
+
+  /// Creates a new [MidiDriver] that talks to the device over the given transport.
+  /// Like [MidiDriver::new], but can't fail, since the transport is already connected.
+  ///
+  /// No [DeviceLock] is taken, since the transport may not be a local MIDI port. The
+  /// transport is [closed](MidiTransport::close) when the event loop stops.
+  pub fn with_transport(
+    device_io: Box<dyn MidiTransport>,
+    config: MidiDriverConfig,
+  ) -> (MidiDriver, impl Future<Output = ()>) {
+    let model = Arc::new(Mutex::new(DeviceModel::default()));
+    let (events_tx, _) = broadcast::channel(config.event_capacity.max(1));
+    let (trace_tx, _) = broadcast::channel(config.trace_capacity.max(1));
+    let last_error = Arc::new(Mutex::new(None));
+    let mut driver_loop = DriverLoop::new(
+      TokioExecutor::new(device_io),
+      model.clone(),
+      events_tx.clone(),
+      trace_tx.clone(),
+      last_error.clone(),
+      &config,
+    );
+    let progress_rx = driver_loop.progress_tx.subscribe();
     let (command_tx, command_rx) = mpsc::channel(128);
     let (done_tx, done_rx) = mpsc::channel(1);
 
     let driver = MidiDriver {
       command_tx,
       done_tx,
+      config,
+      model,
+      events_tx,
+      trace_tx,
+      last_error,
+      submitted: Arc::new(AtomicU64::new(0)),
+      progress_rx,
+    };
+    let driver_future = async move {
+      driver_loop.run(command_rx, done_rx).await;
+      driver_loop.executor.close();
     };
-    Ok((driver, internal.run(command_rx, done_rx)))
+    (driver, driver_future)
   }
 }
 
-impl MidiDriverInternal {
-  fn new(device: &LumatoneDevice) -> Result<Self, LumatoneMidiError> {
-    let device_io = device.connect()?;
-    Ok(MidiDriverInternal {
-      device_io,
-      receive_timeout: None,
-      retry_timeout: None,
-    })
+impl<E: EffectExecutor> DriverLoop<E> {
+  fn new(
+    executor: E,
+    model: Arc<Mutex<DeviceModel>>,
+    events_tx: broadcast::Sender<DriverEvent>,
+    trace_tx: broadcast::Sender<CommandTrace>,
+    last_error: Arc<Mutex<Option<String>>>,
+    config: &MidiDriverConfig,
+  ) -> Self {
+    DriverLoop {
+      executor,
+      model,
+      events_tx,
+      trace_tx,
+      last_error,
+      drain_waiters: Vec::new(),
+      late_responses: VecDeque::new(),
+      receive_timeout_duration: config.receive_timeout,
+      retry_timeout_duration: config.retry_timeout,
+      log_unsolicited: config.log_unsolicited,
+      retry_jitter: config.retry_jitter,
+      rng: StdRng::from_entropy(),
+      send_buf: Vec::new(),
+      progress_tx: watch::channel(Progress::default()).0,
+    }
+  }
+
+  /// Sends `event` to anyone subscribed to driver events.
+  fn emit(&self, event: DriverEvent) {
+    // an error here just means that nobody is subscribed
+    let _ = self.events_tx.send(event);
+  }
+
+  /// Logs the error that's stopping the event loop and keeps it for [MidiDriver::last_error].
+  fn record_failure(&self, reason: String) {
+    error!("{reason}");
+    *self.last_error.lock().unwrap() = Some(reason);
+  }
+
+  /// Records that `resolved` may still get a response for each of its sends that timed out.
+  fn expect_late_responses(&mut self, resolved: &CommandSubmission) {
+    if self.late_responses.len() == MAX_LATE_RESPONSE_COMMANDS {
+      self.late_responses.pop_front();
+    }
+    self
+      .late_responses
+      .push_back((resolved.command.to_sysex_message(), resolved.timeouts));
+  }
+
+  /// Returns true if `msg` is one of the late responses we're expecting, and stops expecting it.
+  fn take_late_response(&mut self, msg: &[u8]) -> bool {
+    let found = self
+      .late_responses
+      .iter()
+      .position(|(sent, _)| is_echo_of(sent, msg));
+    match found {
+      Some(i) => {
+        let remaining = &mut self.late_responses[i].1;
+        *remaining -= 1;
+        if *remaining == 0 {
+          self.late_responses.remove(i);
+        }
+        true
+      }
+      None => false,
+    }
   }
 
   /// Performs some Effect. On success, returns an `Option<Action>`, which should be fed into
@@ -656,92 +2113,191 @@ impl MidiDriverInternal {
   async fn perform_effect(&mut self, effect: Effect) -> Result<Option<Action>, LumatoneMidiError> {
     use Effect::*;
     let maybe_action = match effect {
-      SendMidiMessage(cmd) => {
-        self.device_io.send(&cmd.command.to_sysex_message())?;
+      SendMidiMessage(mut cmd) => {
+        cmd.first_sent_at.get_or_insert_with(Instant::now);
+        cmd.command.encode_into(&mut self.send_buf);
+        if log_enabled!(Level::Trace) {
+          trace!(
+            "sending {} ({})",
+            to_hex_debug_str(&self.send_buf),
+            describe(&self.send_buf)
+          );
+        }
+        self.executor.send_message(&self.send_buf)?;
         Some(MessageSent(cmd))
       }
       StartReceiveTimeout => {
-        let timeout_sec = 30;
-        let timeout = sleep(Duration::from_secs(timeout_sec));
-        self.receive_timeout = Some(Box::pin(timeout));
+        self
+          .executor
+          .start_timer(Timer::Receive, self.receive_timeout_duration);
         None
       }
       StartRetryTimeout => {
-        let timeout_sec = 3;
-        let timeout = sleep(Duration::from_secs(timeout_sec));
-        self.retry_timeout = Some(Box::pin(timeout));
+        // we only wait to retry after a busy response
+        self.emit(DriverEvent::DeviceBusy);
+        let delay = retry_delay(
+          self.retry_timeout_duration,
+          self.retry_jitter,
+          &mut self.rng,
+        );
+        self.executor.start_timer(Timer::Retry, delay);
         None
       }
       NotifyMessageResponse(cmd_submission, result) => {
-        if let Err(err) = cmd_submission.response_tx.send(result).await {
-          error!("error sending response notification: {err}");
+        // update the model before notifying, so it's current by the time `send` resolves
+        match &result {
+          Ok(_) => self.model.lock().unwrap().record(&cmd_submission.command),
+          // busy, with no retries left
+          Err(LumatoneMidiError::DeviceBusy(_)) => self.emit(DriverEvent::DeviceBusy),
+          Err(_) => {}
+        }
+        // an error here just means that nobody is subscribed
+        let _ = self.trace_tx.send(CommandTrace {
+          command: cmd_submission.command.to_string(),
+          result: match &result {
+            Ok(response) => Ok(response.to_string()),
+            Err(err) => Err(err.to_string()),
+          },
+          latency: cmd_submission.first_sent_at.map(|t| t.elapsed()),
+        });
+        if cmd_submission.response_tx.send(result).await.is_err() {
+          debug!(
+            "dropping response to cancelled command {}",
+            cmd_submission.command
+          );
+        }
+        if cmd_submission.timeouts > 0 {
+          self.expect_late_responses(&cmd_submission);
+        }
+        if let Some(drain_tx) = cmd_submission.drain_tx {
+          // don't hold on to waiters that gave up while the queue stays busy
+          self.drain_waiters.retain(|w| !w.is_closed());
+          self.drain_waiters.push(drain_tx);
         }
         Some(ResponseDispatched)
       }
       DispatchAction(action) => Some(action),
+      NotifyQueueDrained => {
+        self.emit(DriverEvent::QueueDrained);
+        for waiter in self.drain_waiters.drain(..) {
+          // the waiter may have given up, which is fine
+          let _ = waiter.send(()).await;
+        }
+        None
+      }
     };
     Ok(maybe_action)
   }
 
+  /// Returns the [Action] for a message from the device, or `None` if the message isn't
+  /// for the state machine.
+  fn action_for_message(&mut self, msg: EncodedSysex, state: &State) -> Option<Action> {
+    if log_enabled!(Level::Trace) {
+      trace!(
+        "received {} ({}, status {:?})",
+        to_hex_debug_str(&msg),
+        describe(&msg),
+        message_answer_code(&msg)
+      );
+    }
+    if is_key_sample_message(&msg) {
+      // streamed, not a response, so it doesn't concern the state machine
+      match Response::from_sysex_message(&msg) {
+        Ok(Response::KeySample(sample)) => self.emit(DriverEvent::KeySample(sample)),
+        Ok(other) => warn!("unexpected key sample decoding: {other}"),
+        Err(err) => warn!("unable to decode key sample: {err}"),
+      }
+      None
+    } else if is_calibration_status_message(&msg) {
+      // also unsolicited, and would otherwise be taken for the ack to the start command
+      match Response::from_sysex_message(&msg) {
+        Ok(Response::CalibrationStatus(status)) => {
+          self.emit(DriverEvent::CalibrationStatus(status))
+        }
+        Ok(other) => warn!("unexpected calibration status decoding: {other}"),
+        Err(err) => warn!("unable to decode calibration status: {err}"),
+      }
+      None
+    } else if is_peripheral_calibration_report(&msg) {
+      // streamed while a pedal or wheel is calibrating. These reuse the id of the command
+      // that started calibration, but only its ack answers it, so they're never a response.
+      self.log_unsolicited_message(&msg, state);
+      None
+    } else if has_echo_flag(&msg) {
+      // our own ping, looped back by something between us and the device
+      debug!("ignoring unanswered ping: {}", to_hex_debug_str(&msg));
+      None
+    } else if !answers_command_in_flight(&msg, state) && self.take_late_response(&msg) {
+      // not an answer to whatever's in flight now, so leave its timeout running
+      Some(Action::LateResponseReceived(msg))
+    } else if !matches!(state, State::AwaitingResponse { .. }) {
+      self.log_unsolicited_message(&msg, state);
+      None
+    } else {
+      self.executor.cancel_timer(Timer::Receive);
+      Some(Action::MessageReceived(msg))
+    }
+  }
+
+  /// Logs a message that arrived when no response was expected. Pedal and wheel calibration
+  /// status reports are routine, so they're only warned about if
+  /// [MidiDriverConfig::log_unsolicited] is set.
+  fn log_unsolicited_message(&self, msg: &[u8], state: &State) {
+    if is_peripheral_calibration_status_message(msg) && !self.log_unsolicited {
+      trace!("calibration status received: {}", to_hex_debug_str(msg));
+    } else {
+      warn!(
+        "Message received when not awaiting response. msg: {:?} current state: {}",
+        to_hex_debug_str(msg),
+        state
+      );
+    }
+  }
+
   /// Run the MidiDriver I/O event loop.
   /// Commands to send to the device should be sent on the `commands` channel.
   ///
   /// To exit the loop, send `()` on the `done_signal` channel.
   ///
   async fn run(
-    mut self,
+    &mut self,
     mut commands: mpsc::Receiver<CommandSubmission>,
     mut done_signal: mpsc::Receiver<()>,
   ) {
     let mut state = State::Idle;
     let mut next_action: Option<Action> = None;
+    let mut accepted = 0;
     loop {
       // The previous state may have resulted in an Action that we should feed into the
-      // state machine. If not, we poll our inputs until something happens.
+      // state machine. If not, we wait for our inputs until something happens.
       let a = match next_action {
+        // Commands submitted while we were busy are still waiting in the channel, so the
+        // queue isn't really empty until we've pulled them in.
+        Some(QueueEmpty) => match commands.try_recv() {
+          Ok(cmd) => Action::SubmitCommand(cmd),
+          Err(_) => QueueEmpty,
+        },
         Some(action) => action.clone(),
         None => {
-          // if either timeout is None, use a timeout with Duration::MAX, to make the select! logic a bit simpler
-          let mut receive_timeout = &mut Box::pin(sleep(Duration::MAX));
-          if let Some(t) = &mut self.receive_timeout {
-            receive_timeout = t;
-          }
-
-          let mut retry_timeout = &mut Box::pin(sleep(Duration::MAX));
-          if let Some(t) = &mut self.retry_timeout {
-            retry_timeout = t;
-          }
-
-          // There are two incoming streams of information: incoming midi messages,
-          // and incoming commands (requests to send out midi messages)
-          // There are also two timeouts: receive_timeout for when we're waiting for a response to a command,
-          // and retry_timeout for when we're waiting to re-send a command (because the device was busy last time).
-          //
-          // This select pulls whatever is available next and maps it to an Action that will advance the state machine.
-          tokio::select! {
-            _ = receive_timeout => {
+          let input = self
+            .executor
+            .next_input(&mut commands, &mut done_signal)
+            .await;
+          match input {
+            Input::TimerFired(Timer::Receive) => {
               info!("receive timeout triggered");
-              self.receive_timeout = None;
               Action::ResponseTimedOut
-            },
-
-            _ = retry_timeout => {
+            }
+            Input::TimerFired(Timer::Retry) => {
               info!("retry timeout triggered");
-              self.retry_timeout = None;
               Action::ReadyToRetry
-            },
-
-            Some(msg) = self.device_io.incoming_messages.recv() => {
-              // info!("message received, forwarding to state machine");
-              self.receive_timeout = None;
-              Action::MessageReceived(msg)
-            }
-
-            Some(cmd) = commands.recv() => {
-              Action::SubmitCommand(cmd)
             }
-
-            _ = done_signal.recv() => {
+            Input::Message(msg) => match self.action_for_message(msg, &state) {
+              Some(action) => action,
+              None => continue,
+            },
+            Input::Command(cmd) => Action::SubmitCommand(cmd),
+            Input::Done => {
               debug!("done signal received, exiting");
               return;
             }
@@ -749,15 +2305,25 @@ impl MidiDriverInternal {
         }
       };
 
+      if let Action::SubmitCommand(_) = a {
+        accepted += 1;
+      }
+
       // Transition to next state based on action
       state = state.next(a);
 
       if let State::Failed(err) = state {
         // TODO: propagate fatal error & return it from `run`
-        error!("state machine error: {err}");
+        self.record_failure(format!("state machine error: {err}"));
         break;
       }
 
+      // an error here just means that nobody is waiting on a flush
+      let _ = self.progress_tx.send(Progress {
+        accepted,
+        idle: matches!(state, State::Idle),
+      });
+
       // The new state's `enter` fn may return an Effect.
       next_action = match state.enter() {
         // if there was no effect, there's no next_action
@@ -773,7 +2339,7 @@ impl MidiDriverInternal {
 
             // TODO: propagate fatal error & return it from `run`
             Err(err) => {
-              error!("effect error: {err}");
+              self.record_failure(format!("effect error: {err}"));
               break;
             }
           }
@@ -798,8 +2364,11 @@ fn log_message_status(status: &ResponseStatusCode, outgoing: &Command) {
   }
 }
 
+#[cfg(test)]
 mod tests {
   use crate::midi::constants::{CommandId, MANUFACTURER_ID};
+  use crate::midi::mock::Responder;
+  use crate::midi::sysex::{SYSEX_END, SYSEX_START};
 
   #[allow(unused_imports)]
   use super::*;
@@ -829,8 +2398,8 @@ mod tests {
     let cmd1 = Command::Ping(1);
     let cmd2 = Command::Ping(2);
 
-    let (sub1, _) = CommandSubmission::new(cmd1.clone());
-    let (sub2, _) = CommandSubmission::new(cmd2.clone());
+    let (sub1, _response_rx) = CommandSubmission::new(cmd1.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(cmd2.clone());
 
     let send_queue = VecDeque::from(vec![sub1.clone()]);
     let init = State::AwaitingResponse {
@@ -859,8 +2428,8 @@ mod tests {
     let cmd1 = Command::Ping(1);
     let cmd2 = Command::Ping(2);
 
-    let (sub1, _) = CommandSubmission::new(cmd1.clone());
-    let (sub2, _) = CommandSubmission::new(cmd2.clone());
+    let (sub1, _response_rx) = CommandSubmission::new(cmd1.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(cmd2.clone());
 
     let send_queue = VecDeque::from(vec![sub1.clone()]);
     let init = State::WaitingToRetry {
@@ -889,8 +2458,8 @@ mod tests {
     let cmd1 = Command::Ping(1);
     let cmd2 = Command::Ping(2);
 
-    let (sub1, _) = CommandSubmission::new(cmd1.clone());
-    let (sub2, _) = CommandSubmission::new(cmd2.clone());
+    let (sub1, _response_rx) = CommandSubmission::new(cmd1.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(cmd2.clone());
 
     let send_queue = VecDeque::from(vec![sub1.clone()]);
     let init = State::ProcessingQueue { send_queue };
@@ -912,8 +2481,8 @@ mod tests {
     let cmd1 = Command::Ping(1);
     let cmd2 = Command::Ping(2);
 
-    let (sub1, _) = CommandSubmission::new(cmd1.clone());
-    let (sub2, _) = CommandSubmission::new(cmd2.clone());
+    let (sub1, _response_rx) = CommandSubmission::new(cmd1.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(cmd2.clone());
 
     let send_queue = VecDeque::from(vec![sub1.clone()]);
     let init = State::ProcessingResponse {
@@ -939,8 +2508,8 @@ mod tests {
     let cmd1 = Command::Ping(1);
     let cmd2 = Command::Ping(2);
 
-    let (sub1, _) = CommandSubmission::new(cmd1.clone());
-    let (sub2, _) = CommandSubmission::new(cmd2.clone());
+    let (sub1, _response_rx) = CommandSubmission::new(cmd1.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(cmd2.clone());
 
     let send_queue = VecDeque::from(vec![sub2.clone()]);
     let init = State::ProcessingQueue { send_queue };
@@ -965,7 +2534,7 @@ mod tests {
   #[test]
   fn message_received_while_awaiting_response_transitions_to_processing_response() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let send_queue = VecDeque::new();
     let init = State::AwaitingResponse {
@@ -1005,8 +2574,8 @@ mod tests {
   #[test]
   fn response_dispatched_while_processing_response_transitions_to_processing_queue() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
-    let (sub2, _) = CommandSubmission::new(Command::Ping(2));
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(Command::Ping(2));
 
     let response: Vec<u8> = vec![0xf0, 0x00];
     let send_queue = VecDeque::from(vec![sub2]);
@@ -1027,10 +2596,14 @@ mod tests {
   }
 
   #[test]
-  fn response_timed_out_while_awaiting_response_transitions_to_processing_queue() {
+  fn response_timed_out_while_awaiting_response_requeues_command() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
-    let (sub2, _) = CommandSubmission::new(Command::Ping(2));
+    let config = MidiDriverConfig {
+      max_timeout_retries: 2,
+      ..Default::default()
+    };
+    let (sub, _response_rx) = CommandSubmission::with_config(cmd.clone(), &config);
+    let (sub2, _response_rx) = CommandSubmission::new(Command::Ping(2));
 
     let send_queue = VecDeque::from(vec![sub2]);
     let init = State::AwaitingResponse {
@@ -1040,10 +2613,105 @@ mod tests {
     let action = Action::ResponseTimedOut;
 
     match init.next(action) {
-      State::ProcessingQueue { send_queue } => {
+      State::ProcessingQueue { mut send_queue } => {
+        assert_eq!(send_queue.len(), 2);
+        let head = send_queue.pop_front().unwrap();
+        assert_eq!(head.command, cmd);
+        assert_eq!(head.timeout_retries_left, 1);
+        assert_eq!(head.timeouts, 1);
+      }
+
+      s => panic!("Unexpected state: {:?}", s),
+    }
+  }
+
+  #[test]
+  fn response_timed_out_with_no_retries_left_transitions_to_timed_out() {
+    let cmd = Command::Ping(1);
+    let config = MidiDriverConfig {
+      max_timeout_retries: 0,
+      ..Default::default()
+    };
+    let (sub, _response_rx) = CommandSubmission::with_config(cmd.clone(), &config);
+    let (sub2, _response_rx) = CommandSubmission::new(Command::Ping(2));
+
+    let init = State::AwaitingResponse {
+      send_queue: VecDeque::from(vec![sub2]),
+      command_sent: sub,
+    };
+
+    match init.next(Action::ResponseTimedOut) {
+      State::TimedOut {
+        send_queue,
+        command_sent,
+      } => {
         assert_eq!(send_queue.len(), 1);
+        assert_eq!(command_sent.command, cmd);
+      }
+
+      s => panic!("Unexpected state: {:?}", s),
+    }
+  }
+
+  #[test]
+  fn response_timed_out_for_cancelled_command_transitions_to_timed_out() {
+    let config = MidiDriverConfig {
+      max_timeout_retries: 2,
+      ..Default::default()
+    };
+    let (mut sub, response_rx) = CommandSubmission::with_config(Command::Ping(1), &config);
+    sub.cancellable = true;
+    drop(response_rx);
+
+    let init = State::AwaitingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+    };
+    match init.next(Action::ResponseTimedOut) {
+      State::TimedOut { command_sent, .. } => {
+        // not re-sent, even though it had retries left
+        assert_eq!(command_sent.timeout_retries_left, 2);
+        assert_eq!(command_sent.timeouts, 1);
       }
+      s => panic!("Unexpected state: {:?}", s),
+    }
+  }
+
+  #[test]
+  fn response_dispatched_while_timed_out_transitions_to_processing_queue() {
+    let (sub, _response_rx) = CommandSubmission::new(Command::Ping(1));
+    let (sub2, _response_rx) = CommandSubmission::new(Command::Ping(2));
+    let init = State::TimedOut {
+      send_queue: VecDeque::from(vec![sub2]),
+      command_sent: sub,
+    };
+
+    match init.next(Action::ResponseDispatched) {
+      State::ProcessingQueue { send_queue } => assert_eq!(send_queue.len(), 1),
+      s => panic!("Unexpected state: {:?}", s),
+    }
+  }
+
+  #[test]
+  fn device_busy_uses_up_a_busy_retry() {
+    let config = MidiDriverConfig {
+      max_busy_retries: 3,
+      max_timeout_retries: 3,
+      ..Default::default()
+    };
+    let (sub, _response_rx) = CommandSubmission::with_config(Command::Ping(1), &config);
+    let init = State::ProcessingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+      response_msg: response_with_status(ResponseStatusCode::Busy),
+    };
 
+    match init.next(Action::DeviceBusy) {
+      State::WaitingToRetry { to_retry, .. } => {
+        assert_eq!(to_retry.busy_retries_left, 2);
+        // the timeout budget is tracked separately
+        assert_eq!(to_retry.timeout_retries_left, 3);
+      }
       s => panic!("Unexpected state: {:?}", s),
     }
   }
@@ -1061,8 +2729,8 @@ mod tests {
   #[test]
   fn ready_to_retry_while_device_busy_transitions_to_processing_queue() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
-    let (sub2, _) = CommandSubmission::new(Command::Ping(2));
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
+    let (sub2, _response_rx) = CommandSubmission::new(Command::Ping(2));
 
     let send_queue = VecDeque::from(vec![sub2]);
     let init = State::WaitingToRetry {
@@ -1107,7 +2775,7 @@ mod tests {
   #[test]
   fn queue_empty_while_processing_queue_transitions_to_failed_if_queue_is_non_empty() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
     let init = State::ProcessingQueue {
       send_queue: VecDeque::from(vec![sub]),
     };
@@ -1128,18 +2796,71 @@ mod tests {
     }
   }
 
-  // endregion
-
-  // region State entry tests (for expected Effect)
-
   #[test]
-  fn entering_idle_state_has_no_effect() {
-    let mut s = State::Idle;
-    match s.enter() {
-      None => (),
-      Some(e) => panic!("unexpected effect: {:?}", e),
-    }
-  }
+  fn response_to_earlier_send_resolves_resent_command() {
+    use crate::midi::mock::reply_with_status;
+
+    // the first send timed out and we've re-sent it, but the answer to the first send
+    // shows up while the re-send is in flight
+    let cmd = Command::Ping(1);
+    let (mut sub, _response_rx) = CommandSubmission::new(cmd.clone());
+    sub.timeouts = 1;
+    let init = State::AwaitingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+    };
+    let late_ack = reply_with_status(&cmd.to_sysex_message(), ResponseStatusCode::Ack);
+
+    let mut next = init.next(Action::MessageReceived(late_ack));
+    match &next {
+      State::ProcessingResponse { command_sent, .. } => assert_eq!(command_sent.command, cmd),
+      s => panic!("unexpected state: {:?}", s),
+    }
+    match next.enter() {
+      Some(Effect::NotifyMessageResponse(_, Ok(_))) => (),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+  }
+
+  #[test]
+  fn late_response_for_resolved_command_does_not_transition() {
+    use crate::midi::mock::reply_with_status;
+
+    // the answer to the re-send shows up after the first send's answer resolved the command
+    let late_ack = reply_with_status(
+      &Command::Ping(1).to_sysex_message(),
+      ResponseStatusCode::Ack,
+    );
+    let (sub, _response_rx) = CommandSubmission::new(Command::Ping(2));
+    let init = State::AwaitingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+    };
+    match init.next(Action::LateResponseReceived(late_ack.clone())) {
+      State::AwaitingResponse { command_sent, .. } => {
+        assert_eq!(command_sent.command, Command::Ping(2))
+      }
+      s => panic!("unexpected state: {:?}", s),
+    }
+
+    match State::Idle.next(Action::LateResponseReceived(late_ack)) {
+      State::Idle => (),
+      s => panic!("unexpected state: {:?}", s),
+    }
+  }
+
+  // endregion
+
+  // region State entry tests (for expected Effect)
+
+  #[test]
+  fn entering_idle_state_notifies_queue_drained() {
+    let mut s = State::Idle;
+    match s.enter() {
+      Some(Effect::NotifyQueueDrained) => (),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+  }
 
   #[test]
   fn entering_processing_queue_while_queue_dispatches_queue_empty_action() {
@@ -1161,7 +2882,7 @@ mod tests {
     use State::ProcessingQueue;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
     let send_queue = VecDeque::from(vec![sub]);
     let mut s = ProcessingQueue { send_queue };
     match s.enter() {
@@ -1170,13 +2891,52 @@ mod tests {
     }
   }
 
+  #[test]
+  fn entering_processing_queue_skips_cancelled_commands() {
+    use Action::QueueEmpty;
+    use Effect::{DispatchAction, SendMidiMessage};
+    use State::ProcessingQueue;
+
+    let (mut cancelled, cancelled_rx) = CommandSubmission::new(Command::Ping(1));
+    let (wanted, _response_rx) = CommandSubmission::new(Command::Ping(2));
+    cancelled.cancellable = true;
+    drop(cancelled_rx);
+
+    let mut s = ProcessingQueue {
+      send_queue: VecDeque::from(vec![cancelled.clone(), wanted]),
+    };
+    match s.enter() {
+      Some(SendMidiMessage(sub)) => assert_eq!(sub.command, Command::Ping(2)),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+
+    let mut s = ProcessingQueue {
+      send_queue: VecDeque::from(vec![cancelled]),
+    };
+    match s.enter() {
+      Some(DispatchAction(QueueEmpty)) => (),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+
+    // dropping the receiver of a command that isn't cancellable doesn't cancel it
+    let (unwatched, unwatched_rx) = CommandSubmission::new(Command::Ping(3));
+    drop(unwatched_rx);
+    let mut s = ProcessingQueue {
+      send_queue: VecDeque::from(vec![unwatched]),
+    };
+    match s.enter() {
+      Some(SendMidiMessage(sub)) => assert_eq!(sub.command, Command::Ping(3)),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+  }
+
   #[test]
   fn entering_waiting_to_retry_returns_start_retry_timeout_effect() {
     use Effect::StartRetryTimeout;
     use State::WaitingToRetry;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
     let mut s = WaitingToRetry {
       send_queue: VecDeque::new(),
       to_retry: sub,
@@ -1193,7 +2953,7 @@ mod tests {
     use State::AwaitingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
     let mut s = AwaitingResponse {
       send_queue: VecDeque::new(),
       command_sent: sub,
@@ -1207,7 +2967,8 @@ mod tests {
   // helper fn to return a "pong" response message with a given status code
   #[allow(dead_code)]
   fn response_with_status(status: ResponseStatusCode) -> Vec<u8> {
-    let mut msg = Vec::from(MANUFACTURER_ID);
+    let mut msg = vec![SYSEX_START];
+    msg.extend(MANUFACTURER_ID);
     msg.push(0x0); // board index
     msg.push(CommandId::LumaPing.into()); // command id
     msg.push(status.into()); // status byte
@@ -1215,6 +2976,7 @@ mod tests {
     msg.push(0x0); // remaining zeros are ping id payload
     msg.push(0x0);
     msg.push(0x0);
+    msg.push(SYSEX_END);
 
     msg
   }
@@ -1225,7 +2987,7 @@ mod tests {
     use State::ProcessingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1245,7 +3007,7 @@ mod tests {
     use State::ProcessingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1265,7 +3027,7 @@ mod tests {
     use State::ProcessingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1286,7 +3048,7 @@ mod tests {
     use State::ProcessingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1300,6 +3062,51 @@ mod tests {
     }
   }
 
+  #[test]
+  fn entering_processing_response_with_status_busy_and_no_retries_left_notifies_error() {
+    use Effect::NotifyMessageResponse;
+
+    let config = MidiDriverConfig {
+      max_busy_retries: 0,
+      ..Default::default()
+    };
+    let (sub, _response_rx) = CommandSubmission::with_config(Command::Ping(1), &config);
+    let mut s = State::ProcessingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+      response_msg: response_with_status(ResponseStatusCode::Busy),
+    };
+
+    match s.enter() {
+      Some(NotifyMessageResponse(_, Err(LumatoneMidiError::DeviceBusy(_)))) => (),
+      e => panic!("unexpected effect: {:?}", e),
+    }
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn entering_timed_out_notifies_error() {
+    use Effect::NotifyMessageResponse;
+
+    let (mut sub, _response_rx) = CommandSubmission::new(Command::Ping(1));
+    sub.timeouts = 2;
+    // the clock is paused, so exactly 5 seconds pass
+    sub.first_sent_at = Some(Instant::now());
+    tokio::time::advance(Duration::from_secs(5)).await;
+    let mut s = State::TimedOut {
+      send_queue: VecDeque::new(),
+      command_sent: sub,
+    };
+
+    match s.enter() {
+      Some(NotifyMessageResponse(_, Err(LumatoneMidiError::ResponseTimedOut(msg)))) => {
+        assert!(msg.contains("Ping(1)"), "{msg}");
+        assert!(msg.contains("2 attempt(s)"), "{msg}");
+        assert!(msg.contains("5.0s since it was first sent"), "{msg}");
+      }
+      e => panic!("unexpected effect: {:?}", e),
+    }
+  }
+
   #[test]
   fn entering_processing_response_with_status_state_dispatches_device_busy_action() {
     use Action::DeviceBusy;
@@ -1307,7 +3114,7 @@ mod tests {
     use State::ProcessingResponse;
 
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1324,7 +3131,7 @@ mod tests {
   #[test]
   fn entering_processing_response_with_status_unknown_returns_no_effect() {
     let cmd = Command::Ping(1);
-    let (sub, _) = CommandSubmission::new(cmd.clone());
+    let (sub, _response_rx) = CommandSubmission::new(cmd.clone());
 
     let mut s = State::ProcessingResponse {
       send_queue: VecDeque::new(),
@@ -1339,4 +3146,1620 @@ mod tests {
   }
 
   // endregion
+
+  // region Device model tests
+
+  #[test]
+  fn device_model_tracks_aftertouch_flag() {
+    let mut model = DeviceModel::default();
+    assert_eq!(model.aftertouch_enabled, None);
+
+    model.record(&Command::SetAftertouchEnabled(true));
+    assert_eq!(model.aftertouch_enabled, Some(true));
+
+    // unrelated commands leave it alone
+    model.record(&Command::SetLightOnKeystrokes(false));
+    assert_eq!(model.aftertouch_enabled, Some(true));
+
+    model.record(&Command::SetAftertouchEnabled(false));
+    assert_eq!(model.aftertouch_enabled, Some(false));
+  }
+
+  #[test]
+  fn device_model_tracks_peripheral_calibration() {
+    use PeripheralCalibrationMode::*;
+
+    let mut model = DeviceModel::default();
+    model.record(&Command::EnablePitchModWheelCalibrationMode(true));
+    model.record(&Command::EnableExpressionPedalCalibrationMode(true));
+    // turning a mode on twice doesn't list it twice
+    model.record(&Command::EnablePitchModWheelCalibrationMode(true));
+    assert_eq!(
+      model.calibration_modes,
+      vec![ExpressionPedal, PitchAndModWheels]
+    );
+
+    model.record(&Command::EnableExpressionPedalCalibrationMode(false));
+    assert_eq!(model.calibration_modes, vec![PitchAndModWheels]);
+  }
+
+  #[tokio::test]
+  async fn cancel_calibration_turns_off_active_modes() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg: &[u8]| {
+      recorded.lock().unwrap().push(msg.to_vec());
+      Some(reply_with_status(msg, ResponseStatusCode::Ack))
+    }));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    // nothing to cancel yet
+    driver.cancel_calibration().await.unwrap();
+    assert!(sent.lock().unwrap().is_empty());
+
+    driver
+      .send(Command::EnableExpressionPedalCalibrationMode(true))
+      .await
+      .unwrap();
+    driver.send(Command::StartKeyCalibration).await.unwrap();
+    assert_eq!(
+      driver.active_calibration_modes(),
+      vec![PeripheralCalibrationMode::ExpressionPedal]
+    );
+
+    sent.lock().unwrap().clear();
+    driver.cancel_calibration().await.unwrap();
+    assert_eq!(
+      *sent.lock().unwrap(),
+      vec![Command::EnableExpressionPedalCalibrationMode(false).to_sysex_message()]
+    );
+    assert!(driver.active_calibration_modes().is_empty());
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn driver_only_records_acknowledged_aftertouch_changes() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    // ack enabling aftertouch, but reject disabling it
+    let responder = |msg: &[u8]| {
+      let status = if msg == Command::SetAftertouchEnabled(false).to_sysex_message() {
+        ResponseStatusCode::Nack
+      } else {
+        ResponseStatusCode::Ack
+      };
+      Some(reply_with_status(msg, status))
+    };
+    let (driver, driver_future) = MidiDriver::with_transport(
+      Box::new(MockDevice::new(Box::new(responder))),
+      MidiDriverConfig::default(),
+    );
+    let handle = tokio::spawn(driver_future);
+
+    assert_eq!(driver.aftertouch_enabled(), None);
+    driver
+      .send(Command::SetAftertouchEnabled(true))
+      .await
+      .unwrap();
+    assert_eq!(driver.aftertouch_enabled(), Some(true));
+
+    assert!(driver
+      .send(Command::SetAftertouchEnabled(false))
+      .await
+      .is_err());
+    assert_eq!(driver.aftertouch_enabled(), Some(true));
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  // endregion
+
+  // region Late response tests
+
+  #[test]
+  fn late_responses_are_expected_once_per_timed_out_send() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    let model = Arc::new(Mutex::new(DeviceModel::default()));
+    let (events_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+    let (trace_tx, _) = broadcast::channel(TRACE_CHANNEL_CAPACITY);
+    let mut internal = DriverLoop::new(
+      TokioExecutor::new(Box::new(MockDevice::acking())),
+      model,
+      events_tx,
+      trace_tx,
+      Arc::new(Mutex::new(None)),
+      &MidiDriverConfig {
+        receive_timeout: Duration::from_secs(1),
+        ..Default::default()
+      },
+    );
+
+    let cmd = Command::Ping(1);
+    let (mut sub, _response_rx) = CommandSubmission::new(cmd.clone());
+    sub.timeouts = 2;
+    internal.expect_late_responses(&sub);
+
+    let ack = reply_with_status(&cmd.to_sysex_message(), ResponseStatusCode::Ack);
+    let other_ack = reply_with_status(
+      &Command::Ping(2).to_sysex_message(),
+      ResponseStatusCode::Ack,
+    );
+    assert!(!internal.take_late_response(&other_ack));
+    assert!(internal.take_late_response(&ack));
+    assert!(internal.take_late_response(&ack));
+    assert!(!internal.take_late_response(&ack));
+  }
+
+  #[test]
+  fn resending_a_timed_out_command_is_answered_right_away() {
+    use crate::midi::constants::{key_loc_unchecked, RGBColor};
+    use crate::midi::mock::reply_with_status;
+
+    // the first send gets lost, and every send after that is acked
+    let mut sends = 0;
+    let responder = move |msg: &[u8]| {
+      sends += 1;
+      (sends > 1).then(|| reply_with_status(msg, ResponseStatusCode::Ack))
+    };
+    let set_color = Command::SetKeyColor {
+      location: key_loc_unchecked(1, 3),
+      color: RGBColor::red(),
+    };
+    let config = MidiDriverConfig {
+      max_timeout_retries: 1,
+      receive_timeout: Duration::from_secs(30),
+      ..Default::default()
+    };
+    let run = run_virtual(
+      Box::new(responder),
+      config,
+      vec![set_color.clone(), set_color.clone()],
+      false,
+    );
+
+    // the first command is resolved by its re-send, but its first send may still be
+    // answered. The ack for the second command mustn't be taken for that late response.
+    assert!(
+      run.results.iter().all(|r| matches!(r, Some(Ok(_)))),
+      "{:?}",
+      run.results
+    );
+    let msg = set_color.to_sysex_message();
+    assert_eq!(
+      run.sent,
+      vec![
+        (Duration::ZERO, msg.clone()),
+        (Duration::from_secs(30), msg.clone()),
+        (Duration::from_secs(30), msg),
+      ]
+    );
+    assert_eq!(run.elapsed, Duration::from_secs(30));
+  }
+
+  #[tokio::test]
+  async fn looped_back_ping_is_not_taken_as_response() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    // something between us and the device sends every message straight back
+    let device = MockDevice::new(Box::new(|msg: &[u8]| Some(msg.to_vec())));
+    let incoming = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let answer = async {
+      sleep(Duration::from_millis(20)).await;
+      let ping = Command::Ping(5).to_sysex_message();
+      incoming
+        .send(reply_with_status(&ping, ResponseStatusCode::Ack))
+        .await
+        .unwrap();
+    };
+    let (res, _) = tokio::join!(driver.send(Command::Ping(5)), answer);
+    assert!(matches!(res, Ok(Response::Pong(5))));
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  // endregion
+
+  // region Deterministic event loop tests
+
+  /// Executes effects against a simulated device, on a virtual clock.
+  ///
+  /// The device's replies are delivered before anything else, then submitted commands, then
+  /// the done signal. Only when none of those are waiting does the clock move, jumping
+  /// straight to the next timer. With nothing left to happen at all, the loop is told to
+  /// stop, so a scenario runs to completion without ever sleeping.
+  struct VirtualExecutor {
+    responder: Responder,
+    /// Replies from the device that the loop hasn't taken yet.
+    inbox: VecDeque<EncodedSysex>,
+    now: Duration,
+    /// When each running timer fires.
+    timers: HashMap<Timer, Duration>,
+    /// Every message sent to the device, with the virtual time it was sent at.
+    sent: Vec<(Duration, EncodedSysex)>,
+  }
+
+  impl VirtualExecutor {
+    fn new(responder: Responder) -> Self {
+      VirtualExecutor {
+        responder,
+        inbox: VecDeque::new(),
+        now: Duration::ZERO,
+        timers: HashMap::new(),
+        sent: vec![],
+      }
+    }
+  }
+
+  impl EffectExecutor for VirtualExecutor {
+    fn send_message(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+      self.sent.push((self.now, msg.to_vec()));
+      self.inbox.extend((self.responder)(msg));
+      Ok(())
+    }
+
+    fn start_timer(&mut self, timer: Timer, duration: Duration) {
+      self.timers.insert(timer, self.now + duration);
+    }
+
+    fn cancel_timer(&mut self, timer: Timer) {
+      self.timers.remove(&timer);
+    }
+
+    async fn next_input(
+      &mut self,
+      commands: &mut mpsc::Receiver<CommandSubmission>,
+      done: &mut mpsc::Receiver<()>,
+    ) -> Input {
+      if let Some(msg) = self.inbox.pop_front() {
+        return Input::Message(msg);
+      }
+      if let Ok(cmd) = commands.try_recv() {
+        return Input::Command(cmd);
+      }
+      if !matches!(done.try_recv(), Err(mpsc::error::TryRecvError::Empty)) {
+        return Input::Done;
+      }
+      let next_timer = self.timers.iter().min_by_key(|(_, at)| **at);
+      match next_timer.map(|(timer, at)| (*timer, *at)) {
+        Some((timer, at)) => {
+          self.timers.remove(&timer);
+          self.now = at;
+          Input::TimerFired(timer)
+        }
+        None => Input::Done,
+      }
+    }
+  }
+
+  /// The outcome of running a driver loop over a [VirtualExecutor].
+  struct VirtualRun {
+    /// Each command's result, or `None` if the loop stopped without resolving it.
+    results: Vec<Option<ResponseResult>>,
+    events: Vec<DriverEvent>,
+    sent: Vec<(Duration, EncodedSysex)>,
+    /// The virtual time when the loop stopped.
+    elapsed: Duration,
+  }
+
+  /// Submits `commands` to a driver loop talking to a device that replies with `responder`,
+  /// then runs the loop until it stops. If `shut_down` is set, the done signal is sent
+  /// along with the commands.
+  fn run_virtual(
+    responder: Responder,
+    config: MidiDriverConfig,
+    commands: Vec<Command>,
+    shut_down: bool,
+  ) -> VirtualRun {
+    let (events_tx, mut events_rx) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+    let (trace_tx, _) = broadcast::channel(TRACE_CHANNEL_CAPACITY);
+    let mut driver_loop = DriverLoop::new(
+      VirtualExecutor::new(responder),
+      Arc::new(Mutex::new(DeviceModel::default())),
+      events_tx,
+      trace_tx,
+      Arc::new(Mutex::new(None)),
+      &config,
+    );
+
+    // so that jittered retries happen at the same times on every run
+    driver_loop.rng = StdRng::seed_from_u64(1002);
+
+    let (command_tx, command_rx) = mpsc::channel(128);
+    let (done_tx, done_rx) = mpsc::channel(1);
+    let mut response_rxs = vec![];
+    for command in commands {
+      let (submission, response_rx) = CommandSubmission::with_config(command, &config);
+      command_tx.try_send(submission).unwrap();
+      response_rxs.push(response_rx);
+    }
+    if shut_down {
+      done_tx.try_send(()).unwrap();
+    }
+
+    // nothing in the loop waits on real time, so no runtime is needed
+    futures::executor::block_on(driver_loop.run(command_rx, done_rx));
+
+    let mut events = vec![];
+    while let Ok(event) = events_rx.try_recv() {
+      events.push(event);
+    }
+    VirtualRun {
+      results: response_rxs
+        .iter_mut()
+        .map(|rx| rx.try_recv().ok())
+        .collect(),
+      events,
+      sent: driver_loop.executor.sent,
+      elapsed: driver_loop.executor.now,
+    }
+  }
+
+  #[test]
+  fn virtual_timeout_resends_then_gives_up() {
+    let config = MidiDriverConfig {
+      max_timeout_retries: 1,
+      receive_timeout: Duration::from_secs(30),
+      ..Default::default()
+    };
+    let run = run_virtual(Box::new(|_| None), config, vec![Command::Ping(1)], false);
+
+    let ping = Command::Ping(1).to_sysex_message();
+    assert_eq!(
+      run.sent,
+      vec![
+        (Duration::ZERO, ping.clone()),
+        (Duration::from_secs(30), ping)
+      ]
+    );
+    match &run.results[0] {
+      Some(Err(LumatoneMidiError::ResponseTimedOut(msg))) => {
+        assert!(msg.contains("2 attempt(s)"), "{msg}")
+      }
+      r => panic!("expected a timeout, got {r:?}"),
+    }
+    assert_eq!(run.elapsed, Duration::from_secs(60));
+    assert_eq!(run.events, vec![DriverEvent::QueueDrained]);
+  }
+
+  #[test]
+  fn retry_delay_stays_within_jitter_window() {
+    let mut rng = StdRng::seed_from_u64(7);
+    assert_eq!(retry_delay(RETRY_DELAY, None, &mut rng), RETRY_DELAY);
+    assert_eq!(
+      retry_delay(RETRY_DELAY, Some(Duration::ZERO), &mut rng),
+      RETRY_DELAY
+    );
+
+    let jitter = Duration::from_millis(500);
+    let delays: Vec<Duration> = (0..100)
+      .map(|_| retry_delay(RETRY_DELAY, Some(jitter), &mut rng))
+      .collect();
+    for delay in &delays {
+      assert!(
+        *delay >= RETRY_DELAY - jitter && *delay <= RETRY_DELAY + jitter,
+        "{delay:?} is outside the jitter window"
+      );
+    }
+    assert!(
+      delays.iter().any(|d| *d != delays[0]),
+      "delays weren't jittered"
+    );
+
+    // the same seed gives the same delays
+    let mut again = StdRng::seed_from_u64(7);
+    retry_delay(RETRY_DELAY, None, &mut again);
+    retry_delay(RETRY_DELAY, Some(Duration::ZERO), &mut again);
+    assert_eq!(
+      retry_delay(RETRY_DELAY, Some(jitter), &mut again),
+      delays[0]
+    );
+
+    // jitter is capped at the delay, so it can't go negative
+    for _ in 0..100 {
+      assert!(retry_delay(RETRY_DELAY, Some(RETRY_DELAY * 10), &mut rng) <= RETRY_DELAY * 2);
+    }
+  }
+
+  #[test]
+  fn virtual_busy_retries_are_jittered() {
+    use crate::midi::mock::reply_with_status;
+
+    let mut busy_replies = 2;
+    let responder = move |msg: &[u8]| {
+      let status = if busy_replies > 0 {
+        busy_replies -= 1;
+        ResponseStatusCode::Busy
+      } else {
+        ResponseStatusCode::Ack
+      };
+      Some(reply_with_status(msg, status))
+    };
+    let jitter = Duration::from_secs(1);
+    let config = MidiDriverConfig {
+      retry_jitter: Some(jitter),
+      ..Default::default()
+    };
+    let run = run_virtual(Box::new(responder), config, vec![Command::Ping(1)], false);
+
+    let send_times: Vec<Duration> = run.sent.iter().map(|(at, _)| *at).collect();
+    assert_eq!(send_times.len(), 3);
+    for pair in send_times.windows(2) {
+      let delay = pair[1] - pair[0];
+      assert!(delay >= RETRY_DELAY - jitter && delay <= RETRY_DELAY + jitter);
+    }
+    assert_ne!(send_times[1], RETRY_DELAY);
+    assert!(matches!(run.results[0], Some(Ok(Response::Pong(1)))));
+  }
+
+  #[test]
+  fn virtual_busy_device_is_retried_after_a_delay() {
+    use crate::midi::mock::reply_with_status;
+
+    let mut busy_replies = 2;
+    let responder = move |msg: &[u8]| {
+      let status = if busy_replies > 0 {
+        busy_replies -= 1;
+        ResponseStatusCode::Busy
+      } else {
+        ResponseStatusCode::Ack
+      };
+      Some(reply_with_status(msg, status))
+    };
+    let run = run_virtual(
+      Box::new(responder),
+      MidiDriverConfig::default(),
+      vec![Command::Ping(1)],
+      false,
+    );
+
+    let send_times: Vec<Duration> = run.sent.iter().map(|(at, _)| *at).collect();
+    assert_eq!(
+      send_times,
+      vec![Duration::ZERO, RETRY_DELAY, RETRY_DELAY * 2]
+    );
+    assert!(matches!(run.results[0], Some(Ok(Response::Pong(1)))));
+    assert_eq!(
+      run.events,
+      vec![
+        DriverEvent::DeviceBusy,
+        DriverEvent::DeviceBusy,
+        DriverEvent::QueueDrained
+      ]
+    );
+  }
+
+  #[test]
+  fn virtual_always_busy_device_gives_up_after_max_busy_retries() {
+    use crate::midi::mock::reply_with_status;
+
+    let responder = |msg: &[u8]| Some(reply_with_status(msg, ResponseStatusCode::Busy));
+    let config = MidiDriverConfig {
+      max_busy_retries: 3,
+      ..Default::default()
+    };
+    let run = run_virtual(Box::new(responder), config, vec![Command::Ping(1)], false);
+
+    // the first send, plus one for each retry
+    assert_eq!(run.sent.len(), 4);
+    assert_eq!(run.elapsed, RETRY_DELAY * 3);
+    assert!(matches!(
+      run.results[0],
+      Some(Err(LumatoneMidiError::DeviceBusy(_)))
+    ));
+    assert_eq!(
+      run.events,
+      vec![
+        DriverEvent::DeviceBusy,
+        DriverEvent::DeviceBusy,
+        DriverEvent::DeviceBusy,
+        DriverEvent::DeviceBusy,
+        DriverEvent::QueueDrained
+      ]
+    );
+  }
+
+  #[test]
+  fn virtual_busy_retry_uses_configured_timeout() {
+    use crate::midi::mock::reply_with_status;
+
+    let mut busy = true;
+    let responder = move |msg: &[u8]| {
+      let status = if busy {
+        ResponseStatusCode::Busy
+      } else {
+        ResponseStatusCode::Ack
+      };
+      busy = false;
+      Some(reply_with_status(msg, status))
+    };
+    let config = MidiDriverConfig {
+      retry_timeout: Duration::from_millis(250),
+      ..Default::default()
+    };
+    let run = run_virtual(Box::new(responder), config, vec![Command::Ping(1)], false);
+
+    let send_times: Vec<Duration> = run.sent.iter().map(|(at, _)| *at).collect();
+    assert_eq!(send_times, vec![Duration::ZERO, Duration::from_millis(250)]);
+    assert!(matches!(run.results[0], Some(Ok(Response::Pong(1)))));
+  }
+
+  #[test]
+  fn virtual_response_cancels_the_receive_timeout() {
+    use crate::midi::mock::reply_with_status;
+
+    // if the receive timer were left running, the loop would wait for it to fire
+    let acking = |msg: &[u8]| Some(reply_with_status(msg, ResponseStatusCode::Ack));
+    let run = run_virtual(
+      Box::new(acking),
+      MidiDriverConfig::default(),
+      vec![Command::Ping(1), Command::Ping(2)],
+      false,
+    );
+    assert_eq!(run.sent.len(), 2);
+    assert!(run.results.iter().all(|r| matches!(r, Some(Ok(_)))));
+    assert_eq!(run.elapsed, Duration::ZERO);
+  }
+
+  #[test]
+  fn virtual_shutdown_after_the_queue_drains() {
+    use crate::midi::mock::reply_with_status;
+
+    // commands already submitted when the done signal arrives are still sent and resolved
+    let acking = |msg: &[u8]| Some(reply_with_status(msg, ResponseStatusCode::Ack));
+    let commands = vec![Command::Ping(1), Command::Ping(2), Command::Ping(3)];
+    let run = run_virtual(
+      Box::new(acking),
+      MidiDriverConfig::default(),
+      commands,
+      true,
+    );
+    assert_eq!(run.sent.len(), 3);
+    assert!(run.results.iter().all(|r| matches!(r, Some(Ok(_)))));
+    assert_eq!(run.events, vec![DriverEvent::QueueDrained]);
+    assert_eq!(run.elapsed, Duration::ZERO);
+
+    // but shutting down doesn't wait for a response that's still on its way
+    let run = run_virtual(
+      Box::new(|_| None),
+      MidiDriverConfig::default(),
+      vec![Command::Ping(1)],
+      true,
+    );
+    assert_eq!(run.sent.len(), 1);
+    assert!(run.results[0].is_none());
+    assert!(run.events.is_empty());
+    assert_eq!(run.elapsed, Duration::ZERO);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn calibration_statuses_are_throttled() {
+    let (events_tx, events_rx) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+    let (status_tx, mut status_rx) = mpsc::channel(16);
+    tokio::spawn(throttle_calibration_status(
+      events_rx,
+      Duration::from_millis(320),
+      status_tx,
+    ));
+
+    // the device reports every 100ms
+    tokio::spawn(async move {
+      for n in 0..10 {
+        let status = CalibrationStatus {
+          kind: CalibrationKind::Keys,
+          board: BoardIndex::Octave1,
+          payload: vec![n],
+        };
+        events_tx
+          .send(DriverEvent::CalibrationStatus(status))
+          .unwrap();
+        events_tx.send(DriverEvent::QueueDrained).unwrap();
+        sleep(Duration::from_millis(100)).await;
+      }
+    });
+
+    let started = Instant::now();
+    let mut received = vec![];
+    while let Some(status) = status_rx.recv().await {
+      received.push((status.payload[0], started.elapsed().as_millis()));
+    }
+    assert_eq!(received, vec![(0, 0), (3, 320), (6, 640), (9, 960)]);
+  }
+
+  thread_local! {
+    static WARNINGS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+  }
+
+  /// Records warnings logged on the current thread, so tests running in parallel don't see
+  /// each other's.
+  struct WarningCapture;
+
+  impl log::Log for WarningCapture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+      metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+      if self.enabled(record.metadata()) {
+        WARNINGS.with(|w| w.borrow_mut().push(record.args().to_string()));
+      }
+    }
+
+    fn flush(&self) {}
+  }
+
+  /// Runs an idle driver loop that receives `messages` from the device, and returns the
+  /// warnings it logged.
+  fn warnings_for_unsolicited(messages: Vec<EncodedSysex>, log_unsolicited: bool) -> Vec<String> {
+    static LOGGER: WarningCapture = WarningCapture;
+    // fails if another test got here first, which is fine
+    if log::set_logger(&LOGGER).is_ok() {
+      log::set_max_level(log::LevelFilter::Warn);
+    }
+
+    let (events_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+    let (trace_tx, _) = broadcast::channel(TRACE_CHANNEL_CAPACITY);
+    let mut executor = VirtualExecutor::new(Box::new(|_| None));
+    executor.inbox.extend(messages);
+    let mut driver_loop = DriverLoop::new(
+      executor,
+      Arc::new(Mutex::new(DeviceModel::default())),
+      events_tx,
+      trace_tx,
+      Arc::new(Mutex::new(None)),
+      &MidiDriverConfig {
+        receive_timeout: Duration::from_secs(1),
+        log_unsolicited,
+        ..Default::default()
+      },
+    );
+    let (_command_tx, command_rx) = mpsc::channel(1);
+    let (_done_tx, done_rx) = mpsc::channel(1);
+
+    WARNINGS.with(|w| w.borrow_mut().clear());
+    futures::executor::block_on(driver_loop.run(command_rx, done_rx));
+    WARNINGS.with(|w| w.take())
+  }
+
+  #[test]
+  fn wheel_calibration_reports_never_answer_a_command() {
+    use crate::midi::constants::CommandId;
+    use crate::midi::mock::reply_with_status;
+    use crate::midi::sysex::create_sysex;
+
+    let (events_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+    let (trace_tx, _) = broadcast::channel(TRACE_CHANNEL_CAPACITY);
+    let mut driver_loop = DriverLoop::new(
+      VirtualExecutor::new(Box::new(|_| None)),
+      Arc::new(Mutex::new(DeviceModel::default())),
+      events_tx,
+      trace_tx,
+      Arc::new(Mutex::new(None)),
+      &MidiDriverConfig::default(),
+    );
+    let awaiting = |command: Command| State::AwaitingResponse {
+      send_queue: VecDeque::new(),
+      command_sent: CommandSubmission::new(command).0,
+    };
+    let ack =
+      |command: &Command| reply_with_status(&command.to_sysex_message(), ResponseStatusCode::Ack);
+    let mut report_data = vec![ResponseStatusCode::Ack as u8];
+    report_data.extend([0x1; 15]);
+    let report = create_sysex(
+      BoardIndex::Server,
+      CommandId::CalibratePitchModWheel,
+      report_data,
+    );
+
+    // enable, then the reports stream in while other commands are in flight, then disable
+    let enable = Command::EnablePitchModWheelCalibrationMode(true);
+    let ping = Command::Ping(1);
+    let disable = Command::EnablePitchModWheelCalibrationMode(false);
+    for command in [enable, ping, disable] {
+      let state = awaiting(command.clone());
+      assert!(driver_loop
+        .action_for_message(report.clone(), &state)
+        .is_none());
+      assert!(matches!(
+        driver_loop.action_for_message(ack(&command), &state),
+        Some(Action::MessageReceived(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn unsolicited_calibration_status_is_quiet_unless_configured() {
+    use crate::midi::mock::reply_with_status;
+
+    let status = |cmd: Command| reply_with_status(&cmd.to_sysex_message(), ResponseStatusCode::Ack);
+    let statuses = vec![
+      status(Command::EnableExpressionPedalCalibrationMode(true)),
+      status(Command::EnablePitchModWheelCalibrationMode(true)),
+    ];
+    assert!(warnings_for_unsolicited(statuses.clone(), false).is_empty());
+    assert_eq!(warnings_for_unsolicited(statuses, true).len(), 2);
+
+    // anything else the device sends out of the blue is still worth a warning
+    let stray = status(Command::Ping(1));
+    let warnings = warnings_for_unsolicited(vec![stray], false);
+    assert_eq!(warnings.len(), 1);
+    assert!(
+      warnings[0].contains("not awaiting response"),
+      "{warnings:?}"
+    );
+  }
+
+  // endregion
+
+  // region Event tests
+
+  #[tokio::test]
+  async fn queue_drained_fires_once_after_last_response() {
+    use crate::midi::mock::MockDevice;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(MockDevice::acking()), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    let mut events = driver.subscribe_events();
+
+    let drained = Arc::new(AtomicUsize::new(0));
+    let drained_in_callback = drained.clone();
+    let results = driver
+      .send_all(
+        vec![Command::Ping(1), Command::Ping(2), Command::Ping(3)],
+        Some(Box::new(move || {
+          drained_in_callback.fetch_add(1, Ordering::SeqCst);
+        })),
+      )
+      .await;
+
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(drained.load(Ordering::SeqCst), 1);
+    assert_eq!(events.recv().await.unwrap(), DriverEvent::QueueDrained);
+    assert!(matches!(events.try_recv(), Err(TryRecvError::Empty)));
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn flush_waits_for_every_submitted_command() {
+    use crate::midi::commands::ping;
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use tokio::time::timeout;
+
+    // the device stays quiet until the test answers for it
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg: &[u8]| {
+      recorded.lock().unwrap().push(msg.to_vec());
+      None
+    }));
+    let device_tx = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    let driver = Arc::new(driver);
+
+    // lets every other task run until it's stuck
+    let settle = || sleep(Duration::from_millis(1));
+
+    let mut sends = vec![];
+    for i in 0..3 {
+      let driver = driver.clone();
+      sends.push(tokio::spawn(async move { driver.send(ping(i)).await }));
+      settle().await;
+    }
+
+    let flush = driver.flush();
+    tokio::pin!(flush);
+    for answered in 0..3 {
+      assert!(
+        timeout(Duration::from_millis(5), &mut flush).await.is_err(),
+        "flush resolved after {answered} responses"
+      );
+      let last_sent = sent.lock().unwrap().last().unwrap().clone();
+      device_tx
+        .send(reply_with_status(&last_sent, ResponseStatusCode::Ack))
+        .await
+        .unwrap();
+      settle().await;
+    }
+    timeout(Duration::from_millis(5), &mut flush)
+      .await
+      .expect("flush should resolve after the third response")
+      .unwrap();
+    for send in sends {
+      assert!(send.await.unwrap().is_ok());
+    }
+
+    // nothing pending, so there's nothing to wait for
+    driver.flush().await.unwrap();
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn send_with_timeout_gives_up_and_drops_the_unsent_command() {
+    use crate::midi::commands::ping;
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    // the device stays quiet until the test answers for it
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg: &[u8]| {
+      recorded.lock().unwrap().push(msg.to_vec());
+      None
+    }));
+    let device_tx = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    let driver = Arc::new(driver);
+
+    // the first ping is in flight, so the second waits in the queue until we give up on it
+    let first = {
+      let driver = driver.clone();
+      tokio::spawn(async move { driver.send(ping(1)).await })
+    };
+    sleep(Duration::from_millis(1)).await;
+    match driver
+      .send_with_timeout(ping(2), Duration::from_secs(1))
+      .await
+    {
+      Err(LumatoneMidiError::ResponseTimedOut(msg)) => assert!(msg.contains("1s"), "{msg}"),
+      r => panic!("expected a timeout, got {r:?}"),
+    }
+
+    let first_sent = sent.lock().unwrap()[0].clone();
+    device_tx
+      .send(reply_with_status(&first_sent, ResponseStatusCode::Ack))
+      .await
+      .unwrap();
+    assert!(matches!(first.await.unwrap(), Ok(Response::Pong(1))));
+
+    // the cancelled ping never goes out, and the driver is free for the next command
+    driver.flush().await.unwrap();
+    assert_eq!(
+      sent.lock().unwrap().clone(),
+      vec![ping(1).to_sysex_message()]
+    );
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn blocking_send_keeps_commands_whose_receiver_was_dropped() {
+    use crate::midi::commands::ping;
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    // the device stays quiet until the test answers for it
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg: &[u8]| {
+      recorded.lock().unwrap().push(msg.to_vec());
+      None
+    }));
+    let device_tx = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    let driver = Arc::new(driver);
+
+    // the first ping is in flight, so the second waits in the queue after its receiver
+    // is dropped
+    let first = {
+      let driver = driver.clone();
+      tokio::spawn(async move { driver.send(ping(1)).await })
+    };
+    while sent.lock().unwrap().is_empty() {
+      sleep(Duration::from_millis(1)).await;
+    }
+    let blocking_driver = driver.clone();
+    tokio::task::spawn_blocking(move || blocking_driver.blocking_send(ping(2)).map(drop))
+      .await
+      .unwrap()
+      .unwrap();
+
+    let first_sent = sent.lock().unwrap()[0].clone();
+    device_tx
+      .send(reply_with_status(&first_sent, ResponseStatusCode::Ack))
+      .await
+      .unwrap();
+    assert!(matches!(first.await.unwrap(), Ok(Response::Pong(1))));
+
+    let second_sent = async {
+      while sent.lock().unwrap().len() < 2 {
+        sleep(Duration::from_millis(1)).await;
+      }
+    };
+    tokio::time::timeout(Duration::from_secs(5), second_sent)
+      .await
+      .expect("the second ping was never sent");
+    let second_sent = sent.lock().unwrap()[1].clone();
+    assert_eq!(second_sent, ping(2).to_sysex_message());
+    device_tx
+      .send(reply_with_status(&second_sent, ResponseStatusCode::Ack))
+      .await
+      .unwrap();
+    driver.flush().await.unwrap();
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn typed_getters_decode_their_responses() {
+    use crate::midi::constants::MidiChannel;
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::responses::{FirmwareVersion, PeripheralChannels};
+    use crate::midi::sysex::{create_sysex, message_command_id};
+
+    // `serial` is None for early firmware, which doesn't send one
+    let device = |serial: Option<[u8; 6]>| {
+      MockDevice::new(Box::new(move |msg: &[u8]| {
+        use CommandId::*;
+        let command = message_command_id(msg).unwrap();
+        let data = match (command, serial) {
+          (GetSerialIdentity, Some(serial)) => serial.to_vec(),
+          (GetFirmwareRevision, _) => vec![1, 9, 4],
+          (GetPeripheralChannels, _) => vec![0, 1, 2, 15],
+          (GetExpressionPedalSensitivity, _) => vec![42],
+          _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+        };
+        let canned = create_sysex(BoardIndex::Server, command, data);
+        Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+      }))
+    };
+
+    let (driver, driver_future) = MidiDriver::with_transport(
+      Box::new(device(Some([1, 2, 3, 4, 5, 6]))),
+      MidiDriverConfig::default(),
+    );
+    let handle = tokio::spawn(driver_future);
+    assert_eq!(driver.get_serial_id().await.unwrap(), [1, 2, 3, 4, 5, 6]);
+    assert_eq!(
+      driver.get_firmware_revision().await.unwrap(),
+      FirmwareVersion {
+        major: 1,
+        minor: 9,
+        revision: 4
+      }
+    );
+    assert_eq!(
+      driver.get_peripheral_channels().await.unwrap(),
+      PeripheralChannels {
+        pitch_wheel: MidiChannel::unchecked(1),
+        mod_wheel: MidiChannel::unchecked(2),
+        expression: MidiChannel::unchecked(3),
+        sustain: MidiChannel::unchecked(16),
+      }
+    );
+    assert_eq!(driver.get_expression_pedal_sensitivity().await.unwrap(), 42);
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device(None)), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    assert!(matches!(
+      driver.get_serial_id().await,
+      Err(LumatoneMidiError::FirmwareTooOld(_))
+    ));
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn get_board_colors_combines_led_tables() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::sysex::{create_sysex, message_command_id, BOARD_IND};
+
+    // board 2's blue table leaves out the last key, and board 3 doesn't answer for green
+    let device = MockDevice::new(Box::new(|msg: &[u8]| {
+      use CommandId::*;
+      let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+      let command = message_command_id(msg).unwrap();
+      let nibbles = |value: u8, key_count: usize| [value >> 4, value & 0xf].repeat(key_count);
+      let data = match (command, board) {
+        (GetGreenLedConfig, BoardIndex::Octave3) => {
+          return Some(reply_with_status(msg, ResponseStatusCode::Nack))
+        }
+        (GetRedLedConfig, _) => nibbles(0x10, 56),
+        (GetGreenLedConfig, _) => nibbles(0x80, 56),
+        (GetBlueLedConfig, BoardIndex::Octave2) => nibbles(0xff, 55),
+        (GetBlueLedConfig, _) => nibbles(0xff, 56),
+        _ => return Some(reply_with_status(msg, ResponseStatusCode::Ack)),
+      };
+      let canned = create_sysex(board, command, data);
+      Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+    }));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let colors = driver.get_board_colors(BoardIndex::Octave1).await.unwrap();
+    assert_eq!(colors, vec![RGBColor(0x10, 0x80, 0xff); 56]);
+    let colors = driver.get_board_colors(BoardIndex::Octave2).await.unwrap();
+    assert_eq!(colors.len(), 55);
+    match driver.get_board_colors(BoardIndex::Octave3).await {
+      Err(LumatoneMidiError::CommandFailed { command, .. }) => {
+        assert_eq!(command, CommandId::GetGreenLedConfig)
+      }
+      other => panic!("expected the green table to fail, got {other:?}"),
+    }
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[test]
+  fn unexpected_responses_name_what_was_expected() {
+    use crate::midi::responses::unexpected_response;
+
+    let err = unexpected_response("a serial id", &Response::Pong(7));
+    assert!(matches!(err, LumatoneMidiError::InvalidResponseMessage(_)));
+    assert_eq!(
+      err.to_string(),
+      "received invalid response: expected a serial id, got Pong(7)"
+    );
+  }
+
+  #[tokio::test]
+  async fn set_key_sends_function_and_color() {
+    use crate::midi::constants::{key_loc_unchecked, LumatoneKeyFunction, MidiChannel, RGBColor};
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::sysex::message_command_id;
+
+    let location = key_loc_unchecked(2, 7);
+    let def = KeyDefinition {
+      function: LumatoneKeyFunction::NoteOnOff {
+        channel: MidiChannel::unchecked(3),
+        note_num: 64,
+      },
+      color: RGBColor::blue(),
+    };
+
+    let sent = Arc::new(Mutex::new(vec![]));
+    let recorded = sent.clone();
+    let device = MockDevice::new(Box::new(move |msg: &[u8]| {
+      recorded
+        .lock()
+        .unwrap()
+        .push(message_command_id(msg).unwrap());
+      Some(reply_with_status(msg, ResponseStatusCode::Ack))
+    }));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    driver.set_key(location, &def).await.unwrap();
+    assert_eq!(
+      *sent.lock().unwrap(),
+      vec![CommandId::ChangeKeyNote, CommandId::SetKeyColour]
+    );
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+
+    // a device that rejects colors
+    let device = MockDevice::new(Box::new(|msg: &[u8]| {
+      let status = match message_command_id(msg) {
+        Ok(CommandId::SetKeyColour) => ResponseStatusCode::Nack,
+        _ => ResponseStatusCode::Ack,
+      };
+      Some(reply_with_status(msg, status))
+    }));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+    match driver.set_key(location, &def).await {
+      Err(LumatoneMidiError::SetKeyFailed {
+        location: failed,
+        errors,
+      }) => {
+        assert_eq!(failed, location);
+        let parts: Vec<_> = errors.iter().map(|(part, _)| *part).collect();
+        assert_eq!(parts, vec![KeyPart::Color]);
+      }
+      other => panic!("expected the color to fail, got {other:?}"),
+    }
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn sample_keys_collects_streamed_readings() {
+    use crate::midi::mock::MockDevice;
+    use crate::midi::sysex::{SYSEX_END, SYSEX_START};
+
+    let frame = |board: BoardIndex, value: u16| {
+      let mut msg = vec![SYSEX_START];
+      msg.extend(MANUFACTURER_ID);
+      msg.push(board as u8);
+      msg.push(CommandId::SetKeySampling as u8);
+      msg.push(ResponseStatusCode::Ack as u8);
+      for _ in 0..56 {
+        msg.extend([
+          (value >> 8) as u8 & 0xf,
+          (value >> 4) as u8 & 0xf,
+          value as u8 & 0xf,
+        ]);
+      }
+      msg.push(SYSEX_END);
+      msg
+    };
+
+    let device = MockDevice::acking();
+    let incoming = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let stream = async {
+      sleep(Duration::from_millis(20)).await;
+      for value in [1000, 1010, 990] {
+        incoming
+          .send(frame(BoardIndex::Octave2, value))
+          .await
+          .unwrap();
+      }
+      // other boards' readings don't count
+      incoming
+        .send(frame(BoardIndex::Octave3, 4000))
+        .await
+        .unwrap();
+    };
+    let (report, _) = tokio::join!(
+      driver.sample_keys(BoardIndex::Octave2, Duration::from_millis(200)),
+      stream
+    );
+    let report = report.unwrap();
+    assert_eq!(report.board, BoardIndex::Octave2);
+    assert_eq!(report.frames, 3);
+    assert_eq!(report.keys.len(), 56);
+    let key = report.key(55).unwrap();
+    assert_eq!((key.min, key.max, key.mean), (990, 1010, 1000.0));
+
+    // the stream didn't get in the way of the next command's response
+    assert!(matches!(
+      driver.send(Command::Ping(7)).await,
+      Ok(Response::Pong(7))
+    ));
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn calibrate_keys_reports_status_messages_until_boards_go_quiet() {
+    use crate::midi::calibration::CalibrationOutcome;
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::sysex::create_sysex;
+
+    let status = |board: BoardIndex| {
+      let msg = create_sysex(board, CommandId::CalibrateKeys, vec![0x1]);
+      reply_with_status(&msg, ResponseStatusCode::Ack)
+    };
+
+    let device = MockDevice::acking();
+    let incoming = device.incoming_sender();
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let timing = CalibrationTiming {
+      quiet_period: Duration::from_millis(100),
+      max_duration: Duration::from_secs(5),
+    };
+    let mut progress = driver.calibrate_keys(timing).await.unwrap();
+    assert_eq!(progress.recv().await, Some(CalibrationProgress::Started));
+
+    for board in [BoardIndex::Octave1, BoardIndex::Octave3] {
+      incoming.send(status(board)).await.unwrap();
+      match progress.recv().await {
+        Some(CalibrationProgress::Status(s)) => assert_eq!(s.board, board),
+        other => panic!("expected a status, got {other:?}"),
+      }
+    }
+    assert_eq!(
+      progress.recv().await,
+      Some(CalibrationProgress::Finished(
+        CalibrationOutcome::Completed {
+          boards: vec![BoardIndex::Octave1, BoardIndex::Octave3],
+        }
+      ))
+    );
+    assert_eq!(progress.recv().await, None);
+
+    // with nothing to report, it gives up at the time limit
+    let timing = CalibrationTiming {
+      quiet_period: Duration::from_millis(100),
+      max_duration: Duration::from_millis(50),
+    };
+    let mut progress = driver.calibrate_aftertouch(timing).await.unwrap();
+    assert_eq!(progress.recv().await, Some(CalibrationProgress::Started));
+    assert_eq!(
+      progress.recv().await,
+      Some(CalibrationProgress::Finished(
+        CalibrationOutcome::Unconfirmed
+      ))
+    );
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn streamed_messages_are_held_in_bounded_buffers() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::sysex::create_sysex;
+
+    // what a device stuck calibrating might send: key calibration status messages, which
+    // become events, and pedal calibration reports, which are only logged
+    let key_status = reply_with_status(
+      &create_sysex(BoardIndex::Octave2, CommandId::CalibrateKeys, vec![0x1]),
+      ResponseStatusCode::Ack,
+    );
+    let pedal_report = create_sysex(
+      BoardIndex::Server,
+      CommandId::PeripheralCalbrationData,
+      vec![0x0, 0x1, 0x2, 0x3],
+    );
+
+    let device = MockDevice::acking();
+    let incoming = device.incoming_sender();
+    let config = MidiDriverConfig {
+      event_capacity: 16,
+      ..Default::default()
+    };
+    let (driver, driver_future) = MidiDriver::with_transport(Box::new(device), config);
+    let handle = tokio::spawn(driver_future);
+
+    // with nobody subscribed, nothing is kept
+    for _ in 0..5_000 {
+      incoming.send(key_status.clone()).await.unwrap();
+      incoming.send(pedal_report.clone()).await.unwrap();
+    }
+    assert!(matches!(
+      driver.send(Command::Ping(1)).await,
+      Ok(Response::Pong(1))
+    ));
+
+    // a subscriber that stops reading only holds the newest events
+    let mut stalled = driver.subscribe_events();
+    for _ in 0..5_000 {
+      incoming.send(key_status.clone()).await.unwrap();
+      incoming.send(pedal_report.clone()).await.unwrap();
+    }
+    assert!(matches!(
+      driver.send(Command::Ping(2)).await,
+      Ok(Response::Pong(2))
+    ));
+    let missed = match stalled.recv().await {
+      Err(broadcast::error::RecvError::Lagged(n)) => n as usize,
+      other => panic!("expected to have missed events, got {other:?}"),
+    };
+    // the ping may have been followed by a QueueDrained event
+    let kept: Vec<DriverEvent> = std::iter::from_fn(|| stalled.try_recv().ok()).collect();
+    assert!(kept.len() <= 16);
+    let statuses = kept
+      .iter()
+      .filter(|e| matches!(e, DriverEvent::CalibrationStatus(_)))
+      .count();
+    assert_eq!(missed + statuses, 5_000);
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn busy_responses_are_reported_as_events() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    let responder = |msg: &[u8]| Some(reply_with_status(msg, ResponseStatusCode::Busy));
+    let config = MidiDriverConfig {
+      max_busy_retries: 0,
+      ..Default::default()
+    };
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(MockDevice::new(Box::new(responder))), config);
+    let handle = tokio::spawn(driver_future);
+    let mut events = driver.subscribe_events();
+
+    assert!(matches!(
+      driver.send(Command::Ping(1)).await,
+      Err(LumatoneMidiError::DeviceBusy(_))
+    ));
+    assert_eq!(events.recv().await.unwrap(), DriverEvent::DeviceBusy);
+    assert_eq!(events.recv().await.unwrap(), DriverEvent::QueueDrained);
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn resolved_commands_are_traced() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+
+    let responder = |msg: &[u8]| {
+      let status = if msg == Command::Ping(2).to_sysex_message().as_slice() {
+        ResponseStatusCode::Nack
+      } else {
+        ResponseStatusCode::Ack
+      };
+      Some(reply_with_status(msg, status))
+    };
+    let (driver, driver_future) = MidiDriver::with_transport(
+      Box::new(MockDevice::new(Box::new(responder))),
+      MidiDriverConfig::default(),
+    );
+    let handle = tokio::spawn(driver_future);
+    let mut trace = driver.subscribe_trace();
+
+    driver.send(Command::Ping(1)).await.unwrap();
+    assert!(driver.send(Command::Ping(2)).await.is_err());
+
+    let ok = trace.recv().await.unwrap();
+    assert_eq!(ok.command, "Ping(1)");
+    assert!(ok.result.is_ok());
+    assert!(ok.latency.is_some());
+
+    let failed = trace.recv().await.unwrap();
+    assert_eq!(failed.command, "Ping(2)");
+    assert!(failed.result.is_err());
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn read_all_thresholds_collects_each_board() {
+    use crate::midi::mock::{reply_with_status, MockDevice};
+    use crate::midi::sysex::{create_sysex, BOARD_IND};
+
+    // each board answers with thresholds based on its index, except any in `failing`
+    let responder = |failing: Vec<BoardIndex>| {
+      move |msg: &[u8]| {
+        let board = BoardIndex::try_from(msg[BOARD_IND + 1]).unwrap();
+        if failing.contains(&board) {
+          return Some(reply_with_status(msg, ResponseStatusCode::Nack));
+        }
+        let n = board as u8;
+        let values = [n, n + 10, n + 20, n + 30, n + 40];
+        let nibbles = values.iter().flat_map(|v| [v >> 4, v & 0xf]).collect();
+        let canned = create_sysex(board, CommandId::GetBoardThresholdValues, nibbles);
+        Some(reply_with_status(&canned, ResponseStatusCode::Ack))
+      }
+    };
+
+    let device = MockDevice::new(Box::new(responder(vec![])));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let thresholds = driver.read_all_thresholds().await.unwrap();
+    assert_eq!(thresholds.len(), 5);
+    assert_eq!(
+      thresholds[&BoardIndex::Octave3],
+      Thresholds {
+        min_high: 3,
+        min_low: 13,
+        max: 23,
+        aftertouch: 33,
+        cc: 43,
+      }
+    );
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+
+    let device = MockDevice::new(Box::new(responder(vec![
+      BoardIndex::Octave2,
+      BoardIndex::Octave5,
+    ])));
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(device), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    match driver.read_all_thresholds().await {
+      Err(LumatoneMidiError::BoardErrors(errors)) => {
+        let boards: Vec<BoardIndex> = errors.iter().map(|(b, _)| *b).collect();
+        assert_eq!(boards, vec![BoardIndex::Octave2, BoardIndex::Octave5]);
+      }
+      other => panic!("expected board errors, got {other:?}"),
+    }
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn runs_over_a_custom_transport() {
+    use crate::midi::mock::reply_with_status;
+    use std::sync::atomic::AtomicBool;
+
+    /// Loops every message straight back, acknowledged, and notes when it's closed.
+    struct Loopback {
+      tx: mpsc::Sender<EncodedSysex>,
+      rx: mpsc::Receiver<EncodedSysex>,
+      closed: Arc<AtomicBool>,
+    }
+
+    impl MidiTransport for Loopback {
+      fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+        self
+          .tx
+          .try_send(reply_with_status(msg, ResponseStatusCode::Ack))
+          .map_err(|e| LumatoneMidiError::DeviceSendError(e.to_string()))
+      }
+
+      fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex> {
+        &mut self.rx
+      }
+
+      fn close(self: Box<Self>) {
+        self.closed.store(true, Ordering::SeqCst);
+      }
+    }
+
+    let (tx, rx) = mpsc::channel(8);
+    let closed = Arc::new(AtomicBool::new(false));
+    let transport = Loopback {
+      tx,
+      rx,
+      closed: closed.clone(),
+    };
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(transport), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    assert!(matches!(
+      driver.send(Command::Ping(42)).await,
+      Ok(Response::Pong(42))
+    ));
+    assert!(!closed.load(Ordering::SeqCst));
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+    assert!(closed.load(Ordering::SeqCst));
+  }
+
+  #[tokio::test]
+  async fn last_error_reports_why_the_driver_stopped() {
+    /// A transport for a device that's been unplugged.
+    struct Unplugged(mpsc::Receiver<EncodedSysex>);
+
+    impl MidiTransport for Unplugged {
+      fn send(&mut self, _msg: &[u8]) -> Result<(), LumatoneMidiError> {
+        Err(LumatoneMidiError::DeviceSendError("unplugged".to_string()))
+      }
+
+      fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex> {
+        &mut self.0
+      }
+    }
+
+    let (_incoming_tx, incoming_rx) = mpsc::channel(1);
+    let (driver, driver_future) = MidiDriver::with_transport(
+      Box::new(Unplugged(incoming_rx)),
+      MidiDriverConfig::default(),
+    );
+    let handle = tokio::spawn(driver_future);
+    assert_eq!(driver.last_error(), None);
+
+    // the pending command fails instead of waiting forever
+    let err = driver.send(Command::Ping(1)).await.unwrap_err();
+    assert!(err.to_string().contains("unplugged"), "{err}");
+    handle.await.unwrap();
+
+    let last_error = driver.last_error().unwrap();
+    assert!(last_error.contains("unplugged"), "{last_error}");
+    assert!(driver.send(Command::Ping(2)).await.is_err());
+  }
+
+  // endregion
+
+  // region Builder tests
+
+  #[tokio::test]
+  async fn builder_creates_a_fully_customized_driver() {
+    use crate::midi::mock::MockDevice;
+
+    let (driver, driver_future) = MidiDriver::builder()
+      .max_busy_retries(2)
+      .max_timeout_retries(0)
+      .receive_timeout(Duration::from_secs(5))
+      .retry_timeout(Duration::from_millis(100))
+      .retry_jitter(Duration::from_millis(20))
+      .log_unsolicited(true)
+      .steal_lock(true)
+      .event_capacity(8)
+      .trace_capacity(4)
+      .build_with_transport(Box::new(MockDevice::acking()))
+      .unwrap();
+    let handle = tokio::spawn(driver_future);
+
+    let config = &driver.config;
+    assert_eq!(config.max_busy_retries, 2);
+    assert_eq!(config.max_timeout_retries, 0);
+    assert_eq!(config.receive_timeout, Duration::from_secs(5));
+    assert_eq!(config.retry_timeout, Duration::from_millis(100));
+    assert_eq!(config.retry_jitter, Some(Duration::from_millis(20)));
+    assert!(config.log_unsolicited);
+    assert!(config.steal_lock);
+    assert_eq!(config.event_capacity, 8);
+    assert_eq!(config.trace_capacity, 4);
+
+    let mut trace = driver.subscribe_trace();
+    driver.send(Command::Ping(7)).await.unwrap();
+    assert_eq!(
+      trace.recv().await.unwrap().command,
+      Command::Ping(7).to_string()
+    );
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+
+  #[test]
+  fn builder_rejects_settings_that_dont_work_together() {
+    let invalid = |builder: MidiDriverBuilder| match builder.config() {
+      Err(LumatoneMidiError::InvalidDriverConfig(err)) => err,
+      other => panic!("expected an invalid config, got {other:?}"),
+    };
+
+    let err = invalid(MidiDriver::builder().receive_timeout(Duration::ZERO));
+    assert_eq!(err, DriverConfigError::ZeroReceiveTimeout);
+    assert_eq!(err.to_string(), "receive_timeout must be longer than zero");
+    assert_eq!(
+      invalid(
+        MidiDriver::builder()
+          .retry_timeout(Duration::from_secs(1))
+          .retry_jitter(Duration::from_secs(2))
+      ),
+      DriverConfigError::JitterExceedsRetryTimeout {
+        retry_jitter: Duration::from_secs(2),
+        retry_timeout: Duration::from_secs(1),
+      }
+    );
+    let err = invalid(MidiDriver::builder().trace_capacity(0));
+    assert_eq!(err, DriverConfigError::ZeroCapacity("trace_capacity"));
+    assert_eq!(err.to_string(), "trace_capacity must be at least 1");
+
+    // the transport isn't touched if the settings are invalid
+    let result = MidiDriver::builder()
+      .receive_timeout(Duration::ZERO)
+      .build_with_transport(Box::new(crate::midi::mock::MockDevice::acking()));
+    assert!(matches!(
+      result,
+      Err(LumatoneMidiError::InvalidDriverConfig(
+        DriverConfigError::ZeroReceiveTimeout
+      ))
+    ));
+
+    // starting from an existing config keeps its settings
+    let config = MidiDriverConfig {
+      max_busy_retries: 4,
+      ..Default::default()
+    };
+    let config = MidiDriverBuilder::from_config(config)
+      .max_timeout_retries(3)
+      .config()
+      .unwrap();
+    assert_eq!(
+      (config.max_busy_retries, config.max_timeout_retries),
+      (4, 3)
+    );
+  }
+
+  // endregion
 }
@@ -1,6 +1,8 @@
-use super::constants::CommandId;
+use super::constants::{BoardIndex, CommandId, LumatoneKeyLocation};
+use super::driver::DriverConfigError;
 
 use std::fmt::Display;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum LumatoneMidiError {
@@ -11,6 +13,7 @@ pub enum LumatoneMidiError {
     actual: usize,
   },
   MessagePayloadTooShort {
+    command: CommandId,
     expected: usize,
     actual: usize,
   },
@@ -27,15 +30,66 @@ pub enum LumatoneMidiError {
   DeviceDetectionFailed(String),
   DeviceConnectionError(String),
   DeviceSendError(String),
+  DeviceBusy(String),
+  /// Another running process holds the device's [lock file](super::lock::DeviceLock).
+  DeviceLocked {
+    pid: u32,
+    lock_file: PathBuf,
+  },
+  ResponseTimedOut(String),
+  /// The device answered, but its firmware predates the feature needed to give a useful answer.
+  FirmwareTooOld(String),
+  /// A command sent to each board failed for some of them. Lists each board that failed,
+  /// along with its error.
+  BoardErrors(Vec<(BoardIndex, LumatoneMidiError)>),
+  /// Setting a key's function and color failed for one or both of them. Lists each part
+  /// that failed, along with its error.
+  SetKeyFailed {
+    location: LumatoneKeyLocation,
+    errors: Vec<(KeyPart, LumatoneMidiError)>,
+  },
+  /// One of a group of commands failed, e.g. while reading a board's keys.
+  CommandFailed {
+    command: CommandId,
+    error: Box<LumatoneMidiError>,
+  },
 
   ResponseDecodingError,
 
+  /// A line of newline-delimited JSON commands (see
+  /// [from_json_lines](super::commands::from_json_lines)) couldn't be read or parsed.
+  /// `line` is 1-based.
+  InvalidCommandJson {
+    line: usize,
+    message: String,
+  },
+
+  /// A [MidiDriverBuilder](super::driver::MidiDriverBuilder) had settings that don't work
+  /// together.
+  InvalidDriverConfig(DriverConfigError),
+
   InvalidBoardIndex(u8),
   InvalidMidiChannel(u8),
   InvalidLumatoneKeyIndex(u8),
   InvalidPresetIndex(u8),
 }
 
+/// Which part of a key a [LumatoneMidiError::SetKeyFailed] error is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPart {
+  Function,
+  Color,
+}
+
+impl Display for KeyPart {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      KeyPart::Function => write!(f, "function"),
+      KeyPart::Color => write!(f, "color"),
+    }
+  }
+}
+
 impl Display for LumatoneMidiError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     use LumatoneMidiError::*;
@@ -47,9 +101,13 @@ impl Display for LumatoneMidiError {
         "expected message to have length of at least {expected}, but received {actual}"
       ),
 
-      MessagePayloadTooShort { expected, actual } => write!(
+      MessagePayloadTooShort {
+        command,
+        expected,
+        actual,
+      } => write!(
         f,
-        "expected message payload to have length of {expected}, but received {actual}"
+        "expected {command:?} payload to have length of at least {expected}, but received {actual}"
       ),
 
       MessagePayloadInvalid(msg) => write!(f, "invalid message payload: {msg}"),
@@ -72,8 +130,56 @@ impl Display for LumatoneMidiError {
 
       DeviceSendError(msg) => write!(f, "failed to send message to device: {msg}"),
 
+      DeviceBusy(msg) => write!(f, "device is busy: {msg}"),
+
+      DeviceLocked { pid, lock_file } => write!(
+        f,
+        "device is in use by another process (pid {pid}). If that's wrong, remove {}",
+        lock_file.display()
+      ),
+
+      ResponseTimedOut(msg) => write!(f, "timed out waiting for response: {msg}"),
+
+      FirmwareTooOld(msg) => write!(f, "device firmware is too old: {msg}"),
+
+      BoardErrors(errors) => {
+        let errors: Vec<String> = errors
+          .iter()
+          .map(|(board, err)| format!("{board}: {err}"))
+          .collect();
+        write!(
+          f,
+          "failed on {} board(s): {}",
+          errors.len(),
+          errors.join("; ")
+        )
+      }
+
+      SetKeyFailed {
+        location: LumatoneKeyLocation(board, key),
+        errors,
+      } => {
+        let errors: Vec<String> = errors
+          .iter()
+          .map(|(part, err)| format!("{part}: {err}"))
+          .collect();
+        write!(
+          f,
+          "failed to set key {key} of {board}: {}",
+          errors.join("; ")
+        )
+      }
+
+      CommandFailed { command, error } => write!(f, "{command:?} failed: {error}"),
+
       ResponseDecodingError => write!(f, "failed to decode response from device"),
 
+      InvalidCommandJson { line, message } => {
+        write!(f, "invalid command on line {line}: {message}")
+      }
+
+      InvalidDriverConfig(err) => write!(f, "invalid driver config: {err}"),
+
       InvalidBoardIndex(n) => write!(f, "invalid board index: {n}"),
 
       UnsupportedCommandId(cmd_id, context) => {
@@ -0,0 +1,321 @@
+//! A bounded, in-memory log of recent driver activity, for showing in an app's UI and
+//! copying into bug reports.
+//!
+//! An [EventLog] is fed from [MidiDriver::subscribe_trace](super::driver::MidiDriver::subscribe_trace)
+//! and [MidiDriver::subscribe_events](super::driver::MidiDriver::subscribe_events), and keeps
+//! only the most recent entries, so it's safe to leave running for a whole session.
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Write};
+use std::time::{Duration, Instant};
+
+use super::driver::{CommandTrace, DriverEvent};
+use super::sysex::to_hex_debug_str;
+
+/// How important a [LogEntry] is. Ordered from least to most severe, so filtering
+/// can keep everything at or above a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+  Debug,
+  Info,
+  Warning,
+  Error,
+}
+
+impl Severity {
+  pub const ALL: [Severity; 4] = [
+    Severity::Debug,
+    Severity::Info,
+    Severity::Warning,
+    Severity::Error,
+  ];
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let s = match self {
+      Severity::Debug => "DEBUG",
+      Severity::Info => "INFO",
+      Severity::Warning => "WARN",
+      Severity::Error => "ERROR",
+    };
+    // pad, so that messages line up when entries are shown one per line
+    f.pad(s)
+  }
+}
+
+/// One line in an [EventLog].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+  /// When this happened, relative to when the log was created.
+  pub elapsed: Duration,
+  pub severity: Severity,
+  /// What happened, e.g. the command that was sent.
+  pub summary: String,
+  /// The response to a command, or the error it failed with.
+  pub detail: Option<String>,
+  /// How long a command took to resolve.
+  pub latency: Option<Duration>,
+}
+
+impl LogEntry {
+  /// Describes a resolved command. Failed commands are logged as errors.
+  pub fn for_trace(elapsed: Duration, trace: &CommandTrace) -> Self {
+    let (severity, detail) = match &trace.result {
+      Ok(response) => (Severity::Info, response.clone()),
+      Err(err) => (Severity::Error, err.clone()),
+    };
+    LogEntry {
+      elapsed,
+      severity,
+      summary: trace.command.clone(),
+      detail: Some(detail),
+      latency: trace.latency,
+    }
+  }
+
+  /// Describes a driver event, or returns `None` for events that aren't worth logging.
+  /// Key sampling readings arrive many times a second, so they're left out.
+  pub fn for_event(elapsed: Duration, event: &DriverEvent) -> Option<Self> {
+    let (severity, summary) = match event {
+      DriverEvent::QueueDrained => (Severity::Debug, "queue drained"),
+      DriverEvent::DeviceBusy => (Severity::Warning, "device busy"),
+      DriverEvent::CalibrationStatus(status) => {
+        return Some(LogEntry {
+          elapsed,
+          severity: Severity::Info,
+          summary: format!("{} calibration status from {}", status.kind, status.board),
+          detail: Some(to_hex_debug_str(&status.payload)),
+          latency: None,
+        })
+      }
+      DriverEvent::KeySample(_) => return None,
+    };
+    Some(LogEntry {
+      elapsed,
+      severity,
+      summary: summary.to_string(),
+      detail: None,
+      latency: None,
+    })
+  }
+}
+
+impl Display for LogEntry {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "+{:.3}s {:<5} {}",
+      self.elapsed.as_secs_f64(),
+      self.severity,
+      self.summary
+    )?;
+    if let Some(detail) = &self.detail {
+      write!(f, ": {detail}")?;
+    }
+    if let Some(latency) = self.latency {
+      write!(f, " ({} ms)", latency.as_millis())?;
+    }
+    Ok(())
+  }
+}
+
+/// Keeps the most recent `capacity` [LogEntry]s, dropping the oldest as new ones arrive.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+  capacity: usize,
+  entries: VecDeque<LogEntry>,
+  dropped: usize,
+  started: Instant,
+}
+
+impl EventLog {
+  /// Creates an empty log that holds up to `capacity` entries (at least one).
+  pub fn new(capacity: usize) -> Self {
+    let capacity = capacity.max(1);
+    EventLog {
+      capacity,
+      entries: VecDeque::with_capacity(capacity),
+      dropped: 0,
+      started: Instant::now(),
+    }
+  }
+
+  /// Adds `entry`, dropping the oldest entry if the log is full.
+  pub fn push(&mut self, entry: LogEntry) {
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+      self.dropped += 1;
+    }
+    self.entries.push_back(entry);
+  }
+
+  /// Logs a resolved command, timestamped now.
+  pub fn record_trace(&mut self, trace: &CommandTrace) {
+    self.push(LogEntry::for_trace(self.started.elapsed(), trace));
+  }
+
+  /// Logs a driver event, timestamped now, unless it's one that [LogEntry::for_event] skips.
+  pub fn record_event(&mut self, event: &DriverEvent) {
+    if let Some(entry) = LogEntry::for_event(self.started.elapsed(), event) {
+      self.push(entry);
+    }
+  }
+
+  /// Returns the entries with at least `min_severity`, oldest first.
+  pub fn entries(&self, min_severity: Severity) -> impl Iterator<Item = &LogEntry> + '_ {
+    self
+      .entries
+      .iter()
+      .filter(move |e| e.severity >= min_severity)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// How many entries have been pushed out of the log to make room for newer ones.
+  pub fn dropped(&self) -> usize {
+    self.dropped
+  }
+
+  /// Removes all entries. The timestamps of later entries are still relative to when the
+  /// log was created.
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.dropped = 0;
+  }
+
+  /// Renders the entries with at least `min_severity` as text, one per line, for pasting
+  /// into a bug report.
+  pub fn to_text(&self, min_severity: Severity) -> String {
+    let mut text = String::new();
+    if self.dropped > 0 {
+      // writing to a String can't fail
+      let _ = writeln!(text, "({} earlier entries not shown)", self.dropped);
+    }
+    for entry in self.entries(min_severity) {
+      let _ = writeln!(text, "{entry}");
+    }
+    text
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{EventLog, LogEntry, Severity};
+  use crate::midi::constants::BoardIndex;
+  use crate::midi::driver::{CommandTrace, DriverEvent};
+  use crate::midi::sampling::KeySample;
+
+  fn entry(secs: u64, severity: Severity, summary: &str) -> LogEntry {
+    LogEntry {
+      elapsed: Duration::from_secs(secs),
+      severity,
+      summary: summary.to_string(),
+      detail: None,
+      latency: None,
+    }
+  }
+
+  #[test]
+  fn test_oldest_entries_are_dropped() {
+    let mut log = EventLog::new(3);
+    for i in 0..5 {
+      log.push(entry(i, Severity::Info, &format!("entry {i}")));
+    }
+    assert_eq!(log.len(), 3);
+    assert_eq!(log.dropped(), 2);
+    let summaries: Vec<&str> = log
+      .entries(Severity::Debug)
+      .map(|e| e.summary.as_str())
+      .collect();
+    assert_eq!(summaries, vec!["entry 2", "entry 3", "entry 4"]);
+
+    log.clear();
+    assert!(log.is_empty());
+    assert_eq!(log.dropped(), 0);
+    assert_eq!(EventLog::new(0).capacity(), 1);
+  }
+
+  #[test]
+  fn test_filter_by_severity() {
+    let mut log = EventLog::new(10);
+    log.push(entry(0, Severity::Debug, "debug"));
+    log.push(entry(1, Severity::Info, "info"));
+    log.push(entry(2, Severity::Warning, "warning"));
+    log.push(entry(3, Severity::Error, "error"));
+
+    assert_eq!(log.entries(Severity::Debug).count(), 4);
+    let serious: Vec<&str> = log
+      .entries(Severity::Warning)
+      .map(|e| e.summary.as_str())
+      .collect();
+    assert_eq!(serious, vec!["warning", "error"]);
+    assert_eq!(log.entries(Severity::Error).count(), 1);
+  }
+
+  #[test]
+  fn test_entries_from_driver_activity() {
+    let ok = LogEntry::for_trace(
+      Duration::from_millis(1500),
+      &CommandTrace {
+        command: "Ping(1)".to_string(),
+        result: Ok("Pong(1)".to_string()),
+        latency: Some(Duration::from_millis(12)),
+      },
+    );
+    assert_eq!(ok.severity, Severity::Info);
+    assert_eq!(ok.to_string(), "+1.500s INFO  Ping(1): Pong(1) (12 ms)");
+
+    let failed = LogEntry::for_trace(
+      Duration::ZERO,
+      &CommandTrace {
+        command: "Ping(2)".to_string(),
+        result: Err("nope".to_string()),
+        latency: None,
+      },
+    );
+    assert_eq!(failed.severity, Severity::Error);
+    assert_eq!(failed.to_string(), "+0.000s ERROR Ping(2): nope");
+
+    let busy = LogEntry::for_event(Duration::ZERO, &DriverEvent::DeviceBusy).unwrap();
+    assert_eq!(busy.severity, Severity::Warning);
+    let sample = DriverEvent::KeySample(KeySample {
+      board: BoardIndex::Octave1,
+      values: vec![],
+    });
+    assert!(LogEntry::for_event(Duration::ZERO, &sample).is_none());
+  }
+
+  #[test]
+  fn test_to_text() {
+    let mut log = EventLog::new(2);
+    log.push(entry(0, Severity::Debug, "queue drained"));
+    log.push(entry(1, Severity::Warning, "device busy"));
+    assert_eq!(
+      log.to_text(Severity::Debug),
+      "+0.000s DEBUG queue drained\n+1.000s WARN  device busy\n"
+    );
+    assert_eq!(
+      log.to_text(Severity::Warning),
+      "+1.000s WARN  device busy\n"
+    );
+
+    log.push(entry(2, Severity::Error, "oops"));
+    assert_eq!(
+      log.to_text(Severity::Error),
+      "(1 earlier entries not shown)\n+2.000s ERROR oops\n"
+    );
+  }
+}
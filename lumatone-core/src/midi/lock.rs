@@ -0,0 +1,299 @@
+//! Advisory lock files, so that two lumatone-rs processes (say, the GUI and the CLI) don't
+//! both try to talk to the same device at once.
+//!
+//! The lock for a device is a small file in the [runtime_dir], naming the process that
+//! holds it and the device's ports. Nothing stops a process from ignoring it, but
+//! [MidiDriver](super::driver::MidiDriver) checks for it before connecting.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use ini::Ini;
+use log::{debug, warn};
+
+use super::{device::LumatoneDevice, error::LumatoneMidiError};
+use crate::settings::runtime_dir;
+
+/// The process holding a device lock, and the ports it's using.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+  pub pid: u32,
+  pub output_port: String,
+  pub input_port: String,
+}
+
+impl LockOwner {
+  /// The current process, using `device`.
+  pub fn current(device: &LumatoneDevice) -> Self {
+    LockOwner {
+      pid: std::process::id(),
+      output_port: device.output_port_name().to_string(),
+      input_port: device.input_port_name().to_string(),
+    }
+  }
+
+  fn parse(contents: &str) -> Option<Self> {
+    let conf = Ini::load_from_str(contents).ok()?;
+    let section = conf.section(Some("lock"))?;
+    Some(LockOwner {
+      pid: section.get("pid")?.parse().ok()?,
+      output_port: section.get("output")?.to_string(),
+      input_port: section.get("input")?.to_string(),
+    })
+  }
+
+  fn to_ini_string(&self) -> String {
+    let mut conf = Ini::new();
+    conf
+      .with_section(Some("lock"))
+      .set("pid", self.pid.to_string())
+      .set("output", &self.output_port)
+      .set("input", &self.input_port);
+    let mut buf = Vec::new();
+    // writing to a Vec can't fail
+    let _ = conf.write_to(&mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+  }
+}
+
+/// A held device lock. The lock file is removed when this is dropped.
+#[derive(Debug)]
+pub struct DeviceLock {
+  path: PathBuf,
+  owner: LockOwner,
+}
+
+impl DeviceLock {
+  /// Takes the lock for `device` on behalf of the current process.
+  ///
+  /// Fails with [LumatoneMidiError::DeviceLocked] if another running process holds it,
+  /// unless `steal` is set. Locks left behind by processes that have exited are taken
+  /// over quietly. Returns `Ok(None)` if the lock file can't be written at all, since
+  /// that's no reason to keep anyone from using the device.
+  pub fn acquire(
+    device: &LumatoneDevice,
+    steal: bool,
+  ) -> Result<Option<DeviceLock>, LumatoneMidiError> {
+    let path = lock_path(&runtime_dir(), device);
+    match acquire_at(&path, LockOwner::current(device), steal, process_is_alive) {
+      Ok(lock) => Ok(Some(lock)),
+      Err(AcquireError::Held(owner)) => Err(LumatoneMidiError::DeviceLocked {
+        pid: owner.pid,
+        lock_file: path,
+      }),
+      Err(AcquireError::Io(err)) => {
+        warn!("unable to write device lock file {}: {err}", path.display());
+        Ok(None)
+      }
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn owner(&self) -> &LockOwner {
+    &self.owner
+  }
+}
+
+impl Drop for DeviceLock {
+  fn drop(&mut self) {
+    // if someone stole the lock, it's theirs to clean up now
+    let current = fs::read_to_string(&self.path)
+      .ok()
+      .and_then(|contents| LockOwner::parse(&contents));
+    if current.is_some_and(|owner| owner.pid == self.owner.pid) {
+      if let Err(err) = fs::remove_file(&self.path) {
+        warn!(
+          "unable to remove device lock file {}: {err}",
+          self.path.display()
+        );
+      }
+    }
+  }
+}
+
+#[derive(Debug)]
+enum AcquireError {
+  Held(LockOwner),
+  Io(std::io::Error),
+}
+
+/// Returns where the lock file for `device` lives within `dir`. Each output port gets its
+/// own lock, so separate devices don't get in each other's way.
+fn lock_path(dir: &Path, device: &LumatoneDevice) -> PathBuf {
+  let name: String = device
+    .output_port_name()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect();
+  dir.join(format!("device-{name}.lock"))
+}
+
+/// Takes the lock at `path` for `owner`, using `is_alive` to tell whether an existing
+/// lock's process is still running.
+fn acquire_at(
+  path: &Path,
+  owner: LockOwner,
+  steal: bool,
+  is_alive: impl Fn(u32) -> bool,
+) -> Result<DeviceLock, AcquireError> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir).map_err(AcquireError::Io)?;
+  }
+
+  // one retry, in case we remove a stale lock and someone else creates one in between
+  for _ in 0..2 {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+      Ok(mut file) => {
+        file
+          .write_all(owner.to_ini_string().as_bytes())
+          .map_err(AcquireError::Io)?;
+        return Ok(DeviceLock {
+          path: path.to_path_buf(),
+          owner,
+        });
+      }
+      Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+      Err(err) => return Err(AcquireError::Io(err)),
+    }
+
+    // unreadable or garbled lock files are treated as stale
+    let existing = fs::read_to_string(path)
+      .ok()
+      .and_then(|contents| LockOwner::parse(&contents));
+    match existing {
+      Some(existing) if existing.pid != owner.pid && is_alive(existing.pid) => {
+        if !steal {
+          return Err(AcquireError::Held(existing));
+        }
+        warn!("taking device lock from running process {}", existing.pid);
+      }
+      Some(existing) => debug!("replacing stale device lock from process {}", existing.pid),
+      None => debug!("replacing unreadable device lock {}", path.display()),
+    }
+    match fs::remove_file(path) {
+      Ok(()) => {}
+      Err(err) if err.kind() == ErrorKind::NotFound => {}
+      Err(err) => return Err(AcquireError::Io(err)),
+    }
+  }
+  Err(AcquireError::Io(std::io::Error::new(
+    ErrorKind::AlreadyExists,
+    "lock file keeps reappearing",
+  )))
+}
+
+/// Returns false if there's definitely no running process with the given id.
+#[cfg(target_os = "linux")]
+fn process_is_alive(pid: u32) -> bool {
+  Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Returns false if there's definitely no running process with the given id.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_is_alive(pid: u32) -> bool {
+  use std::process::{Command, Stdio};
+  Command::new("kill")
+    .args(["-0", &pid.to_string()])
+    .stderr(Stdio::null())
+    .status()
+    .map_or(true, |status| status.success())
+}
+
+/// Returns false if there's definitely no running process with the given id.
+#[cfg(windows)]
+fn process_is_alive(pid: u32) -> bool {
+  use std::process::Command;
+  Command::new("tasklist")
+    .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+    .output()
+    .map_or(true, |out| {
+      String::from_utf8_lossy(&out.stdout).contains(&pid.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::path::PathBuf;
+
+  use super::{acquire_at, lock_path, process_is_alive, AcquireError, LockOwner};
+  use crate::midi::device::LumatoneDevice;
+
+  fn temp_lock_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumatone-lock-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    lock_path(&dir, &LumatoneDevice::new("Lumatone out", "Lumatone in"))
+  }
+
+  fn owner(pid: u32) -> LockOwner {
+    LockOwner {
+      pid,
+      output_port: "Lumatone out".to_string(),
+      input_port: "Lumatone in".to_string(),
+    }
+  }
+
+  fn read_owner(path: &PathBuf) -> Option<LockOwner> {
+    LockOwner::parse(&fs::read_to_string(path).ok()?)
+  }
+
+  #[test]
+  fn test_lock_is_released_on_drop() {
+    let path = temp_lock_path("release");
+    assert!(path.ends_with("device-Lumatone_out.lock"));
+
+    let lock = acquire_at(&path, owner(1), false, |_| true).unwrap();
+    assert_eq!(read_owner(&path), Some(owner(1)));
+    drop(lock);
+    assert!(!path.exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_live_lock_is_refused_unless_stolen() {
+    let path = temp_lock_path("live");
+    let held = acquire_at(&path, owner(1), false, |_| true).unwrap();
+
+    match acquire_at(&path, owner(2), false, |_| true) {
+      Err(AcquireError::Held(existing)) => assert_eq!(existing, owner(1)),
+      other => panic!("expected the lock to be held, got {other:?}"),
+    }
+    assert_eq!(read_owner(&path), Some(owner(1)));
+
+    let stolen = acquire_at(&path, owner(2), true, |_| true).unwrap();
+    assert_eq!(read_owner(&path), Some(owner(2)));
+
+    // the original holder doesn't clean up a lock that's no longer theirs
+    drop(held);
+    assert_eq!(read_owner(&path), Some(owner(2)));
+    drop(stolen);
+    assert!(!path.exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_stale_and_garbled_locks_are_replaced() {
+    let path = temp_lock_path("stale");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+    fs::write(&path, owner(1).to_ini_string()).unwrap();
+    let lock = acquire_at(&path, owner(2), false, |pid| pid != 1).unwrap();
+    assert_eq!(read_owner(&path), Some(owner(2)));
+    drop(lock);
+
+    fs::write(&path, "not a lock file").unwrap();
+    let lock = acquire_at(&path, owner(2), false, |_| true).unwrap();
+    assert_eq!(read_owner(&path), Some(owner(2)));
+    drop(lock);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_current_process_is_alive() {
+    assert!(process_is_alive(std::process::id()));
+  }
+}
@@ -0,0 +1,74 @@
+//! A simulated Lumatone for exercising the driver without hardware.
+
+use tokio::sync::mpsc;
+
+use super::{
+  constants::ResponseStatusCode,
+  device::MidiTransport,
+  error::LumatoneMidiError,
+  sysex::{EncodedSysex, MSG_STATUS},
+};
+
+/// Produces the device's reply to an outgoing message, or `None` to stay silent.
+pub(crate) type Responder = Box<dyn FnMut(&[u8]) -> Option<EncodedSysex> + Send>;
+
+/// A [MidiTransport] that answers each message it's sent using a [Responder].
+pub(crate) struct MockDevice {
+  responder: Responder,
+  incoming_tx: mpsc::Sender<EncodedSysex>,
+  incoming_rx: mpsc::Receiver<EncodedSysex>,
+  /// Every message sent to the device, in order.
+  pub sent: Vec<EncodedSysex>,
+}
+
+impl MockDevice {
+  pub fn new(responder: Responder) -> Self {
+    let (incoming_tx, incoming_rx) = mpsc::channel(32);
+    MockDevice {
+      responder,
+      incoming_tx,
+      incoming_rx,
+      sent: vec![],
+    }
+  }
+
+  /// Returns a sender for delivering messages as if the device had sent them unprompted.
+  pub fn incoming_sender(&self) -> mpsc::Sender<EncodedSysex> {
+    self.incoming_tx.clone()
+  }
+
+  /// A device that acknowledges every message, echoing its payload.
+  pub fn acking() -> Self {
+    MockDevice::new(Box::new(|msg| {
+      Some(reply_with_status(msg, ResponseStatusCode::Ack))
+    }))
+  }
+}
+
+impl MidiTransport for MockDevice {
+  fn send(&mut self, msg: &[u8]) -> Result<(), LumatoneMidiError> {
+    self.sent.push(msg.to_vec());
+    if let Some(reply) = (self.responder)(msg) {
+      self
+        .incoming_tx
+        .try_send(reply)
+        .map_err(|e| LumatoneMidiError::DeviceSendError(format!("mock reply error: {e}")))?;
+    }
+    Ok(())
+  }
+
+  fn incoming_messages(&mut self) -> &mut mpsc::Receiver<EncodedSysex> {
+    &mut self.incoming_rx
+  }
+}
+
+/// Builds a reply to `outgoing` the way the device does: the same header, followed by
+/// a status byte and the outgoing message's data.
+pub(crate) fn reply_with_status(outgoing: &[u8], status: ResponseStatusCode) -> EncodedSysex {
+  // `outgoing` still has its SYSEX_START marker, so the status byte goes one past MSG_STATUS.
+  let status_index = MSG_STATUS + 1;
+  let mut reply = outgoing[..status_index].to_vec();
+  reply.push(status as u8);
+  reply.extend(&outgoing[status_index..]);
+  reply
+}
@@ -1,10 +1,18 @@
+pub mod backoff;
+pub mod calibration;
+pub mod client;
 pub mod commands;
 pub mod constants;
 pub mod detect;
 pub mod device;
+pub mod doctor;
 pub mod driver;
 pub mod error;
+pub mod event_log;
+pub mod lock;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod responses;
+pub mod sampling;
+pub mod script;
 pub mod sysex;
-
-// TODO: public API entrypoints go here
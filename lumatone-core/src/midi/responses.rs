@@ -2,19 +2,42 @@
 use std::fmt::Display;
 
 use super::{
-  constants::{BoardIndex, CommandId, MidiChannel, TEST_ECHO},
+  calibration::{CalibrationKind, CalibrationStatus},
+  commands::Command,
+  constants::{
+    BoardIndex, CommandId, MidiChannel, PeripheralCalibrationMode, ResponseStatusCode, TEST_ECHO,
+  },
   error::LumatoneMidiError,
+  sampling::KeySample,
   sysex::{
-    is_lumatone_message, message_command_id, message_payload, strip_sysex_markers, SysexTable,
-    VelocityIntervalTable, BOARD_IND,
+    calibration_mode_byte, has_echo_flag, is_lumatone_message, message_answer_code,
+    message_command_id, message_payload, strip_sysex_markers, to_hex_debug_str,
+    validate_incoming_message, SysexTable, VelocityIntervalTable, BOARD_IND, CMD_ID,
   },
 };
-
-#[derive(Debug)]
+use num_traits::FromPrimitive;
+use serde::{Serialize, Serializer};
+
+/// A decoded message from the device. Cloning is cheap enough to hand the same response to
+/// several listeners; the largest variants are 128-value tables.
+///
+/// Responses serialize with serde's default enum representation, e.g.
+/// `{"FirmwareRevision":{"major":1,"minor":10,"revision":0}}`, which is handy for comparing
+/// decoded captures against known-good output.
+#[derive(Debug, Clone, Serialize)]
 pub enum Response {
   /// indicates that the command was successful, but no additional data was returned.
   Ack(CommandId),
 
+  /// Indicates that a setter command was successful, and includes the payload echoed back
+  /// by the device. Only produced for commands whose payload the firmware echoes;
+  /// see [echoes_payload]. Use [Response::confirms] to check the echo against the sent command.
+  SetConfirmation {
+    command: CommandId,
+    board: BoardIndex,
+    payload: Vec<u8>,
+  },
+
   Pong(u32),
 
   /// 8-bit key data for red LED intensity. 112 bytes, lower and upper nibbles for 56 values
@@ -63,7 +86,9 @@ pub enum Response {
   LumatouchConfig(Box<SysexTable>),
 
   /// 12-bit velocity interval configuration of keyboard; 127 values
-  VelocityIntervalConfig(Box<VelocityIntervalTable>),
+  VelocityIntervalConfig(
+    #[serde(serialize_with = "serialize_intervals")] Box<VelocityIntervalTable>,
+  ),
 
   /// Serial ID of keyboard (6 bytes).
   SerialId([u8; 6]),
@@ -124,11 +149,72 @@ pub enum Response {
 
   /// 12-bit expression pedal adc threshold, a 12-bit value
   ExpressionPedalThreshold(u16),
+
+  /// Expression pedal sensitivity, as set with [Command::SetExpressionPedalSensitivity]
+  ExpressionPedalSensitivity(u8),
+
+  /// 12-bit sensor readings for each key on a board, sent unprompted while key sampling is
+  /// enabled. See [crate::midi::sampling] for the assumed format.
+  KeySample(KeySample),
+
+  /// A status message from a board during key or aftertouch calibration, sent unprompted.
+  /// See [crate::midi::calibration] for what's assumed about these.
+  CalibrationStatus(CalibrationStatus),
+}
+
+#[allow(clippy::borrowed_box)]
+fn serialize_intervals<S: Serializer>(
+  table: &Box<VelocityIntervalTable>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  serializer.collect_seq(table.iter())
+}
+
+/// The threshold values of one board, as read back with
+/// [Command::GetBoardThresholdValues].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+  pub min_high: u8,
+  pub min_low: u8,
+  pub max: u8,
+  pub aftertouch: u8,
+  pub cc: u8,
+}
+
+/// The device's firmware version, shown as `major.minor.revision`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVersion {
+  pub major: u8,
+  pub minor: u8,
+  pub revision: u8,
+}
+
+impl Display for FirmwareVersion {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.revision)
+  }
+}
+
+/// The MIDI channels the device's peripherals send on. See
+/// [MidiDriver::get_peripheral_channels](super::driver::MidiDriver::get_peripheral_channels).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeripheralChannels {
+  pub pitch_wheel: MidiChannel,
+  pub mod_wheel: MidiChannel,
+  pub expression: MidiChannel,
+  pub sustain: MidiChannel,
+}
+
+/// The error for a command that got a valid response, but not the kind it should have.
+/// `expected` describes the response that was wanted, e.g. "a serial id".
+pub(crate) fn unexpected_response(expected: &str, actual: &Response) -> LumatoneMidiError {
+  LumatoneMidiError::InvalidResponseMessage(format!("expected {expected}, got {actual}"))
 }
 
 impl Response {
   pub fn from_sysex_message(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
     use CommandId::*;
+    validate_incoming_message(msg)?;
     let cmd_id = message_command_id(msg)?;
     match cmd_id {
       LumaPing => decode_ping(msg).map(|val| Response::Pong(val)),
@@ -181,9 +267,15 @@ impl Response {
 
       GetPeripheralChannels => unpack_peripheral_channels(msg),
 
-      CalibrateExpressionPedal => unpack_expression_calibration_status(msg),
+      CalibrateExpressionPedal => {
+        unpack_calibration_mode_ack_or(msg, unpack_expression_calibration_status)
+      }
+
+      CalibratePitchModWheel => {
+        unpack_calibration_mode_ack_or(msg, unpack_wheel_calibration_status)
+      }
 
-      CalibratePitchModWheel => unpack_wheel_calibration_status(msg),
+      PeripheralCalbrationData => unpack_peripheral_calibration_data(msg),
 
       GetAftertouchTriggerDelay => unpack_aftertouch_trigger_delay(msg),
 
@@ -191,9 +283,161 @@ impl Response {
 
       GetExpressionPedalThreshold => unpack_expression_threshold(msg),
 
+      GetExpressionPedalSensitivity => unpack_expression_sensitivity(msg),
+
+      SetKeySampling => unpack_key_sampling(msg),
+
+      CalibrateKeys | CalibrateAftertouch => unpack_calibration_status(msg),
+
+      ref cmd if echoes_payload(cmd) => unpack_set_confirmation(msg),
+
       _ => Ok(Response::Ack(cmd_id)),
     }
   }
+
+  /// For a [Response::SetConfirmation], checks whether the echoed payload matches the data
+  /// that was sent with `command`. Returns `None` for other responses, or if the response
+  /// is for a different command.
+  ///
+  /// Also returns `None` for commands whose echo hasn't been checked against a capture from
+  /// a device yet, since firmware that acks with a zero-padded payload instead of an echo
+  /// would make every one of them look like a mismatch.
+  pub fn confirms(&self, command: &Command) -> Option<bool> {
+    if !CAPTURED_ECHOES.contains(&command.command_id()) {
+      return None;
+    }
+    self.matches_echo(command)
+  }
+
+  /// Compares the payload of a [Response::SetConfirmation] with the data sent with
+  /// `command`, as for [Response::confirms], whether or not its echo has been captured.
+  fn matches_echo(&self, command: &Command) -> Option<bool> {
+    match self {
+      Response::SetConfirmation {
+        command: cmd_id,
+        board,
+        payload,
+      } => {
+        let sent = command.to_sysex_message();
+        let sent = strip_sysex_markers(&sent);
+        if *cmd_id != command.command_id() || sent.len() <= CMD_ID {
+          return None;
+        }
+        if sent[BOARD_IND] != Into::<u8>::into(*board) {
+          return Some(false);
+        }
+        // Outgoing messages have no status byte, so their data starts right after the command id.
+        // Both sides may be zero-padded to the minimum message length, so ignore trailing zeros.
+        let sent_data = trim_trailing_zeros(&sent[CMD_ID + 1..]);
+        let echoed = trim_trailing_zeros(payload);
+        Some(sent_data == echoed)
+      }
+      _ => None,
+    }
+  }
+
+  /// For a [Response::BoardThresholds], returns the board and its [Thresholds].
+  /// Returns `None` for other responses.
+  pub fn thresholds(&self) -> Option<(BoardIndex, Thresholds)> {
+    match *self {
+      Response::BoardThresholds {
+        board_index,
+        min_high,
+        min_low,
+        max,
+        aftertouch,
+        cc,
+      } => Some((
+        board_index,
+        Thresholds {
+          min_high,
+          min_low,
+          max,
+          aftertouch,
+          cc,
+        },
+      )),
+      _ => None,
+    }
+  }
+}
+
+/// Returns true if `msg` is a frame of key sensor readings, which the device streams
+/// while key sampling is enabled rather than in response to any one command.
+pub fn is_key_sample_message(msg: &[u8]) -> bool {
+  matches!(message_command_id(msg), Ok(CommandId::SetKeySampling))
+    && message_answer_code(msg) == ResponseStatusCode::Ack
+    && message_payload(msg).is_ok_and(|p| p.len() >= KEY_SAMPLE_MIN_PAYLOAD_LEN)
+}
+
+/// Returns true if `msg` is a status message sent by an octave board during key or aftertouch
+/// calibration, rather than the Server board's acknowledgement of the command that started it.
+pub fn is_calibration_status_message(msg: &[u8]) -> bool {
+  let msg = strip_sysex_markers(msg);
+  let is_calibration =
+    message_command_id(msg).is_ok_and(|cmd| CalibrationKind::from_command_id(&cmd).is_some());
+  is_calibration && message_board_index(msg).is_ok_and(|board| board != BoardIndex::Server)
+}
+
+/// Returns true if `msg` is one of the status reports the device sends on its own while a
+/// pedal or wheel is being calibrated, roughly every 100ms.
+pub fn is_peripheral_calibration_status_message(msg: &[u8]) -> bool {
+  let msg = strip_sysex_markers(msg);
+  message_command_id(msg).is_ok_and(|cmd| {
+    matches!(
+      cmd,
+      CommandId::CalibrateExpressionPedal
+        | CommandId::CalibratePitchModWheel
+        | CommandId::PeripheralCalbrationData
+    )
+  })
+}
+
+/// Returns true if the device keeps sending messages with `cmd`'s id after acknowledging
+/// it: the pedal and wheel calibration commands, whose status reports reuse their ids.
+/// Only the acknowledgement answers the command; see [is_peripheral_calibration_report].
+pub fn expects_streamed_responses(cmd: &CommandId) -> bool {
+  matches!(
+    cmd,
+    CommandId::CalibrateExpressionPedal | CommandId::CalibratePitchModWheel
+  )
+}
+
+/// Returns true if `msg` is a pedal or wheel calibration status report, as opposed to the
+/// acknowledgement of the command that turned calibration on or off. Unlike
+/// [is_peripheral_calibration_status_message], this never matches a response to a command.
+pub fn is_peripheral_calibration_report(msg: &[u8]) -> bool {
+  match message_command_id(msg) {
+    Ok(CommandId::PeripheralCalbrationData) => true,
+    Ok(cmd) if expects_streamed_responses(&cmd) => {
+      message_payload(msg).is_ok_and(|p| p.len() >= PERIPHERAL_CALIBRATION_STATUS_LEN)
+    }
+    _ => false,
+  }
+}
+
+/// The commands from [echoes_payload] whose acknowledgements have been checked against
+/// captures from a device. Only these are checked by [Response::confirms]. Add a command
+/// here along with a test that decodes its capture.
+const CAPTURED_ECHOES: &[CommandId] = &[];
+
+/// Returns true if the firmware echoes the payload of `cmd` in its acknowledgement.
+pub fn echoes_payload(cmd: &CommandId) -> bool {
+  use CommandId::*;
+  matches!(
+    cmd,
+    ChangeKeyNote
+      | SetKeyColour
+      | SetAftertouchFlag
+      | SetLightOnKeystrokes
+      | InvertFootController
+      | InvertSustainPedal
+  )
+}
+
+fn trim_trailing_zeros(data: &[u8]) -> &[u8] {
+  let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+  &data[..end]
 }
 
 impl Display for Response {
@@ -201,6 +445,7 @@ impl Display for Response {
     use Response::*;
     match self {
       Ack(cmd_id) => write!(f, "Ack({cmd_id:?})"),
+      SetConfirmation { command, board, .. } => write!(f, "SetConfirmation({command:?}, {board})"),
       Pong(val) => write!(f, "Pong({val})"),
       RedLEDConfig(board, _) => write!(f, "RedLEDConfig({board}, <table...>)"),
       GreenLEDConfig(board, _) => write!(f, "GreenLEDConfig({board}, <table..>)"),
@@ -231,33 +476,60 @@ impl Display for Response {
         max,
         aftertouch,
         cc,
-      } => todo!(),
+      } => write!(
+        f,
+        "BoardThresholds({board_index}, min_high: {min_high}, min_low: {min_low}, max: {max}, \
+         aftertouch: {aftertouch}, cc: {cc})"
+      ),
       BoardSensitivity {
         board_index,
         cc,
         aftertouch,
-      } => todo!(),
+      } => write!(
+        f,
+        "BoardSensitivity({board_index}, cc: {cc}, aftertouch: {aftertouch})"
+      ),
       PeripheralChannels {
         pitch_wheel,
         mod_wheel,
         expression,
         sustain,
-      } => todo!(),
+      } => write!(
+        f,
+        "PeripheralChannels(pitch_wheel: {pitch_wheel}, mod_wheel: {mod_wheel}, \
+         expression: {expression}, sustain: {sustain})"
+      ),
       ExpressionCalibrationStatus {
         min_bound,
         max_bound,
         valid,
-      } => todo!(),
+      } => write!(
+        f,
+        "ExpressionCalibrationStatus(min: {min_bound}, max: {max_bound}, valid: {valid})"
+      ),
       WheelCalibrationStatus {
         center_pitch,
         min_pitch,
         max_pitch,
         min_mod,
         max_mod,
-      } => todo!(),
+      } => write!(
+        f,
+        "WheelCalibrationStatus(pitch: {min_pitch} / {center_pitch} / {max_pitch}, \
+         mod: {min_mod} / {max_mod})"
+      ),
       AftertouchTriggerDelay(board, val) => write!(f, "AftertouchTriggerDelay({board}, {val})"),
       LumatouchNoteOffDelay(board, val) => write!(f, "LumatouchNoteOffDelay({board}, {val})"),
       ExpressionPedalThreshold(val) => write!(f, "ExpressionPedalThreshold({val})"),
+      ExpressionPedalSensitivity(val) => write!(f, "ExpressionPedalSensitivity({val})"),
+      KeySample(sample) => write!(f, "KeySample({}, <table..>)", sample.board),
+      CalibrationStatus(status) => write!(
+        f,
+        "CalibrationStatus({}, {}, {})",
+        status.kind,
+        status.board,
+        to_hex_debug_str(&status.payload)
+      ),
     }
   }
 }
@@ -290,9 +562,16 @@ pub fn decode_ping(msg: &[u8]) -> Result<u32, LumatoneMidiError> {
     });
   }
 
+  if has_echo_flag(msg) {
+    return Err(LumatoneMidiError::InvalidResponseMessage(
+      "ping came back unanswered, so this port doesn't lead to a device".to_string(),
+    ));
+  }
+
   let payload = message_payload(msg)?;
   if payload.len() < 4 {
     return Err(LumatoneMidiError::MessagePayloadTooShort {
+      command: cmd_id,
       expected: 4,
       actual: payload.len(),
     });
@@ -326,6 +605,7 @@ fn payload_with_len<'a>(msg: &'a [u8], len: usize) -> Result<&'a [u8], LumatoneM
   let payload = message_payload(msg)?;
   if payload.len() < len {
     Err(LumatoneMidiError::MessagePayloadTooShort {
+      command: message_command_id(msg)?,
       expected: len,
       actual: payload.len(),
     })
@@ -334,33 +614,81 @@ fn payload_with_len<'a>(msg: &'a [u8], len: usize) -> Result<&'a [u8], LumatoneM
   }
 }
 
+fn unpack_set_confirmation(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
+  let msg = valid_lumatone_msg(msg)?;
+  let command = message_command_id(msg)?;
+  let board = message_board_index(msg)?;
+  // Acks without any payload bytes are still acks, there's just nothing to compare.
+  let payload = match message_payload(msg) {
+    Ok(payload) => payload.to_vec(),
+    Err(_) => return Ok(Response::Ack(command)),
+  };
+  Ok(Response::SetConfirmation {
+    command,
+    board,
+    payload,
+  })
+}
+
 fn unpack_sysex_config_table(msg: &[u8]) -> Result<Box<SysexTable>, LumatoneMidiError> {
   let payload = payload_with_len(msg, 128)?;
-  let table: SysexTable = payload.try_into().unwrap();
+  let table = SysexTable::try_from(payload)?;
   Ok(Box::new(table))
 }
 
+/// The number of keys on each board. Older firmware leaves out the last key when
+/// sending per-key data, so one fewer entry is also accepted.
+const OCTAVE_SIZE: usize = 56;
+
+/// Checks that `data`, unpacked from a response to `command`, has an entry for each key on a board.
+fn check_octave_data_len<T>(command: CommandId, data: Vec<T>) -> Result<Vec<T>, LumatoneMidiError> {
+  if data.len() < OCTAVE_SIZE - 1 {
+    Err(LumatoneMidiError::MessagePayloadTooShort {
+      command,
+      expected: OCTAVE_SIZE - 1,
+      actual: data.len(),
+    })
+  } else if data.len() > OCTAVE_SIZE {
+    Err(LumatoneMidiError::MessagePayloadInvalid(format!(
+      "{command:?} response has {} entries, but boards only have {OCTAVE_SIZE} keys",
+      data.len()
+    )))
+  } else {
+    Ok(data)
+  }
+}
+
+/// Unpacks per-key data from a response, where each key's value is split into two 4-bit nibbles.
 fn unpack_octave_data_8bit(msg: &[u8]) -> Result<(BoardIndex, Vec<u8>), LumatoneMidiError> {
   let msg = valid_lumatone_msg(msg)?;
+  let command = message_command_id(msg)?;
   let board_index = message_board_index(msg)?;
   let payload = message_payload(msg)?;
-  Ok((board_index, unpack_8bit(payload)))
+  if payload.len() % 2 != 0 {
+    log::warn!(
+      "{command:?} response has an odd number of nibbles ({}); ignoring the last one",
+      payload.len()
+    );
+  }
+  let data = check_octave_data_len(command, unpack_8bit(payload))?;
+  Ok((board_index, data))
 }
 
+/// Unpacks per-key data from a response, with one 7-bit byte for each key.
 fn unpack_octave_data_7bit(msg: &[u8]) -> Result<(BoardIndex, Vec<u8>), LumatoneMidiError> {
   let msg = valid_lumatone_msg(msg)?;
+  let command = message_command_id(msg)?;
   let board_index = message_board_index(msg)?;
   let payload = message_payload(msg)?;
-  Ok((board_index, payload.to_vec()))
+  let data = check_octave_data_len(command, payload.to_vec())?;
+  Ok((board_index, data))
 }
 
 fn unpack_channel_config(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  let msg = valid_lumatone_msg(msg)?;
-  let board_index = message_board_index(msg)?;
-  let payload = message_payload(msg)?;
-  let mut channels = Vec::with_capacity(payload.len());
-  for byte in payload {
-    let ch = MidiChannel::try_from_zero_indexed(*byte)?;
+  let (board_index, data) = unpack_octave_data_7bit(msg)?;
+  let mut channels = Vec::with_capacity(data.len());
+  for byte in data {
+    let ch = MidiChannel::try_from_zero_indexed(byte)?;
     channels.push(ch);
   }
   let response = Response::ChannelConfig(board_index, channels);
@@ -368,10 +696,8 @@ fn unpack_channel_config(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
 }
 
 fn unpack_key_validity(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  let msg = valid_lumatone_msg(msg)?;
-  let board_index = message_board_index(msg)?;
-  let payload = message_payload(msg)?;
-  let bools = payload.iter().map(|n| *n != 0).collect();
+  let (board_index, data) = unpack_octave_data_7bit(msg)?;
+  let bools = data.iter().map(|n| *n != 0).collect();
   Ok(Response::KeyValidity(board_index, bools))
 }
 
@@ -383,10 +709,21 @@ fn unpack_velocity_intervals(msg: &[u8]) -> Result<Response, LumatoneMidiError>
 }
 
 fn unpack_serial_id(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  // TODO: the C++ driver has a check for msg[MSG_STATUS] == TEST_ECHO
-  // add that if it seems necessary.
+  let msg = valid_lumatone_msg(msg)?;
+  if has_echo_flag(msg) {
+    return Err(LumatoneMidiError::InvalidResponseMessage(
+      "serial id request has the echo flag where the status should be".to_string(),
+    ));
+  }
 
-  // Also note that we're not handling early firmware versions that respond with an ACK but no serial number.
+  // Early firmware acknowledges the request without sending a serial number, so all we
+  // get back is the zero padding from the request.
+  let payload = message_payload(msg).unwrap_or(&[]);
+  if payload.iter().all(|b| *b == 0) {
+    return Err(LumatoneMidiError::FirmwareTooOld(
+      "no serial id returned".to_string(),
+    ));
+  }
 
   let payload = payload_with_len(msg, 6)?;
   let serial: [u8; 6] = payload.try_into().unwrap();
@@ -404,7 +741,8 @@ fn unpack_firmware_revision(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
 
 fn unpack_board_thresholds(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
   let payload = payload_with_len(msg, 10)?;
-  let (board_index, data) = unpack_octave_data_8bit(payload)?;
+  let board_index = message_board_index(valid_lumatone_msg(msg)?)?;
+  let data = unpack_8bit(payload);
   Ok(Response::BoardThresholds {
     board_index,
     min_high: data[0],
@@ -417,7 +755,8 @@ fn unpack_board_thresholds(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
 
 fn unpack_board_sensitivity(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
   let payload = payload_with_len(msg, 4)?;
-  let (board_index, data) = unpack_octave_data_8bit(msg)?;
+  let board_index = message_board_index(valid_lumatone_msg(msg)?)?;
+  let data = unpack_8bit(payload);
   Ok(Response::BoardSensitivity {
     board_index,
     cc: data[0],
@@ -426,8 +765,7 @@ fn unpack_board_sensitivity(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
 }
 
 fn unpack_peripheral_channels(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  let payload = payload_with_len(msg, 4)?;
-  let (board_index, data) = unpack_octave_data_7bit(msg)?;
+  let data = payload_with_len(msg, 4)?;
 
   let pitch_wheel = MidiChannel::try_from_zero_indexed(data[0])?;
   let mod_wheel = MidiChannel::try_from_zero_indexed(data[1])?;
@@ -442,8 +780,25 @@ fn unpack_peripheral_channels(msg: &[u8]) -> Result<Response, LumatoneMidiError>
   })
 }
 
+/// Payload length of a pedal or wheel calibration status report.
+const PERIPHERAL_CALIBRATION_STATUS_LEN: usize = 15;
+
+/// The acknowledgement of the command that turns a pedal or wheel calibration mode on or
+/// off has the same command id as the status reports, but is too short to be one. Anything
+/// long enough is unpacked as a status report with `unpack_status`.
+fn unpack_calibration_mode_ack_or(
+  msg: &[u8],
+  unpack_status: fn(&[u8]) -> Result<Response, LumatoneMidiError>,
+) -> Result<Response, LumatoneMidiError> {
+  let payload_len = message_payload(valid_lumatone_msg(msg)?).map_or(0, |p| p.len());
+  if payload_len < PERIPHERAL_CALIBRATION_STATUS_LEN {
+    return Ok(Response::Ack(message_command_id(msg)?));
+  }
+  unpack_status(msg)
+}
+
 fn unpack_expression_calibration_status(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  let payload = payload_with_len(msg, 15)?;
+  let payload = payload_with_len(msg, PERIPHERAL_CALIBRATION_STATUS_LEN)?;
 
   // the min and max bounds are encoded into the first six bytes of the payload
   let bounds_data = unpack_12bit_from_4bit(payload);
@@ -465,7 +820,7 @@ fn unpack_expression_calibration_status(msg: &[u8]) -> Result<Response, Lumatone
 }
 
 fn unpack_wheel_calibration_status(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
-  let payload = payload_with_len(msg, 15)?;
+  let payload = payload_with_len(msg, PERIPHERAL_CALIBRATION_STATUS_LEN)?;
   let data = unpack_12bit_from_4bit(payload);
   let center_pitch = data[0];
   let min_pitch = data[1];
@@ -481,20 +836,68 @@ fn unpack_wheel_calibration_status(msg: &[u8]) -> Result<Response, LumatoneMidiE
   })
 }
 
+/// Unpacks a [CommandId::PeripheralCalbrationData] frame, which has the same payload as the
+/// expression pedal or wheel calibration status, depending on its calibration mode byte.
+fn unpack_peripheral_calibration_data(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
+  let mode = calibration_mode_byte(valid_lumatone_msg(msg)?)?;
+  match FromPrimitive::from_u8(mode) {
+    Some(PeripheralCalibrationMode::ExpressionPedal) => unpack_expression_calibration_status(msg),
+    Some(PeripheralCalibrationMode::PitchAndModWheels) => unpack_wheel_calibration_status(msg),
+    None => Err(LumatoneMidiError::MessagePayloadInvalid(format!(
+      "unknown peripheral calibration mode {mode:#04x}"
+    ))),
+  }
+}
+
 fn unpack_aftertouch_trigger_delay(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
   let payload = payload_with_len(msg, 2)?;
-  let (board_index, data) = unpack_octave_data_8bit(payload)?;
+  let board_index = message_board_index(valid_lumatone_msg(msg)?)?;
+  let data = unpack_8bit(payload);
   Ok(Response::AftertouchTriggerDelay(board_index, data[0]))
 }
 
 fn unpack_lumatouch_on_off_delay(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
   let payload = payload_with_len(msg, 3)?;
-  let board_index = message_board_index(msg)?;
+  let board_index = message_board_index(valid_lumatone_msg(msg)?)?;
   let data = unpack_12bit_from_4bit(payload);
   let delay = data[0];
   Ok(Response::LumatouchNoteOffDelay(board_index, delay))
 }
 
+/// The shortest payload a key sample can have: three nibbles per key, allowing for
+/// older firmware that leaves out the last key.
+const KEY_SAMPLE_MIN_PAYLOAD_LEN: usize = (OCTAVE_SIZE - 1) * 3;
+
+/// Unpacks a frame of key sensor readings. Anything too short to be one is the
+/// acknowledgement of the command that turned sampling on or off.
+fn unpack_key_sampling(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
+  if !is_key_sample_message(msg) {
+    return Ok(Response::Ack(CommandId::SetKeySampling));
+  }
+  let msg = valid_lumatone_msg(msg)?;
+  let board = message_board_index(msg)?;
+  let payload = message_payload(msg)?;
+  let values = check_octave_data_len(CommandId::SetKeySampling, unpack_12bit_from_4bit(payload))?;
+  Ok(Response::KeySample(KeySample { board, values }))
+}
+
+/// Unpacks a calibration status message, keeping its payload as-is. Messages from the
+/// Server board are the acknowledgement of the start command.
+fn unpack_calibration_status(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
+  let msg = valid_lumatone_msg(msg)?;
+  let cmd_id = message_command_id(msg)?;
+  match CalibrationKind::from_command_id(&cmd_id) {
+    Some(kind) if is_calibration_status_message(msg) => {
+      Ok(Response::CalibrationStatus(CalibrationStatus {
+        kind,
+        board: message_board_index(msg)?,
+        payload: message_payload(msg)?.to_vec(),
+      }))
+    }
+    _ => Ok(Response::Ack(cmd_id)),
+  }
+}
+
 fn unpack_expression_threshold(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
   let payload = payload_with_len(msg, 3)?;
   let data = unpack_12bit_from_4bit(payload);
@@ -502,11 +905,17 @@ fn unpack_expression_threshold(msg: &[u8]) -> Result<Response, LumatoneMidiError
   Ok(Response::ExpressionPedalThreshold(threshold))
 }
 
-/// Generic unpacking of 8-bit data from a SysEx message payload
+fn unpack_expression_sensitivity(msg: &[u8]) -> Result<Response, LumatoneMidiError> {
+  let payload = payload_with_len(msg, 1)?;
+  Ok(Response::ExpressionPedalSensitivity(payload[0]))
+}
+
+/// Generic unpacking of 8-bit data from a SysEx message payload, where each value
+/// is sent as two 4-bit nibbles (high nibble first)
 fn unpack_8bit(payload: &[u8]) -> Vec<u8> {
   payload
     .chunks_exact(2)
-    .flat_map(|c| vec![c[0] << 4, c[1]])
+    .map(|c| (c[0] << 4) | c[1])
     .collect()
 }
 
@@ -527,3 +936,577 @@ fn unpack_12bit_from_4bit(payload: &[u8]) -> Vec<u16> {
 }
 
 // endregion
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::path::Path;
+
+  use super::Response;
+  use crate::midi::{
+    commands::{set_key_color, Command},
+    constants::{
+      key_loc_unchecked, BoardIndex, CommandId, RGBColor, ResponseStatusCode, MANUFACTURER_ID,
+    },
+    error::LumatoneMidiError,
+    sysex::{strip_sysex_markers, CMD_ID, SYSEX_END, SYSEX_START},
+  };
+
+  /// Builds an ack for `command` the way the device does, echoing the sent data after the status byte.
+  fn synthesize_echo(command: &Command) -> Vec<u8> {
+    let sent = command.to_sysex_message();
+    let sent = strip_sysex_markers(&sent);
+    let mut msg = vec![SYSEX_START];
+    msg.extend(&sent[..=CMD_ID]);
+    msg.push(ResponseStatusCode::Ack as u8);
+    msg.extend(&sent[CMD_ID + 1..]);
+    msg.push(SYSEX_END);
+    msg
+  }
+
+  #[test]
+  fn test_decode_key_color_echo() {
+    let cmd = set_key_color(key_loc_unchecked(2, 10), RGBColor(0x10, 0x20, 0x30));
+    let res = Response::from_sysex_message(&synthesize_echo(&cmd)).unwrap();
+    match &res {
+      Response::SetConfirmation { command, board, .. } => {
+        assert_eq!(*command, CommandId::SetKeyColour);
+        assert_eq!(*board, BoardIndex::Octave2);
+      }
+      other => panic!("expected SetConfirmation, got {other:?}"),
+    }
+    assert_eq!(res.matches_echo(&cmd), Some(true));
+  }
+
+  #[test]
+  fn test_corrupted_echo_does_not_confirm() {
+    let cmd = set_key_color(key_loc_unchecked(2, 10), RGBColor(0x10, 0x20, 0x30));
+    let mut msg = synthesize_echo(&cmd);
+    // flip a bit in the key index
+    msg[CMD_ID + 3] ^= 0x1;
+    let res = Response::from_sysex_message(&msg).unwrap();
+    assert_eq!(res.matches_echo(&cmd), Some(false));
+
+    // an echo for a different command doesn't say anything about this one
+    let other = Command::SetAftertouchEnabled(true);
+    assert_eq!(res.matches_echo(&other), None);
+  }
+
+  #[test]
+  fn test_toggle_echo() {
+    let cmd = Command::SetAftertouchEnabled(true);
+    let res = Response::from_sysex_message(&synthesize_echo(&cmd)).unwrap();
+    assert_eq!(res.matches_echo(&cmd), Some(true));
+    assert_eq!(
+      res.matches_echo(&Command::SetAftertouchEnabled(false)),
+      Some(false)
+    );
+  }
+
+  #[test]
+  fn test_uncaptured_echoes_are_not_checked() {
+    // without a capture to go by, even a mismatched echo isn't reported
+    let cmd = Command::SetAftertouchEnabled(true);
+    let res = Response::from_sysex_message(&synthesize_echo(&cmd)).unwrap();
+    assert_eq!(res.confirms(&Command::SetAftertouchEnabled(false)), None);
+    assert_eq!(res.confirms(&cmd), None);
+  }
+
+  #[test]
+  fn test_non_echoing_commands_are_plain_acks() {
+    let cmd = Command::SetModWheelSensitivity(20);
+    let res = Response::from_sysex_message(&synthesize_echo(&cmd)).unwrap();
+    assert!(matches!(
+      res,
+      Response::Ack(CommandId::SetModWheelSensitivity)
+    ));
+    assert_eq!(res.confirms(&cmd), None);
+  }
+
+  /// Builds an ack response to `command` from `board`, with the given payload.
+  fn response_msg(command: CommandId, board: BoardIndex, payload: &[u8]) -> Vec<u8> {
+    let mut msg = vec![SYSEX_START];
+    msg.extend(MANUFACTURER_ID);
+    msg.push(board as u8);
+    msg.push(command as u8);
+    msg.push(ResponseStatusCode::Ack as u8);
+    msg.extend(payload);
+    msg.push(SYSEX_END);
+    msg
+  }
+
+  #[test]
+  fn test_decode_octave_data() {
+    // 8-bit values are sent as two nibbles
+    let payload: Vec<u8> = (0..56).flat_map(|_| [0xa, 0x5]).collect();
+    let msg = response_msg(CommandId::GetRedLedConfig, BoardIndex::Octave3, &payload);
+    match Response::from_sysex_message(&msg).unwrap() {
+      Response::RedLEDConfig(board, data) => {
+        assert_eq!(board, BoardIndex::Octave3);
+        assert_eq!(data, vec![0xa5; 56]);
+      }
+      other => panic!("expected RedLEDConfig, got {other:?}"),
+    }
+
+    // a dangling nibble is dropped
+    let mut payload: Vec<u8> = (0..55).flat_map(|_| [0x1, 0x2]).collect();
+    payload.push(0x3);
+    let msg = response_msg(CommandId::GetBlueLedConfig, BoardIndex::Octave2, &payload);
+    match Response::from_sysex_message(&msg).unwrap() {
+      Response::BlueLEDConfig(_, data) => assert_eq!(data, vec![0x12; 55]),
+      other => panic!("expected BlueLEDConfig, got {other:?}"),
+    }
+
+    // older firmware leaves off the last key
+    let msg = response_msg(CommandId::GetNoteConfig, BoardIndex::Octave1, &[60; 55]);
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::NoteConfig(BoardIndex::Octave1, data)) if data.len() == 55
+    ));
+
+    let msg = response_msg(CommandId::GetNoteConfig, BoardIndex::Octave1, &[60; 57]);
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Err(LumatoneMidiError::MessagePayloadInvalid(_))
+    ));
+  }
+
+  #[test]
+  fn test_clone_fader_config() {
+    let payload: Vec<u8> = (0..128).collect();
+    let msg = response_msg(CommandId::GetFaderConfig, BoardIndex::Server, &payload);
+    let res = Response::from_sysex_message(&msg).unwrap();
+    let copy = res.clone();
+    drop(res);
+    match copy {
+      Response::FaderConfig(table) => assert_eq!(table.to_vec(), payload),
+      other => panic!("expected FaderConfig, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_truncated_responses_return_errors() {
+    use CommandId::*;
+    let octave_8bit = [0x1; 112];
+    let octave_7bit = [0x1; 56];
+    let responses = vec![
+      response_msg(LumaPing, BoardIndex::Server, &[0x7f, 0, 0, 42]),
+      response_msg(GetRedLedConfig, BoardIndex::Octave1, &octave_8bit),
+      response_msg(GetMaxThreshold, BoardIndex::Octave2, &octave_8bit),
+      response_msg(GetNoteConfig, BoardIndex::Octave3, &octave_7bit),
+      response_msg(GetKeytypeConfig, BoardIndex::Octave4, &octave_7bit),
+      response_msg(GetChannelConfig, BoardIndex::Octave5, &octave_7bit),
+      response_msg(GetKeyValidity, BoardIndex::Octave1, &octave_7bit),
+      response_msg(GetVelocityConfig, BoardIndex::Server, &[0x1; 128]),
+      response_msg(GetVelocityIntervals, BoardIndex::Server, &[0x1; 254]),
+      response_msg(GetSerialIdentity, BoardIndex::Server, &[0x1; 6]),
+      response_msg(GetFirmwareRevision, BoardIndex::Server, &[1, 2, 3]),
+      response_msg(GetBoardThresholdValues, BoardIndex::Octave1, &[0x1; 10]),
+      response_msg(GetBoardSensitivityValues, BoardIndex::Octave1, &[0x1; 4]),
+      response_msg(GetPeripheralChannels, BoardIndex::Server, &[0x1; 4]),
+      response_msg(GetAftertouchTriggerDelay, BoardIndex::Octave1, &[0x1; 2]),
+      response_msg(GetLumatouchNoteOffDelay, BoardIndex::Octave1, &[0x1; 3]),
+      response_msg(GetExpressionPedalSensitivity, BoardIndex::Server, &[0x1]),
+    ];
+
+    for full in responses {
+      let cmd = full[CMD_ID + 1];
+      assert!(
+        Response::from_sysex_message(&full).is_ok(),
+        "full response for command {cmd:x} should decode"
+      );
+
+      // Drop bytes from the end of the payload one at a time, keeping the end marker.
+      // None of these should panic, and once the payload is too short to hold the
+      // data they should fail with an error.
+      let body = &full[..full.len() - 1];
+      for len in 0..body.len() {
+        let mut truncated = body[..len].to_vec();
+        truncated.push(SYSEX_END);
+        let _ = Response::from_sysex_message(&truncated);
+      }
+
+      // Without any payload at all, every one of these is an error.
+      let mut empty = full[..=CMD_ID + 2].to_vec();
+      empty.push(SYSEX_END);
+      assert!(
+        Response::from_sysex_message(&empty).is_err(),
+        "empty response for command {cmd:x} should fail"
+      );
+    }
+  }
+
+  #[test]
+  fn test_calibration_mode_acks_are_not_status_reports() {
+    use crate::midi::responses::is_peripheral_calibration_report;
+    use CommandId::*;
+    for command in [CalibrateExpressionPedal, CalibratePitchModWheel] {
+      let status = response_msg(command, BoardIndex::Server, &[0x1; 15]);
+      let res = Response::from_sysex_message(&status).unwrap();
+      assert!(
+        !matches!(res, Response::Ack(_)),
+        "{command:?} status decoded as {res:?}"
+      );
+
+      assert!(is_peripheral_calibration_report(&status));
+
+      // anything shorter, including a padded echo of the enable flag, is an acknowledgement
+      for len in [0, 1, 4, 14] {
+        let ack = response_msg(command, BoardIndex::Server, &vec![0x1; len]);
+        assert!(!is_peripheral_calibration_report(&ack), "{len} bytes");
+        match Response::from_sysex_message(&ack) {
+          Ok(Response::Ack(id)) => assert_eq!(id, command),
+          other => panic!("expected an ack for {command:?} with {len} bytes, got {other:?}"),
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_display_board_values() {
+    use CommandId::*;
+    let thresholds = response_msg(
+      GetBoardThresholdValues,
+      BoardIndex::Octave2,
+      &[0, 1, 0, 2, 0, 3, 0, 4, 0, 5],
+    );
+    assert_eq!(
+      Response::from_sysex_message(&thresholds)
+        .unwrap()
+        .to_string(),
+      format!(
+        "BoardThresholds({}, min_high: 1, min_low: 2, max: 3, aftertouch: 4, cc: 5)",
+        BoardIndex::Octave2
+      )
+    );
+    let sensitivity = response_msg(
+      GetBoardSensitivityValues,
+      BoardIndex::Octave3,
+      &[0, 7, 1, 0],
+    );
+    assert_eq!(
+      Response::from_sysex_message(&sensitivity)
+        .unwrap()
+        .to_string(),
+      format!(
+        "BoardSensitivity({}, cc: 7, aftertouch: 16)",
+        BoardIndex::Octave3
+      )
+    );
+
+    // every response is shown in driver traces, so none of them may panic
+    for (cmd, payload) in [
+      (GetBoardSensitivityValues, &[0x1; 4][..]),
+      (GetPeripheralChannels, &[0x1; 4][..]),
+      (CalibrateExpressionPedal, &[0x1; 15][..]),
+      (CalibratePitchModWheel, &[0x1; 15][..]),
+    ] {
+      let msg = response_msg(cmd, BoardIndex::Octave1, payload);
+      assert!(!Response::from_sysex_message(&msg)
+        .unwrap()
+        .to_string()
+        .is_empty());
+    }
+  }
+
+  /// Packs 12-bit values into nibbles the way key samples are sent.
+  fn pack_12bit(values: &[u16]) -> Vec<u8> {
+    values
+      .iter()
+      .flat_map(|v| [(v >> 8) as u8 & 0xf, (v >> 4) as u8 & 0xf, *v as u8 & 0xf])
+      .collect()
+  }
+
+  #[test]
+  fn test_decode_key_sample() {
+    use crate::midi::responses::is_key_sample_message;
+
+    let values: Vec<u16> = (0..56).map(|k| 4000 - k * 7).collect();
+    let msg = response_msg(
+      CommandId::SetKeySampling,
+      BoardIndex::Octave4,
+      &pack_12bit(&values),
+    );
+    assert!(is_key_sample_message(&msg));
+    match Response::from_sysex_message(&msg).unwrap() {
+      Response::KeySample(sample) => {
+        assert_eq!(sample.board, BoardIndex::Octave4);
+        assert_eq!(sample.values, values);
+      }
+      other => panic!("expected KeySample, got {other:?}"),
+    }
+
+    // older firmware leaves off the last key
+    let msg = response_msg(
+      CommandId::SetKeySampling,
+      BoardIndex::Octave4,
+      &pack_12bit(&values[..55]),
+    );
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::KeySample(sample)) if sample.values.len() == 55
+    ));
+
+    let msg = response_msg(CommandId::SetKeySampling, BoardIndex::Octave4, &[0; 57 * 3]);
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Err(LumatoneMidiError::MessagePayloadInvalid(_))
+    ));
+  }
+
+  #[test]
+  fn test_key_sampling_toggle_ack_is_not_a_sample() {
+    use crate::midi::responses::is_key_sample_message;
+
+    let cmd = Command::EnableKeySampling(BoardIndex::Octave2, true);
+    let ack = synthesize_echo(&cmd);
+    assert!(!is_key_sample_message(&ack));
+    assert!(matches!(
+      Response::from_sysex_message(&ack),
+      Ok(Response::Ack(CommandId::SetKeySampling))
+    ));
+  }
+
+  #[test]
+  fn test_decode_calibration_status() {
+    use crate::midi::calibration::{CalibrationKind, CalibrationStatus};
+    use crate::midi::responses::is_calibration_status_message;
+
+    let msg = response_msg(CommandId::CalibrateKeys, BoardIndex::Octave2, &[0x1, 0x2]);
+    assert!(is_calibration_status_message(&msg));
+    match Response::from_sysex_message(&msg).unwrap() {
+      Response::CalibrationStatus(status) => assert_eq!(
+        status,
+        CalibrationStatus {
+          kind: CalibrationKind::Keys,
+          board: BoardIndex::Octave2,
+          payload: vec![0x1, 0x2],
+        }
+      ),
+      other => panic!("expected CalibrationStatus, got {other:?}"),
+    }
+
+    // the start command is sent to the server board, which acknowledges it
+    let ack = synthesize_echo(&Command::StartAftertouchCalibration);
+    assert!(!is_calibration_status_message(&ack));
+    assert!(matches!(
+      Response::from_sysex_message(&ack),
+      Ok(Response::Ack(CommandId::CalibrateAftertouch))
+    ));
+  }
+
+  #[test]
+  fn test_decode_peripheral_calibration_data() {
+    use crate::midi::constants::PeripheralCalibrationMode;
+    use crate::midi::responses::{
+      is_peripheral_calibration_report, is_peripheral_calibration_status_message,
+    };
+    use crate::midi::sysex::create_sysex;
+
+    // no status byte: the mode comes right after the command id, then the payload
+    let frame = |mode: u8, payload: &[u8]| {
+      let mut data = vec![mode];
+      data.extend(payload);
+      create_sysex(
+        BoardIndex::Server,
+        CommandId::PeripheralCalbrationData,
+        data,
+      )
+    };
+    let wheels = [0, 0, 1, 0, 0, 2, 0, 0, 3, 0, 0, 4, 0, 0, 5];
+
+    let msg = frame(PeripheralCalibrationMode::PitchAndModWheels as u8, &wheels);
+    assert!(is_peripheral_calibration_status_message(&msg));
+    assert!(is_peripheral_calibration_report(&msg));
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::WheelCalibrationStatus {
+        center_pitch: 1,
+        min_pitch: 2,
+        max_pitch: 3,
+        min_mod: 4,
+        max_mod: 5,
+      })
+    ));
+
+    // mode 0 is the expression pedal, even though 0 would be a Nack in a status frame
+    let pedal = [0, 1, 0, 0xf, 0xf, 0xf, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+    let msg = frame(PeripheralCalibrationMode::ExpressionPedal as u8, &pedal);
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::ExpressionCalibrationStatus {
+        min_bound: 0x10,
+        max_bound: 0xfff,
+        valid: true,
+      })
+    ));
+
+    assert!(matches!(
+      Response::from_sysex_message(&frame(0x7, &wheels)),
+      Err(LumatoneMidiError::MessagePayloadInvalid(_))
+    ));
+  }
+
+  #[test]
+  fn test_serial_id() {
+    let serial = [0x1, 0x2, 0x3, 0x4, 0x5, 0x6];
+    let msg = response_msg(CommandId::GetSerialIdentity, BoardIndex::Server, &serial);
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::SerialId(s)) if s == serial
+    ));
+  }
+
+  #[test]
+  fn test_firmware_revision() {
+    let msg = response_msg(
+      CommandId::GetFirmwareRevision,
+      BoardIndex::Server,
+      &[1, 2, 9],
+    );
+    assert!(matches!(
+      Response::from_sysex_message(&msg),
+      Ok(Response::FirmwareRevision {
+        major: 1,
+        minor: 2,
+        revision: 9
+      })
+    ));
+  }
+
+  #[test]
+  fn test_expression_pedal_sensitivity() {
+    let msg = response_msg(
+      CommandId::GetExpressionPedalSensitivity,
+      BoardIndex::Server,
+      &[100],
+    );
+    let res = Response::from_sysex_message(&msg).unwrap();
+    assert!(matches!(res, Response::ExpressionPedalSensitivity(100)));
+    assert_eq!(res.to_string(), "ExpressionPedalSensitivity(100)");
+  }
+
+  #[test]
+  fn test_velocity_intervals() {
+    // each value is two 6-bit halves, high half first
+    let mut payload = vec![];
+    for i in 0..127_u16 {
+      let value = i * 32;
+      payload.extend([(value >> 6) as u8, (value & 0x3f) as u8]);
+    }
+    let msg = response_msg(
+      CommandId::GetVelocityIntervals,
+      BoardIndex::Server,
+      &payload,
+    );
+    match Response::from_sysex_message(&msg) {
+      Ok(Response::VelocityIntervalConfig(table)) => {
+        assert_eq!(table[0], 0);
+        assert_eq!(table[1], 32);
+        assert_eq!(table[126], 126 * 32);
+      }
+      other => panic!("expected VelocityIntervalConfig, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_old_firmware_acks_serial_id_request_without_serial() {
+    // early firmware just echoes the (zero-padded) request back with an Ack
+    let ack = synthesize_echo(&Command::GetSerialId);
+    assert!(matches!(
+      Response::from_sysex_message(&ack),
+      Err(LumatoneMidiError::FirmwareTooOld(_))
+    ));
+
+    // and may not even send the padding
+    let ack = response_msg(CommandId::GetSerialIdentity, BoardIndex::Server, &[]);
+    assert!(matches!(
+      Response::from_sysex_message(&ack),
+      Err(LumatoneMidiError::FirmwareTooOld(_))
+    ));
+  }
+
+  #[test]
+  fn test_echo_flag() {
+    use crate::midi::commands::ping;
+    use crate::midi::sysex::has_echo_flag;
+
+    // a ping that comes straight back has the flag where the status should be
+    let looped_back = ping(42).to_sysex_message();
+    assert!(has_echo_flag(&looped_back));
+    assert!(matches!(
+      Response::from_sysex_message(&looped_back),
+      Err(LumatoneMidiError::InvalidResponseMessage(_))
+    ));
+
+    // the device's answer has an Ack status, with the flag moved into the payload
+    let answer = synthesize_echo(&ping(42));
+    assert!(!has_echo_flag(&answer));
+    assert!(matches!(
+      Response::from_sysex_message(&answer),
+      Ok(Response::Pong(42))
+    ));
+
+    let mut serial_request = Command::GetSerialId.to_sysex_message();
+    serial_request[CMD_ID + 2] = crate::midi::constants::TEST_ECHO;
+    assert!(matches!(
+      Response::from_sysex_message(&serial_request),
+      Err(LumatoneMidiError::InvalidResponseMessage(_))
+    ));
+  }
+
+  #[test]
+  fn test_short_octave_data_reports_command() {
+    let msg = response_msg(
+      CommandId::GetBlueLedConfig,
+      BoardIndex::Octave2,
+      &[0x1; 100],
+    );
+    match Response::from_sysex_message(&msg) {
+      Err(LumatoneMidiError::MessagePayloadTooShort {
+        command,
+        expected,
+        actual,
+      }) => {
+        assert_eq!(command, CommandId::GetBlueLedConfig);
+        assert_eq!(expected, 55);
+        assert_eq!(actual, 50);
+      }
+      other => panic!("expected MessagePayloadTooShort, got {other:?}"),
+    }
+  }
+
+  /// Decodes every `.syx` capture in `dir` and checks it against the `.json` file with the
+  /// same name, which holds the serialized [Response] it should decode to.
+  fn assert_responses_match_baselines(dir: &Path) {
+    let mut captures: Vec<_> = fs::read_dir(dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().path())
+      .filter(|path| path.extension().is_some_and(|ext| ext == "syx"))
+      .collect();
+    captures.sort();
+    assert!(!captures.is_empty(), "no captures in {}", dir.display());
+
+    for capture in captures {
+      let msg = fs::read(&capture).unwrap();
+      let response = Response::from_sysex_message(&msg)
+        .unwrap_or_else(|e| panic!("{} didn't decode: {e}", capture.display()));
+      let baseline = capture.with_extension("json");
+      let expected: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&baseline).unwrap())
+          .unwrap_or_else(|e| panic!("{} isn't valid json: {e}", baseline.display()));
+      assert_eq!(
+        serde_json::to_value(&response).unwrap(),
+        expected,
+        "{} decoded differently than its baseline",
+        capture.display()
+      );
+    }
+  }
+
+  #[test]
+  fn test_captured_responses_match_baselines() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/midi/fixtures/responses");
+    assert_responses_match_baselines(Path::new(dir));
+  }
+}
@@ -0,0 +1,196 @@
+//! Collects statistics from the raw key sensor readings a board streams while key
+//! sampling is enabled (see [Command::EnableKeySampling](super::commands::Command::EnableKeySampling)).
+//!
+//! This is meant for diagnosing flaky keys: a key whose readings jump around while it's
+//! at rest, or that never reaches the same range as its neighbors, probably has a sensor
+//! problem or needs recalibrating.
+//!
+//! ## Capture format
+//!
+//! There's no public documentation of what the firmware sends while sampling, so this is
+//! based on how it packs other per-board data, and should be treated as provisional:
+//!
+//! - Samples arrive as unsolicited messages from the sampled board, with the
+//!   `SetKeySampling` (0x35) command id and an `Ack` status byte, at whatever rate the
+//!   firmware likes.
+//! - The payload holds one 12-bit ADC reading per key, in key index order. Each reading
+//!   is split into three 4-bit nibbles, high nibble first, like the firmware's other
+//!   12-bit values (e.g. the Lumatouch note off delay). That's 168 bytes for 56 keys.
+//!   Older firmware may leave off the last key, as it does for other per-key data.
+//! - The acknowledgement of the enable/disable command itself only echoes the toggle byte,
+//!   so it's much too short to be mistaken for a sample.
+//!
+//! If real captures turn out to differ, [Response::KeySample](super::responses::Response::KeySample)
+//! decoding is the only place that needs to change.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::constants::BoardIndex;
+
+/// One frame of sensor readings from a board, one value per key in key index order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeySample {
+  pub board: BoardIndex,
+  pub values: Vec<u16>,
+}
+
+/// Summary statistics for the readings from a single key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyStats {
+  /// Index of the key on its board.
+  pub key: u8,
+  pub count: usize,
+  pub min: u16,
+  pub max: u16,
+  pub mean: f64,
+  /// Running sum of squared differences from the mean (Welford's algorithm).
+  m2: f64,
+}
+
+impl KeyStats {
+  fn new(key: u8) -> Self {
+    KeyStats {
+      key,
+      count: 0,
+      min: u16::MAX,
+      max: 0,
+      mean: 0.0,
+      m2: 0.0,
+    }
+  }
+
+  fn record(&mut self, value: u16) {
+    self.count += 1;
+    self.min = self.min.min(value);
+    self.max = self.max.max(value);
+    let delta = value as f64 - self.mean;
+    self.mean += delta / self.count as f64;
+    self.m2 += delta * (value as f64 - self.mean);
+  }
+
+  /// The population standard deviation of the readings, or 0 if there are none.
+  pub fn std_dev(&self) -> f64 {
+    if self.count == 0 {
+      0.0
+    } else {
+      (self.m2 / self.count as f64).sqrt()
+    }
+  }
+
+  /// The difference between the highest and lowest readings, or 0 if there are none.
+  pub fn range(&self) -> u16 {
+    self.max.saturating_sub(self.min)
+  }
+}
+
+/// Per-key statistics from a key sampling session on one board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySamplingReport {
+  pub board: BoardIndex,
+  /// How long sampling was enabled for.
+  pub duration: Duration,
+  /// How many frames of readings were received.
+  pub frames: usize,
+  /// Stats for each key that had at least one reading, in key index order.
+  pub keys: Vec<KeyStats>,
+}
+
+impl KeySamplingReport {
+  pub fn new(board: BoardIndex) -> Self {
+    KeySamplingReport {
+      board,
+      duration: Duration::ZERO,
+      frames: 0,
+      keys: Vec::new(),
+    }
+  }
+
+  /// Adds a frame of readings, with one value per key in key index order.
+  pub fn record(&mut self, values: &[u16]) {
+    self.frames += 1;
+    for (i, value) in values.iter().enumerate() {
+      if i == self.keys.len() {
+        self.keys.push(KeyStats::new(i as u8));
+      }
+      self.keys[i].record(*value);
+    }
+  }
+
+  /// Returns the stats for the key at `key` index, if it had any readings.
+  pub fn key(&self, key: u8) -> Option<&KeyStats> {
+    self.keys.get(key as usize)
+  }
+
+  /// Renders the per-key stats as CSV, with a header row.
+  pub fn to_csv(&self) -> String {
+    let mut csv = String::from("key,samples,min,max,range,mean,std_dev\n");
+    for k in &self.keys {
+      // writing to a String can't fail
+      let _ = writeln!(
+        csv,
+        "{},{},{},{},{},{:.2},{:.2}",
+        k.key,
+        k.count,
+        k.min,
+        k.max,
+        k.range(),
+        k.mean,
+        k.std_dev()
+      );
+    }
+    csv
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::KeySamplingReport;
+  use crate::midi::constants::BoardIndex;
+
+  #[test]
+  fn test_report_stats() {
+    let mut report = KeySamplingReport::new(BoardIndex::Octave2);
+    report.record(&[100, 2000, 7]);
+    report.record(&[100, 2004, 7]);
+    report.record(&[100, 1996, 7]);
+    report.record(&[100, 2000, 4095]);
+
+    assert_eq!(report.frames, 4);
+    assert_eq!(report.keys.len(), 3);
+
+    let steady = report.key(0).unwrap();
+    assert_eq!((steady.min, steady.max, steady.range()), (100, 100, 0));
+    assert_eq!(steady.mean, 100.0);
+    assert_eq!(steady.std_dev(), 0.0);
+
+    let noisy = report.key(1).unwrap();
+    assert_eq!(noisy.count, 4);
+    assert_eq!((noisy.min, noisy.max), (1996, 2004));
+    assert_eq!(noisy.mean, 2000.0);
+    // deviations of 0, 4, -4, 0
+    assert!((noisy.std_dev() - 8f64.sqrt()).abs() < 1e-9);
+
+    let spiky = report.key(2).unwrap();
+    assert_eq!(spiky.range(), 4088);
+    assert!(report.key(3).is_none());
+  }
+
+  #[test]
+  fn test_report_csv() {
+    let mut report = KeySamplingReport::new(BoardIndex::Octave1);
+    report.record(&[10, 20]);
+    report.record(&[12, 20]);
+    assert_eq!(
+      report.to_csv(),
+      "key,samples,min,max,range,mean,std_dev\n\
+       0,2,10,12,2,11.00,1.00\n\
+       1,2,20,20,0,20.00,0.00\n"
+    );
+
+    let empty = KeySamplingReport::new(BoardIndex::Octave1);
+    assert_eq!(empty.to_csv(), "key,samples,min,max,range,mean,std_dev\n");
+  }
+}
@@ -0,0 +1,272 @@
+//! Runs a fixed sequence of [Command]s through a [MidiDriver] and collects the results.
+//!
+//! This is handy for scripted sessions (e.g. sending a full preset, or automated test rigs),
+//! where you know up front everything you want to send and just want a transcript at the end.
+
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use super::{
+  commands::Command,
+  device::{LumatoneDevice, MidiTransport},
+  driver::{MidiDriver, MidiDriverConfig},
+  error::LumatoneMidiError,
+  responses::Response,
+};
+
+/// Counts of command outcomes for a script run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DriverStats {
+  pub commands_sent: usize,
+  pub succeeded: usize,
+  pub failed: usize,
+}
+
+/// The outcome of [MidiDriver::run_script].
+#[derive(Debug)]
+pub struct ScriptReport {
+  /// Each command that was sent, along with its result, in the order they were sent.
+  /// If the script stopped early, commands after the first failure are not included.
+  pub results: Vec<(Command, Result<Response, LumatoneMidiError>)>,
+  pub stats: DriverStats,
+  pub duration: Duration,
+}
+
+impl ScriptReport {
+  /// Returns true if every command in the report succeeded.
+  pub fn is_success(&self) -> bool {
+    self.stats.failed == 0
+  }
+}
+
+/// Passed to the callback of [MidiDriver::run_commands_with_progress] after each command.
+#[derive(Debug)]
+pub struct ScriptProgress<'a> {
+  /// How many commands have been sent so far, including this one.
+  pub done: usize,
+  /// How many commands there are in all. Fewer may be sent if the script stops early.
+  pub total: usize,
+  pub command: &'a Command,
+  pub result: &'a Result<Response, LumatoneMidiError>,
+}
+
+impl ScriptProgress<'_> {
+  /// A short name for what the script is working on: `board1` through `board5` for
+  /// commands that target a key, or `global` for everything else.
+  pub fn stage(&self) -> String {
+    match self.command.key_location() {
+      Some(location) => format!("board{}", location.board_index() as u8),
+      None => "global".to_string(),
+    }
+  }
+}
+
+impl MidiDriver {
+  /// Connects to `device`, sends each of `commands` in order, and shuts the driver down
+  /// once they've all been sent.
+  ///
+  /// If `stop_on_error` is true, the script stops at the first command that fails.
+  /// Otherwise all commands are sent regardless of failures.
+  ///
+  /// Returns an error only if unable to connect to the device; failures of individual
+  /// commands are recorded in the returned [ScriptReport].
+  pub async fn run_script(
+    device: &LumatoneDevice,
+    commands: Vec<Command>,
+    stop_on_error: bool,
+  ) -> Result<ScriptReport, LumatoneMidiError> {
+    MidiDriver::run_script_with_config(device, MidiDriverConfig::default(), commands, stop_on_error)
+      .await
+  }
+
+  /// Like [MidiDriver::run_script], but with a custom [MidiDriverConfig].
+  pub async fn run_script_with_config(
+    device: &LumatoneDevice,
+    config: MidiDriverConfig,
+    commands: Vec<Command>,
+    stop_on_error: bool,
+  ) -> Result<ScriptReport, LumatoneMidiError> {
+    let device_io = device.connect()?;
+    Ok(run_script_with_transport(Box::new(device_io), config, commands, stop_on_error).await)
+  }
+}
+
+pub(crate) async fn run_script_with_transport(
+  device_io: Box<dyn MidiTransport>,
+  config: MidiDriverConfig,
+  commands: Vec<Command>,
+  stop_on_error: bool,
+) -> ScriptReport {
+  let (driver, driver_future) = MidiDriver::with_transport(device_io, config);
+  let handle = tokio::spawn(driver_future);
+
+  let report = driver.run_commands(commands, stop_on_error).await;
+
+  if let Err(err) = driver.done().await {
+    log::error!("error sending done signal: {err}");
+  }
+  if let Err(err) = handle.await {
+    log::error!("error joining driver loop: {err}");
+  }
+  report
+}
+
+impl MidiDriver {
+  /// Like [MidiDriver::run_script], but through this driver's already running event loop,
+  /// which is left running afterwards.
+  pub async fn run_commands(&self, commands: Vec<Command>, stop_on_error: bool) -> ScriptReport {
+    self
+      .run_commands_with_progress(commands, stop_on_error, |_| {})
+      .await
+  }
+
+  /// Like [MidiDriver::run_commands], but calls `on_progress` once each command has been
+  /// answered (or has failed).
+  pub async fn run_commands_with_progress(
+    &self,
+    commands: Vec<Command>,
+    stop_on_error: bool,
+    mut on_progress: impl FnMut(ScriptProgress),
+  ) -> ScriptReport {
+    let start = Instant::now();
+    let total = commands.len();
+    let mut results = Vec::with_capacity(commands.len());
+    let mut stats = DriverStats::default();
+    for command in commands {
+      debug!("script: sending command {command}");
+      let res = self.send(command.clone()).await;
+      stats.commands_sent += 1;
+      let failed = res.is_err();
+      if failed {
+        stats.failed += 1;
+      } else {
+        stats.succeeded += 1;
+      }
+      on_progress(ScriptProgress {
+        done: stats.commands_sent,
+        total,
+        command: &command,
+        result: &res,
+      });
+      results.push((command, res));
+
+      if failed && stop_on_error {
+        debug!("script: stopping after failed command");
+        break;
+      }
+    }
+
+    ScriptReport {
+      results,
+      stats,
+      duration: start.elapsed(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{run_script_with_transport, DriverStats, MidiDriver};
+  use crate::midi::{
+    commands::{ping, set_key_color, Command},
+    constants::{key_loc_unchecked, RGBColor, ResponseStatusCode},
+    driver::MidiDriverConfig,
+    mock::{reply_with_status, MockDevice},
+    responses::Response,
+  };
+
+  fn three_commands() -> Vec<Command> {
+    vec![
+      ping(42),
+      set_key_color(key_loc_unchecked(1, 0), RGBColor::red()),
+      Command::SetAftertouchEnabled(true),
+    ]
+  }
+
+  #[tokio::test]
+  async fn test_run_three_command_script() {
+    let report = run_script_with_transport(
+      Box::new(MockDevice::acking()),
+      MidiDriverConfig::default(),
+      three_commands(),
+      true,
+    )
+    .await;
+
+    assert!(report.is_success());
+    assert_eq!(
+      report.stats,
+      DriverStats {
+        commands_sent: 3,
+        succeeded: 3,
+        failed: 0
+      }
+    );
+    let sent: Vec<_> = report.results.iter().map(|(c, _)| c.clone()).collect();
+    assert_eq!(sent, three_commands());
+    assert!(matches!(report.results[0].1, Ok(Response::Pong(42))));
+  }
+
+  #[tokio::test]
+  async fn test_stop_on_first_error() {
+    // NACK the key color command, ack everything else
+    let responder = |msg: &[u8]| {
+      let status =
+        if msg == set_key_color(key_loc_unchecked(1, 0), RGBColor::red()).to_sysex_message() {
+          ResponseStatusCode::Nack
+        } else {
+          ResponseStatusCode::Ack
+        };
+      Some(reply_with_status(msg, status))
+    };
+
+    let report = run_script_with_transport(
+      Box::new(MockDevice::new(Box::new(responder))),
+      MidiDriverConfig::default(),
+      three_commands(),
+      true,
+    )
+    .await;
+    assert!(!report.is_success());
+    assert_eq!(report.results.len(), 2);
+    assert!(report.results[1].1.is_err());
+
+    let report = run_script_with_transport(
+      Box::new(MockDevice::new(Box::new(responder))),
+      MidiDriverConfig::default(),
+      three_commands(),
+      false,
+    )
+    .await;
+    assert_eq!(report.results.len(), 3);
+    assert_eq!(report.stats.failed, 1);
+    assert_eq!(report.stats.succeeded, 2);
+  }
+
+  #[tokio::test]
+  async fn test_progress_is_reported_per_command() {
+    let (driver, driver_future) =
+      MidiDriver::with_transport(Box::new(MockDevice::acking()), MidiDriverConfig::default());
+    let handle = tokio::spawn(driver_future);
+
+    let mut seen = vec![];
+    let report = driver
+      .run_commands_with_progress(three_commands(), false, |p| {
+        seen.push((p.done, p.total, p.stage(), p.result.is_ok()))
+      })
+      .await;
+    assert!(report.is_success());
+    assert_eq!(
+      seen,
+      vec![
+        (1, 3, "global".to_string(), true),
+        (2, 3, "board1".to_string(), true),
+        (3, 3, "global".to_string(), true),
+      ]
+    );
+
+    driver.done().await.unwrap();
+    handle.await.unwrap();
+  }
+}
@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 
 use super::{
-  constants::{BoardIndex, CommandId, RGBColor, ResponseStatusCode, MANUFACTURER_ID},
+  constants::{BoardIndex, CommandId, RGBColor, ResponseStatusCode, MANUFACTURER_ID, TEST_ECHO},
   error::LumatoneMidiError,
 };
 use num_traits::FromPrimitive;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 // index into sysex data of various fields
 pub const MANU_0: usize = 0x0;
@@ -12,7 +13,11 @@ pub const MANU_1: usize = 0x1;
 pub const MANU_3: usize = 0x2;
 pub const BOARD_IND: usize = 0x3;
 pub const CMD_ID: usize = 0x4;
+/// Responses to commands have a [ResponseStatusCode] here. Read it with [status_byte].
 pub const MSG_STATUS: usize = 0x5;
+/// [CommandId::PeripheralCalbrationData] frames, which the device sends on its own while a
+/// pedal or wheel is being calibrated, have no status. The byte where other responses keep
+/// it says which peripheral the data is for instead. Read it with [calibration_mode_byte].
 pub const CALIB_MODE: usize = 0x5;
 pub const PAYLOAD_INIT: usize = 0x6;
 
@@ -22,16 +27,164 @@ pub const SYSEX_END: u8 = 0xf7;
 pub type EncodedSysex = Vec<u8>;
 
 /// Some commands send "tables" of config data (e.g. key velocity, etc).
-/// Tables are always 128 elements long.
-pub type SysexTable = [u8; 128];
+/// Tables are always 128 elements long, and since they're sent as sysex data bytes,
+/// every value must fit in 7 bits. A `SysexTable` can only be made from values that do.
+///
+/// It's a distinct type from [EncodedSysex], so a full message can't be sent as a table
+/// by mistake:
+///
+/// ```compile_fail
+/// use lumatone_core::midi::constants::{BoardIndex, CommandId};
+/// use lumatone_core::midi::sysex::{create_sysex, create_table_sysex};
+///
+/// let frame = create_sysex(BoardIndex::Server, CommandId::SetFaderConfig, vec![0; 128]);
+/// create_table_sysex(CommandId::SetFaderConfig, &frame);
+/// ```
+///
+/// Use `TryFrom` to make one from an array or slice, and [SysexTable::values] (or deref)
+/// to get at the values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysexTable([u8; 128]);
+
+impl SysexTable {
+  /// Makes a table from known-good values, e.g. for constants.
+  ///
+  /// Panics if any value doesn't fit in 7 bits (at compile time, when used in a `const`).
+  pub const fn new(values: [u8; 128]) -> SysexTable {
+    let mut i = 0;
+    while i < values.len() {
+      assert!(values[i] <= 0x7f, "sysex table values must fit in 7 bits");
+      i += 1;
+    }
+    SysexTable(values)
+  }
+
+  pub fn values(&self) -> &[u8; 128] {
+    &self.0
+  }
+}
+
+impl TryFrom<[u8; 128]> for SysexTable {
+  type Error = LumatoneMidiError;
+
+  fn try_from(values: [u8; 128]) -> Result<Self, Self::Error> {
+    match values.iter().position(|v| *v > 0x7f) {
+      Some(i) => Err(LumatoneMidiError::MessagePayloadInvalid(format!(
+        "table value {} at index {i} doesn't fit in 7 bits",
+        values[i]
+      ))),
+      None => Ok(SysexTable(values)),
+    }
+  }
+}
+
+impl TryFrom<&[u8]> for SysexTable {
+  type Error = LumatoneMidiError;
+
+  fn try_from(values: &[u8]) -> Result<Self, Self::Error> {
+    let values: [u8; 128] = values.try_into().map_err(|_| {
+      LumatoneMidiError::MessagePayloadInvalid(format!(
+        "tables have 128 values, got {}",
+        values.len()
+      ))
+    })?;
+    SysexTable::try_from(values)
+  }
+}
+
+impl std::ops::Deref for SysexTable {
+  type Target = [u8; 128];
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+/// Serializes as a list of values. serde only derives for arrays of up to 32 elements.
+impl Serialize for SysexTable {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(self.0.iter())
+  }
+}
+
+/// Reads a list of 128 values, each fitting in 7 bits.
+impl<'de> Deserialize<'de> for SysexTable {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let values = Vec::<u8>::deserialize(deserializer)?;
+    SysexTable::try_from(values.as_slice()).map_err(serde::de::Error::custom)
+  }
+}
+
+impl From<SysexTable> for Vec<u8> {
+  fn from(table: SysexTable) -> Self {
+    table.0.to_vec()
+  }
+}
 
 /// The velocity interval table contains 127 12-bit values.
 pub type VelocityIntervalTable = [u16; 127];
 
+/// Serializes a [VelocityIntervalTable] as a list, since serde only derives for arrays of
+/// up to 32 elements. Use [boxed](velocity_intervals_serde::boxed) or
+/// [option](velocity_intervals_serde::option) with `#[serde(with = "...")]`, for a table
+/// held in a `Box` or an `Option`.
+pub(crate) mod velocity_intervals_serde {
+  use serde::de::Error;
+
+  use super::VelocityIntervalTable;
+
+  fn from_list<E: Error>(values: Vec<u16>) -> Result<VelocityIntervalTable, E> {
+    let len = values.len();
+    values
+      .try_into()
+      .map_err(|_| E::invalid_length(len, &"127 velocity intervals"))
+  }
+
+  pub mod boxed {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{from_list, VelocityIntervalTable};
+
+    pub fn serialize<S: Serializer>(
+      table: &VelocityIntervalTable,
+      serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+      table.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      deserializer: D,
+    ) -> Result<Box<VelocityIntervalTable>, D::Error> {
+      from_list(Vec::<u16>::deserialize(deserializer)?).map(Box::new)
+    }
+  }
+
+  pub mod option {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{from_list, VelocityIntervalTable};
+
+    pub fn serialize<S: Serializer>(
+      table: &Option<VelocityIntervalTable>,
+      serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+      table.as_ref().map(|t| t.as_slice()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+      deserializer: D,
+    ) -> Result<Option<VelocityIntervalTable>, D::Error> {
+      Option::<Vec<u16>>::deserialize(deserializer)?
+        .map(from_list)
+        .transpose()
+    }
+  }
+}
+
 pub fn reverse_table(t: &SysexTable) -> SysexTable {
-  let mut r = t.clone();
+  let mut r = t.0;
   r.reverse();
-  r
+  SysexTable(r)
 }
 
 pub fn to_hex_debug_str(msg: &[u8]) -> String {
@@ -43,23 +196,54 @@ pub fn to_hex_debug_str(msg: &[u8]) -> String {
   format!("[ {s} ]")
 }
 
+/// Returns a short, human-readable summary of a message's header, for logging alongside
+/// [to_hex_debug_str]: the command, the board it's for, and how many data bytes follow.
+///
+/// The summary is the same for a command and its response, since a frame doesn't say which
+/// way it's going. For a response, the first data byte is its status.
+pub fn describe(msg: &[u8]) -> String {
+  if !is_lumatone_message(msg) {
+    return "not a Lumatone message".to_string();
+  }
+  let msg = strip_sysex_markers(msg);
+  if msg.len() <= CMD_ID {
+    return format!("truncated message ({} bytes)", msg.len());
+  }
+  let command = match CommandId::from_u8(msg[CMD_ID]) {
+    Some(cmd) => format!("{cmd:?}"),
+    None => format!("unknown command {:#04x}", msg[CMD_ID]),
+  };
+  let board = match BoardIndex::from_u8(msg[BOARD_IND]) {
+    Some(board) => board.to_string(),
+    None => format!("unknown board {}", msg[BOARD_IND]),
+  };
+  let data_len = msg.len() - MSG_STATUS;
+  let plural = if data_len == 1 { "" } else { "s" };
+  format!("{command} for {board}, {data_len} data byte{plural}")
+}
+
 pub fn create_sysex(board_index: BoardIndex, cmd: CommandId, data: Vec<u8>) -> EncodedSysex {
-  let mut sysex: Vec<u8> = vec![SYSEX_START];
-  sysex.extend(MANUFACTURER_ID.iter());
-  sysex.push(board_index.into());
-  sysex.push(cmd.into());
-  sysex.extend(data.iter());
+  let mut sysex = Vec::with_capacity(data.len() + 11);
+  create_sysex_into(&mut sysex, board_index, cmd, &data);
+  sysex
+}
+
+/// Like [create_sysex], but clears `buf` and writes the message into it, so callers
+/// can reuse one buffer instead of allocating a new message each time.
+pub fn create_sysex_into(buf: &mut Vec<u8>, board_index: BoardIndex, cmd: CommandId, data: &[u8]) {
+  buf.clear();
+  buf.push(SYSEX_START);
+  buf.extend(MANUFACTURER_ID.iter());
+  buf.push(board_index.into());
+  buf.push(cmd.into());
+  buf.extend(data.iter());
 
   // The C++ driver seems to always send a minimum of 9 bytes, not counting the SYSEX_START marker
   // So we add a little padding if we're sending less than 9 bytes.
-  if sysex.len() < 10 {
-    let pad = 10 - sysex.len();
-    for _ in 0..pad {
-      sysex.push(0);
-    }
+  if buf.len() < 10 {
+    buf.resize(10, 0);
   }
-  sysex.push(SYSEX_END);
-  sysex
+  buf.push(SYSEX_END);
 }
 
 pub fn create_sysex_toggle(board_index: BoardIndex, cmd: CommandId, state: bool) -> EncodedSysex {
@@ -95,20 +279,12 @@ pub fn create_extended_macro_color_sysex(cmd: CommandId, color: &RGBColor) -> En
 }
 
 pub fn create_table_sysex(cmd: CommandId, table: &SysexTable) -> EncodedSysex {
-  create_sysex(BoardIndex::Server, cmd, table.to_vec())
+  create_sysex(BoardIndex::Server, cmd, (*table).into())
 }
 
 pub fn strip_sysex_markers<'a>(msg: &'a [u8]) -> &'a [u8] {
-  if msg.len() == 0 {
-    return &msg;
-  }
-
-  let start = if msg[0] == SYSEX_START { 1 } else { 0 };
-  let mut end = msg.len() - 1;
-  if msg[end] == SYSEX_END {
-    end -= 1;
-  }
-  &msg[start..=end]
+  let msg = msg.strip_prefix(&[SYSEX_START]).unwrap_or(msg);
+  msg.strip_suffix(&[SYSEX_END]).unwrap_or(msg)
 }
 
 pub fn is_lumatone_message(msg: &[u8]) -> bool {
@@ -125,6 +301,42 @@ pub fn is_lumatone_message(msg: &[u8]) -> bool {
   return true;
 }
 
+/// Checks that `msg`, as received from the device, is a complete Lumatone message: framed
+/// by [SYSEX_START] and [SYSEX_END], with only 7-bit data bytes in between, starting with
+/// the [MANUFACTURER_ID], long enough to have a status byte, and with a known [CommandId].
+///
+/// Unlike [is_lumatone_message], this catches truncated and garbled messages before they
+/// get to the decoders.
+pub fn validate_incoming_message(msg: &[u8]) -> Result<(), LumatoneMidiError> {
+  if msg.first() != Some(&SYSEX_START) {
+    return Err(LumatoneMidiError::NotLumatoneMessage(msg.to_vec()));
+  }
+  if msg.len() < 2 || msg.last() != Some(&SYSEX_END) {
+    return Err(LumatoneMidiError::MessagePayloadInvalid(
+      "message has no end of sysex marker, so it may have been cut off".to_string(),
+    ));
+  }
+
+  let body = &msg[1..msg.len() - 1];
+  if let Some(i) = body.iter().position(|b| *b > 0x7f) {
+    return Err(LumatoneMidiError::MessagePayloadInvalid(format!(
+      "byte {:#04x} at index {} isn't a sysex data byte",
+      body[i],
+      i + 1
+    )));
+  }
+  if !is_lumatone_message(body) {
+    return Err(LumatoneMidiError::NotLumatoneMessage(msg.to_vec()));
+  }
+  if body.len() < PAYLOAD_INIT {
+    return Err(LumatoneMidiError::MessageTooShort {
+      expected: PAYLOAD_INIT,
+      actual: body.len(),
+    });
+  }
+  message_command_id(body).map(|_| ())
+}
+
 pub fn message_payload<'a>(msg: &'a [u8]) -> Result<&'a [u8], LumatoneMidiError> {
   let msg = strip_sysex_markers(msg);
   if msg.len() <= PAYLOAD_INIT {
@@ -149,15 +361,83 @@ pub fn message_command_id(msg: &[u8]) -> Result<CommandId, LumatoneMidiError> {
   cmd.ok_or(LumatoneMidiError::UnknownCommandId(cmd_id))
 }
 
-pub fn message_answer_code(msg: &[u8]) -> ResponseStatusCode {
+/// Returns the raw status byte of a response. Fails for [CommandId::PeripheralCalbrationData]
+/// frames, which have a [calibration_mode_byte] in that position instead.
+pub fn status_byte(msg: &[u8]) -> Result<u8, LumatoneMidiError> {
   let msg = strip_sysex_markers(msg);
   if msg.len() <= MSG_STATUS {
-    return ResponseStatusCode::Unknown;
+    return Err(LumatoneMidiError::MessageTooShort {
+      expected: MSG_STATUS + 1,
+      actual: msg.len(),
+    });
+  }
+  if has_calibration_mode(msg) {
+    return Err(LumatoneMidiError::InvalidResponseMessage(
+      "peripheral calibration data has no status byte".to_string(),
+    ));
   }
+  Ok(msg[MSG_STATUS])
+}
 
-  let status_byte = msg[MSG_STATUS];
-  let status: Option<ResponseStatusCode> = FromPrimitive::from_u8(status_byte);
-  status.unwrap_or(ResponseStatusCode::Unknown)
+/// Returns the raw calibration mode byte of a [CommandId::PeripheralCalbrationData] frame,
+/// which says which peripheral its data is for. Fails for any other message, since they
+/// have a [status_byte] in that position instead.
+pub fn calibration_mode_byte(msg: &[u8]) -> Result<u8, LumatoneMidiError> {
+  let msg = strip_sysex_markers(msg);
+  if msg.len() <= CALIB_MODE {
+    return Err(LumatoneMidiError::MessageTooShort {
+      expected: CALIB_MODE + 1,
+      actual: msg.len(),
+    });
+  }
+  let cmd_id = message_command_id(msg)?;
+  if cmd_id != CommandId::PeripheralCalbrationData {
+    return Err(LumatoneMidiError::UnexpectedCommandId {
+      expected: CommandId::PeripheralCalbrationData,
+      actual: cmd_id,
+    });
+  }
+  Ok(msg[CALIB_MODE])
+}
+
+fn has_calibration_mode(msg: &[u8]) -> bool {
+  matches!(
+    message_command_id(msg),
+    Ok(CommandId::PeripheralCalbrationData)
+  )
+}
+
+/// Returns the status of a response, or [ResponseStatusCode::Unknown] if it doesn't have
+/// one we understand (including peripheral calibration data, which has no status at all).
+pub fn message_answer_code(msg: &[u8]) -> ResponseStatusCode {
+  status_byte(msg)
+    .ok()
+    .and_then(FromPrimitive::from_u8)
+    .unwrap_or(ResponseStatusCode::Unknown)
+}
+
+/// Returns true if `msg` has the [TEST_ECHO] flag where a response has its status byte.
+///
+/// Pings carry the flag as their first data byte, which is the status position in a response.
+/// The device's answer puts an Ack status there, pushing the flag into the payload. So a
+/// message with the flag in the status position is a ping that came back without being
+/// answered (e.g. through a MIDI thru or loopback port), not a response from the device.
+pub fn has_echo_flag(msg: &[u8]) -> bool {
+  let msg = strip_sysex_markers(msg);
+  msg.len() > MSG_STATUS && msg[MSG_STATUS] == TEST_ECHO
+}
+
+/// Returns true if `incoming` is the device echoing `outgoing` back: the same message, with a
+/// status byte inserted after the command id. Responses that carry data (e.g. the answer to a
+/// "get" command) are not echoes.
+pub fn is_echo_of(outgoing: &[u8], incoming: &[u8]) -> bool {
+  let outgoing = strip_sysex_markers(outgoing);
+  let incoming = strip_sysex_markers(incoming);
+
+  outgoing.len() >= MSG_STATUS
+    && incoming.len() == outgoing.len() + 1
+    && incoming[..MSG_STATUS] == outgoing[..MSG_STATUS]
+    && incoming[MSG_STATUS + 1..] == outgoing[MSG_STATUS..]
 }
 
 pub fn is_response_to_message(outgoing: &[u8], incoming: &[u8]) -> bool {
@@ -174,3 +454,205 @@ pub fn is_response_to_message(outgoing: &[u8], incoming: &[u8]) -> bool {
 
   incoming[CMD_ID] == outgoing[CMD_ID] && incoming[BOARD_IND] == outgoing[BOARD_IND]
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    calibration_mode_byte, create_sysex, create_table_sysex, describe, message_answer_code,
+    reverse_table, status_byte, strip_sysex_markers, validate_incoming_message,
+    velocity_intervals_serde, SysexTable, VelocityIntervalTable, CMD_ID,
+  };
+  use crate::midi::constants::{
+    BoardIndex, CommandId, PeripheralCalibrationMode, ResponseStatusCode,
+  };
+  use crate::midi::error::LumatoneMidiError;
+
+  fn ramp() -> [u8; 128] {
+    std::array::from_fn(|i| i as u8)
+  }
+
+  #[test]
+  fn test_table_values_are_7bit() {
+    let table = SysexTable::try_from(ramp()).unwrap();
+    assert_eq!(table.values(), &ramp());
+    assert_eq!(table[127], 127);
+    assert_eq!(Vec::from(table), ramp().to_vec());
+    assert_eq!(SysexTable::try_from(&ramp()[..]).unwrap(), table);
+
+    let mut values = ramp();
+    values[5] = 0x80;
+    assert!(SysexTable::try_from(values).is_err());
+    assert!(SysexTable::try_from(&ramp()[..127]).is_err());
+    assert!(SysexTable::try_from(&[0; 129][..]).is_err());
+  }
+
+  #[test]
+  fn test_create_table_sysex() {
+    let table = SysexTable::new(ramp());
+    let msg = create_table_sysex(CommandId::SetFaderConfig, &table);
+    // outgoing messages have no status byte, so the data starts right after the command id
+    assert_eq!(&strip_sysex_markers(&msg)[CMD_ID + 1..], ramp());
+
+    let reversed = reverse_table(&table);
+    assert_eq!(reversed[0], 127);
+    assert_eq!(reversed[127], 0);
+  }
+
+  #[test]
+  fn test_status_and_calibration_mode_bytes() {
+    // outgoing frames have their data right after the command id, where responses keep
+    // the status, so these can stand in for the device's messages
+    let status_frame = create_sysex(
+      BoardIndex::Server,
+      CommandId::CalibratePitchModWheel,
+      vec![ResponseStatusCode::Nack as u8, 0x1, 0x2],
+    );
+    let calibration_frame = create_sysex(
+      BoardIndex::Server,
+      CommandId::PeripheralCalbrationData,
+      vec![PeripheralCalibrationMode::ExpressionPedal as u8, 0x1, 0x2],
+    );
+
+    assert_eq!(status_byte(&status_frame).unwrap(), 0);
+    assert_eq!(message_answer_code(&status_frame), ResponseStatusCode::Nack);
+    assert!(matches!(
+      calibration_mode_byte(&status_frame),
+      Err(LumatoneMidiError::UnexpectedCommandId {
+        expected: CommandId::PeripheralCalbrationData,
+        actual: CommandId::CalibratePitchModWheel,
+      })
+    ));
+
+    // the same zero byte is a mode here, not a Nack
+    assert_eq!(calibration_mode_byte(&calibration_frame).unwrap(), 0);
+    assert!(status_byte(&calibration_frame).is_err());
+    assert_eq!(
+      message_answer_code(&calibration_frame),
+      ResponseStatusCode::Unknown
+    );
+
+    // cut off right after the command id
+    assert!(matches!(
+      calibration_mode_byte(&calibration_frame[..CMD_ID + 2]),
+      Err(LumatoneMidiError::MessageTooShort { .. })
+    ));
+  }
+
+  #[test]
+  fn test_describe() {
+    let frame = create_sysex(
+      BoardIndex::Octave2,
+      CommandId::ChangeKeyNote,
+      vec![3, 1, 60, 0],
+    );
+    assert_eq!(describe(&frame), "ChangeKeyNote for Octave2, 4 data bytes");
+
+    // create_sysex pads short payloads, so build this one by hand
+    let ack = [0xf0, 0x00, 0x21, 0x50, 0x00, 0x33, 0x01, 0xf7];
+    assert_eq!(describe(&ack), "LumaPing for Server board, 1 data byte");
+
+    let mut unknown = create_sysex(BoardIndex::Server, CommandId::LumaPing, vec![]);
+    unknown[CMD_ID + 1] = 0x7e;
+    assert_eq!(
+      describe(&unknown),
+      "unknown command 0x7e for Server board, 4 data bytes"
+    );
+
+    assert_eq!(
+      describe(&[0xf0, 0x7e, 0x7f, 0xf7]),
+      "not a Lumatone message"
+    );
+    assert_eq!(describe(&frame[..5]), "truncated message (4 bytes)");
+  }
+
+  #[test]
+  fn test_validate_incoming_message() {
+    use crate::midi::responses::Response;
+    let valid = [0xf0, 0x00, 0x21, 0x50, 0x00, 0x33, 0x01, 0xf7];
+    assert!(validate_incoming_message(&valid).is_ok());
+
+    // truncated: no end marker, or too short for a status byte
+    assert!(matches!(
+      validate_incoming_message(&valid[..6]),
+      Err(LumatoneMidiError::MessagePayloadInvalid(_))
+    ));
+    assert!(matches!(
+      validate_incoming_message(&[0xf0, 0x00, 0x21, 0x50, 0x00, 0x33, 0xf7]),
+      Err(LumatoneMidiError::MessageTooShort {
+        expected: 6,
+        actual: 5
+      })
+    ));
+    assert!(matches!(
+      validate_incoming_message(&[0xf0, 0xf7]),
+      Err(LumatoneMidiError::NotLumatoneMessage(_))
+    ));
+    assert!(matches!(
+      validate_incoming_message(&[]),
+      Err(LumatoneMidiError::NotLumatoneMessage(_))
+    ));
+
+    // garbage: no start marker, someone else's manufacturer id, a stray status byte in
+    // the middle, or an unknown command
+    assert!(matches!(
+      validate_incoming_message(&valid[1..]),
+      Err(LumatoneMidiError::NotLumatoneMessage(_))
+    ));
+    assert!(matches!(
+      validate_incoming_message(&[0xf0, 0x7e, 0x7f, 0x06, 0x02, 0x00, 0x01, 0xf7]),
+      Err(LumatoneMidiError::NotLumatoneMessage(_))
+    ));
+    let mut garbled = valid;
+    garbled[6] = 0x90;
+    match validate_incoming_message(&garbled) {
+      Err(LumatoneMidiError::MessagePayloadInvalid(msg)) => {
+        assert_eq!(msg, "byte 0x90 at index 6 isn't a sysex data byte")
+      }
+      other => panic!("expected an invalid payload, got {other:?}"),
+    }
+    let mut unknown = valid;
+    unknown[CMD_ID + 1] = 0x7e;
+    assert!(matches!(
+      validate_incoming_message(&unknown),
+      Err(LumatoneMidiError::UnknownCommandId(0x7e))
+    ));
+
+    // decoding checks up front, instead of failing deep in a decoder
+    assert!(matches!(
+      Response::from_sysex_message(&valid[..7]),
+      Err(LumatoneMidiError::MessagePayloadInvalid(_))
+    ));
+  }
+
+  #[test]
+  #[should_panic(expected = "7 bits")]
+  fn test_new_table_panics_on_8bit_values() {
+    SysexTable::new([0xff; 128]);
+  }
+
+  #[test]
+  fn test_velocity_intervals_serialize_as_a_list() {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Tables {
+      #[serde(with = "velocity_intervals_serde::option")]
+      intervals: Option<VelocityIntervalTable>,
+    }
+
+    let tables = Tables {
+      intervals: Some(std::array::from_fn(|i| i as u16 * 32)),
+    };
+    let json = serde_json::to_string(&tables).unwrap();
+    assert!(json.starts_with(r#"{"intervals":[0,32,64,"#), "{json}");
+    assert_eq!(serde_json::from_str::<Tables>(&json).unwrap(), tables);
+    assert_eq!(
+      serde_json::from_str::<Tables>(r#"{"intervals":null}"#).unwrap(),
+      Tables { intervals: None }
+    );
+
+    let err = serde_json::from_str::<Tables>(r#"{"intervals":[1,2,3]}"#).unwrap_err();
+    assert!(
+      err.to_string().contains("expected 127 velocity intervals"),
+      "{err}"
+    );
+  }
+}
@@ -0,0 +1,63 @@
+//! Helpers for finding where to persist settings and cached state.
+//! Shared by the CLI and GUI, so they agree on where things live.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Name of our subdirectory within the platform config directory.
+const APP_DIR_NAME: &str = "lumatone-rs";
+
+/// Returns the directory to store settings in, or `None` if it can't be determined
+/// (e.g. `$HOME` isn't set).
+///
+/// - Linux & others: `$XDG_CONFIG_HOME/lumatone-rs`, or `~/.config/lumatone-rs`
+/// - macOS: `~/Library/Application Support/lumatone-rs`
+/// - Windows: `%APPDATA%\lumatone-rs`
+///
+/// The directory isn't created; callers should create it before writing to it.
+pub fn config_dir() -> Option<PathBuf> {
+  platform_config_dir().map(|dir| dir.join(APP_DIR_NAME))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_config_dir() -> Option<PathBuf> {
+  env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_config_dir() -> Option<PathBuf> {
+  env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_config_dir() -> Option<PathBuf> {
+  env::var_os("XDG_CONFIG_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Returns the directory for state that only matters while a process is running, like
+/// lock files.
+///
+/// - Linux & others: `$XDG_RUNTIME_DIR/lumatone-rs` if set
+/// - Everywhere else: `lumatone-rs` within the system temp directory
+///
+/// Like [config_dir], the directory isn't created.
+pub fn runtime_dir() -> PathBuf {
+  platform_runtime_dir()
+    .unwrap_or_else(env::temp_dir)
+    .join(APP_DIR_NAME)
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn platform_runtime_dir() -> Option<PathBuf> {
+  None
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_runtime_dir() -> Option<PathBuf> {
+  env::var_os("XDG_RUNTIME_DIR")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+}